@@ -0,0 +1,202 @@
+use crate::{Command, Result};
+use chrono::{DateTime, Utc};
+use common::room_state::configuration::PrivacyMode;
+use common::room_state::member::{InvitationToken, MemberId};
+use common::room_state::message::{AuthorizedMessageV1, MessageContent};
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use river_client::network::Network;
+use river_client::room_data::{OpenContentError, RoomData, Rooms};
+use river_client::{decode_member_key, KeyStore, LocalKeyStore, RiverClient, RoomEvent};
+use std::time::{Duration, SystemTime};
+
+/// A room as it's named on the command line, by its owner's key
+pub fn room_id(owner_vk: &VerifyingKey) -> String {
+    bs58::encode(owner_vk.as_bytes()).into_string()
+}
+
+pub fn parse_room(text: &str) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = bs58::decode(text.trim())
+        .into_vec()?
+        .try_into()
+        .map_err(|_| format!("{} isn't a room, see `riverctl rooms`", text))?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+pub fn list_rooms(rooms: &Rooms) {
+    for (owner_vk, room_data) in &rooms.map {
+        println!(
+            "{}  {}",
+            room_id(owner_vk),
+            room_data.room_state.configuration.name()
+        );
+    }
+}
+
+/// Brings our rooms up to date, carries out `command`, and waits `wait` for the network to go
+/// quiet before returning our rooms and keys
+pub async fn run<N: Network>(
+    network: N,
+    rooms: Rooms,
+    keys: LocalKeyStore,
+    identity: &SigningKey,
+    command: Command,
+    wait: Duration,
+) -> Result<(Rooms, LocalKeyStore)> {
+    let mut client = RiverClient::new(network, keys, rooms);
+    client.resume().await?;
+    settle(&mut client, wait).await?;
+    match command {
+        Command::Create {
+            name,
+            nickname,
+            private,
+        } => {
+            let privacy_mode = if private {
+                PrivacyMode::Private
+            } else {
+                PrivacyMode::Public
+            };
+            let owner_vk = client.create_room(name, nickname, privacy_mode).await?;
+            println!("{}", room_id(&owner_vk));
+        }
+        Command::Join {
+            invitation,
+            nickname,
+        } => {
+            let token = InvitationToken::decode(&invitation).ok_or("Not an invitation link")?;
+            let owner_vk = client.join(&token, nickname).await?;
+            println!("{}", room_id(&owner_vk));
+        }
+        Command::Add { room } => {
+            client
+                .add_room(parse_room(&room)?, identity.clone())
+                .await?
+        }
+        Command::Invite { room, member_key } => {
+            let member_vk = decode_member_key(&member_key)?;
+            client.invite(&parse_room(&room)?, member_vk).await?;
+        }
+        Command::Post { room, message } => {
+            client.post(&parse_room(&room)?, message, None).await?;
+        }
+        Command::Tail {
+            room,
+            lines,
+            follow,
+        } => {
+            let owner_vk = parse_room(&room)?;
+            tail(room_data(client.rooms(), &owner_vk)?, lines);
+            if follow {
+                loop {
+                    if let RoomEvent::Message {
+                        room,
+                        message,
+                        content,
+                    } = client.next_event().await?
+                    {
+                        if room == owner_vk {
+                            let room_data = room_data(client.rooms(), &owner_vk)?;
+                            println!("{}", format_message(room_data, &message, content));
+                        }
+                    }
+                }
+            }
+        }
+        Command::Ban { room, member, days } => {
+            let owner_vk = parse_room(&room)?;
+            let member_id = find_member(room_data(client.rooms(), &owner_vk)?, &member)?;
+            let expires_at =
+                days.map(|days| SystemTime::now() + Duration::from_secs(days * 24 * 60 * 60));
+            client.ban(&owner_vk, member_id, expires_at).await?;
+        }
+        Command::Nickname { room, nickname } => {
+            client.set_nickname(&parse_room(&room)?, nickname).await?;
+        }
+        Command::Key | Command::Rooms | Command::Dump { .. } | Command::Verify { .. } => {
+            unreachable!("Carried out without a network")
+        }
+    }
+    settle(&mut client, wait).await?;
+    for room_data in client.rooms().map.values() {
+        if room_data.pending_join.is_some() {
+            eprintln!(
+                "Still waiting for the state of room {}, run riverctl again to finish joining",
+                room_id(&room_data.owner_vk)
+            );
+        }
+    }
+    Ok(client.into_parts())
+}
+
+/// Takes in whatever arrives until nothing has for `wait`
+async fn settle<N: Network, K: KeyStore>(
+    client: &mut RiverClient<N, K>,
+    wait: Duration,
+) -> Result<()> {
+    while let Ok(event) = tokio::time::timeout(wait, client.next_event()).await {
+        event?;
+    }
+    Ok(())
+}
+
+fn room_data<'a>(rooms: &'a Rooms, owner_vk: &VerifyingKey) -> Result<&'a RoomData> {
+    rooms
+        .map
+        .get(owner_vk)
+        .ok_or_else(|| format!("Not in room {}, see `riverctl rooms`", room_id(owner_vk)).into())
+}
+
+fn tail(room_data: &RoomData, lines: usize) {
+    let messages = &room_data.room_state.recent_messages;
+    let skip = messages.messages.entries.len().saturating_sub(lines);
+    for message in messages.messages.entries.iter().skip(skip) {
+        let content = match messages.content(message) {
+            MessageContent::Deleted => continue,
+            MessageContent::Original(content) | MessageContent::Edited(content) => {
+                RoomData::open_content(&room_data.room_secrets, content)
+            }
+        };
+        println!("{}", format_message(room_data, message, content));
+    }
+}
+
+/// A message as `tail` prints it, with its author's id to ban them by
+fn format_message(
+    room_data: &RoomData,
+    message: &AuthorizedMessageV1,
+    content: std::result::Result<String, OpenContentError>,
+) -> String {
+    let author = message.message.author();
+    let nickname = room_data
+        .room_state
+        .member_info
+        .member_info
+        .iter()
+        .find(|info| info.member_info.member_id == author)
+        .map_or_else(
+            || "?".to_string(),
+            |info| info.member_info.preferred_nickname.clone(),
+        );
+    let time = DateTime::<Utc>::from(message.message.time()).format("%Y-%m-%d %H:%M");
+    let content = match content {
+        Ok(content) => content,
+        Err(OpenContentError::MissingSecret { .. }) => "<not shared with us>".to_string(),
+        Err(OpenContentError::Undecryptable) => "<undecryptable>".to_string(),
+    };
+    format!("{} {} ({}): {}", time, nickname, author, content)
+}
+
+/// A member by their key, or by their id as `tail` prints it
+fn find_member(room_data: &RoomData, text: &str) -> Result<MemberId> {
+    if let Ok(member_vk) = decode_member_key(text) {
+        return Ok(member_vk.into());
+    }
+    room_data
+        .room_state
+        .members
+        .members
+        .iter()
+        .map(|member| member.member.id())
+        .find(|member_id| member_id.to_string() == text.trim())
+        .ok_or_else(|| format!("No member {} in the room", text).into())
+}
@@ -0,0 +1,166 @@
+//! `riverctl`, River rooms from the command line. Rooms are synced with a Freenet node, or with
+//! room contracts kept as state files in a directory to work offline.
+
+mod commands;
+mod offline;
+mod profile;
+mod state_file;
+
+use clap::{Parser, Subcommand};
+use profile::Profile;
+use river_client::encode_member_key;
+use river_client::network::FreenetNode;
+use state_file::Format;
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+const NODE_URL: &str = "ws://localhost:50509/contract/command?encodingProtocol=native";
+
+#[derive(Parser)]
+#[command(
+    name = "riverctl",
+    about = "Create, post to and moderate River chat rooms"
+)]
+struct Cli {
+    /// Our keys and what we know of our rooms, created if it doesn't exist
+    #[arg(long, default_value = "riverctl.cbor")]
+    profile: PathBuf,
+    /// WebSocket API of the Freenet node
+    #[arg(long, default_value = NODE_URL)]
+    node: String,
+    /// Works on room contracts kept as state files in this directory instead of a node
+    #[arg(long)]
+    offline: Option<PathBuf>,
+    /// How long to wait for updates from the node before exiting, in seconds
+    #[arg(long, default_value_t = 3)]
+    wait: u64,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Prints the key others invite us to their rooms with
+    Key,
+    /// Lists our rooms
+    Rooms,
+    /// Creates a room we own
+    Create {
+        name: String,
+        #[arg(long)]
+        nickname: String,
+        /// Encrypts messages so only members can read them
+        #[arg(long)]
+        private: bool,
+    },
+    /// Joins a room with an invitation link
+    Join {
+        invitation: String,
+        #[arg(long)]
+        nickname: String,
+    },
+    /// Adds a room we were invited to by our key, see `key`
+    Add {
+        room: String,
+    },
+    /// Invites a member by their key
+    Invite {
+        room: String,
+        member_key: String,
+    },
+    Post {
+        room: String,
+        message: String,
+    },
+    /// Prints the room's latest messages
+    Tail {
+        room: String,
+        #[arg(short = 'n', long, default_value_t = 20)]
+        lines: usize,
+        /// Keeps printing messages as they arrive
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Bans a member, by their key or their id as `tail` shows it
+    Ban {
+        room: String,
+        member: String,
+        /// How long the ban lasts, it lasts until it's lifted if not set
+        #[arg(long)]
+        days: Option<u64>,
+    },
+    Nickname {
+        room: String,
+        nickname: String,
+    },
+    /// Prints a room state file, in CBOR or JSON, as JSON or CBOR
+    Dump {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        to: Format,
+    },
+    /// Checks that a room state file is a valid state of the room
+    Verify {
+        file: PathBuf,
+        /// The room the state is of, by default the one the file is named after
+        #[arg(long)]
+        room: Option<String>,
+    },
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("riverctl: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Command::Dump { file, to } => return state_file::dump(&file, to),
+        Command::Verify { file, room } => return state_file::verify(&file, room.as_deref()),
+        _ => {}
+    }
+    let mut profile = Profile::load_or_create(&cli.profile)?;
+    match cli.command {
+        Command::Key => println!("{}", encode_member_key(&profile.identity.verifying_key())),
+        Command::Rooms => commands::list_rooms(&profile.rooms),
+        Command::Tail { follow: true, .. } if cli.offline.is_some() => {
+            return Err("--follow needs a node, nothing else changes offline rooms".into());
+        }
+        command => match &cli.offline {
+            Some(dir) => {
+                let node = offline::load(dir)?;
+                let rooms = std::mem::take(&mut profile.rooms);
+                let keys = std::mem::take(&mut profile.keys);
+                (profile.rooms, profile.keys) = commands::run(
+                    node.connect(),
+                    rooms,
+                    keys,
+                    &profile.identity,
+                    command,
+                    Duration::ZERO,
+                )
+                .await?;
+                offline::save(dir, &node)?;
+            }
+            None => {
+                let node = FreenetNode::connect(&cli.node).await?;
+                let rooms = std::mem::take(&mut profile.rooms);
+                let keys = std::mem::take(&mut profile.keys);
+                let wait = Duration::from_secs(cli.wait);
+                (profile.rooms, profile.keys) =
+                    commands::run(node, rooms, keys, &profile.identity, command, wait).await?;
+            }
+        },
+    }
+    profile.save(&cli.profile)
+}
@@ -0,0 +1,100 @@
+//! Room contracts kept as CBOR state files, one per room named after it, that
+//! `riverctl --offline` works on in place of a node
+
+use crate::commands::{parse_room, room_id};
+use crate::state_file::{self, Format};
+use crate::Result;
+use river_client::network::LocalNode;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+pub fn load(dir: &Path) -> Result<LocalNode> {
+    fs::create_dir_all(dir)?;
+    let mut states = HashMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("cbor") {
+            continue;
+        }
+        let Some(owner_vk) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| parse_room(stem).ok())
+        else {
+            continue;
+        };
+        states.insert(owner_vk, state_file::read(&path)?);
+    }
+    Ok(LocalNode::with_states(states))
+}
+
+pub fn save(dir: &Path, node: &LocalNode) -> Result<()> {
+    for (owner_vk, state) in node.states() {
+        let path = dir.join(format!("{}.cbor", room_id(&owner_vk)));
+        fs::write(path, state_file::encode(&state, Format::Cbor)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::run;
+    use crate::Command;
+    use ed25519_dalek::SigningKey;
+    use river_client::room_data::Rooms;
+    use river_client::LocalKeyStore;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_rooms_kept_in_files() {
+        let dir = std::env::temp_dir().join(format!("riverctl-test-{}", std::process::id()));
+        let identity = SigningKey::generate(&mut rand::thread_rng());
+        let create = Command::Create {
+            name: "Room".into(),
+            nickname: "Alice".into(),
+            private: true,
+        };
+        let node = load(&dir).unwrap();
+        let (rooms, keys) = run(
+            node.connect(),
+            Rooms::default(),
+            LocalKeyStore::default(),
+            &identity,
+            create,
+            Duration::ZERO,
+        )
+        .await
+        .unwrap();
+        save(&dir, &node).unwrap();
+
+        let owner_vk = *rooms.map.keys().next().unwrap();
+        let post = Command::Post {
+            room: room_id(&owner_vk),
+            message: "Hello".into(),
+        };
+        let node = load(&dir).unwrap();
+        let (rooms, _) = run(node.connect(), rooms, keys, &identity, post, Duration::ZERO)
+            .await
+            .unwrap();
+        save(&dir, &node).unwrap();
+
+        let path = dir.join(format!("{}.cbor", room_id(&owner_vk)));
+        assert_eq!(
+            state_file::read(&path).unwrap(),
+            rooms.map[&owner_vk].state()
+        );
+        assert_eq!(
+            rooms.map[&owner_vk]
+                .room_state
+                .recent_messages
+                .messages
+                .entries
+                .len(),
+            1
+        );
+        state_file::verify(&path, None).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
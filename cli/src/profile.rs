@@ -0,0 +1,98 @@
+use crate::Result;
+use common::ChatRoomState;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use river_client::room_data::{PendingJoin, PendingUpgrade, RoomData, Rooms};
+use river_client::util::to_cbor_vec;
+use river_client::LocalKeyStore;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+/// Who we are and the rooms we're in. Our signing keys are kept unencrypted, unlike in the web
+/// UI, so the profile must be kept private.
+pub struct Profile {
+    /// The key others invite us with, and our key in rooms we were invited to with it
+    pub identity: SigningKey,
+    pub rooms: Rooms,
+    /// Our key in each room, kept in the profile as each room's `self_sk`
+    pub keys: LocalKeyStore,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredProfile {
+    identity: SigningKey,
+    rooms: Vec<StoredRoom>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredRoom {
+    owner_vk: VerifyingKey,
+    self_sk: SigningKey,
+    state: ChatRoomState,
+    pending_join: Option<PendingJoin>,
+    /// Whether `state` is a placeholder until the contract's state arrives
+    fetch: bool,
+}
+
+impl Profile {
+    pub fn load_or_create(path: &Path) -> Result<Self> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(Profile {
+                    identity: SigningKey::generate(&mut rand::thread_rng()),
+                    rooms: Rooms::default(),
+                    keys: LocalKeyStore::default(),
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let stored: StoredProfile = ciborium::de::from_reader(bytes.as_slice())
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut rooms = Rooms::default();
+        let mut keys = LocalKeyStore::default();
+        for stored_room in stored.rooms {
+            let self_vk = stored_room.self_sk.verifying_key();
+            let room_data = RoomData {
+                pending_join: stored_room.pending_join,
+                pending_upgrade: stored_room.fetch.then_some(PendingUpgrade::Fetch),
+                ..RoomData::restored(stored_room.owner_vk, self_vk, stored_room.state)
+            };
+            rooms.map.insert(stored_room.owner_vk, room_data);
+            keys.keys.insert(self_vk, stored_room.self_sk);
+        }
+        Ok(Profile {
+            identity: stored.identity,
+            rooms,
+            keys,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let stored = StoredProfile {
+            identity: self.identity.clone(),
+            rooms: self
+                .rooms
+                .map
+                .values()
+                // A room whose key we don't have can't be used, which shouldn't happen
+                .filter_map(|room_data| {
+                    Some(StoredRoom {
+                        owner_vk: room_data.owner_vk,
+                        self_sk: self.keys.keys.get(&room_data.self_vk)?.clone(),
+                        state: room_data.state(),
+                        pending_join: room_data.pending_join.clone(),
+                        fetch: matches!(room_data.pending_upgrade, Some(PendingUpgrade::Fetch)),
+                    })
+                })
+                .collect(),
+        };
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(path)?.write_all(&to_cbor_vec(&stored))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use crate::commands::parse_room;
+use crate::Result;
+use clap::ValueEnum;
+use common::room_state::ChatRoomParametersV1;
+use common::ChatRoomState;
+use freenet_scaffold::ComposableState;
+use river_client::util::to_cbor_vec;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// How a room state file is encoded. Contracts hold CBOR, JSON is for reading and editing.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Format {
+    Cbor,
+    Json,
+}
+
+/// Reads a state file of either format
+pub fn read(path: &Path) -> Result<ChatRoomState> {
+    let bytes = fs::read(path)?;
+    decode(&bytes).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+pub fn decode(bytes: &[u8]) -> Result<ChatRoomState> {
+    // A CBOR state is a map, which never starts with a `{`
+    if bytes.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') {
+        Ok(serde_json::from_slice(bytes)?)
+    } else {
+        Ok(ChatRoomState::from_cbor(bytes)?)
+    }
+}
+
+pub fn encode(state: &ChatRoomState, format: Format) -> Result<Vec<u8>> {
+    Ok(match format {
+        Format::Cbor => to_cbor_vec(state),
+        Format::Json => serde_json::to_vec_pretty(state)?,
+    })
+}
+
+pub fn dump(path: &Path, format: Format) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&encode(&read(path)?, format)?)?;
+    if format == Format::Json {
+        writeln!(stdout)?;
+    }
+    Ok(())
+}
+
+pub fn verify(path: &Path, room: Option<&str>) -> Result<()> {
+    let owner = match room {
+        Some(room) => parse_room(room)?,
+        // Offline room contracts are named after their room
+        None => path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| parse_room(stem).ok())
+            .ok_or("The file isn't named after a room, pass --room")?,
+    };
+    let state = read(path)?;
+    let parameters = ChatRoomParametersV1 { owner };
+    state.verify(&state, &parameters)?;
+    println!("Valid");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::room_state::configuration::PrivacyMode;
+    use ed25519_dalek::SigningKey;
+    use river_client::room_data::Rooms;
+    use river_client::{KeyStore, LocalKeyStore};
+
+    #[tokio::test]
+    async fn test_formats_round_trip() {
+        let mut rooms = Rooms::default();
+        let mut keys = LocalKeyStore::default();
+        let owner_sk = SigningKey::generate(&mut rand::thread_rng());
+        let owner_vk = owner_sk.verifying_key();
+        keys.store(owner_sk).await.unwrap();
+        rooms
+            .create_new_room_with_name(
+                owner_vk,
+                "Room".into(),
+                "Alice".into(),
+                PrivacyMode::Private,
+                &mut keys,
+            )
+            .await
+            .unwrap();
+        let state = rooms.map[&owner_vk].state();
+        for format in [Format::Cbor, Format::Json] {
+            let decoded = decode(&encode(&state, format).unwrap()).unwrap();
+            assert_eq!(decoded, state);
+        }
+    }
+}
@@ -0,0 +1,674 @@
+use crate::error::ClientError;
+use crate::event::RoomEvent;
+use crate::key_store::KeyStore;
+use crate::network::{Network, NetworkEvent, NetworkRequest};
+use crate::room_data::{PendingUpgrade, RoomData, RoomSyncStatus, Rooms};
+use common::keyring::{EncryptedKeyring, Keyring, KeyringKey};
+use common::room_state::configuration::PrivacyMode;
+use common::room_state::member::InvitationToken;
+use common::room_state::message::MessageId;
+use common::room_state::versioned::{ChatRoomStateDelta, ChatRoomStateV2Delta};
+use common::room_state::ChatRoomStateV1Delta;
+use common::ChatRoomStateV1;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use freenet_scaffold::ComposableState;
+use std::collections::hash_map::Entry;
+use std::collections::{HashSet, VecDeque};
+
+mod actions;
+
+/// Our rooms kept in sync with their contracts over `network`, with our keys in them kept by
+/// `key_store`. What we do is sent to the room's contract right away, what others do arrives as
+/// events from `next_event`.
+pub struct RiverClient<N, K> {
+    rooms: Rooms,
+    network: N,
+    key_store: K,
+    events: VecDeque<RoomEvent>,
+}
+
+impl<N: Network, K: KeyStore> RiverClient<N, K> {
+    /// A client for `rooms`, eg. restored from storage, see `resume` to sync them. Our keys in
+    /// them must be in `key_store`.
+    pub fn new(network: N, key_store: K, rooms: Rooms) -> Self {
+        RiverClient {
+            rooms,
+            network,
+            key_store,
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn rooms(&self) -> &Rooms {
+        &self.rooms
+    }
+
+    pub fn into_parts(self) -> (Rooms, K) {
+        (self.rooms, self.key_store)
+    }
+
+    /// Our key in each room encrypted with `key`, to export our identity. The key store encrypts
+    /// it, our keys never leave it in the clear.
+    pub async fn export_keyring(
+        &mut self,
+        key: &KeyringKey,
+    ) -> Result<EncryptedKeyring, ClientError> {
+        Ok(self.key_store.export(key).await?)
+    }
+
+    /// Subscribes to every room and brings their contracts and us up to date with each other.
+    /// Rooms the key store has that we don't, eg. kept by the chat delegate for another browser,
+    /// are fetched.
+    pub async fn resume(&mut self) -> Result<(), ClientError> {
+        let kept = self.key_store.rooms().await?;
+        for (owner_vk, room_data) in &self.rooms.map {
+            if kept.get(owner_vk) != Some(&room_data.self_vk) {
+                self.key_store
+                    .store_room(owner_vk, &room_data.self_vk)
+                    .await?;
+            }
+        }
+        for (owner_vk, self_vk) in kept {
+            self.rooms.add_unfetched(owner_vk, self_vk);
+        }
+        let owner_vks: Vec<VerifyingKey> = self.rooms.map.keys().copied().collect();
+        for owner_vk in owner_vks {
+            self.resume_room(owner_vk).await?;
+        }
+        Ok(())
+    }
+
+    /// Adds rooms we had before and our keys in them, eg. restored from storage once it's
+    /// unlocked, and syncs them as `resume` does. Rooms we already have are kept as they are, and
+    /// rooms of keys that weren't restored are fetched, see `add_keys`.
+    pub async fn restore(&mut self, rooms: Rooms, keyring: Keyring) -> Result<(), ClientError> {
+        let mut restored = Vec::new();
+        for (owner_vk, room_data) in rooms.map {
+            if let Entry::Vacant(entry) = self.rooms.map.entry(owner_vk) {
+                entry.insert(room_data);
+                restored.push(owner_vk);
+            }
+        }
+        // The keys go to the key store first, syncing a private room decrypts its secrets
+        self.add_keys(keyring).await?;
+        for owner_vk in restored {
+            self.resume_room(owner_vk).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn create_room(
+        &mut self,
+        name: String,
+        nickname: String,
+        privacy_mode: PrivacyMode,
+    ) -> Result<VerifyingKey, ClientError> {
+        let self_sk = SigningKey::generate(&mut rand::thread_rng());
+        let self_vk = self_sk.verifying_key();
+        self.key_store.store(self_sk).await?;
+        let owner_vk = self
+            .rooms
+            .create_new_room_with_name(self_vk, name, nickname, privacy_mode, &mut self.key_store)
+            .await?;
+        let state = self.rooms.map[&owner_vk].state();
+        self.network
+            .send(NetworkRequest::Put { owner_vk, state })
+            .await?;
+        self.subscribe(owner_vk).await?;
+        Ok(owner_vk)
+    }
+
+    /// Redeems an invitation, `RoomEvent::Joined` follows once the room's state arrives
+    pub async fn join(
+        &mut self,
+        token: &InvitationToken,
+        nickname: String,
+    ) -> Result<VerifyingKey, ClientError> {
+        let self_sk = SigningKey::generate(&mut rand::thread_rng());
+        let self_vk = self_sk.verifying_key();
+        self.key_store.store(self_sk).await?;
+        let owner_vk = self.rooms.join_with_invitation(token, self_vk, nickname)?;
+        self.key_store.store_room(&owner_vk, &self_vk).await?;
+        self.fetch(owner_vk).await?;
+        Ok(owner_vk)
+    }
+
+    /// Adds the room owned by `owner_vk` that `self_sk` was invited to, see `RiverClient::invite`
+    pub async fn add_room(
+        &mut self,
+        owner_vk: VerifyingKey,
+        self_sk: SigningKey,
+    ) -> Result<(), ClientError> {
+        let mut keyring = Keyring::default();
+        keyring.keys.insert(owner_vk, self_sk);
+        self.add_keys(keyring).await?;
+        Ok(())
+    }
+
+    /// Adds the rooms of keys we aren't in a room with yet, see `Rooms::add_keys`, and fetches
+    /// their state. Every key goes to the key store. Returns how many rooms were added.
+    pub async fn add_keys(&mut self, keyring: Keyring) -> Result<usize, ClientError> {
+        let added: Vec<VerifyingKey> = keyring
+            .keys
+            .keys()
+            .filter(|owner_vk| !self.rooms.map.contains_key(owner_vk))
+            .copied()
+            .collect();
+        for self_sk in keyring.keys.values() {
+            self.key_store.store(self_sk.clone()).await?;
+        }
+        self.rooms.add_keys(&keyring);
+        for owner_vk in &added {
+            let self_vk = keyring.keys[owner_vk].verifying_key();
+            self.key_store.store_room(owner_vk, &self_vk).await?;
+            self.fetch(*owner_vk).await?;
+        }
+        Ok(added.len())
+    }
+
+    /// Hands the room over to a new key, see `RoomData::hand_over`. Returns the new owner's key,
+    /// which the room goes by from now on.
+    pub async fn hand_over(&mut self, room: &VerifyingKey) -> Result<VerifyingKey, ClientError> {
+        let room_data = self.rooms.map.get(room).ok_or(ClientError::UnknownRoom {
+            room_owner: room.into(),
+        })?;
+        let new_room = room_data.hand_over(&mut self.key_store).await?;
+        let new_owner_vk = new_room.owner_vk;
+        self.rooms.map.remove(room);
+        self.rooms.map.insert(new_owner_vk, new_room);
+        self.key_store.remove_room(room).await?;
+        self.resume_room(new_owner_vk).await?;
+        Ok(new_owner_vk)
+    }
+
+    /// Waits for something to happen in one of our rooms. Nothing is lost if it's dropped while
+    /// waiting, eg. to do something in a room instead, as long as `N::send` doesn't wait.
+    pub async fn next_event(&mut self) -> Result<RoomEvent, ClientError> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            let event = self.network.recv().await?;
+            self.receive(event).await?;
+        }
+    }
+
+    /// Subscribes to the room and brings its contract and us up to date with each other
+    async fn resume_room(&mut self, owner_vk: VerifyingKey) -> Result<(), ClientError> {
+        let room_data = self.rooms.map.get_mut(&owner_vk).expect("Room to resume");
+        room_data.decrypt_room_secrets(&mut self.key_store).await?;
+        let announce = match room_data.pending_upgrade.take() {
+            Some(PendingUpgrade::Announce {
+                owner_vk,
+                room_state,
+            }) => Some(NetworkRequest::Put {
+                owner_vk,
+                state: *room_state,
+            }),
+            pending_upgrade => {
+                room_data.pending_upgrade = pending_upgrade;
+                None
+            }
+        };
+        // Rooms we're joining or have no state of yet are fetched rather than sent
+        let request = if room_data.pending_join.is_some() || room_data.pending_upgrade.is_some() {
+            NetworkRequest::Get { owner_vk }
+        } else {
+            NetworkRequest::Put {
+                owner_vk,
+                state: room_data.state(),
+            }
+        };
+        self.network.send(request).await?;
+        self.subscribe(owner_vk).await?;
+        // The old contract learns where the room went once the new one exists
+        if let Some(announce) = announce {
+            self.network.send(announce).await?;
+        }
+        Ok(())
+    }
+
+    async fn receive(&mut self, event: NetworkEvent) -> Result<(), ClientError> {
+        let owner_vk = match &event {
+            NetworkEvent::State { owner_vk, .. } | NetworkEvent::Delta { owner_vk, .. } => {
+                *owner_vk
+            }
+        };
+        let Some(room_data) = self.rooms.map.get_mut(&owner_vk) else {
+            return Ok(());
+        };
+        let joining = room_data.pending_join.is_some();
+        let state_before = room_data.state();
+        match event {
+            NetworkEvent::State { state, .. } => {
+                room_data
+                    .receive_state(state.clone(), &mut self.key_store)
+                    .await?;
+                // The contract may be missing what only we have, eg. our membership if we joined
+                if room_data.state() != state {
+                    let state = room_data.state();
+                    self.network
+                        .send(NetworkRequest::Put { owner_vk, state })
+                        .await?;
+                }
+            }
+            NetworkEvent::Delta { delta, .. } => {
+                // Deltas from a contract we're still fetching find nothing to apply to
+                if joining || room_data.pending_upgrade.is_some() {
+                    return Ok(());
+                }
+                let room_before = room_data.room_state.clone();
+                room_data.apply_state_delta(delta)?;
+                let contract_state = room_data.room_state.clone();
+                // Eg. giving a member who just joined the room secret, if we're the owner
+                room_data
+                    .update_room_secrets(&room_before, &mut self.key_store)
+                    .await?;
+                let parameters = room_data.parameters();
+                let contract_summary = contract_state.summarize(&contract_state, &parameters);
+                if let Some(delta) = room_data.room_state.delta(
+                    &room_data.room_state,
+                    &parameters,
+                    &contract_summary,
+                ) {
+                    self.send_delta(owner_vk, delta).await?;
+                }
+            }
+        }
+        if joining {
+            self.events.push_back(RoomEvent::Joined { room: owner_vk });
+            self.subscribe(owner_vk).await?;
+        }
+        self.push_changes(owner_vk, &state_before.into_latest().room);
+        for (room, new_room) in self.rooms.follow_upgrades() {
+            self.events.push_back(RoomEvent::Moved { room, new_room });
+            let self_vk = self.rooms.map[&new_room].self_vk;
+            self.key_store.store_room(&new_room, &self_vk).await?;
+            self.key_store.remove_room(&room).await?;
+            self.fetch(new_room).await?;
+        }
+        Ok(())
+    }
+
+    /// Queues events for what changed in the room since `room_before`
+    fn push_changes(&mut self, owner_vk: VerifyingKey, room_before: &ChatRoomStateV1) {
+        let room_data = &self.rooms.map[&owner_vk];
+        if room_data.room_state == *room_before {
+            return;
+        }
+        let seen: HashSet<MessageId> = room_before
+            .recent_messages
+            .messages
+            .entries
+            .iter()
+            .map(|message| message.id())
+            .collect();
+        let self_id = room_data.self_id();
+        let secrets = &room_data.room_secrets;
+        for message in &room_data.room_state.recent_messages.messages.entries {
+            if seen.contains(&message.id()) || message.message.author() == self_id {
+                continue;
+            }
+            self.events.push_back(RoomEvent::Message {
+                room: owner_vk,
+                message: message.clone(),
+                content: RoomData::open_content(secrets, message.message.content()),
+            });
+        }
+        self.events.push_back(RoomEvent::Updated { room: owner_vk });
+    }
+
+    async fn send_delta(
+        &mut self,
+        owner_vk: VerifyingKey,
+        delta: ChatRoomStateV1Delta,
+    ) -> Result<(), ClientError> {
+        let delta = ChatRoomStateDelta::V2(ChatRoomStateV2Delta {
+            room: Some(delta),
+            pinned_messages: None,
+        });
+        self.network
+            .send(NetworkRequest::Update { owner_vk, delta })
+            .await?;
+        Ok(())
+    }
+
+    /// Asks for the state of a room we have no state of yet, and to be kept up to date with it
+    async fn fetch(&mut self, owner_vk: VerifyingKey) -> Result<(), ClientError> {
+        self.network.send(NetworkRequest::Get { owner_vk }).await?;
+        self.subscribe(owner_vk).await
+    }
+
+    async fn subscribe(&mut self, owner_vk: VerifyingKey) -> Result<(), ClientError> {
+        self.network
+            .send(NetworkRequest::Subscribe { owner_vk })
+            .await?;
+        if let Some(room_data) = self.rooms.map.get_mut(&owner_vk) {
+            room_data.sync_status = RoomSyncStatus::Subscribed;
+        }
+        Ok(())
+    }
+
+    fn room(&self, owner_vk: &VerifyingKey) -> Result<&RoomData, ClientError> {
+        self.rooms
+            .map
+            .get(owner_vk)
+            .ok_or(ClientError::UnknownRoom {
+                room_owner: owner_vk.into(),
+            })
+    }
+
+    /// The room along with the key store to sign what we do in it
+    fn room_mut(
+        &mut self,
+        owner_vk: &VerifyingKey,
+    ) -> Result<(&mut RoomData, &mut K), ClientError> {
+        let room_data = self
+            .rooms
+            .map
+            .get_mut(owner_vk)
+            .ok_or(ClientError::UnknownRoom {
+                room_owner: owner_vk.into(),
+            })?;
+        Ok((room_data, &mut self.key_store))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_store::LocalKeyStore;
+    use crate::network::LocalNode;
+    use crate::room_data::SendMessageError;
+    use common::room_state::member::MemberId;
+    use common::room_state::message::{MessageAction, MessageContent};
+    use futures::FutureExt;
+    use std::time::{Duration, SystemTime};
+
+    /// Every event that's arrived so far
+    fn events<N: Network, K: KeyStore>(client: &mut RiverClient<N, K>) -> Vec<RoomEvent> {
+        std::iter::from_fn(|| client.next_event().now_or_never())
+            .map(|event| event.unwrap())
+            .collect()
+    }
+
+    fn messages(events: &[RoomEvent]) -> Vec<String> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                RoomEvent::Message { content, .. } => Some(content.clone().unwrap()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn client(node: &LocalNode) -> RiverClient<impl Network, LocalKeyStore> {
+        RiverClient::new(node.connect(), LocalKeyStore::default(), Rooms::default())
+    }
+
+    /// A room created by alice that bob was invited to by his key
+    fn room_with_member(
+        node: &LocalNode,
+    ) -> (
+        RiverClient<impl Network, LocalKeyStore>,
+        RiverClient<impl Network, LocalKeyStore>,
+        VerifyingKey,
+        SigningKey,
+    ) {
+        let mut alice = client(node);
+        let mut bob = client(node);
+        let bob_sk = SigningKey::generate(&mut rand::thread_rng());
+        let room = alice
+            .create_room("Room".into(), "Alice".into(), PrivacyMode::Public)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        alice
+            .invite(&room, bob_sk.verifying_key())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        bob.add_room(room, bob_sk.clone())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert!(events(&mut bob).contains(&RoomEvent::Updated { room }));
+        (alice, bob, room, bob_sk)
+    }
+
+    #[test]
+    fn test_post_and_receive() {
+        let node = LocalNode::default();
+        let (mut alice, mut bob, room, _) = room_with_member(&node);
+        events(&mut alice);
+
+        bob.set_nickname(&room, "Bob".into())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        bob.post(&room, "Hello".into(), None)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let received = events(&mut alice);
+        assert_eq!(messages(&received), vec!["Hello".to_string()]);
+        // Our own messages aren't events
+        assert!(messages(&events(&mut bob)).is_empty());
+
+        let state = node.state(&room).unwrap().into_latest();
+        assert_eq!(state.room, alice.rooms().map[&room].room_state);
+        assert!(state
+            .room
+            .member_info
+            .member_info
+            .iter()
+            .any(|info| info.member_info.preferred_nickname == "Bob"));
+    }
+
+    #[test]
+    fn test_join_private_room_by_invitation() {
+        let node = LocalNode::default();
+        let mut alice = client(&node);
+        let mut bob = client(&node);
+        let room = alice
+            .create_room("Room".into(), "Alice".into(), PrivacyMode::Private)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let token = alice
+            .invitation(&room, SystemTime::now() + Duration::from_secs(3600), None)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+
+        bob.join(&token, "Bob".into())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert!(events(&mut bob).contains(&RoomEvent::Joined { room }));
+        // Alice sees Bob join and gives him the room secret
+        events(&mut alice);
+        events(&mut bob);
+        alice
+            .post(&room, "Secret".into(), None)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(messages(&events(&mut bob)), vec!["Secret".to_string()]);
+        assert!(bob.rooms().map[&room].can_send_message().is_ok());
+    }
+
+    #[test]
+    fn test_hand_over_private_room() {
+        let node = LocalNode::default();
+        let mut alice = client(&node);
+        let mut bob = client(&node);
+        let room = alice
+            .create_room("Room".into(), "Alice".into(), PrivacyMode::Private)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let token = alice
+            .invitation(&room, SystemTime::now() + Duration::from_secs(3600), None)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        bob.join(&token, "Bob".into())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert!(events(&mut bob).contains(&RoomEvent::Joined { room }));
+        events(&mut alice);
+        events(&mut bob);
+        // What Alice posts stays behind once she hands the room over
+        alice
+            .post(&room, "Hello".into(), None)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        events(&mut bob);
+        bob.post(&room, "Secret".into(), None)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        events(&mut alice);
+
+        // The fresh key the room is handed over to is given the secrets and can read what's there
+        let room_data = &alice.rooms.map[&room];
+        let mut new_room = room_data
+            .hand_over(&mut alice.key_store)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let new_owner_id = MemberId::from(&new_room.owner_vk);
+        assert_ne!(new_room.owner_vk, room);
+        assert_eq!(
+            new_room
+                .room_state
+                .secrets
+                .secrets_for(new_owner_id)
+                .count(),
+            room_data.room_state.secrets.versions().count()
+        );
+        new_room.room_secrets.clear();
+        new_room
+            .decrypt_room_secrets(&mut alice.key_store)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let secrets = &new_room.room_secrets;
+        assert_eq!(*secrets, room_data.room_secrets);
+        let contents: Vec<String> = new_room
+            .room_state
+            .recent_messages
+            .messages
+            .entries
+            .iter()
+            .map(|m| RoomData::open_content(secrets, m.message.content()).unwrap())
+            .collect();
+        assert_eq!(contents, vec!["Secret".to_string()]);
+    }
+
+    #[test]
+    fn test_banned_member_cannot_post() {
+        let node = LocalNode::default();
+        let (mut alice, mut bob, room, bob_sk) = room_with_member(&node);
+        alice
+            .ban(&room, bob_sk.verifying_key().into(), None)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        events(&mut bob);
+
+        // Bans take the member out of the room
+        assert_eq!(
+            bob.post(&room, "Hello".into(), None)
+                .now_or_never()
+                .unwrap(),
+            Err(ClientError::CannotSend(SendMessageError::UserNotMember))
+        );
+    }
+
+    #[test]
+    fn test_edit_and_react() {
+        let node = LocalNode::default();
+        let (mut alice, mut bob, room, _) = room_with_member(&node);
+        let message_id = bob
+            .post(&room, "Helo".into(), None)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        events(&mut alice);
+
+        let edit = MessageAction::Edit {
+            content: "Hello".into(),
+        };
+        bob.act_on_message(&room, message_id.clone(), edit)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        alice
+            .react(&room, message_id.clone(), "👍".into())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        events(&mut alice);
+        events(&mut bob);
+
+        let room_state = &alice.rooms().map[&room].room_state;
+        assert!(bob.rooms().map[&room].room_state == *room_state);
+        let messages = &room_state.recent_messages;
+        assert_eq!(
+            messages.content(&messages.messages.entries[0]),
+            MessageContent::Edited("Hello")
+        );
+        assert_eq!(
+            room_state.reactions.for_message(&message_id),
+            vec![("👍", vec![MemberId::from(&room)])]
+        );
+    }
+
+    #[test]
+    fn test_hand_over_moves_members() {
+        let node = LocalNode::default();
+        let (mut alice, mut bob, room, _) = room_with_member(&node);
+        events(&mut alice);
+
+        let new_room = alice.hand_over(&room).now_or_never().unwrap().unwrap();
+        assert!(!alice.rooms().map.contains_key(&room));
+        assert!(events(&mut bob).contains(&RoomEvent::Moved { room, new_room }));
+
+        // Bob is still a member under the new owner
+        bob.post(&new_room, "Hello".into(), None)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(messages(&events(&mut alice)), vec!["Hello".to_string()]);
+
+        // Both key stores keep the room under its new owner
+        assert_eq!(
+            alice.key_store.rooms.keys().collect::<Vec<_>>(),
+            vec![&new_room]
+        );
+        assert_eq!(
+            bob.key_store.rooms.keys().collect::<Vec<_>>(),
+            vec![&new_room]
+        );
+    }
+
+    #[test]
+    fn test_resume_fetches_kept_rooms() {
+        let node = LocalNode::default();
+        let (_alice, bob, room, bob_sk) = room_with_member(&node);
+        let (rooms, key_store) = bob.into_parts();
+        assert_eq!(key_store.rooms[&room], bob_sk.verifying_key());
+
+        // Another device with the same key store but none of the rooms
+        let mut bob = RiverClient::new(node.connect(), key_store, Rooms::default());
+        bob.resume().now_or_never().unwrap().unwrap();
+        assert!(events(&mut bob).contains(&RoomEvent::Updated { room }));
+        let room_data = &bob.rooms().map[&room];
+        assert_eq!(room_data.self_vk, bob_sk.verifying_key());
+        assert!(room_data.room_state == rooms.map[&room].room_state);
+    }
+}
@@ -0,0 +1,201 @@
+//! What we do in a room, made to our state of it by `RoomData` and sent to its contract
+
+use super::RiverClient;
+use crate::error::ClientError;
+use crate::key_store::KeyStore;
+use crate::network::Network;
+use common::chat_delegate::SignRequest;
+use common::room_state::configuration::ModeratorPermissions;
+use common::room_state::member::{AuthorizedInvitation, Invitation, InvitationToken, MemberId};
+use common::room_state::message::{MessageAction, MessageId};
+use common::ChatRoomStateV1;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use freenet_scaffold::ComposableState;
+use std::time::SystemTime;
+
+impl<N: Network, K: KeyStore> RiverClient<N, K> {
+    pub async fn invite(
+        &mut self,
+        room: &VerifyingKey,
+        member_vk: VerifyingKey,
+    ) -> Result<(), ClientError> {
+        let (room_data, key_store) = self.room_mut(room)?;
+        let delta = room_data.invite_member(member_vk, key_store).await?;
+        self.send_delta(*room, delta).await
+    }
+
+    /// An invitation anyone can redeem to join the room as invited by us, see `RiverClient::join`
+    pub async fn invitation(
+        &mut self,
+        room: &VerifyingKey,
+        expires_at: SystemTime,
+        max_uses: Option<u32>,
+    ) -> Result<InvitationToken, ClientError> {
+        let (room_data, key_store) = self.room_mut(room)?;
+        let invitation_sk = SigningKey::generate(&mut rand::thread_rng());
+        let invitation = Invitation {
+            owner_member_id: room.into(),
+            invited_by: room_data.self_id(),
+            invitation_vk: invitation_sk.verifying_key(),
+            expires_at,
+            max_uses,
+        };
+        Ok(InvitationToken {
+            room_owner: *room,
+            invitation: AuthorizedInvitation {
+                signature: key_store
+                    .sign(
+                        &room_data.self_vk,
+                        SignRequest::Invitation(invitation.clone()),
+                    )
+                    .await?,
+                invitation,
+            },
+            invitation_sk,
+        })
+    }
+
+    pub async fn post(
+        &mut self,
+        room: &VerifyingKey,
+        content: String,
+        in_reply_to: Option<MessageId>,
+    ) -> Result<MessageId, ClientError> {
+        let (room_data, key_store) = self.room_mut(room)?;
+        let (message_id, delta) = room_data
+            .post_message(content, in_reply_to, key_store)
+            .await?;
+        self.send_delta(*room, delta).await?;
+        Ok(message_id)
+    }
+
+    /// Edits or deletes a message, see `RoomData::act_on_message`
+    pub async fn act_on_message(
+        &mut self,
+        room: &VerifyingKey,
+        target: MessageId,
+        action: MessageAction,
+    ) -> Result<(), ClientError> {
+        let (room_data, key_store) = self.room_mut(room)?;
+        let delta = room_data.act_on_message(target, action, key_store).await?;
+        self.send_delta(*room, delta).await
+    }
+
+    pub async fn react(
+        &mut self,
+        room: &VerifyingKey,
+        message_id: MessageId,
+        reaction: String,
+    ) -> Result<(), ClientError> {
+        let (room_data, key_store) = self.room_mut(room)?;
+        let delta = room_data.react(message_id, reaction, key_store).await?;
+        self.send_delta(*room, delta).await
+    }
+
+    pub async fn ban(
+        &mut self,
+        room: &VerifyingKey,
+        member_id: MemberId,
+        expires_at: Option<SystemTime>,
+    ) -> Result<(), ClientError> {
+        let (room_data, key_store) = self.room_mut(room)?;
+        let delta = room_data
+            .ban_member(member_id, expires_at, key_store)
+            .await?;
+        self.send_delta(*room, delta).await
+    }
+
+    pub async fn unban(
+        &mut self,
+        room: &VerifyingKey,
+        member_id: MemberId,
+    ) -> Result<(), ClientError> {
+        let (room_data, key_store) = self.room_mut(room)?;
+        let state_before = room_data.room_state.clone();
+        room_data.unban_member(member_id, key_store).await?;
+        self.send_changes(room, &state_before).await
+    }
+
+    /// Takes a member out of the room, see `RoomData::remove_member`
+    pub async fn remove_member(
+        &mut self,
+        room: &VerifyingKey,
+        member_vk: VerifyingKey,
+    ) -> Result<(), ClientError> {
+        let (room_data, key_store) = self.room_mut(room)?;
+        let state_before = room_data.room_state.clone();
+        room_data.remove_member(member_vk, key_store).await?;
+        self.send_changes(room, &state_before).await
+    }
+
+    /// Leaves the room, which we keep what we have of
+    pub async fn leave(&mut self, room: &VerifyingKey) -> Result<(), ClientError> {
+        let self_vk = self.room(room)?.self_vk;
+        self.remove_member(room, self_vk).await
+    }
+
+    pub async fn set_nickname(
+        &mut self,
+        room: &VerifyingKey,
+        nickname: String,
+    ) -> Result<(), ClientError> {
+        let (room_data, key_store) = self.room_mut(room)?;
+        let delta = room_data.set_nickname(nickname, key_store).await?;
+        self.send_delta(*room, delta).await
+    }
+
+    pub async fn rename_room(
+        &mut self,
+        room: &VerifyingKey,
+        name: String,
+    ) -> Result<(), ClientError> {
+        let (room_data, key_store) = self.room_mut(room)?;
+        let state_before = room_data.room_state.clone();
+        room_data.rename_room(name, key_store).await?;
+        self.send_changes(room, &state_before).await
+    }
+
+    pub async fn set_moderator(
+        &mut self,
+        room: &VerifyingKey,
+        member_id: MemberId,
+        permissions: ModeratorPermissions,
+    ) -> Result<(), ClientError> {
+        let (room_data, key_store) = self.room_mut(room)?;
+        let state_before = room_data.room_state.clone();
+        room_data
+            .set_moderator(member_id, permissions, key_store)
+            .await?;
+        self.send_changes(room, &state_before).await
+    }
+
+    pub async fn set_successor(
+        &mut self,
+        room: &VerifyingKey,
+        successor: Option<VerifyingKey>,
+    ) -> Result<(), ClientError> {
+        let (room_data, key_store) = self.room_mut(room)?;
+        let state_before = room_data.room_state.clone();
+        room_data.set_successor(successor, key_store).await?;
+        self.send_changes(room, &state_before).await
+    }
+
+    /// Sends the contract everything that changed in our state of the room since `state_before`,
+    /// for changes `RoomData` doesn't return a delta for
+    async fn send_changes(
+        &mut self,
+        room: &VerifyingKey,
+        state_before: &ChatRoomStateV1,
+    ) -> Result<(), ClientError> {
+        let room_data = self.room(room)?;
+        let parameters = room_data.parameters();
+        let summary_before = state_before.summarize(state_before, &parameters);
+        match room_data
+            .room_state
+            .delta(&room_data.room_state, &parameters, &summary_before)
+        {
+            Some(delta) => self.send_delta(*room, delta).await,
+            None => Ok(()),
+        }
+    }
+}
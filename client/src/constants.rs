@@ -0,0 +1,5 @@
+/// Prefix of a member's key as it's shared to be invited
+pub const KEY_VERSION_PREFIX: &str = "river:v1:user:vk:";
+
+pub const ROOM_CONTRACT_WASM: &[u8] =
+    include_bytes!("../../target/wasm32-unknown-unknown/release/room_contract.wasm");
@@ -0,0 +1,62 @@
+use crate::key_store::KeyStoreError;
+use crate::network::NetworkError;
+use crate::room_data::SendMessageError;
+use common::room_state::error::RoomStateError;
+use common::room_state::member::MemberId;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum ClientError {
+    /// We aren't in the room owned by `room_owner`
+    UnknownRoom {
+        room_owner: MemberId,
+    },
+    CannotSend(SendMessageError),
+    /// The change doesn't apply to our state of the room
+    State(RoomStateError),
+    /// A member key that isn't a key after `KEY_VERSION_PREFIX`
+    InvalidKey(String),
+    Network(NetworkError),
+    KeyStore(KeyStoreError),
+}
+
+impl From<SendMessageError> for ClientError {
+    fn from(error: SendMessageError) -> Self {
+        ClientError::CannotSend(error)
+    }
+}
+
+impl From<RoomStateError> for ClientError {
+    fn from(error: RoomStateError) -> Self {
+        ClientError::State(error)
+    }
+}
+
+impl From<NetworkError> for ClientError {
+    fn from(error: NetworkError) -> Self {
+        ClientError::Network(error)
+    }
+}
+
+impl From<KeyStoreError> for ClientError {
+    fn from(error: KeyStoreError) -> Self {
+        ClientError::KeyStore(error)
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::UnknownRoom { room_owner } => {
+                write!(f, "Not in the room owned by {}", room_owner)
+            }
+            ClientError::CannotSend(error) => write!(f, "Can't send: {}", error),
+            ClientError::State(error) => write!(f, "{}", error),
+            ClientError::InvalidKey(error) => write!(f, "Invalid member key: {}", error),
+            ClientError::Network(error) => write!(f, "{}", error),
+            ClientError::KeyStore(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
@@ -0,0 +1,23 @@
+use crate::room_data::OpenContentError;
+use common::room_state::message::AuthorizedMessageV1;
+use ed25519_dalek::VerifyingKey;
+
+/// What happened in our rooms, each identified by its owner's key
+#[derive(Clone, Debug, PartialEq)]
+pub enum RoomEvent {
+    /// The room's state arrived after redeeming an invitation and we've added ourselves to it
+    Joined { room: VerifyingKey },
+    /// A message from another member, with its content decrypted if the room is private
+    Message {
+        room: VerifyingKey,
+        message: AuthorizedMessageV1,
+        content: Result<String, OpenContentError>,
+    },
+    /// Anything else changed, eg. members, nicknames or bans
+    Updated { room: VerifyingKey },
+    /// The room was upgraded to a new contract, now owned by `new_room`
+    Moved {
+        room: VerifyingKey,
+        new_room: VerifyingKey,
+    },
+}
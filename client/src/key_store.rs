@@ -0,0 +1,212 @@
+//! Where our signing keys are kept. Rooms only know our verifying key in them and whatever we do is
+//! signed by the key store, in the browser the chat delegate so that the UI never holds a key.
+
+use common::chat_delegate::{check_succession, ChatDelegateError, KeptRoom, SignRequest};
+use common::keyring::{EncryptedKeyring, Keyring, KeyringKey};
+use common::room_state::member::MemberId;
+use common::util::diffie_hellman;
+use common::ChatRoomStateV1;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::{self, Future};
+
+pub trait KeyStore {
+    /// Keeps `self_sk` to sign with as its verifying key
+    fn store(&mut self, self_sk: SigningKey) -> impl Future<Output = Result<(), KeyStoreError>>;
+
+    /// Signs what we do in a room kept with our key, as `common::util::sign_struct` would, see
+    /// `SignRequest::check` for what's refused
+    fn sign(
+        &mut self,
+        self_vk: &VerifyingKey,
+        request: SignRequest,
+    ) -> impl Future<Output = Result<Signature, KeyStoreError>>;
+
+    /// Signs `ChatRoomStateV1::succession_payloads` for us to take the room owned by `owner_vk`
+    /// over, see `common::chat_delegate::check_succession`
+    fn sign_succession(
+        &mut self,
+        self_vk: &VerifyingKey,
+        owner_vk: &VerifyingKey,
+        room_state: &ChatRoomStateV1,
+    ) -> impl Future<Output = Result<Vec<Signature>, KeyStoreError>>;
+
+    /// The secret our key shares with `public_key`, see `common::util::diffie_hellman`
+    fn shared_secret(
+        &mut self,
+        self_vk: &VerifyingKey,
+        public_key: [u8; 32],
+    ) -> impl Future<Output = Result<[u8; 32], KeyStoreError>>;
+
+    /// Our key in every room kept, encrypted with `key` to export our identity
+    fn export(
+        &mut self,
+        key: &KeyringKey,
+    ) -> impl Future<Output = Result<EncryptedKeyring, KeyStoreError>>;
+
+    /// Keeps that we're in the room owned by `owner_vk` with `self_vk`, a key that's kept
+    fn store_room(
+        &mut self,
+        owner_vk: &VerifyingKey,
+        self_vk: &VerifyingKey,
+    ) -> impl Future<Output = Result<(), KeyStoreError>>;
+
+    /// Forgets the room, our key in it is kept
+    fn remove_room(
+        &mut self,
+        owner_vk: &VerifyingKey,
+    ) -> impl Future<Output = Result<(), KeyStoreError>>;
+
+    /// The rooms kept, by owner key with our key in each
+    fn rooms(
+        &mut self,
+    ) -> impl Future<Output = Result<HashMap<VerifyingKey, VerifyingKey>, KeyStoreError>>;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeyStoreError {
+    /// The key isn't kept, eg. it was removed on another device
+    UnknownKey { member: MemberId },
+    /// The key store can't be reached or turned the request down
+    Unavailable(String),
+    /// Something that isn't ours to sign, see `SignRequest::check`
+    Refused(String),
+}
+
+/// Keys kept in memory, for the CLI, tests and working without a node
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct LocalKeyStore {
+    pub keys: HashMap<VerifyingKey, SigningKey>,
+    /// Our key in each room, by the room owner's key
+    #[serde(default)]
+    pub rooms: HashMap<VerifyingKey, VerifyingKey>,
+}
+
+impl LocalKeyStore {
+    fn key(&self, self_vk: &VerifyingKey) -> Result<&SigningKey, KeyStoreError> {
+        self.keys.get(self_vk).ok_or(KeyStoreError::UnknownKey {
+            member: self_vk.into(),
+        })
+    }
+
+    fn kept_rooms(&self) -> Vec<KeptRoom> {
+        self.rooms
+            .iter()
+            .map(|(owner_vk, self_vk)| KeptRoom {
+                owner_vk: *owner_vk,
+                self_vk: *self_vk,
+            })
+            .collect()
+    }
+}
+
+impl KeyStore for LocalKeyStore {
+    fn store(&mut self, self_sk: SigningKey) -> impl Future<Output = Result<(), KeyStoreError>> {
+        self.keys.insert(self_sk.verifying_key(), self_sk);
+        future::ready(Ok(()))
+    }
+
+    fn sign(
+        &mut self,
+        self_vk: &VerifyingKey,
+        request: SignRequest,
+    ) -> impl Future<Output = Result<Signature, KeyStoreError>> {
+        future::ready(self.key(self_vk).and_then(|self_sk| {
+            request.check(self_vk, &self.kept_rooms())?;
+            Ok(request.sign(self_sk))
+        }))
+    }
+
+    fn sign_succession(
+        &mut self,
+        self_vk: &VerifyingKey,
+        owner_vk: &VerifyingKey,
+        room_state: &ChatRoomStateV1,
+    ) -> impl Future<Output = Result<Vec<Signature>, KeyStoreError>> {
+        future::ready(self.key(self_vk).and_then(|self_sk| {
+            let payloads = check_succession(self_vk, owner_vk, room_state, &self.kept_rooms())?;
+            Ok(payloads
+                .iter()
+                .map(|payload| self_sk.sign(payload))
+                .collect())
+        }))
+    }
+
+    fn shared_secret(
+        &mut self,
+        self_vk: &VerifyingKey,
+        public_key: [u8; 32],
+    ) -> impl Future<Output = Result<[u8; 32], KeyStoreError>> {
+        future::ready(
+            self.key(self_vk)
+                .map(|self_sk| diffie_hellman(self_sk, &public_key)),
+        )
+    }
+
+    fn export(
+        &mut self,
+        key: &KeyringKey,
+    ) -> impl Future<Output = Result<EncryptedKeyring, KeyStoreError>> {
+        let keyring = Keyring {
+            keys: self
+                .rooms
+                .iter()
+                .filter_map(|(owner_vk, self_vk)| {
+                    Some((*owner_vk, self.keys.get(self_vk)?.clone()))
+                })
+                .collect(),
+        };
+        future::ready(Ok(key.encrypt(&keyring)))
+    }
+
+    fn store_room(
+        &mut self,
+        owner_vk: &VerifyingKey,
+        self_vk: &VerifyingKey,
+    ) -> impl Future<Output = Result<(), KeyStoreError>> {
+        let stored = self.key(self_vk).map(|_| ());
+        if stored.is_ok() {
+            self.rooms.insert(*owner_vk, *self_vk);
+        }
+        future::ready(stored)
+    }
+
+    fn remove_room(
+        &mut self,
+        owner_vk: &VerifyingKey,
+    ) -> impl Future<Output = Result<(), KeyStoreError>> {
+        self.rooms.remove(owner_vk);
+        future::ready(Ok(()))
+    }
+
+    fn rooms(
+        &mut self,
+    ) -> impl Future<Output = Result<HashMap<VerifyingKey, VerifyingKey>, KeyStoreError>> {
+        future::ready(Ok(self.rooms.clone()))
+    }
+}
+
+impl fmt::Display for KeyStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyStoreError::UnknownKey { member } => write!(f, "No key for member {}", member),
+            KeyStoreError::Unavailable(error) => write!(f, "Key store unavailable: {}", error),
+            KeyStoreError::Refused(reason) => write!(f, "Refused to sign: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for KeyStoreError {}
+
+/// The chat delegate turning a request down, see `common::chat_delegate`
+impl From<ChatDelegateError> for KeyStoreError {
+    fn from(error: ChatDelegateError) -> Self {
+        match error {
+            ChatDelegateError::UnknownKey { member } => KeyStoreError::UnknownKey { member },
+            ChatDelegateError::Malformed(_) => KeyStoreError::Unavailable(error.to_string()),
+            ChatDelegateError::Refused(reason) => KeyStoreError::Refused(reason),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+//! Our rooms and what we do in them, kept in sync with the room contracts over whichever `Network`
+//! reaches them and signed by whichever `KeyStore` keeps our keys. The web UI, bots and tests all
+//! run on `RiverClient`.
+
+mod client;
+pub mod constants;
+mod error;
+mod event;
+mod key_store;
+mod member_key;
+pub mod network;
+pub mod room_data;
+pub mod util;
+
+pub use client::RiverClient;
+pub use common::keyring::Keyring;
+pub use error::ClientError;
+pub use event::RoomEvent;
+pub use key_store::{KeyStore, KeyStoreError, LocalKeyStore};
+pub use member_key::{decode_member_key, encode_member_key};
@@ -0,0 +1,25 @@
+use crate::constants::KEY_VERSION_PREFIX;
+use crate::error::ClientError;
+use ed25519_dalek::VerifyingKey;
+
+/// A member's key as it's shared to be invited to a room
+pub fn encode_member_key(member_vk: &VerifyingKey) -> String {
+    format!(
+        "{}{}",
+        KEY_VERSION_PREFIX,
+        bs58::encode(member_vk.as_bytes()).into_string()
+    )
+}
+
+pub fn decode_member_key(encoded: &str) -> Result<VerifyingKey, ClientError> {
+    let encoded = encoded
+        .trim()
+        .strip_prefix(KEY_VERSION_PREFIX)
+        .ok_or_else(|| ClientError::InvalidKey(format!("expected {}...", KEY_VERSION_PREFIX)))?;
+    let bytes: [u8; 32] = bs58::decode(encoded)
+        .into_vec()
+        .map_err(|e| ClientError::InvalidKey(e.to_string()))?
+        .try_into()
+        .map_err(|_| ClientError::InvalidKey("wrong length".to_string()))?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| ClientError::InvalidKey(e.to_string()))
+}
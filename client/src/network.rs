@@ -0,0 +1,77 @@
+//! How the client reaches room contracts. A room's contract is keyed by its owner's key, see
+//! `room_contract_key`, and behaves as the room contract does: states sent to it are merged,
+//! deltas applied, and subscribers are sent whatever changed.
+
+use common::room_state::error::RoomStateError;
+use common::room_state::versioned::ChatRoomStateDelta;
+use common::ChatRoomState;
+use ed25519_dalek::VerifyingKey;
+use std::fmt;
+use std::future::Future;
+
+mod contracts;
+#[cfg(feature = "node")]
+mod freenet;
+mod local;
+
+pub use contracts::{node_error, RoomContracts};
+#[cfg(feature = "node")]
+pub use freenet::FreenetNode;
+pub use local::{LocalConnection, LocalNode};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NetworkRequest {
+    /// Creates the room's contract with `state`, or merges it into the contract's state
+    Put {
+        owner_vk: VerifyingKey,
+        state: ChatRoomState,
+    },
+    Update {
+        owner_vk: VerifyingKey,
+        delta: ChatRoomStateDelta,
+    },
+    /// Asks for the contract's state, which arrives as `NetworkEvent::State`
+    Get { owner_vk: VerifyingKey },
+    /// Asks to be sent every change to the contract's state as `NetworkEvent::Delta`
+    Subscribe { owner_vk: VerifyingKey },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NetworkEvent {
+    State {
+        owner_vk: VerifyingKey,
+        state: ChatRoomState,
+    },
+    Delta {
+        owner_vk: VerifyingKey,
+        delta: ChatRoomStateDelta,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NetworkError {
+    Disconnected,
+    /// The contract refused a state or delta we sent it
+    Rejected(RoomStateError),
+    /// Anything else the node reports, eg. that it doesn't have the contract
+    Node(String),
+}
+
+pub trait Network {
+    fn send(&mut self, request: NetworkRequest) -> impl Future<Output = Result<(), NetworkError>>;
+
+    /// The next event from contracts we've asked for, or an error a request of ours ran into
+    fn recv(&mut self) -> impl Future<Output = Result<NetworkEvent, NetworkError>>;
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkError::Disconnected => write!(f, "Disconnected from the node"),
+            NetworkError::Rejected(error) => write!(f, "Rejected by the room: {}", error),
+            NetworkError::Node(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
@@ -0,0 +1,107 @@
+use super::{NetworkError, NetworkEvent, NetworkRequest};
+use crate::constants::ROOM_CONTRACT_WASM;
+use crate::room_data::room_contract_key;
+use crate::util::to_cbor_vec;
+use common::room_state::error::RoomStateError;
+use common::room_state::versioned::ChatRoomStateDelta;
+use common::room_state::ChatRoomParametersV1;
+use common::ChatRoomState;
+use ed25519_dalek::VerifyingKey;
+use freenet_stdlib::client_api::{ContractRequest, ContractResponse, HostResponse};
+use freenet_stdlib::prelude::{
+    ContractCode, ContractContainer, ContractKey, ContractWasmAPIVersion, Parameters,
+    RelatedContracts, StateDelta, UpdateData, WrappedContract, WrappedState,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Translates between our requests and events and a Freenet node's API, whichever way the node is
+/// reached. The node's responses only name contracts by key, so the owner of every room contract
+/// we've asked about is kept.
+#[derive(Default)]
+pub struct RoomContracts {
+    owners: HashMap<ContractKey, VerifyingKey>,
+}
+
+impl RoomContracts {
+    /// The request to send the node for `request`
+    pub fn request(&mut self, request: NetworkRequest) -> ContractRequest<'static> {
+        match request {
+            // Creates the contract, or merges the state into it if it exists
+            NetworkRequest::Put { owner_vk, state } => {
+                let parameters = ChatRoomParametersV1 { owner: owner_vk };
+                let contract = WrappedContract::new(
+                    Arc::new(ContractCode::from(ROOM_CONTRACT_WASM)),
+                    Parameters::from(to_cbor_vec(&parameters)),
+                );
+                self.contract_key(owner_vk);
+                ContractRequest::Put {
+                    contract: ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract)),
+                    state: WrappedState::new(to_cbor_vec(&state)),
+                    related_contracts: RelatedContracts::default(),
+                }
+            }
+            NetworkRequest::Update { owner_vk, delta } => ContractRequest::Update {
+                key: self.contract_key(owner_vk),
+                data: UpdateData::Delta(StateDelta::from(to_cbor_vec(&delta))),
+            },
+            NetworkRequest::Get { owner_vk } => ContractRequest::Get {
+                key: self.contract_key(owner_vk),
+                return_contract_code: false,
+                subscribe: false,
+            },
+            NetworkRequest::Subscribe { owner_vk } => ContractRequest::Subscribe {
+                key: self.contract_key(owner_vk),
+                summary: None,
+            },
+        }
+    }
+
+    /// The event a response from the node is, if it's about a room
+    pub fn event(&self, response: HostResponse) -> Option<Result<NetworkEvent, NetworkError>> {
+        let HostResponse::ContractResponse(response) = response else {
+            return None;
+        };
+        Some(match response {
+            ContractResponse::GetResponse { key, state, .. } => {
+                decode_state(*self.owners.get(&key)?, state.as_ref())
+            }
+            ContractResponse::UpdateNotification {
+                key,
+                update: UpdateData::State(state),
+            } => decode_state(*self.owners.get(&key)?, state.as_ref()),
+            ContractResponse::UpdateNotification {
+                key,
+                update: UpdateData::Delta(delta),
+            } => decode_delta(*self.owners.get(&key)?, delta.as_ref()),
+            _ => return None,
+        })
+    }
+
+    fn contract_key(&mut self, owner_vk: VerifyingKey) -> ContractKey {
+        let key = room_contract_key(&owner_vk);
+        self.owners.insert(key, owner_vk);
+        key
+    }
+}
+
+fn decode_state(owner_vk: VerifyingKey, bytes: &[u8]) -> Result<NetworkEvent, NetworkError> {
+    ChatRoomState::from_cbor(bytes)
+        .map(|state| NetworkEvent::State { owner_vk, state })
+        .map_err(|e| NetworkError::Node(format!("Undecodable room state: {}", e)))
+}
+
+fn decode_delta(owner_vk: VerifyingKey, bytes: &[u8]) -> Result<NetworkEvent, NetworkError> {
+    ChatRoomStateDelta::from_cbor(bytes)
+        .map(|delta| NetworkEvent::Delta { owner_vk, delta })
+        .map_err(|e| NetworkError::Node(format!("Undecodable room delta: {}", e)))
+}
+
+/// Why the room contract rejected a request rather than its encoding, if it did
+pub fn node_error(error: impl ToString) -> NetworkError {
+    let message = error.to_string();
+    match RoomStateError::from_reason(&message) {
+        Some(error) => NetworkError::Rejected(error),
+        None => NetworkError::Node(message),
+    }
+}
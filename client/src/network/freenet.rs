@@ -0,0 +1,37 @@
+use super::{node_error, Network, NetworkError, NetworkEvent, NetworkRequest, RoomContracts};
+use freenet_stdlib::client_api::WebApi;
+use std::future::Future;
+
+/// A Freenet node reached over its WebSocket API from outside the browser
+pub struct FreenetNode {
+    api: WebApi,
+    contracts: RoomContracts,
+}
+
+impl FreenetNode {
+    pub async fn connect(url: &str) -> Result<Self, NetworkError> {
+        let (stream, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .map_err(|e| NetworkError::Node(format!("Can't reach the node at {}: {}", url, e)))?;
+        Ok(FreenetNode {
+            api: WebApi::start(stream),
+            contracts: RoomContracts::default(),
+        })
+    }
+}
+
+impl Network for FreenetNode {
+    fn send(&mut self, request: NetworkRequest) -> impl Future<Output = Result<(), NetworkError>> {
+        let request = self.contracts.request(request);
+        async move { self.api.send(request.into()).await.map_err(node_error) }
+    }
+
+    async fn recv(&mut self) -> Result<NetworkEvent, NetworkError> {
+        loop {
+            let response = self.api.recv().await.map_err(node_error)?;
+            if let Some(event) = self.contracts.event(response) {
+                return event;
+            }
+        }
+    }
+}
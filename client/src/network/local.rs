@@ -0,0 +1,171 @@
+use super::{Network, NetworkError, NetworkEvent, NetworkRequest};
+use common::room_state::error::RoomStateError;
+use common::room_state::ChatRoomParametersV1;
+use common::ChatRoomState;
+use ed25519_dalek::VerifyingKey;
+use freenet_scaffold::ComposableState;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::future::{self, Future};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Poll, Waker};
+
+/// Room contracts run in memory, for tests and for working without a node. Every connection to
+/// the same node sees the same contracts.
+#[derive(Clone, Default)]
+pub struct LocalNode {
+    contracts: Arc<Mutex<Contracts>>,
+}
+
+pub struct LocalConnection {
+    node: LocalNode,
+    id: usize,
+}
+
+#[derive(Default)]
+struct Contracts {
+    states: HashMap<VerifyingKey, ChatRoomState>,
+    subscribers: HashMap<VerifyingKey, Vec<usize>>,
+    inboxes: Vec<Inbox>,
+}
+
+#[derive(Default)]
+struct Inbox {
+    events: VecDeque<Result<NetworkEvent, NetworkError>>,
+    waker: Option<Waker>,
+}
+
+impl LocalNode {
+    pub fn connect(&self) -> LocalConnection {
+        let mut contracts = self.lock();
+        contracts.inboxes.push(Inbox::default());
+        LocalConnection {
+            node: self.clone(),
+            id: contracts.inboxes.len() - 1,
+        }
+    }
+
+    /// A node whose room contracts have `states`, by their owner's key
+    pub fn with_states(states: HashMap<VerifyingKey, ChatRoomState>) -> Self {
+        let node = LocalNode::default();
+        node.lock().states = states;
+        node
+    }
+
+    /// The state of the room contract owned by `owner_vk`, if it's been created
+    pub fn state(&self, owner_vk: &VerifyingKey) -> Option<ChatRoomState> {
+        self.lock().states.get(owner_vk).cloned()
+    }
+
+    pub fn states(&self) -> HashMap<VerifyingKey, ChatRoomState> {
+        self.lock().states.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Contracts> {
+        self.contracts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Network for LocalConnection {
+    fn send(&mut self, request: NetworkRequest) -> impl Future<Output = Result<(), NetworkError>> {
+        let mut contracts = self.node.lock();
+        // As with a node, what goes wrong is reported along with the events
+        if let Err(error) = contracts.handle(self.id, request) {
+            contracts.push(self.id, Err(error));
+        }
+        future::ready(Ok(()))
+    }
+
+    fn recv(&mut self) -> impl Future<Output = Result<NetworkEvent, NetworkError>> {
+        future::poll_fn(|cx| {
+            let mut contracts = self.node.lock();
+            let inbox = &mut contracts.inboxes[self.id];
+            match inbox.events.pop_front() {
+                Some(event) => Poll::Ready(event),
+                None => {
+                    inbox.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
+
+impl Contracts {
+    fn handle(&mut self, id: usize, request: NetworkRequest) -> Result<(), NetworkError> {
+        match request {
+            NetworkRequest::Put { owner_vk, state } => {
+                if let Entry::Vacant(entry) = self.states.entry(owner_vk) {
+                    let parameters = ChatRoomParametersV1 { owner: owner_vk };
+                    state
+                        .verify(&state, &parameters)
+                        .map_err(NetworkError::Rejected)?;
+                    entry.insert(state);
+                    return Ok(());
+                }
+                self.update(owner_vk, |current, parameters| {
+                    current.merge(&current.clone(), parameters, &state)
+                })
+            }
+            NetworkRequest::Update { owner_vk, delta } => {
+                self.update(owner_vk, |current, parameters| {
+                    current.apply_delta(&current.clone(), parameters, &Some(delta))
+                })
+            }
+            NetworkRequest::Get { owner_vk } => {
+                let state = self.get(&owner_vk)?.clone();
+                self.push(id, Ok(NetworkEvent::State { owner_vk, state }));
+                Ok(())
+            }
+            NetworkRequest::Subscribe { owner_vk } => {
+                self.get(&owner_vk)?;
+                let subscribers = self.subscribers.entry(owner_vk).or_default();
+                if !subscribers.contains(&id) {
+                    subscribers.push(id);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Updates a contract's state as the room contract would, sending subscribers what changed
+    fn update(
+        &mut self,
+        owner_vk: VerifyingKey,
+        update: impl FnOnce(&mut ChatRoomState, &ChatRoomParametersV1) -> Result<(), RoomStateError>,
+    ) -> Result<(), NetworkError> {
+        let parameters = ChatRoomParametersV1 { owner: owner_vk };
+        let old_state = self.get(&owner_vk)?;
+        let mut state = old_state.clone();
+        update(&mut state, &parameters).map_err(NetworkError::Rejected)?;
+        state
+            .verify(&state, &parameters)
+            .map_err(NetworkError::Rejected)?;
+        let old_summary = old_state.summarize(old_state, &parameters);
+        let delta = state.delta(&state, &parameters, &old_summary);
+        self.states.insert(owner_vk, state);
+        if let Some(delta) = delta {
+            for id in self.subscribers.get(&owner_vk).cloned().unwrap_or_default() {
+                let delta = delta.clone();
+                self.push(id, Ok(NetworkEvent::Delta { owner_vk, delta }));
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, owner_vk: &VerifyingKey) -> Result<&ChatRoomState, NetworkError> {
+        self.states
+            .get(owner_vk)
+            .ok_or_else(|| NetworkError::Node("No such room contract".to_string()))
+    }
+
+    fn push(&mut self, id: usize, event: Result<NetworkEvent, NetworkError>) {
+        let inbox = &mut self.inboxes[id];
+        inbox.events.push_back(event);
+        if let Some(waker) = inbox.waker.take() {
+            waker.wake();
+        }
+    }
+}
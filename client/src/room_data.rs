@@ -0,0 +1,763 @@
+use crate::error::ClientError;
+use crate::key_store::KeyStore;
+use crate::util::get_current_system_time;
+use crate::{constants::ROOM_CONTRACT_WASM, util::to_cbor_vec};
+use blake3::Hash;
+use common::chat_delegate::SignRequest;
+use common::keyring::Keyring;
+use common::room_state::ban::{AuthorizedUnban, AuthorizedUserBan, Unban};
+use common::room_state::configuration::{
+    AuthorizedConfigurationV1, AuthorizedRoomRename, Configuration, Moderator,
+    ModeratorPermissions, PrivacyMode, RoomRename,
+};
+use common::room_state::error::RoomStateError;
+use common::room_state::member::{
+    AuthorizedMember, AuthorizedRemoval, InvitationToken, MemberId, MembersDelta, Removal,
+};
+use common::room_state::member_info::{AuthorizedMemberInfo, MemberInfo};
+use common::room_state::pin::{AuthorizedPinnedMessages, PinnedMessages, PinnedMessagesV1};
+use common::room_state::upgrade::AuthorizedUpgradeV1;
+use common::room_state::versioned::{ChatRoomStateDelta, ChatRoomStateV2};
+use common::room_state::{ChatRoomParametersV1, ChatRoomStateV1Delta};
+use common::{ChatRoomState, ChatRoomStateV1};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use freenet_scaffold::collections::LwwRegister;
+use freenet_scaffold::ComposableState;
+use freenet_stdlib::prelude::{ContractCode, ContractInstanceId, ContractKey, Parameters};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::UNIX_EPOCH;
+
+mod actions;
+mod secrets;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SendMessageError {
+    UserNotMember,
+    UserBanned,
+    /// The owner hasn't given us the room secret of a private room yet, or hasn't started the
+    /// epoch a ban requires
+    NoRoomSecret,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum RoomSyncStatus {
+    Unsubscribed,
+    Subscribing,
+    Subscribed,
+    Error(String),
+}
+
+#[derive(Clone, PartialEq)]
+pub struct RoomData {
+    pub owner_vk: VerifyingKey,
+    pub room_state: ChatRoomStateV1,
+    /// What the room holds beyond `room_state`, see `ChatRoomStateV2`
+    pub pinned_messages: PinnedMessagesV1,
+    /// Our key in the room, whatever we do in it is signed with it by a `KeyStore`
+    pub self_vk: VerifyingKey,
+    /// The room secrets of a private room we've decrypted so far, see `update_room_secrets`
+    pub room_secrets: HashMap<u32, [u8; 32]>,
+    pub contract_key: ContractKey,
+    pub sync_status: RoomSyncStatus,
+    /// Set after redeeming an invitation until we've received the room's state and joined it
+    pub pending_join: Option<PendingJoin>,
+    /// Set after moving the room to the contract that replaces it, see `Rooms::follow_upgrades`
+    pub pending_upgrade: Option<PendingUpgrade>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingJoin {
+    pub member: AuthorizedMember,
+    pub nickname: String,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum PendingUpgrade {
+    /// We upgraded the room, the old contract's state pointing at this one is yet to be sent
+    Announce {
+        /// The owner of the old contract
+        owner_vk: VerifyingKey,
+        room_state: Box<ChatRoomState>,
+    },
+    /// We have no state of the room's contract yet, having followed the room here or restored our
+    /// key in it, and show what we have until the contract's state arrives
+    Fetch,
+}
+
+impl RoomData {
+    /// A room we're in as `self_vk`, with a state we had of it before. Its room secrets are
+    /// decrypted once it's resumed.
+    pub fn restored(owner_vk: VerifyingKey, self_vk: VerifyingKey, state: ChatRoomState) -> Self {
+        let state = state.into_latest();
+        RoomData {
+            owner_vk,
+            room_state: state.room,
+            pinned_messages: state.pinned_messages,
+            self_vk,
+            room_secrets: HashMap::new(),
+            contract_key: room_contract_key(&owner_vk),
+            sync_status: RoomSyncStatus::Unsubscribed,
+            pending_join: None,
+            pending_upgrade: None,
+        }
+    }
+
+    /// Check if the user can send a message in the room
+    pub fn can_send_message(&self) -> Result<(), SendMessageError> {
+        let verifying_key = self.self_vk;
+        // Must be owner or a member of the room to send a message
+        if verifying_key == self.owner_vk
+            || self
+                .room_state
+                .members
+                .members
+                .iter()
+                .any(|m| m.member.member_vk == verifying_key)
+        {
+            // Must not be banned from the room to send a message
+            if self
+                .room_state
+                .bans
+                .in_effect()
+                .any(|b| b.ban.banned_user == verifying_key.into())
+            {
+                Err(SendMessageError::UserBanned)
+            } else if self.is_private()
+                && self
+                    .sealing_version()
+                    .and_then(|version| {
+                        self.room_state
+                            .secrets
+                            .secret_for(verifying_key.into(), version)
+                    })
+                    .is_none()
+            {
+                Err(SendMessageError::NoRoomSecret)
+            } else {
+                Ok(())
+            }
+        } else {
+            Err(SendMessageError::UserNotMember)
+        }
+    }
+
+    pub fn owner_id(&self) -> MemberId {
+        self.owner_vk.into()
+    }
+
+    pub fn parameters(&self) -> ChatRoomParametersV1 {
+        ChatRoomParametersV1 {
+            owner: self.owner_vk,
+        }
+    }
+
+    /// The room's state as it's sent to the contract
+    pub fn state(&self) -> ChatRoomState {
+        ChatRoomState::V2(ChatRoomStateV2 {
+            room: self.room_state.clone(),
+            pinned_messages: self.pinned_messages.clone(),
+        })
+    }
+
+    /// Replaces our state with one received from the contract, of any schema
+    pub fn replace_state(&mut self, state: ChatRoomState) {
+        let state = state.into_latest();
+        self.room_state = state.room;
+        self.pinned_messages = state.pinned_messages;
+    }
+
+    /// Merges a state received from the contract, of any schema
+    pub fn merge_state(&mut self, state: ChatRoomState) -> Result<(), RoomStateError> {
+        let other_state = state.into_latest();
+        self.update_state(|state, parameters| {
+            let current_state = state.clone();
+            state.merge(&current_state, parameters, &other_state)
+        })
+    }
+
+    /// Applies a delta received from the contract, of any schema
+    pub fn apply_state_delta(&mut self, delta: ChatRoomStateDelta) -> Result<(), RoomStateError> {
+        self.update_state(|state, parameters| state.apply_versioned_delta(parameters, delta))
+    }
+
+    fn update_state(
+        &mut self,
+        update: impl FnOnce(&mut ChatRoomStateV2, &ChatRoomParametersV1) -> Result<(), RoomStateError>,
+    ) -> Result<(), RoomStateError> {
+        let parameters = self.parameters();
+        let mut state = ChatRoomStateV2 {
+            room: std::mem::take(&mut self.room_state),
+            pinned_messages: std::mem::take(&mut self.pinned_messages),
+        };
+        let result = update(&mut state, &parameters);
+        self.room_state = state.room;
+        self.pinned_messages = state.pinned_messages;
+        result
+    }
+
+    /// Replaces the placeholder state of a room we're joining with the room's actual state, and
+    /// adds ourselves to it
+    pub async fn complete_join(
+        &mut self,
+        state: ChatRoomState,
+        key_store: &mut impl KeyStore,
+    ) -> Result<(), ClientError> {
+        let Some(pending_join) = self.pending_join.take() else {
+            return Ok(());
+        };
+        self.replace_state(state);
+        // Versioned by when we join so that it supersedes info from an earlier membership, which
+        // peers that haven't seen us leave or be removed may still have
+        let joined_at = get_current_system_time()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let member_info = MemberInfo {
+            member_id: pending_join.member.member.id(),
+            version: joined_at.as_secs() as u32,
+            preferred_nickname: pending_join.nickname,
+        };
+        let member_info = AuthorizedMemberInfo {
+            signature: key_store
+                .sign(&self.self_vk, SignRequest::MemberInfo(member_info.clone()))
+                .await?,
+            member_info,
+        };
+        let delta = ChatRoomStateV1Delta {
+            members: Some(MembersDelta::new(vec![pending_join.member])),
+            member_info: Some(vec![member_info]),
+            ..Default::default()
+        };
+        let current_state = self.room_state.clone();
+        self.room_state
+            .apply_delta(&current_state, &self.parameters(), &Some(delta))?;
+        Ok(())
+    }
+
+    /// Takes in a state of the room received from its contract, completing our join or replacing
+    /// the state we showed until it arrived, or merging it with ours otherwise
+    pub async fn receive_state(
+        &mut self,
+        state: ChatRoomState,
+        key_store: &mut impl KeyStore,
+    ) -> Result<(), ClientError> {
+        if self.pending_join.is_some() {
+            self.complete_join(state, key_store).await?;
+        } else if self.pending_upgrade.take().is_some() {
+            // We were showing the old contract's state until now
+            self.replace_state(state);
+        } else {
+            let state_before = self.room_state.clone();
+            let result = self.merge_state(state);
+            self.update_room_secrets(&state_before, key_store).await?;
+            return Ok(result?);
+        }
+        self.decrypt_room_secrets(key_store).await
+    }
+
+    /// Takes `member_vk` out of the room, which is leaving it if it's our own key. Otherwise we
+    /// must be the owner or whoever invited the member for the removal to have any effect.
+    pub async fn remove_member(
+        &mut self,
+        member_vk: VerifyingKey,
+        key_store: &mut impl KeyStore,
+    ) -> Result<(), ClientError> {
+        let removal = Removal {
+            owner_member_id: self.owner_id(),
+            member_vk,
+            removed_by: self.self_vk,
+            removed_at: get_current_system_time(),
+            signed_for: None,
+        };
+        let removal = AuthorizedRemoval {
+            signature: key_store
+                .sign(
+                    &self.self_vk,
+                    SignRequest::Removal(Box::new(removal.clone())),
+                )
+                .await?,
+            removal,
+        };
+        let delta = ChatRoomStateV1Delta {
+            members: Some(MembersDelta::remove(vec![removal])),
+            ..Default::default()
+        };
+        let state_before = self.room_state.clone();
+        self.room_state
+            .apply_delta(&state_before, &self.parameters(), &Some(delta))?;
+        // A removed member mustn't be able to read messages sent after they're gone
+        self.update_room_secrets(&state_before, key_store).await
+    }
+
+    /// The bans in effect that we can lift, those we made or all of them if we're the owner
+    pub fn liftable_bans(&self) -> impl Iterator<Item = &AuthorizedUserBan> {
+        let self_id = self.self_id();
+        let is_owner = self_id == self.owner_id();
+        self.room_state
+            .bans
+            .in_effect()
+            .filter(move |ban| is_owner || ban.banned_by == self_id)
+    }
+
+    /// Lifts the bans on `member_id` that we can, they're back in the room unless a ban made by
+    /// someone else still applies
+    pub async fn unban_member(
+        &mut self,
+        member_id: MemberId,
+        key_store: &mut impl KeyStore,
+    ) -> Result<(), ClientError> {
+        let unbanned_at = get_current_system_time();
+        let bans: Vec<AuthorizedUserBan> = self
+            .liftable_bans()
+            .filter(|ban| ban.ban.banned_user == member_id)
+            .cloned()
+            .collect();
+        let mut lifted = Vec::new();
+        for ban in bans {
+            let unban = Unban {
+                owner_member_id: self.owner_id(),
+                ban_id: ban.id(),
+                unbanned_at,
+            };
+            let signature = key_store
+                .sign(&self.self_vk, SignRequest::Unban(unban.clone()))
+                .await?;
+            lifted.push(ban.lifted(AuthorizedUnban {
+                signature,
+                unban,
+                unbanned_by: self.self_id(),
+            }));
+        }
+        if lifted.is_empty() {
+            return Ok(());
+        }
+        let delta = ChatRoomStateV1Delta {
+            bans: Some(lifted),
+            ..Default::default()
+        };
+        let state_before = self.room_state.clone();
+        self.room_state
+            .apply_delta(&state_before, &self.parameters(), &Some(delta))?;
+        // Give the member back the room secret of a private room
+        self.update_room_secrets(&state_before, key_store).await
+    }
+
+    /// What we may do as a moderator, the owner isn't one as they can do it all
+    pub fn moderator_permissions(&self) -> ModeratorPermissions {
+        self.room_state
+            .configuration
+            .configuration
+            .moderator_permissions(self.self_id())
+    }
+
+    /// Renames the room, with a new configuration if we're the owner or with a rename if we're a
+    /// moderator allowed to
+    pub async fn rename_room(
+        &mut self,
+        name: String,
+        key_store: &mut impl KeyStore,
+    ) -> Result<(), ClientError> {
+        let configuration = self.room_state.configuration.clone();
+        let authorized_configuration = if self.self_vk == self.owner_vk {
+            let mut new_config = configuration.configuration;
+            new_config.name = name;
+            new_config.configuration_version += 1;
+            self.sign_configuration(new_config, key_store).await?
+        } else {
+            let rename = RoomRename {
+                configuration_version: configuration.configuration.configuration_version,
+                name,
+                renamed_at: get_current_system_time(),
+            };
+            configuration.renamed(AuthorizedRoomRename {
+                signature: key_store
+                    .sign(&self.self_vk, SignRequest::Rename(rename.clone()))
+                    .await?,
+                rename,
+                renamed_by: self.self_vk,
+            })
+        };
+        self.apply_configuration(authorized_configuration)
+    }
+
+    /// Makes a member a moderator, or no longer one if `permissions` allow nothing. Only the owner
+    /// can.
+    pub async fn set_moderator(
+        &mut self,
+        member_id: MemberId,
+        permissions: ModeratorPermissions,
+        key_store: &mut impl KeyStore,
+    ) -> Result<(), ClientError> {
+        let mut new_config = self.next_configuration();
+        new_config.moderators.retain(|m| m.member_id != member_id);
+        if permissions.is_moderator() {
+            new_config.moderators.push(Moderator {
+                member_id,
+                permissions,
+            });
+        }
+        let configuration = self.sign_configuration(new_config, key_store).await?;
+        self.apply_configuration(configuration)
+    }
+
+    /// Designates who may take the room over should our key be lost, see `hand_over`. Only the
+    /// owner can.
+    pub async fn set_successor(
+        &mut self,
+        successor: Option<VerifyingKey>,
+        key_store: &mut impl KeyStore,
+    ) -> Result<(), ClientError> {
+        let mut new_config = self.next_configuration();
+        new_config.successor = successor;
+        let configuration = self.sign_configuration(new_config, key_store).await?;
+        self.apply_configuration(configuration)
+    }
+
+    /// The owner's next configuration, keeping the current name even if a moderator gave it
+    fn next_configuration(&self) -> Configuration {
+        let mut new_config = self.room_state.configuration.configuration.clone();
+        new_config.name = self.room_state.configuration.name().to_string();
+        new_config.configuration_version += 1;
+        new_config
+    }
+
+    async fn sign_configuration(
+        &self,
+        configuration: Configuration,
+        key_store: &mut impl KeyStore,
+    ) -> Result<AuthorizedConfigurationV1, ClientError> {
+        Ok(AuthorizedConfigurationV1 {
+            signature: key_store
+                .sign(
+                    &self.self_vk,
+                    SignRequest::Configuration(configuration.clone()),
+                )
+                .await?,
+            configuration,
+            rename: None,
+        })
+    }
+
+    fn apply_configuration(
+        &mut self,
+        configuration: AuthorizedConfigurationV1,
+    ) -> Result<(), ClientError> {
+        let delta = ChatRoomStateV1Delta {
+            configuration: Some(configuration),
+            ..Default::default()
+        };
+        let state_before = self.room_state.clone();
+        self.room_state
+            .apply_delta(&state_before, &self.parameters(), &Some(delta))?;
+        Ok(())
+    }
+
+    /// Who the room was handed over to, if it has been
+    pub fn new_owner(&self) -> Option<VerifyingKey> {
+        self.room_state
+            .upgrade
+            .0
+            .as_ref()
+            .and_then(|upgrade| upgrade.upgrade.new_owner)
+    }
+
+    /// The owner and contract of the room that replaces this one, if it was upgraded to a room
+    /// contract this build knows
+    fn upgraded_to(&self) -> Option<(VerifyingKey, ContractKey)> {
+        let upgrade = &self.room_state.upgrade.0.as_ref()?.upgrade;
+        if upgrade.new_chatroom_address == contract_address(&self.contract_key) {
+            return None;
+        }
+        let new_owner_vk = upgrade.new_owner.unwrap_or(self.owner_vk);
+        let new_contract_key = room_contract_key(&new_owner_vk);
+        (contract_address(&new_contract_key) == upgrade.new_chatroom_address)
+            .then_some((new_owner_vk, new_contract_key))
+    }
+
+    /// Hands the room over to a new key, a fresh one we store if we're the owner or our own if
+    /// we're the designated successor. Returns the room under its new owner, which replaces this
+    /// one.
+    pub async fn hand_over(&self, key_store: &mut impl KeyStore) -> Result<RoomData, ClientError> {
+        let parameters = self.parameters();
+        let new_owner_vk = if self.self_vk == self.owner_vk {
+            let new_owner_sk = SigningKey::generate(&mut rand::thread_rng());
+            let new_owner_vk = new_owner_sk.verifying_key();
+            key_store.store(new_owner_sk).await?;
+            new_owner_vk
+        } else {
+            self.self_vk
+        };
+        key_store.store_room(&new_owner_vk, &new_owner_vk).await?;
+        let mut room_state = self.room_state.clone();
+        let new_contract_key = room_contract_key(&new_owner_vk);
+        let upgrade = room_state.next_upgrade(
+            &parameters,
+            new_owner_vk,
+            contract_address(&new_contract_key),
+        );
+        let upgrade = AuthorizedUpgradeV1 {
+            signature: key_store
+                .sign(&self.self_vk, SignRequest::Upgrade(upgrade.clone()))
+                .await?,
+            upgrade,
+        };
+        // What the new owner signs again is signed ahead of time, see `succeeded_by_signer`
+        let signatures = key_store
+            .sign_succession(&new_owner_vk, &self.owner_vk, &room_state)
+            .await?;
+        let signatures: HashMap<Vec<u8>, Signature> = room_state
+            .succession_payloads(&parameters, new_owner_vk)
+            .into_iter()
+            .zip(signatures)
+            .collect();
+        let new_room_state =
+            room_state.upgrade_with(&parameters, upgrade, &mut |payload| signatures[payload])?;
+        let pinned_messages = match &self.pinned_messages.0 {
+            Some(pinned) => {
+                let pinned = PinnedMessages {
+                    owner_member_id: new_owner_vk.into(),
+                    ..pinned.pinned.clone()
+                };
+                Some(AuthorizedPinnedMessages {
+                    signature: key_store
+                        .sign(&new_owner_vk, SignRequest::PinnedMessages(pinned.clone()))
+                        .await?,
+                    pinned,
+                })
+            }
+            None => None,
+        };
+        let mut new_room = RoomData {
+            owner_vk: new_owner_vk,
+            room_state: new_room_state,
+            pinned_messages: LwwRegister(pinned_messages),
+            self_vk: new_owner_vk,
+            room_secrets: self.room_secrets.clone(),
+            contract_key: new_contract_key,
+            sync_status: RoomSyncStatus::Unsubscribed,
+            pending_join: None,
+            pending_upgrade: Some(PendingUpgrade::Announce {
+                owner_vk: self.owner_vk,
+                room_state: Box::new(ChatRoomState::V2(ChatRoomStateV2 {
+                    room: room_state,
+                    pinned_messages: self.pinned_messages.clone(),
+                })),
+            }),
+        };
+        // A fresh key hasn't been given the room secrets yet
+        if new_room.is_private() {
+            let state = new_room.room_state.clone();
+            new_room.wrap_room_secrets(&state, key_store).await?;
+        }
+        Ok(new_room)
+    }
+}
+
+/// Why message content can't be displayed
+#[derive(Clone, Debug, PartialEq)]
+pub enum OpenContentError {
+    /// The owner hasn't given us the room secret of the epoch the message was sent in, eg. because
+    /// we were banned before it started or it started while they were offline
+    MissingSecret { secret_version: u32 },
+    /// The content claims to be encrypted but doesn't decrypt
+    Undecryptable,
+}
+
+#[derive(Clone, Default)]
+pub struct Rooms {
+    pub map: HashMap<VerifyingKey, RoomData>,
+}
+
+impl PartialEq for Rooms {
+    fn eq(&self, other: &Self) -> bool {
+        self.map == other.map
+    }
+}
+
+impl Rooms {
+    /// Creates a room owned by `owner_vk`, a key in `key_store`
+    pub async fn create_new_room_with_name(
+        &mut self,
+        owner_vk: VerifyingKey,
+        name: String,
+        nickname: String,
+        privacy_mode: PrivacyMode,
+        key_store: &mut impl KeyStore,
+    ) -> Result<VerifyingKey, ClientError> {
+        // We own the room, it goes by our key, and the key store only signs for rooms it keeps
+        key_store.store_room(&owner_vk, &owner_vk).await?;
+        let mut room_state = ChatRoomStateV1::default();
+
+        // Set initial configuration
+        let config = Configuration {
+            name,
+            owner_member_id: owner_vk.into(),
+            privacy_mode,
+            ..Default::default()
+        };
+        room_state.configuration = AuthorizedConfigurationV1 {
+            signature: key_store
+                .sign(&owner_vk, SignRequest::Configuration(config.clone()))
+                .await?,
+            configuration: config,
+            rename: None,
+        };
+
+        // Add owner to member_info
+        let owner_info = MemberInfo {
+            member_id: owner_vk.into(),
+            version: 0,
+            preferred_nickname: nickname,
+        };
+        let authorized_owner_info = AuthorizedMemberInfo {
+            signature: key_store
+                .sign(&owner_vk, SignRequest::MemberInfo(owner_info.clone()))
+                .await?,
+            member_info: owner_info,
+        };
+        room_state
+            .member_info
+            .member_info
+            .push(authorized_owner_info);
+
+        // Generate contract key for the room
+        let contract_key = room_contract_key(&owner_vk);
+
+        let mut room_data = RoomData {
+            owner_vk,
+            room_state,
+            pinned_messages: PinnedMessagesV1::default(),
+            self_vk: owner_vk,
+            room_secrets: HashMap::new(),
+            contract_key,
+            sync_status: RoomSyncStatus::Unsubscribed,
+            pending_join: None,
+            pending_upgrade: None,
+        };
+        // Creates the first room secret for private rooms
+        room_data
+            .update_room_secrets(&ChatRoomStateV1::default(), key_store)
+            .await?;
+
+        self.map.insert(owner_vk, room_data);
+        Ok(owner_vk)
+    }
+
+    /// Redeems an invitation with `self_vk`, a new key, we're added to the room once its state
+    /// arrives, see `RoomData::complete_join`
+    pub fn join_with_invitation(
+        &mut self,
+        token: &InvitationToken,
+        self_vk: VerifyingKey,
+        nickname: String,
+    ) -> Result<VerifyingKey, RoomStateError> {
+        let owner_vk = token.room_owner;
+        let member = token.redeem(self_vk, get_current_system_time())?;
+
+        let contract_key = room_contract_key(&owner_vk);
+
+        let room_data = RoomData {
+            owner_vk,
+            room_state: ChatRoomStateV1::default(),
+            pinned_messages: PinnedMessagesV1::default(),
+            self_vk,
+            room_secrets: HashMap::new(),
+            contract_key,
+            sync_status: RoomSyncStatus::Unsubscribed,
+            pending_join: Some(PendingJoin { member, nickname }),
+            pending_upgrade: None,
+        };
+
+        self.map.insert(owner_vk, room_data);
+        Ok(owner_vk)
+    }
+
+    /// Adds the rooms of keys we aren't in a room with yet, eg. imported from another device, to
+    /// be fetched. The keys themselves go to the key store. Returns how many rooms were added.
+    pub fn add_keys(&mut self, keyring: &Keyring) -> usize {
+        let mut added = 0;
+        for (owner_vk, self_sk) in &keyring.keys {
+            if self.add_unfetched(*owner_vk, self_sk.verifying_key()) {
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// Adds the room owned by `owner_vk` that we're in with `self_vk`, to be fetched, unless we
+    /// have it already. Returns whether it was added.
+    pub fn add_unfetched(&mut self, owner_vk: VerifyingKey, self_vk: VerifyingKey) -> bool {
+        match self.map.entry(owner_vk) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(RoomData {
+                    pending_upgrade: Some(PendingUpgrade::Fetch),
+                    ..RoomData::restored(owner_vk, self_vk, ChatRoomState::V1(Default::default()))
+                });
+                true
+            }
+        }
+    }
+
+    /// Moves rooms that were upgraded to the contract that replaces them, under their new owner
+    /// if they were handed over, see `RoomData::hand_over`. Our key and what we have of the room
+    /// come along. Returns the previous and new owner keys of every room moved.
+    pub fn follow_upgrades(&mut self) -> Vec<(VerifyingKey, VerifyingKey)> {
+        let upgraded: Vec<(VerifyingKey, VerifyingKey, ContractKey)> = self
+            .map
+            .iter()
+            .filter_map(|(owner_vk, room_data)| {
+                let (new_owner_vk, new_contract_key) = room_data.upgraded_to()?;
+                Some((*owner_vk, new_owner_vk, new_contract_key))
+            })
+            .collect();
+        let mut followed = Vec::new();
+        for (owner_vk, new_owner_vk, new_contract_key) in upgraded {
+            let room_data = self.map.remove(&owner_vk).expect("Room to upgrade");
+            // We may already be in the new room, eg. having joined it by invitation
+            self.map.entry(new_owner_vk).or_insert(RoomData {
+                owner_vk: new_owner_vk,
+                contract_key: new_contract_key,
+                sync_status: RoomSyncStatus::Unsubscribed,
+                pending_upgrade: Some(PendingUpgrade::Fetch),
+                ..room_data
+            });
+            followed.push((owner_vk, new_owner_vk));
+        }
+        followed
+    }
+}
+
+/// The address of a contract as an upgrade records it
+fn contract_address(contract_key: &ContractKey) -> Hash {
+    let address: [u8; 32] = contract_key
+        .id()
+        .as_bytes()
+        .try_into()
+        .expect("Invalid key length");
+    Hash::from(address)
+}
+
+/// The key of the room contract owned by `owner_vk`
+pub fn room_contract_key(owner_vk: &VerifyingKey) -> ContractKey {
+    let parameters = ChatRoomParametersV1 { owner: *owner_vk };
+    let params_bytes = to_cbor_vec(&parameters);
+    let contract_code = ContractCode::from(ROOM_CONTRACT_WASM);
+    let instance_id =
+        ContractInstanceId::from_params_and_code(Parameters::from(params_bytes), contract_code);
+    ContractKey::from(instance_id)
+}
+
+impl fmt::Display for SendMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendMessageError::UserNotMember => write!(f, "Not a member of the room"),
+            SendMessageError::UserBanned => write!(f, "Banned from the room"),
+            SendMessageError::NoRoomSecret => {
+                write!(f, "The owner hasn't shared the room secret with us yet")
+            }
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! What we do in a room, signed with our key in it by the key store and applied to our state of
+//! the room. Each returns everything it changed, to send to the room's contract.
+
+use super::RoomData;
+use crate::error::ClientError;
+use crate::key_store::KeyStore;
+use crate::util::get_current_system_time;
+use common::chat_delegate::SignRequest;
+use common::room_state::ban::{AuthorizedUserBan, UserBan};
+use common::room_state::member::{
+    AuthorizedMember, Member, MemberAuthorization, MemberId, MembersDelta,
+};
+use common::room_state::member_info::{AuthorizedMemberInfo, MemberInfo};
+use common::room_state::message::{
+    AuthorizedMessageActionV1, AuthorizedMessageV1, Message, MessageAction, MessageActionV1,
+    MessageId, MessageV1, MessageV2, MessagesDelta,
+};
+use common::room_state::reaction::{AuthorizedReaction, Reaction};
+use common::room_state::ChatRoomStateV1Delta;
+use ed25519_dalek::VerifyingKey;
+use freenet_scaffold::ComposableState;
+use std::time::SystemTime;
+
+impl RoomData {
+    /// Posts a message, replying to `in_reply_to` if set. Returns the message's id along with
+    /// the delta.
+    pub async fn post_message(
+        &mut self,
+        content: String,
+        in_reply_to: Option<MessageId>,
+        key_store: &mut impl KeyStore,
+    ) -> Result<(MessageId, ChatRoomStateV1Delta), ClientError> {
+        self.can_send_message()?;
+        // Private rooms only ever share ciphertext with the contract
+        let content = self.seal_content(content)?;
+        let message = MessageV1 {
+            room_owner: self.owner_id(),
+            author: self.self_id(),
+            time: get_current_system_time(),
+            content,
+        };
+        let message: Message = match in_reply_to {
+            Some(in_reply_to) => MessageV2 {
+                room_owner: message.room_owner,
+                author: message.author,
+                time: message.time,
+                content: message.content,
+                in_reply_to,
+            }
+            .into(),
+            None => message.into(),
+        };
+        let message = AuthorizedMessageV1 {
+            signature: key_store
+                .sign(&self.self_vk, SignRequest::Message(message.clone()))
+                .await?,
+            message,
+        };
+        let id = message.id();
+        let delta = ChatRoomStateV1Delta {
+            recent_messages: Some(MessagesDelta {
+                messages: vec![message],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        Ok((id, self.apply_own(delta, key_store).await?))
+    }
+
+    /// Edits or deletes `target`, as its author or, for deletes, as the owner or a moderator
+    pub async fn act_on_message(
+        &mut self,
+        target: MessageId,
+        action: MessageAction,
+        key_store: &mut impl KeyStore,
+    ) -> Result<ChatRoomStateV1Delta, ClientError> {
+        let action = match action {
+            MessageAction::Edit { content } => MessageAction::Edit {
+                content: self.seal_content(content)?,
+            },
+            MessageAction::Delete => MessageAction::Delete,
+        };
+        let action = MessageActionV1 {
+            room_owner: self.owner_id(),
+            author: self.self_id(),
+            time: get_current_system_time(),
+            target,
+            action,
+        };
+        let action = AuthorizedMessageActionV1 {
+            signature: key_store
+                .sign(&self.self_vk, SignRequest::MessageAction(action.clone()))
+                .await?,
+            action,
+        };
+        let delta = ChatRoomStateV1Delta {
+            recent_messages: Some(MessagesDelta {
+                actions: vec![action],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        self.apply_own(delta, key_store).await
+    }
+
+    pub async fn react(
+        &mut self,
+        message_id: MessageId,
+        reaction: String,
+        key_store: &mut impl KeyStore,
+    ) -> Result<ChatRoomStateV1Delta, ClientError> {
+        let reaction = Reaction {
+            message_id,
+            member_id: self.self_id(),
+            time: get_current_system_time(),
+            reaction,
+        };
+        let reaction = AuthorizedReaction {
+            signature: key_store
+                .sign(&self.self_vk, SignRequest::Reaction(reaction.clone()))
+                .await?,
+            reaction,
+        };
+        let delta = ChatRoomStateV1Delta {
+            reactions: Some(vec![reaction]),
+            ..Default::default()
+        };
+        self.apply_own(delta, key_store).await
+    }
+
+    /// Adds `member_vk` to the room as invited by us
+    pub async fn invite_member(
+        &mut self,
+        member_vk: VerifyingKey,
+        key_store: &mut impl KeyStore,
+    ) -> Result<ChatRoomStateV1Delta, ClientError> {
+        let member = Member {
+            owner_member_id: self.owner_id(),
+            invited_by: self.self_id(),
+            member_vk,
+        };
+        // Timestamped so that members who left or were removed can be invited again
+        let joined_at = get_current_system_time();
+        let member = AuthorizedMember {
+            authorization: MemberAuthorization::Invite {
+                signature: key_store
+                    .sign(
+                        &self.self_vk,
+                        SignRequest::Member {
+                            member: member.clone(),
+                            joined_at,
+                        },
+                    )
+                    .await?,
+                joined_at: Some(joined_at),
+            },
+            member,
+        };
+        let delta = ChatRoomStateV1Delta {
+            members: Some(MembersDelta::new(vec![member])),
+            ..Default::default()
+        };
+        self.apply_own(delta, key_store).await
+    }
+
+    /// Bans `member_id` until `expires_at`, or until the ban is lifted if it's not set. Members
+    /// they invited are removed too.
+    pub async fn ban_member(
+        &mut self,
+        member_id: MemberId,
+        expires_at: Option<SystemTime>,
+        key_store: &mut impl KeyStore,
+    ) -> Result<ChatRoomStateV1Delta, ClientError> {
+        let ban = UserBan {
+            owner_member_id: self.owner_id(),
+            banned_at: get_current_system_time(),
+            banned_user: member_id,
+            expires_at,
+        };
+        let ban = AuthorizedUserBan {
+            signature: key_store
+                .sign(&self.self_vk, SignRequest::Ban(ban.clone()))
+                .await?,
+            ban,
+            banned_by: self.self_id(),
+            unban: None,
+            min_secret_version: None,
+        };
+        let delta = ChatRoomStateV1Delta {
+            bans: Some(vec![ban]),
+            ..Default::default()
+        };
+        self.apply_own(delta, key_store).await
+    }
+
+    pub async fn set_nickname(
+        &mut self,
+        nickname: String,
+        key_store: &mut impl KeyStore,
+    ) -> Result<ChatRoomStateV1Delta, ClientError> {
+        let member_id = self.self_id();
+        // Another device with our key may publish the same version, see `AuthorizedMemberInfo::order_key`
+        let version = self
+            .room_state
+            .member_info
+            .member_info
+            .iter()
+            .find(|info| info.member_info.member_id == member_id)
+            .map_or(0, |info| info.member_info.version + 1);
+        let member_info = MemberInfo {
+            member_id,
+            version,
+            preferred_nickname: nickname,
+        };
+        let member_info = AuthorizedMemberInfo {
+            signature: key_store
+                .sign(&self.self_vk, SignRequest::MemberInfo(member_info.clone()))
+                .await?,
+            member_info,
+        };
+        let delta = ChatRoomStateV1Delta {
+            member_info: Some(vec![member_info]),
+            ..Default::default()
+        };
+        self.apply_own(delta, key_store).await
+    }
+
+    pub fn self_id(&self) -> MemberId {
+        self.self_vk.into()
+    }
+
+    /// Applies a delta we made and keeps the room secrets up to date, eg. giving a new member
+    /// the secret. Returns all that changed.
+    async fn apply_own(
+        &mut self,
+        delta: ChatRoomStateV1Delta,
+        key_store: &mut impl KeyStore,
+    ) -> Result<ChatRoomStateV1Delta, ClientError> {
+        let parameters = self.parameters();
+        let state_before = self.room_state.clone();
+        self.room_state
+            .apply_delta(&state_before, &parameters, &Some(delta))?;
+        self.update_room_secrets(&state_before, key_store).await?;
+        let summary_before = state_before.summarize(&state_before, &parameters);
+        Ok(self
+            .room_state
+            .delta(&self.room_state, &parameters, &summary_before)
+            .unwrap_or_default())
+    }
+}
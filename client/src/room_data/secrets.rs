@@ -0,0 +1,244 @@
+//! The room secrets of private rooms, which message content is encrypted with. The owner gives
+//! every member each secret wrapped for their key, and we decrypt ours with the key store.
+
+use super::{OpenContentError, RoomData, SendMessageError};
+use crate::error::ClientError;
+use crate::key_store::KeyStore;
+use crate::util::{ecies, get_current_system_time};
+use common::chat_delegate::SignRequest;
+use common::room_state::configuration::PrivacyMode;
+use common::room_state::member::MemberId;
+use common::room_state::secret::{
+    AuthorizedEncryptedSecretForMember, AuthorizedSecretEpoch, EncryptedContent,
+    EncryptedSecretForMember, RoomSecretsDelta, SecretEpoch,
+};
+use common::room_state::ChatRoomStateV1Delta;
+use common::ChatRoomStateV1;
+use ed25519_dalek::VerifyingKey;
+use freenet_scaffold::ComposableState;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+impl RoomData {
+    pub fn is_private(&self) -> bool {
+        self.room_state.configuration.configuration.privacy_mode == PrivacyMode::Private
+    }
+
+    /// Decrypts the room secrets the owner has given us since we last did into `room_secrets`
+    pub async fn decrypt_room_secrets(
+        &mut self,
+        key_store: &mut impl KeyStore,
+    ) -> Result<(), ClientError> {
+        let encrypted: Vec<EncryptedSecretForMember> = self
+            .room_state
+            .secrets
+            .secrets_for(self.self_id())
+            .filter(|s| !self.room_secrets.contains_key(&s.secret_version))
+            .cloned()
+            .collect();
+        for s in encrypted {
+            let shared_secret = key_store
+                .shared_secret(&self.self_vk, s.sender_public_key)
+                .await?;
+            let secret = ecies::decrypt_with_shared_secret(&shared_secret, &s.ciphertext, &s.nonce)
+                .ok()
+                .and_then(|secret| <[u8; 32]>::try_from(secret).ok());
+            if let Some(secret) = secret {
+                self.room_secrets.insert(s.secret_version, secret);
+            }
+        }
+        Ok(())
+    }
+
+    /// The version of the room secret to encrypt with now, `None` if there's no epoch yet or the
+    /// owner hasn't started the one the latest ban requires, see `BansV1::min_secret_version`
+    pub(super) fn sealing_version(&self) -> Option<u32> {
+        let current_version = self.room_state.secrets.current_version()?;
+        let min_version = self
+            .room_state
+            .bans
+            .min_secret_version(get_current_system_time());
+        (min_version <= Some(current_version)).then_some(current_version)
+    }
+
+    /// Encrypts message content with the current room secret if the room is private
+    pub fn seal_content(&self, content: String) -> Result<String, SendMessageError> {
+        if !self.is_private() {
+            return Ok(content);
+        }
+        let secret_version = self
+            .sealing_version()
+            .ok_or(SendMessageError::NoRoomSecret)?;
+        let secret = self
+            .room_secrets
+            .get(&secret_version)
+            .ok_or(SendMessageError::NoRoomSecret)?;
+        let (ciphertext, nonce) = ecies::encrypt_with_symmetric_key(secret, content.as_bytes());
+        Ok(EncryptedContent {
+            secret_version,
+            nonce,
+            ciphertext,
+        }
+        .encode())
+    }
+
+    /// Message content as it should be displayed, decrypted with the room secret of its epoch
+    pub fn open_content(
+        secrets: &HashMap<u32, [u8; 32]>,
+        content: &str,
+    ) -> Result<String, OpenContentError> {
+        let Some(encrypted) = EncryptedContent::decode(content) else {
+            return Ok(content.to_string());
+        };
+        let secret_version = encrypted.secret_version;
+        let secret = secrets
+            .get(&secret_version)
+            .ok_or(OpenContentError::MissingSecret { secret_version })?;
+        let plaintext =
+            ecies::decrypt_with_symmetric_key(secret, &encrypted.ciphertext, &encrypted.nonce)
+                .map_err(|_| OpenContentError::Undecryptable)?;
+        String::from_utf8(plaintext).map_err(|_| OpenContentError::Undecryptable)
+    }
+
+    /// Keeps the room secrets of a private room up to date, decrypting those we've been given and,
+    /// if we're the owner, giving them out. A new epoch is started if anyone who was a member in
+    /// `state_before` has since been banned, left or been removed so they can't read new messages,
+    /// or if a ban requires a later epoch than the current one, and members are given the secret of
+    /// every epoch they don't have yet, including those from before they joined whose messages are
+    /// still in the room. The room refuses messages on an epoch from before a ban, so after one
+    /// members can't post until the owner's client gets here.
+    pub async fn update_room_secrets(
+        &mut self,
+        state_before: &ChatRoomStateV1,
+        key_store: &mut impl KeyStore,
+    ) -> Result<(), ClientError> {
+        if !self.is_private() {
+            return Ok(());
+        }
+        // Another of the owner's clients may have started a version at the same time as the one we
+        // had the secret of, and theirs won
+        for epoch in &state_before.secrets.epochs {
+            let version = epoch.epoch.secret_version;
+            if self.room_state.secrets.epoch(version) != Some(&epoch.epoch) {
+                self.room_secrets.remove(&version);
+            }
+        }
+        self.decrypt_room_secrets(key_store).await?;
+        if self.self_vk != self.owner_vk {
+            return Ok(());
+        }
+        self.wrap_room_secrets(state_before, key_store).await
+    }
+
+    /// The owner's part of `update_room_secrets`, also for the key we handed the room over to,
+    /// which knows the secrets the key we handed it over from was given
+    pub(super) async fn wrap_room_secrets(
+        &mut self,
+        state_before: &ChatRoomStateV1,
+        key_store: &mut impl KeyStore,
+    ) -> Result<(), ClientError> {
+        let parameters = self.parameters();
+        let members: HashSet<MemberId> = self
+            .room_state
+            .members
+            .members
+            .iter()
+            .map(|m| m.member.id())
+            .collect();
+        let mut state = self.room_state.clone();
+        let mut epochs = Vec::new();
+        let current_version = state.secrets.current_version();
+        let rekey = state_before
+            .members
+            .members
+            .iter()
+            .any(|m| !members.contains(&m.member.id()))
+            || state.bans.min_secret_version(get_current_system_time()) > current_version;
+        if rekey || current_version.is_none() {
+            let secret_version = current_version.map_or(0, |v| v + 1);
+            // The epoch has to start after the bans it follows, whatever the banner's clock said
+            let created_at = state
+                .bans
+                .0
+                .iter()
+                .filter(|ban| ban.min_secret_version > current_version)
+                .map(|ban| ban.ban.banned_at + Duration::from_nanos(1))
+                .chain([get_current_system_time()])
+                .max()
+                .expect("The current time is always there");
+            let epoch = SecretEpoch {
+                secret_version,
+                created_at,
+            };
+            let epoch = AuthorizedSecretEpoch {
+                signature: key_store
+                    .sign(&self.self_vk, SignRequest::SecretEpoch(epoch.clone()))
+                    .await?,
+                epoch,
+            };
+            epochs.push(epoch.clone());
+            self.room_secrets
+                .insert(secret_version, rand::random::<[u8; 32]>());
+            // Record the epoch first so the secrets below can be wrapped for it
+            state.secrets.epochs.push(epoch);
+        }
+
+        let mut member_vks: HashMap<MemberId, VerifyingKey> = state
+            .members
+            .members
+            .iter()
+            .map(|m| (m.member.id(), m.member.member_vk))
+            .collect();
+        member_vks.insert(self.owner_id(), self.owner_vk);
+        let mut secrets = Vec::new();
+        for secret_version in state.secrets.versions() {
+            let epoch_id = state.secrets.epoch(secret_version).map(SecretEpoch::id);
+            let Some(secret) = self.room_secrets.get(&secret_version) else {
+                log::error!(
+                    "Missing our own copy of room secret version {}",
+                    secret_version
+                );
+                continue;
+            };
+            for member_id in state
+                .secrets
+                .members_without(secret_version, &state, &parameters)
+            {
+                let Some(member_vk) = member_vks.get(&member_id) else {
+                    continue;
+                };
+                let (ciphertext, nonce, sender_public_key) = ecies::encrypt(member_vk, secret);
+                let secret = EncryptedSecretForMember {
+                    member_id,
+                    secret_version,
+                    ciphertext,
+                    nonce,
+                    sender_public_key: sender_public_key.to_bytes(),
+                    epoch_id,
+                };
+                secrets.push(AuthorizedEncryptedSecretForMember {
+                    signature: key_store
+                        .sign(&self.self_vk, SignRequest::RoomSecret(secret.clone()))
+                        .await?,
+                    secret,
+                });
+            }
+        }
+        if epochs.is_empty() && secrets.is_empty() {
+            return Ok(());
+        }
+
+        let delta = ChatRoomStateV1Delta {
+            secrets: Some(RoomSecretsDelta { epochs, secrets }),
+            ..Default::default()
+        };
+        let current_state = self.room_state.clone();
+        if let Err(e) = self
+            .room_state
+            .apply_delta(&current_state, &parameters, &Some(delta))
+        {
+            log::error!("Failed to update room secrets: {}", e);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+pub mod ecies;
+
+use std::time::*;
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(inline_js = "
+export function get_current_time() {
+    return Date.now();
+}
+")]
+extern "C" {
+    fn get_current_time() -> f64;
+}
+
+pub fn get_current_system_time() -> SystemTime {
+    #[cfg(target_arch = "wasm32")]
+    {
+        // Convert milliseconds since epoch to a Duration
+        let millis = get_current_time();
+        let duration_since_epoch = Duration::from_millis(millis as u64);
+        UNIX_EPOCH + duration_since_epoch
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        SystemTime::now()
+    }
+}
+
+pub fn to_cbor_vec<T: serde::Serialize>(value: &T) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    ciborium::ser::into_writer(value, &mut buffer).unwrap();
+    buffer
+}
@@ -2,10 +2,11 @@ use aes_gcm::{
     aead::{Aead, KeyInit},
     Aes256Gcm, Nonce,
 };
+use common::util::diffie_hellman;
 use curve25519_dalek::edwards::CompressedEdwardsY;
 use ed25519_dalek::{SigningKey, VerifyingKey};
 use rand::rngs::OsRng;
-use sha2::{Digest, Sha256, Sha512};
+use sha2::{Digest, Sha256};
 use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret as X25519EphemeralSecret};
 
 /// Encrypts a plaintext message using ECIES (Elliptic Curve Integrated Encryption Scheme).
@@ -22,7 +23,6 @@ use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret as X25519Ephemeral
 /// * The encrypted ciphertext.
 /// * A 12-byte nonce used for encryption.
 /// * The ephemeral public key of the sender.
-#[allow(dead_code)]
 pub fn encrypt(
     recipient_public_key: &VerifyingKey,
     plaintext: &[u8],
@@ -52,7 +52,6 @@ pub fn encrypt(
     (ciphertext, nonce, sender_public_key)
 }
 
-#[allow(dead_code)]
 fn ed25519_to_x25519_public_key(ed25519_pk: &VerifyingKey) -> X25519PublicKey {
     let ed_y = CompressedEdwardsY(ed25519_pk.to_bytes())
         .decompress()
@@ -72,41 +71,56 @@ fn ed25519_to_x25519_public_key(ed25519_pk: &VerifyingKey) -> X25519PublicKey {
 ///
 /// # Returns
 ///
-/// The decrypted plaintext message as a vector of bytes.
-#[allow(dead_code)]
+/// The decrypted plaintext message as a vector of bytes, or an error if the ciphertext wasn't
+/// encrypted to this key.
 pub fn decrypt(
     recipient_private_key: &SigningKey,
     sender_public_key: &X25519PublicKey,
     ciphertext: &[u8],
     nonce: &[u8; 12],
-) -> Vec<u8> {
-    // Convert Ed25519 signing key to X25519 private key
-    let recipient_x25519_private_key = ed25519_to_x25519_private_key(recipient_private_key);
-
-    // Derive shared secret using recipient's private key and sender's public key
-    let shared_secret = recipient_x25519_private_key.diffie_hellman(sender_public_key);
+) -> Result<Vec<u8>, aes_gcm::Error> {
+    let shared_secret = diffie_hellman(recipient_private_key, sender_public_key.as_bytes());
+    decrypt_with_shared_secret(&shared_secret, ciphertext, nonce)
+}
 
+/// Like `decrypt` with the secret the recipient's key shares with the sender's ephemeral key
+/// already derived, eg. by a `KeyStore` that keeps the recipient's key.
+pub fn decrypt_with_shared_secret(
+    shared_secret: &[u8; 32],
+    ciphertext: &[u8],
+    nonce: &[u8; 12],
+) -> Result<Vec<u8>, aes_gcm::Error> {
     // Use the shared secret to derive the symmetric key
-    let symmetric_key = Sha256::digest(shared_secret.as_bytes());
+    let symmetric_key = Sha256::digest(shared_secret);
 
     // Decrypt the ciphertext using AES-GCM
     let cipher = Aes256Gcm::new_from_slice(&symmetric_key).expect("Failed to create cipher");
-    let decrypted_message = cipher
-        .decrypt(&Nonce::from(*nonce), ciphertext.as_ref())
-        .expect("decryption failure!");
+    cipher.decrypt(&Nonce::from(*nonce), ciphertext.as_ref())
+}
+
+/// Encrypts a plaintext message with a symmetric key, such as the room secret.
+///
+/// # Returns
+///
+/// A tuple containing the encrypted ciphertext and the 12-byte nonce used for encryption.
+pub fn encrypt_with_symmetric_key(key: &[u8; 32], plaintext: &[u8]) -> (Vec<u8>, [u8; 12]) {
+    let nonce = rand::random::<[u8; 12]>();
+    let cipher = Aes256Gcm::new_from_slice(key).expect("Failed to create cipher");
+    let ciphertext = cipher
+        .encrypt(&Nonce::from(nonce), plaintext)
+        .expect("encryption failure!");
 
-    decrypted_message
+    (ciphertext, nonce)
 }
 
-#[allow(dead_code)]
-fn ed25519_to_x25519_private_key(ed25519_sk: &SigningKey) -> X25519EphemeralSecret {
-    let h = Sha512::digest(ed25519_sk.to_bytes());
-    let mut key = [0u8; 32];
-    key.copy_from_slice(&h[..32]);
-    key[0] &= 248;
-    key[31] &= 127;
-    key[31] |= 64;
-    X25519EphemeralSecret::from(key)
+/// Decrypts a ciphertext message encrypted with `encrypt_with_symmetric_key`.
+pub fn decrypt_with_symmetric_key(
+    key: &[u8; 32],
+    ciphertext: &[u8],
+    nonce: &[u8; 12],
+) -> Result<Vec<u8>, aes_gcm::Error> {
+    let cipher = Aes256Gcm::new_from_slice(key).expect("Failed to create cipher");
+    cipher.decrypt(&Nonce::from(*nonce), ciphertext)
 }
 
 #[cfg(test)]
@@ -132,9 +146,27 @@ mod tests {
             &sender_public_key,
             &ciphertext,
             &nonce,
-        );
+        )
+        .unwrap();
 
         // Ensure the decrypted message matches the original
         assert_eq!(decrypted_message, plaintext);
+
+        // Only the recipient can decrypt it
+        let other_private_key = SigningKey::generate(&mut rng);
+        assert!(decrypt(&other_private_key, &sender_public_key, &ciphertext, &nonce).is_err());
+    }
+
+    #[test]
+    fn test_symmetric_encryption_decryption() {
+        let key = rand::random::<[u8; 32]>();
+        let plaintext = b"Secret message";
+        let (ciphertext, nonce) = encrypt_with_symmetric_key(&key, plaintext);
+
+        assert_eq!(
+            decrypt_with_symmetric_key(&key, &ciphertext, &nonce).unwrap(),
+            plaintext
+        );
+        assert!(decrypt_with_symmetric_key(&[0; 32], &ciphertext, &nonce).is_err());
     }
 }
@@ -0,0 +1,153 @@
+//! Sizes of summaries and of the deltas computed from them for rooms with thousands of members,
+//! with each field listed or compacted. Run with `cargo bench -p river-common --bench summary_size`.
+
+use ed25519_dalek::SigningKey;
+use freenet_scaffold::ComposableState;
+use rand::rngs::OsRng;
+use river_common::room_state::configuration::{AuthorizedConfigurationV1, Configuration};
+use river_common::room_state::member::{AuthorizedMember, Member, MembersDelta};
+use river_common::room_state::message::{AuthorizedMessageV1, MessageV1, MessagesDelta};
+use river_common::room_state::summary::SummaryCompaction;
+use river_common::room_state::{ChatRoomParametersV1, ChatRoomStateV1Delta};
+use river_common::ChatRoomStateV1;
+use serde::Serialize;
+use std::time::SystemTime;
+
+const MESSAGES: usize = 100;
+/// The peer being summarized is missing one in this many members, and the first message
+const MISSING_ONE_IN: usize = 100;
+
+fn main() {
+    println!(
+        "{:>8} {:>10} {:>14} {:>12} {:>10} {:>8}",
+        "members", "compacted", "summary bytes", "delta bytes", "missing", "missed"
+    );
+    let settings = [
+        ("none", compaction(false, false)),
+        ("members", compaction(true, false)),
+        ("messages", compaction(false, true)),
+        ("all", SummaryCompaction::default()),
+    ];
+    for members in [1000, 2000, 5000] {
+        let (parameters, state, peer_state) = room(members);
+        for (compacted, compaction) in &settings {
+            let mut summary = peer_state.summarize(&peer_state, &parameters);
+            summary.compact(compaction);
+            let delta = state.delta(&state, &parameters, &summary);
+            // What the peer still lacks once it applied the delta was missed because of a false
+            // positive in the summary
+            let mut updated = peer_state.clone();
+            updated
+                .apply_delta(&peer_state, &parameters, &delta)
+                .unwrap();
+            let missing = count(&state) - count(&peer_state);
+            let missed = count(&state) - count(&updated);
+            println!(
+                "{:>8} {:>10} {:>14} {:>12} {:>10} {:>8}",
+                members,
+                compacted,
+                size(&summary),
+                size(&delta),
+                missing,
+                missed
+            );
+        }
+    }
+}
+
+/// Compacts only members and messages, the room has no bans or reactions
+fn compaction(members: bool, messages: bool) -> SummaryCompaction {
+    SummaryCompaction {
+        bans: false,
+        members,
+        messages,
+        reactions: false,
+        ..SummaryCompaction::default()
+    }
+}
+
+/// A room with `members` members who posted `MESSAGES` messages, and the state of a peer that
+/// is missing some of both
+fn room(members: usize) -> (ChatRoomParametersV1, ChatRoomStateV1, ChatRoomStateV1) {
+    let owner_signing_key = SigningKey::generate(&mut OsRng);
+    let parameters = ChatRoomParametersV1 {
+        owner: owner_signing_key.verifying_key(),
+    };
+    let owner_id = parameters.owner_id();
+    let configuration = Configuration {
+        owner_member_id: owner_id,
+        max_members: members,
+        max_recent_messages: MESSAGES,
+        ..Configuration::default()
+    };
+    let empty = ChatRoomStateV1 {
+        configuration: AuthorizedConfigurationV1::new(configuration, &owner_signing_key),
+        ..ChatRoomStateV1::default()
+    };
+
+    let keys: Vec<SigningKey> = (0..members)
+        .map(|_| SigningKey::generate(&mut OsRng))
+        .collect();
+    let members: Vec<AuthorizedMember> = keys
+        .iter()
+        .map(|key| {
+            let member = Member {
+                owner_member_id: owner_id,
+                invited_by: owner_id,
+                member_vk: key.verifying_key(),
+            };
+            AuthorizedMember::new(member, &owner_signing_key)
+        })
+        .collect();
+    let messages: Vec<AuthorizedMessageV1> = keys[..MESSAGES]
+        .iter()
+        .map(|key| {
+            let message = MessageV1 {
+                room_owner: owner_id,
+                author: key.verifying_key().into(),
+                time: SystemTime::now(),
+                content: "Hello".to_string(),
+            };
+            AuthorizedMessageV1::new(message, key)
+        })
+        .collect();
+
+    let with = |members: Vec<AuthorizedMember>, messages: Vec<AuthorizedMessageV1>| {
+        let delta = ChatRoomStateV1Delta {
+            members: Some(MembersDelta::new(members)),
+            recent_messages: Some(MessagesDelta {
+                messages,
+                actions: Vec::new(),
+            }),
+            ..Default::default()
+        };
+        let mut state = empty.clone();
+        state
+            .apply_delta(&empty, &parameters, &Some(delta))
+            .unwrap();
+        state
+    };
+    let state = with(members.clone(), messages.clone());
+    // Authors stay, so that every message the peer has is still valid
+    let peer_state = with(
+        members
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| *i < MESSAGES || i % MISSING_ONE_IN != 0)
+            .map(|(_, member)| member)
+            .collect(),
+        messages.into_iter().skip(1).collect(),
+    );
+    (parameters, state, peer_state)
+}
+
+/// Members and messages in `state`
+fn count(state: &ChatRoomStateV1) -> usize {
+    state.members.members.len() + state.recent_messages.messages.entries.len()
+}
+
+fn size(value: &impl Serialize) -> usize {
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(value, &mut bytes).unwrap();
+    bytes.len()
+}
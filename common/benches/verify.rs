@@ -0,0 +1,144 @@
+//! Time to verify rooms with 200, 2,000 and 20,000 members, invited or joined through invitations,
+//! and for a peer to take in all the members of the larger ones.
+//! Run with `cargo bench -p river-common --bench verify`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use ed25519_dalek::SigningKey;
+use freenet_scaffold::ComposableState;
+use rand::rngs::OsRng;
+use river_common::room_state::configuration::{AuthorizedConfigurationV1, Configuration};
+use river_common::room_state::member::{
+    AuthorizedMember, InvitationToken, Member, MembersDelta, MembersV1,
+};
+use river_common::room_state::message::{AuthorizedMessageV1, MessageV1, MessagesV1};
+use river_common::room_state::ChatRoomParametersV1;
+use river_common::ChatRoomStateV1;
+use std::time::{Duration, SystemTime};
+
+const MESSAGES: usize = 100;
+/// How many members each member invited, the owner invited as many
+const INVITES: usize = 4;
+
+/// A room with `members` members in an invite tree under the owner, the first of them posted
+/// `MESSAGES` messages. With `redeemed` the members the owner didn't invite joined by redeeming an
+/// invitation from their inviter.
+fn room(members: usize, redeemed: bool) -> (ChatRoomParametersV1, ChatRoomStateV1) {
+    let owner_signing_key = SigningKey::generate(&mut OsRng);
+    let parameters = ChatRoomParametersV1 {
+        owner: owner_signing_key.verifying_key(),
+    };
+    let owner_id = parameters.owner_id();
+    let configuration = Configuration {
+        owner_member_id: owner_id,
+        max_members: members,
+        max_recent_messages: MESSAGES,
+        ..Configuration::default()
+    };
+
+    let keys: Vec<SigningKey> = (0..members)
+        .map(|_| SigningKey::generate(&mut OsRng))
+        .collect();
+    let mut members: Vec<AuthorizedMember> = keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| {
+            let inviter_key = match i.checked_sub(INVITES) {
+                Some(i) => &keys[i / INVITES],
+                None => &owner_signing_key,
+            };
+            if redeemed && i >= INVITES {
+                let expires_at = SystemTime::now() + Duration::from_secs(3600);
+                let token = InvitationToken::new(parameters.owner, inviter_key, expires_at, None);
+                return token
+                    .redeem(key.verifying_key(), SystemTime::now())
+                    .unwrap();
+            }
+            let member = Member {
+                owner_member_id: owner_id,
+                invited_by: inviter_key.verifying_key().into(),
+                member_vk: key.verifying_key(),
+            };
+            AuthorizedMember::new(member, inviter_key)
+        })
+        .collect();
+    members.sort_by_key(|m| m.member.id());
+    let redemptions = members
+        .iter()
+        .filter(|m| m.redemption().is_some())
+        .cloned()
+        .collect();
+    let messages: Vec<AuthorizedMessageV1> = keys[..MESSAGES]
+        .iter()
+        .map(|key| {
+            let message = MessageV1 {
+                room_owner: owner_id,
+                author: key.verifying_key().into(),
+                time: SystemTime::now(),
+                content: "Hello".to_string(),
+            };
+            AuthorizedMessageV1::new(message, key)
+        })
+        .collect();
+
+    let state = ChatRoomStateV1 {
+        configuration: AuthorizedConfigurationV1::new(configuration, &owner_signing_key),
+        members: MembersV1 {
+            members,
+            redemptions,
+            ..MembersV1::default()
+        },
+        recent_messages: MessagesV1 {
+            messages: messages.into(),
+            ..MessagesV1::default()
+        },
+        ..ChatRoomStateV1::default()
+    };
+    (parameters, state)
+}
+
+fn verify(c: &mut Criterion) {
+    let mut group = c.benchmark_group("verify");
+    group.sample_size(10);
+    for members in [200, 2_000, 20_000] {
+        let (parameters, state) = room(members, false);
+        assert_eq!(state.verify(&state, &parameters), Ok(()));
+        group.bench_with_input(BenchmarkId::new("members", members), &state, |b, state| {
+            b.iter(|| state.members.verify(state, &parameters))
+        });
+        group.bench_with_input(BenchmarkId::new("room", members), &state, |b, state| {
+            b.iter(|| state.verify(state, &parameters))
+        });
+
+        let (parameters, state) = room(members, true);
+        assert_eq!(state.verify(&state, &parameters), Ok(()));
+        group.bench_with_input(
+            BenchmarkId::new("redeemed members", members),
+            &state,
+            |b, state| b.iter(|| state.members.verify(state, &parameters)),
+        );
+    }
+    group.finish();
+}
+
+fn apply_delta(c: &mut Criterion) {
+    let mut group = c.benchmark_group("apply_delta");
+    group.sample_size(10);
+    for members in [2_000, 20_000] {
+        let (parameters, state) = room(members, true);
+        let delta = Some(MembersDelta::new(state.members.members.clone()));
+        group.bench_with_input(BenchmarkId::new("members", members), &state, |b, state| {
+            b.iter_batched(
+                MembersV1::default,
+                |mut joined| {
+                    joined.apply_delta(state, &parameters, &delta).unwrap();
+                    assert_eq!(joined.members.len(), members);
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, verify, apply_delta);
+criterion_main!(benches);
@@ -0,0 +1,518 @@
+//! Messages between the UI and the chat delegate, which keeps our signing keys and the rooms we're
+//! in, and signs on the UI's behalf so that the keys never leave the delegate's secret storage. It
+//! only signs what we do in our rooms, never bytes it can't check.
+
+use crate::keyring::{EncryptedKeyring, KeyringKey};
+use crate::room_state::ban::{Unban, UserBan};
+use crate::room_state::configuration::{Configuration, RoomRename};
+use crate::room_state::member::{Invitation, Member, MemberId, Removal};
+use crate::room_state::member_info::MemberInfo;
+use crate::room_state::message::{Message, MessageActionV1};
+use crate::room_state::pin::PinnedMessages;
+use crate::room_state::reaction::Reaction;
+use crate::room_state::secret::{EncryptedSecretForMember, SecretEpoch};
+use crate::room_state::upgrade::UpgradeV1;
+use crate::room_state::ChatRoomParametersV1;
+use crate::util::sign_struct;
+use crate::ChatRoomStateV1;
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use freenet_scaffold::ComposableState;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::SystemTime;
+
+/// Keys are identified by their verifying key, which is our member key in the rooms we use it in
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ChatDelegateRequestMsg {
+    /// Keep `self_sk`, replacing the same key kept before
+    StoreKey { self_sk: SigningKey },
+    /// Forget a key and the rooms we're in with it
+    RemoveKey { self_vk: VerifyingKey },
+    /// The keys we have
+    ListKeys,
+    /// Keep that we're in the room owned by `owner_vk` with `self_vk`, a key that's kept,
+    /// replacing the key we were in it with before
+    StoreRoom {
+        owner_vk: VerifyingKey,
+        self_vk: VerifyingKey,
+    },
+    /// Forget a room, our key in it is kept
+    RemoveRoom { owner_vk: VerifyingKey },
+    /// The rooms we're in
+    ListRooms,
+    /// Sign what we do in one of our rooms, see `SignRequest::check`
+    Sign {
+        self_vk: VerifyingKey,
+        request: SignRequest,
+    },
+    /// Sign as the room's new owner what the owner of the room owned by `owner_vk` signed, see
+    /// `ChatRoomStateV1::succeeded_by` and `check_succession`
+    SignSuccession {
+        self_vk: VerifyingKey,
+        owner_vk: VerifyingKey,
+        room_state: Box<ChatRoomStateV1>,
+    },
+    /// The secret our key shares with `public_key`, to open what was encrypted for us, see
+    /// `util::diffie_hellman`
+    SharedSecret {
+        self_vk: VerifyingKey,
+        public_key: [u8; 32],
+    },
+    /// Our key in every room kept, encrypted with `key` to export our identity, see
+    /// `keyring::Keyring`. The keys only leave the delegate encrypted.
+    ExportKeys { key: KeyringKey },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ChatDelegateResponseMsg {
+    KeyStored {
+        self_vk: VerifyingKey,
+    },
+    KeyRemoved {
+        self_vk: VerifyingKey,
+    },
+    Keys(Vec<VerifyingKey>),
+    RoomStored {
+        owner_vk: VerifyingKey,
+    },
+    RoomRemoved {
+        owner_vk: VerifyingKey,
+    },
+    Rooms(Vec<KeptRoom>),
+    Signed {
+        self_vk: VerifyingKey,
+        signature: Signature,
+    },
+    /// A signature for each of `ChatRoomStateV1::succession_payloads`, in order
+    SignedSuccession {
+        self_vk: VerifyingKey,
+        signatures: Vec<Signature>,
+    },
+    SharedSecret {
+        self_vk: VerifyingKey,
+        public_key: [u8; 32],
+        secret: [u8; 32],
+    },
+    ExportedKeys(EncryptedKeyring),
+    Error(ChatDelegateError),
+}
+
+/// What we sign in a room, as it's signed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SignRequest {
+    Message(Message),
+    MessageAction(MessageActionV1),
+    Reaction(Reaction),
+    /// Inviting a member, see `AuthorizedMember::new_at`
+    Member {
+        member: Member,
+        joined_at: SystemTime,
+    },
+    Invitation(Invitation),
+    Ban(UserBan),
+    Unban(Unban),
+    Removal(Box<Removal>),
+    MemberInfo(MemberInfo),
+    Configuration(Configuration),
+    Rename(RoomRename),
+    Upgrade(UpgradeV1),
+    PinnedMessages(PinnedMessages),
+    SecretEpoch(SecretEpoch),
+    RoomSecret(EncryptedSecretForMember),
+}
+
+impl SignRequest {
+    /// Checks that it's ours to sign with `self_vk`: whoever it names as its signer is us, and the
+    /// room it's for is one of `rooms` we're in with that key. What doesn't name a room must be
+    /// for a room we're in with the key, and what only owners sign for a room we own. Whether we
+    /// may do it in the room is left to the room's contract.
+    pub fn check(
+        &self,
+        self_vk: &VerifyingKey,
+        rooms: &[KeptRoom],
+    ) -> Result<(), ChatDelegateError> {
+        let self_id = MemberId::from(self_vk);
+        let (room_owner, signer) = match self {
+            SignRequest::Message(message) => (Some(message.room_owner()), Some(message.author())),
+            SignRequest::MessageAction(action) => (Some(action.room_owner), Some(action.author)),
+            SignRequest::Reaction(reaction) => (None, Some(reaction.member_id)),
+            SignRequest::Member { member, .. } => {
+                (Some(member.owner_member_id), Some(member.invited_by))
+            }
+            SignRequest::Invitation(invitation) => (
+                Some(invitation.owner_member_id),
+                Some(invitation.invited_by),
+            ),
+            SignRequest::Ban(ban) => (Some(ban.owner_member_id), None),
+            SignRequest::Unban(unban) => (Some(unban.owner_member_id), None),
+            SignRequest::Removal(removal) => (
+                Some(removal.owner_member_id),
+                Some(MemberId::from(&removal.removed_by)),
+            ),
+            SignRequest::MemberInfo(info) => (None, Some(info.member_id)),
+            SignRequest::Rename(_) => (None, None),
+            SignRequest::Configuration(Configuration {
+                owner_member_id, ..
+            })
+            | SignRequest::PinnedMessages(PinnedMessages {
+                owner_member_id, ..
+            }) => (Some(*owner_member_id), Some(*owner_member_id)),
+            // A designated successor hands the room over to themselves
+            SignRequest::Upgrade(upgrade) if upgrade.new_owner == Some(*self_vk) => {
+                (Some(upgrade.owner_member_id), None)
+            }
+            SignRequest::Upgrade(upgrade) => {
+                (Some(upgrade.owner_member_id), Some(upgrade.owner_member_id))
+            }
+            SignRequest::SecretEpoch(_) | SignRequest::RoomSecret(_) => (Some(self_id), None),
+        };
+        if let Some(signer) = signer.filter(|signer| *signer != self_id) {
+            return Err(ChatDelegateError::Refused(format!(
+                "signed by {}, not {}",
+                signer, self_id
+            )));
+        }
+        let in_room = rooms.iter().any(|room| {
+            room.self_vk == *self_vk
+                && room_owner.is_none_or(|owner| MemberId::from(&room.owner_vk) == owner)
+        });
+        if !in_room {
+            return Err(ChatDelegateError::Refused(match room_owner {
+                Some(owner) => format!("not in room {} as {}", owner, self_id),
+                None => format!("not in a room as {}", self_id),
+            }));
+        }
+        Ok(())
+    }
+
+    pub fn sign(&self, signing_key: &SigningKey) -> Signature {
+        match self {
+            SignRequest::Message(message) => sign_struct(message, signing_key),
+            SignRequest::MessageAction(action) => sign_struct(action, signing_key),
+            SignRequest::Reaction(reaction) => sign_struct(reaction, signing_key),
+            SignRequest::Member { member, joined_at } => {
+                sign_struct((member, joined_at), signing_key)
+            }
+            SignRequest::Invitation(invitation) => sign_struct(invitation, signing_key),
+            SignRequest::Ban(ban) => sign_struct(ban, signing_key),
+            SignRequest::Unban(unban) => sign_struct(unban, signing_key),
+            SignRequest::Removal(removal) => sign_struct(removal, signing_key),
+            SignRequest::MemberInfo(info) => sign_struct(info, signing_key),
+            SignRequest::Configuration(configuration) => sign_struct(configuration, signing_key),
+            SignRequest::Rename(rename) => sign_struct(rename, signing_key),
+            SignRequest::Upgrade(upgrade) => sign_struct(upgrade, signing_key),
+            SignRequest::PinnedMessages(pinned) => sign_struct(pinned, signing_key),
+            SignRequest::SecretEpoch(epoch) => sign_struct(epoch, signing_key),
+            SignRequest::RoomSecret(secret) => sign_struct(secret, signing_key),
+        }
+    }
+}
+
+/// Checks that we can take the room owned by `owner_vk` over with `self_vk` and returns what we
+/// sign to, see `ChatRoomStateV1::succession_payloads`. We must own the room or be its designated
+/// successor, be in the room that continues it, and `room_state` must be the room's valid state
+/// so that what's signed again was signed by the owner.
+pub fn check_succession(
+    self_vk: &VerifyingKey,
+    owner_vk: &VerifyingKey,
+    room_state: &ChatRoomStateV1,
+    rooms: &[KeptRoom],
+) -> Result<Vec<Vec<u8>>, ChatDelegateError> {
+    let refused = |reason: &str| Err(ChatDelegateError::Refused(reason.to_string()));
+    let successor = room_state.configuration.configuration.successor;
+    let ours = rooms.iter().any(|room| {
+        room.owner_vk == *owner_vk && (room.self_vk == *owner_vk || successor == Some(*self_vk))
+    });
+    if !ours {
+        return refused("not the room's owner or successor");
+    }
+    if !rooms.contains(&KeptRoom {
+        owner_vk: *self_vk,
+        self_vk: *self_vk,
+    }) {
+        return refused("not in the room that continues it");
+    }
+    let parameters = ChatRoomParametersV1 { owner: *owner_vk };
+    if let Err(e) = room_state.verify(room_state, &parameters) {
+        return Err(ChatDelegateError::Refused(e.to_string()));
+    }
+    Ok(room_state.succession_payloads(&parameters, *self_vk))
+}
+
+/// A room we're in, by its owner's key, and our key in it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct KeptRoom {
+    pub owner_vk: VerifyingKey,
+    pub self_vk: VerifyingKey,
+}
+
+/// Why the delegate turned down a request
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ChatDelegateError {
+    /// The key isn't kept
+    UnknownKey { member: MemberId },
+    /// A request or stored secret that doesn't decode
+    Malformed(String),
+    /// Something that isn't ours to sign, see `SignRequest::check`
+    Refused(String),
+}
+
+impl fmt::Display for ChatDelegateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatDelegateError::UnknownKey { member } => {
+                write!(f, "No key for member {}", member)
+            }
+            ChatDelegateError::Malformed(error) => write!(f, "Malformed: {}", error),
+            ChatDelegateError::Refused(reason) => write!(f, "Refused to sign: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ChatDelegateError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::ban::BanId;
+    use crate::room_state::message::{MessageAction, MessageId, MessageV1};
+    use blake3::Hash;
+    use ciborium::{de::from_reader, ser::into_writer};
+    use freenet_scaffold::util::VersionedHash;
+    use rand::rngs::OsRng;
+
+    fn generate_vk() -> VerifyingKey {
+        SigningKey::generate(&mut OsRng).verifying_key()
+    }
+
+    /// A request of each type that names who signs it, in the room owned by `room_owner`
+    fn signed_by(signer: &VerifyingKey, room_owner: MemberId) -> Vec<SignRequest> {
+        let signer_id = MemberId::from(signer);
+        let now = SystemTime::now();
+        let message_id = MessageId(VersionedHash::Blake3V1([0; 32]));
+        vec![
+            SignRequest::Message(Message::from(MessageV1 {
+                room_owner,
+                author: signer_id,
+                time: now,
+                content: "Hello".to_string(),
+            })),
+            SignRequest::MessageAction(MessageActionV1 {
+                room_owner,
+                author: signer_id,
+                time: now,
+                target: message_id.clone(),
+                action: MessageAction::Delete,
+            }),
+            SignRequest::Reaction(Reaction {
+                message_id,
+                member_id: signer_id,
+                time: now,
+                reaction: "👍".to_string(),
+            }),
+            SignRequest::Member {
+                member: Member {
+                    owner_member_id: room_owner,
+                    invited_by: signer_id,
+                    member_vk: generate_vk(),
+                },
+                joined_at: now,
+            },
+            SignRequest::Invitation(Invitation {
+                owner_member_id: room_owner,
+                invited_by: signer_id,
+                invitation_vk: generate_vk(),
+                expires_at: now,
+                max_uses: None,
+            }),
+            SignRequest::Removal(Box::new(Removal {
+                owner_member_id: room_owner,
+                member_vk: generate_vk(),
+                removed_by: *signer,
+                removed_at: now,
+                signed_for: None,
+            })),
+            SignRequest::MemberInfo(MemberInfo {
+                member_id: signer_id,
+                version: 1,
+                preferred_nickname: "Alice".to_string(),
+            }),
+        ]
+    }
+
+    /// A request of each type only the owner of the room owned by `room_owner` signs
+    fn owned_by(room_owner: MemberId) -> Vec<SignRequest> {
+        vec![
+            SignRequest::Configuration(Configuration {
+                owner_member_id: room_owner,
+                ..Configuration::default()
+            }),
+            SignRequest::PinnedMessages(PinnedMessages {
+                owner_member_id: room_owner,
+                version: 1,
+                message_ids: Vec::new(),
+            }),
+            SignRequest::Upgrade(UpgradeV1 {
+                owner_member_id: room_owner,
+                version: 1,
+                new_chatroom_address: Hash::from([0; 32]),
+                new_owner: None,
+            }),
+        ]
+    }
+
+    /// What only owners sign that doesn't name the room, it must be for one we own
+    fn room_secrets() -> Vec<SignRequest> {
+        vec![
+            SignRequest::SecretEpoch(SecretEpoch {
+                secret_version: 1,
+                created_at: SystemTime::now(),
+            }),
+            SignRequest::RoomSecret(EncryptedSecretForMember {
+                member_id: MemberId::from(&generate_vk()),
+                secret_version: 1,
+                ciphertext: vec![0; 48],
+                nonce: [0; 12],
+                sender_public_key: [0; 32],
+                epoch_id: None,
+            }),
+        ]
+    }
+
+    fn is_refused(result: Result<(), ChatDelegateError>) -> bool {
+        matches!(result, Err(ChatDelegateError::Refused(_)))
+    }
+
+    #[test]
+    fn test_sign_what_is_ours() {
+        let self_vk = generate_vk();
+        let owner_vk = generate_vk();
+        let rooms = [
+            KeptRoom { owner_vk, self_vk },
+            KeptRoom {
+                owner_vk: self_vk,
+                self_vk,
+            },
+        ];
+        let requests = signed_by(&self_vk, owner_vk.into())
+            .into_iter()
+            .chain(owned_by(self_vk.into()))
+            .chain(room_secrets())
+            .chain([
+                SignRequest::Ban(UserBan {
+                    owner_member_id: owner_vk.into(),
+                    banned_at: SystemTime::now(),
+                    banned_user: generate_vk().into(),
+                    expires_at: None,
+                }),
+                SignRequest::Rename(RoomRename {
+                    configuration_version: 1,
+                    name: "Renamed".to_string(),
+                    renamed_at: SystemTime::now(),
+                }),
+                // Handing the room over to us as its successor
+                SignRequest::Upgrade(UpgradeV1 {
+                    owner_member_id: owner_vk.into(),
+                    version: 1,
+                    new_chatroom_address: Hash::from([0; 32]),
+                    new_owner: Some(self_vk),
+                }),
+            ]);
+        for request in requests {
+            assert_eq!(request.check(&self_vk, &rooms), Ok(()), "{:?}", request);
+        }
+    }
+
+    #[test]
+    fn test_refuse_foreign_keys() {
+        let self_vk = generate_vk();
+        let owner_vk = generate_vk();
+        let rooms = [KeptRoom { owner_vk, self_vk }];
+
+        // Signed by someone else in a room we're in, including the room's owner
+        for signer in [generate_vk(), owner_vk] {
+            for request in signed_by(&signer, owner_vk.into()) {
+                assert!(is_refused(request.check(&self_vk, &rooms)), "{:?}", request);
+            }
+        }
+        // A successor's upgrade only for the successor
+        let upgrade = SignRequest::Upgrade(UpgradeV1 {
+            owner_member_id: owner_vk.into(),
+            version: 1,
+            new_chatroom_address: Hash::from([0; 32]),
+            new_owner: Some(generate_vk()),
+        });
+        assert!(is_refused(upgrade.check(&self_vk, &rooms)));
+    }
+
+    #[test]
+    fn test_refuse_rooms_not_ours() {
+        let self_vk = generate_vk();
+        let owner_vk = generate_vk();
+
+        // Not in the room, or in it with another of our keys
+        for rooms in [
+            Vec::new(),
+            vec![KeptRoom {
+                owner_vk: generate_vk(),
+                self_vk,
+            }],
+            vec![KeptRoom {
+                owner_vk,
+                self_vk: generate_vk(),
+            }],
+        ] {
+            let requests = signed_by(&self_vk, owner_vk.into())
+                .into_iter()
+                .filter(|request| {
+                    // Those that don't name a room only need one we're in with the key
+                    rooms.iter().all(|room| room.self_vk != self_vk)
+                        || !matches!(
+                            request,
+                            SignRequest::Reaction(_) | SignRequest::MemberInfo(_)
+                        )
+                })
+                .chain([SignRequest::Unban(Unban {
+                    owner_member_id: owner_vk.into(),
+                    ban_id: BanId(VersionedHash::Blake3V1([0; 32])),
+                    unbanned_at: SystemTime::now(),
+                })]);
+            for request in requests {
+                assert!(is_refused(request.check(&self_vk, &rooms)), "{:?}", request);
+            }
+        }
+
+        // Only owners sign these, even as the owner's signer in a room we're a member of
+        let rooms = [KeptRoom { owner_vk, self_vk }];
+        for request in owned_by(owner_vk.into()).into_iter().chain(room_secrets()) {
+            assert!(is_refused(request.check(&self_vk, &rooms)), "{:?}", request);
+        }
+        for request in owned_by(self_vk.into()) {
+            assert!(is_refused(request.check(&self_vk, &rooms)), "{:?}", request);
+        }
+    }
+
+    #[test]
+    fn test_untyped_payload() {
+        /// How `Sign` took the bytes to sign before requests were typed
+        #[derive(Serialize)]
+        enum UntypedRequestMsg {
+            Sign {
+                self_vk: VerifyingKey,
+                payload: Vec<u8>,
+            },
+        }
+        let mut payload = Vec::new();
+        into_writer(&Configuration::default(), &mut payload).unwrap();
+        let mut bytes = Vec::new();
+        into_writer(
+            &UntypedRequestMsg::Sign {
+                self_vk: generate_vk(),
+                payload,
+            },
+            &mut bytes,
+        )
+        .unwrap();
+        assert!(from_reader::<ChatDelegateRequestMsg, _>(bytes.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! Our keys as they're exported or stored without a node, encrypted with a key derived from the
+//! user's passphrase. The chat delegate encrypts them itself so that they never leave it in the
+//! clear.
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use argon2::Argon2;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+const KEYRING_VERSION: u8 = 1;
+
+/// Our signing key in each room, by the room owner's key
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Keyring {
+    pub keys: HashMap<VerifyingKey, SigningKey>,
+}
+
+/// A keyring as it's stored or exported, encrypted with a key derived from the user's passphrase
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct EncryptedKeyring {
+    version: u8,
+    salt: [u8; 16],
+    nonce: [u8; 12],
+    ciphertext: Vec<u8>,
+}
+
+/// The key a keyring is encrypted with. Deriving it from the passphrase is slow on purpose, so
+/// it's derived once when the keyring is unlocked and kept for the session.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct KeyringKey {
+    salt: [u8; 16],
+    key: [u8; 32],
+}
+
+impl fmt::Debug for KeyringKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The key is deliberately left out
+        f.debug_struct("KeyringKey").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum KeyringError {
+    /// The passphrase doesn't decrypt the keyring, or the keyring was tampered with
+    WrongPassphrase,
+    /// A keyring from a newer version of River
+    UnsupportedVersion(u8),
+    Malformed(String),
+}
+
+impl KeyringKey {
+    /// A key for a new keyring protected by `passphrase`
+    pub fn new(passphrase: &str) -> Result<Self, KeyringError> {
+        Self::derive(passphrase, rand::random())
+    }
+
+    /// Decrypts `encrypted` with `passphrase`, along with the key to encrypt it with from then on
+    pub fn unlock(
+        passphrase: &str,
+        encrypted: &EncryptedKeyring,
+    ) -> Result<(Self, Keyring), KeyringError> {
+        if encrypted.version != KEYRING_VERSION {
+            return Err(KeyringError::UnsupportedVersion(encrypted.version));
+        }
+        let key = Self::derive(passphrase, encrypted.salt)?;
+        let plaintext = key
+            .cipher()
+            .decrypt(
+                &Nonce::from(encrypted.nonce),
+                encrypted.ciphertext.as_slice(),
+            )
+            .map_err(|_| KeyringError::WrongPassphrase)?;
+        let keyring = ciborium::de::from_reader(plaintext.as_slice())
+            .map_err(|e| KeyringError::Malformed(e.to_string()))?;
+        Ok((key, keyring))
+    }
+
+    pub fn encrypt(&self, keyring: &Keyring) -> EncryptedKeyring {
+        let mut plaintext = Vec::new();
+        ciborium::ser::into_writer(keyring, &mut plaintext).expect("Serialization should not fail");
+        let nonce = rand::random::<[u8; 12]>();
+        let ciphertext = self
+            .cipher()
+            .encrypt(&Nonce::from(nonce), plaintext.as_slice())
+            .expect("encryption failure!");
+        EncryptedKeyring {
+            version: KEYRING_VERSION,
+            salt: self.salt,
+            nonce,
+            ciphertext,
+        }
+    }
+
+    fn derive(passphrase: &str, salt: [u8; 16]) -> Result<Self, KeyringError> {
+        let mut key = [0; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| KeyringError::Malformed(e.to_string()))?;
+        Ok(KeyringKey { salt, key })
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new_from_slice(&self.key).expect("Failed to create cipher")
+    }
+}
+
+impl fmt::Display for KeyringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyringError::WrongPassphrase => write!(f, "Wrong passphrase"),
+            KeyringError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "Keyring version {} needs a newer version of River",
+                    version
+                )
+            }
+            KeyringError::Malformed(error) => write!(f, "Invalid keyring: {}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_encrypt_unlock() {
+        let mut keyring = Keyring::default();
+        for _ in 0..3 {
+            keyring.keys.insert(
+                SigningKey::generate(&mut OsRng).verifying_key(),
+                SigningKey::generate(&mut OsRng),
+            );
+        }
+        let key = KeyringKey::new("correct horse").unwrap();
+        let encrypted = key.encrypt(&keyring);
+
+        let (unlocked_key, unlocked) = KeyringKey::unlock("correct horse", &encrypted).unwrap();
+        assert!(unlocked == keyring);
+        // The key unlocked is the one the keyring was encrypted with
+        let (_, unlocked) =
+            KeyringKey::unlock("correct horse", &unlocked_key.encrypt(&keyring)).unwrap();
+        assert!(unlocked == keyring);
+
+        assert_eq!(
+            KeyringKey::unlock("battery staple", &encrypted).err(),
+            Some(KeyringError::WrongPassphrase)
+        );
+    }
+}
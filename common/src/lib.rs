@@ -1,4 +1,7 @@
+pub mod chat_delegate;
+pub mod keyring;
 pub mod room_state;
 pub mod util;
 
+pub use room_state::versioned::{ChatRoomState, ChatRoomStateV2};
 pub use room_state::ChatRoomStateV1;
@@ -1,15 +1,26 @@
 pub mod ban;
 pub mod configuration;
+#[cfg(test)]
+mod convergence_tests;
+pub mod error;
+pub mod legacy;
 pub mod member;
 pub mod member_info;
 pub mod message;
+pub mod pin;
+pub mod reaction;
+pub mod secret;
+pub mod summary;
 pub mod upgrade;
+pub mod versioned;
 
 use crate::room_state::ban::BansV1;
 use crate::room_state::configuration::AuthorizedConfigurationV1;
 use crate::room_state::member::{MemberId, MembersV1};
 use crate::room_state::member_info::MemberInfoV1;
 use crate::room_state::message::MessagesV1;
+use crate::room_state::reaction::ReactionsV1;
+use crate::room_state::secret::RoomSecretsV1;
 use crate::room_state::upgrade::OptionalUpgradeV1;
 use ed25519_dalek::VerifyingKey;
 use freenet_scaffold_macro::composable;
@@ -19,7 +30,8 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
 pub struct ChatRoomStateV1 {
     // WARNING: The order of these fields is important for the purposes of the #[composable] macro.
-    // `configuration` must be first, followed by `bans`, `members`, `member_info_modal`, and then `recent_messages`.
+    // `configuration` must be first, followed by `bans`, `members`, `secrets`, `member_info_modal`,
+    // `recent_messages` and then `reactions`.
     // This is due to interdependencies between the fields and the order in which they must be applied in
     // the `apply_delta` function. DO NOT reorder fields without fully understanding the implications.
     /// Configures things like maximum message length, can be updated by the owner.
@@ -32,12 +44,20 @@ pub struct ChatRoomStateV1 {
     /// The members in the chat room along with who invited them
     pub members: MembersV1,
 
+    /// In private rooms, the room secret encrypted by the owner for each member.
+    #[serde(default)]
+    pub secrets: RoomSecretsV1,
+
     /// Metadata about members like their nickname, can be updated by members themselves.
     pub member_info: MemberInfoV1,
 
     /// The most recent messages in the chat room, the number is limited by the room configuration.
     pub recent_messages: MessagesV1,
 
+    /// Reactions to `recent_messages`, limited per member by the room configuration.
+    #[serde(default)]
+    pub reactions: ReactionsV1,
+
     /// If this contract has been replaced by a new contract this will contain the new contract address.
     /// This can only be set by the owner.
     pub upgrade: OptionalUpgradeV1,
@@ -131,8 +151,10 @@ mod tests {
                 configuration: config,
                 bans: BansV1::default(),
                 members: MembersV1::default(),
+                secrets: RoomSecretsV1::default(),
                 member_info: MemberInfoV1::default(),
                 recent_messages: MessagesV1::default(),
+                reactions: ReactionsV1::default(),
                 upgrade: OptionalUpgradeV1(None),
             },
             ChatRoomParametersV1 {
@@ -179,6 +201,7 @@ mod tests {
         );
         assert!(delta.bans.is_none(), "Bans delta should be None");
         assert!(delta.members.is_none(), "Members delta should be None");
+        assert!(delta.secrets.is_none(), "Secrets delta should be None");
         assert!(
             delta.member_info.is_none(),
             "Member info delta should be None"
@@ -187,6 +210,7 @@ mod tests {
             delta.recent_messages.is_none(),
             "Recent messages delta should be None"
         );
+        assert!(delta.reactions.is_none(), "Reactions delta should be None");
         assert!(delta.upgrade.is_none(), "Upgrade delta should be None");
 
         // Apply the partial delta
@@ -1,106 +1,140 @@
-use crate::room_state::member::MemberId;
+use crate::room_state::configuration::PrivacyMode;
+use crate::room_state::error::{RoomStateError, StateField};
 use crate::room_state::ChatRoomParametersV1;
-use crate::util::{sign_struct, verify_struct};
 use crate::ChatRoomStateV1;
-use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
-use freenet_scaffold::util::{fast_hash, FastHash};
+use freenet_scaffold::id_set::IdSet;
 use freenet_scaffold::ComposableState;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::hash::{Hash, Hasher};
+use std::collections::{HashMap, HashSet};
 use std::time::SystemTime;
 
+mod expiry;
+mod unban;
+mod user_ban;
+
+pub use unban::{AuthorizedUnban, Unban};
+pub use user_ban::{AuthorizedUserBan, BanId, UserBan};
+
+/*
+ A ban can expire, see `expiry.rs`. It ends earlier if it's lifted, the unban is kept with it
+ until it expires, see `Unban`.
+
+ Unlike messages, bans aren't kept in a `BoundedLog`: a ban changes in place when it's lifted, and
+ once the room has `max_user_bans` new bans are refused rather than evicting the oldest, which
+ would let whoever they banned back in.
+*/
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct BansV1(pub Vec<AuthorizedUserBan>);
 
 impl BansV1 {
+    /// The bans that haven't been lifted
+    pub fn in_effect(&self) -> impl Iterator<Item = &AuthorizedUserBan> {
+        self.0.iter().filter(|ban| ban.unban.is_none())
+    }
+
+    /// The lowest version of the room secret content posted at `time` may be encrypted with in a
+    /// private room, so that no one banned before then can read it
+    pub fn min_secret_version(&self, time: SystemTime) -> Option<u32> {
+        self.0
+            .iter()
+            .filter(|ban| ban.ban.banned_at < time)
+            .filter_map(|ban| ban.min_secret_version)
+            .max()
+    }
+
+    /// Records in each ban of a private room the lowest secret version messages after it may be
+    /// encrypted with, that of the first epoch the owner started after the ban or, until there is
+    /// one, the version after the current one. See `secret.rs`.
+    fn record_secret_versions(&mut self, parent_state: &ChatRoomStateV1) {
+        let private = parent_state.configuration.configuration.privacy_mode == PrivacyMode::Private;
+        let secrets = &parent_state.secrets;
+        let next_version = secrets.current_version().map_or(0, |v| v + 1);
+        for ban in &mut self.0 {
+            ban.min_secret_version = private.then(|| {
+                secrets
+                    .epochs
+                    .iter()
+                    .filter(|e| e.epoch.created_at > ban.ban.banned_at)
+                    .map(|e| e.epoch.secret_version)
+                    .min()
+                    .unwrap_or(next_version)
+            });
+        }
+    }
+
     fn get_invalid_bans(
         &self,
         parent_state: &ChatRoomStateV1,
         parameters: &ChatRoomParametersV1,
-    ) -> HashMap<BanId, String> {
-        let member_map = parent_state.members.members_by_member_id();
+    ) -> HashMap<BanId, RoomStateError> {
+        let index = parent_state.members.index(parameters);
+        let configuration = &parent_state.configuration.configuration;
         let mut invalid_bans = HashMap::new();
 
         for ban in &self.0 {
-            // Check banned member first
-            let banned_member = match member_map.get(&ban.ban.banned_user) {
-                Some(member) => member,
-                None => {
-                    invalid_bans.insert(
-                        ban.id(),
-                        "Banned member not found in member list".to_string(),
-                    );
-                    continue;
-                }
-            };
+            if ban.banned_by.0.is_legacy() || ban.ban.banned_user.0.is_legacy() {
+                invalid_bans.insert(
+                    ban.id(),
+                    RoomStateError::LegacyId {
+                        field: StateField::Ban,
+                        id: ban.id().0,
+                    },
+                );
+                continue;
+            }
 
-            // Skip banning member verification if banner is room owner
-            if ban.banned_by != parameters.owner_id() {
-                let banning_member = match member_map.get(&ban.banned_by) {
-                    Some(member) => member,
-                    None => {
-                        invalid_bans.insert(
-                            ban.id(),
-                            "Banning member not found in member list".to_string(),
-                        );
-                        continue;
-                    }
-                };
-                // No need to check invite chain if banner is owner
-                let mut current_member = banned_member;
-                let mut chain = Vec::new();
-                let mut is_valid = false;
-
-                while current_member.member.id() != parameters.owner_id() {
-                    chain.push(current_member);
-                    if current_member.member.id() == banning_member.member.id() {
-                        is_valid = true;
-                        break;
-                    }
-                    current_member = match member_map.get(&current_member.member.invited_by) {
-                        Some(m) => m,
-                        None => {
-                            invalid_bans.insert(
-                                ban.id(),
-                                format!(
-                                    "Inviting member not found for {:?}",
-                                    current_member.member.id()
-                                ),
-                            );
-                            break;
-                        }
-                    };
-                    if chain.contains(&current_member) {
-                        invalid_bans.insert(
-                            ban.id(),
-                            format!(
-                                "Self-invitation detected for member {:?}",
-                                current_member.member.id()
-                            ),
-                        );
-                        break;
-                    }
-                }
+            if ban
+                .ban
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= ban.ban.banned_at)
+            {
+                invalid_bans.insert(ban.id(), RoomStateError::InvalidBanExpiry { ban: ban.id() });
+                continue;
+            }
 
-                if !is_valid {
-                    invalid_bans.insert(
-                        ban.id(),
-                        "Banner is not in the invite chain of the banned member".to_string(),
-                    );
-                }
+            // The banner must be the owner or have been in the room. The banned member needn't be
+            // in the member list, the ban stays once it has removed them, but unless the banner is
+            // the owner or a moderator they must be upline of a membership of theirs.
+            if ban.banned_by != parameters.owner_id()
+                && index.known_member_vk(ban.banned_by).is_none()
+            {
+                invalid_bans.insert(
+                    ban.id(),
+                    RoomStateError::UnknownAuthor {
+                        field: StateField::Ban,
+                        author: ban.banned_by,
+                    },
+                );
+                continue;
+            }
+            if !index.may_ban(ban.banned_by, ban.ban.banned_user, configuration) {
+                invalid_bans.insert(
+                    ban.id(),
+                    RoomStateError::Unauthorized {
+                        field: StateField::Ban,
+                        id: ban.id().0,
+                    },
+                );
             }
         }
 
-        let extra_bans =
-            self.0.len() as isize - parent_state.configuration.configuration.max_user_bans as isize;
+        let max_user_bans = parent_state.configuration.configuration.max_user_bans;
+        let extra_bans = self.0.len() as isize - max_user_bans as isize;
         if extra_bans > 0 {
             // Add oldest extra bans to invalid bans
             let mut extra_bans_vec = self.0.clone();
             extra_bans_vec.sort_by_key(|ban| ban.ban.banned_at);
             extra_bans_vec.reverse();
             for ban in extra_bans_vec.iter().take(extra_bans as usize) {
-                invalid_bans.insert(ban.id(), "Exceeded maximum number of user bans".to_string());
+                invalid_bans.insert(
+                    ban.id(),
+                    RoomStateError::LimitExceeded {
+                        field: StateField::Ban,
+                        count: self.0.len(),
+                        max: max_user_bans,
+                    },
+                );
             }
         }
 
@@ -116,46 +150,50 @@ impl Default for BansV1 {
 
 impl ComposableState for BansV1 {
     type ParentState = ChatRoomStateV1;
-    type Summary = Vec<BanId>;
+    type Summary = IdSet<BanId>;
     type Delta = Vec<AuthorizedUserBan>;
     type Parameters = ChatRoomParametersV1;
+    type Error = RoomStateError;
 
     fn verify(
         &self,
         parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
-    ) -> Result<(), String> {
-        let invalid_bans = self.get_invalid_bans(parent_state, parameters);
-        if !invalid_bans.is_empty() {
-            return Err(format!("Invalid bans: {:?}", invalid_bans));
+    ) -> Result<(), Self::Error> {
+        // Report the first invalid ban so that the error doesn't depend on the map's order
+        let mut invalid_bans = self.get_invalid_bans(parent_state, parameters);
+        if let Some(error) = self.0.iter().find_map(|ban| invalid_bans.remove(&ban.id())) {
+            return Err(error);
         }
 
         // Check if the number of bans exceeds the maximum allowed
-        if self.0.len() > parent_state.configuration.configuration.max_user_bans as usize {
-            return Err(format!(
-                "Number of bans ({}) exceeds the maximum allowed ({})",
-                self.0.len(),
-                parent_state.configuration.configuration.max_user_bans
-            ));
+        if self.0.len() > parent_state.configuration.configuration.max_user_bans {
+            return Err(RoomStateError::LimitExceeded {
+                field: StateField::Ban,
+                count: self.0.len(),
+                max: parent_state.configuration.configuration.max_user_bans,
+            });
         }
 
-        let members_by_id = parent_state.members.members_by_member_id();
-
         let owner_vk = parameters.owner;
         let owner_id = parameters.owner_id();
+        let index = parent_state.members.index(parameters);
 
         // Verify signatures for all bans
         for ban in &self.0 {
             if ban.banned_by == owner_id {
-                ban.verify_signature(&owner_vk)
-                    .map_err(|e| format!("Invalid ban signature: {}", e))?;
+                ban.verify_signature(&owner_vk)?;
             } else {
-                let banning_member = members_by_id
-                    .get(&ban.banned_by)
-                    .ok_or_else(|| "Banning member not found".to_string())?;
-                ban.verify_signature(&banning_member.member.member_vk)
-                    .map_err(|e| format!("Invalid ban signature: {}", e))?;
+                let banner_vk =
+                    index
+                        .known_member_vk(ban.banned_by)
+                        .ok_or(RoomStateError::UnknownAuthor {
+                            field: StateField::Ban,
+                            author: ban.banned_by,
+                        })?;
+                ban.verify_signature(banner_vk)?;
             }
+            ban.verify_unban(&index, parameters)?;
         }
 
         Ok(())
@@ -166,7 +204,12 @@ impl ComposableState for BansV1 {
         _parent_state: &Self::ParentState,
         _parameters: &Self::Parameters,
     ) -> Self::Summary {
-        self.0.iter().map(|ban| ban.id()).collect()
+        // Lifted bans are listed along with their unban, so that peers which only have the ban get
+        // the unban
+        self.0
+            .iter()
+            .flat_map(|ban| std::iter::once(ban.id()).chain(ban.unban.as_ref().map(|u| u.id())))
+            .collect()
     }
 
     fn delta(
@@ -179,7 +222,13 @@ impl ComposableState for BansV1 {
         let delta = self
             .0
             .iter()
-            .filter(|ban| !old_state_summary.contains(&ban.id()))
+            .filter(|ban| {
+                !old_state_summary.contains(&ban.id())
+                    || ban
+                        .unban
+                        .as_ref()
+                        .is_some_and(|unban| !old_state_summary.contains(&unban.id()))
+            })
             .cloned()
             .collect::<Vec<_>>();
         if delta.is_empty() {
@@ -194,99 +243,127 @@ impl ComposableState for BansV1 {
         parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
         delta: &Option<Self::Delta>,
-    ) -> Result<(), String> {
+    ) -> Result<(), Self::Error> {
+        let mut pending = HashSet::new();
         if let Some(delta) = delta {
-            // Check for duplicate bans
-            let existing_ban_ids: std::collections::HashSet<_> =
-                self.0.iter().map(|ban| ban.id()).collect();
+            // Create a temporary BansV1 with the new bans, a ban we already have can only come
+            // again to be lifted
+            let mut temp_bans = self.clone();
             for new_ban in delta {
-                if existing_ban_ids.contains(&new_ban.id()) {
-                    return Err(format!("Duplicate ban detected: {:?}", new_ban.id()));
+                match temp_bans.0.iter_mut().find(|ban| ban.id() == new_ban.id()) {
+                    Some(existing)
+                        if new_ban.unban.is_some() && existing.unban != new_ban.unban =>
+                    {
+                        // Lifted on more than one peer, keep the earliest unban
+                        let earliest = existing
+                            .unban
+                            .iter()
+                            .chain(&new_ban.unban)
+                            .min_by_key(|unban| unban.order_key())
+                            .cloned();
+                        existing.unban = earliest;
+                    }
+                    Some(_) => {
+                        return Err(RoomStateError::Duplicate {
+                            field: StateField::Ban,
+                            id: new_ban.id().0,
+                        })
+                    }
+                    None => temp_bans.0.push(new_ban.clone()),
                 }
             }
 
-            // Create a temporary BansV1 with the new bans
-            let mut temp_bans = self.clone();
-            temp_bans.0.extend(delta.iter().cloned());
-
-            // Verify the temporary room_state
-            if let Err(e) = temp_bans.verify(parent_state, parameters) {
-                return Err(format!("Invalid delta: {}", e));
+            // Bans are applied before members, so a ban can come with its banner, or the membership
+            // that makes them upline of the banned member, in the same delta. Those can't be
+            // checked yet and are kept until the state settles, without taking up room.
+            let index = parent_state.members.index(parameters);
+            pending = delta
+                .iter()
+                .filter(|ban| {
+                    ban.banned_by != parameters.owner_id()
+                        && !ban.banned_by.0.is_legacy()
+                        && (index.known_member_vk(ban.banned_by).is_none()
+                            || !index.may_ban(
+                                ban.banned_by,
+                                ban.ban.banned_user,
+                                &parent_state.configuration.configuration,
+                            ))
+                })
+                .map(|ban| ban.id())
+                .collect();
+            // Bans we already have were checked when they came, the configuration this delta
+            // brings may have taken their banner's rights away, they're dropped below instead
+            let incoming: HashSet<BanId> = delta.iter().map(|ban| ban.id()).collect();
+            let checkable = BansV1(
+                temp_bans
+                    .0
+                    .iter()
+                    .filter(|ban| incoming.contains(&ban.id()) && !pending.contains(&ban.id()))
+                    .cloned()
+                    .collect(),
+            );
+            // Bans that have already expired don't take up room
+            temp_bans.remove_expired(parent_state, parameters);
+            let count = temp_bans
+                .0
+                .iter()
+                .filter(|ban| !pending.contains(&ban.id()))
+                .count();
+            if count > parent_state.configuration.configuration.max_user_bans {
+                return Err(RoomStateError::LimitExceeded {
+                    field: StateField::Ban,
+                    count,
+                    max: parent_state.configuration.configuration.max_user_bans,
+                });
             }
+            checkable.verify(parent_state, parameters)?;
 
             // If verification passes, update the actual room_state
             self.0 = temp_bans.0;
         }
-        Ok(())
-    }
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
-pub struct AuthorizedUserBan {
-    pub ban: UserBan,
-    pub banned_by: MemberId,
-    pub signature: Signature,
-}
-
-impl Eq for AuthorizedUserBan {}
-
-impl Hash for AuthorizedUserBan {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.signature.to_bytes().hash(state);
-    }
-}
-
-impl AuthorizedUserBan {
-    pub fn new(ban: UserBan, banned_by: MemberId, banner_signing_key: &SigningKey) -> Self {
-        assert_eq!(
-            MemberId::from(banner_signing_key.verifying_key()),
-            banned_by
-        );
 
-        let signature = sign_struct(&ban, banner_signing_key);
-
-        Self {
-            ban,
-            banned_by,
-            signature,
+        // Drop bans whose banner's membership can't be found or who may no longer ban the member.
+        // Bans stand after the banner leaves or is removed, they may be back and would otherwise
+        // bring back whoever they banned.
+        let index = parent_state.members.index(parameters);
+        let configuration = &parent_state.configuration.configuration;
+        self.0.retain(|ban| {
+            ban.is_authorized(&index, configuration, parameters) || pending.contains(&ban.id())
+        });
+        self.remove_expired(parent_state, parameters);
+        self.record_secret_versions(parent_state);
+
+        // Peers may have received the bans in a different order
+        self.0.sort_by_key(|ban| (ban.ban.banned_at, ban.id()));
+        // Bans that were pending when they came can take the room over the limit once they're
+        // checked, the latest go
+        if pending.is_empty() {
+            self.0.truncate(configuration.max_user_bans);
         }
+        Ok(())
     }
-
-    pub fn verify_signature(&self, banner_verifying_key: &VerifyingKey) -> Result<(), String> {
-        verify_struct(&self.ban, &self.signature, banner_verifying_key)
-            .map_err(|e| format!("Invalid ban signature: {}", e))
-    }
-
-    pub fn id(&self) -> BanId {
-        BanId(fast_hash(&self.signature.to_bytes()))
-    }
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
-pub struct UserBan {
-    pub owner_member_id: MemberId,
-    pub banned_at: SystemTime,
-    pub banned_user: MemberId,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Hash, Debug)]
-pub struct BanId(pub FastHash);
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use ed25519_dalek::SigningKey;
     use std::time::Duration;
-    use crate::room_state::configuration::AuthorizedConfigurationV1;
-    use crate::room_state::member::{AuthorizedMember, Member, MembersV1};
+    use crate::room_state::configuration::{
+        AuthorizedConfigurationV1, Configuration, Moderator, ModeratorPermissions,
+    };
+    use crate::room_state::member::{AuthorizedMember, Member, MemberId, MembersDelta, MembersV1};
+    use crate::room_state::ChatRoomStateV1Delta;
 
-    fn create_test_chat_room_state() -> ChatRoomStateV1 {
+    pub(super) fn create_test_chat_room_state() -> ChatRoomStateV1 {
         // Create a minimal ChatRoomStateV1 for testing
         ChatRoomStateV1 {
             configuration: AuthorizedConfigurationV1::default(),
             members: MembersV1::default(),
+            secrets: Default::default(),
             member_info: Default::default(),
             recent_messages: Default::default(),
+            reactions: Default::default(),
             upgrade: Default::default(),
             bans: Default::default(),
         }
@@ -300,6 +377,22 @@ mod tests {
         }
     }
 
+    pub(super) fn owner_ban(
+        owner_key: &SigningKey,
+        banned_user: MemberId,
+        banned_at: SystemTime,
+        expires_at: Option<SystemTime>,
+    ) -> AuthorizedUserBan {
+        let owner_id: MemberId = owner_key.verifying_key().into();
+        let ban = UserBan {
+            owner_member_id: owner_id,
+            banned_at,
+            banned_user,
+            expires_at,
+        };
+        AuthorizedUserBan::new(ban, owner_id, owner_key)
+    }
+
     #[test]
     fn test_bans_verify() {
         let mut state = create_test_chat_room_state();
@@ -348,6 +441,7 @@ mod tests {
                 owner_member_id: owner_id.clone(),
                 banned_at: SystemTime::now(),
                 banned_user: member1_id.clone(),
+                expires_at: None,
             },
             owner_id.clone(),
             &owner_key,
@@ -368,6 +462,7 @@ mod tests {
                     owner_member_id: owner_id.clone(),
                     banned_at: SystemTime::now(),
                     banned_user: member1_id.clone(),
+                    expires_at: None,
                 },
                 owner_id.clone(),
                 &owner_key,
@@ -387,6 +482,7 @@ mod tests {
                 owner_member_id: owner_id.clone(),
                 banned_at: SystemTime::now(),
                 banned_user: member2_id.clone(),
+                expires_at: None,
             },
             invalid_id,
             &invalid_key,
@@ -404,6 +500,7 @@ mod tests {
                 owner_member_id: owner_id.clone(),
                 banned_at: SystemTime::now(),
                 banned_user: member2_id.clone(),
+                expires_at: None,
             },
             member1_id.clone(),
             &member1_key,
@@ -429,6 +526,7 @@ mod tests {
                 owner_member_id: id.clone(),
                 banned_at: SystemTime::now(),
                 banned_user: id.clone(),
+                expires_at: None,
             },
             id.clone(),
             &key,
@@ -439,6 +537,7 @@ mod tests {
                 owner_member_id: id.clone(),
                 banned_at: SystemTime::now() + Duration::from_secs(1),
                 banned_user: id.clone(),
+                expires_at: None,
             },
             id.clone(),
             &key,
@@ -447,7 +546,7 @@ mod tests {
         let bans = BansV1(vec![ban1.clone(), ban2.clone()]);
         let summary = bans.summarize(&state, &params);
 
-        assert_eq!(summary.len(), 2);
+        assert_eq!(summary.listed().map(|ids| ids.len()), Some(2));
         assert!(summary.contains(&ban1.id()));
         assert!(summary.contains(&ban2.id()));
     }
@@ -465,6 +564,7 @@ mod tests {
                 owner_member_id: id.clone(),
                 banned_at: SystemTime::now(),
                 banned_user: id.clone(),
+                expires_at: None,
             },
             id.clone(),
             &key,
@@ -475,6 +575,7 @@ mod tests {
                 owner_member_id: id.clone(),
                 banned_at: SystemTime::now() + Duration::from_secs(1),
                 banned_user: id.clone(),
+                expires_at: None,
             },
             id.clone(),
             &key,
@@ -483,17 +584,17 @@ mod tests {
         let bans = BansV1(vec![ban1.clone(), ban2.clone()]);
 
         // Test 1: Empty old summary
-        let empty_summary = Vec::new();
+        let empty_summary = IdSet::default();
         let delta = bans.delta(&state, &params, &empty_summary);
         assert_eq!(delta, Some(vec![ban1.clone(), ban2.clone()]));
 
         // Test 2: Partial old summary
-        let partial_summary = vec![ban1.id()];
+        let partial_summary = [ban1.id()].into_iter().collect();
         let delta = bans.delta(&state, &params, &partial_summary);
         assert_eq!(delta, Some(vec![ban2.clone()]));
 
         // Test 3: Full old summary
-        let full_summary = vec![ban1.id(), ban2.id()];
+        let full_summary = [ban1.id(), ban2.id()].into_iter().collect();
         let delta = bans.delta(&state, &params, &full_summary);
         assert_eq!(delta, None);
     }
@@ -536,6 +637,7 @@ mod tests {
                 owner_member_id: owner_id.clone(),
                 banned_at: SystemTime::now(),
                 banned_user: member_id.clone(),
+                expires_at: None,
             },
             owner_id.clone(),
             &owner_key,
@@ -563,6 +665,7 @@ mod tests {
                     owner_member_id: owner_id.clone(),
                     banned_at: SystemTime::now(),
                     banned_user: member_id.clone(),
+                    expires_at: None,
                 },
                 owner_id.clone(),
                 &owner_key,
@@ -602,6 +705,7 @@ mod tests {
                     owner_member_id: owner_id.clone(),
                     banned_at: SystemTime::now(),
                     banned_user: member_id.clone(),
+                    expires_at: None,
                 },
                 owner_id.clone(),
                 &owner_key,
@@ -620,46 +724,238 @@ mod tests {
     }
 
     #[test]
-    fn test_authorized_user_ban() {
+    fn test_ban_outlives_the_membership_it_ended() {
         let owner_key = SigningKey::generate(&mut rand::thread_rng());
-        let owner_id : MemberId = owner_key.verifying_key().into();
-        let member_key = SigningKey::generate(&mut rand::thread_rng());
-        let member_id : MemberId = member_key.verifying_key().into();
-
-        let ban = UserBan {
-            owner_member_id: owner_id.clone(),
-            banned_at: SystemTime::now(),
-            banned_user: member_id.clone(),
+        let owner_id: MemberId = owner_key.verifying_key().into();
+        let params = ChatRoomParametersV1 {
+            owner: owner_key.verifying_key(),
         };
+        let configuration = Configuration {
+            owner_member_id: owner_id,
+            max_user_bans: 5,
+            ..Configuration::default()
+        };
+        let empty = ChatRoomStateV1 {
+            configuration: AuthorizedConfigurationV1::new(configuration, &owner_key),
+            ..ChatRoomStateV1::default()
+        };
+        let inviter_key = SigningKey::generate(&mut rand::thread_rng());
+        let inviter = AuthorizedMember::new(
+            Member {
+                owner_member_id: owner_id,
+                invited_by: owner_id,
+                member_vk: inviter_key.verifying_key(),
+            },
+            &owner_key,
+        );
+        let invitee = AuthorizedMember::new(
+            Member {
+                owner_member_id: owner_id,
+                invited_by: inviter.member.id(),
+                member_vk: SigningKey::generate(&mut rand::thread_rng()).verifying_key(),
+            },
+            &inviter_key,
+        );
+        let mut state = empty.clone();
+        let delta = ChatRoomStateV1Delta {
+            members: Some(MembersDelta::new(vec![inviter.clone(), invitee.clone()])),
+            ..Default::default()
+        };
+        state
+            .apply_delta(&state.clone(), &params, &Some(delta))
+            .unwrap();
 
-        let authorized_ban = AuthorizedUserBan::new(ban.clone(), owner_id.clone(), &owner_key);
-
-        // Test 1: Verify signature
-        assert!(authorized_ban
-            .verify_signature(&owner_key.verifying_key())
-            .is_ok());
-
-        // Test 2: Verify signature with wrong key
-        let wrong_key = SigningKey::generate(&mut rand::thread_rng());
-        assert!(authorized_ban
-            .verify_signature(&wrong_key.verifying_key())
-            .is_err());
-
-        // Test 3: Check ban ID
-        let id1 = authorized_ban.id();
-        let id2 = authorized_ban.id();
-        assert_eq!(id1, id2);
-
-        // Test 4: Different bans should have different IDs
-        let another_ban = AuthorizedUserBan::new(
+        // The inviter bans the member they invited, who is then no longer in the member list
+        let ban = AuthorizedUserBan::new(
             UserBan {
-                owner_member_id: owner_id.clone(),
-                banned_at: SystemTime::now() + Duration::from_secs(1),
-                banned_user: member_id.clone(),
+                owner_member_id: owner_id,
+                banned_at: SystemTime::now(),
+                banned_user: invitee.member.id(),
+                expires_at: None,
             },
-            owner_id.clone(),
-            &owner_key,
+            inviter.member.id(),
+            &inviter_key,
+        );
+        let delta = ChatRoomStateV1Delta {
+            bans: Some(vec![ban.clone()]),
+            ..Default::default()
+        };
+        state
+            .apply_delta(&state.clone(), &params, &Some(delta))
+            .unwrap();
+        assert_eq!(state.members.members, vec![inviter]);
+        assert_eq!(state.bans.0, vec![ban.clone()]);
+        assert_eq!(state.verify(&state, &params), Ok(()));
+
+        // A peer that never saw the banned member takes the ban along with the rest of the room
+        let mut peer = empty.clone();
+        let delta = state.delta(&state, &params, &empty.summarize(&empty, &params));
+        peer.apply_delta(&peer.clone(), &params, &delta).unwrap();
+        assert_eq!(peer, state);
+    }
+
+    #[test]
+    fn test_unauthorized_bans_dont_take_up_room() {
+        let owner_key = SigningKey::generate(&mut rand::thread_rng());
+        let owner_id: MemberId = owner_key.verifying_key().into();
+        let params = ChatRoomParametersV1 {
+            owner: owner_key.verifying_key(),
+        };
+        let configuration = Configuration {
+            owner_member_id: owner_id,
+            max_user_bans: 10,
+            ..Configuration::default()
+        };
+        let mut state = ChatRoomStateV1 {
+            configuration: AuthorizedConfigurationV1::new(configuration, &owner_key),
+            ..ChatRoomStateV1::default()
+        };
+        let join = |member_key: &SigningKey| {
+            AuthorizedMember::new(
+                Member {
+                    owner_member_id: owner_id,
+                    invited_by: owner_id,
+                    member_vk: member_key.verifying_key(),
+                },
+                &owner_key,
+            )
+        };
+        let member_key = SigningKey::generate(&mut rand::thread_rng());
+        let other = join(&SigningKey::generate(&mut rand::thread_rng()));
+        let delta = ChatRoomStateV1Delta {
+            members: Some(MembersDelta::new(vec![join(&member_key), other.clone()])),
+            ..Default::default()
+        };
+        state
+            .apply_delta(&state.clone(), &params, &Some(delta))
+            .unwrap();
+
+        // A member bans ids that never joined, and someone they didn't invite
+        let member_ban = |banned_user| {
+            let ban = UserBan {
+                owner_member_id: owner_id,
+                banned_at: SystemTime::now(),
+                banned_user,
+                expires_at: None,
+            };
+            AuthorizedUserBan::new(ban, member_key.verifying_key().into(), &member_key)
+        };
+        let junk: Vec<AuthorizedUserBan> = (0..10u8)
+            .map(|n| member_ban(SigningKey::from_bytes(&[n; 32]).verifying_key().into()))
+            .chain([member_ban(other.member.id())])
+            .collect();
+        assert_eq!(
+            BansV1(junk[..1].to_vec()).verify(&state, &params),
+            Err(RoomStateError::Unauthorized {
+                field: StateField::Ban,
+                id: junk[0].id().0,
+            })
         );
-        assert_ne!(authorized_ban.id(), another_ban.id());
+        let delta = ChatRoomStateV1Delta {
+            bans: Some(junk),
+            ..Default::default()
+        };
+        state
+            .apply_delta(&state.clone(), &params, &Some(delta))
+            .unwrap();
+        assert!(state.bans.0.is_empty());
+        assert!(state.members.members.contains(&other));
+
+        // so the owner can still ban
+        let ban = owner_ban(&owner_key, other.member.id(), SystemTime::now(), None);
+        let delta = ChatRoomStateV1Delta {
+            bans: Some(vec![ban.clone()]),
+            ..Default::default()
+        };
+        state
+            .apply_delta(&state.clone(), &params, &Some(delta))
+            .unwrap();
+        assert_eq!(state.bans.0, vec![ban]);
+        assert_eq!(state.verify(&state, &params), Ok(()));
+    }
+
+    #[test]
+    fn test_configuration_dropping_a_moderator_drops_their_bans() {
+        let owner_key = SigningKey::generate(&mut rand::thread_rng());
+        let owner_id: MemberId = owner_key.verifying_key().into();
+        let params = ChatRoomParametersV1 {
+            owner: owner_key.verifying_key(),
+        };
+        let moderator_key = SigningKey::generate(&mut rand::thread_rng());
+        let mut configuration = Configuration {
+            owner_member_id: owner_id,
+            max_user_bans: 10,
+            moderators: vec![Moderator {
+                member_id: moderator_key.verifying_key().into(),
+                permissions: ModeratorPermissions {
+                    ban_anyone: true,
+                    ..Default::default()
+                },
+            }],
+            ..Configuration::default()
+        };
+        let mut state = ChatRoomStateV1 {
+            configuration: AuthorizedConfigurationV1::new(configuration.clone(), &owner_key),
+            ..ChatRoomStateV1::default()
+        };
+        let join = |member_key: &SigningKey| {
+            AuthorizedMember::new(
+                Member {
+                    owner_member_id: owner_id,
+                    invited_by: owner_id,
+                    member_vk: member_key.verifying_key(),
+                },
+                &owner_key,
+            )
+        };
+        let members: Vec<AuthorizedMember> = [&moderator_key]
+            .into_iter()
+            .chain(&[
+                SigningKey::generate(&mut rand::thread_rng()),
+                SigningKey::generate(&mut rand::thread_rng()),
+            ])
+            .map(join)
+            .collect();
+        let delta = ChatRoomStateV1Delta {
+            members: Some(MembersDelta::new(members.clone())),
+            ..Default::default()
+        };
+        state
+            .apply_delta(&state.clone(), &params, &Some(delta))
+            .unwrap();
+
+        // The moderator bans a member they didn't invite
+        let ban = UserBan {
+            owner_member_id: owner_id,
+            banned_at: SystemTime::now(),
+            banned_user: members[1].member.id(),
+            expires_at: None,
+        };
+        let moderator_ban =
+            AuthorizedUserBan::new(ban, moderator_key.verifying_key().into(), &moderator_key);
+        let delta = ChatRoomStateV1Delta {
+            bans: Some(vec![moderator_ban]),
+            ..Default::default()
+        };
+        state
+            .apply_delta(&state.clone(), &params, &Some(delta))
+            .unwrap();
+        assert_eq!(state.bans.0.len(), 1);
+
+        // A configuration without them comes along with another ban, their ban is dropped rather
+        // than failing the delta
+        configuration.configuration_version += 1;
+        configuration.moderators.clear();
+        let ban = owner_ban(&owner_key, members[2].member.id(), SystemTime::now(), None);
+        let delta = ChatRoomStateV1Delta {
+            configuration: Some(AuthorizedConfigurationV1::new(configuration, &owner_key)),
+            bans: Some(vec![ban.clone()]),
+            ..Default::default()
+        };
+        state
+            .apply_delta(&state.clone(), &params, &Some(delta))
+            .unwrap();
+        assert_eq!(state.bans.0, vec![ban]);
+        assert_eq!(state.verify(&state, &params), Ok(()));
     }
 }
@@ -0,0 +1,186 @@
+use crate::room_state::ban::BansV1;
+use crate::room_state::ChatRoomParametersV1;
+use crate::ChatRoomStateV1;
+use std::time::SystemTime;
+
+/*
+ A ban can expire, but the contract has no clock, so it expires once the room has moved past
+ `expires_at`: when a ban the owner or a moderator who may ban anyone made after that is in the
+ state. The latest such ban never expires by then, so this time only moves forward and every peer
+ prunes the same bans whatever order it saw them in. Other fields can't be used for it, they're
+ applied after bans. Bans by other members don't count, or any of them could date one in the
+ future to end every ban early.
+*/
+
+impl BansV1 {
+    /// The latest time the room vouches for, see above
+    pub(crate) fn room_time(
+        &self,
+        parent_state: &ChatRoomStateV1,
+        parameters: &ChatRoomParametersV1,
+    ) -> Option<SystemTime> {
+        let configuration = &parent_state.configuration.configuration;
+        let index = parent_state.members.index(parameters);
+        self.0
+            .iter()
+            .filter(|ban| {
+                ban.banned_by == parameters.owner_id()
+                    || configuration
+                        .moderator_permissions(ban.banned_by)
+                        .ban_anyone
+            })
+            .filter(|ban| ban.is_authorized(&index, configuration, parameters))
+            .map(|ban| ban.ban.banned_at)
+            .max()
+    }
+
+    pub(super) fn remove_expired(
+        &mut self,
+        parent_state: &ChatRoomStateV1,
+        parameters: &ChatRoomParametersV1,
+    ) {
+        if let Some(now) = self.room_time(parent_state, parameters) {
+            self.0
+                .retain(|ban| ban.ban.expires_at.is_none_or(|expires_at| expires_at > now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::ban::tests::{create_test_chat_room_state, owner_ban};
+    use crate::room_state::ban::{AuthorizedUserBan, UserBan};
+    use crate::room_state::configuration::{Moderator, ModeratorPermissions};
+    use crate::room_state::error::RoomStateError;
+    use crate::room_state::member::{AuthorizedMember, Member, MemberId};
+    use ed25519_dalek::SigningKey;
+    use freenet_scaffold::ComposableState;
+    use std::time::Duration;
+
+    #[test]
+    fn test_expired_bans_are_pruned() {
+        let owner_key = SigningKey::generate(&mut rand::thread_rng());
+        let params = ChatRoomParametersV1 {
+            owner: owner_key.verifying_key(),
+        };
+        let mut state = create_test_chat_room_state();
+        state.configuration.configuration.max_user_bans = 5;
+        let user = |n: u8| MemberId::from(SigningKey::from_bytes(&[n; 32]).verifying_key());
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+
+        let expiring = owner_ban(&owner_key, user(1), at(100), Some(at(160)));
+        let permanent = owner_ban(&owner_key, user(2), at(100), None);
+        let mut bans = BansV1::default();
+        bans.apply_delta(&state, &params, &Some(vec![expiring.clone(), permanent]))
+            .unwrap();
+
+        // Nothing in the room is dated after the expiry yet
+        let before_expiry = owner_ban(&owner_key, user(3), at(130), None);
+        bans.apply_delta(&state, &params, &Some(vec![before_expiry]))
+            .unwrap();
+        assert!(bans.0.contains(&expiring));
+
+        // A later ban moves the room past it
+        let after_expiry = owner_ban(&owner_key, user(4), at(160), None);
+        bans.apply_delta(&state, &params, &Some(vec![after_expiry]))
+            .unwrap();
+        assert!(!bans.0.contains(&expiring));
+        assert_eq!(bans.0.len(), 3);
+
+        // Peers that still have the expired ban can't bring it back
+        bans.apply_delta(&state, &params, &Some(vec![expiring]))
+            .unwrap();
+        assert_eq!(bans.0.len(), 3);
+
+        // A ban can't expire before it's made
+        let invalid = owner_ban(&owner_key, user(5), at(200), Some(at(200)));
+        assert_eq!(
+            bans.apply_delta(&state, &params, &Some(vec![invalid.clone()])),
+            Err(RoomStateError::InvalidBanExpiry { ban: invalid.id() })
+        );
+    }
+
+    #[test]
+    fn test_future_dated_member_ban_doesnt_expire_bans() {
+        let owner_key = SigningKey::generate(&mut rand::thread_rng());
+        let owner_id: MemberId = owner_key.verifying_key().into();
+        let params = ChatRoomParametersV1 {
+            owner: owner_key.verifying_key(),
+        };
+        let mut state = create_test_chat_room_state();
+        state.configuration.configuration.max_user_bans = 5;
+        let user = |n: u8| MemberId::from(SigningKey::from_bytes(&[n; 32]).verifying_key());
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let join = |member_key: &SigningKey| {
+            AuthorizedMember::new(
+                Member {
+                    owner_member_id: owner_id,
+                    invited_by: owner_id,
+                    member_vk: member_key.verifying_key(),
+                },
+                &owner_key,
+            )
+        };
+        let member_key = SigningKey::generate(&mut rand::thread_rng());
+        let moderator_key = SigningKey::generate(&mut rand::thread_rng());
+        // Members can only ban those they invited
+        let invite = |inviter_key: &SigningKey, n: u8| {
+            AuthorizedMember::new(
+                Member {
+                    owner_member_id: owner_id,
+                    invited_by: inviter_key.verifying_key().into(),
+                    member_vk: SigningKey::from_bytes(&[n; 32]).verifying_key(),
+                },
+                inviter_key,
+            )
+        };
+        state.members.members = vec![
+            join(&member_key),
+            join(&moderator_key),
+            invite(&member_key, 2),
+            invite(&moderator_key, 3),
+        ];
+        let member_ban = |banner_key: &SigningKey, banned_user, banned_at| {
+            let ban = UserBan {
+                owner_member_id: owner_id,
+                banned_at,
+                banned_user,
+                expires_at: None,
+            };
+            AuthorizedUserBan::new(ban, banner_key.verifying_key().into(), banner_key)
+        };
+
+        let expiring = owner_ban(&owner_key, user(1), at(100), Some(at(160)));
+        let mut bans = BansV1::default();
+        bans.apply_delta(&state, &params, &Some(vec![expiring.clone()]))
+            .unwrap();
+
+        // A member dating a ban far in the future doesn't move the room past it
+        let future = member_ban(&member_key, user(2), at(1_000_000));
+        bans.apply_delta(&state, &params, &Some(vec![future]))
+            .unwrap();
+        assert!(bans.0.contains(&expiring));
+
+        // Neither does a moderator who can only ban their own invitees
+        state.configuration.configuration.moderators = vec![Moderator {
+            member_id: moderator_key.verifying_key().into(),
+            permissions: ModeratorPermissions {
+                delete_messages: true,
+                ..Default::default()
+            },
+        }];
+        let later = member_ban(&moderator_key, user(3), at(200));
+        bans.apply_delta(&state, &params, &Some(vec![later.clone()]))
+            .unwrap();
+        assert!(bans.0.contains(&expiring));
+
+        // but one who may ban anyone does
+        state.configuration.configuration.moderators[0]
+            .permissions
+            .ban_anyone = true;
+        bans.apply_delta(&state, &params, &None).unwrap();
+        assert!(!bans.0.contains(&expiring));
+        assert_eq!(bans.0.len(), 2);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::room_state::ban::BanId;
+use crate::room_state::error::{RoomStateError, StateField};
+use crate::room_state::member::MemberId;
+use crate::util::{sign_struct, verify_struct};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use freenet_scaffold::util::blake3_hash;
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+/*
+ An unban lifts a ban, it can be made by whoever made the ban or by the owner. It's kept along with
+ the ban it lifts rather than replacing it, otherwise peers which haven't seen the unban would bring
+ the ban back.
+*/
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Unban {
+    pub owner_member_id: MemberId,
+    pub ban_id: BanId,
+    pub unbanned_at: SystemTime,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AuthorizedUnban {
+    pub unban: Unban,
+    pub unbanned_by: MemberId,
+    pub signature: Signature,
+}
+
+const UNBAN_ID_CONTEXT: &str = "river 2025-01 unban id";
+
+impl AuthorizedUnban {
+    pub fn new(unban: Unban, unbanned_by: MemberId, unbanner_signing_key: &SigningKey) -> Self {
+        assert_eq!(
+            MemberId::from(unbanner_signing_key.verifying_key()),
+            unbanned_by
+        );
+
+        Self {
+            signature: sign_struct(&unban, unbanner_signing_key),
+            unban,
+            unbanned_by,
+        }
+    }
+
+    /// Unbans are summarized along with the bans they lift, so their ids share the same type
+    pub fn id(&self) -> BanId {
+        BanId(blake3_hash(UNBAN_ID_CONTEXT, &self.signature.to_bytes()))
+    }
+
+    pub fn verify_signature(
+        &self,
+        unbanner_verifying_key: &VerifyingKey,
+    ) -> Result<(), RoomStateError> {
+        verify_struct(&self.unban, &self.signature, unbanner_verifying_key).map_err(|_| {
+            RoomStateError::InvalidSignature {
+                field: StateField::Unban,
+                id: self.id().0,
+            }
+        })
+    }
+
+    /// Orders unbans of the same ban so that peers keep the same one
+    pub fn order_key(&self) -> (SystemTime, BanId) {
+        (self.unban.unbanned_at, self.id())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::ban::tests::owner_ban;
+    use crate::room_state::configuration::{AuthorizedConfigurationV1, Configuration};
+    use crate::room_state::member::{AuthorizedMember, Member, MembersDelta};
+    use crate::room_state::{ChatRoomParametersV1, ChatRoomStateV1Delta};
+    use crate::ChatRoomStateV1;
+    use freenet_scaffold::ComposableState;
+
+    #[test]
+    fn test_unban_lets_member_back() {
+        let owner_key = SigningKey::generate(&mut rand::thread_rng());
+        let owner_id: MemberId = owner_key.verifying_key().into();
+        let params = ChatRoomParametersV1 {
+            owner: owner_key.verifying_key(),
+        };
+        let configuration = Configuration {
+            owner_member_id: owner_id,
+            max_user_bans: 5,
+            ..Configuration::default()
+        };
+        let mut state = ChatRoomStateV1 {
+            configuration: AuthorizedConfigurationV1::new(configuration, &owner_key),
+            ..ChatRoomStateV1::default()
+        };
+        let member_key = SigningKey::generate(&mut rand::thread_rng());
+        let member = AuthorizedMember::new(
+            Member {
+                owner_member_id: owner_id,
+                invited_by: owner_id,
+                member_vk: member_key.verifying_key(),
+            },
+            &owner_key,
+        );
+        let delta = ChatRoomStateV1Delta {
+            members: Some(MembersDelta::new(vec![member.clone()])),
+            ..Default::default()
+        };
+        state
+            .apply_delta(&state.clone(), &params, &Some(delta))
+            .unwrap();
+
+        let ban = owner_ban(&owner_key, member.member.id(), SystemTime::now(), None);
+        let delta = ChatRoomStateV1Delta {
+            bans: Some(vec![ban.clone()]),
+            ..Default::default()
+        };
+        state
+            .apply_delta(&state.clone(), &params, &Some(delta))
+            .unwrap();
+        assert!(state.members.members.is_empty());
+        let banned_summary = state.summarize(&state, &params);
+
+        // Only the banner or the owner can lift the ban
+        let unban = Unban {
+            owner_member_id: owner_id,
+            ban_id: ban.id(),
+            unbanned_at: SystemTime::now(),
+        };
+        let forged = ban.clone().lifted(AuthorizedUnban::new(
+            unban.clone(),
+            member.member.id(),
+            &member_key,
+        ));
+        let delta = ChatRoomStateV1Delta {
+            bans: Some(vec![forged]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            state.apply_delta(&state.clone(), &params, &Some(delta)),
+            Err(RoomStateError::Unauthorized {
+                field: StateField::Unban,
+                ..
+            })
+        ));
+
+        let lifted = ban.lifted(AuthorizedUnban::new(unban, owner_id, &owner_key));
+        let delta = ChatRoomStateV1Delta {
+            bans: Some(vec![lifted.clone()]),
+            ..Default::default()
+        };
+        state
+            .apply_delta(&state.clone(), &params, &Some(delta))
+            .unwrap();
+        assert_eq!(state.members.members, vec![member]);
+        assert_eq!(state.bans.0, vec![lifted.clone()]);
+        assert_eq!(state.bans.in_effect().count(), 0);
+
+        // Peers that only have the ban get the unban
+        assert_eq!(
+            state.bans.delta(&state, &params, &banned_summary.bans),
+            Some(vec![lifted])
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use crate::room_state::ban::AuthorizedUnban;
+use crate::room_state::configuration::Configuration;
+use crate::room_state::error::{RoomStateError, StateField};
+use crate::room_state::member::{MemberId, MemberIndex};
+use crate::room_state::ChatRoomParametersV1;
+use crate::util::{sign_struct, verify_struct};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use freenet_scaffold::util::{blake3_hash, VersionedHash};
+use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
+use std::time::SystemTime;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuthorizedUserBan {
+    pub ban: UserBan,
+    pub banned_by: MemberId,
+    pub signature: Signature,
+    /// Set once the ban is lifted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unban: Option<AuthorizedUnban>,
+    /// In private rooms, the lowest version of the room secret that messages after the ban may be
+    /// encrypted with, recorded by `BansV1::apply_delta` rather than signed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_secret_version: Option<u32>,
+}
+
+impl Eq for AuthorizedUserBan {}
+
+impl Hash for AuthorizedUserBan {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.signature.to_bytes().hash(state);
+    }
+}
+
+impl AuthorizedUserBan {
+    pub fn new(ban: UserBan, banned_by: MemberId, banner_signing_key: &SigningKey) -> Self {
+        assert_eq!(
+            MemberId::from(banner_signing_key.verifying_key()),
+            banned_by
+        );
+
+        let signature = sign_struct(&ban, banner_signing_key);
+
+        Self {
+            ban,
+            banned_by,
+            signature,
+            unban: None,
+            min_secret_version: None,
+        }
+    }
+
+    /// Lifts the ban, `unban` must be made by the banner or the owner
+    pub fn lifted(self, unban: AuthorizedUnban) -> Self {
+        Self {
+            unban: Some(unban),
+            ..self
+        }
+    }
+
+    pub fn verify_signature(
+        &self,
+        banner_verifying_key: &VerifyingKey,
+    ) -> Result<(), RoomStateError> {
+        verify_struct(&self.ban, &self.signature, banner_verifying_key).map_err(|_| {
+            RoomStateError::InvalidSignature {
+                field: StateField::Ban,
+                id: self.id().0,
+            }
+        })
+    }
+
+    pub fn id(&self) -> BanId {
+        BanId(blake3_hash(BAN_ID_CONTEXT, &self.signature.to_bytes()))
+    }
+
+    /// Checks the unban, if any, lifts this ban and was made by the banner or the owner
+    pub fn verify_unban(
+        &self,
+        index: &MemberIndex,
+        parameters: &ChatRoomParametersV1,
+    ) -> Result<(), RoomStateError> {
+        let Some(unban) = &self.unban else {
+            return Ok(());
+        };
+        if unban.unban.ban_id != self.id()
+            || (unban.unbanned_by != self.banned_by && unban.unbanned_by != parameters.owner_id())
+        {
+            return Err(RoomStateError::Unauthorized {
+                field: StateField::Unban,
+                id: unban.id().0,
+            });
+        }
+        if unban.unbanned_by == parameters.owner_id() {
+            return unban.verify_signature(&parameters.owner);
+        }
+        let unbanner_vk =
+            index
+                .known_member_vk(unban.unbanned_by)
+                .ok_or(RoomStateError::UnknownAuthor {
+                    field: StateField::Unban,
+                    author: unban.unbanned_by,
+                })?;
+        unban.verify_signature(unbanner_vk)
+    }
+
+    /// Checks the signatures and that the banner is the owner or has been in the room, and may ban
+    /// the member, see `MemberIndex::may_ban`
+    pub(super) fn is_authorized(
+        &self,
+        index: &MemberIndex,
+        configuration: &Configuration,
+        parameters: &ChatRoomParametersV1,
+    ) -> bool {
+        if self.banned_by.0.is_legacy() || self.ban.banned_user.0.is_legacy() {
+            return false;
+        }
+        let signed = if self.banned_by == parameters.owner_id() {
+            self.verify_signature(&parameters.owner).is_ok()
+        } else {
+            index
+                .known_member_vk(self.banned_by)
+                .is_some_and(|banner_vk| self.verify_signature(banner_vk).is_ok())
+        };
+        signed
+            && index.may_ban(self.banned_by, self.ban.banned_user, configuration)
+            && self.verify_unban(index, parameters).is_ok()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UserBan {
+    pub owner_member_id: MemberId,
+    pub banned_at: SystemTime,
+    pub banned_user: MemberId,
+    /// When the ban lifts by itself, `None` for a ban that stands until it's lifted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<SystemTime>,
+}
+
+const BAN_ID_CONTEXT: &str = "river 2025-01 ban id";
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Hash, Debug, Ord, PartialOrd)]
+pub struct BanId(pub VersionedHash);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::ban::tests::create_test_chat_room_state;
+    use crate::room_state::ban::BansV1;
+    use crate::room_state::member::{AuthorizedMember, Member};
+    use freenet_scaffold::ComposableState;
+    use std::time::Duration;
+
+    #[test]
+    fn test_authorized_user_ban() {
+        let owner_key = SigningKey::generate(&mut rand::thread_rng());
+        let owner_id: MemberId = owner_key.verifying_key().into();
+        let member_key = SigningKey::generate(&mut rand::thread_rng());
+        let member_id: MemberId = member_key.verifying_key().into();
+
+        let ban = UserBan {
+            owner_member_id: owner_id.clone(),
+            banned_at: SystemTime::now(),
+            banned_user: member_id.clone(),
+            expires_at: None,
+        };
+
+        let authorized_ban = AuthorizedUserBan::new(ban.clone(), owner_id.clone(), &owner_key);
+
+        // Test 1: Verify signature
+        assert!(authorized_ban
+            .verify_signature(&owner_key.verifying_key())
+            .is_ok());
+
+        // Test 2: Verify signature with wrong key
+        let wrong_key = SigningKey::generate(&mut rand::thread_rng());
+        assert!(authorized_ban
+            .verify_signature(&wrong_key.verifying_key())
+            .is_err());
+
+        // Test 3: Check ban ID
+        let id1 = authorized_ban.id();
+        let id2 = authorized_ban.id();
+        assert_eq!(id1, id2);
+
+        // Test 4: Different bans should have different IDs
+        let another_ban = AuthorizedUserBan::new(
+            UserBan {
+                owner_member_id: owner_id.clone(),
+                banned_at: SystemTime::now() + Duration::from_secs(1),
+                banned_user: member_id.clone(),
+                expires_at: None,
+            },
+            owner_id.clone(),
+            &owner_key,
+        );
+        assert_ne!(authorized_ban.id(), another_ban.id());
+    }
+
+    #[test]
+    fn test_forged_legacy_banner_id_rejected() {
+        let owner_key = SigningKey::generate(&mut rand::thread_rng());
+        let owner_id: MemberId = owner_key.verifying_key().into();
+        let attacker_key = SigningKey::generate(&mut rand::thread_rng());
+        let victim_key = SigningKey::generate(&mut rand::thread_rng());
+        let victim_id: MemberId = victim_key.verifying_key().into();
+        let params = ChatRoomParametersV1 {
+            owner: owner_key.verifying_key(),
+        };
+
+        let mut state = create_test_chat_room_state();
+        for key in [&attacker_key, &victim_key] {
+            state.members.members.push(AuthorizedMember::new(
+                Member {
+                    owner_member_id: owner_id,
+                    invited_by: owner_id,
+                    member_vk: key.verifying_key(),
+                },
+                &owner_key,
+            ));
+        }
+
+        // An attacker whose fast_hash id collided with the owner's could claim to be the owner
+        let forged_owner_id = MemberId(VersionedHash::Legacy(freenet_scaffold::util::fast_hash(
+            &owner_key.verifying_key().to_bytes(),
+        )));
+        let ban = UserBan {
+            owner_member_id: owner_id,
+            banned_at: SystemTime::now(),
+            banned_user: victim_id,
+            expires_at: None,
+        };
+        let forged_ban = AuthorizedUserBan {
+            signature: sign_struct(&ban, &attacker_key),
+            ban,
+            banned_by: forged_owner_id,
+            unban: None,
+            min_secret_version: None,
+        };
+
+        let bans = BansV1(vec![forged_ban.clone()]);
+        assert!(bans.verify(&state, &params).is_err());
+
+        let mut bans = BansV1::default();
+        assert!(bans
+            .apply_delta(&state, &params, &Some(vec![forged_ban]))
+            .is_err());
+        assert!(bans.0.is_empty());
+    }
+}
@@ -1,32 +1,55 @@
+use crate::room_state::error::{RoomStateError, StateField};
 use crate::room_state::member::MemberId;
 use crate::room_state::ChatRoomParametersV1;
 use crate::util::truncated_base64;
 use crate::ChatRoomStateV1;
 use ed25519_dalek::{Signature, SignatureError, Signer, SigningKey, Verifier, VerifyingKey};
-use freenet_scaffold::util::{fast_hash, FastHash};
+use freenet_scaffold::util::{blake3_hash, VersionedHash};
 use freenet_scaffold::ComposableState;
+use rsa::RsaPublicKey;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+mod rename;
+
+pub use rename::{AuthorizedRoomRename, RoomRename};
+
+const CONFIGURATION_ID_CONTEXT: &str = "river 2025-01 configuration id";
+
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct AuthorizedConfigurationV1 {
     pub configuration: Configuration,
     pub signature: Signature,
+    /// The latest rename of this configuration by a moderator, see `AuthorizedRoomRename`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rename: Option<AuthorizedRoomRename>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ConfigurationSummary {
+    pub version: u32,
+    #[serde(default)]
+    pub rename: Option<VersionedHash>,
 }
 
 impl ComposableState for AuthorizedConfigurationV1 {
     type ParentState = ChatRoomStateV1;
-    type Summary = u32;
+    type Summary = ConfigurationSummary;
     type Delta = AuthorizedConfigurationV1;
     type Parameters = ChatRoomParametersV1;
+    type Error = RoomStateError;
 
     fn verify(
         &self,
         _parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
-    ) -> Result<(), String> {
+    ) -> Result<(), Self::Error> {
         self.verify_signature(&parameters.owner)
-            .map_err(|e| format!("Invalid signature: {}", e))
+            .map_err(|_| self.invalid_signature())?;
+        match &self.rename {
+            Some(rename) => rename.verify(&self.configuration),
+            None => Ok(()),
+        }
     }
 
     fn summarize(
@@ -34,16 +57,24 @@ impl ComposableState for AuthorizedConfigurationV1 {
         _parent_state: &Self::ParentState,
         _parameters: &Self::Parameters,
     ) -> Self::Summary {
-        self.configuration.configuration_version
+        ConfigurationSummary {
+            version: self.configuration.configuration_version,
+            rename: self.rename.as_ref().map(|rename| rename.id()),
+        }
     }
 
     fn delta(
         &self,
         _parent_state: &Self::ParentState,
         _parameters: &Self::Parameters,
-        old_version: &Self::Summary,
+        old_summary: &Self::Summary,
     ) -> Option<Self::Delta> {
-        if self.configuration.configuration_version > *old_version {
+        let version = self.configuration.configuration_version;
+        let renamed = self
+            .rename
+            .as_ref()
+            .is_some_and(|rename| old_summary.rename != Some(rename.id()));
+        if version > old_summary.version || (version == old_summary.version && renamed) {
             Some(self.clone())
         } else {
             None
@@ -55,23 +86,38 @@ impl ComposableState for AuthorizedConfigurationV1 {
         _parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
         delta: &Option<Self::Delta>,
-    ) -> Result<(), String> {
+    ) -> Result<(), Self::Error> {
         if let Some(delta) = delta {
             // Verify the delta's signature
             delta
                 .verify_signature(&parameters.owner)
-                .map_err(|e| format!("Invalid signature: {}", e))?;
+                .map_err(|_| delta.invalid_signature())?;
+
+            // A rename of the configuration we have, keep the latest so that peers agree
+            if let (Some(rename), true) = (&delta.rename, delta.configuration == self.configuration)
+            {
+                rename.verify(&self.configuration)?;
+                if self
+                    .rename
+                    .as_ref()
+                    .is_none_or(|current| rename.order_key() > current.order_key())
+                {
+                    self.rename = Some(rename.clone());
+                }
+                return Ok(());
+            }
 
             // Check if the new version is greater than the current version
             if delta.configuration.configuration_version <= self.configuration.configuration_version {
-                return Err(
-                    "New configuration version must be greater than the current version".to_string(),
-                );
+                return Err(RoomStateError::StaleConfiguration {
+                    current: self.configuration.configuration_version,
+                    received: delta.configuration.configuration_version,
+                });
             }
 
             // Verify that the owner_member_id hasn't changed
             if delta.configuration.owner_member_id != self.configuration.owner_member_id {
-                return Err("Cannot change the owner_member_id".to_string());
+                return Err(RoomStateError::OwnerChanged);
             }
 
             // Verify that the new configuration is valid
@@ -81,12 +127,17 @@ impl ComposableState for AuthorizedConfigurationV1 {
                 || delta.configuration.max_nickname_size == 0
                 || delta.configuration.max_members == 0
             {
-                return Err("Invalid configuration values".to_string());
+                return Err(RoomStateError::InvalidConfiguration);
+            }
+
+            if let Some(rename) = &delta.rename {
+                rename.verify(&delta.configuration)?;
             }
 
             // If all checks pass, apply the delta
             self.configuration = delta.configuration.clone();
             self.signature = delta.signature.clone();
+            self.rename = delta.rename.clone();
         }
 
         Ok(())
@@ -103,6 +154,23 @@ impl AuthorizedConfigurationV1 {
         Self {
             configuration,
             signature,
+            rename: None,
+        }
+    }
+
+    /// Adds a moderator's rename of this configuration
+    pub fn renamed(self, rename: AuthorizedRoomRename) -> Self {
+        Self {
+            rename: Some(rename),
+            ..self
+        }
+    }
+
+    /// The name of the room, as last renamed by a moderator if it was since the owner set it
+    pub fn name(&self) -> &str {
+        match &self.rename {
+            Some(rename) => &rename.rename.name,
+            None => &self.configuration.name,
         }
     }
 
@@ -116,8 +184,15 @@ impl AuthorizedConfigurationV1 {
         owner_verifying_key.verify(&serialized_config, &self.signature)
     }
 
-    pub fn id(&self) -> FastHash {
-        fast_hash(&self.signature.to_bytes())
+    pub fn id(&self) -> VersionedHash {
+        blake3_hash(CONFIGURATION_ID_CONTEXT, &self.signature.to_bytes())
+    }
+
+    fn invalid_signature(&self) -> RoomStateError {
+        RoomStateError::InvalidSignature {
+            field: StateField::Configuration,
+            id: self.id(),
+        }
     }
 }
 
@@ -132,7 +207,7 @@ impl Default for AuthorizedConfigurationV1 {
 impl Default for Configuration {
     fn default() -> Self {
         Configuration {
-            owner_member_id: MemberId(FastHash(0)), // Default value, should be overwritten
+            owner_member_id: MemberId(VersionedHash::Blake3V1([0; 32])), // Default value, should be overwritten
             configuration_version: 1,
             name: "Default Room Name".to_string(),
             max_recent_messages: 100,
@@ -140,6 +215,13 @@ impl Default for Configuration {
             max_message_size: 1000,
             max_nickname_size: 50,
             max_members: 200,
+            max_reactions_per_member: DEFAULT_MAX_REACTIONS_PER_MEMBER,
+            privacy_mode: PrivacyMode::Public,
+            ghost_key_issuers: Vec::new(),
+            moderators: Vec::new(),
+            successor: None,
+            max_redemptions: DEFAULT_MAX_REDEMPTIONS,
+            max_removals: DEFAULT_MAX_REMOVALS,
         }
     }
 }
@@ -152,6 +234,7 @@ impl fmt::Debug for AuthorizedConfigurationV1 {
                 "signature",
                 &format_args!("{}", truncated_base64(self.signature.to_bytes())),
             )
+            .field("rename", &self.rename)
             .finish()
     }
 }
@@ -166,12 +249,133 @@ pub struct Configuration {
     pub max_message_size: usize,
     pub max_nickname_size: usize,
     pub max_members: usize,
+    /// Left out when it's the default so that configurations signed before it existed still
+    /// serialize, and so verify, the same
+    #[serde(
+        default = "default_max_reactions_per_member",
+        skip_serializing_if = "is_default_max_reactions_per_member"
+    )]
+    pub max_reactions_per_member: usize,
+    /// Whether message content is encrypted with the room secret, see `RoomSecretsV1`
+    #[serde(default, skip_serializing_if = "PrivacyMode::is_public")]
+    pub privacy_mode: PrivacyMode,
+    /// Keys trusted to issue ghost key certificates, members certified by one of them can join
+    /// without an invitation, see `GhostKeyCertificate`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ghost_key_issuers: Vec<RsaPublicKey>,
+    /// Members the owner trusts with some of their powers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub moderators: Vec<Moderator>,
+    /// Who may hand the room over to themselves should the owner's key be lost, see
+    /// `UpgradeV1::new_owner`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub successor: Option<VerifyingKey>,
+    /// How many redemptions of members who are no longer in the room are kept to count how often
+    /// their invitations were used, see `MembersV1::redemptions`
+    #[serde(
+        default = "default_max_redemptions",
+        skip_serializing_if = "is_default_max_redemptions"
+    )]
+    pub max_redemptions: usize,
+    /// How many removals the room keeps, and how many of the memberships they ended, see
+    /// `MembersV1::removals`
+    #[serde(
+        default = "default_max_removals",
+        skip_serializing_if = "is_default_max_removals"
+    )]
+    pub max_removals: usize,
+}
+
+impl Configuration {
+    /// What `member_id` may do as a moderator, nothing if they aren't one
+    pub fn moderator_permissions(&self, member_id: MemberId) -> ModeratorPermissions {
+        self.moderators
+            .iter()
+            .find(|moderator| moderator.member_id == member_id)
+            .map(|moderator| moderator.permissions)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Moderator {
+    pub member_id: MemberId,
+    pub permissions: ModeratorPermissions,
+}
+
+/// What the owner lets a moderator do, the owner can do all of it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ModeratorPermissions {
+    /// Ban any member rather than only those they invited, the owner excepted
+    pub ban_anyone: bool,
+    /// Delete other members' messages
+    pub delete_messages: bool,
+    /// Rename the room, see `AuthorizedRoomRename`
+    pub edit_room_name: bool,
+}
+
+impl ModeratorPermissions {
+    pub fn is_moderator(&self) -> bool {
+        self.ban_anyone || self.delete_messages || self.edit_room_name
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PrivacyMode {
+    #[default]
+    Public,
+    /// Message content is only readable by members who have been given the room secret
+    Private,
+}
+
+impl PrivacyMode {
+    pub fn is_public(&self) -> bool {
+        *self == PrivacyMode::Public
+    }
+}
+
+const DEFAULT_MAX_REACTIONS_PER_MEMBER: usize = 100;
+
+fn default_max_reactions_per_member() -> usize {
+    DEFAULT_MAX_REACTIONS_PER_MEMBER
+}
+
+fn is_default_max_reactions_per_member(max: &usize) -> bool {
+    *max == DEFAULT_MAX_REACTIONS_PER_MEMBER
+}
+
+const DEFAULT_MAX_REDEMPTIONS: usize = 100;
+
+fn default_max_redemptions() -> usize {
+    DEFAULT_MAX_REDEMPTIONS
+}
+
+fn is_default_max_redemptions(max: &usize) -> bool {
+    *max == DEFAULT_MAX_REDEMPTIONS
+}
+
+const DEFAULT_MAX_REMOVALS: usize = 100;
+
+fn default_max_removals() -> usize {
+    DEFAULT_MAX_REMOVALS
+}
+
+fn is_default_max_removals(max: &usize) -> bool {
+    *max == DEFAULT_MAX_REMOVALS
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use rand::rngs::OsRng;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn summary(version: u32) -> ConfigurationSummary {
+        ConfigurationSummary {
+            version,
+            rename: None,
+        }
+    }
 
     #[test]
     fn test_verify() {
@@ -238,7 +442,10 @@ mod tests {
 
         assert_eq!(
             authorized_configuration.summarize(&parent_state, &parameters),
-            configuration.configuration_version
+            ConfigurationSummary {
+                version: configuration.configuration_version,
+                rename: None,
+            }
         );
     }
 
@@ -264,7 +471,7 @@ mod tests {
             AuthorizedConfigurationV1::new(new_configuration.clone(), &owner_signing_key);
 
         assert_eq!(
-            new_authorized_configuration.delta(&parent_state, &parameters, &1),
+            new_authorized_configuration.delta(&parent_state, &parameters, &summary(1)),
             Some(new_authorized_configuration)
         );
     }
@@ -289,7 +496,7 @@ mod tests {
         // Test against a newer version (2)
         // The delta should return None since our configuration is older
         assert_eq!(
-            old_authorized_configuration.delta(&parent_state, &parameters, &2),
+            old_authorized_configuration.delta(&parent_state, &parameters, &summary(2)),
             None
         );
     }
@@ -355,10 +562,12 @@ mod tests {
             &Some(new_authorized_configuration),
         );
 
-        assert!(result.is_err());
         assert_eq!(
-            result.unwrap_err(),
-            "New configuration version must be greater than the current version"
+            result,
+            Err(RoomStateError::StaleConfiguration {
+                current: 1,
+                received: 0
+            })
         );
         assert_eq!(authorized_configuration, orig_authorized_configuration);
     }
@@ -368,7 +577,7 @@ mod tests {
         let owner_signing_key = SigningKey::generate(&mut OsRng);
         let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
         let mut configuration = Configuration::default();
-        configuration.owner_member_id = MemberId(FastHash(1));
+        configuration.owner_member_id = MemberId(VersionedHash::Blake3V1([1; 32]));
         let mut authorized_configuration =
             AuthorizedConfigurationV1::new(configuration.clone(), &owner_signing_key);
 
@@ -380,7 +589,7 @@ mod tests {
 
         let mut new_configuration = configuration.clone();
         new_configuration.configuration_version += 1;
-        new_configuration.owner_member_id = MemberId(FastHash(2));
+        new_configuration.owner_member_id = MemberId(VersionedHash::Blake3V1([2; 32]));
         let new_authorized_configuration =
             AuthorizedConfigurationV1::new(new_configuration, &owner_signing_key);
 
@@ -390,8 +599,7 @@ mod tests {
             &Some(new_authorized_configuration),
         );
 
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Cannot change the owner_member_id");
+        assert_eq!(result, Err(RoomStateError::OwnerChanged));
     }
 
     #[test]
@@ -420,7 +628,130 @@ mod tests {
             &Some(new_authorized_configuration),
         );
 
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Invalid configuration values");
+        assert_eq!(result, Err(RoomStateError::InvalidConfiguration));
+    }
+
+    #[test]
+    fn test_configuration_signed_before_reaction_limit() {
+        // The configuration as it was before `max_reactions_per_member` was added
+        #[derive(Serialize)]
+        struct OldConfiguration {
+            owner_member_id: MemberId,
+            configuration_version: u32,
+            name: String,
+            max_recent_messages: usize,
+            max_user_bans: usize,
+            max_message_size: usize,
+            max_nickname_size: usize,
+            max_members: usize,
+        }
+
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let configuration = Configuration::default();
+        let old_configuration = OldConfiguration {
+            owner_member_id: configuration.owner_member_id,
+            configuration_version: configuration.configuration_version,
+            name: configuration.name.clone(),
+            max_recent_messages: configuration.max_recent_messages,
+            max_user_bans: configuration.max_user_bans,
+            max_message_size: configuration.max_message_size,
+            max_nickname_size: configuration.max_nickname_size,
+            max_members: configuration.max_members,
+        };
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&old_configuration, &mut bytes).unwrap();
+        let signature = owner_signing_key.sign(&bytes);
+
+        let decoded: Configuration = ciborium::de::from_reader(&bytes[..]).unwrap();
+        assert_eq!(decoded, configuration);
+        let authorized = AuthorizedConfigurationV1 {
+            configuration: decoded,
+            signature,
+            rename: None,
+        };
+        assert!(authorized
+            .verify_signature(&owner_signing_key.verifying_key())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_moderator_rename() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let moderator_signing_key = SigningKey::generate(&mut OsRng);
+        let member_signing_key = SigningKey::generate(&mut OsRng);
+        let configuration = Configuration {
+            moderators: vec![Moderator {
+                member_id: moderator_signing_key.verifying_key().into(),
+                permissions: ModeratorPermissions {
+                    edit_room_name: true,
+                    ..ModeratorPermissions::default()
+                },
+            }],
+            ..Configuration::default()
+        };
+        let mut authorized_configuration =
+            AuthorizedConfigurationV1::new(configuration.clone(), &owner_signing_key);
+        let parent_state = ChatRoomStateV1::default();
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        let original = authorized_configuration.clone();
+        let rename = |name: &str, secs: u64, signing_key: &SigningKey| {
+            let rename = RoomRename {
+                configuration_version: configuration.configuration_version,
+                name: name.to_string(),
+                renamed_at: UNIX_EPOCH + Duration::from_secs(secs),
+            };
+            original
+                .clone()
+                .renamed(AuthorizedRoomRename::new(rename, signing_key))
+        };
+
+        // Only moderators allowed to rename the room can
+        let forged = rename("Forged", 1, &member_signing_key);
+        assert!(matches!(
+            authorized_configuration.apply_delta(&parent_state, &parameters, &Some(forged)),
+            Err(RoomStateError::Unauthorized {
+                field: StateField::Rename,
+                ..
+            })
+        ));
+
+        // The latest rename is kept whatever order they come in
+        let earlier = rename("Earlier", 1, &moderator_signing_key);
+        let later = rename("Later", 2, &moderator_signing_key);
+        let summary = authorized_configuration.summarize(&parent_state, &parameters);
+        for delta in [&later, &earlier] {
+            authorized_configuration
+                .apply_delta(&parent_state, &parameters, &Some(delta.clone()))
+                .unwrap();
+        }
+        assert_eq!(authorized_configuration, later);
+        assert_eq!(authorized_configuration.name(), "Later");
+        assert!(authorized_configuration
+            .verify(&parent_state, &parameters)
+            .is_ok());
+        assert_eq!(
+            authorized_configuration.delta(&parent_state, &parameters, &summary),
+            Some(later)
+        );
+
+        // A new configuration from the owner replaces the rename
+        let new_configuration = Configuration {
+            configuration_version: 2,
+            ..configuration.clone()
+        };
+        authorized_configuration
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(AuthorizedConfigurationV1::new(
+                    new_configuration,
+                    &owner_signing_key,
+                )),
+            )
+            .unwrap();
+        assert_eq!(authorized_configuration.rename, None);
+        assert_eq!(authorized_configuration.name(), configuration.name);
     }
 }
@@ -0,0 +1,89 @@
+use crate::room_state::configuration::Configuration;
+use crate::room_state::error::{RoomStateError, StateField};
+use crate::room_state::member::MemberId;
+use crate::util::{sign_struct, truncated_base64, verify_struct};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use freenet_scaffold::util::{blake3_hash, VersionedHash};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::SystemTime;
+
+/*
+ Only the owner signs configurations, so moderators allowed to edit the room name sign a rename of
+ the configuration they saw instead. It's kept with that configuration and dropped along with it
+ once the owner makes a new one, which should carry the name over.
+*/
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RoomRename {
+    pub configuration_version: u32,
+    pub name: String,
+    pub renamed_at: SystemTime,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct AuthorizedRoomRename {
+    pub rename: RoomRename,
+    pub renamed_by: VerifyingKey,
+    pub signature: Signature,
+}
+
+const ROOM_RENAME_ID_CONTEXT: &str = "river 2025-01 room rename id";
+
+impl AuthorizedRoomRename {
+    pub fn new(rename: RoomRename, moderator_signing_key: &SigningKey) -> Self {
+        Self {
+            signature: sign_struct(&rename, moderator_signing_key),
+            rename,
+            renamed_by: moderator_signing_key.verifying_key(),
+        }
+    }
+
+    pub fn id(&self) -> VersionedHash {
+        blake3_hash(ROOM_RENAME_ID_CONTEXT, &self.signature.to_bytes())
+    }
+
+    /// Checks the rename was signed by a moderator of `configuration` who may edit the room name
+    pub fn verify(&self, configuration: &Configuration) -> Result<(), RoomStateError> {
+        if self.rename.configuration_version != configuration.configuration_version {
+            return Err(RoomStateError::StaleConfiguration {
+                current: configuration.configuration_version,
+                received: self.rename.configuration_version,
+            });
+        }
+        if !configuration
+            .moderator_permissions(MemberId::from(&self.renamed_by))
+            .edit_room_name
+            || self.rename.name.is_empty()
+        {
+            return Err(RoomStateError::Unauthorized {
+                field: StateField::Rename,
+                id: self.id(),
+            });
+        }
+        verify_struct(&self.rename, &self.signature, &self.renamed_by).map_err(|_| {
+            RoomStateError::InvalidSignature {
+                field: StateField::Rename,
+                id: self.id(),
+            }
+        })
+    }
+
+    /// Orders renames of the same configuration so that peers keep the same one
+    pub fn order_key(&self) -> (SystemTime, VersionedHash) {
+        (self.rename.renamed_at, self.id())
+    }
+}
+
+impl fmt::Debug for AuthorizedRoomRename {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthorizedRoomRename")
+            .field("rename", &self.rename)
+            .field("renamed_by", &MemberId::from(&self.renamed_by))
+            .field(
+                "signature",
+                &format_args!("{}", truncated_base64(self.signature.to_bytes())),
+            )
+            .finish()
+    }
+}
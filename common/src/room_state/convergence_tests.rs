@@ -0,0 +1,608 @@
+//! Runs the freenet-scaffold convergence harness against ChatRoomStateV2, with several peers
+//! inviting, banning, unbanning, removing, posting and reconfiguring independently before
+//! syncing in random order.
+
+use super::*;
+use crate::room_state::ban::{AuthorizedUnban, AuthorizedUserBan, Unban, UserBan};
+use crate::room_state::configuration::{
+    AuthorizedRoomRename, Configuration, Moderator, ModeratorPermissions, RoomRename,
+};
+use crate::room_state::member::{
+    AuthorizedMember, AuthorizedRemoval, Member, MembersDelta, Removal,
+};
+use crate::room_state::member_info::{AuthorizedMemberInfo, MemberInfo};
+use crate::room_state::message::{
+    AuthorizedMessageActionV1, AuthorizedMessageV1, Message, MessageAction, MessageActionV1,
+    MessageV1, MessageV2, MessagesDelta,
+};
+use crate::room_state::reaction::{AuthorizedReaction, Reaction};
+use crate::room_state::versioned::ChatRoomStateV2Delta;
+use crate::ChatRoomStateV2;
+use ed25519_dalek::SigningKey;
+use freenet_scaffold::testing::{run_steps, steps, Mutation, Step};
+use once_cell::sync::Lazy;
+use proptest::prelude::*;
+use proptest::sample::Index;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::time::{Duration, UNIX_EPOCH};
+
+const PEERS: usize = 3;
+const MAX_STEPS: usize = 30;
+
+/// User 0 is the room owner, every invite brings in a new user so there is one per step
+static SIGNING_KEYS: Lazy<Vec<SigningKey>> = Lazy::new(|| {
+    (0..=MAX_STEPS as u32)
+        .map(|user| {
+            let mut seed = [0u8; 32];
+            seed[..4].copy_from_slice(&(user + 1).to_le_bytes());
+            SigningKey::from_bytes(&seed)
+        })
+        .collect()
+});
+
+/// Users are picked by index among those that can take the action in the peer's state. Invitees
+/// are new users and only users the peer has seen removed rejoin. Inviting a user who is still a
+/// member elsewhere through someone else changes who is above them in the invite chain, so
+/// members below them who were out of the room on some peers while their inviter was kicked come
+/// back, and the messages they posted meanwhile are only kept where they never left.
+#[derive(Clone, Debug)]
+enum Action {
+    Invite {
+        inviter: Index,
+    },
+    /// Bans a member, for a few steps if `expires` is set and the ban can end, see `LIFTABLE`
+    Ban {
+        banner: Index,
+        banned: Index,
+        expires: bool,
+    },
+    /// Lifts a ban, as whoever made it or as the owner
+    Unban {
+        unbanner: Index,
+        ban: Index,
+    },
+    /// A member leaves or is kicked by someone in their invite chain
+    Remove {
+        remover: Index,
+        removed: Index,
+    },
+    /// Invites a user who left or was kicked
+    Rejoin {
+        inviter: Index,
+        user: Index,
+    },
+    /// Posts a message, replying to one the peer has if `reply_to` is set
+    Message {
+        author: Index,
+        reply_to: Option<Index>,
+    },
+    /// Edits or deletes a message, as its author or, for deletes, as the owner or a moderator
+    /// if `moderator` is set
+    EditOrDelete {
+        message: Index,
+        delete: bool,
+        moderator: Option<Index>,
+    },
+    React {
+        user: Index,
+        message: Index,
+        reaction: Index,
+    },
+    /// Sets the version after the one the peer has, so a member renaming themselves on two peers
+    /// publishes two infos of the same version, as from two devices
+    Nickname {
+        user: Index,
+    },
+    /// Renames the room, and changes the message, reaction and removal limits if `limits` is set
+    Configure {
+        limits: Option<(usize, usize, usize)>,
+    },
+    /// The owner makes a member a moderator who may do everything. A configuration the owner made
+    /// meanwhile on another peer drops them again, lifting the bans only they could make, so those
+    /// are limited like unbans, see `LIFTABLE`.
+    Moderate {
+        member: Index,
+    },
+    /// Renames the room as a moderator
+    Rename {
+        moderator: Index,
+    },
+}
+
+/// An action along with its position in the test, which makes users, timestamps and versions unique
+#[derive(Clone, Debug)]
+struct Numbered {
+    action: Action,
+    seq: u32,
+}
+
+fn action() -> impl Strategy<Value = Action> {
+    prop_oneof![
+        3 => any::<Index>().prop_map(|inviter| Action::Invite { inviter }),
+        2 => any::<(Index, Index, bool)>()
+            .prop_map(|(banner, banned, expires)| Action::Ban { banner, banned, expires }),
+        1 => any::<(Index, Index)>().prop_map(|(unbanner, ban)| Action::Unban { unbanner, ban }),
+        1 => any::<(Index, Index)>()
+            .prop_map(|(remover, removed)| Action::Remove { remover, removed }),
+        1 => any::<(Index, Index)>().prop_map(|(inviter, user)| Action::Rejoin { inviter, user }),
+        3 => any::<(Index, Option<Index>)>()
+            .prop_map(|(author, reply_to)| Action::Message { author, reply_to }),
+        2 => any::<(Index, bool, Option<Index>)>().prop_map(|(message, delete, moderator)| {
+            Action::EditOrDelete { message, delete, moderator }
+        }),
+        2 => any::<(Index, Index, Index)>()
+            .prop_map(|(user, message, reaction)| Action::React { user, message, reaction }),
+        1 => any::<Index>().prop_map(|user| Action::Nickname { user }),
+        1 => proptest::option::of((1..6usize, 1..4usize, 1..4usize))
+            .prop_map(|limits| Action::Configure { limits }),
+        1 => any::<Index>().prop_map(|member| Action::Moderate { member }),
+        1 => any::<Index>().prop_map(|moderator| Action::Rename { moderator }),
+    ]
+}
+
+fn numbered_steps() -> impl Strategy<Value = Vec<Step<Numbered>>> {
+    steps(PEERS, action(), MAX_STEPS).prop_map(|steps| {
+        steps
+            .into_iter()
+            .enumerate()
+            .map(|(seq, step)| match step {
+                Step::Mutate { peer, mutation } => Step::Mutate {
+                    peer,
+                    mutation: Numbered {
+                        action: mutation,
+                        seq: seq as u32,
+                    },
+                },
+                Step::Sync { from, to } => Step::Sync { from, to },
+            })
+            .collect()
+    })
+}
+
+thread_local! {
+    /// Users removed so far in the current case, who stop acting for good even where they're
+    /// still a member or once they're invited again. Otherwise a message they post on a peer that
+    /// hasn't seen them removed yet is dropped by peers that have, unless those saw them rejoin
+    /// first, and removals don't converge alongside it.
+    static REMOVED: RefCell<HashSet<VerifyingKey>> = RefCell::new(HashSet::new());
+    /// Users who posted, reacted, picked a nickname or invited someone in the current case
+    static ACTED: RefCell<HashSet<VerifyingKey>> = RefCell::new(HashSet::new());
+    /// Users banned before they acted, only their bans expire or are lifted. What a banned member
+    /// posted is dropped and only comes back on peers that learned the ban ended before they
+    /// dropped it, so that doesn't converge. They're left out of the actors for good too.
+    static LIFTABLE: RefCell<HashSet<MemberId>> = RefCell::new(HashSet::new());
+}
+
+fn acted(key: &SigningKey) {
+    ACTED.with(|acted| acted.borrow_mut().insert(key.verifying_key()));
+}
+
+/// The owner followed by the members of `state` who haven't been removed anywhere, ie. everyone
+/// who can act in the room
+fn actors(state: &ChatRoomStateV1) -> Vec<&'static SigningKey> {
+    let members: Vec<VerifyingKey> = state
+        .members
+        .members
+        .iter()
+        .map(|m| m.member.member_vk)
+        .collect();
+    SIGNING_KEYS
+        .iter()
+        .enumerate()
+        .filter(|(user, key)| {
+            *user == 0
+                || members.contains(&key.verifying_key())
+                    && !REMOVED.with(|removed| removed.borrow().contains(&key.verifying_key()))
+        })
+        .map(|(_, key)| key)
+        .collect()
+}
+
+fn id(key: &SigningKey) -> MemberId {
+    key.verifying_key().into()
+}
+
+impl Mutation<ChatRoomStateV2> for Numbered {
+    fn delta(
+        &self,
+        state: &ChatRoomStateV2,
+        parameters: &ChatRoomParametersV1,
+    ) -> Option<ChatRoomStateV2Delta> {
+        let state = &state.room;
+        let time = UNIX_EPOCH + Duration::from_secs(self.seq as u64);
+        let owner_id = parameters.owner_id();
+        let actors = actors(state);
+        let mut delta = ChatRoomStateV1Delta::default();
+        match &self.action {
+            Action::Invite { inviter } => {
+                let inviter = inviter.get(&actors);
+                acted(inviter);
+                let member = Member {
+                    owner_member_id: owner_id,
+                    invited_by: id(inviter),
+                    member_vk: SIGNING_KEYS[self.seq as usize + 1].verifying_key(),
+                };
+                let member = AuthorizedMember::new_at(member, inviter, time);
+                delta.members = Some(MembersDelta::new(vec![member]));
+            }
+            Action::Ban {
+                banner,
+                banned,
+                expires,
+            } => {
+                // Members can only ban members they invited, directly or indirectly. Moderators
+                // can ban others too, but only users who never acted, see `Moderate`.
+                let banner = banner.get(&actors);
+                let moderator = state
+                    .configuration
+                    .configuration
+                    .moderator_permissions(id(banner))
+                    .ban_anyone;
+                let bannable: Vec<&AuthorizedMember> =
+                    state
+                        .members
+                        .members
+                        .iter()
+                        .filter(|m| {
+                            id(banner) == owner_id
+                                || state.members.get_invite_chain(m, parameters).is_ok_and(
+                                    |chain| chain.iter().any(|i| i.member.id() == id(banner)),
+                                )
+                                || moderator
+                                    && m.member.id() != id(banner)
+                                    && !ACTED
+                                        .with(|acted| acted.borrow().contains(&m.member.member_vk))
+                        })
+                        .collect();
+                if bannable.is_empty() {
+                    return None;
+                }
+                let banned = banned.get(&bannable).member.member_vk;
+                let liftable = !ACTED.with(|acted| acted.borrow().contains(&banned));
+                if liftable {
+                    LIFTABLE.with(|liftable| liftable.borrow_mut().insert(banned.into()));
+                    REMOVED.with(|removed| removed.borrow_mut().insert(banned));
+                }
+                let ban = UserBan {
+                    owner_member_id: owner_id,
+                    banned_at: time,
+                    banned_user: banned.into(),
+                    expires_at: (liftable && *expires).then(|| time + Duration::from_secs(3)),
+                };
+                delta.bans = Some(vec![AuthorizedUserBan::new(ban, id(banner), banner)]);
+            }
+            Action::Unban { unbanner, ban } => {
+                let unbanner = unbanner.get(&actors);
+                let liftable: Vec<&AuthorizedUserBan> = state
+                    .bans
+                    .in_effect()
+                    .filter(|ban| {
+                        (id(unbanner) == owner_id || ban.banned_by == id(unbanner))
+                            && LIFTABLE
+                                .with(|liftable| liftable.borrow().contains(&ban.ban.banned_user))
+                    })
+                    .collect();
+                if liftable.is_empty() {
+                    return None;
+                }
+                let ban = ban.get(&liftable);
+                let unban = Unban {
+                    owner_member_id: owner_id,
+                    ban_id: ban.id(),
+                    unbanned_at: time,
+                };
+                let unban = AuthorizedUnban::new(unban, id(unbanner), unbanner);
+                delta.bans = Some(vec![(*ban).clone().lifted(unban)]);
+            }
+            Action::Remove { remover, removed } => {
+                let remover = remover.get(&actors);
+                let removable: Vec<&AuthorizedMember> = state
+                    .members
+                    .members
+                    .iter()
+                    .filter(|m| {
+                        id(remover) == owner_id
+                            || m.member.id() == id(remover)
+                            || m.member.invited_by == id(remover)
+                    })
+                    .collect();
+                if removable.is_empty() {
+                    return None;
+                }
+                let member_vk = removed.get(&removable).member.member_vk;
+                REMOVED.with(|removed| removed.borrow_mut().insert(member_vk));
+                let removal = Removal {
+                    owner_member_id: owner_id,
+                    member_vk,
+                    removed_by: remover.verifying_key(),
+                    removed_at: time,
+                    signed_for: None,
+                };
+                delta.members = Some(MembersDelta::remove(vec![AuthorizedRemoval::new(
+                    removal, remover,
+                )]));
+            }
+            Action::Rejoin { inviter, user } => {
+                // Anyone the peer has seen leave or be kicked who isn't a member again yet. Those
+                // whose ban may end don't come back, their nickname would be an act, see `LIFTABLE`.
+                let former: Vec<&SigningKey> = SIGNING_KEYS[1..=self.seq as usize]
+                    .iter()
+                    .filter(|key| {
+                        !LIFTABLE.with(|liftable| {
+                            liftable.borrow().contains(&key.verifying_key().into())
+                        }) && !state
+                            .members
+                            .members
+                            .iter()
+                            .any(|m| m.member.member_vk == key.verifying_key())
+                            && state
+                                .members
+                                .removals
+                                .iter()
+                                .any(|r| r.removal.member_vk == key.verifying_key())
+                    })
+                    .collect();
+                if former.is_empty() {
+                    return None;
+                }
+                let inviter = inviter.get(&actors);
+                let user = user.get(&former);
+                acted(inviter);
+                acted(user);
+                let member = Member {
+                    owner_member_id: owner_id,
+                    invited_by: id(inviter),
+                    member_vk: user.verifying_key(),
+                };
+                let member = AuthorizedMember::new_at(member, inviter, time);
+                delta.members = Some(MembersDelta::new(vec![member]));
+                // Like a join, with a version that supersedes info from their earlier membership
+                // which peers that haven't seen them removed still have
+                let info = MemberInfo {
+                    member_id: id(user),
+                    version: self.seq,
+                    preferred_nickname: format!("Nickname {}", self.seq),
+                };
+                delta.member_info =
+                    Some(vec![AuthorizedMemberInfo::new_with_member_key(info, user)]);
+            }
+            Action::Message { author, reply_to } => {
+                let author = author.get(&actors);
+                acted(author);
+                let message = MessageV1 {
+                    room_owner: owner_id,
+                    author: id(author),
+                    time,
+                    content: format!("Message {}", self.seq),
+                };
+                let messages = &state.recent_messages.messages.entries;
+                let message: Message = match reply_to {
+                    Some(reply_to) if !messages.is_empty() => MessageV2 {
+                        room_owner: message.room_owner,
+                        author: message.author,
+                        time: message.time,
+                        content: message.content,
+                        in_reply_to: reply_to.get(messages).id(),
+                    }
+                    .into(),
+                    _ => message.into(),
+                };
+                delta.recent_messages = Some(MessagesDelta {
+                    messages: vec![AuthorizedMessageV1::new(message, author)],
+                    ..Default::default()
+                });
+            }
+            Action::EditOrDelete {
+                message,
+                delete,
+                moderator,
+            } => {
+                let messages = &state.recent_messages.messages.entries;
+                if messages.is_empty() {
+                    return None;
+                }
+                let target = message.get(messages);
+                let author = if let (true, Some(moderator)) = (delete, moderator) {
+                    let moderators: Vec<&SigningKey> = actors
+                        .iter()
+                        .filter(|a| {
+                            id(a) == owner_id
+                                || state
+                                    .configuration
+                                    .configuration
+                                    .moderator_permissions(id(a))
+                                    .delete_messages
+                        })
+                        .copied()
+                        .collect();
+                    *moderator.get(&moderators)
+                } else {
+                    // The author may have been banned since, they can't act anymore then
+                    *actors.iter().find(|a| id(a) == target.message.author())?
+                };
+                acted(author);
+                let action = MessageActionV1 {
+                    room_owner: owner_id,
+                    author: id(author),
+                    time,
+                    target: target.id(),
+                    action: if *delete {
+                        MessageAction::Delete
+                    } else {
+                        MessageAction::Edit {
+                            content: format!("Edit {}", self.seq),
+                        }
+                    },
+                };
+                delta.recent_messages = Some(MessagesDelta {
+                    actions: vec![AuthorizedMessageActionV1::new(action, author)],
+                    ..Default::default()
+                });
+            }
+            Action::React {
+                user,
+                message,
+                reaction,
+            } => {
+                let messages = &state.recent_messages.messages.entries;
+                if messages.is_empty() {
+                    return None;
+                }
+                let user = user.get(&actors);
+                acted(user);
+                let reaction = Reaction {
+                    message_id: message.get(messages).id(),
+                    member_id: id(user),
+                    time,
+                    // Few enough that the same reaction is often made twice
+                    reaction: reaction.get(&["👍", "🎉"]).to_string(),
+                };
+                delta.reactions = Some(vec![AuthorizedReaction::new(reaction, user)]);
+            }
+            Action::Nickname { user } => {
+                let user = user.get(&actors);
+                acted(user);
+                let version = state
+                    .member_info
+                    .member_info
+                    .iter()
+                    .find(|i| i.member_info.member_id == id(user))
+                    .map_or(0, |i| i.member_info.version + 1);
+                let info = MemberInfo {
+                    member_id: id(user),
+                    version,
+                    preferred_nickname: format!("Nickname {}", self.seq),
+                };
+                let info = AuthorizedMemberInfo::new_with_member_key(info, user);
+                delta.member_info = Some(vec![info]);
+            }
+            Action::Configure { limits } => {
+                let mut configuration = Configuration {
+                    configuration_version: self.seq + 2,
+                    name: format!("Room {}", self.seq),
+                    ..state.configuration.configuration.clone()
+                };
+                if let Some((messages, reactions, removals)) = *limits {
+                    configuration.max_recent_messages = messages;
+                    configuration.max_reactions_per_member = reactions;
+                    configuration.max_removals = removals;
+                }
+                if configuration.configuration_version
+                    <= state.configuration.configuration.configuration_version
+                {
+                    return None;
+                }
+                delta.configuration = Some(AuthorizedConfigurationV1::new(
+                    configuration,
+                    &SIGNING_KEYS[0],
+                ));
+            }
+            Action::Moderate { member } => {
+                let members: Vec<MemberId> = actors[1..]
+                    .iter()
+                    .map(|a| id(a))
+                    .filter(|member| {
+                        !state
+                            .configuration
+                            .configuration
+                            .moderator_permissions(*member)
+                            .is_moderator()
+                    })
+                    .collect();
+                if members.is_empty() {
+                    return None;
+                }
+                let mut configuration = Configuration {
+                    configuration_version: self.seq + 2,
+                    name: state.configuration.name().to_string(),
+                    ..state.configuration.configuration.clone()
+                };
+                if configuration.configuration_version
+                    <= state.configuration.configuration.configuration_version
+                {
+                    return None;
+                }
+                configuration.moderators.push(Moderator {
+                    member_id: *member.get(&members),
+                    permissions: ModeratorPermissions {
+                        ban_anyone: true,
+                        delete_messages: true,
+                        edit_room_name: true,
+                    },
+                });
+                delta.configuration = Some(AuthorizedConfigurationV1::new(
+                    configuration,
+                    &SIGNING_KEYS[0],
+                ));
+            }
+            Action::Rename { moderator } => {
+                let moderators: Vec<&SigningKey> = actors
+                    .iter()
+                    .filter(|a| {
+                        state
+                            .configuration
+                            .configuration
+                            .moderator_permissions(id(a))
+                            .edit_room_name
+                    })
+                    .copied()
+                    .collect();
+                if moderators.is_empty() {
+                    return None;
+                }
+                let moderator = moderator.get(&moderators);
+                let rename = RoomRename {
+                    configuration_version: state.configuration.configuration.configuration_version,
+                    name: format!("Renamed {}", self.seq),
+                    renamed_at: time,
+                };
+                delta.configuration = Some(
+                    state
+                        .configuration
+                        .clone()
+                        .renamed(AuthorizedRoomRename::new(rename, moderator)),
+                );
+            }
+        }
+        Some(ChatRoomStateV2Delta {
+            room: Some(delta),
+            pinned_messages: None,
+        })
+    }
+}
+
+fn initial_state() -> (ChatRoomStateV2, ChatRoomParametersV1) {
+    let owner_key = &SIGNING_KEYS[0];
+    let parameters = ChatRoomParametersV1 {
+        owner: owner_key.verifying_key(),
+    };
+    // Bans beyond the limit are rejected rather than evicted, so allow one per step. Reactions
+    // and removals are capped low so that members go over them.
+    let configuration = Configuration {
+        owner_member_id: parameters.owner_id(),
+        max_user_bans: MAX_STEPS,
+        max_reactions_per_member: 2,
+        max_removals: 2,
+        ..Configuration::default()
+    };
+    let room = ChatRoomStateV1 {
+        configuration: AuthorizedConfigurationV1::new(configuration, owner_key),
+        ..ChatRoomStateV1::default()
+    };
+    (room.into(), parameters)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(256))]
+
+    #[test]
+    fn chat_room_state_converges(steps in numbered_steps()) {
+        REMOVED.with(|removed| removed.borrow_mut().clear());
+        ACTED.with(|acted| acted.borrow_mut().clear());
+        LIFTABLE.with(|liftable| liftable.borrow_mut().clear());
+        let (state, parameters) = initial_state();
+        let result = run_steps(&state, &parameters, PEERS, &steps);
+        prop_assert!(result.is_ok(), "{}", result.unwrap_err());
+    }
+}
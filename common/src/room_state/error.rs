@@ -0,0 +1,317 @@
+use crate::room_state::ban::BanId;
+use crate::room_state::member::{InvitationId, MemberId};
+use crate::room_state::message::MessageId;
+use crate::room_state::reaction::{ReactionId, MAX_REACTION_SIZE};
+use base64::{engine::general_purpose, Engine as _};
+use freenet_scaffold::util::VersionedHash;
+use freenet_scaffold::ComposableError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Marks the encoded error within a contract error's reason, see [`RoomStateError::to_reason`]
+const REASON_TAG: &str = "room-state-error:";
+
+/// Why a room state or delta was rejected
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum RoomStateError {
+    /// A signature doesn't verify against the key of whoever should have made it
+    InvalidSignature {
+        field: StateField,
+        id: VersionedHash,
+    },
+    /// Something was signed by a member who isn't in the room
+    UnknownAuthor { field: StateField, author: MemberId },
+    /// A field holds more entries than the room configuration allows
+    LimitExceeded {
+        field: StateField,
+        count: usize,
+        max: usize,
+    },
+    /// A member's invite chain doesn't lead back to the owner
+    InviteChainBroken {
+        member: MemberId,
+        reason: InviteChainError,
+    },
+    /// Something was signed by a member who isn't allowed to make that change
+    Unauthorized {
+        field: StateField,
+        id: VersionedHash,
+    },
+    /// A reply that is older than the message it replies to
+    InvalidReply {
+        message: MessageId,
+        in_reply_to: MessageId,
+    },
+    /// A room secret wrapped for an epoch the owner never recorded
+    UnknownSecretVersion { secret_version: u32 },
+    /// A wrapped secret for an epoch the room doesn't keep, eg. one that lost to another epoch of
+    /// the same version
+    UnknownSecretEpoch { id: VersionedHash },
+    /// Plaintext content in a private room
+    UnencryptedContent {
+        field: StateField,
+        id: VersionedHash,
+    },
+    /// Content encrypted with a room secret that a member banned before it was posted holds
+    StaleSecretVersion {
+        field: StateField,
+        id: VersionedHash,
+        secret_version: u32,
+        min_secret_version: u32,
+    },
+    /// A member redeemed an invitation after it expired
+    InvitationExpired { invitation: InvitationId },
+    /// A ghost key member whose certificate wasn't issued by one of the room's trusted issuers
+    UntrustedGhostKeyIssuer { member: MemberId },
+    /// A reaction that is empty or longer than `MAX_REACTION_SIZE`
+    InvalidReaction { id: ReactionId },
+    /// A message action refers to a message that isn't in the room
+    UnknownMessage { id: MessageId },
+    /// A ban signed by a member who didn't invite the banned member, directly or indirectly
+    UnauthorizedBan { ban: BanId },
+    /// A ban that expires before it was made
+    InvalidBanExpiry { ban: BanId },
+    /// The same entry was added twice
+    Duplicate {
+        field: StateField,
+        id: VersionedHash,
+    },
+    /// An entry refers to an id computed with the legacy hash, the state must be migrated
+    LegacyId {
+        field: StateField,
+        id: VersionedHash,
+    },
+    /// The owner is in the members list, they are only ever in the parameters
+    OwnerInMembers,
+    /// A configuration that isn't newer than the current one
+    StaleConfiguration { current: u32, received: u32 },
+    /// A configuration with a different owner
+    OwnerChanged,
+    /// A configuration with a limit of zero
+    InvalidConfiguration,
+    /// Rejected by one of the scaffold's collections or generated implementations
+    Composable(ComposableError),
+}
+
+/// The part of the room state an error refers to
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateField {
+    Configuration,
+    Rename,
+    Ban,
+    Unban,
+    Member,
+    MemberInfo,
+    Message,
+    MessageAction,
+    Reaction,
+    Invitation,
+    GhostKey,
+    Removal,
+    Secret,
+    SecretEpoch,
+    Upgrade,
+    PinnedMessages,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InviteChainError {
+    SelfInvitation,
+    Circular,
+    InviterNotFound { inviter: MemberId },
+}
+
+impl RoomStateError {
+    /// Describes the error for a `ContractError`, ending with an encoding of the error itself that
+    /// [`RoomStateError::from_reason`] recovers so that clients can tell why an update failed.
+    pub fn to_reason(&self) -> String {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(self, &mut bytes).expect("Serialization should not fail");
+        format!(
+            "{} [{}{}]",
+            self,
+            REASON_TAG,
+            general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+        )
+    }
+
+    /// Finds the error encoded by [`RoomStateError::to_reason`] in a message that contains it
+    pub fn from_reason(reason: &str) -> Option<Self> {
+        let start = reason.rfind(REASON_TAG)? + REASON_TAG.len();
+        let encoded = reason[start..].split(']').next()?;
+        let bytes = general_purpose::URL_SAFE_NO_PAD.decode(encoded).ok()?;
+        ciborium::de::from_reader(bytes.as_slice()).ok()
+    }
+}
+
+impl From<ComposableError> for RoomStateError {
+    fn from(error: ComposableError) -> Self {
+        RoomStateError::Composable(error)
+    }
+}
+
+impl std::error::Error for RoomStateError {}
+
+impl fmt::Display for RoomStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoomStateError::InvalidSignature { field, id } => {
+                write!(f, "Invalid signature on {} {:?}", field, id)
+            }
+            RoomStateError::UnknownAuthor { field, author } => {
+                write!(f, "The author {} of a {} isn't a member", author, field)
+            }
+            RoomStateError::LimitExceeded { field, count, max } => write!(
+                f,
+                "Number of {} entries ({}) exceeds the maximum allowed ({})",
+                field, count, max
+            ),
+            RoomStateError::InviteChainBroken { member, reason } => {
+                write!(f, "Invalid invite chain for member {}: {}", member, reason)
+            }
+            RoomStateError::Unauthorized { field, id } => {
+                write!(
+                    f,
+                    "The author of {} {:?} isn't allowed to make it",
+                    field, id
+                )
+            }
+            RoomStateError::InvalidReply {
+                message,
+                in_reply_to,
+            } => write!(
+                f,
+                "Message {} replies to the later message {}",
+                message, in_reply_to
+            ),
+            RoomStateError::UnknownSecretVersion { secret_version } => {
+                write!(f, "Room secret version {} has no epoch", secret_version)
+            }
+            RoomStateError::UnknownSecretEpoch { id } => {
+                write!(
+                    f,
+                    "Room secret {:?} was wrapped for an epoch the room doesn't keep",
+                    id
+                )
+            }
+            RoomStateError::UnencryptedContent { field, id } => {
+                write!(
+                    f,
+                    "The {} {:?} isn't encrypted in a private room",
+                    field, id
+                )
+            }
+            RoomStateError::StaleSecretVersion {
+                field,
+                id,
+                secret_version,
+                min_secret_version,
+            } => write!(
+                f,
+                "The {} {:?} is encrypted with room secret version {}, a ban before it requires {}",
+                field, id, secret_version, min_secret_version
+            ),
+            RoomStateError::InvitationExpired { invitation } => {
+                write!(f, "Invitation {:?} has expired", invitation.0)
+            }
+            RoomStateError::UntrustedGhostKeyIssuer { member } => write!(
+                f,
+                "The ghost key of member {} wasn't issued by a trusted issuer",
+                member
+            ),
+            RoomStateError::InvalidReaction { id } => write!(
+                f,
+                "Reaction {:?} must be between 1 and {} bytes",
+                id.0, MAX_REACTION_SIZE
+            ),
+            RoomStateError::UnknownMessage { id } => {
+                write!(f, "Message {} not found", id)
+            }
+            RoomStateError::UnauthorizedBan { ban } => write!(
+                f,
+                "Banner is not in the invite chain of the banned member, ban {:?}",
+                ban.0
+            ),
+            RoomStateError::InvalidBanExpiry { ban } => {
+                write!(f, "Ban {:?} expires before it was made", ban.0)
+            }
+            RoomStateError::Duplicate { field, id } => {
+                write!(f, "Duplicate {} {:?}", field, id)
+            }
+            RoomStateError::LegacyId { field, id } => write!(
+                f,
+                "The {} {:?} references a legacy id, the state must be migrated",
+                field, id
+            ),
+            RoomStateError::OwnerInMembers => {
+                write!(f, "Owner should not be included in the members list")
+            }
+            RoomStateError::StaleConfiguration { current, received } => write!(
+                f,
+                "New configuration version {} must be greater than the current version {}",
+                received, current
+            ),
+            RoomStateError::OwnerChanged => write!(f, "Cannot change the owner_member_id"),
+            RoomStateError::InvalidConfiguration => write!(f, "Invalid configuration values"),
+            RoomStateError::Composable(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl fmt::Display for StateField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            StateField::Configuration => "configuration",
+            StateField::Rename => "room rename",
+            StateField::Ban => "ban",
+            StateField::Unban => "unban",
+            StateField::Member => "member",
+            StateField::MemberInfo => "member info",
+            StateField::Message => "message",
+            StateField::MessageAction => "message action",
+            StateField::Reaction => "reaction",
+            StateField::Invitation => "invitation",
+            StateField::GhostKey => "ghost key",
+            StateField::Removal => "removal",
+            StateField::Secret => "room secret",
+            StateField::SecretEpoch => "room secret epoch",
+            StateField::Upgrade => "upgrade",
+            StateField::PinnedMessages => "pinned messages",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for InviteChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InviteChainError::SelfInvitation => write!(f, "Self-invitation detected"),
+            InviteChainError::Circular => write!(f, "Circular invite chain detected"),
+            InviteChainError::InviterNotFound { inviter } => {
+                write!(f, "Inviter {} not found", inviter)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    #[test]
+    fn test_reason_round_trip() {
+        let member = MemberId::from(SigningKey::from_bytes(&[1; 32]).verifying_key());
+        let error = RoomStateError::UnknownAuthor {
+            field: StateField::Message,
+            author: member,
+        };
+        let reason = error.to_reason();
+        assert!(reason.starts_with(&error.to_string()));
+
+        // Contract errors wrap the reason in their own message
+        let wrapped = format!("invalid contract update, reason: {}", reason);
+        assert_eq!(RoomStateError::from_reason(&wrapped), Some(error));
+        assert_eq!(RoomStateError::from_reason("invalid contract update"), None);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::room_state::ban::{AuthorizedUserBan, BansV1, UserBan};
+use crate::room_state::configuration::AuthorizedConfigurationV1;
+use crate::room_state::member::{AuthorizedMember, Member, MemberId, MembersV1};
+use crate::room_state::member_info::{AuthorizedMemberInfo, MemberInfo, MemberInfoV1};
+use crate::room_state::message::{AuthorizedMessageV1, MessageV1, MessagesV1};
+use crate::room_state::ChatRoomParametersV1;
+use crate::ChatRoomStateV1;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use freenet_scaffold::util::{fast_hash, VersionedHash};
+use std::collections::HashMap;
+
+/*
+ States serialized before MemberId, BanId and MessageId switched to blake3 contain ids computed with
+ the 64-bit `fast_hash`. They still deserialize, but the ids come back as `VersionedHash::Legacy`,
+ which never equals a freshly computed id, so such states fail verification until the owner
+ migrates them.
+*/
+
+/// Returns true if any member id referenced by the state was computed with the legacy hash
+pub fn contains_legacy_ids(state: &ChatRoomStateV1) -> bool {
+    let members = state.members.members.iter().flat_map(|m| {
+        [m.member.owner_member_id, m.member.invited_by]
+    });
+    let bans = state.bans.0.iter().flat_map(|b| {
+        [b.banned_by, b.ban.banned_user, b.ban.owner_member_id]
+    });
+    let messages = state.recent_messages.messages.entries.iter().flat_map(|m| {
+        [m.message.author(), m.message.room_owner()]
+    });
+    let member_info = state.member_info.member_info.iter().map(|i| i.member_info.member_id);
+    std::iter::once(state.configuration.configuration.owner_member_id)
+        .chain(members)
+        .chain(bans)
+        .chain(messages)
+        .chain(member_info)
+        .any(|id| id.0.is_legacy())
+}
+
+/// Rebuilds a legacy state so that it verifies with blake3 ids, re-signing everything with the
+/// owner's key. The owner can only vouch for data it authored or has authority over, so:
+///
+/// - every member is re-invited directly by the owner, flattening the invite tree
+/// - bans are re-issued by the owner
+/// - member info and messages authored by anyone other than the owner are dropped, as are
+///   references to ids that can't be resolved unambiguously to a known key
+pub fn migrate_legacy_state(
+    legacy: &ChatRoomStateV1,
+    parameters: &ChatRoomParametersV1,
+    owner_sk: &SigningKey,
+) -> Result<ChatRoomStateV1, String> {
+    if owner_sk.verifying_key() != parameters.owner {
+        return Err("Only the room owner can migrate a legacy state".to_string());
+    }
+    let owner_id = parameters.owner_id();
+    let resolver = LegacyIdResolver::new(legacy, parameters);
+
+    let mut configuration = legacy.configuration.configuration.clone();
+    configuration.owner_member_id = owner_id;
+
+    let members = legacy
+        .members
+        .members
+        .iter()
+        .filter(|m| m.member.member_vk != parameters.owner)
+        .map(|m| {
+            let member = Member {
+                owner_member_id: owner_id,
+                invited_by: owner_id,
+                member_vk: m.member.member_vk,
+            };
+            AuthorizedMember::new(member, owner_sk)
+        })
+        .collect();
+
+    let bans = legacy
+        .bans
+        .0
+        .iter()
+        .filter_map(|b| {
+            let ban = UserBan {
+                owner_member_id: owner_id,
+                banned_at: b.ban.banned_at,
+                banned_user: resolver.resolve(b.ban.banned_user)?,
+                expires_at: b.ban.expires_at,
+            };
+            Some(AuthorizedUserBan::new(ban, owner_id, owner_sk))
+        })
+        .collect();
+
+    let member_info = legacy
+        .member_info
+        .member_info
+        .iter()
+        .filter(|i| resolver.resolve(i.member_info.member_id) == Some(owner_id))
+        .map(|i| {
+            let info = MemberInfo {
+                member_id: owner_id,
+                ..i.member_info.clone()
+            };
+            AuthorizedMemberInfo::new(info, owner_sk)
+        })
+        .collect();
+
+    let messages: Vec<AuthorizedMessageV1> = legacy
+        .recent_messages
+        .messages
+        .entries
+        .iter()
+        .filter(|m| resolver.resolve(m.message.author()) == Some(owner_id))
+        .map(|m| {
+            // Legacy states predate replies, so messages are all `MessageV1`
+            let message = MessageV1 {
+                room_owner: owner_id,
+                author: owner_id,
+                time: m.message.time(),
+                content: m.message.content().to_string(),
+            };
+            AuthorizedMessageV1::new(message, owner_sk)
+        })
+        .collect();
+
+    Ok(ChatRoomStateV1 {
+        configuration: AuthorizedConfigurationV1::new(configuration, owner_sk),
+        bans: BansV1(bans),
+        members: MembersV1 {
+            members,
+            ..Default::default()
+        },
+        // Legacy rooms are all public
+        secrets: Default::default(),
+        member_info: MemberInfoV1 { member_info },
+        recent_messages: MessagesV1 {
+            messages: messages.into(),
+            ..Default::default()
+        },
+        // Legacy states predate reactions
+        reactions: Default::default(),
+        upgrade: legacy.upgrade.clone(),
+    })
+}
+
+/// Maps legacy ids back to the current id of the owner or member key they were computed from
+struct LegacyIdResolver {
+    ids: HashMap<MemberId, Option<MemberId>>,
+}
+
+impl LegacyIdResolver {
+    fn new(state: &ChatRoomStateV1, parameters: &ChatRoomParametersV1) -> Self {
+        let mut ids: HashMap<MemberId, Option<MemberId>> = HashMap::new();
+        let keys = std::iter::once(&parameters.owner)
+            .chain(state.members.members.iter().map(|m| &m.member.member_vk));
+        for vk in keys {
+            let current_id = MemberId::from(vk);
+            ids.entry(legacy_member_id(vk))
+                // Two keys sharing a legacy id is exactly the collision we're migrating away
+                // from, so neither can be trusted
+                .and_modify(|existing| {
+                    if *existing != Some(current_id) {
+                        *existing = None
+                    }
+                })
+                .or_insert(Some(current_id));
+        }
+        Self { ids }
+    }
+
+    fn resolve(&self, id: MemberId) -> Option<MemberId> {
+        if id.0.is_legacy() {
+            self.ids.get(&id).copied().flatten()
+        } else {
+            Some(id)
+        }
+    }
+}
+
+fn legacy_member_id(vk: &VerifyingKey) -> MemberId {
+    MemberId(VersionedHash::Legacy(fast_hash(&vk.to_bytes())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::member::MemberAuthorization;
+    use crate::util::sign_struct;
+    use freenet_scaffold::ComposableState;
+    use rand::rngs::OsRng;
+    use std::time::SystemTime;
+
+    fn legacy_state(owner_sk: &SigningKey, member_sk: &SigningKey) -> ChatRoomStateV1 {
+        let owner_id = legacy_member_id(&owner_sk.verifying_key());
+        let member_id = legacy_member_id(&member_sk.verifying_key());
+        let mut state = ChatRoomStateV1::default();
+
+        let mut configuration = state.configuration.configuration.clone();
+        configuration.owner_member_id = owner_id;
+        state.configuration = AuthorizedConfigurationV1::new(configuration, owner_sk);
+
+        let member = Member {
+            owner_member_id: owner_id,
+            invited_by: owner_id,
+            member_vk: member_sk.verifying_key(),
+        };
+        state.members.members.push(AuthorizedMember {
+            authorization: MemberAuthorization::Invite {
+                signature: sign_struct(&member, owner_sk),
+                joined_at: None,
+            },
+            member,
+        });
+
+        for (author, sk) in [(owner_id, owner_sk), (member_id, member_sk)] {
+            let message = MessageV1 {
+                room_owner: owner_id,
+                author,
+                time: SystemTime::now(),
+                content: "Hello".to_string(),
+            };
+            state
+                .recent_messages
+                .messages
+                .entries
+                .push(AuthorizedMessageV1::new(message, sk));
+        }
+        state
+    }
+
+    #[test]
+    fn test_legacy_state_round_trips_and_is_detected() {
+        let owner_sk = SigningKey::generate(&mut OsRng);
+        let member_sk = SigningKey::generate(&mut OsRng);
+        let state = legacy_state(&owner_sk, &member_sk);
+
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&state, &mut bytes).unwrap();
+        let decoded: ChatRoomStateV1 = ciborium::de::from_reader(bytes.as_slice()).unwrap();
+
+        assert_eq!(decoded, state);
+        assert!(contains_legacy_ids(&decoded));
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_sk.verifying_key(),
+        };
+        assert!(decoded.verify(&decoded, &parameters).is_err());
+    }
+
+    #[test]
+    fn test_migrate_legacy_state() {
+        let owner_sk = SigningKey::generate(&mut OsRng);
+        let member_sk = SigningKey::generate(&mut OsRng);
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_sk.verifying_key(),
+        };
+        let legacy = legacy_state(&owner_sk, &member_sk);
+
+        let migrated = migrate_legacy_state(&legacy, &parameters, &owner_sk).unwrap();
+
+        assert!(!contains_legacy_ids(&migrated));
+        assert!(
+            migrated.verify(&migrated, &parameters).is_ok(),
+            "{:?}",
+            migrated.verify(&migrated, &parameters)
+        );
+        assert_eq!(migrated.members.members.len(), 1);
+        // Only the owner's message can be re-signed
+        assert_eq!(migrated.recent_messages.messages.entries.len(), 1);
+        assert_eq!(
+            migrated.recent_messages.messages.entries[0]
+                .message
+                .author(),
+            parameters.owner_id()
+        );
+    }
+
+    #[test]
+    fn test_migrate_requires_owner_key() {
+        let owner_sk = SigningKey::generate(&mut OsRng);
+        let member_sk = SigningKey::generate(&mut OsRng);
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_sk.verifying_key(),
+        };
+        let legacy = legacy_state(&owner_sk, &member_sk);
+
+        assert!(migrate_legacy_state(&legacy, &parameters, &member_sk).is_err());
+    }
+}
@@ -1,15 +1,36 @@
 use crate::room_state::ban::BansV1;
+use crate::room_state::configuration::Configuration;
+use crate::room_state::error::{InviteChainError, RoomStateError, StateField};
 use crate::room_state::ChatRoomParametersV1;
-use crate::util::{sign_struct, truncated_base32, verify_struct};
+use crate::util::truncated_base32;
 use crate::ChatRoomStateV1;
-use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
-use freenet_scaffold::util::{fast_hash, FastHash};
+use ed25519_dalek::VerifyingKey;
+use freenet_scaffold::id_set::IdSet;
+use freenet_scaffold::util::{blake3_hash, VersionedHash};
 use freenet_scaffold::ComposableState;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fmt::Display;
-use std::hash::{Hash, Hasher};
+use std::hash::Hash;
+use std::time::SystemTime;
+
+mod authorization;
+mod banned;
+mod ghost_key;
+mod inactive;
+mod index;
+mod invitation;
+mod invite_chain;
+mod limits;
+mod removal;
+
+pub use authorization::{AuthorizedMember, MemberAuthorization};
+pub use ghost_key::{BlindSignature, BlindedGhostKey, GhostKeyCertificate, GhostKeyRequest};
+pub use index::MemberIndex;
+use index::MembershipSet;
+pub use invitation::{AuthorizedInvitation, Invitation, InvitationId, InvitationToken, Redemption};
+pub use removal::{AuthorizedRemoval, Removal, RemovalId};
 
 /*
  Note that the owner should not be in the members list but for most purposes (eg. sending messages)
@@ -20,79 +41,170 @@ use std::hash::{Hash, Hasher};
 #[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
 pub struct MembersV1 {
     pub members: Vec<AuthorizedMember>,
+    /// Everyone who joined by redeeming an invitation, kept after they leave or are banned so that
+    /// invitations can't be used more than `max_uses` times. Only the latest `max_redemptions` of
+    /// members who are gone are kept, see `Configuration`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redemptions: Vec<AuthorizedMember>,
+    /// Members who left or were kicked, the latest removal of each member by each remover. Only the
+    /// latest `max_removals` are kept, see `forget_removals`, along with as many of the memberships
+    /// they ended, see `Configuration`. Once the removals that ended a membership are forgotten the
+    /// member is back if it still stands, as a peer that never saw them would bring it back anyway.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removals: Vec<AuthorizedRemoval>,
+    /// Memberships that aren't in effect, eg. one a member was invited again over, one whose
+    /// inviter is gone or one that was removed. Every peer picks the latest membership that stands
+    /// from the same ones whatever order it saw them in.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inactive: Vec<AuthorizedMember>,
 }
 
 impl Default for MembersV1 {
     fn default() -> Self {
         MembersV1 {
             members: Vec::new(),
+            redemptions: Vec::new(),
+            removals: Vec::new(),
+            inactive: Vec::new(),
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct MembersSummary {
+    /// The memberships the peer has by when they started, a member invited again after being
+    /// removed can have more than one
+    pub members: IdSet<(MemberId, Option<SystemTime>)>,
+    #[serde(default)]
+    pub removals: IdSet<RemovalId>,
+}
+
 impl ComposableState for MembersV1 {
     type ParentState = ChatRoomStateV1;
-    type Summary = HashSet<MemberId>;
+    type Summary = MembersSummary;
     type Delta = MembersDelta;
     type Parameters = ChatRoomParametersV1;
+    type Error = RoomStateError;
 
     fn verify(
         &self,
         parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
-    ) -> Result<(), String> {
+    ) -> Result<(), Self::Error> {
+        let max_removals = parent_state.configuration.configuration.max_removals;
+        if self.removals.len() > max_removals {
+            return Err(RoomStateError::LimitExceeded {
+                field: StateField::Removal,
+                count: self.removals.len(),
+                max: max_removals,
+            });
+        }
+        for removal in &self.removals {
+            Self::verify_removal(removal, parameters)?;
+        }
+
+        // Inactive memberships have no invite chain to check, only their signature
+        let index = self.index(parameters);
+        for member in &self.inactive {
+            Self::verify_member_invite(member, &index, parent_state)?;
+        }
+
         if self.members.is_empty() {
             return Ok(());
         }
 
         if !self.members.is_empty() && self.members.len() > parent_state.configuration.configuration.max_members {
-            return Err(format!(
-                "Too many members: {} > {}",
-                self.members.len(),
-                parent_state.configuration.configuration.max_members
-            ));
+            return Err(RoomStateError::LimitExceeded {
+                field: StateField::Member,
+                count: self.members.len(),
+                max: parent_state.configuration.configuration.max_members,
+            });
         }
 
         let owner_id = parameters.owner_id();
+        let redemptions = MembershipSet::new(&self.redemptions);
 
-        // Build a map of member IDs to their invited_by IDs to check for loops
-        let mut invite_map: HashMap<MemberId, MemberId> = HashMap::new();
+        let mut seen = HashSet::new();
         for member in &self.members {
-            if member.member.id() == owner_id {
-                return Err("Owner should not be included in the members list".to_string());
+            if member.member.id() == owner_id || member.member.member_vk == parameters.owner {
+                return Err(RoomStateError::OwnerInMembers);
             }
-            if member.member.member_vk == parameters.owner {
-                return Err("Member cannot have the same verifying key as the room owner".to_string());
+
+            if member.member.invited_by.0.is_legacy() {
+                return Err(RoomStateError::LegacyId {
+                    field: StateField::Member,
+                    id: member.member.id().0,
+                });
             }
 
             // Check for self-invites and invite loops
             if member.member.invited_by == member.member.id() {
-                return Err("Self-invitation detected".to_string());
+                return Err(RoomStateError::InviteChainBroken {
+                    member: member.member.id(),
+                    reason: InviteChainError::SelfInvitation,
+                });
             }
 
-            let mut current_id = member.member.id();
-            let mut visited = HashSet::new();
-            visited.insert(current_id);
+            index.invite_chain_len(member)?;
+            if !seen.insert(member.member.id()) {
+                return Err(RoomStateError::Duplicate {
+                    field: StateField::Member,
+                    id: member.member.id().0,
+                });
+            }
+            Self::verify_ghost_key_issuer(member, parent_state)?;
 
-            while current_id != owner_id {
-                let invited_by = member.member.invited_by;
-                if !visited.insert(invited_by) {
-                    return Err("Circular invite chain detected".to_string());
-                }
-                if invited_by != owner_id && !self.members.iter().any(|m| m.member.id() == invited_by) {
-                    return Err(format!("Inviter {} not found for member {}", invited_by, current_id));
-                }
-                if invited_by == owner_id {
-                    break;
-                }
-                current_id = self.members.iter()
-                    .find(|m| m.member.id() == invited_by)
-                    .map(|m| m.member.invited_by)
-                    .unwrap_or(invited_by);
+            if member.redemption().is_some() && !redemptions.contains(member) {
+                return Err(RoomStateError::InvalidSignature {
+                    field: StateField::Invitation,
+                    id: member.member.id().0,
+                });
             }
+        }
 
-            invite_map.insert(member.member.id(), member.member.invited_by);
-            self.get_invite_chain(member, parameters)?;
+        let max_redemptions = parent_state.configuration.configuration.max_redemptions;
+        let present = MembershipSet::new(&self.members);
+        let departed = self
+            .redemptions
+            .iter()
+            .filter(|m| !present.contains(m))
+            .count();
+        if departed > max_redemptions {
+            return Err(RoomStateError::LimitExceeded {
+                field: StateField::Invitation,
+                count: departed,
+                max: max_redemptions,
+            });
+        }
+
+        let mut uses: HashMap<InvitationId, u32> = HashMap::new();
+        for redeemed in &self.redemptions {
+            let Some(redemption) = redeemed.redemption() else {
+                return Err(RoomStateError::InvalidSignature {
+                    field: StateField::Invitation,
+                    id: redeemed.member.id().0,
+                });
+            };
+            let Some(inviter_vk) = index.inviter_vk(redeemed) else {
+                return Err(RoomStateError::InviteChainBroken {
+                    member: redeemed.member.id(),
+                    reason: InviteChainError::InviterNotFound {
+                        inviter: redeemed.member.invited_by,
+                    },
+                });
+            };
+            redeemed.verify_signature(inviter_vk)?;
+            let count = uses.entry(redemption.invitation.id()).or_default();
+            *count += 1;
+            if let Some(max_uses) = redemption.invitation.invitation.max_uses {
+                if *count > max_uses {
+                    return Err(RoomStateError::LimitExceeded {
+                        field: StateField::Invitation,
+                        count: *count as usize,
+                        max: max_uses as usize,
+                    });
+                }
+            }
         }
         Ok(())
     }
@@ -101,25 +213,63 @@ impl ComposableState for MembersV1 {
         _parent_state: &Self::ParentState,
         _parameters: &Self::Parameters,
     ) -> Self::Summary {
-        self.members.iter().map(|m| m.member.id()).collect()
+        MembersSummary {
+            members: self
+                .members
+                .iter()
+                .chain(&self.redemptions)
+                .chain(&self.inactive)
+                .map(|m| (m.member.id(), m.joined_at()))
+                .collect(),
+            removals: self.removals.iter().map(|r| r.id()).collect(),
+        }
     }
 
     fn delta(
         &self,
         _parent_state: &Self::ParentState,
-        _parameters: &Self::Parameters,
+        parameters: &Self::Parameters,
         old_state_summary: &Self::Summary,
     ) -> Option<Self::Delta> {
-        let added = self
+        let mut seen = HashSet::new();
+        let mut added = self
             .members
             .iter()
-            .filter(|m| !old_state_summary.contains(&m.member.id()))
+            .chain(&self.redemptions)
+            .chain(&self.inactive)
+            .filter(|m| {
+                let membership = (m.member.id(), m.joined_at());
+                !old_state_summary.members.contains(&membership) && seen.insert(membership)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        // A compacted summary can wrongly claim the peer has an inviter, the peer couldn't verify
+        // the members they invited without them so their invite chains go along
+        if old_state_summary.members.listed().is_none() {
+            let index = self.index(parameters);
+            let mut next = 0;
+            while let Some(member) = added.get(next) {
+                if let Some(inviter) = member.inviter() {
+                    let chain = index
+                        .memberships(inviter)
+                        .filter(|m| seen.insert((m.member.id(), m.joined_at())))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    added.extend(chain);
+                }
+                next += 1;
+            }
+        }
+        let removed = self
+            .removals
+            .iter()
+            .filter(|r| !old_state_summary.removals.contains(&r.id()))
             .cloned()
             .collect::<Vec<_>>();
-        if added.is_empty() {
+        if added.is_empty() && removed.is_empty() {
             None
         } else {
-            Some(MembersDelta { added })
+            Some(MembersDelta { added, removed })
         }
     }
 
@@ -128,259 +278,213 @@ impl ComposableState for MembersV1 {
         parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
         delta: &Option<Self::Delta>,
-    ) -> Result<(), String> {
+    ) -> Result<(), Self::Error> {
         let max_members = parent_state.configuration.configuration.max_members;
 
+        let existing: HashSet<MemberId> = self.members.iter().map(|m| m.member.id()).collect();
+        let mut memberships: Vec<AuthorizedMember> = self
+            .members
+            .iter()
+            .chain(&self.redemptions)
+            .chain(&self.inactive)
+            .cloned()
+            .collect();
         if let Some(delta) = delta {
-            // Verify that all new members have valid invites
-            for member in &delta.added {
-                self.verify_member_invite(member, parent_state, parameters)?;
+            for removal in &delta.removed {
+                Self::verify_removal(removal, parameters)?;
             }
 
-            // Add new members, but don't exceed max_members
+            // Verify that all new memberships were signed by their inviter, who may be in the same
+            // delta. Whether their invite chain still stands is decided below.
+            let index = MemberIndex::new(
+                &[],
+                self.memberships().chain(&delta.added),
+                &self.removals,
+                parameters,
+            );
             for member in &delta.added {
-                if self.members.len() < max_members {
-                    self.members.push(member.clone());
-                } else {
-                    break;
-                }
+                Self::verify_member_invite(member, &index, parent_state)?;
             }
+            memberships.extend(delta.added.iter().cloned());
+
+            // Removals by someone who couldn't remove the member aren't kept, they'd take the
+            // place of those that count
+            let index = MemberIndex::new(&[], &memberships, &[], parameters);
+            let authorized: Vec<AuthorizedRemoval> = delta
+                .removed
+                .iter()
+                .filter(|r| Self::is_authorized_removal(r, &index, parameters))
+                .cloned()
+                .collect();
+            self.add_removals(&authorized);
+        }
+
+        let room_time = self.room_time(parent_state, parameters);
+        self.forget_removals(
+            parent_state.configuration.configuration.max_removals,
+            room_time,
+            parameters,
+        );
+        self.refuse_late_redemptions(&mut memberships, room_time);
+        memberships.sort_by_key(|m| (m.member.id(), m.joined_at(), m.signature().to_bytes()));
+        memberships.dedup();
+
+        // Remember who redeemed invitations even once they're gone
+        let mut redeemed: HashSet<MemberId> =
+            self.redemptions.iter().map(|m| m.member.id()).collect();
+        let new_redemptions: Vec<AuthorizedMember> = memberships
+            .iter()
+            .filter(|m| m.redemption().is_some() && redeemed.insert(m.member.id()))
+            .cloned()
+            .collect();
+        self.redemptions.extend(new_redemptions);
+
+        // A member can be invited again after being removed, keep the membership that still stands.
+        // Whether a membership was banned depends on all of them.
+        self.inactive = memberships.clone();
+        self.pick_memberships(&memberships, parent_state, parameters);
+
+        // Members already in the room keep their place, new members are added inviters first
+        // while there's room
+        if self.members.len() > max_members {
+            let index = self.index(parameters);
+            let chain_lengths: HashMap<MemberId, usize> = self
+                .members
+                .iter()
+                .map(|m| {
+                    let length = index.invite_chain_len(m).unwrap_or(usize::MAX);
+                    (m.member.id(), length)
+                })
+                .collect();
+            let (mut members, mut added): (Vec<_>, Vec<_>) = std::mem::take(&mut self.members)
+                .into_iter()
+                .partition(|m| existing.contains(&m.member.id()));
+            let delta_order: HashMap<MemberId, usize> = delta
+                .iter()
+                .flat_map(|delta| delta.added.iter().enumerate())
+                .map(|(i, m)| (m.member.id(), i))
+                .collect();
+            added.sort_by_key(|m| {
+                let id = m.member.id();
+                (chain_lengths[&id], delta_order.get(&id).copied(), id)
+            });
+            added.truncate(max_members.saturating_sub(members.len()));
+            members.extend(added);
+            self.members = members;
         }
 
         // Always check for and remove banned members
-        self.remove_banned_members(&parent_state.bans, parameters);
+        self.remove_banned_members(
+            &parent_state.bans,
+            &parent_state.configuration.configuration,
+            parameters,
+        );
+
+        // Invitations redeemed more often than allowed keep their earliest redemptions
+        self.enforce_invitation_limits(parameters);
 
         // Always enforce max members limit
         self.remove_excess_members(parameters, max_members);
 
-        Ok(())
-    }
-}
+        self.forget_redemptions(parent_state.configuration.configuration.max_redemptions);
+
+        self.keep_inactive(memberships, parent_state, parameters);
+
+        // Keep a canonical order so that peers which merged the same members in a different order
+        // end up with identical states
+        self.members.sort_by_key(|m| m.member.id());
+        self.redemptions.sort_by_key(|m| m.member.id());
+        self.removals.sort_by_key(|r| r.id());
+        self.inactive
+            .sort_by_key(|m| (m.member.id(), m.joined_at()));
 
-impl MembersV1 {
-    fn verify_member_invite(
-        &self,
-        member: &AuthorizedMember,
-        _parent_state: &ChatRoomStateV1,
-        parameters: &ChatRoomParametersV1,
-    ) -> Result<(), String> {
-        if member.member.invited_by == parameters.owner_id() {
-            // Member was invited by the owner, verify signature against owner's key
-            member
-                .verify_signature(&parameters.owner)
-                .map_err(|e| format!("Invalid signature for member invited by owner: {}", e))?;
-        } else {
-            // Member was invited by another member, verify the invite chain
-            self.get_invite_chain(member, parameters)?;
-        }
         Ok(())
     }
 }
 
 impl MembersV1 {
-    /// Returns true if the given member_id invited the target_id, properly handling both
-    /// regular members and the room owner. Use this instead of checking the members list directly.
-    pub fn is_inviter_of(&self, member_id: MemberId, target_id: MemberId, params: &ChatRoomParametersV1) -> bool {
-        if member_id == params.owner_id() {
-            // Check if target was invited by owner
-            self.members.iter()
-                .find(|m| m.member.id() == target_id)
-                .map(|m| m.member.invited_by == member_id)
-                .unwrap_or(false)
-        } else {
-            // Check regular members
-            self.members.iter()
-                .find(|m| m.member.id() == target_id)
-                .map(|m| m.member.invited_by == member_id)
-                .unwrap_or(false)
-        }
-    }
-
     /// Note: doesn't include owner
     pub fn members_by_member_id(&self) -> HashMap<MemberId, &AuthorizedMember> {
         self.members.iter().map(|m| (m.member.id(), m)).collect()
     }
 
-    /// Checks if there are any banned members or members downstream of banned members in the invite chain
-    pub fn has_banned_members(&self, bans_v1: &BansV1, parameters: &ChatRoomParametersV1) -> bool {
-        self.check_banned_members(bans_v1, parameters).is_some()
-    }
-
-    /// Removes banned members or members downstream of banned members in the invite chain
-    fn remove_banned_members(&mut self, bans_v1: &BansV1, _parameters: &ChatRoomParametersV1) {
-        let mut banned_ids = HashSet::new();
-        for ban in &bans_v1.0 {
-            banned_ids.insert(ban.ban.banned_user);
-            banned_ids.extend(self.get_downstream_members(ban.ban.banned_user));
-        }
+    /// Lookups by member id for a pass over the state, each method here that looks a member up
+    /// goes through every membership
+    pub fn index<'a>(&'a self, parameters: &'a ChatRoomParametersV1) -> MemberIndex<'a> {
+        MemberIndex::new(
+            &self.members,
+            self.memberships(),
+            &self.removals,
+            parameters,
+        )
+    }
+
+    /// The key of `author` if they're the owner or had a membership at `time` that wasn't banned.
+    /// Members who are out of the room because their inviter was removed keep what they posted,
+    /// as they'll be back if their inviter is invited again. Content from after a member was removed, or from
+    /// before they joined, doesn't count.
+    pub fn author_vk<'a>(
+        &'a self,
+        author: MemberId,
+        time: SystemTime,
+        bans: &BansV1,
+        configuration: &Configuration,
+        parameters: &'a ChatRoomParametersV1,
+    ) -> Option<&'a VerifyingKey> {
+        self.index(parameters)
+            .author_vk(author, time, bans, configuration)
+    }
+
+    /// The key of `member_id` if they're the owner or have a membership that hasn't been removed
+    /// or banned, even if they're out of the room because their inviter was removed
+    pub fn member_key<'a>(
+        &'a self,
+        member_id: MemberId,
+        bans: &BansV1,
+        configuration: &Configuration,
+        parameters: &'a ChatRoomParametersV1,
+    ) -> Option<&'a VerifyingKey> {
+        self.index(parameters)
+            .member_key(member_id, bans, configuration)
+    }
+
+    /// The key of anyone whose membership is kept, whether it's in effect or not. Unlike removals,
+    /// memberships can't be made up by someone who was never invited.
+    pub fn known_member_vk(&self, member_id: MemberId) -> Option<&VerifyingKey> {
+        self.memberships()
+            .find(|m| m.member.id() == member_id)
+            .map(|m| &m.member.member_vk)
+    }
+
+    /// Every membership that's kept, in effect or not, a redeemed one can be listed twice
+    pub fn memberships(&self) -> impl Iterator<Item = &AuthorizedMember> {
         self.members
-            .retain(|m| !banned_ids.contains(&m.member.id()));
-    }
-
-    /// Helper function to get all downstream members of a given member
-    fn get_downstream_members(&self, member_id: MemberId) -> HashSet<MemberId> {
-        let mut downstream = HashSet::new();
-        let mut to_check = vec![member_id];
-        while let Some(current) = to_check.pop() {
-            for member in &self.members {
-                if member.member.invited_by == current {
-                    downstream.insert(member.member.id());
-                    to_check.push(member.member.id());
-                }
-            }
-        }
-        downstream
-    }
-
-    /// If the number of members exceeds the specified limit, remove the members with the longest invite chains
-    /// until the limit is satisfied
-    fn remove_excess_members(&mut self, parameters: &ChatRoomParametersV1, max_members: usize) {
-        while self.members.len() > max_members {
-            let member_to_remove = self
-                .members
-                .iter()
-                .max_by_key(|m| self.get_invite_chain(m, parameters).unwrap().len())
-                .unwrap()
-                .member
-                .id();
-            self.members.retain(|m| m.member.id() != member_to_remove);
-        }
-    }
-
-    /// Checks for banned members and returns a set of member IDs to be removed if any are found
-    fn check_banned_members(
-        &self,
-        bans_v1: &BansV1,
-        parameters: &ChatRoomParametersV1,
-    ) -> Option<HashSet<MemberId>> {
-        let mut banned_ids = HashSet::new();
-        for m in &self.members {
-            if let Ok(invite_chain) = self.get_invite_chain(m, parameters) {
-                if invite_chain
-                    .iter()
-                    .any(|m| bans_v1.0.iter().any(|b| b.ban.banned_user == m.member.id()))
-                {
-                    banned_ids.insert(m.member.id());
-                }
-            }
-        }
-        if banned_ids.is_empty() {
-            None
-        } else {
-            Some(banned_ids)
-        }
-    }
-
-    pub fn get_invite_chain(
-        &self,
-        member: &AuthorizedMember,
-        parameters: &ChatRoomParametersV1,
-    ) -> Result<Vec<AuthorizedMember>, String> {
-        let mut invite_chain = Vec::new();
-        let mut current_member = member;
-        let owner_id = parameters.owner_id();
-        let mut visited_members = HashSet::new();
-
-        loop {
-            if !visited_members.insert(current_member.member.id()) {
-                return Err(format!(
-                    "Circular invite chain detected for member {:?}",
-                    current_member.member.id()
-                ));
-            }
-
-            if current_member.member.invited_by == current_member.member.id() {
-                return Err(format!(
-                    "Self-invitation detected for member {:?}",
-                    current_member.member.id()
-                ));
-            }
-
-            if current_member.member.invited_by == owner_id {
-                // Member was directly invited by the owner, so we need to verify their signature against the owner's key
-                current_member
-                    .verify_signature(&parameters.owner)
-                    .map_err(|e| {
-                        format!(
-                            "Invalid signature for member {:?} invited by owner: {}",
-                            current_member.member.id(),
-                            e
-                        )
-                    })?;
-                break;
-            } else {
-                let inviter = self
-                    .members
-                    .iter()
-                    .find(|m| m.member.id() == current_member.member.invited_by)
-                    .ok_or_else(|| {
-                        format!(
-                            "Inviter {:?} not found for member {:?}",
-                            current_member.member.invited_by,
-                            current_member.member.id()
-                        )
-                    })?;
-
-                current_member
-                    .verify_signature(&inviter.member.member_vk)
-                    .map_err(|e| {
-                        format!(
-                            "Invalid signature for member {:?}: {}",
-                            current_member.member.id(),
-                            e
-                        )
-                    })?;
-
-                invite_chain.push(inviter.clone());
-                current_member = inviter;
-            }
-        }
-
-        Ok(invite_chain)
+            .iter()
+            .chain(&self.inactive)
+            .chain(&self.redemptions)
     }
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
 pub struct MembersDelta {
     added: Vec<AuthorizedMember>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    removed: Vec<AuthorizedRemoval>,
 }
 
 impl MembersDelta {
     pub fn new(added: Vec<AuthorizedMember>) -> Self {
-        MembersDelta { added }
-    }
-}
-
-// TODO: need to generalize to support multiple authorization mechanisms such as ghost keys
-
-#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
-pub struct AuthorizedMember {
-    pub member: Member,
-    pub signature: Signature,
-}
-
-impl AuthorizedMember {
-    pub fn new(member: Member, inviter_signing_key: &SigningKey) -> Self {
-        assert_eq!(
-            member.invited_by,
-            VerifyingKey::from(inviter_signing_key).into(),
-            "The member's invited_by must match the inviter's signing key"
-        );
-        Self {
-            member: member.clone(),
-            signature: sign_struct(&member, inviter_signing_key),
+        MembersDelta {
+            added,
+            removed: Vec::new(),
         }
     }
 
-    pub fn verify_signature(&self, inviter_vk: &VerifyingKey) -> Result<(), String> {
-        verify_struct(&self.member, &self.signature, inviter_vk)
-            .map_err(|e| format!("Invalid signature: {}", e))
-    }
-}
-
-impl Hash for AuthorizedMember {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.member.hash(state);
+    pub fn remove(removed: Vec<AuthorizedRemoval>) -> Self {
+        MembersDelta {
+            added: Vec::new(),
+            removed,
+        }
     }
 }
 
@@ -402,28 +506,32 @@ impl fmt::Debug for Member {
     }
 }
 
+const MEMBER_ID_CONTEXT: &str = "river 2025-01 member id";
+
 /*
-Finding a VerifyingKey that would have a MemberId collision would require approximately 
-3 * 10^59 years on current hardware.
+ MemberIds are 256-bit blake3 hashes of the member's VerifyingKey, so finding a key that collides
+ with an existing member requires a second preimage attack on blake3. Ids deserialized from states
+ written with the old 64-bit `fast_hash` are `VersionedHash::Legacy` and never match a computed id,
+ see `legacy::migrate_legacy_state`.
 */
 #[derive(Eq, PartialEq, Hash, Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Copy)]
-pub struct MemberId(pub FastHash);
+pub struct MemberId(pub VersionedHash);
 
 impl Display for MemberId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", truncated_base32(&self.0 .0.to_le_bytes()))
+        write!(f, "{}", truncated_base32(&self.0.as_bytes()))
     }
 }
 
 impl From<&VerifyingKey> for MemberId {
     fn from(vk: &VerifyingKey) -> Self {
-        MemberId(fast_hash(&vk.to_bytes()))
+        MemberId(blake3_hash(MEMBER_ID_CONTEXT, &vk.to_bytes()))
     }
 }
 
 impl From<VerifyingKey> for MemberId {
     fn from(vk: VerifyingKey) -> Self {
-        MemberId(fast_hash(&vk.to_bytes()))
+        MemberId::from(&vk)
     }
 }
 
@@ -436,12 +544,11 @@ impl Member {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::room_state::ban::{AuthorizedUserBan, UserBan};
     use ed25519_dalek::SigningKey;
+    use freenet_scaffold::id_set::BloomFilter;
     use rand::rngs::OsRng;
-    use std::time::SystemTime;
 
-    fn create_test_member(owner_id: MemberId, invited_by: MemberId) -> (Member, SigningKey) {
+    pub(super) fn create_test_member(owner_id: MemberId, invited_by: MemberId) -> (Member, SigningKey) {
         let signing_key = SigningKey::generate(&mut OsRng);
         let verifying_key = signing_key.verifying_key();
         let member = Member {
@@ -466,6 +573,7 @@ mod tests {
 
         let members = MembersV1 {
             members: vec![authorized_member1.clone(), authorized_member2.clone()],
+            ..Default::default()
         };
 
         println!("Member1 ID: {:?}", member1.id());
@@ -491,6 +599,7 @@ mod tests {
         let authorized_owner = AuthorizedMember::new(owner_member, &owner_signing_key);
         let members_with_owner = MembersV1 {
             members: vec![authorized_owner, authorized_member1, authorized_member2],
+            ..Default::default()
         };
         let result_with_owner = members_with_owner.verify(&parent_state, &parameters);
         println!("Verification result with owner: {:?}", result_with_owner);
@@ -515,6 +624,7 @@ mod tests {
 
         let members = MembersV1 {
             members: vec![authorized_member1, authorized_member2],
+            ..Default::default()
         };
 
         let parent_state = ChatRoomStateV1::default();
@@ -523,9 +633,9 @@ mod tests {
         };
 
         let summary = members.summarize(&parent_state, &parameters);
-        assert_eq!(summary.len(), 2);
-        assert!(summary.contains(&member1.id()));
-        assert!(summary.contains(&member2.id()));
+        assert_eq!(summary.members.listed().map(|m| m.len()), Some(2));
+        assert!(summary.members.contains(&(member1.id(), None)));
+        assert!(summary.members.contains(&(member2.id(), None)));
     }
 
     #[test]
@@ -544,10 +654,12 @@ mod tests {
 
         let old_members = MembersV1 {
             members: vec![authorized_member1.clone(), authorized_member2.clone()],
+            ..Default::default()
         };
 
         let new_members = MembersV1 {
             members: vec![authorized_member1.clone(), authorized_member3.clone()],
+            ..Default::default()
         };
 
         let parent_state = ChatRoomStateV1::default();
@@ -564,6 +676,58 @@ mod tests {
         assert_eq!(delta.added[0].member.id(), member3.id());
     }
 
+    #[test]
+    fn test_compacted_summary_false_positive_inviter() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_id = owner_signing_key.verifying_key().into();
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+
+        let (member1, member1_signing_key) = create_test_member(owner_id, owner_id);
+        let (member2, member2_signing_key) = create_test_member(owner_id, member1.id());
+
+        // The peer has no one, but its filter wrongly claims it has the inviters
+        let claimed = [(member1.id(), None), (member2.id(), None)].into();
+        let summary = MembersSummary {
+            members: IdSet::Bloom(BloomFilter::new(&claimed, 0.01)),
+            ..Default::default()
+        };
+        // And only them
+        let member3 = loop {
+            let (member3, _) = create_test_member(owner_id, member2.id());
+            if !summary.members.contains(&(member3.id(), None)) {
+                break member3;
+            }
+        };
+
+        let authorized_member1 = AuthorizedMember::new(member1.clone(), &owner_signing_key);
+        let authorized_member2 = AuthorizedMember::new(member2.clone(), &member1_signing_key);
+        let authorized_member3 = AuthorizedMember::new(member3.clone(), &member2_signing_key);
+        let members = MembersV1 {
+            members: vec![authorized_member1, authorized_member2, authorized_member3],
+            ..Default::default()
+        };
+
+        let parent_state = ChatRoomStateV1::default();
+        let delta = members.delta(&parent_state, &parameters, &summary);
+        let mut peer = MembersV1::default();
+        peer.apply_delta(&parent_state, &parameters, &delta)
+            .unwrap();
+        assert_eq!(
+            peer.summarize(&parent_state, &parameters),
+            members.summarize(&parent_state, &parameters)
+        );
+
+        // A listed summary is exact, nothing it has is sent again
+        let listed = MembersSummary {
+            members: claimed.into_iter().collect(),
+            ..Default::default()
+        };
+        let delta = members.delta(&parent_state, &parameters, &listed).unwrap();
+        assert_eq!(delta.added.len(), 1);
+    }
+
     #[test]
     fn test_members_apply_delta_simple() {
         let owner_signing_key = SigningKey::generate(&mut OsRng);
@@ -580,11 +744,10 @@ mod tests {
 
         let original_members = MembersV1 {
             members: vec![authorized_member1.clone(), authorized_member2.clone()],
+            ..Default::default()
         };
 
-        let delta = MembersDelta {
-            added: vec![authorized_member3.clone()],
-        };
+        let delta = MembersDelta::new(vec![authorized_member3.clone()]);
 
         let mut parent_state = ChatRoomStateV1::default();
         parent_state.configuration.configuration.max_members = 3;
@@ -614,356 +777,15 @@ mod tests {
             .any(|m| m.member.id() == member2.id()));
     }
 
-    #[test]
-    fn test_authorized_member_validate() {
-        let owner_signing_key = SigningKey::generate(&mut OsRng);
-        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
-        let owner_id = owner_verifying_key.into();
-
-        let (member1, member1_signing_key) = create_test_member(owner_id, owner_id);
-        let (member2, _) = create_test_member(owner_id, member1.id());
-
-        let authorized_member1 = AuthorizedMember::new(member1.clone(), &owner_signing_key);
-        let authorized_member2 = AuthorizedMember::new(member2.clone(), &member1_signing_key);
-
-        assert!(authorized_member1
-            .verify_signature(&owner_verifying_key)
-            .is_ok());
-        assert!(authorized_member2
-            .verify_signature(&member1.member_vk)
-            .is_ok());
-
-        // Test with invalid signature
-        let invalid_member2 = AuthorizedMember {
-            member: member2.clone(),
-            signature: Signature::from_bytes(&[0; 64]),
-        };
-        assert!(invalid_member2
-            .verify_signature(&member1.member_vk)
-            .is_err());
-    }
-
     #[test]
     fn test_member_id() {
-        let owner_id = MemberId(FastHash(0));
+        let owner_id = MemberId(VersionedHash::Blake3V1([0; 32]));
         let (member, _) = create_test_member(owner_id, owner_id);
         let member_id = member.id();
 
         assert_eq!(member_id, member.member_vk.into());
     }
 
-    #[test]
-    fn test_verify_self_invited_member() {
-        let owner_signing_key = SigningKey::generate(&mut OsRng);
-        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
-        let owner_id = owner_verifying_key.into();
-
-        let (mut member, member_signing_key) = create_test_member(owner_id, owner_id);
-        member.invited_by = member.id(); // Self-invite
-
-        let authorized_member = AuthorizedMember::new(member, &member_signing_key);
-
-        let members = MembersV1 {
-            members: vec![authorized_member],
-        };
-
-        let parent_state = ChatRoomStateV1::default();
-        let parameters = ChatRoomParametersV1 {
-            owner: owner_verifying_key,
-        };
-
-        let result = members.verify(&parent_state, &parameters);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Self-invitation detected"));
-    }
-
-    #[test]
-    fn test_verify_circular_invite_chain() {
-        let owner_signing_key = SigningKey::generate(&mut OsRng);
-        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
-        let owner_id = owner_verifying_key.into();
-
-        let (mut member1, member1_signing_key) = create_test_member(owner_id, owner_id);
-        let (member2, member2_signing_key) = create_test_member(owner_id, member1.id());
-        let (member3, member3_signing_key) = create_test_member(owner_id, member2.id());
-        member1.invited_by = member3.id(); // Create a circular chain
-
-        let authorized_member1 = AuthorizedMember::new(member1, &member3_signing_key);
-        let authorized_member2 = AuthorizedMember::new(member2, &member1_signing_key);
-        let authorized_member3 = AuthorizedMember::new(member3, &member2_signing_key);
-
-        let members = MembersV1 {
-            members: vec![authorized_member1, authorized_member2, authorized_member3],
-        };
-
-        let parent_state = ChatRoomStateV1::default();
-        let parameters = ChatRoomParametersV1 {
-            owner: owner_verifying_key,
-        };
-
-        let result = members.verify(&parent_state, &parameters);
-        assert!(result.is_err());
-        assert!(result
-            .unwrap_err()
-            .contains("Circular invite chain detected"));
-    }
-
-    #[test]
-    fn test_check_invite_chain() {
-        let owner_signing_key = SigningKey::generate(&mut OsRng);
-        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
-        let owner_id = owner_verifying_key.into();
-
-        // Test case 1: Valid invite chain
-        let (member1, member1_signing_key) = create_test_member(owner_id, owner_id);
-        let (member2, member2_signing_key) = create_test_member(owner_id, member1.id());
-        let (member3, _) = create_test_member(owner_id, member2.id());
-
-        let authorized_member1 = AuthorizedMember::new(member1.clone(), &owner_signing_key);
-        let authorized_member2 = AuthorizedMember::new(member2, &member1_signing_key);
-        let authorized_member3 = AuthorizedMember::new(member3, &member2_signing_key);
-
-        let members = MembersV1 {
-            members: vec![authorized_member1, authorized_member2.clone()],
-        };
-
-        let parameters = ChatRoomParametersV1 {
-            owner: owner_verifying_key,
-        };
-
-        let result = members.get_invite_chain(&authorized_member3, &parameters);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap().len(), 2);
-
-        // Test case 2: Circular invite chain
-        let (mut circular_member1, circular_member1_signing_key) =
-            create_test_member(owner_id, owner_id);
-        let (circular_member2, circular_member2_signing_key) =
-            create_test_member(owner_id, circular_member1.id());
-        circular_member1.invited_by = circular_member2.id();
-
-        let circular_authorized_member1 =
-            AuthorizedMember::new(circular_member1, &circular_member2_signing_key);
-        let circular_authorized_member2 =
-            AuthorizedMember::new(circular_member2, &circular_member1_signing_key);
-
-        let circular_members = MembersV1 {
-            members: vec![
-                circular_authorized_member1.clone(),
-                circular_authorized_member2,
-            ],
-        };
-
-        let result = circular_members.get_invite_chain(&circular_authorized_member1, &parameters);
-        assert!(result.is_err());
-        assert!(result
-            .clone()
-            .unwrap_err()
-            .contains("Circular invite chain detected"));
-
-        // Test case 3: Missing inviter
-        let non_existent_inviter_id = MemberId(FastHash(999));
-        let (orphan_member, _) = create_test_member(owner_id, non_existent_inviter_id);
-        let orphan_authorized_member = AuthorizedMember {
-            member: orphan_member,
-            signature: Signature::from_bytes(&[0; 64]), // Use a dummy signature
-        };
-
-        let result = members.get_invite_chain(&orphan_authorized_member, &parameters);
-        assert!(result.is_err());
-        let err = result.unwrap_err();
-        assert!(err.contains("Inviter"), "Error message: {}", err);
-        assert!(err.contains("not found"), "Error message: {}", err);
-
-        // Test case 4: Invalid signature
-        let (invalid_member, _) = create_test_member(owner_id, member1.id());
-        let invalid_authorized_member = AuthorizedMember {
-            member: invalid_member,
-            signature: Signature::from_bytes(&[0; 64]),
-        };
-
-        let result = members.get_invite_chain(&invalid_authorized_member, &parameters);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Invalid signature"));
-    }
-
-    #[test]
-    fn test_has_banned_members() {
-        let owner_signing_key = SigningKey::generate(&mut OsRng);
-        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
-        let owner_id = owner_verifying_key.into();
-
-        let (member1, member1_signing_key) = create_test_member(owner_id, owner_id);
-        let (member2, member2_signing_key) = create_test_member(owner_id, member1.id());
-        let (member3, _) = create_test_member(owner_id, member2.id());
-
-        let authorized_member1 = AuthorizedMember::new(member1.clone(), &owner_signing_key);
-        let authorized_member2 = AuthorizedMember::new(member2.clone(), &member1_signing_key);
-        let authorized_member3 = AuthorizedMember::new(member3.clone(), &member2_signing_key);
-
-        let members = MembersV1 {
-            members: vec![authorized_member1, authorized_member2, authorized_member3],
-        };
-
-        let parameters = ChatRoomParametersV1 {
-            owner: owner_verifying_key,
-        };
-
-        // Test case 1: No banned members
-        let empty_bans = BansV1(vec![]);
-        assert!(!members.has_banned_members(&empty_bans, &parameters));
-
-        // Test case 2: One banned member
-        let banned_member = UserBan {
-            owner_member_id: owner_id,
-            banned_at: SystemTime::now(),
-            banned_user: member2.id(),
-        };
-        let authorized_ban = AuthorizedUserBan::new(banned_member, owner_id, &owner_signing_key);
-        let bans = BansV1(vec![authorized_ban]);
-        assert!(members.has_banned_members(&bans, &parameters));
-    }
-
-    #[test]
-    fn test_remove_banned_members() {
-        let owner_signing_key = SigningKey::generate(&mut OsRng);
-        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
-        let owner_id = owner_verifying_key.into();
-
-        let (member1, member1_signing_key) = create_test_member(owner_id, owner_id);
-        let (member2, member2_signing_key) = create_test_member(owner_id, member1.id());
-        let (member3, _) = create_test_member(owner_id, member2.id());
-        let (member4, _) = create_test_member(owner_id, member1.id());
-
-        let authorized_member1 = AuthorizedMember::new(member1.clone(), &owner_signing_key);
-        let authorized_member2 = AuthorizedMember::new(member2.clone(), &member1_signing_key);
-        let authorized_member3 = AuthorizedMember::new(member3.clone(), &member2_signing_key);
-        let authorized_member4 = AuthorizedMember::new(member4.clone(), &member1_signing_key);
-
-        let mut members = MembersV1 {
-            members: vec![
-                authorized_member1.clone(),
-                authorized_member2.clone(),
-                authorized_member3.clone(),
-                authorized_member4.clone(),
-            ],
-        };
-
-        let parameters = ChatRoomParametersV1 {
-            owner: owner_verifying_key,
-        };
-
-        // Test case 1: No banned members
-        let empty_bans = BansV1(vec![]);
-        members.remove_banned_members(&empty_bans, &parameters);
-        assert_eq!(members.members.len(), 4);
-
-        // Test case 2: One banned member
-        let banned_member = UserBan {
-            owner_member_id: owner_id,
-            banned_at: SystemTime::now(),
-            banned_user: member2.id(),
-        };
-        let authorized_ban = AuthorizedUserBan::new(banned_member, owner_id, &owner_signing_key);
-        let bans = BansV1(vec![authorized_ban]);
-        members.remove_banned_members(&bans, &parameters);
-        assert_eq!(members.members.len(), 2);
-        assert!(members
-            .members
-            .iter()
-            .any(|m| m.member.id() == member1.id()));
-        assert!(members
-            .members
-            .iter()
-            .any(|m| m.member.id() == member4.id()));
-        assert!(!members
-            .members
-            .iter()
-            .any(|m| m.member.id() == member2.id()));
-        assert!(!members
-            .members
-            .iter()
-            .any(|m| m.member.id() == member3.id()));
-
-        // Test case 3: Banning a member with no downstream members
-        members = MembersV1 {
-            members: vec![
-                authorized_member1,
-                authorized_member2,
-                authorized_member3,
-                authorized_member4,
-            ],
-        };
-        let banned_member = UserBan {
-            owner_member_id: owner_id,
-            banned_at: SystemTime::now(),
-            banned_user: member4.id(),
-        };
-        let authorized_ban = AuthorizedUserBan::new(banned_member, owner_id, &owner_signing_key);
-        let bans = BansV1(vec![authorized_ban]);
-        members.remove_banned_members(&bans, &parameters);
-        assert_eq!(members.members.len(), 3);
-        assert!(members
-            .members
-            .iter()
-            .any(|m| m.member.id() == member1.id()));
-        assert!(members
-            .members
-            .iter()
-            .any(|m| m.member.id() == member2.id()));
-        assert!(members
-            .members
-            .iter()
-            .any(|m| m.member.id() == member3.id()));
-        assert!(!members
-            .members
-            .iter()
-            .any(|m| m.member.id() == member4.id()));
-    }
-
-    #[test]
-    fn test_remove_excess_members() {
-        let owner_signing_key = SigningKey::generate(&mut OsRng);
-        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
-        let owner_id = owner_verifying_key.into();
-
-        let (member1, member1_signing_key) = create_test_member(owner_id, owner_id);
-        let (member2, member2_signing_key) = create_test_member(owner_id, member1.id());
-        let (member3, _) = create_test_member(owner_id, member2.id());
-
-        let authorized_member1 = AuthorizedMember::new(member1.clone(), &owner_signing_key);
-        let authorized_member2 = AuthorizedMember::new(member2.clone(), &member1_signing_key);
-        let authorized_member3 = AuthorizedMember::new(member3.clone(), &member2_signing_key);
-
-        let mut members = MembersV1 {
-            members: vec![authorized_member1, authorized_member2, authorized_member3],
-        };
-
-        let parameters = ChatRoomParametersV1 {
-            owner: owner_verifying_key,
-        };
-
-        // Test case 1: No excess members
-        members.remove_excess_members(&parameters, 3);
-        assert_eq!(members.members.len(), 3);
-
-        // Test case 2: One excess member
-        members.remove_excess_members(&parameters, 2);
-        assert_eq!(members.members.len(), 2);
-        assert!(members
-            .members
-            .iter()
-            .any(|m| m.member.id() == member1.id()));
-        assert!(members
-            .members
-            .iter()
-            .any(|m| m.member.id() == member2.id()));
-        assert!(!members
-            .members
-            .iter()
-            .any(|m| m.member.id() == member3.id()));
-    }
-
     #[test]
     fn test_members_by_member_id() {
         let owner_signing_key = SigningKey::generate(&mut OsRng);
@@ -978,6 +800,7 @@ mod tests {
 
         let members = MembersV1 {
             members: vec![authorized_member1.clone(), authorized_member2.clone()],
+            ..Default::default()
         };
 
         let members_map = members.members_by_member_id();
@@ -1011,6 +834,7 @@ mod tests {
 
         let mut members = MembersV1 {
             members: vec![authorized_member1.clone(), authorized_member2.clone()],
+            ..Default::default()
         };
 
         let mut parent_state = ChatRoomStateV1::default();
@@ -1021,9 +845,7 @@ mod tests {
         };
 
         // Test applying delta that would exceed max_members
-        let delta = MembersDelta {
-            added: vec![authorized_member3.clone(), authorized_member4.clone()],
-        };
+        let delta = MembersDelta::new(vec![authorized_member3.clone(), authorized_member4.clone()]);
 
         let result = members.apply_delta(&parent_state, &parameters, &Some(delta));
         assert!(result.is_ok());
@@ -1046,60 +868,13 @@ mod tests {
             .any(|m| m.member.id() == member4.id()));
 
         // Test applying delta with already existing member
-        let delta = MembersDelta {
-            added: vec![authorized_member2.clone()],
-        };
+        let delta = MembersDelta::new(vec![authorized_member2.clone()]);
 
         let result = members.apply_delta(&parent_state, &parameters, &Some(delta));
         assert!(result.is_ok());
         assert_eq!(members.members.len(), 3);
     }
 
-    #[test]
-    fn test_remove_excess_members_edge_cases() {
-        let owner_signing_key = SigningKey::generate(&mut OsRng);
-        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
-        let owner_id = owner_verifying_key.into();
-
-        let (member1, member1_signing_key) = create_test_member(owner_id, owner_id);
-        let (member2, _) = create_test_member(owner_id, member1.id());
-
-        let authorized_member1 = AuthorizedMember::new(member1.clone(), &owner_signing_key);
-        let authorized_member2 = AuthorizedMember::new(member2.clone(), &member1_signing_key);
-
-        let mut members = MembersV1 {
-            members: vec![authorized_member1.clone(), authorized_member2.clone()],
-        };
-
-        let parameters = ChatRoomParametersV1 {
-            owner: owner_verifying_key,
-        };
-
-        // Test with max_members set to 0
-        members.remove_excess_members(&parameters, 0);
-        assert_eq!(members.members.len(), 0);
-
-        // Reset members
-        members.members = vec![authorized_member1.clone(), authorized_member2.clone()];
-
-        // Test with max_members greater than current number of members
-        members.remove_excess_members(&parameters, 3);
-        assert_eq!(members.members.len(), 2);
-    }
-
-    #[test]
-    #[should_panic(expected = "The member's invited_by must match the inviter's signing key")]
-    fn test_authorized_member_new_mismatch() {
-        let owner_signing_key = SigningKey::generate(&mut OsRng);
-        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
-        let owner_id = owner_verifying_key.into();
-
-        let (member, _) = create_test_member(owner_id, owner_id);
-        let wrong_signing_key = SigningKey::generate(&mut OsRng);
-
-        AuthorizedMember::new(member, &wrong_signing_key);
-    }
-
     #[test]
     fn test_members_verify_edge_cases() {
         let owner_signing_key = SigningKey::generate(&mut OsRng);
@@ -1114,7 +889,7 @@ mod tests {
         };
 
         // Test with empty member list
-        let empty_members = MembersV1 { members: vec![] };
+        let empty_members = MembersV1::default();
         assert!(empty_members.verify(&parent_state, &parameters).is_ok());
 
         // Test with maximum allowed number of members
@@ -1126,6 +901,7 @@ mod tests {
 
         let max_members = MembersV1 {
             members: vec![authorized_member1, authorized_member2],
+            ..Default::default()
         };
         assert!(max_members.verify(&parent_state, &parameters).is_ok());
 
@@ -1139,6 +915,7 @@ mod tests {
 
         let invalid_members = MembersV1 {
             members: vec![invalid_authorized_member],
+            ..Default::default()
         };
         assert!(invalid_members.verify(&parent_state, &parameters).is_err());
     }
@@ -1159,6 +936,7 @@ mod tests {
 
         let members = MembersV1 {
             members: vec![authorized_owner_member],
+            ..Default::default()
         };
 
         let mut parent_state = ChatRoomStateV1::default();
@@ -1170,6 +948,34 @@ mod tests {
 
         let result = members.verify(&parent_state, &parameters);
         assert!(result.is_err(), "Room owner should not be allowed in the members list");
-        assert!(result.unwrap_err().contains("Owner should not be included in the members list"));
+        assert_eq!(result, Err(RoomStateError::OwnerInMembers));
+    }
+
+    #[test]
+    fn test_duplicate_member_id_rejected() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
+        let owner_id = owner_verifying_key.into();
+
+        let (member1, member1_signing_key) = create_test_member(owner_id, owner_id);
+        let (mut member2, _) = create_test_member(owner_id, member1.id());
+        let authorized_member1 = AuthorizedMember::new(member1.clone(), &owner_signing_key);
+        let authorized_member2 = AuthorizedMember::new(member2.clone(), &member1_signing_key);
+
+        // The same key invited a second time through a different path aliases the first entry
+        member2.invited_by = owner_id;
+        let duplicate = AuthorizedMember::new(member2, &owner_signing_key);
+
+        let members = MembersV1 {
+            members: vec![authorized_member1, authorized_member2, duplicate],
+            ..Default::default()
+        };
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_verifying_key,
+        };
+
+        let result = members.verify(&ChatRoomStateV1::default(), &parameters);
+        assert!(matches!(result, Err(RoomStateError::Duplicate { .. })));
     }
+
 }
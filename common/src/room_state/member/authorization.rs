@@ -0,0 +1,334 @@
+use crate::room_state::error::{RoomStateError, StateField};
+use crate::room_state::member::{GhostKeyCertificate, Member, MemberId, Redemption};
+use crate::util::{sign_struct, verify_struct};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
+use std::time::SystemTime;
+
+/*
+ A member is let into the room by whoever invited them, by redeeming an invitation or with a
+ ghost key. All three are kept as an `AuthorizedMember`, which is encoded as the member and a
+ signature along with what else the way they joined needs, so members invited before there were
+ other ways to join keep their encoding.
+*/
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(into = "AuthorizedMemberFields", try_from = "AuthorizedMemberFields")]
+pub struct AuthorizedMember {
+    pub member: Member,
+    pub authorization: MemberAuthorization,
+}
+
+/// How a member was let into the room. The signature covers `joined_at` along with the member
+/// when it's set, which lets the member back in after they've been removed, see `Removal`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum MemberAuthorization {
+    /// Signed by the member themselves and certified by a ghost key issuer, `invited_by` must be
+    /// the owner but the member has no invite chain
+    GhostKey {
+        signature: Signature,
+        ghost_key: GhostKeyCertificate,
+        joined_at: Option<SystemTime>,
+    },
+    /// Signed with the key of an invitation the member redeemed
+    Invitation {
+        signature: Signature,
+        redemption: Box<Redemption>,
+    },
+    /// Signed by the inviter
+    Invite {
+        signature: Signature,
+        joined_at: Option<SystemTime>,
+    },
+}
+
+impl AuthorizedMember {
+    pub fn new(member: Member, inviter_signing_key: &SigningKey) -> Self {
+        assert_eq!(
+            member.invited_by,
+            VerifyingKey::from(inviter_signing_key).into(),
+            "The member's invited_by must match the inviter's signing key"
+        );
+        Self {
+            member: member.clone(),
+            authorization: MemberAuthorization::Invite {
+                signature: sign_struct(&member, inviter_signing_key),
+                joined_at: None,
+            },
+        }
+    }
+
+    /// Like `new` but records when the member was invited, so that they can be invited again after
+    /// leaving or being kicked
+    pub fn new_at(member: Member, inviter_signing_key: &SigningKey, joined_at: SystemTime) -> Self {
+        assert_eq!(
+            member.invited_by,
+            VerifyingKey::from(inviter_signing_key).into(),
+            "The member's invited_by must match the inviter's signing key"
+        );
+        Self {
+            authorization: MemberAuthorization::Invite {
+                signature: sign_struct((&member, joined_at), inviter_signing_key),
+                joined_at: Some(joined_at),
+            },
+            member,
+        }
+    }
+
+    /// Signs the member into the room owned by `owner_member_id` with a ghost key certificate
+    pub fn with_ghost_key(
+        owner_member_id: MemberId,
+        member_signing_key: &SigningKey,
+        ghost_key: GhostKeyCertificate,
+        joined_at: SystemTime,
+    ) -> Self {
+        let member = Member {
+            owner_member_id,
+            invited_by: owner_member_id,
+            member_vk: member_signing_key.verifying_key(),
+        };
+        Self {
+            authorization: MemberAuthorization::GhostKey {
+                signature: sign_struct((&member, joined_at), member_signing_key),
+                ghost_key,
+                joined_at: Some(joined_at),
+            },
+            member,
+        }
+    }
+
+    /// The member who invited this member, `None` for members without an invite chain
+    pub fn inviter(&self) -> Option<MemberId> {
+        match self.authorization {
+            MemberAuthorization::GhostKey { .. } => None,
+            _ => Some(self.member.invited_by),
+        }
+    }
+
+    pub fn redemption(&self) -> Option<&Redemption> {
+        match &self.authorization {
+            MemberAuthorization::Invitation { redemption, .. } => Some(redemption.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn ghost_key(&self) -> Option<&GhostKeyCertificate> {
+        match &self.authorization {
+            MemberAuthorization::GhostKey { ghost_key, .. } => Some(ghost_key),
+            _ => None,
+        }
+    }
+
+    /// When the member was let in, `None` if that wasn't recorded in which case any removal of the
+    /// member applies
+    pub fn joined_at(&self) -> Option<SystemTime> {
+        match &self.authorization {
+            MemberAuthorization::GhostKey { joined_at, .. }
+            | MemberAuthorization::Invite { joined_at, .. } => *joined_at,
+            MemberAuthorization::Invitation { redemption, .. } => Some(redemption.redeemed_at),
+        }
+    }
+
+    pub(super) fn signature(&self) -> &Signature {
+        match &self.authorization {
+            MemberAuthorization::GhostKey { signature, .. }
+            | MemberAuthorization::Invitation { signature, .. }
+            | MemberAuthorization::Invite { signature, .. } => signature,
+        }
+    }
+
+    /// Ghost key members sign themselves in so `inviter_vk` is ignored for them, whether their
+    /// certificate's issuer is trusted is up to the room's configuration
+    pub fn verify_signature(&self, inviter_vk: &VerifyingKey) -> Result<(), RoomStateError> {
+        let invalid = || RoomStateError::InvalidSignature {
+            field: StateField::Member,
+            id: self.member.id().0,
+        };
+        let verify = |signature, joined_at: &Option<SystemTime>, signer| match joined_at {
+            Some(joined_at) => verify_struct(&(&self.member, joined_at), signature, signer),
+            None => verify_struct(&self.member, signature, signer),
+        };
+        match &self.authorization {
+            MemberAuthorization::GhostKey {
+                signature,
+                ghost_key,
+                joined_at,
+            } => {
+                if self.member.invited_by != self.member.owner_member_id {
+                    return Err(invalid());
+                }
+                ghost_key.verify(&self.member)?;
+                verify(signature, joined_at, &self.member.member_vk).map_err(|_| invalid())
+            }
+            MemberAuthorization::Invitation {
+                signature,
+                redemption,
+            } => redemption.verify(&self.member, signature, inviter_vk),
+            MemberAuthorization::Invite {
+                signature,
+                joined_at,
+            } => verify(signature, joined_at, inviter_vk).map_err(|_| invalid()),
+        }
+    }
+}
+
+/// How an `AuthorizedMember` is serialized, members invited before there were other ways to
+/// join keep their encoding
+#[derive(Serialize, Deserialize)]
+struct AuthorizedMemberFields {
+    member: Member,
+    signature: Signature,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    redemption: Option<Redemption>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ghost_key: Option<GhostKeyCertificate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    joined_at: Option<SystemTime>,
+}
+
+impl From<AuthorizedMember> for AuthorizedMemberFields {
+    fn from(member: AuthorizedMember) -> Self {
+        let (signature, redemption, ghost_key, joined_at) = match member.authorization {
+            MemberAuthorization::GhostKey {
+                signature,
+                ghost_key,
+                joined_at,
+            } => (signature, None, Some(ghost_key), joined_at),
+            MemberAuthorization::Invitation {
+                signature,
+                redemption,
+            } => (signature, Some(*redemption), None, None),
+            MemberAuthorization::Invite {
+                signature,
+                joined_at,
+            } => (signature, None, None, joined_at),
+        };
+        AuthorizedMemberFields {
+            member: member.member,
+            signature,
+            redemption,
+            ghost_key,
+            joined_at,
+        }
+    }
+}
+
+impl TryFrom<AuthorizedMemberFields> for AuthorizedMember {
+    type Error = String;
+
+    fn try_from(fields: AuthorizedMemberFields) -> Result<Self, Self::Error> {
+        let signature = fields.signature;
+        let joined_at = fields.joined_at;
+        let authorization = match (fields.redemption, fields.ghost_key) {
+            (None, None) => MemberAuthorization::Invite {
+                signature,
+                joined_at,
+            },
+            (Some(redemption), None) if joined_at.is_none() => MemberAuthorization::Invitation {
+                signature,
+                redemption: Box::new(redemption),
+            },
+            (None, Some(ghost_key)) => MemberAuthorization::GhostKey {
+                signature,
+                ghost_key,
+                joined_at,
+            },
+            _ => return Err("A member can only be authorized in one way".to_string()),
+        };
+        Ok(AuthorizedMember {
+            member: fields.member,
+            authorization,
+        })
+    }
+}
+
+impl Hash for AuthorizedMember {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.member.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::member::tests::create_test_member;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_authorized_member_validate() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
+        let owner_id = owner_verifying_key.into();
+
+        let (member1, member1_signing_key) = create_test_member(owner_id, owner_id);
+        let (member2, _) = create_test_member(owner_id, member1.id());
+
+        let authorized_member1 = AuthorizedMember::new(member1.clone(), &owner_signing_key);
+        let authorized_member2 = AuthorizedMember::new(member2.clone(), &member1_signing_key);
+
+        assert!(authorized_member1
+            .verify_signature(&owner_verifying_key)
+            .is_ok());
+        assert!(authorized_member2
+            .verify_signature(&member1.member_vk)
+            .is_ok());
+
+        // Test with invalid signature
+        let invalid_member2 = AuthorizedMember {
+            member: member2.clone(),
+            authorization: MemberAuthorization::Invite {
+                signature: Signature::from_bytes(&[0; 64]),
+                joined_at: None,
+            },
+        };
+        assert!(invalid_member2
+            .verify_signature(&member1.member_vk)
+            .is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "The member's invited_by must match the inviter's signing key")]
+    fn test_authorized_member_new_mismatch() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
+        let owner_id = owner_verifying_key.into();
+
+        let (member, _) = create_test_member(owner_id, owner_id);
+        let wrong_signing_key = SigningKey::generate(&mut OsRng);
+
+        AuthorizedMember::new(member, &wrong_signing_key);
+    }
+
+    #[test]
+    fn test_invite_encoding_unchanged() {
+        #[derive(Serialize)]
+        struct InvitedMember<'a> {
+            member: &'a Member,
+            signature: &'a Signature,
+        }
+
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_id = owner_signing_key.verifying_key().into();
+        let (member, _) = create_test_member(owner_id, owner_id);
+        let member = AuthorizedMember::new(member, &owner_signing_key);
+        let MemberAuthorization::Invite { signature, .. } = &member.authorization else {
+            panic!("Expected an invite");
+        };
+
+        let mut encoded = Vec::new();
+        ciborium::ser::into_writer(
+            &InvitedMember {
+                member: &member.member,
+                signature,
+            },
+            &mut encoded,
+        )
+        .unwrap();
+        let mut reencoded = Vec::new();
+        ciborium::ser::into_writer(&member, &mut reencoded).unwrap();
+        assert_eq!(reencoded, encoded);
+        let decoded: AuthorizedMember = ciborium::de::from_reader(encoded.as_slice()).unwrap();
+        assert_eq!(decoded, member);
+    }
+}
@@ -0,0 +1,264 @@
+use crate::room_state::ban::BansV1;
+use crate::room_state::configuration::Configuration;
+use crate::room_state::member::{MemberId, MembersV1};
+use crate::room_state::ChatRoomParametersV1;
+use std::collections::HashSet;
+
+/*
+ A ban takes the member out of the room along with everyone downstream of them in the invite
+ chain. Who may ban whom is up to `MemberIndex::may_ban`, see `BansV1`.
+*/
+
+impl MembersV1 {
+    /// Checks if there are any banned members or members downstream of banned members in the invite chain
+    pub fn has_banned_members(&self, bans_v1: &BansV1, parameters: &ChatRoomParametersV1) -> bool {
+        self.check_banned_members(bans_v1, parameters).is_some()
+    }
+
+    /// Removes banned members or members downstream of banned members in the invite chain
+    pub(super) fn remove_banned_members(
+        &mut self,
+        bans_v1: &BansV1,
+        configuration: &Configuration,
+        parameters: &ChatRoomParametersV1,
+    ) {
+        let index = self.index(parameters);
+        let mut banned_ids = HashSet::new();
+        for member in &self.members {
+            if index.is_banned(member, bans_v1, configuration) {
+                banned_ids.insert(member.member.id());
+                banned_ids.extend(index.downstream(member.member.id()));
+            }
+        }
+        self.members
+            .retain(|m| !banned_ids.contains(&m.member.id()));
+    }
+
+    /// Checks for banned members and returns a set of member IDs to be removed if any are found
+    fn check_banned_members(
+        &self,
+        bans_v1: &BansV1,
+        parameters: &ChatRoomParametersV1,
+    ) -> Option<HashSet<MemberId>> {
+        let index = self.index(parameters);
+        let mut banned_ids = HashSet::new();
+        for m in &self.members {
+            if let Ok(invite_chain) = index.invite_chain(m) {
+                if invite_chain.iter().any(|m| {
+                    bans_v1
+                        .in_effect()
+                        .any(|b| b.ban.banned_user == m.member.id())
+                }) {
+                    banned_ids.insert(m.member.id());
+                }
+            }
+        }
+        if banned_ids.is_empty() {
+            None
+        } else {
+            Some(banned_ids)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::ban::{AuthorizedUserBan, UserBan};
+    use crate::room_state::configuration::{Moderator, ModeratorPermissions};
+    use crate::room_state::member::tests::create_test_member;
+    use crate::room_state::member::AuthorizedMember;
+    use ed25519_dalek::{SigningKey, VerifyingKey};
+    use rand::rngs::OsRng;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_has_banned_members() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
+        let owner_id = owner_verifying_key.into();
+
+        let (member1, member1_signing_key) = create_test_member(owner_id, owner_id);
+        let (member2, member2_signing_key) = create_test_member(owner_id, member1.id());
+        let (member3, _) = create_test_member(owner_id, member2.id());
+
+        let authorized_member1 = AuthorizedMember::new(member1.clone(), &owner_signing_key);
+        let authorized_member2 = AuthorizedMember::new(member2.clone(), &member1_signing_key);
+        let authorized_member3 = AuthorizedMember::new(member3.clone(), &member2_signing_key);
+
+        let members = MembersV1 {
+            members: vec![authorized_member1, authorized_member2, authorized_member3],
+            ..Default::default()
+        };
+
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_verifying_key,
+        };
+
+        // Test case 1: No banned members
+        let empty_bans = BansV1(vec![]);
+        assert!(!members.has_banned_members(&empty_bans, &parameters));
+
+        // Test case 2: One banned member
+        let banned_member = UserBan {
+            owner_member_id: owner_id,
+            banned_at: SystemTime::now(),
+            banned_user: member2.id(),
+            expires_at: None,
+        };
+        let authorized_ban = AuthorizedUserBan::new(banned_member, owner_id, &owner_signing_key);
+        let bans = BansV1(vec![authorized_ban]);
+        assert!(members.has_banned_members(&bans, &parameters));
+    }
+
+    #[test]
+    fn test_remove_banned_members() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
+        let owner_id = owner_verifying_key.into();
+
+        let (member1, member1_signing_key) = create_test_member(owner_id, owner_id);
+        let (member2, member2_signing_key) = create_test_member(owner_id, member1.id());
+        let (member3, _) = create_test_member(owner_id, member2.id());
+        let (member4, _) = create_test_member(owner_id, member1.id());
+
+        let authorized_member1 = AuthorizedMember::new(member1.clone(), &owner_signing_key);
+        let authorized_member2 = AuthorizedMember::new(member2.clone(), &member1_signing_key);
+        let authorized_member3 = AuthorizedMember::new(member3.clone(), &member2_signing_key);
+        let authorized_member4 = AuthorizedMember::new(member4.clone(), &member1_signing_key);
+
+        let mut members = MembersV1 {
+            members: vec![
+                authorized_member1.clone(),
+                authorized_member2.clone(),
+                authorized_member3.clone(),
+                authorized_member4.clone(),
+            ],
+            ..Default::default()
+        };
+
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_verifying_key,
+        };
+
+        // Test case 1: No banned members
+        let empty_bans = BansV1(vec![]);
+        members.remove_banned_members(&empty_bans, &Configuration::default(), &parameters);
+        assert_eq!(members.members.len(), 4);
+
+        // Test case 2: One banned member
+        let banned_member = UserBan {
+            owner_member_id: owner_id,
+            banned_at: SystemTime::now(),
+            banned_user: member2.id(),
+            expires_at: None,
+        };
+        let authorized_ban = AuthorizedUserBan::new(banned_member, owner_id, &owner_signing_key);
+        let bans = BansV1(vec![authorized_ban]);
+        members.remove_banned_members(&bans, &Configuration::default(), &parameters);
+        assert_eq!(members.members.len(), 2);
+        assert!(members
+            .members
+            .iter()
+            .any(|m| m.member.id() == member1.id()));
+        assert!(members
+            .members
+            .iter()
+            .any(|m| m.member.id() == member4.id()));
+        assert!(!members
+            .members
+            .iter()
+            .any(|m| m.member.id() == member2.id()));
+        assert!(!members
+            .members
+            .iter()
+            .any(|m| m.member.id() == member3.id()));
+
+        // Test case 3: Banning a member with no downstream members
+        members = MembersV1 {
+            members: vec![
+                authorized_member1,
+                authorized_member2,
+                authorized_member3,
+                authorized_member4,
+            ],
+            ..Default::default()
+        };
+        let banned_member = UserBan {
+            owner_member_id: owner_id,
+            banned_at: SystemTime::now(),
+            banned_user: member4.id(),
+            expires_at: None,
+        };
+        let authorized_ban = AuthorizedUserBan::new(banned_member, owner_id, &owner_signing_key);
+        let bans = BansV1(vec![authorized_ban]);
+        members.remove_banned_members(&bans, &Configuration::default(), &parameters);
+        assert_eq!(members.members.len(), 3);
+        assert!(members
+            .members
+            .iter()
+            .any(|m| m.member.id() == member1.id()));
+        assert!(members
+            .members
+            .iter()
+            .any(|m| m.member.id() == member2.id()));
+        assert!(members
+            .members
+            .iter()
+            .any(|m| m.member.id() == member3.id()));
+        assert!(!members
+            .members
+            .iter()
+            .any(|m| m.member.id() == member4.id()));
+    }
+
+    #[test]
+    fn test_moderator_bans() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_id = owner_signing_key.verifying_key().into();
+        let (member, _) = create_test_member(owner_id, owner_id);
+        let (moderator, moderator_signing_key) = create_test_member(owner_id, owner_id);
+        let members = MembersV1 {
+            members: vec![
+                AuthorizedMember::new(member.clone(), &owner_signing_key),
+                AuthorizedMember::new(moderator.clone(), &owner_signing_key),
+            ],
+            ..Default::default()
+        };
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        let ban = UserBan {
+            owner_member_id: owner_id,
+            banned_at: SystemTime::now(),
+            banned_user: member.id(),
+            expires_at: None,
+        };
+        let bans = BansV1(vec![AuthorizedUserBan::new(
+            ban,
+            moderator.id(),
+            &moderator_signing_key,
+        )]);
+
+        // The member wasn't invited by whoever banned them, so only a moderator can
+        let mut configuration = Configuration::default();
+        let mut remaining = members.clone();
+        remaining.remove_banned_members(&bans, &configuration, &parameters);
+        assert_eq!(remaining.members.len(), 2);
+
+        configuration.moderators = vec![Moderator {
+            member_id: moderator.id(),
+            permissions: ModeratorPermissions {
+                ban_anyone: true,
+                ..ModeratorPermissions::default()
+            },
+        }];
+        let mut remaining = members.clone();
+        remaining.remove_banned_members(&bans, &configuration, &parameters);
+        assert_eq!(remaining.members.len(), 1);
+        assert_eq!(remaining.members[0].member.id(), moderator.id());
+        assert!(members
+            .member_key(member.id(), &bans, &configuration, &parameters)
+            .is_none());
+    }
+}
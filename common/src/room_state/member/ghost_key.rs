@@ -0,0 +1,340 @@
+use crate::room_state::error::{RoomStateError, StateField};
+use crate::room_state::member::{AuthorizedMember, Member, MemberId, MembersV1};
+use crate::util::truncated_base64;
+use crate::ChatRoomStateV1;
+use ed25519_dalek::VerifyingKey;
+use num_bigint_dig::ModInverse;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use rsa::traits::PublicKeyParts;
+use rsa::{BigUint, RsaPrivateKey, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/*
+ A ghost key lets someone join a room without being invited by anyone in it. An issuer the owner
+ trusts, listed in `Configuration::ghost_key_issuers`, certifies the member's key, typically after
+ they've shown they're a real person by making a donation.
+
+ Certificates are blind RSA signatures over a full domain hash of the key. The member blinds their
+ key with a random factor before sending it to the issuer, see `GhostKeyRequest`, and removes the
+ factor from the issuer's signature afterwards. The issuer only ever sees the blinded key, so it
+ can't tell which key it certified or link the certificate to the member it certified it for.
+
+ Ghost key members sign themselves in, they have no invite chain so only the owner can ban them.
+*/
+
+const GHOST_KEY_CONTEXT: &str = "river 2025-01 ghost key";
+
+/// An issuer's signature on a member's key
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct GhostKeyCertificate {
+    pub issuer: RsaPublicKey,
+    /// The unblinded signature, big-endian
+    pub signature: Vec<u8>,
+}
+
+impl fmt::Debug for GhostKeyCertificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GhostKeyCertificate")
+            .field(
+                "issuer",
+                &format_args!("{}", truncated_base64(self.issuer.n().to_bytes_be())),
+            )
+            .field(
+                "signature",
+                &format_args!("{}", truncated_base64(&self.signature)),
+            )
+            .finish()
+    }
+}
+
+impl GhostKeyCertificate {
+    /// Checks that the certificate was made for `member` by its issuer, whether the issuer is
+    /// trusted depends on the room's configuration
+    pub fn verify(&self, member: &Member) -> Result<(), RoomStateError> {
+        if self.certifies(&member.member_vk) {
+            Ok(())
+        } else {
+            Err(RoomStateError::InvalidSignature {
+                field: StateField::GhostKey,
+                id: member.id().0,
+            })
+        }
+    }
+
+    fn certifies(&self, member_vk: &VerifyingKey) -> bool {
+        // Any key can be named, those `RsaPublicKey::new` would refuse take too long to check
+        if RsaPublicKey::new(self.issuer.n().clone(), self.issuer.e().clone()).is_err() {
+            return false;
+        }
+        let signature = BigUint::from_bytes_be(&self.signature);
+        &signature < self.issuer.n()
+            && signature.modpow(self.issuer.e(), self.issuer.n())
+                == key_digest(member_vk, &self.issuer)
+    }
+}
+
+/// A member's key blinded for an issuer to sign, see `GhostKeyRequest`
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct BlindedGhostKey(pub Vec<u8>);
+
+impl BlindedGhostKey {
+    /// The issuer's signature on the blinded key, the member unblinds it into their certificate
+    pub fn sign(&self, issuer_key: &RsaPrivateKey) -> Result<BlindSignature, rsa::Error> {
+        let blinded = BigUint::from_bytes_be(&self.0);
+        rsa::hazmat::rsa_decrypt_and_check(issuer_key, Some(&mut OsRng), &blinded)
+            .map(|signature| BlindSignature(signature.to_bytes_be()))
+    }
+}
+
+/// An issuer's signature on a `BlindedGhostKey`
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct BlindSignature(pub Vec<u8>);
+
+/// What the member keeps while the issuer signs their blinded key
+pub struct GhostKeyRequest {
+    member_vk: VerifyingKey,
+    issuer: RsaPublicKey,
+    /// The inverse of the blinding factor
+    unblinder: BigUint,
+}
+
+impl GhostKeyRequest {
+    /// Blinds the member's key for `issuer` to sign, the issuer learns nothing about the key
+    pub fn new(member_vk: &VerifyingKey, issuer: &RsaPublicKey) -> (Self, BlindedGhostKey) {
+        let n = issuer.n();
+        let (factor, unblinder) = loop {
+            let mut bytes = vec![0; issuer.size()];
+            OsRng.fill_bytes(&mut bytes);
+            let factor = BigUint::from_bytes_be(&bytes) % n;
+            let unblinder = factor
+                .clone()
+                .mod_inverse(n)
+                .and_then(|inverse| inverse.to_biguint());
+            if let Some(unblinder) = unblinder {
+                break (factor, unblinder);
+            }
+        };
+        let blinded = key_digest(member_vk, issuer) * factor.modpow(issuer.e(), n) % n;
+        let request = GhostKeyRequest {
+            member_vk: *member_vk,
+            issuer: issuer.clone(),
+            unblinder,
+        };
+        (request, BlindedGhostKey(blinded.to_bytes_be()))
+    }
+
+    /// Unblinds the issuer's signature into the member's certificate, checking it
+    pub fn certificate(
+        self,
+        signature: &BlindSignature,
+    ) -> Result<GhostKeyCertificate, RoomStateError> {
+        let signature = BigUint::from_bytes_be(&signature.0) * self.unblinder % self.issuer.n();
+        let certificate = GhostKeyCertificate {
+            issuer: self.issuer,
+            signature: signature.to_bytes_be(),
+        };
+        if !certificate.certifies(&self.member_vk) {
+            return Err(RoomStateError::InvalidSignature {
+                field: StateField::GhostKey,
+                id: MemberId::from(&self.member_vk).0,
+            });
+        }
+        Ok(certificate)
+    }
+}
+
+/// The member's key hashed to a number below the issuer's modulus
+fn key_digest(member_vk: &VerifyingKey, issuer: &RsaPublicKey) -> BigUint {
+    let mut hasher = blake3::Hasher::new_derive_key(GHOST_KEY_CONTEXT);
+    hasher.update(member_vk.as_bytes());
+    // Longer than the modulus so that reducing it leaves no noticeable bias
+    let mut bytes = vec![0; issuer.size() + 16];
+    hasher.finalize_xof().fill(&mut bytes);
+    BigUint::from_bytes_be(&bytes) % issuer.n()
+}
+
+impl MembersV1 {
+    /// Checks that a ghost key member's certificate comes from an issuer the room trusts
+    pub(super) fn verify_ghost_key_issuer(
+        member: &AuthorizedMember,
+        parent_state: &ChatRoomStateV1,
+    ) -> Result<(), RoomStateError> {
+        match member.ghost_key() {
+            Some(ghost_key)
+                if !parent_state
+                    .configuration
+                    .configuration
+                    .ghost_key_issuers
+                    .contains(&ghost_key.issuer) =>
+            {
+                Err(RoomStateError::UntrustedGhostKeyIssuer {
+                    member: member.member.id(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::ban::{AuthorizedUserBan, BansV1, UserBan};
+    use crate::room_state::member::tests::create_test_member;
+    use crate::room_state::member::MembersDelta;
+    use crate::room_state::ChatRoomParametersV1;
+    use ed25519_dalek::SigningKey;
+    use freenet_scaffold::ComposableState;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_ghost_key_member() {
+        let owner_vk = SigningKey::generate(&mut OsRng).verifying_key();
+        let issuer_key = RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+        let member_signing_key = SigningKey::generate(&mut OsRng);
+
+        // The issuer only sees the blinded key
+        let member_vk = member_signing_key.verifying_key();
+        let (request, blinded) = GhostKeyRequest::new(&member_vk, &issuer_key.to_public_key());
+        let digest = key_digest(&member_vk, &issuer_key.to_public_key());
+        assert_ne!(BigUint::from_bytes_be(&blinded.0), digest);
+        let signature = blinded.sign(&issuer_key).unwrap();
+        let certificate = request.certificate(&signature).unwrap();
+        assert_ne!(signature.0, certificate.signature);
+
+        let member = AuthorizedMember::with_ghost_key(
+            owner_vk.into(),
+            &member_signing_key,
+            certificate.clone(),
+            SystemTime::now(),
+        );
+        assert_eq!(member.inviter(), None);
+        assert_eq!(member.verify_signature(&owner_vk), Ok(()));
+
+        // A certificate made for someone else's key
+        let other_signing_key = SigningKey::generate(&mut OsRng);
+        let stolen = AuthorizedMember::with_ghost_key(
+            owner_vk.into(),
+            &other_signing_key,
+            certificate,
+            SystemTime::now(),
+        );
+        assert!(matches!(
+            stolen.verify_signature(&owner_vk),
+            Err(RoomStateError::InvalidSignature {
+                field: StateField::GhostKey,
+                ..
+            })
+        ));
+
+        // A signature on something other than the blinded key doesn't unblind to a certificate
+        let (request, _) = GhostKeyRequest::new(&member_vk, &issuer_key.to_public_key());
+        let (_, other) = GhostKeyRequest::new(
+            &other_signing_key.verifying_key(),
+            &issuer_key.to_public_key(),
+        );
+        assert!(request
+            .certificate(&other.sign(&issuer_key).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_ghost_key_members() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
+        let owner_id: MemberId = owner_verifying_key.into();
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_verifying_key,
+        };
+        let issuer_key = rsa::RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+        let mut parent_state = ChatRoomStateV1::default();
+        parent_state.configuration.configuration.ghost_key_issuers =
+            vec![issuer_key.to_public_key()];
+
+        let ghost_signing_key = SigningKey::generate(&mut OsRng);
+        let (request, blinded) = GhostKeyRequest::new(
+            &ghost_signing_key.verifying_key(),
+            &issuer_key.to_public_key(),
+        );
+        let certificate = request
+            .certificate(&blinded.sign(&issuer_key).unwrap())
+            .unwrap();
+        let ghost = AuthorizedMember::with_ghost_key(
+            owner_id,
+            &ghost_signing_key,
+            certificate,
+            SystemTime::now(),
+        );
+        let (invitee, _) = create_test_member(owner_id, ghost.member.id());
+        let invitee = AuthorizedMember::new(invitee, &ghost_signing_key);
+        let (member, member_signing_key) = create_test_member(owner_id, owner_id);
+        let member = AuthorizedMember::new(member, &owner_signing_key);
+
+        // Ghost key members join without an inviter and can invite others
+        let mut members = MembersV1::default();
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MembersDelta::new(vec![
+                    ghost.clone(),
+                    invitee.clone(),
+                    member.clone(),
+                ])),
+            )
+            .unwrap();
+        assert_eq!(members.members.len(), 3);
+        assert_eq!(members.verify(&parent_state, &parameters), Ok(()));
+        assert_eq!(members.get_invite_chain(&ghost, &parameters), Ok(vec![]));
+        assert_eq!(
+            members.get_invite_chain(&invitee, &parameters),
+            Ok(vec![ghost.clone()])
+        );
+
+        // Only the owner can ban a member without an invite chain
+        let mut banned_state = parent_state.clone();
+        let ban = AuthorizedUserBan::new(
+            UserBan {
+                owner_member_id: owner_id,
+                banned_at: SystemTime::now(),
+                banned_user: ghost.member.id(),
+                expires_at: None,
+            },
+            member.member.id(),
+            &member_signing_key,
+        );
+        banned_state.bans = BansV1(vec![ban]);
+        let mut after_ban = members.clone();
+        after_ban
+            .apply_delta(&banned_state, &parameters, &None)
+            .unwrap();
+        assert_eq!(after_ban.members, members.members);
+
+        // Certificates from issuers the owner doesn't trust are rejected
+        let untrusted_state = ChatRoomStateV1::default();
+        assert_eq!(
+            MembersV1::default().apply_delta(
+                &untrusted_state,
+                &parameters,
+                &Some(MembersDelta::new(vec![ghost.clone()])),
+            ),
+            Err(RoomStateError::UntrustedGhostKeyIssuer {
+                member: ghost.member.id()
+            })
+        );
+        assert_eq!(
+            members.verify(&untrusted_state, &parameters),
+            Err(RoomStateError::UntrustedGhostKeyIssuer {
+                member: ghost.member.id()
+            })
+        );
+
+        // and once the owner stops trusting an issuer its members leave along with their invitees
+        members
+            .apply_delta(&untrusted_state, &parameters, &None)
+            .unwrap();
+        assert_eq!(members.members, vec![member]);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::room_state::member::{AuthorizedMember, MemberId, MemberIndex, MembersV1};
+use crate::room_state::ChatRoomParametersV1;
+use crate::ChatRoomStateV1;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::time::SystemTime;
+
+/*
+ A member can have more than one membership, eg. when they're invited again after being removed,
+ and memberships can be seen in any order. Which of them is in effect is decided again from all
+ of them whenever they change, and the others are kept as inactive memberships so that every peer
+ decides from the same ones.
+*/
+
+impl MembersV1 {
+    /// Replaces the members with the latest membership of each member that still stands. It stands
+    /// if it wasn't removed or banned, its ghost key issuer is trusted and whoever invited the
+    /// member is in the room, so members are decided from the owner down. Peers agree on the result whatever
+    /// order they saw the memberships in.
+    pub(super) fn pick_memberships(
+        &mut self,
+        memberships: &[AuthorizedMember],
+        parent_state: &ChatRoomStateV1,
+        parameters: &ChatRoomParametersV1,
+    ) {
+        let mut undecided: BTreeMap<MemberId, Vec<AuthorizedMember>> = BTreeMap::new();
+        for member in memberships {
+            undecided
+                .entry(member.member.id())
+                .or_default()
+                .push(member.clone());
+        }
+        for memberships in undecided.values_mut() {
+            memberships.sort_by_key(|m| Reverse((m.joined_at(), m.signature().to_bytes())));
+        }
+
+        // Every membership is one of `memberships`, whether it's in effect is decided here
+        let index = MemberIndex::new(&[], memberships, &self.removals, parameters);
+        let mut present = HashSet::new();
+        self.members.clear();
+        // Members whose inviters are waiting on each other are decided one at a time as if the
+        // undecided inviters weren't in the room
+        let mut stuck = false;
+        while !undecided.is_empty() {
+            let mut decided = Vec::new();
+            for (member_id, memberships) in &undecided {
+                let pick = Self::pick_membership(
+                    memberships,
+                    &undecided,
+                    stuck,
+                    &present,
+                    &index,
+                    parent_state,
+                    parameters,
+                );
+                if let Some(pick) = pick {
+                    decided.push((*member_id, pick));
+                    if stuck {
+                        break;
+                    }
+                }
+            }
+            stuck = decided.is_empty();
+            for (member_id, pick) in decided {
+                undecided.remove(&member_id);
+                if pick.is_some() {
+                    present.insert(member_id);
+                }
+                self.members.extend(pick);
+            }
+        }
+    }
+
+    /// The member's latest membership that stands, `None` if it depends on members that are still
+    /// undecided
+    fn pick_membership(
+        memberships: &[AuthorizedMember],
+        undecided: &BTreeMap<MemberId, Vec<AuthorizedMember>>,
+        stuck: bool,
+        present: &HashSet<MemberId>,
+        index: &MemberIndex,
+        parent_state: &ChatRoomStateV1,
+        parameters: &ChatRoomParametersV1,
+    ) -> Option<Option<AuthorizedMember>> {
+        for member in memberships {
+            let inviter_present = match member.inviter() {
+                None => true,
+                Some(inviter) if inviter == parameters.owner_id() => true,
+                Some(inviter) if present.contains(&inviter) => true,
+                Some(inviter) if undecided.contains_key(&inviter) && !stuck => return None,
+                Some(_) => false,
+            };
+            if inviter_present
+                && Self::verify_ghost_key_issuer(member, parent_state).is_ok()
+                && !index.is_removed(member)
+                && !index.is_banned(
+                    member,
+                    &parent_state.bans,
+                    &parent_state.configuration.configuration,
+                )
+            {
+                return Some(Some(member.clone()));
+            }
+        }
+        Some(None)
+    }
+
+    /// Keeps the memberships that aren't in effect, ended ones too as what the member posted before
+    /// is kept and their invitees may be back if they're invited again. Ghost keys from issuers
+    /// the room doesn't trust aren't kept, redemptions already are, and memberships whose inviter
+    /// isn't known any more can't be verified. Only `max_removals` of those a removal ended are
+    /// kept.
+    pub(super) fn keep_inactive(
+        &mut self,
+        memberships: Vec<AuthorizedMember>,
+        parent_state: &ChatRoomStateV1,
+        parameters: &ChatRoomParametersV1,
+    ) {
+        let active: HashSet<(MemberId, Option<SystemTime>)> = self
+            .members
+            .iter()
+            .map(|m| (m.member.id(), m.joined_at()))
+            .collect();
+        self.inactive = memberships
+            .into_iter()
+            .filter(|m| {
+                m.redemption().is_none()
+                    && Self::verify_ghost_key_issuer(m, parent_state).is_ok()
+                    && !active.contains(&(m.member.id(), m.joined_at()))
+            })
+            .collect();
+        loop {
+            let before = self.inactive.len();
+            let known: HashSet<MemberId> = self
+                .members
+                .iter()
+                .chain(&self.redemptions)
+                .chain(&self.inactive)
+                .map(|m| m.member.id())
+                .chain(self.removals.iter().map(|r| r.removal.member_id()))
+                .chain([parameters.owner_id()])
+                .collect();
+            self.inactive
+                .retain(|m| known.contains(&m.member.invited_by));
+            if self.inactive.len() == before {
+                break;
+            }
+        }
+
+        // Memberships a removal ended stay out of the room, so past `max_removals` those that
+        // started earliest are forgotten, except those of banners, whose bans would go with them.
+        // Others may be back once a ban ends or their inviter is invited again, forgetting them
+        // would depend on when the peer learned that.
+        let max_removals = parent_state.configuration.configuration.max_removals;
+        let index = MemberIndex::new(&[], std::iter::empty(), &self.removals, parameters);
+        let (mut ended, others): (Vec<_>, Vec<_>) = std::mem::take(&mut self.inactive)
+            .into_iter()
+            .partition(|m| index.is_removed(m));
+        if ended.len() > max_removals {
+            let banners: HashSet<MemberId> = parent_state
+                .bans
+                .0
+                .iter()
+                .map(|ban| ban.banned_by)
+                .collect();
+            ended.sort_by_key(|m| {
+                let id = m.member.id();
+                Reverse((
+                    banners.contains(&id),
+                    m.joined_at(),
+                    id,
+                    m.signature().to_bytes(),
+                ))
+            });
+            ended.truncate(max_removals);
+        }
+        self.inactive = others;
+        self.inactive.extend(ended);
+    }
+}
@@ -0,0 +1,548 @@
+use crate::room_state::ban::BansV1;
+use crate::room_state::configuration::Configuration;
+use crate::room_state::error::{InviteChainError, RoomStateError};
+use crate::room_state::member::{AuthorizedMember, AuthorizedRemoval, MemberId, MembersV1};
+use crate::room_state::ChatRoomParametersV1;
+use ed25519_dalek::VerifyingKey;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+
+/*
+ Finding a member in `MembersV1` means going through every membership, and checking a message,
+ reaction or invite chain takes a few of those lookups, so a pass over a large room takes quadratic
+ time or worse. A `MemberIndex` maps ids to memberships once for such a pass. It borrows what it
+ indexes, so it can't outlive a change to the members.
+
+ Checking an invite chain means checking every inviter's chain above it, `invite_chain_len`
+ remembers the result for each member so that every signature is checked once per pass.
+
+ Matching the members in the room with the redemptions kept for them is a lookup too, a
+ `MembershipSet` finds a membership by member and when they joined.
+*/
+
+/// Lookups by member id, see above
+pub struct MemberIndex<'a> {
+    parameters: &'a ChatRoomParametersV1,
+    /// The members in the room
+    members: HashMap<MemberId, &'a AuthorizedMember>,
+    /// Every membership that's kept, in effect or not, in the order they were indexed
+    memberships: HashMap<MemberId, Vec<&'a AuthorizedMember>>,
+    removals: HashMap<VerifyingKey, Vec<&'a AuthorizedRemoval>>,
+    /// The keys of removed members, who may have no membership left
+    removed: HashMap<MemberId, &'a VerifyingKey>,
+    /// The members in the room each member invited
+    invitees: HashMap<MemberId, Vec<MemberId>>,
+    /// The invite chain length of each member whose chain was checked
+    chain_lengths: RefCell<HashMap<MemberId, Result<usize, RoomStateError>>>,
+}
+
+impl<'a> MemberIndex<'a> {
+    pub(super) fn new(
+        members: &'a [AuthorizedMember],
+        memberships: impl IntoIterator<Item = &'a AuthorizedMember>,
+        removals: &'a [AuthorizedRemoval],
+        parameters: &'a ChatRoomParametersV1,
+    ) -> Self {
+        let mut by_id: HashMap<MemberId, Vec<&'a AuthorizedMember>> = HashMap::new();
+        for membership in memberships {
+            by_id
+                .entry(membership.member.id())
+                .or_default()
+                .push(membership);
+        }
+        let mut by_vk: HashMap<VerifyingKey, Vec<&'a AuthorizedRemoval>> = HashMap::new();
+        let mut removed = HashMap::new();
+        for removal in removals {
+            by_vk
+                .entry(removal.removal.member_vk)
+                .or_default()
+                .push(removal);
+            removed.insert(removal.removal.member_id(), &removal.removal.member_vk);
+        }
+        let mut invitees: HashMap<MemberId, Vec<MemberId>> = HashMap::new();
+        for member in members {
+            if let Some(inviter) = member.inviter() {
+                invitees
+                    .entry(inviter)
+                    .or_default()
+                    .push(member.member.id());
+            }
+        }
+        MemberIndex {
+            parameters,
+            // The first of duplicate members, as a scan would find
+            members: members.iter().rev().map(|m| (m.member.id(), m)).collect(),
+            memberships: by_id,
+            removals: by_vk,
+            removed,
+            invitees,
+            chain_lengths: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// The member in the room with this id, never the owner
+    pub fn member(&self, member_id: MemberId) -> Option<&'a AuthorizedMember> {
+        self.members.get(&member_id).copied()
+    }
+
+    /// Every membership of the member that's kept, in effect or not
+    pub fn memberships(
+        &self,
+        member_id: MemberId,
+    ) -> impl Iterator<Item = &'a AuthorizedMember> + '_ {
+        self.memberships
+            .get(&member_id)
+            .into_iter()
+            .flatten()
+            .copied()
+    }
+
+    /// See [`MembersV1::author_vk`]
+    pub fn author_vk(
+        &self,
+        author: MemberId,
+        time: SystemTime,
+        bans: &BansV1,
+        configuration: &Configuration,
+    ) -> Option<&'a VerifyingKey> {
+        if author == self.parameters.owner_id() {
+            return Some(&self.parameters.owner);
+        }
+        self.memberships(author)
+            .find(|m| {
+                m.joined_at() <= Some(time)
+                    && !self.removed_before(m, time)
+                    && self.stands(m, bans, configuration, &mut HashSet::new())
+            })
+            .map(|m| &m.member.member_vk)
+    }
+
+    /// See [`MembersV1::member_key`]
+    pub fn member_key(
+        &self,
+        member_id: MemberId,
+        bans: &BansV1,
+        configuration: &Configuration,
+    ) -> Option<&'a VerifyingKey> {
+        if member_id == self.parameters.owner_id() {
+            return Some(&self.parameters.owner);
+        }
+        self.memberships(member_id)
+            .find(|m| {
+                !self.is_removed(m) && self.stands(m, bans, configuration, &mut HashSet::new())
+            })
+            .map(|m| &m.member.member_vk)
+    }
+
+    /// The key the member's invitation or membership must be signed with, `None` if their inviter
+    /// isn't in the room
+    pub fn inviter_vk(&self, member: &AuthorizedMember) -> Option<&'a VerifyingKey> {
+        if member.member.invited_by == self.parameters.owner_id() {
+            return Some(&self.parameters.owner);
+        }
+        self.member(member.member.invited_by)
+            .map(|m| &m.member.member_vk)
+    }
+
+    /// See [`MembersV1::known_member_vk`]
+    pub fn known_member_vk(&self, member_id: MemberId) -> Option<&'a VerifyingKey> {
+        self.memberships(member_id)
+            .next()
+            .map(|m| &m.member.member_vk)
+    }
+
+    /// The key of the owner or anyone known to have been in the room, including those only a
+    /// removal is left of
+    pub(super) fn member_vk(&self, member_id: MemberId) -> Option<&'a VerifyingKey> {
+        if member_id == self.parameters.owner_id() {
+            return Some(&self.parameters.owner);
+        }
+        self.known_member_vk(member_id)
+            .or_else(|| self.removed.get(&member_id).copied())
+    }
+
+    /// The members in the room below `member_id` in the invite tree
+    pub(super) fn downstream(&self, member_id: MemberId) -> HashSet<MemberId> {
+        let mut downstream = HashSet::new();
+        let mut to_check = vec![member_id];
+        while let Some(current) = to_check.pop() {
+            for invitee in self.invitees.get(&current).into_iter().flatten() {
+                if downstream.insert(*invitee) {
+                    to_check.push(*invitee);
+                }
+            }
+        }
+        downstream
+    }
+
+    /// Whether neither the membership nor one of its inviter's, up to the owner, was banned
+    fn stands(
+        &self,
+        member: &AuthorizedMember,
+        bans: &BansV1,
+        configuration: &Configuration,
+        visited: &mut HashSet<(MemberId, Option<SystemTime>)>,
+    ) -> bool {
+        if self.is_banned(member, bans, configuration) {
+            return false;
+        }
+        match member.inviter() {
+            None => true,
+            Some(inviter) if inviter == self.parameters.owner_id() => true,
+            Some(inviter) => {
+                visited.insert((member.member.id(), member.joined_at()))
+                    && self
+                        .memberships(inviter)
+                        .any(|m| self.stands(m, bans, configuration, visited))
+            }
+        }
+    }
+
+    /// Whether the membership was ended by someone allowed to remove the member
+    pub(super) fn is_removed(&self, member: &AuthorizedMember) -> bool {
+        self.removals_of(member)
+            .any(|r| MembersV1::ends(r, member, self.parameters))
+    }
+
+    /// Whether the membership was ended by someone allowed to remove the member before `time`
+    fn removed_before(&self, member: &AuthorizedMember, time: SystemTime) -> bool {
+        self.removals_of(member)
+            .any(|r| r.removal.removed_at < time && MembersV1::ends(r, member, self.parameters))
+    }
+
+    fn removals_of(
+        &self,
+        member: &AuthorizedMember,
+    ) -> impl Iterator<Item = &'a AuthorizedRemoval> + '_ {
+        self.removals
+            .get(&member.member.member_vk)
+            .into_iter()
+            .flatten()
+            .copied()
+    }
+
+    /// Whether the member was banned by the owner, a moderator who may ban anyone or someone in
+    /// this membership's invite chain
+    pub(super) fn is_banned(
+        &self,
+        member: &AuthorizedMember,
+        bans: &BansV1,
+        configuration: &Configuration,
+    ) -> bool {
+        bans.in_effect().any(|ban| {
+            ban.ban.banned_user == member.member.id()
+                && (ban.banned_by == self.parameters.owner_id()
+                    || configuration
+                        .moderator_permissions(ban.banned_by)
+                        .ban_anyone
+                    || self.is_upline(ban.banned_by, member, &mut HashSet::new()))
+        })
+    }
+
+    /// Whether `banner` may ban `banned_user`. The owner and moderators who may ban anyone can ban
+    /// anyone but the owner, other members only those they're upline of in a kept membership, so
+    /// that they can't fill the room's bans with ids that never joined.
+    pub fn may_ban(
+        &self,
+        banner: MemberId,
+        banned_user: MemberId,
+        configuration: &Configuration,
+    ) -> bool {
+        if banned_user == self.parameters.owner_id() {
+            return false;
+        }
+        banner == self.parameters.owner_id()
+            || configuration.moderator_permissions(banner).ban_anyone
+            || self
+                .memberships(banned_user)
+                .any(|m| self.is_upline(banner, m, &mut HashSet::new()))
+    }
+
+    /// Whether `upline` invited the member, or invited one of their inviter's memberships and so
+    /// on. Every membership that is kept counts, whether it's in effect or not, so that a ban by
+    /// an upline inviter still applies while the member is out of the room.
+    fn is_upline(
+        &self,
+        upline: MemberId,
+        member: &AuthorizedMember,
+        visited: &mut HashSet<MemberId>,
+    ) -> bool {
+        match member.inviter() {
+            Some(inviter) if inviter == upline => true,
+            Some(inviter) if inviter != self.parameters.owner_id() && visited.insert(inviter) => {
+                self.memberships(inviter)
+                    .any(|m| self.is_upline(upline, m, visited))
+            }
+            _ => false,
+        }
+    }
+
+    /// The inviters of the member up to the owner or a ghost key member, checking that each one
+    /// signed the member below them in. See also `invite_chain_len`.
+    pub fn invite_chain(
+        &self,
+        member: &AuthorizedMember,
+    ) -> Result<Vec<AuthorizedMember>, RoomStateError> {
+        let mut invite_chain = Vec::new();
+        let mut visited = HashSet::new();
+        let mut current: &AuthorizedMember = member;
+        loop {
+            if !visited.insert(current.member.id()) {
+                return Err(RoomStateError::InviteChainBroken {
+                    member: member.member.id(),
+                    reason: InviteChainError::Circular,
+                });
+            }
+            match self.check_link(current)? {
+                Some(inviter) => {
+                    invite_chain.push(inviter.clone());
+                    current = inviter;
+                }
+                None => return Ok(invite_chain),
+            }
+        }
+    }
+
+    /// The length of the member's invite chain, checked as `invite_chain` does. Results are kept
+    /// for the members in the room, so checking everyone's chain checks each signature once.
+    pub fn invite_chain_len(&self, member: &AuthorizedMember) -> Result<usize, RoomStateError> {
+        let member_id = member.member.id();
+        // Another membership of the member may have a different inviter
+        if self.member(member_id) != Some(member) {
+            return self.invite_chain(member).map(|chain| chain.len());
+        }
+        if let Some(known) = self.chain_lengths.borrow().get(&member_id) {
+            return known.clone();
+        }
+
+        // Go up the chain to the first inviter whose chain is known or where it starts, then fill
+        // in the lengths on the way back down
+        let mut path: Vec<MemberId> = Vec::new();
+        let mut visited = HashSet::new();
+        let mut current: &AuthorizedMember = member;
+        let mut result = loop {
+            let current_id = current.member.id();
+            if let Some(known) = self.chain_lengths.borrow().get(&current_id) {
+                break known.clone();
+            }
+            if !visited.insert(current_id) {
+                break Err(RoomStateError::InviteChainBroken {
+                    member: current_id,
+                    reason: InviteChainError::Circular,
+                });
+            }
+            match self.check_link(current) {
+                Ok(Some(inviter)) => {
+                    path.push(current_id);
+                    current = inviter;
+                }
+                result => {
+                    let result = result.map(|_| 0);
+                    self.chain_lengths
+                        .borrow_mut()
+                        .insert(current_id, result.clone());
+                    break result;
+                }
+            }
+        };
+
+        let mut chain_lengths = self.chain_lengths.borrow_mut();
+        for member_id in path.into_iter().rev() {
+            result = match result {
+                Ok(len) => Ok(len + 1),
+                // Whoever's chain leads into a loop is in a loop too
+                Err(RoomStateError::InviteChainBroken {
+                    reason: InviteChainError::Circular,
+                    ..
+                }) => Err(RoomStateError::InviteChainBroken {
+                    member: member_id,
+                    reason: InviteChainError::Circular,
+                }),
+                Err(error) => Err(error),
+            };
+            chain_lengths.insert(member_id, result.clone());
+        }
+        result
+    }
+
+    /// Checks the member was signed in by their inviter, returns the inviter unless the chain
+    /// starts with the member
+    fn check_link(
+        &self,
+        member: &AuthorizedMember,
+    ) -> Result<Option<&'a AuthorizedMember>, RoomStateError> {
+        if member.member.invited_by == member.member.id() {
+            return Err(RoomStateError::InviteChainBroken {
+                member: member.member.id(),
+                reason: InviteChainError::SelfInvitation,
+            });
+        }
+        // Ghost key members sign themselves in and, like those the owner invited, are where their
+        // chain starts
+        if member.inviter().is_none() || member.member.invited_by == self.parameters.owner_id() {
+            member.verify_signature(&self.parameters.owner)?;
+            return Ok(None);
+        }
+        let inviter =
+            self.member(member.member.invited_by)
+                .ok_or(RoomStateError::InviteChainBroken {
+                    member: member.member.id(),
+                    reason: InviteChainError::InviterNotFound {
+                        inviter: member.member.invited_by,
+                    },
+                })?;
+        member.verify_signature(&inviter.member.member_vk)?;
+        Ok(Some(inviter))
+    }
+}
+
+/// Memberships by member and when they joined, to find one without comparing it with every other
+pub(super) struct MembershipSet<'a>(
+    HashMap<(MemberId, Option<SystemTime>), Vec<&'a AuthorizedMember>>,
+);
+
+impl<'a> MembershipSet<'a> {
+    pub(super) fn new(memberships: impl IntoIterator<Item = &'a AuthorizedMember>) -> Self {
+        let mut set: HashMap<_, Vec<_>> = HashMap::new();
+        for membership in memberships {
+            set.entry((membership.member.id(), membership.joined_at()))
+                .or_default()
+                .push(membership);
+        }
+        MembershipSet(set)
+    }
+
+    pub(super) fn contains(&self, membership: &AuthorizedMember) -> bool {
+        self.0
+            .get(&(membership.member.id(), membership.joined_at()))
+            .is_some_and(|memberships| memberships.contains(&membership))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::member::Member;
+    use ed25519_dalek::SigningKey;
+    use rand::rngs::OsRng;
+
+    fn member(
+        parameters: &ChatRoomParametersV1,
+        invited_by: MemberId,
+        signing_key: &SigningKey,
+    ) -> (AuthorizedMember, SigningKey) {
+        let member_signing_key = SigningKey::generate(&mut OsRng);
+        let member = Member {
+            owner_member_id: parameters.owner_id(),
+            invited_by,
+            member_vk: member_signing_key.verifying_key(),
+        };
+        (
+            AuthorizedMember::new(member, signing_key),
+            member_signing_key,
+        )
+    }
+
+    #[test]
+    fn test_invite_chain_len() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        let mut members = Vec::new();
+
+        // A chain of ten from the owner down
+        let (mut inviter_id, mut inviter_key) = (parameters.owner_id(), owner_signing_key.clone());
+        for _ in 0..10 {
+            let (m, key) = member(&parameters, inviter_id, &inviter_key);
+            inviter_id = m.member.id();
+            inviter_key = key;
+            members.push(m);
+        }
+        // Someone whose membership doesn't match what the owner signed, and their invitee
+        let (mut forged, _) = member(&parameters, parameters.owner_id(), &owner_signing_key);
+        let forged_key = SigningKey::generate(&mut OsRng);
+        forged.member.member_vk = forged_key.verifying_key();
+        let (below_forged, _) = member(&parameters, forged.member.id(), &forged_key);
+        // Someone whose inviter is gone
+        let gone_key = SigningKey::generate(&mut OsRng);
+        let (orphan, _) = member(&parameters, gone_key.verifying_key().into(), &gone_key);
+        members.extend([forged.clone(), below_forged.clone(), orphan.clone()]);
+
+        // Members listed in any order get the same lengths and errors as from their chains
+        members.reverse();
+        let state = MembersV1 {
+            members: members.clone(),
+            ..MembersV1::default()
+        };
+        let index = state.index(&parameters);
+        for m in &members {
+            let chain = index.invite_chain(m);
+            assert_eq!(index.invite_chain_len(m), chain.map(|c| c.len()));
+        }
+        assert_eq!(index.invite_chain_len(&members[3]), Ok(9));
+        assert!(matches!(
+            index.invite_chain_len(&below_forged),
+            Err(RoomStateError::InvalidSignature { .. })
+        ));
+        assert!(matches!(
+            index.invite_chain_len(&orphan),
+            Err(RoomStateError::InviteChainBroken {
+                reason: InviteChainError::InviterNotFound { .. },
+                ..
+            })
+        ));
+
+        // Another membership of a member is checked along its own chain
+        let (other, _) = member(&parameters, parameters.owner_id(), &owner_signing_key);
+        let rejoined = AuthorizedMember::new(
+            Member {
+                member_vk: members[3].member.member_vk,
+                ..other.member
+            },
+            &owner_signing_key,
+        );
+        assert_eq!(index.invite_chain_len(&rejoined), Ok(0));
+    }
+
+    #[test]
+    fn test_circular_invite_chain() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        // Two members who invited each other, and someone one of them invited
+        let (a_key, b_key) = (
+            SigningKey::generate(&mut OsRng),
+            SigningKey::generate(&mut OsRng),
+        );
+        let signed_in = |key: &SigningKey, inviter: &SigningKey| {
+            let member = Member {
+                owner_member_id: parameters.owner_id(),
+                invited_by: inviter.verifying_key().into(),
+                member_vk: key.verifying_key(),
+            };
+            AuthorizedMember::new(member, inviter)
+        };
+        let (c, _) = member(&parameters, a_key.verifying_key().into(), &a_key);
+        let state = MembersV1 {
+            members: vec![
+                c.clone(),
+                signed_in(&a_key, &b_key),
+                signed_in(&b_key, &a_key),
+            ],
+            ..MembersV1::default()
+        };
+        let index = state.index(&parameters);
+        for m in &state.members {
+            assert_eq!(
+                index.invite_chain_len(m),
+                Err(RoomStateError::InviteChainBroken {
+                    member: m.member.id(),
+                    reason: InviteChainError::Circular,
+                })
+            );
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use crate::room_state::error::{RoomStateError, StateField};
+use crate::room_state::member::{AuthorizedMember, Member, MemberAuthorization, MemberId};
+use crate::util::{sign_struct, truncated_base64, verify_struct};
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use freenet_scaffold::util::{blake3_hash, VersionedHash};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::SystemTime;
+
+/*
+ An invitation lets a member invite someone without knowing their key in advance. The inviter signs
+ an invitation for a fresh keypair and shares its signing key in a link, whoever holds the link can
+ then add themselves to the room by signing their own `Member` with it, see `InvitationToken`.
+
+ The contract has no clock, so expiry is checked against the time the invitee says they redeemed
+ the invitation. A modified client could date a redemption back, so once the room time (see
+ `BansV1`) is past an invitation's expiry its redemptions are refused unless the room already has
+ them. A redemption that's still on its way to a peer then is refused by it too, keep `expires_at`
+ well ahead of when the invitation is shared. The room only remembers `max_redemptions`
+ redemptions by members who have since left, so an invitation many of whose invitees left can be
+ used again.
+*/
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Hash, Clone, Debug)]
+pub struct Invitation {
+    pub owner_member_id: MemberId,
+    pub invited_by: MemberId,
+    /// Members who redeem the invitation sign themselves in with the matching signing key
+    pub invitation_vk: VerifyingKey,
+    pub expires_at: SystemTime,
+    /// How many members can join with the invitation, unlimited if `None`
+    pub max_uses: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct AuthorizedInvitation {
+    pub invitation: Invitation,
+    pub signature: Signature,
+}
+
+impl fmt::Debug for AuthorizedInvitation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthorizedInvitation")
+            .field("invitation", &self.invitation)
+            .field(
+                "signature",
+                &format_args!("{}", truncated_base64(self.signature.to_bytes())),
+            )
+            .finish()
+    }
+}
+
+const INVITATION_ID_CONTEXT: &str = "river 2025-01 invitation id";
+
+#[derive(Eq, PartialEq, Hash, Serialize, Deserialize, Clone, Debug, Ord, PartialOrd)]
+pub struct InvitationId(pub VersionedHash);
+
+impl AuthorizedInvitation {
+    pub fn new(invitation: Invitation, inviter_signing_key: &SigningKey) -> Self {
+        assert_eq!(
+            invitation.invited_by,
+            inviter_signing_key.verifying_key().into(),
+            "The invitation's invited_by must match the inviter's signing key"
+        );
+        Self {
+            signature: sign_struct(&invitation, inviter_signing_key),
+            invitation,
+        }
+    }
+
+    pub fn id(&self) -> InvitationId {
+        InvitationId(blake3_hash(
+            INVITATION_ID_CONTEXT,
+            &self.signature.to_bytes(),
+        ))
+    }
+
+    pub fn verify_signature(&self, inviter_vk: &VerifyingKey) -> Result<(), RoomStateError> {
+        verify_struct(&self.invitation, &self.signature, inviter_vk).map_err(|_| {
+            RoomStateError::InvalidSignature {
+                field: StateField::Invitation,
+                id: self.id().0,
+            }
+        })
+    }
+}
+
+/// Shows that a member joined by redeeming an invitation, their `AuthorizedMember` is then signed
+/// with the invitation's key rather than by the inviter
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Redemption {
+    pub invitation: AuthorizedInvitation,
+    pub redeemed_at: SystemTime,
+}
+
+impl Redemption {
+    /// Checks the invitation was made by `inviter_vk` for `member`, and that `signature` was made
+    /// with the invitation's key before it expired
+    pub(super) fn verify(
+        &self,
+        member: &Member,
+        signature: &Signature,
+        inviter_vk: &VerifyingKey,
+    ) -> Result<(), RoomStateError> {
+        let invitation = &self.invitation.invitation;
+        self.invitation.verify_signature(inviter_vk)?;
+        if invitation.invited_by != member.invited_by
+            || invitation.owner_member_id != member.owner_member_id
+        {
+            return Err(RoomStateError::InvalidSignature {
+                field: StateField::Member,
+                id: member.id().0,
+            });
+        }
+        if self.redeemed_at > invitation.expires_at {
+            return Err(RoomStateError::InvitationExpired {
+                invitation: self.invitation.id(),
+            });
+        }
+        verify_struct(
+            &(member, self.redeemed_at),
+            signature,
+            &invitation.invitation_vk,
+        )
+        .map_err(|_| RoomStateError::InvalidSignature {
+            field: StateField::Member,
+            id: member.id().0,
+        })
+    }
+}
+
+/// Marks an encoded `InvitationToken`
+const INVITATION_TOKEN_PREFIX: &str = "river:v1:invite:";
+
+/// Everything needed to join a room with an invitation, shared as a link
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct InvitationToken {
+    pub room_owner: VerifyingKey,
+    pub invitation: AuthorizedInvitation,
+    pub invitation_sk: SigningKey,
+}
+
+impl fmt::Debug for InvitationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The signing key is deliberately left out
+        f.debug_struct("InvitationToken")
+            .field("invitation", &self.invitation)
+            .finish()
+    }
+}
+
+impl InvitationToken {
+    /// Creates an invitation to the room owned by `room_owner` for a freshly generated key
+    pub fn new(
+        room_owner: VerifyingKey,
+        inviter_signing_key: &SigningKey,
+        expires_at: SystemTime,
+        max_uses: Option<u32>,
+    ) -> Self {
+        let invitation_sk = SigningKey::generate(&mut OsRng);
+        let invitation = Invitation {
+            owner_member_id: room_owner.into(),
+            invited_by: inviter_signing_key.verifying_key().into(),
+            invitation_vk: invitation_sk.verifying_key(),
+            expires_at,
+            max_uses,
+        };
+        Self {
+            room_owner,
+            invitation: AuthorizedInvitation::new(invitation, inviter_signing_key),
+            invitation_sk,
+        }
+    }
+
+    /// Signs `member_vk` into the room, fails if the invitation expired before `redeemed_at`
+    pub fn redeem(
+        &self,
+        member_vk: VerifyingKey,
+        redeemed_at: SystemTime,
+    ) -> Result<AuthorizedMember, RoomStateError> {
+        let invitation = &self.invitation.invitation;
+        if redeemed_at > invitation.expires_at {
+            return Err(RoomStateError::InvitationExpired {
+                invitation: self.invitation.id(),
+            });
+        }
+        let member = Member {
+            owner_member_id: invitation.owner_member_id,
+            invited_by: invitation.invited_by,
+            member_vk,
+        };
+        Ok(AuthorizedMember {
+            authorization: MemberAuthorization::Invitation {
+                signature: sign_struct((&member, redeemed_at), &self.invitation_sk),
+                redemption: Box::new(Redemption {
+                    invitation: self.invitation.clone(),
+                    redeemed_at,
+                }),
+            },
+            member,
+        })
+    }
+
+    pub fn encode(&self) -> String {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(self, &mut bytes).expect("Serialization should not fail");
+        format!(
+            "{}{}",
+            INVITATION_TOKEN_PREFIX,
+            general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+        )
+    }
+
+    /// Recovers a token from `encode`, ignoring anything before the prefix such as the address of
+    /// the page the link points to
+    pub fn decode(link: &str) -> Option<Self> {
+        let (_, encoded) = link.split_once(INVITATION_TOKEN_PREFIX)?;
+        let bytes = general_purpose::URL_SAFE_NO_PAD.decode(encoded).ok()?;
+        ciborium::de::from_reader(bytes.as_slice()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_invitation_token_round_trip() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let token = InvitationToken::new(
+            owner_signing_key.verifying_key(),
+            &owner_signing_key,
+            SystemTime::now() + Duration::from_secs(3600),
+            Some(1),
+        );
+        let link = format!("https://example.com/river/#{}", token.encode());
+        assert_eq!(InvitationToken::decode(&link), Some(token));
+        assert_eq!(InvitationToken::decode("https://example.com/river/"), None);
+    }
+
+    #[test]
+    fn test_redeem_expired_invitation() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let expires_at = SystemTime::now();
+        let token = InvitationToken::new(
+            owner_signing_key.verifying_key(),
+            &owner_signing_key,
+            expires_at,
+            None,
+        );
+        let member_vk = SigningKey::generate(&mut OsRng).verifying_key();
+
+        let member = token.redeem(member_vk, expires_at).unwrap();
+        assert_eq!(
+            member.verify_signature(&owner_signing_key.verifying_key()),
+            Ok(())
+        );
+        assert_eq!(
+            token.redeem(member_vk, expires_at + Duration::from_secs(1)),
+            Err(RoomStateError::InvitationExpired {
+                invitation: token.invitation.id()
+            })
+        );
+
+        // Backdating the redemption after the fact breaks the signature
+        let mut backdated = member.clone();
+        if let MemberAuthorization::Invitation { redemption, .. } = &mut backdated.authorization {
+            redemption.redeemed_at -= Duration::from_secs(60);
+        }
+        assert!(matches!(
+            backdated.verify_signature(&owner_signing_key.verifying_key()),
+            Err(RoomStateError::InvalidSignature {
+                field: StateField::Member,
+                ..
+            })
+        ));
+    }
+}
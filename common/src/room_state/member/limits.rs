@@ -0,0 +1,473 @@
+use crate::room_state::member::{
+    AuthorizedMember, InvitationId, MemberId, MembersV1, MembershipSet,
+};
+use crate::room_state::ChatRoomParametersV1;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+
+/*
+ Limits on the members a room keeps: an invitation can be redeemed at most `max_uses` times, only
+ the latest `max_redemptions` of members who are gone are remembered and the room holds at most
+ `max_members`. Peers can see members in any order, so what's dropped past a limit only depends on
+ the members themselves.
+*/
+
+impl MembersV1 {
+    /// Drops the redemptions the room doesn't keep yet of invitations that expired by `room_time`.
+    /// The contract has no clock, such a redemption may have been dated back to before the
+    /// expiry. Those the room keeps were seen before, so they stay.
+    pub(super) fn refuse_late_redemptions(
+        &self,
+        memberships: &mut Vec<AuthorizedMember>,
+        room_time: Option<SystemTime>,
+    ) {
+        let Some(room_time) = room_time else {
+            return;
+        };
+        let kept = MembershipSet::new(self.memberships());
+        memberships.retain(|m| {
+            m.redemption().is_none_or(|redemption| {
+                redemption.invitation.invitation.expires_at > room_time || kept.contains(m)
+            })
+        });
+    }
+
+    /// Drops redemptions that can no longer be verified because the inviter is gone, and
+    /// redemptions beyond an invitation's `max_uses` along with the members who made them. Peers can
+    /// see concurrent redemptions in any order so the earliest are kept, by time and then id.
+    pub(super) fn enforce_invitation_limits(&mut self, parameters: &ChatRoomParametersV1) {
+        let mut redemptions = std::mem::take(&mut self.redemptions);
+        let index = self.index(parameters);
+        redemptions.retain(|m| {
+            index
+                .inviter_vk(m)
+                .is_some_and(|vk| m.redemption().is_some() && m.verify_signature(vk).is_ok())
+        });
+        redemptions.sort_by_key(|m| {
+            let redemption = m.redemption().expect("Checked above");
+            (redemption.redeemed_at, m.member.id())
+        });
+
+        let mut uses: HashMap<InvitationId, u32> = HashMap::new();
+        let mut over_limit = HashSet::new();
+        redemptions.retain(|m| {
+            let invitation = &m.redemption().expect("Checked above").invitation;
+            let count = uses.entry(invitation.id()).or_default();
+            *count += 1;
+            let allowed = invitation
+                .invitation
+                .max_uses
+                .is_none_or(|max_uses| *count <= max_uses);
+            if !allowed {
+                over_limit.insert(m.member.id());
+            }
+            allowed
+        });
+        let mut removed = over_limit.clone();
+        for member_id in &over_limit {
+            removed.extend(index.downstream(*member_id));
+        }
+        self.redemptions = redemptions;
+        if !removed.is_empty() {
+            self.members.retain(|m| !removed.contains(&m.member.id()));
+        }
+    }
+
+    /// Forgets the oldest redemptions of members who are gone beyond `max_redemptions`, by time
+    /// and then id. An invitation they redeemed can then be used that many more times.
+    pub(super) fn forget_redemptions(&mut self, max_redemptions: usize) {
+        let members = MembershipSet::new(&self.members);
+        let (present, mut departed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.redemptions)
+            .into_iter()
+            .partition(|m| members.contains(m));
+        if departed.len() > max_redemptions {
+            departed.sort_by_key(|m| {
+                let redemption = m.redemption().expect("Only redemptions are kept");
+                Reverse((redemption.redeemed_at, m.member.id()))
+            });
+            departed.truncate(max_redemptions);
+        }
+        self.redemptions = present;
+        self.redemptions.extend(departed);
+    }
+
+    /// If the number of members exceeds the specified limit, remove the members with the longest
+    /// invite chains until the limit is satisfied
+    pub(super) fn remove_excess_members(
+        &mut self,
+        parameters: &ChatRoomParametersV1,
+        max_members: usize,
+    ) {
+        if self.members.len() <= max_members {
+            return;
+        }
+        // Whoever has the longest chain invited none of the others, so removing them doesn't
+        // change anyone's chain
+        let index = self.index(parameters);
+        let mut chain_lengths: Vec<(usize, MemberId)> = self
+            .members
+            .iter()
+            .map(|m| (index.invite_chain_len(m).unwrap(), m.member.id()))
+            .collect();
+        // Of those with the same chain length the last ones go first
+        chain_lengths.sort_by_key(|(length, _)| *length);
+        let excess: HashSet<MemberId> = chain_lengths
+            .split_off(max_members)
+            .into_iter()
+            .map(|(_, member_id)| member_id)
+            .collect();
+        self.members.retain(|m| !excess.contains(&m.member.id()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::ban::{AuthorizedUserBan, UserBan};
+    use crate::room_state::error::{RoomStateError, StateField};
+    use crate::room_state::member::tests::create_test_member;
+    use crate::room_state::member::{
+        AuthorizedMember, AuthorizedRemoval, InvitationToken, MembersDelta, Removal,
+    };
+    use crate::ChatRoomStateV1;
+    use ed25519_dalek::{SigningKey, VerifyingKey};
+    use freenet_scaffold::ComposableState;
+    use rand::rngs::OsRng;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_invitation_redemptions() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
+        let owner_id: MemberId = owner_verifying_key.into();
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_verifying_key,
+        };
+        let parent_state = ChatRoomStateV1::default();
+
+        // A member invites through a single use invitation
+        let (inviter, inviter_signing_key) = create_test_member(owner_id, owner_id);
+        let inviter = AuthorizedMember::new(inviter, &owner_signing_key);
+        let now = SystemTime::now();
+        let token = InvitationToken::new(
+            owner_verifying_key,
+            &inviter_signing_key,
+            now + std::time::Duration::from_secs(3600),
+            Some(1),
+        );
+        let redeem = |seconds: u64| {
+            token
+                .redeem(
+                    SigningKey::generate(&mut OsRng).verifying_key(),
+                    now + std::time::Duration::from_secs(seconds),
+                )
+                .unwrap()
+        };
+        let first = redeem(20);
+        let later = redeem(30);
+        let earlier = redeem(10);
+
+        let mut members = MembersV1::default();
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MembersDelta::new(vec![inviter.clone(), first.clone()])),
+            )
+            .unwrap();
+        assert!(members.members.contains(&first));
+        assert_eq!(members.redemptions, vec![first.clone()]);
+        assert_eq!(members.verify(&parent_state, &parameters), Ok(()));
+
+        // The invitation is used up
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MembersDelta::new(vec![later.clone()])),
+            )
+            .unwrap();
+        assert!(!members.members.contains(&later));
+
+        // A peer that saw an earlier redemption first keeps it instead, so all peers converge
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MembersDelta::new(vec![earlier.clone()])),
+            )
+            .unwrap();
+        assert_eq!(members.members.len(), 2);
+        assert!(members.members.contains(&earlier));
+        assert_eq!(members.redemptions, vec![earlier.clone()]);
+
+        // The redemption outlives a ban so the invitation can't be used again
+        let mut banned_state = parent_state.clone();
+        banned_state.bans.0.push(AuthorizedUserBan::new(
+            UserBan {
+                owner_member_id: owner_id,
+                banned_at: now,
+                banned_user: earlier.member.id(),
+                expires_at: None,
+            },
+            inviter.member.id(),
+            &inviter_signing_key,
+        ));
+        members
+            .apply_delta(
+                &banned_state,
+                &parameters,
+                &Some(MembersDelta::new(vec![first.clone()])),
+            )
+            .unwrap();
+        assert_eq!(members.members, vec![inviter]);
+        assert_eq!(members.redemptions, vec![earlier]);
+
+        // Going over the limit doesn't verify
+        members.redemptions.push(first);
+        assert!(matches!(
+            members.verify(&parent_state, &parameters),
+            Err(RoomStateError::LimitExceeded {
+                field: StateField::Invitation,
+                count: 2,
+                max: 1,
+            })
+        ));
+    }
+
+    #[test]
+    fn test_backdated_redemption_refused() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
+        let owner_id: MemberId = owner_verifying_key.into();
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_verifying_key,
+        };
+        let parent_state = ChatRoomStateV1::default();
+        let now = SystemTime::now();
+        let at = |seconds: u64| now + std::time::Duration::from_secs(seconds);
+
+        let (inviter, inviter_signing_key) = create_test_member(owner_id, owner_id);
+        let inviter = AuthorizedMember::new(inviter, &owner_signing_key);
+        let token = InvitationToken::new(owner_verifying_key, &inviter_signing_key, at(60), None);
+        let redeem = |seconds| {
+            token
+                .redeem(
+                    SigningKey::generate(&mut OsRng).verifying_key(),
+                    at(seconds),
+                )
+                .unwrap()
+        };
+        let (redeemed, backdated) = (redeem(10), redeem(20));
+        let mut members = MembersV1::default();
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MembersDelta::new(vec![inviter.clone(), redeemed.clone()])),
+            )
+            .unwrap();
+
+        // The owner bans someone after the invitation expired, a redemption the room hadn't seen
+        // by then is refused however early it says it was made
+        let mut later_state = parent_state.clone();
+        later_state.bans.0.push(AuthorizedUserBan::new(
+            UserBan {
+                owner_member_id: owner_id,
+                banned_at: at(120),
+                banned_user: create_test_member(owner_id, owner_id).0.id(),
+                expires_at: None,
+            },
+            owner_id,
+            &owner_signing_key,
+        ));
+        let mut accepted = members.clone();
+        accepted
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MembersDelta::new(vec![backdated.clone()])),
+            )
+            .unwrap();
+        assert!(accepted.members.contains(&backdated));
+        members
+            .apply_delta(
+                &later_state,
+                &parameters,
+                &Some(MembersDelta::new(vec![backdated.clone()])),
+            )
+            .unwrap();
+        assert!(!members.members.contains(&backdated));
+        assert_eq!(members.redemptions, vec![redeemed.clone()]);
+
+        // Those the room already had stay
+        accepted
+            .apply_delta(&later_state, &parameters, &None)
+            .unwrap();
+        assert!(accepted.members.contains(&backdated));
+        assert!(accepted.members.contains(&redeemed));
+    }
+
+    #[test]
+    fn test_departed_redemptions_limited() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
+        let owner_id: MemberId = owner_verifying_key.into();
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_verifying_key,
+        };
+        let mut parent_state = ChatRoomStateV1::default();
+        parent_state.configuration.configuration.max_redemptions = 1;
+
+        // Three members join through an invitation anyone can use
+        let (inviter, inviter_signing_key) = create_test_member(owner_id, owner_id);
+        let inviter = AuthorizedMember::new(inviter, &owner_signing_key);
+        let now = SystemTime::now();
+        let at = |seconds: u64| now + std::time::Duration::from_secs(seconds);
+        let token = InvitationToken::new(owner_verifying_key, &inviter_signing_key, at(3600), None);
+        let redeemers: Vec<(SigningKey, AuthorizedMember)> = (1..=3)
+            .map(|i| {
+                let signing_key = SigningKey::generate(&mut OsRng);
+                let member = token
+                    .redeem(signing_key.verifying_key(), at(i * 10))
+                    .unwrap();
+                (signing_key, member)
+            })
+            .collect();
+        let mut members = MembersV1::default();
+        let mut added = vec![inviter.clone()];
+        added.extend(redeemers.iter().map(|(_, member)| member.clone()));
+        members
+            .apply_delta(&parent_state, &parameters, &Some(MembersDelta::new(added)))
+            .unwrap();
+        assert_eq!(members.redemptions.len(), 3);
+
+        // Two of them leave, only the latest of those is remembered
+        let leaves = redeemers[..2]
+            .iter()
+            .map(|(signing_key, member)| {
+                AuthorizedRemoval::new(
+                    Removal {
+                        owner_member_id: owner_id,
+                        member_vk: member.member.member_vk,
+                        removed_by: signing_key.verifying_key(),
+                        removed_at: at(40),
+                        signed_for: None,
+                    },
+                    signing_key,
+                )
+            })
+            .collect();
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MembersDelta::remove(leaves)),
+            )
+            .unwrap();
+        let mut kept = vec![redeemers[1].1.clone(), redeemers[2].1.clone()];
+        kept.sort_by_key(|m| m.member.id());
+        assert_eq!(members.redemptions, kept);
+        assert_eq!(members.verify(&parent_state, &parameters), Ok(()));
+
+        // A peer that still has the forgotten redemption doesn't bring it back
+        let before = members.clone();
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MembersDelta::new(vec![redeemers[0].1.clone()])),
+            )
+            .unwrap();
+        assert_eq!(members, before);
+
+        // Keeping more doesn't verify
+        members.redemptions.push(redeemers[0].1.clone());
+        assert!(matches!(
+            members.verify(&parent_state, &parameters),
+            Err(RoomStateError::LimitExceeded {
+                field: StateField::Invitation,
+                count: 2,
+                max: 1,
+            })
+        ));
+    }
+
+    #[test]
+    fn test_remove_excess_members() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
+        let owner_id = owner_verifying_key.into();
+
+        let (member1, member1_signing_key) = create_test_member(owner_id, owner_id);
+        let (member2, member2_signing_key) = create_test_member(owner_id, member1.id());
+        let (member3, _) = create_test_member(owner_id, member2.id());
+
+        let authorized_member1 = AuthorizedMember::new(member1.clone(), &owner_signing_key);
+        let authorized_member2 = AuthorizedMember::new(member2.clone(), &member1_signing_key);
+        let authorized_member3 = AuthorizedMember::new(member3.clone(), &member2_signing_key);
+
+        let mut members = MembersV1 {
+            members: vec![authorized_member1, authorized_member2, authorized_member3],
+            ..Default::default()
+        };
+
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_verifying_key,
+        };
+
+        // Test case 1: No excess members
+        members.remove_excess_members(&parameters, 3);
+        assert_eq!(members.members.len(), 3);
+
+        // Test case 2: One excess member
+        members.remove_excess_members(&parameters, 2);
+        assert_eq!(members.members.len(), 2);
+        assert!(members
+            .members
+            .iter()
+            .any(|m| m.member.id() == member1.id()));
+        assert!(members
+            .members
+            .iter()
+            .any(|m| m.member.id() == member2.id()));
+        assert!(!members
+            .members
+            .iter()
+            .any(|m| m.member.id() == member3.id()));
+    }
+
+    #[test]
+    fn test_remove_excess_members_edge_cases() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
+        let owner_id = owner_verifying_key.into();
+
+        let (member1, member1_signing_key) = create_test_member(owner_id, owner_id);
+        let (member2, _) = create_test_member(owner_id, member1.id());
+
+        let authorized_member1 = AuthorizedMember::new(member1.clone(), &owner_signing_key);
+        let authorized_member2 = AuthorizedMember::new(member2.clone(), &member1_signing_key);
+
+        let mut members = MembersV1 {
+            members: vec![authorized_member1.clone(), authorized_member2.clone()],
+            ..Default::default()
+        };
+
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_verifying_key,
+        };
+
+        // Test with max_members set to 0
+        members.remove_excess_members(&parameters, 0);
+        assert_eq!(members.members.len(), 0);
+
+        // Reset members
+        members.members = vec![authorized_member1.clone(), authorized_member2.clone()];
+
+        // Test with max_members greater than current number of members
+        members.remove_excess_members(&parameters, 3);
+        assert_eq!(members.members.len(), 2);
+    }
+}
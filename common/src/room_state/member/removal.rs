@@ -0,0 +1,534 @@
+use crate::room_state::error::{RoomStateError, StateField};
+use crate::room_state::member::{AuthorizedMember, MemberId, MemberIndex, MembersV1};
+use crate::room_state::ChatRoomParametersV1;
+use crate::util::{sign_struct, truncated_base64, verify_struct};
+use crate::ChatRoomStateV1;
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use freenet_scaffold::util::{blake3_hash, VersionedHash};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::SystemTime;
+
+/*
+ A removal takes a member out of the room without banning them, either because they left or
+ because the owner or whoever invited them kicked them. Removals are kept so that peers
+ which still have the member drop them too, but they only end memberships that started before
+ `removed_at`, so the member can be invited again later.
+
+ Both keys are part of the removal so that it can be verified after the member and whoever removed
+ them are gone.
+*/
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Removal {
+    pub owner_member_id: MemberId,
+    pub member_vk: VerifyingKey,
+    /// The member's own key if they left
+    pub removed_by: VerifyingKey,
+    pub removed_at: SystemTime,
+    /// Whoever removed the member in the room this one continues, when the new owner signed the
+    /// removal again for them, see `ChatRoomStateV1::succeeded_by`. Only the owner can sign for
+    /// someone else.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_for: Option<VerifyingKey>,
+}
+
+impl Removal {
+    pub fn member_id(&self) -> MemberId {
+        self.member_vk.into()
+    }
+
+    /// Who removed the member, the member themselves if they left
+    pub fn remover(&self) -> VerifyingKey {
+        match self.signed_for {
+            Some(remover) if MemberId::from(self.removed_by) == self.owner_member_id => remover,
+            _ => self.removed_by,
+        }
+    }
+
+    pub fn is_leave(&self) -> bool {
+        self.remover() == self.member_vk
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AuthorizedRemoval {
+    pub removal: Removal,
+    pub signature: Signature,
+}
+
+impl fmt::Debug for AuthorizedRemoval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthorizedRemoval")
+            .field("removal", &self.removal)
+            .field(
+                "signature",
+                &format_args!("{}", truncated_base64(self.signature.to_bytes())),
+            )
+            .finish()
+    }
+}
+
+const REMOVAL_ID_CONTEXT: &str = "river 2025-01 removal id";
+
+#[derive(Eq, PartialEq, Hash, Serialize, Deserialize, Clone, Copy, Debug, Ord, PartialOrd)]
+pub struct RemovalId(pub VersionedHash);
+
+impl AuthorizedRemoval {
+    pub fn new(removal: Removal, remover_signing_key: &SigningKey) -> Self {
+        assert_eq!(
+            removal.removed_by,
+            remover_signing_key.verifying_key(),
+            "The removal's removed_by must match the remover's signing key"
+        );
+        Self {
+            signature: sign_struct(&removal, remover_signing_key),
+            removal,
+        }
+    }
+
+    pub fn id(&self) -> RemovalId {
+        RemovalId(blake3_hash(REMOVAL_ID_CONTEXT, &self.signature.to_bytes()))
+    }
+
+    pub fn verify_signature(&self) -> Result<(), RoomStateError> {
+        verify_struct(&self.removal, &self.signature, &self.removal.removed_by).map_err(|_| {
+            RoomStateError::InvalidSignature {
+                field: StateField::Removal,
+                id: self.id().0,
+            }
+        })
+    }
+}
+
+impl MembersV1 {
+    /// Checks a removal was signed by whoever it says removed the member, whether they were allowed
+    /// to is checked against the memberships it's applied to, see `is_authorized_removal`
+    pub(super) fn verify_removal(
+        removal: &AuthorizedRemoval,
+        parameters: &ChatRoomParametersV1,
+    ) -> Result<(), RoomStateError> {
+        if removal.removal.owner_member_id != parameters.owner_id() {
+            return Err(RoomStateError::Unauthorized {
+                field: StateField::Removal,
+                id: removal.id().0,
+            });
+        }
+        removal.verify_signature()
+    }
+
+    /// Keeps the latest removal of each member by each remover, by time and then id, so that peers
+    /// agree whatever order they see them in
+    pub(super) fn add_removals(&mut self, removals: &[AuthorizedRemoval]) {
+        let key = |r: &AuthorizedRemoval| (r.removal.member_id(), r.removal.removed_by);
+        let order = |r: &AuthorizedRemoval| (r.removal.removed_at, r.id());
+        let mut positions: HashMap<(MemberId, VerifyingKey), usize> = self
+            .removals
+            .iter()
+            .enumerate()
+            .map(|(i, r)| (key(r), i))
+            .collect();
+        for removal in removals {
+            match positions.entry(key(removal)) {
+                Entry::Occupied(entry) => {
+                    let existing = &mut self.removals[*entry.get()];
+                    if order(removal) > order(existing) {
+                        *existing = removal.clone();
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(self.removals.len());
+                    self.removals.push(removal.clone());
+                }
+            }
+        }
+    }
+
+    /// Whether the removal is of a member with a known membership that the remover may end
+    pub(super) fn is_authorized_removal(
+        removal: &AuthorizedRemoval,
+        index: &MemberIndex,
+        parameters: &ChatRoomParametersV1,
+    ) -> bool {
+        index.memberships(removal.removal.member_id()).any(|m| {
+            m.member.member_vk == removal.removal.member_vk
+                && Self::can_remove(m, &removal.removal.removed_by, parameters)
+        })
+    }
+
+    /// The latest time the room vouches for, that of bans (see `BansV1`) or of the owner's latest
+    /// removal. Removals by anyone else can't be dated past it to outlast the others, the owner's
+    /// latest one always outlasts them so this time only moves forward.
+    pub(super) fn room_time(
+        &self,
+        parent_state: &ChatRoomStateV1,
+        parameters: &ChatRoomParametersV1,
+    ) -> Option<SystemTime> {
+        let owner_removals = self
+            .removals
+            .iter()
+            .filter(|r| r.removal.removed_by == parameters.owner)
+            .map(|r| r.removal.removed_at);
+        owner_removals
+            .chain(parent_state.bans.room_time(parent_state, parameters))
+            .max()
+    }
+
+    /// Forgets the oldest removals beyond `max_removals`, by time and then id. Removals by anyone
+    /// but the owner count as no later than `room_time`, or the epoch if the room vouches for no
+    /// time yet, and go before the owner's of the same time.
+    pub(super) fn forget_removals(
+        &mut self,
+        max_removals: usize,
+        room_time: Option<SystemTime>,
+        parameters: &ChatRoomParametersV1,
+    ) {
+        if self.removals.len() <= max_removals {
+            return;
+        }
+        let room_time = room_time.unwrap_or(SystemTime::UNIX_EPOCH);
+        self.removals.sort_by_key(|r| {
+            let by_owner = r.removal.removed_by == parameters.owner;
+            let removed_at = if by_owner {
+                r.removal.removed_at
+            } else {
+                r.removal.removed_at.min(room_time)
+            };
+            Reverse((removed_at, by_owner, r.id()))
+        });
+        self.removals.truncate(max_removals);
+    }
+
+    /// Whether `remover` may take `member` out of the room: the member themselves, whoever invited
+    /// them, or the owner. Upline inviters further up can't, as the chain above the inviter can
+    /// change when members are invited again, which would bring kicked members back on some peers
+    fn can_remove(
+        member: &AuthorizedMember,
+        remover: &VerifyingKey,
+        parameters: &ChatRoomParametersV1,
+    ) -> bool {
+        *remover == member.member.member_vk
+            || *remover == parameters.owner
+            || MemberId::from(remover) == member.member.invited_by
+    }
+
+    /// Whether the removal ends the membership, it must be by someone allowed to remove the member
+    /// and not from before they joined
+    pub(super) fn ends(
+        removal: &AuthorizedRemoval,
+        member: &AuthorizedMember,
+        parameters: &ChatRoomParametersV1,
+    ) -> bool {
+        removal.removal.member_vk == member.member.member_vk
+            && member.joined_at() <= Some(removal.removal.removed_at)
+            && Self::can_remove(member, &removal.removal.removed_by, parameters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::member::tests::create_test_member;
+    use crate::room_state::member::MembersDelta;
+    use freenet_scaffold::ComposableState;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_removals_limited() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
+        let owner_id: MemberId = owner_verifying_key.into();
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_verifying_key,
+        };
+        let mut parent_state = ChatRoomStateV1::default();
+        parent_state.configuration.configuration.max_removals = 2;
+        let start = SystemTime::now();
+        let at = |seconds: u64| start + std::time::Duration::from_secs(seconds);
+
+        let join = |invited_by: MemberId, signing_key: &SigningKey, seconds| {
+            let (member, member_signing_key) = create_test_member(owner_id, invited_by);
+            (
+                AuthorizedMember::new_at(member, signing_key, at(seconds)),
+                member_signing_key,
+            )
+        };
+        let (inviter, inviter_signing_key) = join(owner_id, &owner_signing_key, 0);
+        let (early, early_signing_key) = join(owner_id, &owner_signing_key, 1);
+        let (late, late_signing_key) = join(owner_id, &owner_signing_key, 2);
+        let (invitee, _) = join(inviter.member.id(), &inviter_signing_key, 3);
+        let leave = |member: &AuthorizedMember, signing_key: &SigningKey, seconds| {
+            AuthorizedRemoval::new(
+                Removal {
+                    owner_member_id: owner_id,
+                    member_vk: member.member.member_vk,
+                    removed_by: signing_key.verifying_key(),
+                    removed_at: at(seconds),
+                    signed_for: None,
+                },
+                signing_key,
+            )
+        };
+
+        let mut members = MembersV1::default();
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MembersDelta::new(vec![
+                    inviter.clone(),
+                    early.clone(),
+                    late.clone(),
+                    invitee.clone(),
+                ])),
+            )
+            .unwrap();
+        let forgotten = leave(&early, &early_signing_key, 10);
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MembersDelta::remove(vec![
+                    forgotten.clone(),
+                    leave(&late, &late_signing_key, 11),
+                    leave(&inviter, &owner_signing_key, 12),
+                ])),
+            )
+            .unwrap();
+
+        // Only the latest removals are remembered, the owner's kick vouches for the time of the
+        // others. The member whose removal was forgotten is back, as they would be on a peer that
+        // never saw it.
+        assert_eq!(members.removals.len(), 2);
+        assert!(!members.removals.contains(&forgotten));
+        assert_eq!(members.members, vec![early.clone()]);
+
+        // A member who's invited again and leaves again has two memberships the same removal
+        // ended, only the latest of those are kept. The invitee of a forgotten one is kept as
+        // their inviter may be back.
+        let rejoined = AuthorizedMember::new_at(late.member.clone(), &owner_signing_key, at(13));
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MembersDelta {
+                    added: vec![rejoined.clone()],
+                    removed: vec![leave(&rejoined, &late_signing_key, 14)],
+                }),
+            )
+            .unwrap();
+        assert_eq!(members.removals.len(), 2);
+        let mut inactive = members.inactive.clone();
+        inactive.sort_by_key(|m| m.joined_at());
+        assert_eq!(inactive, vec![late, invitee, rejoined]);
+        assert_eq!(members.verify(&parent_state, &parameters), Ok(()));
+
+        // A peer that still has the forgotten removal, or a membership past the limit, changes
+        // nothing
+        let before = members.clone();
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MembersDelta {
+                    added: vec![inviter],
+                    removed: vec![forgotten.clone()],
+                }),
+            )
+            .unwrap();
+        assert_eq!(members, before);
+
+        // Keeping more doesn't verify
+        members.removals.push(forgotten);
+        assert!(matches!(
+            members.verify(&parent_state, &parameters),
+            Err(RoomStateError::LimitExceeded {
+                field: StateField::Removal,
+                count: 3,
+                max: 2,
+            })
+        ));
+    }
+
+    #[test]
+    fn test_member_removals() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_verifying_key = VerifyingKey::from(&owner_signing_key);
+        let owner_id: MemberId = owner_verifying_key.into();
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_verifying_key,
+        };
+        let parent_state = ChatRoomStateV1::default();
+        let start = SystemTime::now();
+        let at = |seconds: u64| start + std::time::Duration::from_secs(seconds);
+
+        let (inviter, inviter_signing_key) = create_test_member(owner_id, owner_id);
+        let inviter = AuthorizedMember::new_at(inviter, &owner_signing_key, at(0));
+        let (invitee, invitee_signing_key) = create_test_member(owner_id, inviter.member.id());
+        let invitee = AuthorizedMember::new_at(invitee, &inviter_signing_key, at(1));
+        let (other, other_signing_key) = create_test_member(owner_id, owner_id);
+        let other = AuthorizedMember::new(other, &owner_signing_key);
+        let remove = |member: &AuthorizedMember, signing_key: &SigningKey, seconds| {
+            MembersDelta::remove(vec![AuthorizedRemoval::new(
+                Removal {
+                    owner_member_id: owner_id,
+                    member_vk: member.member.member_vk,
+                    removed_by: signing_key.verifying_key(),
+                    removed_at: at(seconds),
+                    signed_for: None,
+                },
+                signing_key,
+            )])
+        };
+
+        let mut members = MembersV1::default();
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MembersDelta::new(vec![
+                    inviter.clone(),
+                    invitee.clone(),
+                    other.clone(),
+                ])),
+            )
+            .unwrap();
+        let before = members.clone();
+
+        // Members can't kick members they didn't invite, or anyone who was never a member
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(remove(&invitee, &other_signing_key, 2)),
+            )
+            .unwrap();
+        assert_eq!(members.members.len(), 3);
+        assert!(members.removals.is_empty());
+        let (stranger, stranger_signing_key) = create_test_member(owner_id, owner_id);
+        let stranger = AuthorizedMember::new(stranger, &owner_signing_key);
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(remove(&stranger, &stranger_signing_key, 2)),
+            )
+            .unwrap();
+        assert!(members.removals.is_empty());
+
+        // but can kick those they did
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(remove(&invitee, &inviter_signing_key, 2)),
+            )
+            .unwrap();
+        assert_eq!(members.members, {
+            let mut remaining = vec![inviter.clone(), other.clone()];
+            remaining.sort_by_key(|m| m.member.id());
+            remaining
+        });
+        assert_eq!(members.verify(&parent_state, &parameters), Ok(()));
+
+        // A member who leaves takes the members they invited with them
+        let mut left = before.clone();
+        left.apply_delta(
+            &parent_state,
+            &parameters,
+            &Some(remove(&inviter, &inviter_signing_key, 2)),
+        )
+        .unwrap();
+        assert_eq!(left.members, vec![other.clone()]);
+
+        // The invitee can be invited again, peers which still have the old membership catch up
+        let reinvited =
+            AuthorizedMember::new_at(invitee.member.clone(), &inviter_signing_key, at(3));
+        members
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MembersDelta::new(vec![reinvited.clone()])),
+            )
+            .unwrap();
+        assert!(members.members.contains(&reinvited));
+        let mut stale = before.clone();
+        let delta = members.delta(
+            &parent_state,
+            &parameters,
+            &stale.summarize(&parent_state, &parameters),
+        );
+        stale
+            .apply_delta(&parent_state, &parameters, &delta)
+            .unwrap();
+        assert_eq!(stale, members);
+
+        // Removals have to be signed by the remover
+        let mut forged = remove(&other, &invitee_signing_key, 4);
+        forged.removed[0].removal.removed_by = owner_verifying_key;
+        assert!(matches!(
+            members.apply_delta(&parent_state, &parameters, &Some(forged)),
+            Err(RoomStateError::InvalidSignature {
+                field: StateField::Removal,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_future_dated_removals_dont_outlast_the_owners() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_id: MemberId = owner_signing_key.verifying_key().into();
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        let mut parent_state = ChatRoomStateV1::default();
+        parent_state.configuration.configuration.max_removals = 1;
+        let start = SystemTime::now();
+        let at = |seconds: u64| start + std::time::Duration::from_secs(seconds);
+
+        let (kicked, _) = create_test_member(owner_id, owner_id);
+        let kicked = AuthorizedMember::new_at(kicked, &owner_signing_key, at(0));
+        let (leaving, leaving_signing_key) = create_test_member(owner_id, owner_id);
+        let leaving = AuthorizedMember::new_at(leaving, &owner_signing_key, at(0));
+        let removal = |member: &AuthorizedMember, signing_key: &SigningKey, seconds| {
+            AuthorizedRemoval::new(
+                Removal {
+                    owner_member_id: owner_id,
+                    member_vk: member.member.member_vk,
+                    removed_by: signing_key.verifying_key(),
+                    removed_at: at(seconds),
+                    signed_for: None,
+                },
+                signing_key,
+            )
+        };
+        let kick = removal(&kicked, &owner_signing_key, 3);
+        let leave = removal(&leaving, &leaving_signing_key, 1_000_000);
+
+        // Whichever order they're seen in, the leave counts as no later than the owner's kick
+        for removals in [[kick.clone(), leave.clone()], [leave.clone(), kick.clone()]] {
+            let mut members = MembersV1::default();
+            members
+                .apply_delta(
+                    &parent_state,
+                    &parameters,
+                    &Some(MembersDelta::new(vec![kicked.clone(), leaving.clone()])),
+                )
+                .unwrap();
+            for removal in removals {
+                members
+                    .apply_delta(
+                        &parent_state,
+                        &parameters,
+                        &Some(MembersDelta::remove(vec![removal])),
+                    )
+                    .unwrap();
+            }
+            assert_eq!(members.removals, vec![kick.clone()]);
+        }
+    }
+}
@@ -1,11 +1,13 @@
+use crate::room_state::error::{RoomStateError, StateField};
 use crate::room_state::member::MemberId;
 use crate::room_state::ChatRoomParametersV1;
 use crate::room_state::ChatRoomStateV1;
 use crate::util::{sign_struct, verify_struct};
 use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use freenet_scaffold::util::{blake3_hash, VersionedHash};
 use freenet_scaffold::ComposableState;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::HashMap;
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct MemberInfoV1 {
@@ -22,17 +24,18 @@ impl Default for MemberInfoV1 {
 
 impl ComposableState for MemberInfoV1 {
     type ParentState = ChatRoomStateV1;
-    type Summary = Vec<MemberId>;
+    type Summary = Vec<MemberInfoVersion>;
     type Delta = Vec<AuthorizedMemberInfo>;
     type Parameters = ChatRoomParametersV1;
+    type Error = RoomStateError;
 
     fn verify(
         &self,
         parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
-    ) -> Result<(), String> {
-        let members_by_id = parent_state.members.members_by_member_id();
+    ) -> Result<(), Self::Error> {
         let owner_id = parameters.owner_id();
+        let members = parent_state.members.index(parameters);
 
         for member_info in &self.member_info {
             let member_id = member_info.member_info.member_id;
@@ -41,16 +44,20 @@ impl ComposableState for MemberInfoV1 {
                 // If this is the owner's member info, verify against owner's key
                 member_info.verify_signature(parameters)?;
             } else {
-                // For non-owner members, verify they exist in members list
-                let member = members_by_id.get(&member_id).ok_or_else(|| {
-                    format!(
-                        "MemberInfo exists for non-existent member: {:?}",
-                        member_id
+                // For non-owner members, verify they haven't been removed or banned
+                let member_vk = members
+                    .member_key(
+                        member_id,
+                        &parent_state.bans,
+                        &parent_state.configuration.configuration,
                     )
-                })?;
+                    .ok_or(RoomStateError::UnknownAuthor {
+                        field: StateField::MemberInfo,
+                        author: member_id,
+                    })?;
                 
                 // Verify the signature with member's key
-                member_info.verify_signature_with_key(&member.member.member_vk)?;
+                member_info.verify_signature_with_key(member_vk)?;
             }
         }
         Ok(())
@@ -63,7 +70,9 @@ impl ComposableState for MemberInfoV1 {
     ) -> Self::Summary {
         self.member_info
             .iter()
-            .map(|info| info.member_info.member_id)
+            .map(|info| {
+                MemberInfoVersion::Versioned(info.member_info.member_id, info.member_info.version)
+            })
             .collect()
     }
 
@@ -73,11 +82,16 @@ impl ComposableState for MemberInfoV1 {
         _parameters: &Self::Parameters,
         old_state_summary: &Self::Summary,
     ) -> Option<Self::Delta> {
-        let old_members: HashSet<_> = old_state_summary.iter().collect();
+        let old_versions: HashMap<_, _> =
+            old_state_summary.iter().map(|old| old.key()).collect();
         let delta: Vec<AuthorizedMemberInfo> = self
             .member_info
             .iter()
-            .filter(|info| !old_members.contains(&info.member_info.member_id))
+            .filter(|info| {
+                old_versions
+                    .get(&info.member_info.member_id)
+                    .is_none_or(|&old| info.member_info.version > old)
+            })
             .cloned()
             .collect();
         if delta.is_empty() {
@@ -92,7 +106,8 @@ impl ComposableState for MemberInfoV1 {
         parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
         delta: &Option<Self::Delta>,
-    ) -> Result<(), String> {
+    ) -> Result<(), Self::Error> {
+        let members = parent_state.members.index(parameters);
         if let Some(delta) = delta {
             for member_info in delta {
                 let member_id = &member_info.member_info.member_id;
@@ -100,9 +115,17 @@ impl ComposableState for MemberInfoV1 {
                 if *member_id == parameters.owner_id() {
                     // If it's the owner, verify against the room owner's key
                     member_info.verify_signature(parameters)?;
-                } else if let Some(member) = parent_state.members.members_by_member_id().get(member_id) {
+                } else if let Some(member_vk) = members.member_key(
+                    *member_id,
+                    &parent_state.bans,
+                    &parent_state.configuration.configuration,
+                ) {
                     // For non-owners, verify against their member key
-                    member_info.verify_signature_with_key(&member.member.member_vk)?;
+                    member_info.verify_signature_with_key(member_vk)?;
+                } else {
+                    // The member may have been removed, eg. banned, by a peer that hasn't seen the
+                    // info yet, so this isn't an error. Like messages it's dropped.
+                    continue;
                 }
                 
                 // Update or add the member info
@@ -111,7 +134,7 @@ impl ComposableState for MemberInfoV1 {
                     .iter_mut()
                     .find(|info| info.member_info.member_id == *member_id)
                 {
-                    if member_info.member_info.version > existing_info.member_info.version {
+                    if member_info.order_key() > existing_info.order_key() {
                         *existing_info = member_info.clone();
                     }
                 } else {
@@ -119,19 +142,90 @@ impl ComposableState for MemberInfoV1 {
                 }
             }
         }
-        // Always remove any member info that is not in parent_state.members
-        let member_map = parent_state.members.members_by_member_id();
+        // Always remove any member info of members who were removed or banned. Those out of the
+        // room because their inviter was removed keep theirs, they may be back.
         self.member_info.retain(|info| {
-            parameters.owner_id() == info.member_info.member_id
-                ||
-            member_map
-                .contains_key(&info.member_info.member_id)
+            members
+                .member_key(
+                    info.member_info.member_id,
+                    &parent_state.bans,
+                    &parent_state.configuration.configuration,
+                )
+                .is_some()
         });
+        self.member_info.sort_by_key(|info| info.member_info.member_id);
 
         Ok(())
     }
 }
 
+impl MemberInfoV1 {
+    /// The id prefix of every member info, see `AuthorizedMemberInfo::order_key`. V2 summaries
+    /// carry it next to the version, the V1 summary can't as its encoding is fixed.
+    pub fn summarize_ids(&self) -> Vec<(MemberId, u64)> {
+        self.member_info
+            .iter()
+            .map(|info| (info.member_info.member_id, info.order_key().1))
+            .collect()
+    }
+
+    /// Like `delta`, but also sends the infos that win a tie with those of the summary, given the
+    /// id prefixes it came with
+    pub fn delta_with_ids(
+        &self,
+        old_state_summary: &[MemberInfoVersion],
+        old_ids: &[(MemberId, u64)],
+    ) -> Option<Vec<AuthorizedMemberInfo>> {
+        let old_ids: HashMap<_, _> = old_ids.iter().copied().collect();
+        let old_keys: HashMap<_, _> = old_state_summary
+            .iter()
+            .map(|old| {
+                let (member_id, version) = old.key();
+                // Without its id an info of the same version can't be beaten
+                let id = old_ids.get(&member_id).copied().unwrap_or(u64::MAX);
+                (member_id, (version, id))
+            })
+            .collect();
+        let delta: Vec<AuthorizedMemberInfo> = self
+            .member_info
+            .iter()
+            .filter(|info| {
+                old_keys
+                    .get(&info.member_info.member_id)
+                    .is_none_or(|&old| info.order_key() > old)
+            })
+            .cloned()
+            .collect();
+        if delta.is_empty() {
+            None
+        } else {
+            Some(delta)
+        }
+    }
+}
+
+/// What the summary lists for a member info
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MemberInfoVersion {
+    /// The member id and version of the info
+    Versioned(MemberId, u32),
+    /// Just the member id, as summaries listed before they carried versions. Any info of a later
+    /// version than 0 is sent for it.
+    Listed(MemberId),
+}
+
+impl MemberInfoVersion {
+    pub fn key(&self) -> (MemberId, u32) {
+        match *self {
+            MemberInfoVersion::Versioned(member_id, version) => (member_id, version),
+            MemberInfoVersion::Listed(member_id) => (member_id, 0),
+        }
+    }
+}
+
+const MEMBER_INFO_ID_CONTEXT: &str = "river 2025-01 member info id";
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AuthorizedMemberInfo {
     pub member_info: MemberInfo,
@@ -155,13 +249,31 @@ impl AuthorizedMemberInfo {
         }
     }
 
-    pub fn verify_signature(&self, parameters: &ChatRoomParametersV1) -> Result<(), String> {
+    pub fn id(&self) -> VersionedHash {
+        blake3_hash(MEMBER_INFO_ID_CONTEXT, &self.signature.to_bytes())
+    }
+
+    /// Orders infos of the same member so that peers keep the same one. A member using their key
+    /// on two devices may publish the same version from both, the id then decides between them.
+    /// Only a prefix of the id is used, it's in V2 summaries.
+    pub fn order_key(&self) -> (u32, u64) {
+        let id = self.id().as_bytes();
+        let mut prefix = [0; 8];
+        prefix.copy_from_slice(&id[..8]);
+        (self.member_info.version, u64::from_be_bytes(prefix))
+    }
+
+    pub fn verify_signature(&self, parameters: &ChatRoomParametersV1) -> Result<(), RoomStateError> {
         self.verify_signature_with_key(&parameters.owner)
     }
 
-    pub fn verify_signature_with_key(&self, verifying_key: &VerifyingKey) -> Result<(), String> {
-        verify_struct(&self.member_info, &self.signature, verifying_key)
-            .map_err(|e| format!("Invalid signature: {}", e))
+    pub fn verify_signature_with_key(&self, verifying_key: &VerifyingKey) -> Result<(), RoomStateError> {
+        verify_struct(&self.member_info, &self.signature, verifying_key).map_err(|_| {
+            RoomStateError::InvalidSignature {
+                field: StateField::MemberInfo,
+                id: self.member_info.member_id.0,
+            }
+        })
     }
 
     // Helper method for tests
@@ -184,7 +296,7 @@ mod tests {
     use super::*;
     use ed25519_dalek::{Signer, SigningKey};
     use rand::rngs::OsRng;
-    use crate::room_state::member::{AuthorizedMember, Member};
+    use crate::room_state::member::{AuthorizedMember, Member, MemberAuthorization};
 
     fn create_test_member_info(member_id: MemberId) -> MemberInfo {
         MemberInfo {
@@ -247,13 +359,11 @@ mod tests {
             verify_result.is_err(),
             "Expected verification to fail, but it succeeded"
         );
-        if let Err(err) = verify_result {
-            assert!(
-                err.contains("MemberInfo exists for non-existent member"),
-                "Unexpected error message: {}",
-                err
-            );
-        }
+        assert!(
+            matches!(verify_result, Err(RoomStateError::UnknownAuthor { .. })),
+            "Unexpected error: {:?}",
+            verify_result
+        );
 
         // Test with invalid signature
         let invalid_authorized_member_info = authorized_member_info.with_invalid_signature();
@@ -265,13 +375,11 @@ mod tests {
             verify_result.is_err(),
             "Expected verification to fail, but it succeeded"
         );
-        if let Err(err) = verify_result {
-            assert!(
-                err.contains("Invalid signature"),
-                "Unexpected error message: {}",
-                err
-            );
-        }
+        assert!(
+            matches!(verify_result, Err(RoomStateError::InvalidSignature { .. })),
+            "Unexpected error: {:?}",
+            verify_result
+        );
     }
 
     #[test]
@@ -291,7 +399,7 @@ mod tests {
 
         let summary = member_info_v1.summarize(&parent_state, &parameters);
         assert_eq!(summary.len(), 1);
-        assert!(summary.contains(&member_id));
+        assert!(summary.contains(&MemberInfoVersion::Versioned(member_id, 1)));
     }
 
     #[test]
@@ -315,7 +423,7 @@ mod tests {
             owner: owner_signing_key.verifying_key(),
         };
 
-        let old_summary = vec![member_id1];
+        let old_summary = vec![MemberInfoVersion::Versioned(member_id1, 1)];
         let delta = member_info_v1.delta(&parent_state, &parameters, &old_summary);
 
         assert!(delta.is_some());
@@ -347,10 +455,13 @@ mod tests {
                 invited_by: owner_id,
                 member_vk: member_verifying_key,
             },
-            signature: owner_signing_key
-                .sign("TestUser".as_bytes())
-                .to_bytes()
-                .into(),
+            authorization: MemberAuthorization::Invite {
+                signature: owner_signing_key
+                    .sign("TestUser".as_bytes())
+                    .to_bytes()
+                    .into(),
+                joined_at: None,
+            },
         });
 
         let parameters = ChatRoomParametersV1 {
@@ -388,7 +499,8 @@ mod tests {
 
         let result = member_info_v1.apply_delta(&parent_state, &parameters, &Some(non_existent_delta));
         println!("Result: {:?}", result);
-        assert!(result.is_err());
+        assert!(result.is_ok());
+        assert_eq!(member_info_v1.member_info.len(), 1, "Info for a non-existent member should be ignored");
 
         // Test applying delta with an older version (should not update)
         println!("Applying delta with an older version");
@@ -417,10 +529,13 @@ mod tests {
                 invited_by: owner_id,
                 member_vk: new_member_verifying_key,
             },
-            signature: owner_signing_key
-                .sign("NewTestUser".as_bytes())
-                .to_bytes()
-                .into(),
+            authorization: MemberAuthorization::Invite {
+                signature: owner_signing_key
+                    .sign("NewTestUser".as_bytes())
+                    .to_bytes()
+                    .into(),
+                joined_at: None,
+            },
         });
 
         let multi_delta = vec![updated_authorized_member_info.clone(), new_authorized_member_info.clone()];
@@ -482,22 +597,175 @@ mod tests {
         assert_eq!(delta.unwrap().len(), 5);
 
         // Test when all members are old
-        let old_summary: Vec<MemberId> = member_infos
+        let old_summary: Vec<MemberInfoVersion> = member_infos
             .iter()
-            .map(|info| info.member_info.member_id)
+            .map(|info| {
+                MemberInfoVersion::Versioned(info.member_info.member_id, info.member_info.version)
+            })
             .collect();
         let delta = member_info_v1.delta(&parent_state, &parameters, &old_summary);
         assert!(delta.is_none());
 
         // Test with a mix of new and old members
         let old_summary = vec![
-            member_infos[0].member_info.member_id,
-            member_infos[1].member_info.member_id,
+            MemberInfoVersion::Versioned(member_infos[0].member_info.member_id, 1),
+            MemberInfoVersion::Versioned(member_infos[1].member_info.member_id, 1),
         ];
         let delta = member_info_v1.delta(&parent_state, &parameters, &old_summary);
         assert_eq!(delta.unwrap().len(), 3);
     }
 
+    #[test]
+    fn test_member_info_v1_delta_sends_newer_versions() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_id = owner_signing_key.verifying_key().into();
+        let parent_state = ChatRoomStateV1::default();
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+
+        let mut member_info = create_test_member_info(owner_id);
+        member_info.version = 2;
+        member_info.preferred_nickname = "Renamed".to_string();
+        let member_info_v1 = MemberInfoV1 {
+            member_info: vec![AuthorizedMemberInfo::new(member_info, &owner_signing_key)],
+        };
+
+        let summary = vec![MemberInfoVersion::Versioned(owner_id, 1)];
+        let delta = member_info_v1.delta(&parent_state, &parameters, &summary);
+        assert_eq!(delta.unwrap().len(), 1);
+        let summary = vec![MemberInfoVersion::Versioned(owner_id, 2)];
+        let delta = member_info_v1.delta(&parent_state, &parameters, &summary);
+        assert!(delta.is_none());
+    }
+
+    #[test]
+    fn test_renamed_member_info_reaches_peers_with_the_old_one() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_id = owner_signing_key.verifying_key().into();
+        let parent_state = ChatRoomStateV1::default();
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+
+        let original = create_test_member_info(owner_id);
+        let mut renamed = original.clone();
+        renamed.version += 1;
+        renamed.preferred_nickname = "Renamed".to_string();
+        let state = |member_info: &MemberInfo| MemberInfoV1 {
+            member_info: vec![AuthorizedMemberInfo::new(member_info.clone(), &owner_signing_key)],
+        };
+
+        // Whichever peer merges the other's state, both end up with the rename
+        let mut old = state(&original);
+        old.merge(&parent_state, &parameters, &state(&renamed))
+            .unwrap();
+        assert_eq!(old, state(&renamed));
+        let mut new = state(&renamed);
+        new.merge(&parent_state, &parameters, &state(&original))
+            .unwrap();
+        assert_eq!(new, state(&renamed));
+    }
+
+    #[test]
+    fn test_summary_of_member_ids_decodes() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_id: MemberId = owner_signing_key.verifying_key().into();
+        let parent_state = ChatRoomStateV1::default();
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+
+        // Summaries used to list the ids alone
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&vec![owner_id], &mut bytes).unwrap();
+        let summary: Vec<MemberInfoVersion> = ciborium::de::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(summary, vec![MemberInfoVersion::Listed(owner_id)]);
+
+        // The first version of an info isn't sent again, later ones are
+        let mut member_info = create_test_member_info(owner_id);
+        member_info.version = 0;
+        let mut member_info_v1 = MemberInfoV1 {
+            member_info: vec![AuthorizedMemberInfo::new(member_info.clone(), &owner_signing_key)],
+        };
+        assert!(member_info_v1
+            .delta(&parent_state, &parameters, &summary)
+            .is_none());
+        member_info.version = 1;
+        member_info_v1.member_info =
+            vec![AuthorizedMemberInfo::new(member_info, &owner_signing_key)];
+        assert!(member_info_v1
+            .delta(&parent_state, &parameters, &summary)
+            .is_some());
+
+        // Summaries with versions keep their encoding
+        let versioned = vec![MemberInfoVersion::Versioned(owner_id, 1)];
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&versioned, &mut bytes).unwrap();
+        let mut expected = Vec::new();
+        ciborium::ser::into_writer(&vec![(owner_id, 1u32)], &mut expected).unwrap();
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn test_same_version_from_two_devices_converges() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_id = owner_signing_key.verifying_key().into();
+        let parent_state = ChatRoomStateV1::default();
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+
+        // Both devices pick the version after the one they've seen
+        let infos: Vec<AuthorizedMemberInfo> = ["Laptop", "Phone"]
+            .iter()
+            .map(|nickname| {
+                let mut member_info = create_test_member_info(owner_id);
+                member_info.version = 2;
+                member_info.preferred_nickname = nickname.to_string();
+                AuthorizedMemberInfo::new(member_info, &owner_signing_key)
+            })
+            .collect();
+        let (winner, loser) = if infos[0].order_key() > infos[1].order_key() {
+            (&infos[0], &infos[1])
+        } else {
+            (&infos[1], &infos[0])
+        };
+
+        let mut first = MemberInfoV1::default();
+        let mut second = MemberInfoV1::default();
+        for info in &infos {
+            first
+                .apply_delta(&parent_state, &parameters, &Some(vec![info.clone()]))
+                .unwrap();
+        }
+        for info in infos.iter().rev() {
+            second
+                .apply_delta(&parent_state, &parameters, &Some(vec![info.clone()]))
+                .unwrap();
+        }
+        assert_eq!(first, second);
+        assert_eq!(first.member_info, vec![winner.clone()]);
+
+        // A peer that kept the other one is sent the winner once it sends the ids, but not the
+        // other way around
+        let losing_state = MemberInfoV1 {
+            member_info: vec![loser.clone()],
+        };
+        let loser_summary = losing_state.summarize(&parent_state, &parameters);
+        assert!(first
+            .delta(&parent_state, &parameters, &loser_summary)
+            .is_none());
+        assert_eq!(
+            first.delta_with_ids(&loser_summary, &losing_state.summarize_ids()),
+            Some(vec![winner.clone()])
+        );
+        let winner_summary = first.summarize(&parent_state, &parameters);
+        assert!(losing_state
+            .delta_with_ids(&winner_summary, &first.summarize_ids())
+            .is_none());
+    }
+
     #[test]
     fn test_room_owner_member_info() {
         let owner_signing_key = SigningKey::generate(&mut OsRng);
@@ -517,7 +785,10 @@ mod tests {
                 invited_by: owner_id,
                 member_vk: owner_verifying_key,
             },
-            signature: owner_signing_key.sign("TestOwner".as_bytes()).to_bytes().into(),
+            authorization: MemberAuthorization::Invite {
+                signature: owner_signing_key.sign("TestOwner".as_bytes()).to_bytes().into(),
+                joined_at: None,
+            },
         });
 
         let parameters = ChatRoomParametersV1 {
@@ -558,7 +829,10 @@ mod tests {
                 invited_by: owner_id,
                 member_vk: member_verifying_key,
             },
-            signature: owner_signing_key.sign("TestMember".as_bytes()).to_bytes().into(),
+            authorization: MemberAuthorization::Invite {
+                signature: owner_signing_key.sign("TestMember".as_bytes()).to_bytes().into(),
+                joined_at: None,
+            },
         });
 
         let parameters = ChatRoomParametersV1 {
@@ -1,54 +1,96 @@
+use crate::room_state::error::{RoomStateError, StateField};
 use crate::room_state::member::MemberId;
+use crate::room_state::secret::verify_sealed;
 use crate::room_state::ChatRoomParametersV1;
-use crate::util::sign_struct;
-use crate::util::{truncated_base64, verify_struct};
 use crate::ChatRoomStateV1;
-use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
-use freenet_scaffold::util::{fast_hash, FastHash};
+use freenet_scaffold::collections::BoundedLog;
+use freenet_scaffold::id_set::IdSet;
 use freenet_scaffold::ComposableState;
 use serde::{Deserialize, Serialize};
-use std::fmt;
-use std::time::SystemTime;
+use std::collections::{HashMap, HashSet};
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+mod action;
+mod authorized;
+mod reply;
+
+pub use action::{
+    AuthorizedMessageActionV1, MessageAction, MessageActionId, MessageActionV1, MessageContent,
+};
+pub use authorized::{AuthorizedMessageV1, Message, MessageId, MessageV1, MessageV2};
+use reply::verify_reply;
+pub use reply::ReplyTarget;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
 pub struct MessagesV1 {
+    /// The latest `max_recent_messages` messages, ordered by time
+    pub messages: BoundedLog<AuthorizedMessageV1>,
+    /// Edits and deletions of `messages`, only those that still matter are kept
+    #[serde(default)]
+    pub actions: Vec<AuthorizedMessageActionV1>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct MessagesSummary {
+    pub messages: IdSet<MessageId>,
+    pub actions: IdSet<MessageActionId>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct MessagesDelta {
     pub messages: Vec<AuthorizedMessageV1>,
+    pub actions: Vec<AuthorizedMessageActionV1>,
 }
 
 impl ComposableState for MessagesV1 {
     type ParentState = ChatRoomStateV1;
-    type Summary = Vec<MessageId>;
-    type Delta = Vec<AuthorizedMessageV1>;
+    type Summary = MessagesSummary;
+    type Delta = MessagesDelta;
     type Parameters = ChatRoomParametersV1;
+    type Error = RoomStateError;
 
     fn verify(
         &self,
         parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
-    ) -> Result<(), String> {
-        let members_by_id = parent_state.members.members_by_member_id();
+    ) -> Result<(), Self::Error> {
         let owner_id = parameters.owner_id();
+        let members = parent_state.members.index(parameters);
 
-        for message in &self.messages {
-            let verifying_key = if message.message.author == owner_id {
-                // Owner's messages are validated against the owner's key
-                &parameters.owner
-            } else if let Some(member) = members_by_id.get(&message.message.author) {
-                // Regular member messages are validated against their member key
-                &member.member.member_vk
-            } else {
-                return Err(format!(
-                    "Message author not found: {:?}",
-                    message.message.author
-                ));
-            };
+        self.messages.verify(parent_state, parameters)?;
 
-            if message.validate(verifying_key).is_err() {
-                return Err(format!(
-                    "Invalid message signature: id:{:?} content:{:?}",
-                    message.id(),
-                    message.message.content
-                ));
+        let messages_by_id: HashMap<MessageId, &AuthorizedMessageV1> =
+            self.messages.entries.iter().map(|m| (m.id(), m)).collect();
+        for message in &self.messages.entries {
+            verify_reply(message, &messages_by_id)?;
+        }
+
+        let configuration = &parent_state.configuration.configuration;
+        for action in &self.actions {
+            let target = messages_by_id.get(&action.action.target).ok_or(
+                RoomStateError::UnknownMessage {
+                    id: action.action.target.clone(),
+                },
+            )?;
+            let author_vk = members
+                .author_vk(
+                    action.action.author,
+                    action.action.time,
+                    &parent_state.bans,
+                    &parent_state.configuration.configuration,
+                )
+                .ok_or(RoomStateError::UnknownAuthor {
+                    field: StateField::MessageAction,
+                    author: action.action.author,
+                })?;
+            action.verify(target, author_vk, owner_id, configuration)?;
+            if let MessageAction::Edit { content } = &action.action.action {
+                verify_sealed(
+                    content,
+                    action.action.time,
+                    StateField::MessageAction,
+                    action.id().0,
+                    parent_state,
+                )?;
             }
         }
 
@@ -57,25 +99,34 @@ impl ComposableState for MessagesV1 {
 
     fn summarize(
         &self,
-        _parent_state: &Self::ParentState,
-        _parameters: &Self::Parameters,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
     ) -> Self::Summary {
-        self.messages.iter().map(|m| m.id()).collect()
+        MessagesSummary {
+            messages: self.messages.summarize(parent_state, parameters),
+            actions: self.actions.iter().map(|a| a.id()).collect(),
+        }
     }
 
     fn delta(
         &self,
-        _parent_state: &Self::ParentState,
-        _parameters: &Self::Parameters,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
         old_state_summary: &Self::Summary,
     ) -> Option<Self::Delta> {
-        let delta: Vec<AuthorizedMessageV1> = self
-            .messages
-            .iter()
-            .filter(|m| !old_state_summary.contains(&m.id()))
-            .cloned()
-            .collect();
-        if delta.is_empty() {
+        let delta = MessagesDelta {
+            messages: self
+                .messages
+                .delta(parent_state, parameters, &old_state_summary.messages)
+                .unwrap_or_default(),
+            actions: self
+                .actions
+                .iter()
+                .filter(|a| !old_state_summary.actions.contains(&a.id()))
+                .cloned()
+                .collect(),
+        };
+        if delta.messages.is_empty() && delta.actions.is_empty() {
             None
         } else {
             Some(delta)
@@ -87,112 +138,131 @@ impl ComposableState for MessagesV1 {
         parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
         delta: &Option<Self::Delta>,
-    ) -> Result<(), String> {
+    ) -> Result<(), Self::Error> {
         let max_recent_messages = parent_state.configuration.configuration.max_recent_messages;
         let max_message_size = parent_state.configuration.configuration.max_message_size;
 
-        // Add new messages if delta exists
+        // Add new messages and actions if delta exists
         if let Some(delta) = delta {
-            self.messages.extend(delta.iter().cloned());
+            // Deltas can hold what we already have, eg. our own changes echoed back to us
+            self.messages.extend(delta.messages.iter().cloned());
+            let mut known: HashSet<MessageActionId> = self.actions.iter().map(|a| a.id()).collect();
+            self.actions.extend(
+                delta
+                    .actions
+                    .iter()
+                    .filter(|a| known.insert(a.id()))
+                    .cloned(),
+            );
         }
 
         // Always enforce message constraints
         // Ensure there are no messages over the size limit
         self.messages
-            .retain(|m| m.message.content.len() <= max_message_size);
+            .entries
+            .retain(|m| m.message.content().len() <= max_message_size);
 
         // Ensure all messages are signed by a valid member or the room owner, remove if not
-        let members_by_id = parent_state.members.members_by_member_id();
         let owner_id = MemberId::from(&parameters.owner);
-        self.messages
-            .retain(|m| members_by_id.contains_key(&m.message.author) || m.message.author == owner_id);
-
-        // Sort messages by time
-        self.messages
-            .sort_by(|a, b| a.message.time.cmp(&b.message.time));
-
-        // Remove oldest messages if there are too many
-        if self.messages.len() > max_recent_messages {
+        let members = parent_state.members.index(parameters);
+        self.messages.entries.retain(|m| {
+            members
+                .author_vk(
+                    m.message.author(),
+                    m.message.time(),
+                    &parent_state.bans,
+                    &parent_state.configuration.configuration,
+                )
+                .is_some_and(|vk| m.validate(vk).is_ok())
+        });
+
+        // Private rooms only take content encrypted with a secret no one banned before it holds
+        self.messages.entries.retain(|m| {
+            let message = &m.message;
+            verify_sealed(
+                message.content(),
+                message.time(),
+                StateField::Message,
+                m.id().0,
+                parent_state,
+            )
+            .is_ok()
+        });
+
+        // Drop replies that don't fit their parent. This happens before eviction so that whether a
+        // reply is kept doesn't depend on whether its parent was evicted yet.
+        let invalid_replies: HashSet<MessageId> = {
+            let messages_by_id: HashMap<MessageId, &AuthorizedMessageV1> =
+                self.messages.entries.iter().map(|m| (m.id(), m)).collect();
             self.messages
-                .drain(0..self.messages.len() - max_recent_messages);
-        }
+                .entries
+                .iter()
+                .filter(|m| verify_reply(m, &messages_by_id).is_err())
+                .map(|m| m.id())
+                .collect()
+        };
+        self.messages
+            .entries
+            .retain(|m| !invalid_replies.contains(&m.id()));
+
+        // Sort messages by time and remove the oldest if there are too many, messages sent at the
+        // same time are ordered by id so that every peer evicts the same ones
+        self.messages.settle(max_recent_messages);
+
+        // Drop actions on messages that are gone, like messages they're dropped rather than
+        // rejected because their target may have been removed by a peer that hadn't seen them
+        let messages_by_id: HashMap<MessageId, &AuthorizedMessageV1> =
+            self.messages.entries.iter().map(|m| (m.id(), m)).collect();
+        let mut actions = std::mem::take(&mut self.actions);
+        actions.retain(|a| {
+            let target = messages_by_id.get(&a.action.target);
+            let author_vk = members.author_vk(
+                a.action.author,
+                a.action.time,
+                &parent_state.bans,
+                &parent_state.configuration.configuration,
+            );
+            let sealed = match &a.action.action {
+                MessageAction::Edit { content } => verify_sealed(
+                    content,
+                    a.action.time,
+                    StateField::MessageAction,
+                    a.id().0,
+                    parent_state,
+                )
+                .is_ok(),
+                MessageAction::Delete => true,
+            };
+            match (target, author_vk) {
+                (Some(target), Some(author_vk)) => {
+                    sealed
+                        && a.verify(
+                            target,
+                            author_vk,
+                            owner_id,
+                            &parent_state.configuration.configuration,
+                        )
+                        .is_ok()
+                }
+                _ => false,
+            }
+        });
+        action::prune_actions(&mut actions);
+        self.actions = actions;
 
         Ok(())
     }
 }
 
-impl Default for MessagesV1 {
-    fn default() -> Self {
-        Self {
-            messages: Vec::new(),
-        }
-    }
-}
-
-#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
-pub struct MessageV1 {
-    pub room_owner: MemberId,
-    pub author: MemberId,
-    pub time: SystemTime,
-    pub content: String,
-}
-
-#[derive(Clone, PartialEq, Serialize, Deserialize)]
-pub struct AuthorizedMessageV1 {
-    pub message: MessageV1,
-    pub signature: Signature,
-}
-
-impl fmt::Debug for AuthorizedMessageV1 {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("AuthorizedMessage")
-            .field("message", &self.message)
-            .field(
-                "signature",
-                &format_args!("{}", truncated_base64(self.signature.to_bytes())),
-            )
-            .finish()
-    }
-}
-
-#[derive(Eq, PartialEq, Hash, Serialize, Deserialize, Clone, Debug, Ord, PartialOrd)]
-pub struct MessageId(pub FastHash);
-
-impl fmt::Display for MessageId {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self.0)
-    }
-}
-
-impl AuthorizedMessageV1 {
-    pub fn new(message: MessageV1, signing_key: &SigningKey) -> Self {
-        Self {
-            message: message.clone(),
-            signature: sign_struct(&message, signing_key),
-        }
-    }
-
-    pub fn validate(
-        &self,
-        verifying_key: &VerifyingKey,
-    ) -> Result<(), ed25519_dalek::SignatureError> {
-        verify_struct(&self.message, &self.signature, &verifying_key)
-    }
-
-    pub fn id(&self) -> MessageId {
-        MessageId(fast_hash(&self.signature.to_bytes()))
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-    use ed25519_dalek::{Signer, SigningKey};
+    use ed25519_dalek::{Signature, Signer, SigningKey};
+    use freenet_scaffold::util::VersionedHash;
     use rand::rngs::OsRng;
-    use std::collections::HashMap;
-    use std::time::Duration;
+    use std::time::{Duration, SystemTime};
 
-    fn create_test_message(owner_id: MemberId, author_id: MemberId) -> MessageV1 {
+    pub(super) fn create_test_message(owner_id: MemberId, author_id: MemberId) -> MessageV1 {
         MessageV1 {
             room_owner: owner_id,
             author: author_id,
@@ -204,65 +274,7 @@ mod tests {
     #[test]
     fn test_messages_v1_default() {
         let default_messages = MessagesV1::default();
-        assert!(default_messages.messages.is_empty());
-    }
-
-    #[test]
-    fn test_authorized_message_v1_debug() {
-        let signing_key = SigningKey::generate(&mut OsRng);
-        let owner_id = MemberId(FastHash(0));
-        let author_id = MemberId(FastHash(1));
-
-        let message = create_test_message(owner_id, author_id);
-        let authorized_message = AuthorizedMessageV1::new(message, &signing_key);
-
-        let debug_output = format!("{:?}", authorized_message);
-        assert!(debug_output.contains("AuthorizedMessage"));
-        assert!(debug_output.contains("message"));
-        assert!(debug_output.contains("signature"));
-    }
-
-    #[test]
-    fn test_authorized_message_new_and_validate() {
-        let signing_key = SigningKey::generate(&mut OsRng);
-        let verifying_key = signing_key.verifying_key();
-        let owner_id = MemberId(FastHash(0));
-        let author_id = MemberId(FastHash(1));
-
-        let message = create_test_message(owner_id, author_id);
-        let authorized_message = AuthorizedMessageV1::new(message.clone(), &signing_key);
-
-        assert_eq!(authorized_message.message, message);
-        assert!(authorized_message.validate(&verifying_key).is_ok());
-
-        // Test with wrong key
-        let wrong_key = SigningKey::generate(&mut OsRng).verifying_key();
-        assert!(authorized_message.validate(&wrong_key).is_err());
-
-        // Test with tampered message
-        let mut tampered_message = authorized_message.clone();
-        tampered_message.message.content = "Tampered content".to_string();
-        assert!(tampered_message.validate(&verifying_key).is_err());
-    }
-
-    #[test]
-    fn test_message_id() {
-        let signing_key = SigningKey::generate(&mut OsRng);
-        let owner_id = MemberId(FastHash(0));
-        let author_id = MemberId(FastHash(1));
-
-        let message = create_test_message(owner_id, author_id);
-        let authorized_message = AuthorizedMessageV1::new(message, &signing_key);
-
-        let id1 = authorized_message.id();
-        let id2 = authorized_message.id();
-
-        assert_eq!(id1, id2);
-
-        // Test that different messages have different IDs
-        let message2 = create_test_message(owner_id, author_id);
-        let authorized_message2 = AuthorizedMessageV1::new(message2, &signing_key);
-        assert_ne!(authorized_message.id(), authorized_message2.id());
+        assert!(default_messages.messages.entries.is_empty());
     }
 
     #[test]
@@ -283,7 +295,8 @@ mod tests {
 
         // Create a Messages struct with the authorized message
         let messages = MessagesV1 {
-            messages: vec![authorized_message],
+            messages: vec![authorized_message].into(),
+            ..Default::default()
         };
 
         // Set up a parent room_state (ChatRoomState) with the author as a member
@@ -311,7 +324,7 @@ mod tests {
 
         // Test with invalid signature
         let mut invalid_messages = messages.clone();
-        invalid_messages.messages[0].signature = Signature::from_bytes(&[0; 64]); // Replace with an invalid signature
+        invalid_messages.messages.entries[0].signature = Signature::from_bytes(&[0; 64]); // Replace with an invalid signature
         assert!(
             invalid_messages.verify(&parent_state, &parameters).is_err(),
             "Messages with invalid signature should fail verification"
@@ -324,7 +337,8 @@ mod tests {
         let invalid_authorized_message =
             AuthorizedMessageV1::new(invalid_message, &author_signing_key);
         let invalid_messages = MessagesV1 {
-            messages: vec![invalid_authorized_message],
+            messages: vec![invalid_authorized_message].into(),
+            ..Default::default()
         };
         assert!(
             invalid_messages.verify(&parent_state, &parameters).is_err(),
@@ -335,8 +349,8 @@ mod tests {
     #[test]
     fn test_messages_summarize() {
         let signing_key = SigningKey::generate(&mut OsRng);
-        let owner_id = MemberId(FastHash(0));
-        let author_id = MemberId(FastHash(1));
+        let owner_id = MemberId(VersionedHash::Blake3V1([0; 32]));
+        let author_id = MemberId(VersionedHash::Blake3V1([1; 32]));
 
         let message1 = create_test_message(owner_id, author_id);
         let message2 = create_test_message(owner_id, author_id);
@@ -345,7 +359,8 @@ mod tests {
         let authorized_message2 = AuthorizedMessageV1::new(message2, &signing_key);
 
         let messages = MessagesV1 {
-            messages: vec![authorized_message1.clone(), authorized_message2.clone()],
+            messages: vec![authorized_message1.clone(), authorized_message2.clone()].into(),
+            ..Default::default()
         };
 
         let parent_state = ChatRoomStateV1::default();
@@ -354,21 +369,25 @@ mod tests {
         };
 
         let summary = messages.summarize(&parent_state, &parameters);
-        assert_eq!(summary.len(), 2);
-        assert_eq!(summary[0], authorized_message1.id());
-        assert_eq!(summary[1], authorized_message2.id());
+        assert_eq!(
+            summary.messages,
+            [authorized_message1.id(), authorized_message2.id()]
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(summary.actions, IdSet::default());
 
         // Test empty messages
-        let empty_messages = MessagesV1 { messages: vec![] };
+        let empty_messages = MessagesV1::default();
         let empty_summary = empty_messages.summarize(&parent_state, &parameters);
-        assert!(empty_summary.is_empty());
+        assert_eq!(empty_summary, MessagesSummary::default());
     }
 
     #[test]
     fn test_messages_delta() {
         let signing_key = SigningKey::generate(&mut OsRng);
-        let owner_id = MemberId(FastHash(0));
-        let author_id = MemberId(FastHash(1));
+        let owner_id = MemberId(VersionedHash::Blake3V1([0; 32]));
+        let author_id = MemberId(VersionedHash::Blake3V1([1; 32]));
 
         let message1 = create_test_message(owner_id, author_id);
         let message2 = create_test_message(owner_id, author_id);
@@ -383,7 +402,9 @@ mod tests {
                 authorized_message1.clone(),
                 authorized_message2.clone(),
                 authorized_message3.clone(),
-            ],
+            ]
+            .into(),
+            ..Default::default()
         };
 
         let parent_state = ChatRoomStateV1::default();
@@ -392,27 +413,26 @@ mod tests {
         };
 
         // Test with partial old summary
-        let old_summary = vec![authorized_message1.id(), authorized_message2.id()];
+        let old_summary = MessagesSummary {
+            messages: [authorized_message1.id(), authorized_message2.id()]
+                .into_iter()
+                .collect(),
+            actions: IdSet::default(),
+        };
         let delta = messages
             .delta(&parent_state, &parameters, &old_summary)
             .unwrap();
-        assert_eq!(delta.len(), 1);
-        assert_eq!(delta[0], authorized_message3);
+        assert_eq!(delta.messages, vec![authorized_message3.clone()]);
 
         // Test with empty old summary
-        let empty_summary: Vec<MessageId> = vec![];
+        let empty_summary = MessagesSummary::default();
         let full_delta = messages
             .delta(&parent_state, &parameters, &empty_summary)
             .unwrap();
-        assert_eq!(full_delta.len(), 3);
-        assert_eq!(full_delta, messages.messages);
+        assert_eq!(full_delta.messages, messages.messages.entries);
 
         // Test with full old summary (no changes)
-        let full_summary = vec![
-            authorized_message1.id(),
-            authorized_message2.id(),
-            authorized_message3.id(),
-        ];
+        let full_summary = messages.summarize(&parent_state, &parameters);
         let no_delta = messages.delta(&parent_state, &parameters, &full_summary);
         assert!(no_delta.is_none());
     }
@@ -437,7 +457,10 @@ mod tests {
                 invited_by: owner_id,
                 member_vk: author_verifying_key,
             },
-            signature: owner_signing_key.try_sign(&[0; 32]).unwrap(),
+            authorization: crate::room_state::member::MemberAuthorization::Invite {
+                signature: owner_signing_key.try_sign(&[0; 32]).unwrap(),
+                joined_at: None,
+            },
         }];
 
         let parameters = ChatRoomParametersV1 {
@@ -463,62 +486,221 @@ mod tests {
 
         // Initial room_state with 2 messages
         let mut messages = MessagesV1 {
-            messages: vec![message1.clone(), message2.clone()],
+            messages: vec![message1.clone(), message2.clone()].into(),
+            ..Default::default()
         };
 
         // Apply delta with 2 new messages
-        let delta = vec![message3.clone(), message4.clone()];
+        let delta = MessagesDelta {
+            messages: vec![message3.clone(), message4.clone()],
+            ..Default::default()
+        };
         assert!(messages
             .apply_delta(&parent_state, &parameters, &Some(delta))
             .is_ok());
 
         // Check results
         assert_eq!(
-            messages.messages.len(),
+            messages.messages.entries.len(),
             3,
             "Should have 3 messages after applying delta"
         );
         assert!(
-            !messages.messages.contains(&message1),
+            !messages.messages.entries.contains(&message1),
             "Oldest message should be removed"
         );
         assert!(
-            messages.messages.contains(&message2),
+            messages.messages.entries.contains(&message2),
             "Second oldest message should be retained"
         );
         assert!(
-            messages.messages.contains(&message3),
+            messages.messages.entries.contains(&message3),
             "New message should be added"
         );
         assert!(
-            messages.messages.contains(&message4),
+            messages.messages.entries.contains(&message4),
             "Newest message should be added"
         );
 
         // Apply delta with an older message
         let old_message = create_message(now - Duration::from_secs(4));
-        let delta = vec![old_message.clone()];
+        let delta = MessagesDelta {
+            messages: vec![old_message.clone()],
+            ..Default::default()
+        };
         assert!(messages
             .apply_delta(&parent_state, &parameters, &Some(delta))
             .is_ok());
 
         // Check results
-        assert_eq!(messages.messages.len(), 3, "Should still have 3 messages");
+        assert_eq!(
+            messages.messages.entries.len(),
+            3,
+            "Should still have 3 messages"
+        );
         assert!(
-            !messages.messages.contains(&old_message),
+            !messages.messages.entries.contains(&old_message),
             "Older message should not be added"
         );
         assert!(
-            messages.messages.contains(&message2),
+            messages.messages.entries.contains(&message2),
             "Message2 should be retained"
         );
         assert!(
-            messages.messages.contains(&message3),
+            messages.messages.entries.contains(&message3),
             "Message3 should be retained"
         );
         assert!(
-            messages.messages.contains(&message4),
+            messages.messages.entries.contains(&message4),
             "Newest message should be retained"
         );
     }
+
+    #[test]
+    fn test_known_messages_not_added_twice() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let author_signing_key = SigningKey::generate(&mut OsRng);
+        let (parent_state, parameters, message) =
+            room_with_message(&owner_signing_key, &author_signing_key);
+        let edit = message_action(
+            &message,
+            &author_signing_key,
+            SystemTime::now(),
+            edit("Edited"),
+        );
+        let delta = Some(MessagesDelta {
+            messages: vec![message.clone(), message.clone()],
+            actions: vec![edit.clone(), edit.clone()],
+        });
+
+        let mut messages = MessagesV1::default();
+        messages
+            .apply_delta(&parent_state, &parameters, &delta)
+            .unwrap();
+        assert_eq!(messages.messages.entries, vec![message]);
+        assert_eq!(messages.actions, vec![edit]);
+
+        // Applying it again, eg. when it's echoed back to its sender, changes nothing
+        let before = messages.clone();
+        messages
+            .apply_delta(&parent_state, &parameters, &delta)
+            .unwrap();
+        assert_eq!(messages, before);
+    }
+
+    #[test]
+    fn test_forged_legacy_author_id_rejected() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_id = MemberId::from(&owner_signing_key.verifying_key());
+        let attacker_signing_key = SigningKey::generate(&mut OsRng);
+        let victim_verifying_key = SigningKey::generate(&mut OsRng).verifying_key();
+
+        let mut parent_state = ChatRoomStateV1::default();
+        for member_vk in [attacker_signing_key.verifying_key(), victim_verifying_key] {
+            let member = crate::room_state::member::Member {
+                owner_member_id: owner_id,
+                invited_by: owner_id,
+                member_vk,
+            };
+            parent_state
+                .members
+                .members
+                .push(crate::room_state::member::AuthorizedMember::new(
+                    member,
+                    &owner_signing_key,
+                ));
+        }
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+
+        // Impersonate the victim using the id their key had under fast_hash
+        let forged_victim_id = MemberId(VersionedHash::Legacy(
+            freenet_scaffold::util::fast_hash(&victim_verifying_key.to_bytes()),
+        ));
+        let forged_message = AuthorizedMessageV1::new(
+            create_test_message(owner_id, forged_victim_id),
+            &attacker_signing_key,
+        );
+
+        let messages = MessagesV1 {
+            messages: vec![forged_message.clone()].into(),
+            ..Default::default()
+        };
+        assert!(messages.verify(&parent_state, &parameters).is_err());
+
+        let mut messages = MessagesV1::default();
+        messages
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MessagesDelta {
+                    messages: vec![forged_message],
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+        assert!(messages.messages.entries.is_empty());
+    }
+
+    /// A room with `author` as a member and one message by them
+    pub(super) fn room_with_message(
+        owner_signing_key: &SigningKey,
+        author_signing_key: &SigningKey,
+    ) -> (ChatRoomStateV1, ChatRoomParametersV1, AuthorizedMessageV1) {
+        let owner_id = MemberId::from(&owner_signing_key.verifying_key());
+        let mut parent_state = ChatRoomStateV1::default();
+        let member = crate::room_state::member::Member {
+            owner_member_id: owner_id,
+            invited_by: owner_id,
+            member_vk: author_signing_key.verifying_key(),
+        };
+        parent_state.members.members = vec![crate::room_state::member::AuthorizedMember::new(
+            member,
+            owner_signing_key,
+        )];
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        let message = AuthorizedMessageV1::new(
+            create_test_message(
+                owner_id,
+                MemberId::from(&author_signing_key.verifying_key()),
+            ),
+            author_signing_key,
+        );
+        (parent_state, parameters, message)
+    }
+
+    pub(super) fn message_action(
+        target: &AuthorizedMessageV1,
+        signing_key: &SigningKey,
+        time: SystemTime,
+        action: MessageAction,
+    ) -> AuthorizedMessageActionV1 {
+        let action = MessageActionV1 {
+            room_owner: target.message.room_owner(),
+            author: MemberId::from(&signing_key.verifying_key()),
+            time,
+            target: target.id(),
+            action,
+        };
+        AuthorizedMessageActionV1::new(action, signing_key)
+    }
+
+    pub(super) fn edit(content: &str) -> MessageAction {
+        MessageAction::Edit {
+            content: content.to_string(),
+        }
+    }
+
+    pub(super) fn reply(parent: &AuthorizedMessageV1, time: SystemTime) -> MessageV2 {
+        MessageV2 {
+            room_owner: parent.message.room_owner(),
+            author: parent.message.author(),
+            time,
+            content: "Reply".to_string(),
+            in_reply_to: parent.id(),
+        }
+    }
 }
@@ -0,0 +1,369 @@
+use crate::room_state::configuration::Configuration;
+use crate::room_state::error::{RoomStateError, StateField};
+use crate::room_state::member::MemberId;
+use crate::room_state::message::{AuthorizedMessageV1, MessageId, MessagesV1};
+use crate::util::{sign_struct, truncated_base64, verify_struct};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use freenet_scaffold::util::{blake3_hash, VersionedHash};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::SystemTime;
+
+/// A change to a message already in the room, made by its author or, for deletes, the owner or a
+/// moderator allowed to delete messages
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct MessageActionV1 {
+    pub room_owner: MemberId,
+    pub author: MemberId,
+    pub time: SystemTime,
+    pub target: MessageId,
+    pub action: MessageAction,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum MessageAction {
+    /// Replaces the content of the message, the latest edit wins
+    Edit { content: String },
+    /// Leaves a tombstone in place of the message, overrides any edits
+    Delete,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthorizedMessageActionV1 {
+    pub action: MessageActionV1,
+    pub signature: Signature,
+}
+
+impl fmt::Debug for AuthorizedMessageActionV1 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthorizedMessageAction")
+            .field("action", &self.action)
+            .field(
+                "signature",
+                &format_args!("{}", truncated_base64(self.signature.to_bytes())),
+            )
+            .finish()
+    }
+}
+
+const MESSAGE_ACTION_ID_CONTEXT: &str = "river 2025-01 message action id";
+
+#[derive(Eq, PartialEq, Hash, Serialize, Deserialize, Clone, Debug, Ord, PartialOrd)]
+pub struct MessageActionId(pub VersionedHash);
+
+impl AuthorizedMessageActionV1 {
+    pub fn new(action: MessageActionV1, signing_key: &SigningKey) -> Self {
+        Self {
+            signature: sign_struct(&action, signing_key),
+            action,
+        }
+    }
+
+    pub fn validate(
+        &self,
+        verifying_key: &VerifyingKey,
+    ) -> Result<(), ed25519_dalek::SignatureError> {
+        verify_struct(&self.action, &self.signature, verifying_key)
+    }
+
+    pub fn id(&self) -> MessageActionId {
+        MessageActionId(blake3_hash(
+            MESSAGE_ACTION_ID_CONTEXT,
+            &self.signature.to_bytes(),
+        ))
+    }
+
+    /// Checks that the action was signed by `author_vk`, the key of its author, and that its
+    /// author may act on `target`
+    pub fn verify(
+        &self,
+        target: &AuthorizedMessageV1,
+        author_vk: &VerifyingKey,
+        owner_id: MemberId,
+        configuration: &Configuration,
+    ) -> Result<(), RoomStateError> {
+        let is_target_author = self.action.author == target.message.author();
+        let authorized = match &self.action.action {
+            MessageAction::Edit { content } => {
+                if content.len() > configuration.max_message_size {
+                    return Err(RoomStateError::LimitExceeded {
+                        field: StateField::MessageAction,
+                        count: content.len(),
+                        max: configuration.max_message_size,
+                    });
+                }
+                is_target_author
+            }
+            MessageAction::Delete => {
+                is_target_author
+                    || self.action.author == owner_id
+                    || configuration
+                        .moderator_permissions(self.action.author)
+                        .delete_messages
+            }
+        };
+        if !authorized {
+            return Err(RoomStateError::Unauthorized {
+                field: StateField::MessageAction,
+                id: self.id().0,
+            });
+        }
+        self.validate(author_vk)
+            .map_err(|_| RoomStateError::InvalidSignature {
+                field: StateField::MessageAction,
+                id: self.id().0,
+            })
+    }
+
+    fn order_key(&self) -> (SystemTime, MessageActionId) {
+        (self.action.time, self.id())
+    }
+}
+
+impl MessagesV1 {
+    /// What `message` shows after any edits or deletion
+    pub fn content<'a>(&'a self, message: &'a AuthorizedMessageV1) -> MessageContent<'a> {
+        let id = message.id();
+        let mut content = MessageContent::Original(message.message.content());
+        for action in self.actions.iter().filter(|a| a.action.target == id) {
+            match &action.action.action {
+                MessageAction::Delete => return MessageContent::Deleted,
+                MessageAction::Edit { content: edited } => content = MessageContent::Edited(edited),
+            }
+        }
+        content
+    }
+}
+
+/// Keeps only the actions that affect what their target shows: the earliest delete if there is
+/// one, otherwise the latest edit. Which actions remain only depends on which were given, not on
+/// the order peers received them in.
+pub(super) fn prune_actions(actions: &mut Vec<AuthorizedMessageActionV1>) {
+    let mut kept: HashMap<MessageId, AuthorizedMessageActionV1> = HashMap::new();
+    for action in actions.drain(..) {
+        let replace = match kept.get(&action.action.target) {
+            None => true,
+            Some(current) => match (&current.action.action, &action.action.action) {
+                (MessageAction::Delete, MessageAction::Delete) => {
+                    action.order_key() < current.order_key()
+                }
+                (MessageAction::Delete, MessageAction::Edit { .. }) => false,
+                (MessageAction::Edit { .. }, MessageAction::Delete) => true,
+                (MessageAction::Edit { .. }, MessageAction::Edit { .. }) => {
+                    action.order_key() > current.order_key()
+                }
+            },
+        };
+        if replace {
+            kept.insert(action.action.target.clone(), action);
+        }
+    }
+    actions.extend(kept.into_values());
+    actions.sort_by_key(|action| action.order_key());
+}
+
+/// What a message shows once the actions on it are taken into account
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MessageContent<'a> {
+    Original(&'a str),
+    Edited(&'a str),
+    Deleted,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::configuration::{Moderator, ModeratorPermissions};
+    use crate::room_state::member::{AuthorizedMember, Member};
+    use crate::room_state::message::tests::{edit, message_action, room_with_message};
+    use crate::room_state::message::MessagesDelta;
+    use freenet_scaffold::ComposableState;
+    use rand::rngs::OsRng;
+    use std::time::Duration;
+
+    #[test]
+    fn test_latest_edit_wins() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let author_signing_key = SigningKey::generate(&mut OsRng);
+        let (parent_state, parameters, message) =
+            room_with_message(&owner_signing_key, &author_signing_key);
+        let now = SystemTime::now();
+        let first = message_action(&message, &author_signing_key, now, edit("First"));
+        let second = message_action(
+            &message,
+            &author_signing_key,
+            now + Duration::from_secs(1),
+            edit("Second"),
+        );
+
+        // Peers receiving the edits in either order end up with the same state
+        let mut results = Vec::new();
+        for edits in [
+            vec![first.clone(), second.clone()],
+            vec![second.clone(), first.clone()],
+        ] {
+            let mut messages = MessagesV1 {
+                messages: vec![message.clone()].into(),
+                ..Default::default()
+            };
+            for edit in edits {
+                let delta = MessagesDelta {
+                    actions: vec![edit],
+                    ..Default::default()
+                };
+                messages
+                    .apply_delta(&parent_state, &parameters, &Some(delta))
+                    .unwrap();
+            }
+            assert_eq!(messages.verify(&parent_state, &parameters), Ok(()));
+            assert_eq!(messages.content(&message), MessageContent::Edited("Second"));
+            results.push(messages);
+        }
+        assert_eq!(results[0], results[1]);
+        assert_eq!(results[0].actions, vec![second]);
+    }
+
+    #[test]
+    fn test_delete_overrides_edit() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let author_signing_key = SigningKey::generate(&mut OsRng);
+        let (parent_state, parameters, message) =
+            room_with_message(&owner_signing_key, &author_signing_key);
+        let now = SystemTime::now();
+        // The owner may delete any message, and a later edit doesn't bring it back
+        let delete = message_action(&message, &owner_signing_key, now, MessageAction::Delete);
+        let edit = message_action(
+            &message,
+            &author_signing_key,
+            now + Duration::from_secs(1),
+            edit("Edited"),
+        );
+
+        let mut messages = MessagesV1 {
+            messages: vec![message.clone()].into(),
+            ..Default::default()
+        };
+        let delta = MessagesDelta {
+            actions: vec![edit, delete.clone()],
+            ..Default::default()
+        };
+        messages
+            .apply_delta(&parent_state, &parameters, &Some(delta))
+            .unwrap();
+        assert_eq!(messages.actions, vec![delete]);
+        assert_eq!(messages.content(&message), MessageContent::Deleted);
+        assert_eq!(messages.verify(&parent_state, &parameters), Ok(()));
+    }
+
+    #[test]
+    fn test_unauthorized_actions_rejected() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let author_signing_key = SigningKey::generate(&mut OsRng);
+        let (parent_state, parameters, message) =
+            room_with_message(&owner_signing_key, &author_signing_key);
+        let now = SystemTime::now();
+
+        // Only the author may edit, even the owner can't
+        let owner_edit = message_action(&message, &owner_signing_key, now, edit("Owner"));
+        let messages = MessagesV1 {
+            messages: vec![message.clone()].into(),
+            actions: vec![owner_edit.clone()],
+        };
+        assert_eq!(
+            messages.verify(&parent_state, &parameters),
+            Err(RoomStateError::Unauthorized {
+                field: StateField::MessageAction,
+                id: owner_edit.id().0,
+            })
+        );
+
+        // Signed by someone other than the author it claims
+        let mut forged = message_action(&message, &owner_signing_key, now, MessageAction::Delete);
+        forged.action.author = message.message.author();
+        let messages = MessagesV1 {
+            messages: vec![message.clone()].into(),
+            actions: vec![forged.clone()],
+        };
+        assert!(matches!(
+            messages.verify(&parent_state, &parameters),
+            Err(RoomStateError::InvalidSignature {
+                field: StateField::MessageAction,
+                ..
+            })
+        ));
+
+        // Invalid actions in a delta are dropped
+        let mut messages = MessagesV1 {
+            messages: vec![message.clone()].into(),
+            ..Default::default()
+        };
+        let delta = MessagesDelta {
+            actions: vec![owner_edit, forged],
+            ..Default::default()
+        };
+        messages
+            .apply_delta(&parent_state, &parameters, &Some(delta))
+            .unwrap();
+        assert!(messages.actions.is_empty());
+        assert_eq!(
+            messages.content(&message),
+            MessageContent::Original("Test message")
+        );
+    }
+
+    #[test]
+    fn test_moderator_delete() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let author_signing_key = SigningKey::generate(&mut OsRng);
+        let moderator_signing_key = SigningKey::generate(&mut OsRng);
+        let (mut parent_state, parameters, message) =
+            room_with_message(&owner_signing_key, &author_signing_key);
+        let moderator = Member {
+            owner_member_id: parameters.owner_id(),
+            invited_by: parameters.owner_id(),
+            member_vk: moderator_signing_key.verifying_key(),
+        };
+        parent_state
+            .members
+            .members
+            .push(AuthorizedMember::new(moderator, &owner_signing_key));
+        let delete = message_action(
+            &message,
+            &moderator_signing_key,
+            SystemTime::now(),
+            MessageAction::Delete,
+        );
+        let messages = MessagesV1 {
+            messages: vec![message.clone()].into(),
+            actions: vec![delete.clone()],
+        };
+
+        // Moderators need to be allowed to delete messages
+        let mut permissions = ModeratorPermissions {
+            ban_anyone: true,
+            edit_room_name: true,
+            ..ModeratorPermissions::default()
+        };
+        let mut set_moderator = |permissions| {
+            parent_state.configuration.configuration.moderators = vec![Moderator {
+                member_id: moderator_signing_key.verifying_key().into(),
+                permissions,
+            }];
+            parent_state.clone()
+        };
+        assert_eq!(
+            messages.verify(&set_moderator(permissions), &parameters),
+            Err(RoomStateError::Unauthorized {
+                field: StateField::MessageAction,
+                id: delete.id().0,
+            })
+        );
+        permissions.delete_messages = true;
+        assert_eq!(
+            messages.verify(&set_moderator(permissions), &parameters),
+            Ok(())
+        );
+        assert_eq!(messages.content(&message), MessageContent::Deleted);
+    }
+}
@@ -0,0 +1,298 @@
+use crate::room_state::error::{RoomStateError, StateField};
+use crate::room_state::member::MemberId;
+use crate::room_state::secret::verify_sealed;
+use crate::room_state::ChatRoomParametersV1;
+use crate::util::{sign_struct, truncated_base64, verify_struct};
+use crate::ChatRoomStateV1;
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use freenet_scaffold::collections::{Element, Identified, LogEntry};
+use freenet_scaffold::util::{blake3_hash, VersionedHash};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::SystemTime;
+
+/*
+ A message is signed by its author, who must have been in the room when they posted it. In a
+ private room its content is sealed with the room secret, see `secret.rs`. Messages are kept in a
+ `BoundedLog` ordered by time, see `MessagesV1`.
+*/
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct MessageV1 {
+    pub room_owner: MemberId,
+    pub author: MemberId,
+    pub time: SystemTime,
+    pub content: String,
+}
+
+/// A reply to another message in the room
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct MessageV2 {
+    pub room_owner: MemberId,
+    pub author: MemberId,
+    pub time: SystemTime,
+    pub content: String,
+    pub in_reply_to: MessageId,
+}
+
+/// Any version of a message. It's untagged so that a `MessageV1` serializes, and so is signed,
+/// exactly as it did before there were other versions. `MessageV2` must come first as it has
+/// every field of `MessageV1`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(untagged)]
+pub enum Message {
+    V2(MessageV2),
+    V1(MessageV1),
+}
+
+impl Message {
+    pub fn room_owner(&self) -> MemberId {
+        match self {
+            Message::V1(message) => message.room_owner,
+            Message::V2(message) => message.room_owner,
+        }
+    }
+
+    pub fn author(&self) -> MemberId {
+        match self {
+            Message::V1(message) => message.author,
+            Message::V2(message) => message.author,
+        }
+    }
+
+    pub fn time(&self) -> SystemTime {
+        match self {
+            Message::V1(message) => message.time,
+            Message::V2(message) => message.time,
+        }
+    }
+
+    pub fn content(&self) -> &str {
+        match self {
+            Message::V1(message) => &message.content,
+            Message::V2(message) => &message.content,
+        }
+    }
+
+    pub fn in_reply_to(&self) -> Option<&MessageId> {
+        match self {
+            Message::V1(_) => None,
+            Message::V2(message) => Some(&message.in_reply_to),
+        }
+    }
+}
+
+impl From<MessageV1> for Message {
+    fn from(message: MessageV1) -> Self {
+        Message::V1(message)
+    }
+}
+
+impl From<MessageV2> for Message {
+    fn from(message: MessageV2) -> Self {
+        Message::V2(message)
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthorizedMessageV1 {
+    pub message: Message,
+    pub signature: Signature,
+}
+
+impl fmt::Debug for AuthorizedMessageV1 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthorizedMessage")
+            .field("message", &self.message)
+            .field(
+                "signature",
+                &format_args!("{}", truncated_base64(self.signature.to_bytes())),
+            )
+            .finish()
+    }
+}
+
+const MESSAGE_ID_CONTEXT: &str = "river 2025-01 message id";
+
+#[derive(Eq, PartialEq, Hash, Serialize, Deserialize, Clone, Debug, Ord, PartialOrd)]
+pub struct MessageId(pub VersionedHash);
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl AuthorizedMessageV1 {
+    pub fn new(message: impl Into<Message>, signing_key: &SigningKey) -> Self {
+        let message = message.into();
+        Self {
+            signature: sign_struct(&message, signing_key),
+            message,
+        }
+    }
+
+    pub fn validate(
+        &self,
+        verifying_key: &VerifyingKey,
+    ) -> Result<(), ed25519_dalek::SignatureError> {
+        verify_struct(&self.message, &self.signature, &verifying_key)
+    }
+
+    pub fn id(&self) -> MessageId {
+        MessageId(blake3_hash(MESSAGE_ID_CONTEXT, &self.signature.to_bytes()))
+    }
+}
+
+impl Element for AuthorizedMessageV1 {
+    type ParentState = ChatRoomStateV1;
+    type Parameters = ChatRoomParametersV1;
+    type Error = RoomStateError;
+
+    /// Checks the message was signed by its author, who may post at its time, and is sealed as
+    /// the room requires. Replies are checked by `MessagesV1`, they depend on the other messages.
+    fn verify(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), Self::Error> {
+        let author = self.message.author();
+        let verifying_key = parent_state
+            .members
+            .index(parameters)
+            .author_vk(
+                author,
+                self.message.time(),
+                &parent_state.bans,
+                &parent_state.configuration.configuration,
+            )
+            .ok_or(RoomStateError::UnknownAuthor {
+                field: StateField::Message,
+                author,
+            })?;
+
+        if self.validate(verifying_key).is_err() {
+            return Err(RoomStateError::InvalidSignature {
+                field: StateField::Message,
+                id: self.id().0,
+            });
+        }
+        verify_sealed(
+            self.message.content(),
+            self.message.time(),
+            StateField::Message,
+            self.id().0,
+            parent_state,
+        )
+    }
+}
+
+impl Identified for AuthorizedMessageV1 {
+    type Id = MessageId;
+
+    fn id(&self) -> MessageId {
+        AuthorizedMessageV1::id(self)
+    }
+}
+
+impl LogEntry for AuthorizedMessageV1 {
+    type OrderKey = SystemTime;
+
+    fn order_key(&self) -> SystemTime {
+        self.message.time()
+    }
+
+    fn capacity(parent_state: &ChatRoomStateV1, _parameters: &ChatRoomParametersV1) -> usize {
+        parent_state.configuration.configuration.max_recent_messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::message::tests::create_test_message;
+    use crate::room_state::message::tests::reply;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_authorized_message_v1_debug() {
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let owner_id = MemberId(VersionedHash::Blake3V1([0; 32]));
+        let author_id = MemberId(VersionedHash::Blake3V1([1; 32]));
+
+        let message = create_test_message(owner_id, author_id);
+        let authorized_message = AuthorizedMessageV1::new(message, &signing_key);
+
+        let debug_output = format!("{:?}", authorized_message);
+        assert!(debug_output.contains("AuthorizedMessage"));
+        assert!(debug_output.contains("message"));
+        assert!(debug_output.contains("signature"));
+    }
+
+    #[test]
+    fn test_authorized_message_new_and_validate() {
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let verifying_key = signing_key.verifying_key();
+        let owner_id = MemberId(VersionedHash::Blake3V1([0; 32]));
+        let author_id = MemberId(VersionedHash::Blake3V1([1; 32]));
+
+        let message = create_test_message(owner_id, author_id);
+        let authorized_message = AuthorizedMessageV1::new(message.clone(), &signing_key);
+
+        assert_eq!(authorized_message.message, Message::V1(message.clone()));
+        assert!(authorized_message.validate(&verifying_key).is_ok());
+
+        // Test with wrong key
+        let wrong_key = SigningKey::generate(&mut OsRng).verifying_key();
+        assert!(authorized_message.validate(&wrong_key).is_err());
+
+        // Test with tampered message
+        let mut tampered_message = authorized_message.clone();
+        tampered_message.message = Message::V1(MessageV1 {
+            content: "Tampered content".to_string(),
+            ..message
+        });
+        assert!(tampered_message.validate(&verifying_key).is_err());
+    }
+
+    #[test]
+    fn test_message_id() {
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let owner_id = MemberId(VersionedHash::Blake3V1([0; 32]));
+        let author_id = MemberId(VersionedHash::Blake3V1([1; 32]));
+
+        let message = create_test_message(owner_id, author_id);
+        let authorized_message = AuthorizedMessageV1::new(message, &signing_key);
+
+        let id1 = authorized_message.id();
+        let id2 = authorized_message.id();
+
+        assert_eq!(id1, id2);
+
+        // Test that different messages have different IDs
+        let message2 = create_test_message(owner_id, author_id);
+        let authorized_message2 = AuthorizedMessageV1::new(message2, &signing_key);
+        assert_ne!(authorized_message.id(), authorized_message2.id());
+    }
+
+    #[test]
+    fn test_message_versions_serialization() {
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let owner_id = MemberId(VersionedHash::Blake3V1([0; 32]));
+        let author_id = MemberId(VersionedHash::Blake3V1([1; 32]));
+        let message = create_test_message(owner_id, author_id);
+
+        // A MessageV1 is signed exactly as it was before there were other versions
+        let original = AuthorizedMessageV1::new(message.clone(), &signing_key);
+        assert_eq!(original.signature, sign_struct(&message, &signing_key));
+
+        let reply = AuthorizedMessageV1::new(reply(&original, SystemTime::now()), &signing_key);
+        for authorized in [original, reply] {
+            let mut bytes = Vec::new();
+            ciborium::ser::into_writer(&authorized, &mut bytes).unwrap();
+            let decoded: AuthorizedMessageV1 = ciborium::de::from_reader(&bytes[..]).unwrap();
+            assert_eq!(decoded, authorized);
+            assert!(decoded.validate(&signing_key.verifying_key()).is_ok());
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use crate::room_state::error::{RoomStateError, StateField};
+use crate::room_state::message::{AuthorizedMessageV1, MessageId, MessagesV1};
+use std::collections::HashMap;
+
+/*
+ A reply refers to the message it replies to by id. It can't predate its parent, but the parent
+ needn't be in the room: it may have been evicted by `max_recent_messages`, or not have reached
+ this peer yet. Whether a reply is kept only depends on the parents that are in the room, so that
+ every peer keeps the same replies whichever messages it evicted first.
+*/
+
+impl MessagesV1 {
+    /// The message `message` replies to, if it's a reply
+    pub fn reply_target(&self, message: &AuthorizedMessageV1) -> Option<ReplyTarget<'_>> {
+        let parent_id = message.message.in_reply_to()?;
+        Some(
+            match self.messages.entries.iter().find(|m| &m.id() == parent_id) {
+                Some(parent) => ReplyTarget::Present(parent),
+                None => ReplyTarget::Evicted(parent_id.clone()),
+            },
+        )
+    }
+}
+
+/// Checks that a reply doesn't predate the message it replies to. Parents that aren't in the
+/// room can't be checked, they're usually older messages evicted by `max_recent_messages`.
+pub(super) fn verify_reply(
+    message: &AuthorizedMessageV1,
+    messages_by_id: &HashMap<MessageId, &AuthorizedMessageV1>,
+) -> Result<(), RoomStateError> {
+    let Some(parent_id) = message.message.in_reply_to() else {
+        return Ok(());
+    };
+    if parent_id.0.is_legacy() {
+        return Err(RoomStateError::LegacyId {
+            field: StateField::Message,
+            id: parent_id.0,
+        });
+    }
+    match messages_by_id.get(parent_id) {
+        Some(parent) if parent.message.time() > message.message.time() => {
+            Err(RoomStateError::InvalidReply {
+                message: message.id(),
+                in_reply_to: parent_id.clone(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The message a reply refers to
+#[derive(Clone, PartialEq, Debug)]
+pub enum ReplyTarget<'a> {
+    Present(&'a AuthorizedMessageV1),
+    /// No longer in the room, only its id is known
+    Evicted(MessageId),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::message::tests::{reply, room_with_message};
+    use crate::room_state::message::MessagesDelta;
+    use ed25519_dalek::SigningKey;
+    use freenet_scaffold::ComposableState;
+    use rand::rngs::OsRng;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reply_before_parent_rejected() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let author_signing_key = SigningKey::generate(&mut OsRng);
+        let (parent_state, parameters, parent) =
+            room_with_message(&owner_signing_key, &author_signing_key);
+        let early = AuthorizedMessageV1::new(
+            reply(&parent, parent.message.time() - Duration::from_secs(1)),
+            &author_signing_key,
+        );
+        let messages = MessagesV1 {
+            messages: vec![early.clone(), parent.clone()].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            messages.verify(&parent_state, &parameters),
+            Err(RoomStateError::InvalidReply {
+                message: early.id(),
+                in_reply_to: parent.id(),
+            })
+        );
+
+        let mut messages = MessagesV1 {
+            messages: vec![parent.clone()].into(),
+            ..Default::default()
+        };
+        let delta = MessagesDelta {
+            messages: vec![early],
+            ..Default::default()
+        };
+        messages
+            .apply_delta(&parent_state, &parameters, &Some(delta))
+            .unwrap();
+        assert_eq!(messages.messages.entries, vec![parent]);
+    }
+
+    #[test]
+    fn test_reply_to_evicted_message() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let author_signing_key = SigningKey::generate(&mut OsRng);
+        let (mut parent_state, parameters, parent) =
+            room_with_message(&owner_signing_key, &author_signing_key);
+        parent_state.configuration.configuration.max_recent_messages = 1;
+        let reply = AuthorizedMessageV1::new(
+            reply(&parent, parent.message.time() + Duration::from_secs(1)),
+            &author_signing_key,
+        );
+
+        let mut messages = MessagesV1 {
+            messages: vec![parent.clone()].into(),
+            ..Default::default()
+        };
+        assert_eq!(messages.reply_target(&parent), None);
+        assert_eq!(
+            messages.reply_target(&reply),
+            Some(ReplyTarget::Present(&parent))
+        );
+
+        // The reply evicts its parent but is kept, and still refers to it
+        let delta = MessagesDelta {
+            messages: vec![reply.clone()],
+            ..Default::default()
+        };
+        messages
+            .apply_delta(&parent_state, &parameters, &Some(delta))
+            .unwrap();
+        assert_eq!(messages.messages.entries, vec![reply.clone()]);
+        assert_eq!(messages.verify(&parent_state, &parameters), Ok(()));
+        assert_eq!(
+            messages.reply_target(&reply),
+            Some(ReplyTarget::Evicted(parent.id()))
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use crate::room_state::error::{RoomStateError, StateField};
+use crate::room_state::member::MemberId;
+use crate::room_state::message::MessageId;
+use crate::room_state::versioned::ChatRoomStateV2;
+use crate::room_state::ChatRoomParametersV1;
+use crate::util::{sign_struct, truncated_base64, verify_struct};
+use ed25519_dalek::{Signature, SigningKey};
+use freenet_scaffold::collections::{Element, Identified, LwwRegister, Versioned};
+use freenet_scaffold::util::{blake3_hash, VersionedHash};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How many messages the owner can pin at once
+pub const MAX_PINNED_MESSAGES: usize = 10;
+
+const PINNED_MESSAGES_ID_CONTEXT: &str = "river 2025-01 pinned messages id";
+
+/// The messages the owner pinned, the latest list they signed replaces any earlier one. Two
+/// lists of the same version, which the owner could sign on two devices, are ordered by id.
+pub type PinnedMessagesV1 = LwwRegister<AuthorizedPinnedMessages>;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PinnedMessages {
+    pub owner_member_id: MemberId,
+    pub version: u32,
+    /// Pinned messages may since have been dropped from `recent_messages`
+    pub message_ids: Vec<MessageId>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct AuthorizedPinnedMessages {
+    pub pinned: PinnedMessages,
+    pub signature: Signature,
+}
+
+impl Element for AuthorizedPinnedMessages {
+    type ParentState = ChatRoomStateV2;
+    type Parameters = ChatRoomParametersV1;
+    type Error = RoomStateError;
+
+    fn verify(
+        &self,
+        _parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), Self::Error> {
+        AuthorizedPinnedMessages::verify(self, parameters)
+    }
+}
+
+impl Versioned for AuthorizedPinnedMessages {
+    type Version = u32;
+
+    fn version(&self) -> u32 {
+        self.pinned.version
+    }
+}
+
+impl Identified for AuthorizedPinnedMessages {
+    type Id = VersionedHash;
+
+    fn id(&self) -> VersionedHash {
+        blake3_hash(PINNED_MESSAGES_ID_CONTEXT, &self.signature.to_bytes())
+    }
+}
+
+impl AuthorizedPinnedMessages {
+    pub fn new(pinned: PinnedMessages, owner_signing_key: &SigningKey) -> Self {
+        Self {
+            signature: sign_struct(&pinned, owner_signing_key),
+            pinned,
+        }
+    }
+
+    /// Checks the list was signed by the owner and isn't longer than `MAX_PINNED_MESSAGES`
+    pub fn verify(&self, parameters: &ChatRoomParametersV1) -> Result<(), RoomStateError> {
+        verify_struct(&self.pinned, &self.signature, &parameters.owner).map_err(|_| {
+            RoomStateError::InvalidSignature {
+                field: StateField::PinnedMessages,
+                id: self.id(),
+            }
+        })?;
+        if self.pinned.message_ids.len() > MAX_PINNED_MESSAGES {
+            return Err(RoomStateError::LimitExceeded {
+                field: StateField::PinnedMessages,
+                count: self.pinned.message_ids.len(),
+                max: MAX_PINNED_MESSAGES,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for AuthorizedPinnedMessages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthorizedPinnedMessages")
+            .field("pinned", &self.pinned)
+            .field(
+                "signature",
+                &format_args!("{}", truncated_base64(self.signature.to_bytes())),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+    use freenet_scaffold::ComposableState;
+    use rand::rngs::OsRng;
+
+    fn pinned(owner_signing_key: &SigningKey, version: u32, pins: u8) -> AuthorizedPinnedMessages {
+        let pinned = PinnedMessages {
+            owner_member_id: owner_signing_key.verifying_key().into(),
+            version,
+            message_ids: (0..pins)
+                .map(|i| MessageId(VersionedHash::Blake3V1([i; 32])))
+                .collect(),
+        };
+        AuthorizedPinnedMessages::new(pinned, owner_signing_key)
+    }
+
+    #[test]
+    fn test_pinned_messages() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        let parent_state = ChatRoomStateV2::default();
+        let mut state = PinnedMessagesV1::default();
+
+        let first = pinned(&owner_signing_key, 1, 2);
+        let second = pinned(&owner_signing_key, 2, 1);
+        state
+            .apply_delta(&parent_state, &parameters, &Some(second.clone()))
+            .unwrap();
+        // The earlier list arriving late doesn't replace the later one
+        state
+            .apply_delta(&parent_state, &parameters, &Some(first))
+            .unwrap();
+        assert_eq!(state.0, Some(second));
+        assert_eq!(state.verify(&parent_state, &parameters), Ok(()));
+        let summary = state.summarize(&parent_state, &parameters);
+        assert_eq!(state.delta(&parent_state, &parameters, &summary), None);
+
+        // Only the owner can pin messages, and only so many
+        let other_signing_key = SigningKey::generate(&mut OsRng);
+        let result = state.apply_delta(
+            &parent_state,
+            &parameters,
+            &Some(pinned(&other_signing_key, 3, 1)),
+        );
+        assert!(matches!(
+            result,
+            Err(RoomStateError::InvalidSignature { .. })
+        ));
+        let result = state.apply_delta(
+            &parent_state,
+            &parameters,
+            &Some(pinned(&owner_signing_key, 3, MAX_PINNED_MESSAGES as u8 + 1)),
+        );
+        assert!(matches!(result, Err(RoomStateError::LimitExceeded { .. })));
+    }
+}
@@ -0,0 +1,514 @@
+use crate::room_state::error::{RoomStateError, StateField};
+use crate::room_state::member::{MemberId, MemberIndex};
+use crate::room_state::message::MessageId;
+use crate::room_state::ChatRoomParametersV1;
+use crate::util::{sign_struct, truncated_base64, verify_struct};
+use crate::ChatRoomStateV1;
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use freenet_scaffold::id_set::IdSet;
+use freenet_scaffold::util::{blake3_hash, VersionedHash};
+use freenet_scaffold::ComposableState;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::SystemTime;
+
+/// The longest reaction allowed in bytes, enough for any emoji sequence
+pub const MAX_REACTION_SIZE: usize = 32;
+
+/// Reactions to the messages in `recent_messages`, they go when their message is evicted or
+/// their member leaves the room.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct ReactionsV1 {
+    pub reactions: Vec<AuthorizedReaction>,
+}
+
+impl ComposableState for ReactionsV1 {
+    type ParentState = ChatRoomStateV1;
+    type Summary = IdSet<ReactionId>;
+    type Delta = Vec<AuthorizedReaction>;
+    type Parameters = ChatRoomParametersV1;
+    type Error = RoomStateError;
+
+    fn verify(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), Self::Error> {
+        let message_ids: HashSet<MessageId> = parent_state
+            .recent_messages
+            .messages
+            .entries
+            .iter()
+            .map(|m| m.id())
+            .collect();
+        let max_per_member = parent_state
+            .configuration
+            .configuration
+            .max_reactions_per_member;
+        let members = parent_state.members.index(parameters);
+        let mut ids = HashSet::new();
+        let mut counts: HashMap<MemberId, usize> = HashMap::new();
+
+        for reaction in &self.reactions {
+            reaction.verify(&message_ids, &members, parent_state)?;
+            if !ids.insert(reaction.id()) {
+                return Err(RoomStateError::Duplicate {
+                    field: StateField::Reaction,
+                    id: reaction.id().0,
+                });
+            }
+            let count = counts.entry(reaction.reaction.member_id).or_default();
+            *count += 1;
+            if *count > max_per_member {
+                return Err(RoomStateError::LimitExceeded {
+                    field: StateField::Reaction,
+                    count: *count,
+                    max: max_per_member,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        self.reactions.iter().map(|r| r.id()).collect()
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let delta: Vec<AuthorizedReaction> = self
+            .reactions
+            .iter()
+            .filter(|r| !old_state_summary.contains(&r.id()))
+            .cloned()
+            .collect();
+        if delta.is_empty() {
+            None
+        } else {
+            Some(delta)
+        }
+    }
+
+    fn apply_delta(
+        &mut self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), Self::Error> {
+        if let Some(delta) = delta {
+            let existing: HashSet<ReactionId> = self.reactions.iter().map(|r| r.id()).collect();
+            self.reactions.extend(
+                delta
+                    .iter()
+                    .filter(|r| !existing.contains(&r.id()))
+                    .cloned(),
+            );
+        }
+
+        // Like messages, reactions whose message or member is gone are dropped rather than
+        // rejected, a peer may have evicted the message or banned the member without seeing them
+        let message_ids: HashSet<MessageId> = parent_state
+            .recent_messages
+            .messages
+            .entries
+            .iter()
+            .map(|m| m.id())
+            .collect();
+        let members = parent_state.members.index(parameters);
+        self.reactions
+            .retain(|r| r.verify(&message_ids, &members, parent_state).is_ok());
+
+        // Reacting twice with the same reaction counts once, the latest is kept. Were the earliest
+        // kept, a later duplicate would come back once the earliest was evicted by the cap, but
+        // only on peers that merged them in that order.
+        self.reactions.sort_by_key(|r| Reverse(r.order_key()));
+        let mut seen = HashSet::new();
+        self.reactions.retain(|r| {
+            seen.insert((
+                r.reaction.message_id.clone(),
+                r.reaction.member_id,
+                r.reaction.reaction.clone(),
+            ))
+        });
+
+        // Members over the cap lose their oldest reactions
+        let max_per_member = parent_state
+            .configuration
+            .configuration
+            .max_reactions_per_member;
+        let mut counts: HashMap<MemberId, usize> = HashMap::new();
+        self.reactions.retain(|r| {
+            let count = counts.entry(r.reaction.member_id).or_default();
+            *count += 1;
+            *count <= max_per_member
+        });
+        self.reactions.sort_by_key(|r| r.order_key());
+
+        Ok(())
+    }
+}
+
+impl ReactionsV1 {
+    /// The reactions to `message_id` with the members who made each, in the order each
+    /// reaction was made
+    pub fn for_message(&self, message_id: &MessageId) -> Vec<(&str, Vec<MemberId>)> {
+        let mut grouped: Vec<(&str, Vec<MemberId>)> = Vec::new();
+        for reaction in self
+            .reactions
+            .iter()
+            .filter(|r| &r.reaction.message_id == message_id)
+        {
+            let members = match grouped
+                .iter_mut()
+                .find(|(r, _)| *r == reaction.reaction.reaction)
+            {
+                Some((_, members)) => members,
+                None => {
+                    grouped.push((&reaction.reaction.reaction, Vec::new()));
+                    &mut grouped.last_mut().unwrap().1
+                }
+            };
+            members.push(reaction.reaction.member_id);
+        }
+        grouped
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Reaction {
+    pub message_id: MessageId,
+    pub member_id: MemberId,
+    pub time: SystemTime,
+    /// Usually a single emoji, at most `MAX_REACTION_SIZE` bytes
+    pub reaction: String,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthorizedReaction {
+    pub reaction: Reaction,
+    pub signature: Signature,
+}
+
+impl fmt::Debug for AuthorizedReaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthorizedReaction")
+            .field("reaction", &self.reaction)
+            .field(
+                "signature",
+                &format_args!("{}", truncated_base64(self.signature.to_bytes())),
+            )
+            .finish()
+    }
+}
+
+const REACTION_ID_CONTEXT: &str = "river 2025-01 reaction id";
+
+#[derive(Eq, PartialEq, Hash, Serialize, Deserialize, Clone, Debug, Ord, PartialOrd)]
+pub struct ReactionId(pub VersionedHash);
+
+impl AuthorizedReaction {
+    pub fn new(reaction: Reaction, signing_key: &SigningKey) -> Self {
+        Self {
+            signature: sign_struct(&reaction, signing_key),
+            reaction,
+        }
+    }
+
+    pub fn validate(
+        &self,
+        verifying_key: &VerifyingKey,
+    ) -> Result<(), ed25519_dalek::SignatureError> {
+        verify_struct(&self.reaction, &self.signature, verifying_key)
+    }
+
+    pub fn id(&self) -> ReactionId {
+        ReactionId(blake3_hash(REACTION_ID_CONTEXT, &self.signature.to_bytes()))
+    }
+
+    /// Checks the reaction is to one of `message_ids` and signed by the owner or a member
+    fn verify(
+        &self,
+        message_ids: &HashSet<MessageId>,
+        members: &MemberIndex,
+        parent_state: &ChatRoomStateV1,
+    ) -> Result<(), RoomStateError> {
+        let reaction = &self.reaction.reaction;
+        if reaction.is_empty() || reaction.len() > MAX_REACTION_SIZE {
+            return Err(RoomStateError::InvalidReaction { id: self.id() });
+        }
+        if !message_ids.contains(&self.reaction.message_id) {
+            return Err(RoomStateError::UnknownMessage {
+                id: self.reaction.message_id.clone(),
+            });
+        }
+        let member_id = self.reaction.member_id;
+        let verifying_key = members
+            .author_vk(
+                member_id,
+                self.reaction.time,
+                &parent_state.bans,
+                &parent_state.configuration.configuration,
+            )
+            .ok_or(RoomStateError::UnknownAuthor {
+                field: StateField::Reaction,
+                author: member_id,
+            })?;
+        self.validate(verifying_key)
+            .map_err(|_| RoomStateError::InvalidSignature {
+                field: StateField::Reaction,
+                id: self.id().0,
+            })
+    }
+
+    fn order_key(&self) -> (SystemTime, ReactionId) {
+        (self.reaction.time, self.id())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::member::{AuthorizedMember, Member};
+    use crate::room_state::message::{AuthorizedMessageV1, MessageV1};
+    use rand::rngs::OsRng;
+    use std::time::Duration;
+
+    /// A room with one member and a message by them
+    fn setup() -> (
+        ChatRoomStateV1,
+        ChatRoomParametersV1,
+        SigningKey,
+        SigningKey,
+        MessageId,
+    ) {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let member_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_id = MemberId::from(&owner_signing_key.verifying_key());
+        let member_id = MemberId::from(&member_signing_key.verifying_key());
+
+        let mut parent_state = ChatRoomStateV1::default();
+        parent_state
+            .configuration
+            .configuration
+            .max_reactions_per_member = 2;
+        let member = Member {
+            owner_member_id: owner_id,
+            invited_by: owner_id,
+            member_vk: member_signing_key.verifying_key(),
+        };
+        parent_state.members.members = vec![AuthorizedMember::new(member, &owner_signing_key)];
+        let message = AuthorizedMessageV1::new(
+            MessageV1 {
+                room_owner: owner_id,
+                author: member_id,
+                time: SystemTime::now(),
+                content: "Hello".to_string(),
+            },
+            &member_signing_key,
+        );
+        let message_id = message.id();
+        parent_state.recent_messages.messages = vec![message].into();
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        (
+            parent_state,
+            parameters,
+            owner_signing_key,
+            member_signing_key,
+            message_id,
+        )
+    }
+
+    fn react(
+        signing_key: &SigningKey,
+        message_id: &MessageId,
+        reaction: &str,
+        seconds: u64,
+    ) -> AuthorizedReaction {
+        let reaction = Reaction {
+            message_id: message_id.clone(),
+            member_id: signing_key.verifying_key().into(),
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(seconds),
+            reaction: reaction.to_string(),
+        };
+        AuthorizedReaction::new(reaction, signing_key)
+    }
+
+    #[test]
+    fn test_reactions_apply_delta() {
+        let (parent_state, parameters, owner_signing_key, member_signing_key, message_id) = setup();
+        let thumbs_up = react(&member_signing_key, &message_id, "👍", 1);
+        let owner_thumbs_up = react(&owner_signing_key, &message_id, "👍", 2);
+        let heart = react(&member_signing_key, &message_id, "❤️", 3);
+
+        let mut reactions = ReactionsV1::default();
+        let delta = vec![heart.clone(), thumbs_up.clone(), owner_thumbs_up.clone()];
+        reactions
+            .apply_delta(&parent_state, &parameters, &Some(delta))
+            .unwrap();
+        assert_eq!(
+            reactions.reactions,
+            vec![thumbs_up.clone(), owner_thumbs_up, heart]
+        );
+        assert_eq!(reactions.verify(&parent_state, &parameters), Ok(()));
+
+        let owner_id = parameters.owner_id();
+        let member_id = MemberId::from(&member_signing_key.verifying_key());
+        assert_eq!(
+            reactions.for_message(&message_id),
+            vec![("👍", vec![member_id, owner_id]), ("❤️", vec![member_id])]
+        );
+
+        // The summary covers everything, so there's nothing to send
+        let summary = reactions.summarize(&parent_state, &parameters);
+        assert_eq!(reactions.delta(&parent_state, &parameters, &summary), None);
+        let partial = [thumbs_up.id()].into_iter().collect();
+        let delta = reactions.delta(&parent_state, &parameters, &partial);
+        assert_eq!(delta.map(|d| d.len()), Some(2));
+    }
+
+    #[test]
+    fn test_reactions_per_member_cap() {
+        let (parent_state, parameters, _, member_signing_key, message_id) = setup();
+        let reactions: Vec<AuthorizedReaction> = ["👍", "❤️", "🎉"]
+            .iter()
+            .enumerate()
+            .map(|(i, r)| react(&member_signing_key, &message_id, r, i as u64))
+            .collect();
+
+        // The oldest reaction makes way for newer ones
+        let mut state = ReactionsV1::default();
+        state
+            .apply_delta(&parent_state, &parameters, &Some(reactions.clone()))
+            .unwrap();
+        assert_eq!(state.reactions, reactions[1..].to_vec());
+
+        let over_cap = ReactionsV1 { reactions };
+        assert!(matches!(
+            over_cap.verify(&parent_state, &parameters),
+            Err(RoomStateError::LimitExceeded {
+                field: StateField::Reaction,
+                count: 3,
+                max: 2,
+            })
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_reaction_counts_once() {
+        let (parent_state, parameters, _, member_signing_key, message_id) = setup();
+        let first = react(&member_signing_key, &message_id, "👍", 1);
+        let second = react(&member_signing_key, &message_id, "👍", 2);
+
+        let mut reactions = ReactionsV1::default();
+        reactions
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(vec![second.clone(), first]),
+            )
+            .unwrap();
+        assert_eq!(reactions.reactions, vec![second]);
+    }
+
+    #[test]
+    fn test_duplicate_reaction_after_eviction() {
+        let (parent_state, parameters, _, member_signing_key, message_id) = setup();
+        let thumbs_up = react(&member_signing_key, &message_id, "👍", 1);
+        let heart = react(&member_signing_key, &message_id, "❤️", 2);
+        let party = react(&member_signing_key, &message_id, "🎉", 3);
+        let thumbs_up_again = react(&member_signing_key, &message_id, "👍", 4);
+
+        // Whether the first 👍 was evicted before the second arrived doesn't matter
+        let mut evicted_first = ReactionsV1::default();
+        for delta in [
+            vec![thumbs_up.clone(), heart.clone(), party.clone()],
+            vec![thumbs_up_again.clone()],
+        ] {
+            evicted_first
+                .apply_delta(&parent_state, &parameters, &Some(delta))
+                .unwrap();
+        }
+        let mut all_at_once = ReactionsV1::default();
+        all_at_once
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(vec![
+                    thumbs_up,
+                    heart,
+                    party.clone(),
+                    thumbs_up_again.clone(),
+                ]),
+            )
+            .unwrap();
+        assert_eq!(evicted_first, all_at_once);
+        assert_eq!(all_at_once.reactions, vec![party, thumbs_up_again]);
+    }
+
+    #[test]
+    fn test_reactions_removed_with_message_or_member() {
+        let (parent_state, parameters, owner_signing_key, member_signing_key, message_id) = setup();
+        let member_reaction = react(&member_signing_key, &message_id, "👍", 1);
+        let owner_reaction = react(&owner_signing_key, &message_id, "🎉", 2);
+        let mut reactions = ReactionsV1 {
+            reactions: vec![member_reaction.clone(), owner_reaction.clone()],
+        };
+        assert_eq!(reactions.verify(&parent_state, &parameters), Ok(()));
+
+        // The member leaves, eg. because they were banned
+        let mut without_member = parent_state.clone();
+        without_member.members.members.clear();
+        assert_eq!(
+            reactions.verify(&without_member, &parameters),
+            Err(RoomStateError::UnknownAuthor {
+                field: StateField::Reaction,
+                author: member_signing_key.verifying_key().into(),
+            })
+        );
+        reactions
+            .apply_delta(&without_member, &parameters, &None)
+            .unwrap();
+        assert_eq!(reactions.reactions, vec![owner_reaction]);
+
+        // The message is evicted
+        let mut without_message = without_member;
+        without_message.recent_messages.messages.entries.clear();
+        reactions
+            .apply_delta(&without_message, &parameters, &None)
+            .unwrap();
+        assert!(reactions.reactions.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_reactions_rejected() {
+        let (parent_state, parameters, owner_signing_key, member_signing_key, message_id) = setup();
+        let too_long = react(&member_signing_key, &message_id, &"👍".repeat(9), 1);
+        let mut forged = react(&owner_signing_key, &message_id, "👍", 2);
+        forged.reaction.member_id = member_signing_key.verifying_key().into();
+
+        for reaction in [too_long, forged] {
+            let reactions = ReactionsV1 {
+                reactions: vec![reaction.clone()],
+            };
+            assert!(reactions.verify(&parent_state, &parameters).is_err());
+
+            let mut reactions = ReactionsV1::default();
+            reactions
+                .apply_delta(&parent_state, &parameters, &Some(vec![reaction]))
+                .unwrap();
+            assert!(reactions.reactions.is_empty());
+        }
+    }
+}
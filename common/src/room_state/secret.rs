@@ -0,0 +1,1066 @@
+use crate::room_state::configuration::PrivacyMode;
+use crate::room_state::error::{RoomStateError, StateField};
+use crate::room_state::member::MemberId;
+use crate::room_state::message::MessageAction;
+use crate::room_state::ChatRoomParametersV1;
+use crate::util::{sign_struct, truncated_base64, verify_struct};
+use crate::ChatRoomStateV1;
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Signature, SigningKey};
+use freenet_scaffold::util::{blake3_hash, VersionedHash};
+use freenet_scaffold::ComposableState;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::SystemTime;
+
+/*
+In private rooms message content is encrypted with a symmetric room secret. Each version of the
+secret is an epoch, recorded and signed by the owner, who wraps the secret of every epoch to each
+member's key and stores the results here, so the contract only ever sees ciphertext. The owner's
+client starts a new epoch when it sees that a member was banned or removed, so that they can't read
+anything encrypted after that.
+
+Messages and edits in a private room must be encrypted, and those posted after a ban with an epoch
+the banned member doesn't hold. Each ban records the lowest version messages after it may use, that
+of the first epoch the owner started after the ban, see `BansV1::min_secret_version`. Only the
+owner can start an epoch while members and moderators can ban too, so after a ban no one can post
+until the owner's client comes online and starts one. The ban's record is worked out again whenever
+the state changes rather than signed, so it follows the epochs every peer ends up with.
+
+The owner can use the room from more than one client, and two of them can start the same version
+at once. Where their epochs meet the one with the lowest id wins, wrapped secrets name the epoch
+they're for and those of the epoch that lost are dropped. Summaries list the ids so that a peer
+holding the losing epoch is sent the winner. Messages already encrypted with the losing secret
+can't be read.
+
+Members need the secret of every epoch that messages in `recent_messages` were encrypted with,
+including epochs from before they joined. Once the last message of an old epoch is evicted its
+wrapped secrets are dropped, only the current epoch is kept regardless.
+*/
+
+/// Room secret epochs and the secrets wrapped by the owner for each member
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct RoomSecretsV1 {
+    #[serde(default)]
+    pub epochs: Vec<AuthorizedSecretEpoch>,
+    pub secrets: Vec<AuthorizedEncryptedSecretForMember>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct RoomSecretsSummary {
+    pub epochs: Vec<u32>,
+    /// The member id and secret version of every wrapped secret
+    pub secrets: Vec<(MemberId, u32)>,
+    /// The id of each of `epochs`, see `SecretEpoch::id`, empty in summaries from before epochs of
+    /// the same version were told apart
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub epoch_ids: Vec<VersionedHash>,
+    /// The id of each of `secrets`, which covers the epoch it was wrapped for
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub secret_ids: Vec<VersionedHash>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct RoomSecretsDelta {
+    pub epochs: Vec<AuthorizedSecretEpoch>,
+    pub secrets: Vec<AuthorizedEncryptedSecretForMember>,
+}
+
+impl ComposableState for RoomSecretsV1 {
+    type ParentState = ChatRoomStateV1;
+    type Summary = RoomSecretsSummary;
+    type Delta = RoomSecretsDelta;
+    type Parameters = ChatRoomParametersV1;
+    type Error = RoomStateError;
+
+    fn verify(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), Self::Error> {
+        let mut versions = HashSet::new();
+        for epoch in &self.epochs {
+            epoch.verify_signature(parameters)?;
+            if !versions.insert(epoch.epoch.secret_version) {
+                return Err(RoomStateError::Duplicate {
+                    field: StateField::SecretEpoch,
+                    id: epoch.id(),
+                });
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for secret in &self.secrets {
+            secret.verify(parent_state, parameters)?;
+            if !versions.contains(&secret.secret.secret_version) {
+                return Err(RoomStateError::UnknownSecretVersion {
+                    secret_version: secret.secret.secret_version,
+                });
+            }
+            if !self.has_epoch_of(secret) {
+                return Err(RoomStateError::UnknownSecretEpoch { id: secret.id() });
+            }
+            if !seen.insert(secret.secret.key()) {
+                return Err(RoomStateError::Duplicate {
+                    field: StateField::Secret,
+                    id: secret.id(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        RoomSecretsSummary {
+            epochs: self.epochs.iter().map(|e| e.epoch.secret_version).collect(),
+            secrets: self.secrets.iter().map(|s| s.secret.key()).collect(),
+            epoch_ids: self.epochs.iter().map(|e| e.epoch.id()).collect(),
+            secret_ids: self.secrets.iter().map(|s| s.id()).collect(),
+        }
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        // Two of the owner's clients can start the same version at once. Peers are sent our epoch
+        // if theirs of the version loses to it, and any secret they hold another wrapping of, as
+        // it may be for the epoch that wins. Summaries without ids only tell versions apart.
+        let old = old_state_summary;
+        let old_epochs: HashMap<u32, Option<&VersionedHash>> =
+            if old.epoch_ids.len() == old.epochs.len() {
+                old.epochs
+                    .iter()
+                    .copied()
+                    .zip(old.epoch_ids.iter().map(Some))
+                    .collect()
+            } else {
+                old.epochs.iter().map(|v| (*v, None)).collect()
+            };
+        let old_secrets: HashMap<(MemberId, u32), Option<&VersionedHash>> =
+            if old.secret_ids.len() == old.secrets.len() {
+                old.secrets
+                    .iter()
+                    .copied()
+                    .zip(old.secret_ids.iter().map(Some))
+                    .collect()
+            } else {
+                old.secrets.iter().map(|key| (*key, None)).collect()
+            };
+        let delta = RoomSecretsDelta {
+            epochs: self
+                .epochs
+                .iter()
+                .filter(|e| match old_epochs.get(&e.epoch.secret_version) {
+                    None => true,
+                    Some(old_id) => old_id.is_some_and(|old_id| e.epoch.id() < *old_id),
+                })
+                .cloned()
+                .collect(),
+            secrets: self
+                .secrets
+                .iter()
+                .filter(|s| match old_secrets.get(&s.secret.key()) {
+                    None => true,
+                    Some(old_id) => old_id.is_some_and(|old_id| s.id() != *old_id),
+                })
+                .cloned()
+                .collect(),
+        };
+        if delta.epochs.is_empty() && delta.secrets.is_empty() {
+            None
+        } else {
+            Some(delta)
+        }
+    }
+
+    fn apply_delta(
+        &mut self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), Self::Error> {
+        if let Some(delta) = delta {
+            for epoch in &delta.epochs {
+                epoch.verify_signature(parameters)?;
+                // Two of the owner's clients can start the same version at once, when their epochs
+                // meet the one with the lowest id wins so every peer keeps the same one
+                match self
+                    .epochs
+                    .iter_mut()
+                    .find(|e| e.epoch.secret_version == epoch.epoch.secret_version)
+                {
+                    Some(existing) if existing.epoch.id() <= epoch.epoch.id() => {}
+                    Some(existing) => *existing = epoch.clone(),
+                    None => self.epochs.push(epoch.clone()),
+                }
+            }
+            for secret in &delta.secrets {
+                secret.verify_signature(parameters)?;
+                // Of two wrappings of a version for a member, one for the epoch that won is kept,
+                // then one that names its epoch, then the one with the lowest id
+                let rank = |s: &AuthorizedEncryptedSecretForMember| {
+                    (!self.has_epoch_of(s), s.secret.epoch_id.is_none(), s.id())
+                };
+                match self
+                    .secrets
+                    .iter()
+                    .position(|s| s.secret.key() == secret.secret.key())
+                {
+                    Some(i) if rank(&self.secrets[i]) <= rank(secret) => {}
+                    Some(i) => self.secrets[i] = secret.clone(),
+                    None => self.secrets.push(secret.clone()),
+                }
+            }
+        }
+
+        // Epochs no remaining message was encrypted with are dropped, along with their secrets
+        let current_version = self.current_version();
+        let in_use = versions_in_use(parent_state);
+        self.epochs.retain(|e| {
+            Some(e.epoch.secret_version) == current_version
+                || in_use.contains(&e.epoch.secret_version)
+        });
+        self.epochs.sort_by_key(|e| e.epoch.secret_version);
+
+        // Secrets of members who left or were banned are dropped, like their member info, and so
+        // are those of epochs that lost to another of their version
+        let epochs = self.epochs.clone();
+        self.secrets.retain(|s| {
+            epochs.iter().any(|e| s.secret.is_for(&e.epoch))
+                && s.verify(parent_state, parameters).is_ok()
+        });
+        self.secrets
+            .sort_by_key(|s| (s.secret.secret_version, s.secret.member_id));
+
+        Ok(())
+    }
+}
+
+/// The secret versions that messages and edits in the room are encrypted with
+fn versions_in_use(parent_state: &ChatRoomStateV1) -> HashSet<u32> {
+    let messages = &parent_state.recent_messages;
+    let edits = messages
+        .actions
+        .iter()
+        .filter_map(|a| match &a.action.action {
+            MessageAction::Edit { content } => Some(content.as_str()),
+            MessageAction::Delete => None,
+        });
+    messages
+        .messages
+        .entries
+        .iter()
+        .map(|m| m.message.content())
+        .chain(edits)
+        .filter_map(EncryptedContent::decode)
+        .map(|c| c.secret_version)
+        .collect()
+}
+
+impl RoomSecretsV1 {
+    /// Whether the epoch the secret was wrapped for is kept
+    fn has_epoch_of(&self, secret: &AuthorizedEncryptedSecretForMember) -> bool {
+        self.epochs.iter().any(|e| secret.secret.is_for(&e.epoch))
+    }
+
+    /// The epoch of `secret_version`, if it's kept
+    pub fn epoch(&self, secret_version: u32) -> Option<&SecretEpoch> {
+        self.epochs
+            .iter()
+            .map(|e| &e.epoch)
+            .find(|e| e.secret_version == secret_version)
+    }
+
+    /// The version of the latest epoch, if there is one
+    pub fn current_version(&self) -> Option<u32> {
+        self.epochs.iter().map(|e| e.epoch.secret_version).max()
+    }
+
+    /// The secret versions of every epoch still needed to read the room, oldest first
+    pub fn versions(&self) -> impl Iterator<Item = u32> + '_ {
+        self.epochs.iter().map(|e| e.epoch.secret_version)
+    }
+
+    /// The secret wrapped for `member_id`, if they have been given `secret_version`
+    pub fn secret_for(
+        &self,
+        member_id: MemberId,
+        secret_version: u32,
+    ) -> Option<&EncryptedSecretForMember> {
+        self.secrets
+            .iter()
+            .map(|s| &s.secret)
+            .find(|s| s.member_id == member_id && s.secret_version == secret_version)
+    }
+
+    /// Every secret wrapped for `member_id`, one per epoch they've been given
+    pub fn secrets_for(
+        &self,
+        member_id: MemberId,
+    ) -> impl Iterator<Item = &EncryptedSecretForMember> {
+        self.secrets
+            .iter()
+            .map(|s| &s.secret)
+            .filter(move |s| s.member_id == member_id)
+    }
+
+    /// The owner and members who haven't been given `secret_version` yet, eg. because they were
+    /// invited since it was created
+    pub fn members_without(
+        &self,
+        secret_version: u32,
+        parent_state: &ChatRoomStateV1,
+        parameters: &ChatRoomParametersV1,
+    ) -> Vec<MemberId> {
+        std::iter::once(parameters.owner_id())
+            .chain(parent_state.members.members.iter().map(|m| m.member.id()))
+            .filter(|member_id| self.secret_for(*member_id, secret_version).is_none())
+            .collect()
+    }
+}
+
+/// The start of a new version of the room secret
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SecretEpoch {
+    pub secret_version: u32,
+    pub created_at: SystemTime,
+}
+
+const SECRET_EPOCH_CONTENT_ID_CONTEXT: &str = "river 2025-01 secret epoch content id";
+
+impl SecretEpoch {
+    /// Identifies the epoch by what it records rather than by its signature, so that it stays the
+    /// same when a successor signs the epoch again
+    pub fn id(&self) -> VersionedHash {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(self, &mut bytes).expect("Serialization should not fail");
+        blake3_hash(SECRET_EPOCH_CONTENT_ID_CONTEXT, &bytes)
+    }
+}
+
+/// Signed by the owner, the only one who can rotate the room secret
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthorizedSecretEpoch {
+    pub epoch: SecretEpoch,
+    pub signature: Signature,
+}
+
+impl fmt::Debug for AuthorizedSecretEpoch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthorizedSecretEpoch")
+            .field("epoch", &self.epoch)
+            .field(
+                "signature",
+                &format_args!("{}", truncated_base64(self.signature.to_bytes())),
+            )
+            .finish()
+    }
+}
+
+const SECRET_EPOCH_ID_CONTEXT: &str = "river 2025-01 secret epoch id";
+
+impl AuthorizedSecretEpoch {
+    pub fn new(epoch: SecretEpoch, owner_signing_key: &SigningKey) -> Self {
+        Self {
+            signature: sign_struct(&epoch, owner_signing_key),
+            epoch,
+        }
+    }
+
+    pub fn id(&self) -> VersionedHash {
+        blake3_hash(SECRET_EPOCH_ID_CONTEXT, &self.signature.to_bytes())
+    }
+
+    pub fn verify_signature(
+        &self,
+        parameters: &ChatRoomParametersV1,
+    ) -> Result<(), RoomStateError> {
+        verify_struct(&self.epoch, &self.signature, &parameters.owner).map_err(|_| {
+            RoomStateError::InvalidSignature {
+                field: StateField::SecretEpoch,
+                id: self.id(),
+            }
+        })
+    }
+}
+
+/// A room secret encrypted to one member's key with ECIES
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct EncryptedSecretForMember {
+    pub member_id: MemberId,
+    pub secret_version: u32,
+    pub ciphertext: Vec<u8>,
+    pub nonce: [u8; 12],
+    /// The ephemeral X25519 public key the secret was encrypted with
+    pub sender_public_key: [u8; 32],
+    /// The id of the epoch the secret is for, see `SecretEpoch::id`. Secrets wrapped before epochs
+    /// of the same version were told apart leave it out and go with any epoch of their version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub epoch_id: Option<VersionedHash>,
+}
+
+impl EncryptedSecretForMember {
+    fn key(&self) -> (MemberId, u32) {
+        (self.member_id, self.secret_version)
+    }
+
+    /// Whether the secret was wrapped for `epoch`
+    pub fn is_for(&self, epoch: &SecretEpoch) -> bool {
+        self.secret_version == epoch.secret_version
+            && self.epoch_id.is_none_or(|id| id == epoch.id())
+    }
+}
+
+/// Signed by the owner, the only one who can distribute secrets
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthorizedEncryptedSecretForMember {
+    pub secret: EncryptedSecretForMember,
+    pub signature: Signature,
+}
+
+impl fmt::Debug for AuthorizedEncryptedSecretForMember {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthorizedEncryptedSecretForMember")
+            .field("secret", &self.secret)
+            .field(
+                "signature",
+                &format_args!("{}", truncated_base64(self.signature.to_bytes())),
+            )
+            .finish()
+    }
+}
+
+const SECRET_ID_CONTEXT: &str = "river 2025-01 secret id";
+
+impl AuthorizedEncryptedSecretForMember {
+    pub fn new(secret: EncryptedSecretForMember, owner_signing_key: &SigningKey) -> Self {
+        Self {
+            signature: sign_struct(&secret, owner_signing_key),
+            secret,
+        }
+    }
+
+    pub fn id(&self) -> VersionedHash {
+        blake3_hash(SECRET_ID_CONTEXT, &self.signature.to_bytes())
+    }
+
+    pub fn verify_signature(
+        &self,
+        parameters: &ChatRoomParametersV1,
+    ) -> Result<(), RoomStateError> {
+        verify_struct(&self.secret, &self.signature, &parameters.owner).map_err(|_| {
+            RoomStateError::InvalidSignature {
+                field: StateField::Secret,
+                id: self.id(),
+            }
+        })
+    }
+
+    /// Checks the owner signed the secret for themselves or a current member
+    fn verify(
+        &self,
+        parent_state: &ChatRoomStateV1,
+        parameters: &ChatRoomParametersV1,
+    ) -> Result<(), RoomStateError> {
+        let member_id = self.secret.member_id;
+        if member_id != parameters.owner_id()
+            && !parent_state
+                .members
+                .members
+                .iter()
+                .any(|m| m.member.id() == member_id)
+        {
+            return Err(RoomStateError::UnknownAuthor {
+                field: StateField::Secret,
+                author: member_id,
+            });
+        }
+        self.verify_signature(parameters)
+    }
+}
+
+/// Checks that content posted at `time` in a private room is encrypted, with a version of the room
+/// secret that no one banned before then holds
+pub(crate) fn verify_sealed(
+    content: &str,
+    time: SystemTime,
+    field: StateField,
+    id: VersionedHash,
+    parent_state: &ChatRoomStateV1,
+) -> Result<(), RoomStateError> {
+    if parent_state.configuration.configuration.privacy_mode != PrivacyMode::Private {
+        return Ok(());
+    }
+    let encrypted = EncryptedContent::decode(content)
+        .ok_or(RoomStateError::UnencryptedContent { field, id })?;
+    match parent_state.bans.min_secret_version(time) {
+        Some(min_secret_version) if encrypted.secret_version < min_secret_version => {
+            Err(RoomStateError::StaleSecretVersion {
+                field,
+                id,
+                secret_version: encrypted.secret_version,
+                min_secret_version,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Marks message content that is encrypted with a room secret
+const ENCRYPTED_CONTENT_PREFIX: &str = "river-encrypted:";
+
+/// Message content encrypted with version `secret_version` of the room secret. It's stored in the
+/// message's `content` as text so that messages keep the same shape, and so the same signatures,
+/// in public and private rooms.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct EncryptedContent {
+    pub secret_version: u32,
+    pub nonce: [u8; 12],
+    pub ciphertext: Vec<u8>,
+}
+
+impl EncryptedContent {
+    pub fn encode(&self) -> String {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(self, &mut bytes).expect("Serialization should not fail");
+        format!(
+            "{}{}",
+            ENCRYPTED_CONTENT_PREFIX,
+            general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+        )
+    }
+
+    /// Recovers encrypted content from a message's `content`, `None` if it's plaintext
+    pub fn decode(content: &str) -> Option<Self> {
+        let encoded = content.strip_prefix(ENCRYPTED_CONTENT_PREFIX)?;
+        let bytes = general_purpose::URL_SAFE_NO_PAD.decode(encoded).ok()?;
+        ciborium::de::from_reader(bytes.as_slice()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::ban::{AuthorizedUserBan, BansV1, UserBan};
+    use crate::room_state::member::{AuthorizedMember, Member};
+    use crate::room_state::message::{AuthorizedMessageV1, MessageV1, MessagesDelta, MessagesV1};
+    use proptest::prelude::*;
+    use rand::rngs::OsRng;
+    use std::time::Duration;
+
+    fn wrapped(member_id: MemberId, secret_version: u32) -> EncryptedSecretForMember {
+        EncryptedSecretForMember {
+            member_id,
+            secret_version,
+            ciphertext: vec![secret_version as u8; 48],
+            nonce: [0; 12],
+            sender_public_key: [0; 32],
+            epoch_id: None,
+        }
+    }
+
+    fn epoch(secret_version: u32, owner_signing_key: &SigningKey) -> AuthorizedSecretEpoch {
+        AuthorizedSecretEpoch::new(
+            SecretEpoch {
+                secret_version,
+                created_at: SystemTime::UNIX_EPOCH,
+            },
+            owner_signing_key,
+        )
+    }
+
+    fn encrypted_message(
+        author_signing_key: &SigningKey,
+        room_owner: MemberId,
+        secret_version: u32,
+        time: SystemTime,
+    ) -> AuthorizedMessageV1 {
+        let content = EncryptedContent {
+            secret_version,
+            nonce: [0; 12],
+            ciphertext: vec![1, 2, 3],
+        };
+        AuthorizedMessageV1::new(
+            MessageV1 {
+                room_owner,
+                author: MemberId::from(&author_signing_key.verifying_key()),
+                time,
+                content: content.encode(),
+            },
+            author_signing_key,
+        )
+    }
+
+    struct Room {
+        owner_signing_key: SigningKey,
+        owner_id: MemberId,
+        member_id: MemberId,
+        parameters: ChatRoomParametersV1,
+        parent_state: ChatRoomStateV1,
+    }
+
+    fn room_with_member() -> Room {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let owner_id = MemberId::from(&owner_signing_key.verifying_key());
+        let member_vk = SigningKey::generate(&mut OsRng).verifying_key();
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        let mut parent_state = ChatRoomStateV1::default();
+        let member = Member {
+            owner_member_id: owner_id,
+            invited_by: owner_id,
+            member_vk,
+        };
+        parent_state.members.members = vec![AuthorizedMember::new(member, &owner_signing_key)];
+        Room {
+            owner_signing_key,
+            owner_id,
+            member_id: MemberId::from(&member_vk),
+            parameters,
+            parent_state,
+        }
+    }
+
+    #[test]
+    fn test_secrets_apply_delta() {
+        let Room {
+            owner_signing_key,
+            owner_id,
+            member_id,
+            parameters,
+            mut parent_state,
+        } = room_with_member();
+
+        let owner_secret =
+            AuthorizedEncryptedSecretForMember::new(wrapped(owner_id, 1), &owner_signing_key);
+        let member_secret =
+            AuthorizedEncryptedSecretForMember::new(wrapped(member_id, 1), &owner_signing_key);
+        let mut secrets = RoomSecretsV1::default();
+        secrets
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(RoomSecretsDelta {
+                    epochs: vec![epoch(1, &owner_signing_key)],
+                    secrets: vec![member_secret.clone(), owner_secret.clone()],
+                }),
+            )
+            .unwrap();
+        assert_eq!(secrets.verify(&parent_state, &parameters), Ok(()));
+        assert_eq!(secrets.current_version(), Some(1));
+        assert_eq!(
+            secrets.secret_for(member_id, 1),
+            Some(&member_secret.secret)
+        );
+        assert_eq!(
+            secrets.members_without(2, &parent_state, &parameters),
+            vec![owner_id, member_id]
+        );
+
+        // Only the owner can distribute secrets
+        let forged = AuthorizedEncryptedSecretForMember::new(
+            wrapped(member_id, 1),
+            &SigningKey::generate(&mut OsRng),
+        );
+        assert!(matches!(
+            secrets.apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(RoomSecretsDelta {
+                    secrets: vec![forged],
+                    ..Default::default()
+                })
+            ),
+            Err(RoomStateError::InvalidSignature {
+                field: StateField::Secret,
+                ..
+            })
+        ));
+
+        // The member's secret goes with them
+        parent_state.members.members.clear();
+        secrets
+            .apply_delta(&parent_state, &parameters, &None)
+            .unwrap();
+        assert_eq!(secrets.secrets, vec![owner_secret]);
+    }
+
+    #[test]
+    fn test_secrets_need_an_epoch() {
+        let Room {
+            owner_signing_key,
+            member_id,
+            parameters,
+            parent_state,
+            ..
+        } = room_with_member();
+
+        let orphan =
+            AuthorizedEncryptedSecretForMember::new(wrapped(member_id, 1), &owner_signing_key);
+        let mut secrets = RoomSecretsV1::default();
+        secrets
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(RoomSecretsDelta {
+                    secrets: vec![orphan.clone()],
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+        assert!(secrets.secrets.is_empty());
+
+        secrets.secrets.push(orphan);
+        assert_eq!(
+            secrets.verify(&parent_state, &parameters),
+            Err(RoomStateError::UnknownSecretVersion { secret_version: 1 })
+        );
+
+        let forged = epoch(2, &SigningKey::generate(&mut OsRng));
+        assert!(matches!(
+            secrets.apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(RoomSecretsDelta {
+                    epochs: vec![forged],
+                    ..Default::default()
+                })
+            ),
+            Err(RoomStateError::InvalidSignature {
+                field: StateField::SecretEpoch,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_epochs_kept_while_messages_use_them() {
+        let Room {
+            owner_signing_key,
+            owner_id,
+            member_id,
+            parameters,
+            mut parent_state,
+        } = room_with_member();
+
+        let delta = RoomSecretsDelta {
+            epochs: (0..3).map(|v| epoch(v, &owner_signing_key)).collect(),
+            secrets: (0..3)
+                .flat_map(|v| [wrapped(owner_id, v), wrapped(member_id, v)])
+                .map(|s| AuthorizedEncryptedSecretForMember::new(s, &owner_signing_key))
+                .collect(),
+        };
+        parent_state.recent_messages.messages = vec![encrypted_message(
+            &owner_signing_key,
+            owner_id,
+            1,
+            SystemTime::now(),
+        )]
+        .into();
+
+        let mut secrets = RoomSecretsV1::default();
+        secrets
+            .apply_delta(&parent_state, &parameters, &Some(delta))
+            .unwrap();
+        // Nothing is encrypted with epoch 0 anymore, 1 is still needed to read the message and 2 is
+        // current
+        assert_eq!(secrets.versions().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(
+            secrets
+                .secrets_for(member_id)
+                .map(|s| s.secret_version)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+
+        parent_state.recent_messages.messages.entries.clear();
+        secrets
+            .apply_delta(&parent_state, &parameters, &None)
+            .unwrap();
+        assert_eq!(secrets.versions().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(secrets.secrets.len(), 2);
+    }
+
+    #[test]
+    fn test_private_rooms_refuse_plaintext_and_stale_epochs() {
+        let Room {
+            owner_signing_key,
+            owner_id,
+            member_id,
+            parameters,
+            mut parent_state,
+        } = room_with_member();
+        parent_state.configuration.configuration.privacy_mode = PrivacyMode::Private;
+        parent_state.secrets.epochs = vec![epoch(0, &owner_signing_key)];
+        let now = SystemTime::now();
+
+        let plaintext = AuthorizedMessageV1::new(
+            MessageV1 {
+                room_owner: owner_id,
+                author: owner_id,
+                time: now,
+                content: "Hello".to_string(),
+            },
+            &owner_signing_key,
+        );
+        let messages = MessagesV1 {
+            messages: vec![plaintext.clone()].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            messages.verify(&parent_state, &parameters),
+            Err(RoomStateError::UnencryptedContent {
+                field: StateField::Message,
+                id: plaintext.id().0,
+            })
+        );
+        let mut messages = MessagesV1::default();
+        messages
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MessagesDelta {
+                    messages: vec![plaintext],
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+        assert!(messages.messages.entries.is_empty());
+
+        // Until the owner starts an epoch after the ban, messages after it have nothing to use
+        let banned_at = now - Duration::from_secs(60);
+        let ban = AuthorizedUserBan::new(
+            UserBan {
+                owner_member_id: owner_id,
+                banned_at,
+                banned_user: member_id,
+                expires_at: None,
+            },
+            owner_id,
+            &owner_signing_key,
+        );
+        let mut bans = BansV1::default();
+        bans.apply_delta(&parent_state, &parameters, &Some(vec![ban]))
+            .unwrap();
+        assert_eq!(bans.min_secret_version(now), Some(1));
+        assert_eq!(bans.min_secret_version(banned_at), None);
+        parent_state.bans = bans;
+
+        let before_ban = encrypted_message(&owner_signing_key, owner_id, 0, banned_at);
+        let stale = encrypted_message(&owner_signing_key, owner_id, 0, now);
+        let messages = MessagesV1 {
+            messages: vec![before_ban.clone(), stale.clone()].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            messages.verify(&parent_state, &parameters),
+            Err(RoomStateError::StaleSecretVersion {
+                field: StateField::Message,
+                id: stale.id().0,
+                secret_version: 0,
+                min_secret_version: 1,
+            })
+        );
+
+        // The ban records the first epoch the owner starts after it
+        let rekeyed = AuthorizedSecretEpoch::new(
+            SecretEpoch {
+                secret_version: 1,
+                created_at: banned_at + Duration::from_secs(1),
+            },
+            &owner_signing_key,
+        );
+        parent_state.secrets.epochs.push(rekeyed);
+        let mut bans = parent_state.bans.clone();
+        bans.apply_delta(&parent_state, &parameters, &None).unwrap();
+        assert_eq!(bans.min_secret_version(now), Some(1));
+        parent_state.bans = bans;
+
+        let fresh = encrypted_message(&owner_signing_key, owner_id, 1, now);
+        let mut messages = MessagesV1::default();
+        messages
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(MessagesDelta {
+                    messages: vec![before_ban.clone(), stale, fresh.clone()],
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+        assert_eq!(messages.messages.entries, vec![before_ban, fresh]);
+        assert_eq!(messages.verify(&parent_state, &parameters), Ok(()));
+    }
+
+    /// Sends `to` what `from` has that it lacks, as a peer would
+    fn sync(
+        from: &RoomSecretsV1,
+        to: &mut RoomSecretsV1,
+        parent_state: &ChatRoomStateV1,
+        parameters: &ChatRoomParametersV1,
+    ) {
+        let summary = to.summarize(parent_state, parameters);
+        let delta = from.delta(parent_state, parameters, &summary);
+        to.apply_delta(parent_state, parameters, &delta).unwrap();
+    }
+
+    proptest! {
+        /// Two of the owner's clients start the same version at once and wrap it for some of the
+        /// members, the peers end up with the same epoch and only secrets wrapped for it
+        #[test]
+        fn test_concurrent_rotations_converge(
+            created_at in prop::array::uniform2(0..3u64),
+            wrapped_for in prop::array::uniform2(prop::collection::vec(any::<bool>(), 3)),
+            first_to_second: bool,
+        ) {
+            let Room {
+                owner_signing_key,
+                owner_id,
+                member_id,
+                parameters,
+                mut parent_state,
+            } = room_with_member();
+            for _ in 0..2 {
+                let member = Member {
+                    owner_member_id: owner_id,
+                    invited_by: owner_id,
+                    member_vk: SigningKey::generate(&mut OsRng).verifying_key(),
+                };
+                parent_state
+                    .members
+                    .members
+                    .push(AuthorizedMember::new(member, &owner_signing_key));
+            }
+            let member_ids: Vec<MemberId> = parent_state
+                .members
+                .members
+                .iter()
+                .map(|m| m.member.id())
+                .collect();
+            prop_assert_eq!(member_ids[0], member_id);
+
+            let mut peers: Vec<RoomSecretsV1> = (0..2)
+                .map(|client| {
+                    let epoch = AuthorizedSecretEpoch::new(
+                        SecretEpoch {
+                            secret_version: 1,
+                            created_at: SystemTime::UNIX_EPOCH
+                                + Duration::from_secs(created_at[client]),
+                        },
+                        &owner_signing_key,
+                    );
+                    let secrets = std::iter::once(owner_id)
+                        .chain(
+                            member_ids
+                                .iter()
+                                .zip(&wrapped_for[client])
+                                .filter(|(_, wrapped)| **wrapped)
+                                .map(|(member_id, _)| *member_id),
+                        )
+                        .map(|member_id| {
+                            let secret = EncryptedSecretForMember {
+                                ciphertext: vec![client as u8; 48],
+                                epoch_id: Some(epoch.epoch.id()),
+                                ..wrapped(member_id, 1)
+                            };
+                            AuthorizedEncryptedSecretForMember::new(secret, &owner_signing_key)
+                        })
+                        .collect();
+                    let mut peer = RoomSecretsV1::default();
+                    peer.apply_delta(
+                        &parent_state,
+                        &parameters,
+                        &Some(RoomSecretsDelta {
+                            epochs: vec![epoch],
+                            secrets,
+                        }),
+                    )
+                    .unwrap();
+                    peer
+                })
+                .collect();
+            let winner = peers
+                .iter()
+                .map(|peer| peer.epochs[0].clone())
+                .min_by_key(|e| e.epoch.id())
+                .unwrap();
+
+            let (from, to) = if first_to_second { (0, 1) } else { (1, 0) };
+            let sent = peers[from].clone();
+            sync(&sent, &mut peers[to], &parent_state, &parameters);
+            let sent = peers[to].clone();
+            sync(&sent, &mut peers[from], &parent_state, &parameters);
+
+            prop_assert_eq!(&peers[0], &peers[1]);
+            prop_assert_eq!(&peers[0].epochs, &vec![winner.clone()]);
+            prop_assert!(peers[0].secrets.iter().all(|s| s.secret.is_for(&winner.epoch)));
+            prop_assert!(peers[0].secret_for(owner_id, 1).is_some());
+            prop_assert_eq!(peers[0].verify(&parent_state, &parameters), Ok(()));
+            // Nothing more to send either way
+            let summary = peers[1].summarize(&parent_state, &parameters);
+            prop_assert_eq!(peers[0].delta(&parent_state, &parameters, &summary), None);
+        }
+    }
+
+    #[test]
+    fn test_summaries_without_ids() {
+        let Room {
+            owner_signing_key,
+            owner_id,
+            parameters,
+            parent_state,
+            ..
+        } = room_with_member();
+        let mut secrets = RoomSecretsV1::default();
+        secrets
+            .apply_delta(
+                &parent_state,
+                &parameters,
+                &Some(RoomSecretsDelta {
+                    epochs: vec![epoch(1, &owner_signing_key)],
+                    secrets: vec![AuthorizedEncryptedSecretForMember::new(
+                        wrapped(owner_id, 1),
+                        &owner_signing_key,
+                    )],
+                }),
+            )
+            .unwrap();
+
+        // Summaries from before ids were added still decode, and are sent what they lack by version
+        let summary = RoomSecretsSummary {
+            epoch_ids: Vec::new(),
+            secret_ids: Vec::new(),
+            ..secrets.summarize(&parent_state, &parameters)
+        };
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&summary, &mut bytes).unwrap();
+        let decoded: RoomSecretsSummary = ciborium::de::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(decoded, summary);
+        assert_eq!(secrets.delta(&parent_state, &parameters, &summary), None);
+        assert!(secrets
+            .delta(&parent_state, &parameters, &RoomSecretsSummary::default())
+            .is_some());
+    }
+
+    #[test]
+    fn test_encrypted_content_round_trip() {
+        let content = EncryptedContent {
+            secret_version: 3,
+            nonce: [7; 12],
+            ciphertext: vec![1, 2, 3],
+        };
+        let encoded = content.encode();
+        assert!(encoded.starts_with(ENCRYPTED_CONTENT_PREFIX));
+        assert_eq!(EncryptedContent::decode(&encoded), Some(content));
+        assert_eq!(EncryptedContent::decode("Hello"), None);
+    }
+}
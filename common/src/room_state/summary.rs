@@ -0,0 +1,144 @@
+use crate::room_state::versioned::ChatRoomStateSummary;
+use crate::room_state::ChatRoomStateV1Summary;
+
+/*
+ A summary lists the id of everything a peer has, which for a room with thousands of members
+ outgrows the deltas it saves. Before a summary is sent to another peer the larger id lists can be
+ compacted into Bloom filters, in exchange for `delta` occasionally leaving out something the peer
+ is missing as it looks present. Filters are seeded with the ids they cover, so what was missed
+ is sent once the peer's state next changes. Members are sent with their invite chain when the
+ summary's members are compacted, a peer can't take a member whose inviter it doesn't have.
+
+ Only summaries that go over the network are compacted. `merge` relies on the exact summary to
+ converge, so `summarize` itself never compacts.
+*/
+
+/// Which fields of a summary to compact, and how
+#[derive(Clone, Debug, PartialEq)]
+pub struct SummaryCompaction {
+    pub bans: bool,
+    pub members: bool,
+    /// Messages and their edits and deletions
+    pub messages: bool,
+    pub reactions: bool,
+    /// Lists shorter than this stay as they are, a filter isn't much smaller
+    pub min_ids: usize,
+    /// How often a compacted field wrongly claims to contain an id
+    pub false_positive_rate: f64,
+}
+
+impl Default for SummaryCompaction {
+    fn default() -> Self {
+        SummaryCompaction {
+            bans: true,
+            members: true,
+            messages: true,
+            reactions: true,
+            min_ids: 64,
+            false_positive_rate: 0.01,
+        }
+    }
+}
+
+impl ChatRoomStateV1Summary {
+    pub fn compact(&mut self, compaction: &SummaryCompaction) {
+        let (min_ids, rate) = (compaction.min_ids, compaction.false_positive_rate);
+        if compaction.bans {
+            self.bans.compact(min_ids, rate);
+        }
+        if compaction.members {
+            self.members.members.compact(min_ids, rate);
+            self.members.removals.compact(min_ids, rate);
+        }
+        if compaction.messages {
+            self.recent_messages.messages.compact(min_ids, rate);
+            self.recent_messages.actions.compact(min_ids, rate);
+        }
+        if compaction.reactions {
+            self.reactions.compact(min_ids, rate);
+        }
+    }
+}
+
+impl ChatRoomStateSummary {
+    pub fn compact(&mut self, compaction: &SummaryCompaction) {
+        match self {
+            ChatRoomStateSummary::V1(room) => room.compact(compaction),
+            ChatRoomStateSummary::V2(state) => state.room.compact(compaction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::configuration::{AuthorizedConfigurationV1, Configuration};
+    use crate::room_state::member::{AuthorizedMember, Member, MembersDelta};
+    use crate::room_state::{ChatRoomParametersV1, ChatRoomStateV1Delta};
+    use crate::ChatRoomStateV1;
+    use ed25519_dalek::SigningKey;
+    use freenet_scaffold::id_set::IdSet;
+    use freenet_scaffold::ComposableState;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_compact() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        let configuration = Configuration {
+            owner_member_id: parameters.owner_id(),
+            max_members: 1000,
+            ..Configuration::default()
+        };
+        let mut state = ChatRoomStateV1 {
+            configuration: AuthorizedConfigurationV1::new(configuration, &owner_signing_key),
+            ..ChatRoomStateV1::default()
+        };
+        let members = (0..500)
+            .map(|_| {
+                let member = Member {
+                    owner_member_id: parameters.owner_id(),
+                    invited_by: parameters.owner_id(),
+                    member_vk: SigningKey::generate(&mut OsRng).verifying_key(),
+                };
+                AuthorizedMember::new(member, &owner_signing_key)
+            })
+            .collect();
+        let delta = ChatRoomStateV1Delta {
+            members: Some(MembersDelta::new(members)),
+            ..Default::default()
+        };
+        let current_state = state.clone();
+        state
+            .apply_delta(&current_state, &parameters, &Some(delta))
+            .unwrap();
+
+        let summary = state.summarize(&state, &parameters);
+        let mut compacted = summary.clone();
+        compacted.compact(&SummaryCompaction::default());
+        assert!(matches!(compacted.members.members, IdSet::Bloom(_)));
+        // Nothing to compact in the other fields
+        assert_eq!(compacted.bans, summary.bans);
+        assert_eq!(compacted.members.removals, summary.members.removals);
+
+        let size = |summary: &ChatRoomStateV1Summary| {
+            let mut bytes = Vec::new();
+            ciborium::ser::into_writer(summary, &mut bytes).unwrap();
+            bytes.len()
+        };
+        assert!(size(&compacted) * 5 < size(&summary));
+
+        // The members field can be left as it is
+        let mut uncompacted = summary.clone();
+        uncompacted.compact(&SummaryCompaction {
+            members: false,
+            ..SummaryCompaction::default()
+        });
+        assert_eq!(uncompacted, summary);
+
+        // There are no false negatives, so a peer with everything is sent nothing
+        assert_eq!(state.delta(&state, &parameters, &compacted), None);
+    }
+}
@@ -1,13 +1,18 @@
+use crate::room_state::configuration::Configuration;
+use crate::room_state::error::{RoomStateError, StateField};
 use crate::room_state::member::MemberId;
-use crate::room_state::ChatRoomParametersV1;
+use crate::room_state::{ChatRoomParametersV1, ChatRoomStateV1Delta};
 use crate::util::{sign_struct, truncated_base64, verify_struct};
 use crate::ChatRoomStateV1;
 use blake3::Hash;
-use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use freenet_scaffold::util::{blake3_hash, VersionedHash};
 use freenet_scaffold::ComposableState;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+mod succession;
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct OptionalUpgradeV1(pub Option<AuthorizedUpgradeV1>);
 
@@ -17,6 +22,8 @@ impl Default for OptionalUpgradeV1 {
     }
 }
 
+const UPGRADE_ID_CONTEXT: &str = "river 2025-01 upgrade id";
+
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct AuthorizedUpgradeV1 {
     pub upgrade: UpgradeV1,
@@ -28,16 +35,15 @@ impl ComposableState for OptionalUpgradeV1 {
     type Summary = Option<u8>;
     type Delta = AuthorizedUpgradeV1;
     type Parameters = ChatRoomParametersV1;
+    type Error = RoomStateError;
 
     fn verify(
         &self,
-        _parent_state: &Self::ParentState,
+        parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
-    ) -> Result<(), String> {
+    ) -> Result<(), Self::Error> {
         if let Some(upgrade) = &self.0 {
-            upgrade
-                .validate(&parameters.owner)
-                .map_err(|e| format!("Invalid signature: {}", e))
+            upgrade.verify(&parent_state.configuration.configuration, parameters)
         } else {
             Ok(())
         }
@@ -75,18 +81,27 @@ impl ComposableState for OptionalUpgradeV1 {
 
     fn apply_delta(
         &mut self,
-        _parent_state: &Self::ParentState,
+        parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
         delta: &Option<Self::Delta>,
-    ) -> Result<(), String> {
+    ) -> Result<(), Self::Error> {
+        let configuration = &parent_state.configuration.configuration;
         if let Some(delta) = delta {
             // Verify the delta before applying it
-            delta
-                .validate(&parameters.owner)
-                .map_err(|e| format!("Invalid upgrade signature: {}", e))?;
+            delta.verify(configuration, parameters)?;
 
             *self = OptionalUpgradeV1(Some(delta.clone()));
         }
+
+        // A successor's upgrade lapses once the owner designates someone else, peers that saw the
+        // new configuration first never accepted it
+        if self
+            .0
+            .as_ref()
+            .is_some_and(|upgrade| upgrade.verify(configuration, parameters).is_err())
+        {
+            self.0 = None;
+        }
         Ok(())
     }
 }
@@ -105,6 +120,35 @@ impl AuthorizedUpgradeV1 {
     ) -> Result<(), ed25519_dalek::SignatureError> {
         verify_struct(&self.upgrade, &self.signature, &verifying_key)
     }
+
+    /// Checks the upgrade was signed by the owner, or by the successor designated in
+    /// `configuration` if it hands the room over to them
+    pub fn verify(
+        &self,
+        configuration: &Configuration,
+        parameters: &ChatRoomParametersV1,
+    ) -> Result<(), RoomStateError> {
+        if self.validate(&parameters.owner).is_ok() {
+            return Ok(());
+        }
+        match (self.upgrade.new_owner, configuration.successor) {
+            (Some(new_owner), Some(successor)) if new_owner == successor => self
+                .validate(&successor)
+                .map_err(|_| self.invalid_signature()),
+            _ => Err(self.invalid_signature()),
+        }
+    }
+
+    pub fn id(&self) -> VersionedHash {
+        blake3_hash(UPGRADE_ID_CONTEXT, &self.signature.to_bytes())
+    }
+
+    fn invalid_signature(&self) -> RoomStateError {
+        RoomStateError::InvalidSignature {
+            field: StateField::Upgrade,
+            id: self.id(),
+        }
+    }
 }
 
 impl fmt::Debug for AuthorizedUpgradeV1 {
@@ -124,14 +168,87 @@ pub struct UpgradeV1 {
     pub owner_member_id: MemberId,
     pub version: u8,
     pub new_chatroom_address: Hash,
+    /// Set when the room is handed over to a new owner, the room at `new_chatroom_address` is owned
+    /// by them and continues this one, see `ChatRoomStateV1::succeeded_by`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_owner: Option<VerifyingKey>,
+}
+
+impl ChatRoomStateV1 {
+    /// Points the room at the contract that replaces it, at `new_chatroom_address` and owned by
+    /// `new_owner_signing_key`, and returns that contract's initial state. The upgrade is signed
+    /// with `signing_key`, the owner's or the designated successor's when handing it to them.
+    pub fn upgrade_to(
+        &mut self,
+        parameters: &ChatRoomParametersV1,
+        signing_key: &SigningKey,
+        new_owner_signing_key: &SigningKey,
+        new_chatroom_address: Hash,
+    ) -> Result<ChatRoomStateV1, RoomStateError> {
+        let upgrade = self.next_upgrade(
+            parameters,
+            new_owner_signing_key.verifying_key(),
+            new_chatroom_address,
+        );
+        self.upgrade_with(
+            parameters,
+            AuthorizedUpgradeV1::new(upgrade, signing_key),
+            &mut |data| new_owner_signing_key.sign(data),
+        )
+    }
+
+    /// The upgrade pointing the room at `new_chatroom_address` owned by `new_owner_vk`, to be signed
+    pub fn next_upgrade(
+        &self,
+        parameters: &ChatRoomParametersV1,
+        new_owner_vk: VerifyingKey,
+        new_chatroom_address: Hash,
+    ) -> UpgradeV1 {
+        UpgradeV1 {
+            owner_member_id: parameters.owner_id(),
+            version: self
+                .upgrade
+                .0
+                .as_ref()
+                .map_or(1, |upgrade| upgrade.upgrade.version.saturating_add(1)),
+            new_chatroom_address,
+            new_owner: (new_owner_vk != parameters.owner).then_some(new_owner_vk),
+        }
+    }
+
+    /// Like `upgrade_to` with the upgrade signed already and `sign` signing as the new owner, see
+    /// `succeeded_by_signer`
+    pub fn upgrade_with(
+        &mut self,
+        parameters: &ChatRoomParametersV1,
+        upgrade: AuthorizedUpgradeV1,
+        sign: &mut dyn FnMut(&[u8]) -> Signature,
+    ) -> Result<ChatRoomStateV1, RoomStateError> {
+        // With the same owner every signature still holds and the room carries over as it is
+        let new_state = match upgrade.upgrade.new_owner {
+            Some(new_owner_vk) => self.succeeded_by_signer(parameters, new_owner_vk, sign)?,
+            None => ChatRoomStateV1 {
+                upgrade: OptionalUpgradeV1::default(),
+                ..self.clone()
+            },
+        };
+        let delta = ChatRoomStateV1Delta {
+            upgrade: Some(upgrade),
+            ..Default::default()
+        };
+        let current_state = self.clone();
+        self.apply_delta(&current_state, parameters, &Some(delta))?;
+        Ok(new_state)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::room_state::configuration::AuthorizedConfigurationV1;
     use crate::room_state::member::MemberId;
     use ed25519_dalek::SigningKey;
-    use freenet_scaffold::util::FastHash;
+    use freenet_scaffold::util::VersionedHash;
     use rand::rngs::OsRng;
 
     fn create_test_upgrade(owner_id: MemberId) -> UpgradeV1 {
@@ -139,6 +256,7 @@ mod tests {
             owner_member_id: owner_id,
             version: 1,
             new_chatroom_address: Hash::from([0; 32]),
+            new_owner: None,
         }
     }
 
@@ -146,7 +264,7 @@ mod tests {
     fn test_authorized_upgrade_new_and_validate() {
         let signing_key = SigningKey::generate(&mut OsRng);
         let verifying_key = signing_key.verifying_key();
-        let owner_id = MemberId(FastHash(0));
+        let owner_id = MemberId(VersionedHash::Blake3V1([0; 32]));
 
         let upgrade = create_test_upgrade(owner_id);
         let authorized_upgrade = AuthorizedUpgradeV1::new(upgrade.clone(), &signing_key);
@@ -202,7 +320,7 @@ mod tests {
     #[test]
     fn test_optional_upgrade_summarize() {
         let signing_key = SigningKey::generate(&mut OsRng);
-        let owner_id = MemberId(FastHash(0));
+        let owner_id = MemberId(VersionedHash::Blake3V1([0; 32]));
 
         let upgrade = create_test_upgrade(owner_id);
         let authorized_upgrade = AuthorizedUpgradeV1::new(upgrade, &signing_key);
@@ -225,7 +343,7 @@ mod tests {
     #[test]
     fn test_optional_upgrade_delta() {
         let signing_key = SigningKey::generate(&mut OsRng);
-        let owner_id = MemberId(FastHash(0));
+        let owner_id = MemberId(VersionedHash::Blake3V1([0; 32]));
 
         let upgrade = create_test_upgrade(owner_id);
         let authorized_upgrade = AuthorizedUpgradeV1::new(upgrade, &signing_key);
@@ -250,7 +368,7 @@ mod tests {
     #[test]
     fn test_optional_upgrade_apply_delta() {
         let signing_key = SigningKey::generate(&mut OsRng);
-        let owner_id = MemberId(FastHash(0));
+        let owner_id = MemberId(VersionedHash::Blake3V1([0; 32]));
 
         let upgrade = create_test_upgrade(owner_id);
         let authorized_upgrade = AuthorizedUpgradeV1::new(upgrade, &signing_key);
@@ -268,4 +386,90 @@ mod tests {
             .is_ok());
         assert_eq!(optional_upgrade, OptionalUpgradeV1(Some(delta)));
     }
+
+    #[test]
+    fn test_successor_upgrade() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let successor_signing_key = SigningKey::generate(&mut OsRng);
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        let handover = |new_owner: &SigningKey| {
+            let upgrade = UpgradeV1 {
+                new_owner: Some(new_owner.verifying_key()),
+                ..create_test_upgrade(parameters.owner_id())
+            };
+            AuthorizedUpgradeV1::new(upgrade, &successor_signing_key)
+        };
+        let to_successor = Some(handover(&successor_signing_key));
+        let to_owner = Some(handover(&owner_signing_key));
+        let mut parent_state = ChatRoomStateV1::default();
+        let mut optional_upgrade = OptionalUpgradeV1(None);
+
+        // Only a designated successor can hand the room over, and only to themselves
+        assert!(optional_upgrade
+            .apply_delta(&parent_state, &parameters, &to_successor)
+            .is_err());
+        parent_state.configuration.configuration.successor =
+            Some(successor_signing_key.verifying_key());
+        assert!(optional_upgrade
+            .apply_delta(&parent_state, &parameters, &to_owner)
+            .is_err());
+        assert!(optional_upgrade
+            .apply_delta(&parent_state, &parameters, &to_successor)
+            .is_ok());
+        assert!(optional_upgrade.0.is_some());
+
+        // The upgrade lapses once someone else is designated
+        parent_state.configuration.configuration.successor = None;
+        assert!(optional_upgrade
+            .apply_delta(&parent_state, &parameters, &None)
+            .is_ok());
+        assert_eq!(optional_upgrade, OptionalUpgradeV1(None));
+    }
+
+    #[test]
+    fn test_upgrade_to() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        let configuration = Configuration {
+            owner_member_id: parameters.owner_id(),
+            ..Configuration::default()
+        };
+        let state = ChatRoomStateV1 {
+            configuration: AuthorizedConfigurationV1::new(configuration, &owner_signing_key),
+            ..ChatRoomStateV1::default()
+        };
+        let address = Hash::from([1; 32]);
+
+        // Upgrading to a new contract of the same owner carries the room over as it is
+        let mut upgraded = state.clone();
+        let new_state = upgraded
+            .upgrade_to(&parameters, &owner_signing_key, &owner_signing_key, address)
+            .unwrap();
+        assert_eq!(new_state, state);
+        let upgrade = &upgraded.upgrade.0.as_ref().unwrap().upgrade;
+        assert_eq!(upgrade.new_chatroom_address, address);
+        assert_eq!(upgrade.new_owner, None);
+
+        // A new owner gets a room of their own
+        let new_owner_signing_key = SigningKey::generate(&mut OsRng);
+        let new_parameters = ChatRoomParametersV1 {
+            owner: new_owner_signing_key.verifying_key(),
+        };
+        let new_state = upgraded
+            .upgrade_to(
+                &parameters,
+                &owner_signing_key,
+                &new_owner_signing_key,
+                address,
+            )
+            .unwrap();
+        assert_eq!(new_state.verify(&new_state, &new_parameters), Ok(()));
+        let upgrade = &upgraded.upgrade.0.as_ref().unwrap().upgrade;
+        assert_eq!(upgrade.version, 2);
+        assert_eq!(upgrade.new_owner, Some(new_parameters.owner));
+    }
 }
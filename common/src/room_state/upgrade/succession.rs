@@ -0,0 +1,570 @@
+use crate::room_state::ban::{AuthorizedUserBan, UserBan};
+use crate::room_state::configuration::AuthorizedConfigurationV1;
+use crate::room_state::error::RoomStateError;
+use crate::room_state::member::{
+    AuthorizedMember, AuthorizedRemoval, Member, MemberAuthorization, MemberId, MembersDelta,
+    Removal,
+};
+use crate::room_state::member_info::{AuthorizedMemberInfo, MemberInfo};
+use crate::room_state::secret::{
+    AuthorizedEncryptedSecretForMember, AuthorizedSecretEpoch, RoomSecretsDelta,
+};
+use crate::room_state::{ChatRoomParametersV1, ChatRoomStateV1Delta};
+use crate::util::sign_struct_with;
+use crate::ChatRoomStateV1;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use freenet_scaffold::ComposableState;
+use std::collections::HashSet;
+
+/*
+ The owner's key is part of the room's parameters, so handing the room over to a new key means
+ starting a new room owned by it. The old room is upgraded to point at the new one with
+ `UpgradeV1::new_owner` set, signed by the owner or by the successor they designated, and clients
+ follow the room to its new owner when they see it.
+
+ What the old owner signed is signed again by the new owner: the configuration, their bans, the
+ secrets of private rooms and the memberships of those they invited. Members further down an invite
+ chain were signed by their inviter, who keeps their key, so their memberships, messages and
+ reactions carry over as they are. What the old owner posted stays in the old room. Removals name
+ the owner, so the new owner signs all of them again, those the old owner didn't make for whoever
+ did, see `Removal::signed_for`. Members who left still left.
+*/
+
+impl ChatRoomStateV1 {
+    /// The state of the room owned by `new_owner_signing_key` that continues this one, see above
+    pub fn succeeded_by(
+        &self,
+        parameters: &ChatRoomParametersV1,
+        new_owner_signing_key: &SigningKey,
+    ) -> Result<ChatRoomStateV1, RoomStateError> {
+        self.succeeded_by_signer(
+            parameters,
+            new_owner_signing_key.verifying_key(),
+            &mut |data| new_owner_signing_key.sign(data),
+        )
+    }
+
+    /// Like `succeeded_by` with `sign` signing as the new owner, eg. with signatures of
+    /// `succession_payloads` made ahead of time by a key kept elsewhere
+    pub fn succeeded_by_signer(
+        &self,
+        parameters: &ChatRoomParametersV1,
+        new_owner_vk: VerifyingKey,
+        sign: &mut dyn FnMut(&[u8]) -> Signature,
+    ) -> Result<ChatRoomStateV1, RoomStateError> {
+        let new_parameters = ChatRoomParametersV1 {
+            owner: new_owner_vk,
+        };
+        let (mut state, delta, later_delta) = self.signed_again(parameters, new_owner_vk, sign);
+        let current_state = state.clone();
+        state.apply_delta(&current_state, &new_parameters, &Some(delta))?;
+        // Secrets come once the messages are in, epochs no message uses would be dropped before.
+        // Removals come once the members are in, otherwise they would have nobody to end.
+        let current_state = state.clone();
+        state.apply_delta(&current_state, &new_parameters, &Some(later_delta))?;
+        Ok(state)
+    }
+
+    /// Everything the new owner signs to take the room over, as it's signed
+    pub fn succession_payloads(
+        &self,
+        parameters: &ChatRoomParametersV1,
+        new_owner_vk: VerifyingKey,
+    ) -> Vec<Vec<u8>> {
+        let mut payloads = Vec::new();
+        self.signed_again(parameters, new_owner_vk, &mut |data| {
+            payloads.push(data.to_vec());
+            Signature::from_bytes(&[0; 64])
+        });
+        payloads
+    }
+
+    /// The new owner's room with their configuration, what they signed again to go in it, and
+    /// what goes in after that
+    fn signed_again(
+        &self,
+        parameters: &ChatRoomParametersV1,
+        new_owner_vk: VerifyingKey,
+        sign: &mut dyn FnMut(&[u8]) -> Signature,
+    ) -> (ChatRoomStateV1, ChatRoomStateV1Delta, ChatRoomStateV1Delta) {
+        let old_owner_id = parameters.owner_id();
+        let new_owner_id = MemberId::from(&new_owner_vk);
+
+        let mut configuration = self.configuration.configuration.clone();
+        configuration.owner_member_id = new_owner_id;
+        configuration.configuration_version += 1;
+        configuration.name = self.configuration.name().to_string();
+        // The new owner may have been a member, the owner is neither a moderator nor a successor
+        configuration
+            .moderators
+            .retain(|moderator| moderator.member_id != new_owner_id);
+        if configuration.successor == Some(new_owner_vk) {
+            configuration.successor = None;
+        }
+        let state = ChatRoomStateV1 {
+            configuration: AuthorizedConfigurationV1 {
+                signature: sign_struct_with(&configuration, sign),
+                configuration,
+                rename: None,
+            },
+            ..Default::default()
+        };
+
+        let bans = self
+            .bans
+            .in_effect()
+            .filter(|ban| ban.ban.banned_user != new_owner_id)
+            .map(|ban| {
+                if ban.banned_by == old_owner_id {
+                    let ban = UserBan {
+                        owner_member_id: new_owner_id,
+                        ..ban.ban.clone()
+                    };
+                    AuthorizedUserBan {
+                        signature: sign_struct_with(&ban, sign),
+                        ban,
+                        banned_by: new_owner_id,
+                        unban: None,
+                        min_secret_version: None,
+                    }
+                } else {
+                    ban.clone()
+                }
+            })
+            .collect();
+
+        let mut seen = HashSet::new();
+        let members = self
+            .members
+            .memberships()
+            .filter(|m| {
+                m.member.id() != new_owner_id && seen.insert((m.member.id(), m.joined_at()))
+            })
+            .map(|m| {
+                if m.inviter() != Some(old_owner_id) {
+                    return m.clone();
+                }
+                let member = Member {
+                    owner_member_id: new_owner_id,
+                    invited_by: new_owner_id,
+                    member_vk: m.member.member_vk,
+                };
+                let joined_at = m.joined_at();
+                let signature = match joined_at {
+                    Some(joined_at) => sign_struct_with((&member, joined_at), sign),
+                    None => sign_struct_with(&member, sign),
+                };
+                AuthorizedMember {
+                    member,
+                    authorization: MemberAuthorization::Invite {
+                        signature,
+                        joined_at,
+                    },
+                }
+            })
+            .collect();
+
+        // The new owner keeps their own info if they were a member, otherwise takes the old owner's
+        let has_info = self
+            .member_info
+            .member_info
+            .iter()
+            .any(|info| info.member_info.member_id == new_owner_id);
+        let member_info = self
+            .member_info
+            .member_info
+            .iter()
+            .filter_map(|info| {
+                if info.member_info.member_id != old_owner_id {
+                    Some(info.clone())
+                } else if has_info {
+                    None
+                } else {
+                    let member_info = MemberInfo {
+                        member_id: new_owner_id,
+                        ..info.member_info.clone()
+                    };
+                    Some(AuthorizedMemberInfo {
+                        signature: sign_struct_with(&member_info, sign),
+                        member_info,
+                    })
+                }
+            })
+            .collect();
+
+        let empty = ChatRoomStateV1::default();
+        let delta = ChatRoomStateV1Delta {
+            bans: Some(bans),
+            members: Some(MembersDelta::new(members)),
+            member_info: Some(member_info),
+            recent_messages: self.recent_messages.delta(
+                self,
+                parameters,
+                &empty.recent_messages.summarize(&empty, parameters),
+            ),
+            reactions: self.reactions.delta(
+                self,
+                parameters,
+                &empty.reactions.summarize(&empty, parameters),
+            ),
+            ..Default::default()
+        };
+
+        let removals = self
+            .members
+            .removals
+            .iter()
+            .filter(|r| r.removal.member_vk != new_owner_vk)
+            .map(|r| {
+                let remover = r.removal.remover();
+                let removal = Removal {
+                    owner_member_id: new_owner_id,
+                    removed_by: new_owner_vk,
+                    signed_for: (remover != parameters.owner).then_some(remover),
+                    ..r.removal.clone()
+                };
+                AuthorizedRemoval {
+                    signature: sign_struct_with(&removal, sign),
+                    removal,
+                }
+            })
+            .collect();
+        let secrets = RoomSecretsDelta {
+            epochs: self
+                .secrets
+                .epochs
+                .iter()
+                .map(|e| AuthorizedSecretEpoch {
+                    signature: sign_struct_with(&e.epoch, sign),
+                    epoch: e.epoch.clone(),
+                })
+                .collect(),
+            secrets: self
+                .secrets
+                .secrets
+                .iter()
+                .map(|s| AuthorizedEncryptedSecretForMember {
+                    signature: sign_struct_with(&s.secret, sign),
+                    secret: s.secret.clone(),
+                })
+                .collect(),
+        };
+        let later_delta = ChatRoomStateV1Delta {
+            members: Some(MembersDelta::remove(removals)),
+            secrets: Some(secrets),
+            ..Default::default()
+        };
+        (state, delta, later_delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::configuration::Configuration;
+    use crate::room_state::message::{AuthorizedMessageV1, MessageV1, MessagesDelta};
+    use blake3::Hash;
+    use ed25519_dalek::VerifyingKey;
+    use rand::rngs::OsRng;
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_succeeded_by() {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        let owner_id = parameters.owner_id();
+        let configuration = Configuration {
+            owner_member_id: owner_id,
+            ..Configuration::default()
+        };
+        let mut state = ChatRoomStateV1 {
+            configuration: AuthorizedConfigurationV1::new(configuration, &owner_signing_key),
+            ..ChatRoomStateV1::default()
+        };
+
+        // The owner invites the first three and the fifth and bans the third, the first invites
+        // the fourth
+        let keys: Vec<SigningKey> = (0..5).map(|_| SigningKey::generate(&mut OsRng)).collect();
+        let id = |key: &SigningKey| MemberId::from(&key.verifying_key());
+        let member = |invitee: &SigningKey, inviter: &SigningKey| {
+            let member = Member {
+                owner_member_id: owner_id,
+                invited_by: id(inviter),
+                member_vk: invitee.verifying_key(),
+            };
+            AuthorizedMember::new(member, inviter)
+        };
+        let message = |author: &SigningKey| {
+            let message = MessageV1 {
+                room_owner: owner_id,
+                author: id(author),
+                time: SystemTime::now(),
+                content: "Hello".to_string(),
+            };
+            AuthorizedMessageV1::new(message, author)
+        };
+        let ban = UserBan {
+            owner_member_id: owner_id,
+            banned_at: SystemTime::now(),
+            banned_user: id(&keys[2]),
+            expires_at: None,
+        };
+        let delta = ChatRoomStateV1Delta {
+            bans: Some(vec![AuthorizedUserBan::new(
+                ban,
+                owner_id,
+                &owner_signing_key,
+            )]),
+            members: Some(MembersDelta::new(vec![
+                member(&keys[0], &owner_signing_key),
+                member(&keys[1], &owner_signing_key),
+                member(&keys[2], &owner_signing_key),
+                member(&keys[3], &keys[0]),
+                member(&keys[4], &owner_signing_key),
+            ])),
+            recent_messages: Some(MessagesDelta {
+                messages: vec![message(&owner_signing_key), message(&keys[3])],
+                actions: Vec::new(),
+            }),
+            ..Default::default()
+        };
+        let current_state = state.clone();
+        state
+            .apply_delta(&current_state, &parameters, &Some(delta))
+            .unwrap();
+
+        // The owner kicks the second and the fifth leaves
+        let removal = |member: &SigningKey, remover: &SigningKey| {
+            let removal = Removal {
+                owner_member_id: owner_id,
+                member_vk: member.verifying_key(),
+                removed_by: remover.verifying_key(),
+                removed_at: SystemTime::now(),
+                signed_for: None,
+            };
+            AuthorizedRemoval::new(removal, remover)
+        };
+        let delta = ChatRoomStateV1Delta {
+            members: Some(MembersDelta::remove(vec![
+                removal(&keys[1], &owner_signing_key),
+                removal(&keys[4], &keys[4]),
+            ])),
+            ..Default::default()
+        };
+        let current_state = state.clone();
+        state
+            .apply_delta(&current_state, &parameters, &Some(delta))
+            .unwrap();
+
+        let new_owner_signing_key = SigningKey::generate(&mut OsRng);
+        let new_parameters = ChatRoomParametersV1 {
+            owner: new_owner_signing_key.verifying_key(),
+        };
+        let succeeded = state
+            .succeeded_by(&parameters, &new_owner_signing_key)
+            .unwrap();
+        assert_eq!(succeeded.verify(&succeeded, &new_parameters), Ok(()));
+        assert_eq!(
+            succeeded.configuration.configuration.owner_member_id,
+            new_parameters.owner_id()
+        );
+
+        // The kicked and banned members stay out and the ban is now the new owner's
+        let mut members: Vec<MemberId> = succeeded
+            .members
+            .members
+            .iter()
+            .map(|m| m.member.id())
+            .collect();
+        members.sort();
+        let mut expected = vec![id(&keys[0]), id(&keys[3])];
+        expected.sort();
+        assert_eq!(members, expected);
+        assert_eq!(succeeded.bans.0.len(), 1);
+        assert_eq!(succeeded.bans.0[0].banned_by, new_parameters.owner_id());
+
+        // The kick is now the new owner's while the member who left still left
+        let removal_of = |key: &SigningKey| {
+            succeeded
+                .members
+                .removals
+                .iter()
+                .find(|r| r.removal.member_vk == key.verifying_key())
+                .map(|r| r.removal.clone())
+                .unwrap()
+        };
+        assert_eq!(removal_of(&keys[1]).remover(), new_parameters.owner);
+        assert!(!removal_of(&keys[1]).is_leave());
+        assert!(removal_of(&keys[4]).is_leave());
+
+        // What the old owner posted stays behind
+        let authors: Vec<MemberId> = succeeded
+            .recent_messages
+            .messages
+            .entries
+            .iter()
+            .map(|m| m.message.author())
+            .collect();
+        assert_eq!(authors, vec![id(&keys[3])]);
+    }
+
+    /// A room whose owner designated its only member as their successor
+    fn room_with_successor() -> (
+        ChatRoomStateV1,
+        ChatRoomParametersV1,
+        SigningKey,
+        SigningKey,
+    ) {
+        let owner_signing_key = SigningKey::generate(&mut OsRng);
+        let successor_signing_key = SigningKey::generate(&mut OsRng);
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        let configuration = Configuration {
+            owner_member_id: parameters.owner_id(),
+            successor: Some(successor_signing_key.verifying_key()),
+            ..Configuration::default()
+        };
+        let mut state = ChatRoomStateV1 {
+            configuration: AuthorizedConfigurationV1::new(configuration, &owner_signing_key),
+            ..ChatRoomStateV1::default()
+        };
+        let member = Member {
+            owner_member_id: parameters.owner_id(),
+            invited_by: parameters.owner_id(),
+            member_vk: successor_signing_key.verifying_key(),
+        };
+        let delta = ChatRoomStateV1Delta {
+            members: Some(MembersDelta::new(vec![AuthorizedMember::new(
+                member,
+                &owner_signing_key,
+            )])),
+            ..Default::default()
+        };
+        let current_state = state.clone();
+        state
+            .apply_delta(&current_state, &parameters, &Some(delta))
+            .unwrap();
+        (state, parameters, owner_signing_key, successor_signing_key)
+    }
+
+    #[test]
+    fn test_successor_takes_over() {
+        let (state, parameters, _, successor_signing_key) = room_with_successor();
+        let address = Hash::from([1; 32]);
+
+        // The successor can only hand the room over to themselves
+        let other_signing_key = SigningKey::generate(&mut OsRng);
+        let mut upgraded = state.clone();
+        assert!(matches!(
+            upgraded.upgrade_to(
+                &parameters,
+                &successor_signing_key,
+                &other_signing_key,
+                address
+            ),
+            Err(RoomStateError::InvalidSignature { .. })
+        ));
+        assert_eq!(upgraded, state);
+
+        let new_state = upgraded
+            .upgrade_to(
+                &parameters,
+                &successor_signing_key,
+                &successor_signing_key,
+                address,
+            )
+            .unwrap();
+        assert_eq!(upgraded.verify(&upgraded, &parameters), Ok(()));
+        let new_parameters = ChatRoomParametersV1 {
+            owner: successor_signing_key.verifying_key(),
+        };
+        assert_eq!(new_state.verify(&new_state, &new_parameters), Ok(()));
+
+        // They're the owner now rather than a member or the successor
+        assert!(new_state.members.members.is_empty());
+        assert_eq!(new_state.configuration.configuration.successor, None);
+    }
+
+    #[test]
+    fn test_succession_signed_ahead() {
+        let (state, parameters, _, successor_signing_key) = room_with_successor();
+        let new_owner_vk = successor_signing_key.verifying_key();
+
+        // Signing the payloads ahead of time gives the room signing as it goes would
+        let signatures: HashMap<Vec<u8>, Signature> = state
+            .succession_payloads(&parameters, new_owner_vk)
+            .into_iter()
+            .map(|payload| {
+                let signature = successor_signing_key.sign(&payload);
+                (payload, signature)
+            })
+            .collect();
+        let new_state = state
+            .succeeded_by_signer(&parameters, new_owner_vk, &mut |data| signatures[data])
+            .unwrap();
+        assert_eq!(
+            new_state,
+            state
+                .succeeded_by(&parameters, &successor_signing_key)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_stale_successor_upgrade() {
+        let (state, parameters, owner_signing_key, successor_signing_key) = room_with_successor();
+        let reconfigured = |successor: Option<VerifyingKey>| {
+            let mut configuration = state.configuration.configuration.clone();
+            configuration.configuration_version += 1;
+            configuration.successor = successor;
+            let delta = ChatRoomStateV1Delta {
+                configuration: Some(AuthorizedConfigurationV1::new(
+                    configuration,
+                    &owner_signing_key,
+                )),
+                ..Default::default()
+            };
+            let mut reconfigured = state.clone();
+            reconfigured
+                .apply_delta(&state, &parameters, &Some(delta))
+                .unwrap();
+            reconfigured
+        };
+        let mut upgraded = state.clone();
+        upgraded
+            .upgrade_to(
+                &parameters,
+                &successor_signing_key,
+                &successor_signing_key,
+                Hash::from([1; 32]),
+            )
+            .unwrap();
+
+        // Meanwhile the owner designates someone else on another peer. A peer with the upgrade
+        // drops it once it sees that, one that saw the new configuration first never accepts it.
+        let redesignated = reconfigured(Some(SigningKey::generate(&mut OsRng).verifying_key()));
+        let mut merged = upgraded.clone();
+        merged.merge(&upgraded, &parameters, &redesignated).unwrap();
+        assert_eq!(merged, redesignated);
+        let delta = ChatRoomStateV1Delta {
+            upgrade: upgraded.upgrade.0.clone(),
+            ..Default::default()
+        };
+        let mut rejected = redesignated.clone();
+        assert!(matches!(
+            rejected.apply_delta(&redesignated, &parameters, &Some(delta)),
+            Err(RoomStateError::InvalidSignature { .. })
+        ));
+
+        // A later configuration that keeps the successor leaves the upgrade be
+        let kept = reconfigured(Some(successor_signing_key.verifying_key()));
+        let mut merged = upgraded.clone();
+        merged.merge(&upgraded, &parameters, &kept).unwrap();
+        assert_eq!(merged.upgrade, upgraded.upgrade);
+        assert_eq!(merged.verify(&merged, &parameters), Ok(()));
+    }
+}
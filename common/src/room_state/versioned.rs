@@ -0,0 +1,570 @@
+use crate::room_state::error::RoomStateError;
+use crate::room_state::member::MemberId;
+use crate::room_state::pin::{AuthorizedPinnedMessages, PinnedMessagesV1};
+use crate::room_state::{ChatRoomParametersV1, ChatRoomStateV1Delta, ChatRoomStateV1Summary};
+use crate::ChatRoomStateV1;
+use freenet_scaffold_macro::composable;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/*
+ Contracts and clients exchange states, deltas and summaries wrapped in an envelope tagged with the
+ version of their schema: `ChatRoomState`, `ChatRoomStateDelta` and `ChatRoomStateSummary`. Rooms
+ stored before the envelope hold a bare `ChatRoomStateV1`, which still decodes as the `V1` variant.
+
+ A room moves to a later schema once a peer sends it a state of that schema, in which case it
+ converts its own state to that schema and merges the sent one into it as `#[composable]` does for
+ enums, so it loses nothing it had. Clients keep the latest schema, into which states and deltas
+ of earlier schemas convert.
+*/
+
+#[composable]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum ChatRoomState {
+    V1(ChatRoomStateV1),
+    V2(ChatRoomStateV2),
+}
+
+/// A V1 room along with the fields added since
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct ChatRoomStateV2 {
+    pub room: ChatRoomStateV1,
+    /// Messages the owner pinned to the top of the room
+    pub pinned_messages: PinnedMessagesV1,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ChatRoomStateV2Summary {
+    pub room: ChatRoomStateV1Summary,
+    pub pinned_messages: <PinnedMessagesV1 as ComposableState>::Summary,
+    /// The id prefix of every member info, so that peers agree on infos of the same version, see
+    /// `AuthorizedMemberInfo::order_key`. Summaries sent before it was added decode without it,
+    /// and peers from then skip it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub member_info_ids: Vec<(MemberId, u64)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct ChatRoomStateV2Delta {
+    pub room: Option<ChatRoomStateV1Delta>,
+    pub pinned_messages: Option<AuthorizedPinnedMessages>,
+}
+
+// Not `#[composable]` as the V1 room is its own parent state, the fields added since are parented
+// by the V2 state
+impl ComposableState for ChatRoomStateV2 {
+    type ParentState = ChatRoomStateV2;
+    type Summary = ChatRoomStateV2Summary;
+    type Delta = ChatRoomStateV2Delta;
+    type Parameters = ChatRoomParametersV1;
+    type Error = RoomStateError;
+
+    fn verify(
+        &self,
+        _parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), Self::Error> {
+        self.room.verify(&self.room, parameters)?;
+        self.pinned_messages.verify(self, parameters)
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        ChatRoomStateV2Summary {
+            room: self.room.summarize(&self.room, parameters),
+            pinned_messages: self.pinned_messages.summarize(self, parameters),
+            member_info_ids: self.room.member_info.summarize_ids(),
+        }
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let mut delta = ChatRoomStateV2Delta {
+            room: self
+                .room
+                .delta(&self.room, parameters, &old_state_summary.room),
+            pinned_messages: self.pinned_messages.delta(
+                self,
+                parameters,
+                &old_state_summary.pinned_messages,
+            ),
+        };
+        // The V1 delta's member infos are those of a later version, which these include
+        let member_info = self.room.member_info.delta_with_ids(
+            &old_state_summary.room.member_info,
+            &old_state_summary.member_info_ids,
+        );
+        match (&mut delta.room, member_info) {
+            (Some(room), member_info) => room.member_info = member_info,
+            (None, Some(member_info)) => {
+                delta.room = Some(ChatRoomStateV1Delta {
+                    member_info: Some(member_info),
+                    ..Default::default()
+                })
+            }
+            (None, None) => {}
+        }
+        (delta != ChatRoomStateV2Delta::default()).then_some(delta)
+    }
+
+    fn apply_delta(
+        &mut self,
+        _parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), Self::Error> {
+        if let Some(delta) = delta {
+            let room = self.room.clone();
+            self.room.apply_delta(&room, parameters, &delta.room)?;
+            let self_clone = self.clone();
+            self.pinned_messages
+                .apply_delta(&self_clone, parameters, &delta.pinned_messages)?;
+        }
+        Ok(())
+    }
+}
+
+impl ChatRoomStateV2 {
+    /// Applies a delta of any schema, one of an earlier schema applies to what it covers
+    pub fn apply_versioned_delta(
+        &mut self,
+        parameters: &ChatRoomParametersV1,
+        delta: ChatRoomStateDelta,
+    ) -> Result<(), RoomStateError> {
+        let current_state = self.clone();
+        match delta {
+            ChatRoomStateDelta::V1(room) => {
+                let delta = ChatRoomStateV2Delta {
+                    room: Some(room),
+                    ..Default::default()
+                };
+                self.apply_delta(&current_state, parameters, &Some(delta))
+            }
+            ChatRoomStateDelta::V2(delta) => {
+                self.apply_delta(&current_state, parameters, &Some(delta))
+            }
+            ChatRoomStateDelta::Replace(state) => {
+                self.merge(&current_state, parameters, &state.into_latest())
+            }
+        }
+    }
+}
+
+impl From<ChatRoomStateV1> for ChatRoomStateV2 {
+    fn from(room: ChatRoomStateV1) -> Self {
+        ChatRoomStateV2 {
+            room,
+            ..Default::default()
+        }
+    }
+}
+
+impl ChatRoomState {
+    /// Decodes a state, a bare `ChatRoomStateV1` as stored before the envelope included
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self, ciborium::de::Error<std::io::Error>> {
+        from_cbor_or_v1(bytes, ChatRoomState::V1)
+    }
+
+    /// The state in the latest schema
+    pub fn into_latest(self) -> ChatRoomStateV2 {
+        match self {
+            ChatRoomState::V1(room) => room.into(),
+            ChatRoomState::V2(state) => state,
+        }
+    }
+}
+
+impl ChatRoomStateDelta {
+    /// Decodes a delta, a bare `ChatRoomStateV1Delta` as sent before the envelope included
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self, ciborium::de::Error<std::io::Error>> {
+        from_cbor_or_v1(bytes, ChatRoomStateDelta::V1)
+    }
+}
+
+impl ChatRoomStateSummary {
+    /// Decodes a summary, a bare `ChatRoomStateV1Summary` as sent before the envelope included
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self, ciborium::de::Error<std::io::Error>> {
+        from_cbor_or_v1(bytes, ChatRoomStateSummary::V1)
+    }
+}
+
+/// Decodes `T`, falling back to the bare V1 form `v1` wraps. Fails as `T` does if neither decodes.
+fn from_cbor_or_v1<T: DeserializeOwned, V1: DeserializeOwned>(
+    bytes: &[u8],
+    v1: impl FnOnce(V1) -> T,
+) -> Result<T, ciborium::de::Error<std::io::Error>> {
+    ciborium::de::from_reader(bytes)
+        .or_else(|error| ciborium::de::from_reader(bytes).map(v1).map_err(|_| error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_state::configuration::{AuthorizedConfigurationV1, Configuration};
+    use crate::room_state::legacy::{contains_legacy_ids, migrate_legacy_state};
+    use crate::room_state::member::{AuthorizedMember, Member, MembersDelta};
+    use crate::room_state::member_info::{AuthorizedMemberInfo, MemberInfo};
+    use crate::room_state::message::{AuthorizedMessageV1, MessageV1, MessagesDelta};
+    use crate::room_state::pin::PinnedMessages;
+    use ed25519_dalek::SigningKey;
+    use freenet_scaffold::collections::LwwRegister;
+    use std::time::{Duration, SystemTime};
+
+    /*
+     The fixtures in `tests/fixtures` are the encodings of a room in every schema. They must keep
+     decoding to the same room, any change to them means rooms already stored break, so they're
+     never rewritten, `UPDATE_FIXTURES=1` only writes the ones that are missing.
+     `room_state_v1_baseline.cbor` was encoded by River before ids were blake3 hashes and states
+     had an envelope.
+    */
+
+    fn fixture_path(name: &str) -> String {
+        format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    /// Checks `encoded` matches the fixture `name` and returns the fixture's bytes. A missing
+    /// fixture fails the test, for a new schema run the tests with `UPDATE_FIXTURES=1` to write it
+    /// and check it in.
+    fn fixture(name: &str, encoded: &[u8]) -> Vec<u8> {
+        let path = fixture_path(name);
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(_) if std::env::var_os("UPDATE_FIXTURES").is_some_and(|v| v == "1") => {
+                std::fs::write(&path, encoded).unwrap();
+                encoded.to_vec()
+            }
+            Err(e) => panic!("Can't read the fixture {}: {}", name, e),
+        };
+        assert!(bytes == encoded, "The encoding of {} changed", name);
+        bytes
+    }
+
+    fn to_cbor<T: Serialize>(value: &T) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(value, &mut bytes).unwrap();
+        bytes
+    }
+
+    /// A room with the owner, a member and a message, keys and times are fixed so that its
+    /// encoding is too
+    fn golden_room() -> (ChatRoomStateV1, ChatRoomParametersV1, SigningKey) {
+        let owner_signing_key = SigningKey::from_bytes(&[1; 32]);
+        let member_signing_key = SigningKey::from_bytes(&[2; 32]);
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        let owner_id = parameters.owner_id();
+        let member_id = member_signing_key.verifying_key().into();
+
+        let configuration = Configuration {
+            owner_member_id: owner_id,
+            name: "Golden".to_string(),
+            ..Configuration::default()
+        };
+        let mut room = ChatRoomStateV1 {
+            configuration: AuthorizedConfigurationV1::new(configuration, &owner_signing_key),
+            ..Default::default()
+        };
+        let member = Member {
+            owner_member_id: owner_id,
+            invited_by: owner_id,
+            member_vk: member_signing_key.verifying_key(),
+        };
+        let member_info = MemberInfo {
+            member_id,
+            version: 0,
+            preferred_nickname: "Member".to_string(),
+        };
+        let message = MessageV1 {
+            room_owner: owner_id,
+            author: member_id,
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            content: "Hello".to_string(),
+        };
+        let delta = ChatRoomStateV1Delta {
+            members: Some(MembersDelta::new(vec![AuthorizedMember::new(
+                member,
+                &owner_signing_key,
+            )])),
+            member_info: Some(vec![AuthorizedMemberInfo::new(
+                member_info,
+                &member_signing_key,
+            )]),
+            recent_messages: Some(MessagesDelta {
+                messages: vec![AuthorizedMessageV1::new(message, &member_signing_key)],
+                actions: Vec::new(),
+            }),
+            ..Default::default()
+        };
+        let current_state = room.clone();
+        room.apply_delta(&current_state, &parameters, &Some(delta))
+            .unwrap();
+        (room, parameters, owner_signing_key)
+    }
+
+    fn golden_pins(room: &ChatRoomStateV1, owner_signing_key: &SigningKey) -> PinnedMessagesV1 {
+        let pinned = PinnedMessages {
+            owner_member_id: owner_signing_key.verifying_key().into(),
+            version: 1,
+            message_ids: vec![room.recent_messages.messages.entries[0].id()],
+        };
+        LwwRegister(Some(AuthorizedPinnedMessages::new(
+            pinned,
+            owner_signing_key,
+        )))
+    }
+
+    #[test]
+    fn test_baseline_room_decodes_and_migrates() {
+        let owner_signing_key = SigningKey::from_bytes(&[1; 32]);
+        let parameters = ChatRoomParametersV1 {
+            owner: owner_signing_key.verifying_key(),
+        };
+        let bytes = std::fs::read(fixture_path("room_state_v1_baseline.cbor")).unwrap();
+
+        let ChatRoomState::V1(room) = ChatRoomState::from_cbor(&bytes).unwrap() else {
+            panic!("A bare room must decode as V1");
+        };
+        assert!(contains_legacy_ids(&room));
+        assert_eq!(room.configuration.configuration.name, "Golden");
+        assert_eq!(room.members.members.len(), 1);
+        assert_eq!(room.recent_messages.messages.entries.len(), 2);
+        assert!(room.verify(&room, &parameters).is_err());
+
+        let migrated = migrate_legacy_state(&room, &parameters, &owner_signing_key).unwrap();
+        assert!(!contains_legacy_ids(&migrated));
+        assert_eq!(migrated.verify(&migrated, &parameters), Ok(()));
+        assert_eq!(
+            migrated.members.members[0].member.member_vk,
+            room.members.members[0].member.member_vk
+        );
+        // Only what the owner wrote can be signed again
+        let messages: Vec<_> = migrated
+            .recent_messages
+            .messages
+            .entries
+            .iter()
+            .map(|message| message.message.content())
+            .collect();
+        assert_eq!(messages, vec!["Welcome"]);
+        assert_eq!(migrated.member_info.member_info.len(), 1);
+
+        let state = ChatRoomState::V1(migrated.clone());
+        assert_eq!(state.verify(&state, &parameters), Ok(()));
+        assert_eq!(state.into_latest(), ChatRoomStateV2::from(migrated));
+    }
+
+    #[test]
+    fn test_v1_fixtures() {
+        let (room, parameters, _) = golden_room();
+
+        // Rooms stored before the envelope
+        let bytes = fixture("room_state_v1_bare.cbor", &to_cbor(&room));
+        let state = ChatRoomState::from_cbor(&bytes).unwrap();
+        assert_eq!(state, ChatRoomState::V1(room.clone()));
+        assert_eq!(state.verify(&state, &parameters), Ok(()));
+
+        let state = ChatRoomState::V1(room.clone());
+        let bytes = fixture("room_state_v1.cbor", &to_cbor(&state));
+        assert_eq!(ChatRoomState::from_cbor(&bytes).unwrap(), state);
+
+        // A bare V1 delta is the V1 room sent whole
+        let empty = ChatRoomStateV1::default();
+        let delta = room
+            .delta(&room, &parameters, &empty.summarize(&empty, &parameters))
+            .unwrap();
+        let bytes = fixture("room_state_v1_bare_delta.cbor", &to_cbor(&delta));
+        assert_eq!(
+            ChatRoomStateDelta::from_cbor(&bytes).unwrap(),
+            ChatRoomStateDelta::V1(delta)
+        );
+    }
+
+    #[test]
+    fn test_v2_fixtures() {
+        let (room, parameters, owner_signing_key) = golden_room();
+        let pinned_messages = golden_pins(&room, &owner_signing_key);
+        let v2 = ChatRoomStateV2 {
+            room: room.clone(),
+            pinned_messages: pinned_messages.clone(),
+        };
+
+        let state = ChatRoomState::V2(v2.clone());
+        let bytes = fixture("room_state_v2.cbor", &to_cbor(&state));
+        let decoded = ChatRoomState::from_cbor(&bytes).unwrap();
+        assert_eq!(decoded, state);
+        assert_eq!(decoded.verify(&decoded, &parameters), Ok(()));
+
+        let summary = state.summarize(&state, &parameters);
+        let bytes = fixture("room_state_v2_summary_ids.cbor", &to_cbor(&summary));
+        assert_eq!(ChatRoomStateSummary::from_cbor(&bytes).unwrap(), summary);
+
+        // Peers from before member info ids skip them
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        enum SummaryBefore {
+            V1(ChatRoomStateV1Summary),
+            V2 {
+                room: ChatRoomStateV1Summary,
+                pinned_messages: <PinnedMessagesV1 as ComposableState>::Summary,
+            },
+        }
+        let SummaryBefore::V2 { room: before, .. } = ciborium::de::from_reader(&bytes[..]).unwrap()
+        else {
+            panic!("A V2 summary must decode as V2");
+        };
+        let ChatRoomStateSummary::V2(with_ids) = &summary else {
+            panic!("A V2 room has a V2 summary");
+        };
+        assert_eq!(before, with_ids.room);
+
+        // Summaries sent before they carried member info ids
+        let bytes = std::fs::read(fixture_path("room_state_v2_summary.cbor")).unwrap();
+        let ChatRoomStateSummary::V2(mut without_ids) = summary.clone() else {
+            panic!("A V2 room has a V2 summary");
+        };
+        without_ids.member_info_ids.clear();
+        assert_eq!(
+            ChatRoomStateSummary::from_cbor(&bytes).unwrap(),
+            ChatRoomStateSummary::V2(without_ids)
+        );
+
+        // The pins are all a V2 room has over the V1 one
+        let unpinned = ChatRoomStateV2::from(room);
+        let unpinned_summary = ChatRoomStateSummary::V2(unpinned.summarize(&unpinned, &parameters));
+        let delta = state.delta(&state, &parameters, &unpinned_summary).unwrap();
+        let bytes = fixture("room_state_v2_delta.cbor", &to_cbor(&delta));
+        assert_eq!(ChatRoomStateDelta::from_cbor(&bytes).unwrap(), delta);
+        assert_eq!(
+            delta,
+            ChatRoomStateDelta::V2(ChatRoomStateV2Delta {
+                room: None,
+                pinned_messages: pinned_messages.0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_v2_syncs_member_info_of_the_same_version() {
+        let (room, parameters, _) = golden_room();
+        let member_signing_key = SigningKey::from_bytes(&[2; 32]);
+
+        // The member renames themselves on two devices at once
+        let renamed = |nickname: &str| {
+            let mut state = room.clone();
+            let mut info = state.member_info.member_info[0].member_info.clone();
+            info.version += 1;
+            info.preferred_nickname = nickname.to_string();
+            state.member_info.member_info[0] =
+                AuthorizedMemberInfo::new_with_member_key(info, &member_signing_key);
+            state
+        };
+        let (laptop, phone) = (renamed("Laptop"), renamed("Phone"));
+
+        // V1 summaries can't tell the two apart
+        let sync = |from: &ChatRoomState, to: &mut ChatRoomState| {
+            let delta = from.delta(from, &parameters, &to.summarize(to, &parameters));
+            let current_state = to.clone();
+            to.apply_delta(&current_state, &parameters, &delta).unwrap();
+        };
+        let v1 = |room: &ChatRoomStateV1| ChatRoomState::V1(room.clone());
+        let (mut first, mut second) = (v1(&laptop), v1(&phone));
+        sync(&first.clone(), &mut second);
+        sync(&second.clone(), &mut first);
+        assert_ne!(first, second);
+
+        // V2 ones can, whichever way they're synced
+        let v2 = |room: &ChatRoomStateV1| ChatRoomState::V2(room.clone().into());
+        let (mut first, mut second) = (v2(&laptop), v2(&phone));
+        sync(&first.clone(), &mut second);
+        sync(&second.clone(), &mut first);
+        assert_eq!(first, second);
+        let (mut third, mut fourth) = (v2(&laptop), v2(&phone));
+        sync(&fourth.clone(), &mut third);
+        sync(&third.clone(), &mut fourth);
+        assert_eq!(third, first);
+        assert_eq!(fourth, first);
+    }
+
+    #[test]
+    fn test_v1_to_v2() {
+        let (room, parameters, owner_signing_key) = golden_room();
+
+        // A V1 room takes what a V2 state adds
+        let mut state = ChatRoomState::V1(room.clone());
+        let v2 = ChatRoomStateV2 {
+            room: room.clone(),
+            pinned_messages: golden_pins(&room, &owner_signing_key),
+        };
+        let current_state = state.clone();
+        state
+            .merge(&current_state, &parameters, &ChatRoomState::V2(v2.clone()))
+            .unwrap();
+        assert_eq!(state, ChatRoomState::V2(v2.clone()));
+
+        // A V2 room keeps its pins when given what a V1 room has
+        let empty = ChatRoomStateV1 {
+            configuration: room.configuration.clone(),
+            ..Default::default()
+        };
+        let mut latest = ChatRoomState::V1(empty.clone()).into_latest();
+        let delta = room
+            .delta(&room, &parameters, &empty.summarize(&empty, &parameters))
+            .unwrap();
+        latest
+            .apply_versioned_delta(&parameters, ChatRoomStateDelta::V1(delta))
+            .unwrap();
+        latest
+            .apply_versioned_delta(
+                &parameters,
+                ChatRoomStateDelta::Replace(Box::new(ChatRoomState::V2(v2.clone()))),
+            )
+            .unwrap();
+        assert_eq!(latest, v2);
+    }
+
+    #[test]
+    fn test_upgrade_keeps_what_the_room_had() {
+        let (room, parameters, _) = golden_room();
+
+        // A V2 state with nothing but the configuration, which verifies on its own
+        let bare = ChatRoomState::V2(ChatRoomStateV2::from(ChatRoomStateV1 {
+            configuration: room.configuration.clone(),
+            ..Default::default()
+        }));
+        assert_eq!(bare.verify(&bare, &parameters), Ok(()));
+        let upgraded = ChatRoomState::V2(room.clone().into());
+
+        // Merged into the V1 room, sent as a delta, or the other way around
+        let mut merged = ChatRoomState::V1(room.clone());
+        let current_state = merged.clone();
+        merged.merge(&current_state, &parameters, &bare).unwrap();
+        assert_eq!(merged, upgraded);
+
+        let mut replaced = ChatRoomState::V1(room.clone());
+        let current_state = replaced.clone();
+        let delta = bare.delta(
+            &bare,
+            &parameters,
+            &replaced.summarize(&replaced, &parameters),
+        );
+        assert!(matches!(delta, Some(ChatRoomStateDelta::Replace(_))));
+        replaced
+            .apply_delta(&current_state, &parameters, &delta)
+            .unwrap();
+        assert_eq!(replaced, upgraded);
+
+        let mut reversed = bare.clone();
+        reversed
+            .merge(&bare, &parameters, &ChatRoomState::V1(room))
+            .unwrap();
+        assert_eq!(reversed, upgraded);
+    }
+}
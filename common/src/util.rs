@@ -2,11 +2,21 @@ use base64::{engine::general_purpose, Engine as _};
 use ed25519_dalek::{Signature, SignatureError, Signer, SigningKey, Verifier, VerifyingKey};
 use serde::Serialize;
 use data_encoding::BASE32;
+use sha2::{Digest, Sha512};
+use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret as X25519Secret};
 
 pub fn sign_struct<T: Serialize>(message: T, signing_key: &SigningKey) -> Signature {
+    sign_struct_with(message, &mut |data| signing_key.sign(data))
+}
+
+/// Like `sign_struct` with `sign` signing the bytes, eg. with a key kept elsewhere
+pub fn sign_struct_with<T: Serialize>(
+    message: T,
+    sign: &mut dyn FnMut(&[u8]) -> Signature,
+) -> Signature {
     let mut data_to_sign = Vec::new();
     ciborium::ser::into_writer(&message, &mut data_to_sign).expect("Serialization should not fail");
-    signing_key.sign(&data_to_sign)
+    sign(&data_to_sign)
 }
 
 pub fn verify_struct<T: Serialize>(
@@ -19,6 +29,20 @@ pub fn verify_struct<T: Serialize>(
     verifying_key.verify(&data_to_sign, signature)
 }
 
+/// The X25519 secret `signing_key` shares with `public_key`, with the signing key converted to the
+/// X25519 key it corresponds to. What's encrypted for a member's verifying key is decrypted with it.
+pub fn diffie_hellman(signing_key: &SigningKey, public_key: &[u8; 32]) -> [u8; 32] {
+    let hash = Sha512::digest(signing_key.to_bytes());
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&hash[..32]);
+    key[0] &= 248;
+    key[31] &= 127;
+    key[31] |= 64;
+    X25519Secret::from(key)
+        .diffie_hellman(&X25519PublicKey::from(*public_key))
+        .to_bytes()
+}
+
 pub fn truncated_base64<T: AsRef<[u8]>>(data: T) -> String {
     let encoded = general_purpose::STANDARD_NO_PAD.encode(data);
     encoded.chars().take(10).collect()
@@ -1,8 +1,10 @@
 use ciborium::{de::from_reader, ser::into_writer};
 use freenet_stdlib::prelude::*;
 
-use common::room_state::{ChatRoomParametersV1, ChatRoomStateV1Delta, ChatRoomStateV1Summary};
-use common::ChatRoomStateV1;
+use common::room_state::summary::SummaryCompaction;
+use common::room_state::versioned::{ChatRoomStateDelta, ChatRoomStateSummary};
+use common::room_state::ChatRoomParametersV1;
+use common::ChatRoomState;
 use freenet_scaffold::ComposableState;
 use freenet_stdlib::prelude::ContractError;
 
@@ -21,8 +23,8 @@ impl ContractInterface for Contract {
         if bytes.is_empty() {
             return Ok(ValidateResult::Valid);
         }
-        let chat_state = from_reader::<ChatRoomStateV1, &[u8]>(bytes)
-            .map_err(|e| ContractError::Deser(e.to_string()))?;
+        let chat_state =
+            ChatRoomState::from_cbor(bytes).map_err(|e| ContractError::Deser(e.to_string()))?;
 
         let parameters = from_reader::<ChatRoomParametersV1, &[u8]>(parameters.as_ref())
             .map_err(|e| ContractError::Deser(e.to_string()))?;
@@ -30,7 +32,7 @@ impl ContractInterface for Contract {
         chat_state
             .verify(&chat_state, &parameters)
             .map(|_| ValidateResult::Valid)
-            .map_err(|e| ContractError::InvalidState)
+            .map_err(|e| ContractError::Other(e.to_reason()))
     }
 
     fn update_state(
@@ -40,24 +42,28 @@ impl ContractInterface for Contract {
     ) -> Result<UpdateModification<'static>, freenet_stdlib::prelude::ContractError> {
         let parameters = from_reader::<ChatRoomParametersV1, &[u8]>(parameters.as_ref())
             .map_err(|e| ContractError::Deser(e.to_string()))?;
-        let mut chat_state = from_reader::<ChatRoomStateV1, &[u8]>(state.as_ref())
+        let mut chat_state = ChatRoomState::from_cbor(state.as_ref())
             .map_err(|e| ContractError::Deser(e.to_string()))?;
 
         for update in data {
             match update {
                 UpdateData::State(new_state) => {
-                    let new_state = from_reader::<ChatRoomStateV1, &[u8]>(new_state.as_ref())
+                    let new_state = ChatRoomState::from_cbor(new_state.as_ref())
                         .map_err(|e| ContractError::Deser(e.to_string()))?;
                     chat_state
                         .merge(&chat_state.clone(), &parameters, &new_state)
-                        .map_err(|_| ContractError::InvalidUpdate)?;
+                        .map_err(|e| ContractError::InvalidUpdateWithInfo {
+                            reason: e.to_reason(),
+                        })?;
                 }
                 UpdateData::Delta(d) => {
-                    let delta = from_reader::<ChatRoomStateV1Delta, &[u8]>(d.as_ref())
+                    let delta = ChatRoomStateDelta::from_cbor(d.as_ref())
                         .map_err(|e| ContractError::Deser(e.to_string()))?;
                     chat_state
                         .apply_delta(&chat_state.clone(), &parameters, &Some(delta))
-                        .map_err(|_| ContractError::InvalidUpdate)?;
+                        .map_err(|e| ContractError::InvalidUpdateWithInfo {
+                            reason: e.to_reason(),
+                        })?;
                 }
                 UpdateData::RelatedState {
                     related_to: _,
@@ -86,9 +92,11 @@ impl ContractInterface for Contract {
         }
         let parameters = from_reader::<ChatRoomParametersV1, &[u8]>(parameters.as_ref())
             .map_err(|e| ContractError::Deser(e.to_string()))?;
-        let state = from_reader::<ChatRoomStateV1, &[u8]>(state)
-            .map_err(|e| ContractError::Deser(e.to_string()))?;
-        let summary = state.summarize(&state, &parameters);
+        let state =
+            ChatRoomState::from_cbor(state).map_err(|e| ContractError::Deser(e.to_string()))?;
+        // Summaries go to other peers, so the id lists of large rooms are sent compacted
+        let mut summary = state.summarize(&state, &parameters);
+        summary.compact(&SummaryCompaction::default());
         let mut summary_bytes = vec![];
         into_writer(&summary, &mut summary_bytes)
             .map_err(|e| ContractError::Deser(e.to_string()))?;
@@ -100,11 +108,11 @@ impl ContractInterface for Contract {
         state: State<'static>,
         summary: StateSummary<'static>,
     ) -> Result<StateDelta<'static>, freenet_stdlib::prelude::ContractError> {
-        let chat_state = from_reader::<ChatRoomStateV1, &[u8]>(state.as_ref())
+        let chat_state = ChatRoomState::from_cbor(state.as_ref())
             .map_err(|e| ContractError::Deser(e.to_string()))?;
         let parameters = from_reader::<ChatRoomParametersV1, &[u8]>(parameters.as_ref())
             .map_err(|e| ContractError::Deser(e.to_string()))?;
-        let summary = from_reader::<ChatRoomStateV1Summary, &[u8]>(summary.as_ref())
+        let summary = ChatRoomStateSummary::from_cbor(summary.as_ref())
             .map_err(|e| ContractError::Deser(e.to_string()))?;
         let delta = chat_state.delta(&chat_state, &parameters, &summary);
         let mut delta_bytes = vec![];
@@ -1,14 +1,371 @@
-pub fn add(left: u64, right: u64) -> u64 {
-    left + right
+//! Keeps our signing keys and the rooms we're in with them in the node's secret storage, and signs
+//! with the keys on the UI's behalf, see `common::chat_delegate` for the requests it answers. The
+//! delegate is registered with the River UI's contract instance id as its parameters and only
+//! answers the UI attested to be that contract.
+//!
+//! The delegate can't read a secret directly, it asks the runtime for it and is called again with
+//! the secret. The request waiting for it is carried in the context as a [`Pending`].
+
+mod pending;
+#[cfg(test)]
+mod runtime;
+
+use ciborium::{de::from_reader, ser::into_writer};
+use common::chat_delegate::{ChatDelegateError, ChatDelegateRequestMsg, ChatDelegateResponseMsg};
+use freenet_stdlib::prelude::*;
+use pending::Pending;
+
+/// The secret holding our keys and rooms
+const KEYS_SECRET: &[u8] = b"keys";
+
+#[allow(dead_code)]
+struct ChatDelegate;
+
+#[delegate]
+impl DelegateInterface for ChatDelegate {
+    fn process(
+        parameters: Parameters<'static>,
+        attested: Option<&'static [u8]>,
+        message: InboundDelegateMsg,
+    ) -> Result<Vec<OutboundDelegateMsg>, DelegateError> {
+        match message {
+            InboundDelegateMsg::ApplicationMessage(message) => {
+                if attested != Some(parameters.as_ref()) {
+                    return Err(DelegateError::Other(
+                        "only the River UI may use the chat delegate".to_string(),
+                    ));
+                }
+                match from_reader::<ChatDelegateRequestMsg, _>(message.payload.as_slice()) {
+                    Ok(request) => Ok(vec![get_secret(Pending::new(message.app, request))]),
+                    Err(e) => Ok(vec![reply(
+                        message.app,
+                        ChatDelegateResponseMsg::Error(ChatDelegateError::Malformed(e.to_string())),
+                    )]),
+                }
+            }
+            // Only the runtime answers our own requests for secrets
+            InboundDelegateMsg::GetSecretResponse(response) => {
+                let pending = Pending::from_context(&response.context)
+                    .map_err(|e| DelegateError::Deser(e.to_string()))?;
+                Ok(pending.resume(response.value))
+            }
+            _ => Err(DelegateError::Other("unexpected message".to_string())),
+        }
+    }
+}
+
+/// Every request needs our keys or rooms, it waits for them as `pending`
+fn get_secret(pending: Pending) -> OutboundDelegateMsg {
+    OutboundDelegateMsg::GetSecretRequest(GetSecretRequest {
+        key: SecretsId::new(KEYS_SECRET.to_vec()),
+        context: pending.into_context(),
+        processed: false,
+    })
+}
+
+/// Stores `value` under `key`, or deletes the secret if `value` is `None`
+fn set_secret(key: Vec<u8>, value: Option<Vec<u8>>) -> OutboundDelegateMsg {
+    OutboundDelegateMsg::SetSecretRequest(SetSecretRequest {
+        key: SecretsId::new(key),
+        value,
+    })
+}
+
+fn reply(app: ContractInstanceId, response: ChatDelegateResponseMsg) -> OutboundDelegateMsg {
+    let mut payload = Vec::new();
+    into_writer(&response, &mut payload).expect("Serialization should not fail");
+    OutboundDelegateMsg::ApplicationMessage(ApplicationMessage::new(app, payload).processed(true))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::runtime::LocalRuntime;
+    use common::chat_delegate::{KeptRoom, SignRequest};
+    use common::keyring::KeyringKey;
+    use common::room_state::configuration::{Configuration, RoomRename};
+    use common::room_state::member::MemberId;
+    use common::room_state::message::{AuthorizedMessageV1, Message, MessageV1};
+    use common::util::{diffie_hellman, sign_struct};
+    use ed25519_dalek::{SigningKey, VerifyingKey};
+    use rand::rngs::OsRng;
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_store_and_sign() {
+        let mut runtime = LocalRuntime::new();
+        let self_sk = SigningKey::generate(&mut OsRng);
+        let self_vk = self_sk.verifying_key();
+
+        // Storing a key twice keeps it once
+        for _ in 0..2 {
+            assert_eq!(
+                runtime.request(ChatDelegateRequestMsg::StoreKey {
+                    self_sk: self_sk.clone(),
+                }),
+                ChatDelegateResponseMsg::KeyStored { self_vk }
+            );
+        }
+        assert_eq!(
+            runtime.request(ChatDelegateRequestMsg::ListKeys),
+            ChatDelegateResponseMsg::Keys(vec![self_vk])
+        );
+
+        // What's signed is what signing the struct with the key gives
+        let room_owner_vk = SigningKey::generate(&mut OsRng).verifying_key();
+        runtime.request(ChatDelegateRequestMsg::StoreRoom {
+            owner_vk: room_owner_vk,
+            self_vk,
+        });
+        let message = Message::from(MessageV1 {
+            room_owner: room_owner_vk.into(),
+            author: MemberId::from(&self_vk),
+            time: SystemTime::now(),
+            content: "Hello".to_string(),
+        });
+        let ChatDelegateResponseMsg::Signed { signature, .. } =
+            runtime.request(ChatDelegateRequestMsg::Sign {
+                self_vk,
+                request: SignRequest::Message(message.clone()),
+            })
+        else {
+            panic!("message not signed");
+        };
+        assert_eq!(signature, sign_struct(&message, &self_sk));
+        let message = AuthorizedMessageV1 { message, signature };
+        assert!(message.validate(&self_vk).is_ok());
+
+        let public_key = [7; 32];
+        assert_eq!(
+            runtime.request(ChatDelegateRequestMsg::SharedSecret {
+                self_vk,
+                public_key
+            }),
+            ChatDelegateResponseMsg::SharedSecret {
+                self_vk,
+                public_key,
+                secret: diffie_hellman(&self_sk, &public_key),
+            }
+        );
+
+        // Exported encrypted with the key we give, as our key in each room we're in
+        let key = KeyringKey::new("correct horse").unwrap();
+        let ChatDelegateResponseMsg::ExportedKeys(exported) =
+            runtime.request(ChatDelegateRequestMsg::ExportKeys { key })
+        else {
+            panic!("keys not exported");
+        };
+        let (_, keyring) = KeyringKey::unlock("correct horse", &exported).unwrap();
+        assert!(keyring.keys == HashMap::from([(room_owner_vk, self_sk)]));
+    }
+
+    #[test]
+    fn test_refusals() {
+        let mut runtime = LocalRuntime::new();
+        let self_sk = SigningKey::generate(&mut OsRng);
+        let self_vk = self_sk.verifying_key();
+        let room_owner_vk = SigningKey::generate(&mut OsRng).verifying_key();
+        let message = |author: &VerifyingKey| {
+            SignRequest::Message(Message::from(MessageV1 {
+                room_owner: room_owner_vk.into(),
+                author: author.into(),
+                time: SystemTime::now(),
+                content: "Hello".to_string(),
+            }))
+        };
+        assert_eq!(
+            runtime.request(ChatDelegateRequestMsg::Sign {
+                self_vk,
+                request: message(&self_vk),
+            }),
+            ChatDelegateResponseMsg::Error(ChatDelegateError::UnknownKey {
+                member: self_vk.into()
+            })
+        );
+
+        // Not in a room we aren't kept in, nor for someone else in one we are
+        runtime.request(ChatDelegateRequestMsg::StoreKey { self_sk });
+        let refused = |response| {
+            matches!(
+                response,
+                ChatDelegateResponseMsg::Error(ChatDelegateError::Refused(_))
+            )
+        };
+        assert!(refused(runtime.request(ChatDelegateRequestMsg::Sign {
+            self_vk,
+            request: message(&self_vk),
+        })));
+        runtime.request(ChatDelegateRequestMsg::StoreRoom {
+            owner_vk: room_owner_vk,
+            self_vk,
+        });
+        assert!(refused(runtime.request(ChatDelegateRequestMsg::Sign {
+            self_vk,
+            request: message(&room_owner_vk),
+        })));
+        // Only owners sign configurations, and only for their own rooms
+        let configuration = Configuration {
+            owner_member_id: room_owner_vk.into(),
+            ..Configuration::default()
+        };
+        assert!(refused(runtime.request(ChatDelegateRequestMsg::Sign {
+            self_vk,
+            request: SignRequest::Configuration(configuration),
+        })));
+        // Nor taking over a room we neither own nor succeed to
+        assert!(refused(runtime.request(
+            ChatDelegateRequestMsg::SignSuccession {
+                self_vk,
+                owner_vk: room_owner_vk,
+                room_state: Box::default(),
+            }
+        )));
+        assert!(matches!(
+            runtime.request_bytes(vec![1, 2, 3]),
+            ChatDelegateResponseMsg::Error(ChatDelegateError::Malformed(_))
+        ));
+    }
 
     #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
+    fn test_only_the_ui() {
+        let mut payload = Vec::new();
+        into_writer(&ChatDelegateRequestMsg::ListKeys, &mut payload).unwrap();
+
+        // Neither an app the runtime didn't attest nor one attested to be another contract
+        for attested in [None, Some([2; 32].as_slice())] {
+            let mut runtime = LocalRuntime::attested(attested);
+            assert!(matches!(
+                runtime.run(payload.clone()),
+                Err(DelegateError::Other(_))
+            ));
+        }
+        assert!(LocalRuntime::new().run(payload).is_ok());
+    }
+
+    #[test]
+    fn test_attested_must_be_the_parameters() {
+        let mut payload = Vec::new();
+        into_writer(&ChatDelegateRequestMsg::ListKeys, &mut payload).unwrap();
+
+        // Exactly the UI contract, `[1; 32]`, not a prefix of it or something it's a prefix of
+        for attested in [[1; 31].as_slice(), [1; 33].as_slice(), [].as_slice()] {
+            let mut runtime = LocalRuntime::attested(Some(attested));
+            assert!(matches!(
+                runtime.run(payload.clone()),
+                Err(DelegateError::Other(_))
+            ));
+        }
+        // Nor the UI if the delegate was registered for another contract
+        for parameters in [[2; 32].as_slice(), [].as_slice()] {
+            let mut runtime = LocalRuntime::registered_for(parameters);
+            assert!(matches!(
+                runtime.run(payload.clone()),
+                Err(DelegateError::Other(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_rooms() {
+        let mut runtime = LocalRuntime::new();
+        let keys: Vec<SigningKey> = (0..2).map(|_| SigningKey::generate(&mut OsRng)).collect();
+        let owner_vks: Vec<VerifyingKey> = (0..2)
+            .map(|_| SigningKey::generate(&mut OsRng).verifying_key())
+            .collect();
+        let (owner_vk, self_vk) = (owner_vks[0], keys[0].verifying_key());
+
+        // Only with a key that's kept
+        assert_eq!(
+            runtime.request(ChatDelegateRequestMsg::StoreRoom { owner_vk, self_vk }),
+            ChatDelegateResponseMsg::Error(ChatDelegateError::UnknownKey {
+                member: self_vk.into()
+            })
+        );
+        for self_sk in &keys {
+            runtime.request(ChatDelegateRequestMsg::StoreKey {
+                self_sk: self_sk.clone(),
+            });
+        }
+        for (owner_vk, self_sk) in owner_vks.iter().zip(&keys) {
+            assert_eq!(
+                runtime.request(ChatDelegateRequestMsg::StoreRoom {
+                    owner_vk: *owner_vk,
+                    self_vk: self_sk.verifying_key(),
+                }),
+                ChatDelegateResponseMsg::RoomStored {
+                    owner_vk: *owner_vk
+                }
+            );
+        }
+        // A room is kept once, with the key it was last stored with
+        runtime.request(ChatDelegateRequestMsg::StoreRoom {
+            owner_vk: owner_vks[1],
+            self_vk,
+        });
+        assert_eq!(
+            runtime.request(ChatDelegateRequestMsg::ListRooms),
+            ChatDelegateResponseMsg::Rooms(vec![
+                KeptRoom { owner_vk, self_vk },
+                KeptRoom {
+                    owner_vk: owner_vks[1],
+                    self_vk
+                },
+            ])
+        );
+
+        assert_eq!(
+            runtime.request(ChatDelegateRequestMsg::RemoveRoom { owner_vk }),
+            ChatDelegateResponseMsg::RoomRemoved { owner_vk }
+        );
+        assert_eq!(
+            runtime.request(ChatDelegateRequestMsg::ListKeys),
+            ChatDelegateResponseMsg::Keys(keys.iter().map(SigningKey::verifying_key).collect())
+        );
+        // The rooms we're in with a key go with it
+        runtime.request(ChatDelegateRequestMsg::RemoveKey { self_vk });
+        assert_eq!(
+            runtime.request(ChatDelegateRequestMsg::ListRooms),
+            ChatDelegateResponseMsg::Rooms(Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_remove_key() {
+        let mut runtime = LocalRuntime::new();
+        let keys: Vec<SigningKey> = (0..2).map(|_| SigningKey::generate(&mut OsRng)).collect();
+        for self_sk in &keys {
+            runtime.request(ChatDelegateRequestMsg::StoreKey {
+                self_sk: self_sk.clone(),
+            });
+        }
+        let vks: Vec<VerifyingKey> = keys.iter().map(SigningKey::verifying_key).collect();
+        assert_eq!(
+            runtime.request(ChatDelegateRequestMsg::ListKeys),
+            ChatDelegateResponseMsg::Keys(vks.clone())
+        );
+
+        let self_vk = vks[0];
+        assert_eq!(
+            runtime.request(ChatDelegateRequestMsg::RemoveKey { self_vk }),
+            ChatDelegateResponseMsg::KeyRemoved { self_vk }
+        );
+        assert_eq!(
+            runtime.request(ChatDelegateRequestMsg::ListKeys),
+            ChatDelegateResponseMsg::Keys(vec![vks[1]])
+        );
+        assert_eq!(
+            runtime.request(ChatDelegateRequestMsg::Sign {
+                self_vk,
+                request: SignRequest::Rename(RoomRename {
+                    configuration_version: 1,
+                    name: "Renamed".to_string(),
+                    renamed_at: SystemTime::now(),
+                }),
+            }),
+            ChatDelegateResponseMsg::Error(ChatDelegateError::UnknownKey {
+                member: self_vk.into()
+            })
+        );
     }
 }
@@ -0,0 +1,168 @@
+use crate::{reply, set_secret, KEYS_SECRET};
+use ciborium::{de::from_reader, ser::into_writer};
+use common::chat_delegate::{
+    check_succession, ChatDelegateError, ChatDelegateRequestMsg, ChatDelegateResponseMsg, KeptRoom,
+};
+use common::keyring::Keyring;
+use common::util::diffie_hellman;
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use freenet_stdlib::prelude::{ContractInstanceId, DelegateContext, OutboundDelegateMsg};
+use serde::{Deserialize, Serialize};
+
+/// A request waiting for the keys secret, carried in the context of the request for it. A
+/// `StoreKey` request carries the key it stores, the context only goes to the runtime and back.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Pending {
+    /// Who to reply to
+    app: ContractInstanceId,
+    request: ChatDelegateRequestMsg,
+}
+
+impl Pending {
+    pub(crate) fn new(app: ContractInstanceId, request: ChatDelegateRequestMsg) -> Self {
+        Pending { app, request }
+    }
+
+    pub(crate) fn into_context(self) -> DelegateContext {
+        let mut bytes = Vec::new();
+        into_writer(&self, &mut bytes).expect("Serialization should not fail");
+        DelegateContext::new(bytes)
+    }
+
+    pub(crate) fn from_context(
+        context: &DelegateContext,
+    ) -> Result<Self, ciborium::de::Error<std::io::Error>> {
+        from_reader(context.as_ref())
+    }
+
+    /// Finishes the request with `secret`, the keys secret if there is one
+    pub(crate) fn resume(self, secret: Option<Vec<u8>>) -> Vec<OutboundDelegateMsg> {
+        let mut kept = match Kept::from_secret(secret) {
+            Ok(kept) => kept,
+            Err(e) => return vec![reply(self.app, ChatDelegateResponseMsg::Error(e))],
+        };
+        let response = match self.request {
+            ChatDelegateRequestMsg::StoreKey { self_sk } => {
+                let self_vk = self_sk.verifying_key();
+                kept.keys.retain(|key| key.verifying_key() != self_vk);
+                kept.keys.push(self_sk);
+                return kept.update(self.app, ChatDelegateResponseMsg::KeyStored { self_vk });
+            }
+            ChatDelegateRequestMsg::RemoveKey { self_vk } => {
+                kept.keys.retain(|key| key.verifying_key() != self_vk);
+                kept.rooms.retain(|room| room.self_vk != self_vk);
+                return kept.update(self.app, ChatDelegateResponseMsg::KeyRemoved { self_vk });
+            }
+            ChatDelegateRequestMsg::ListKeys => ChatDelegateResponseMsg::Keys(
+                kept.keys.iter().map(SigningKey::verifying_key).collect(),
+            ),
+            ChatDelegateRequestMsg::StoreRoom { owner_vk, self_vk } => {
+                if let Err(e) = kept.key(&self_vk) {
+                    return vec![reply(self.app, ChatDelegateResponseMsg::Error(e))];
+                }
+                kept.rooms.retain(|room| room.owner_vk != owner_vk);
+                kept.rooms.push(KeptRoom { owner_vk, self_vk });
+                return kept.update(self.app, ChatDelegateResponseMsg::RoomStored { owner_vk });
+            }
+            ChatDelegateRequestMsg::RemoveRoom { owner_vk } => {
+                kept.rooms.retain(|room| room.owner_vk != owner_vk);
+                return kept.update(self.app, ChatDelegateResponseMsg::RoomRemoved { owner_vk });
+            }
+            ChatDelegateRequestMsg::ListRooms => ChatDelegateResponseMsg::Rooms(kept.rooms),
+            ChatDelegateRequestMsg::Sign { self_vk, request } => match kept
+                .key(&self_vk)
+                .and_then(|self_sk| request.check(&self_vk, &kept.rooms).map(|_| self_sk))
+            {
+                Ok(self_sk) => ChatDelegateResponseMsg::Signed {
+                    self_vk,
+                    signature: request.sign(self_sk),
+                },
+                Err(e) => ChatDelegateResponseMsg::Error(e),
+            },
+            ChatDelegateRequestMsg::SignSuccession {
+                self_vk,
+                owner_vk,
+                room_state,
+            } => match kept.key(&self_vk).and_then(|self_sk| {
+                check_succession(&self_vk, &owner_vk, &room_state, &kept.rooms)
+                    .map(|payloads| (self_sk, payloads))
+            }) {
+                Ok((self_sk, payloads)) => ChatDelegateResponseMsg::SignedSuccession {
+                    self_vk,
+                    signatures: payloads
+                        .iter()
+                        .map(|payload| self_sk.sign(payload))
+                        .collect(),
+                },
+                Err(e) => ChatDelegateResponseMsg::Error(e),
+            },
+            ChatDelegateRequestMsg::SharedSecret {
+                self_vk,
+                public_key,
+            } => match kept.key(&self_vk) {
+                Ok(self_sk) => ChatDelegateResponseMsg::SharedSecret {
+                    self_vk,
+                    public_key,
+                    secret: diffie_hellman(self_sk, &public_key),
+                },
+                Err(e) => ChatDelegateResponseMsg::Error(e),
+            },
+            ChatDelegateRequestMsg::ExportKeys { key } => {
+                ChatDelegateResponseMsg::ExportedKeys(key.encrypt(&kept.keyring()))
+            }
+        };
+        vec![reply(self.app, response)]
+    }
+}
+
+/// What's kept in the keys secret: our keys and the rooms we're in with them
+#[derive(Serialize, Deserialize, Default)]
+struct Kept {
+    keys: Vec<SigningKey>,
+    rooms: Vec<KeptRoom>,
+}
+
+impl Kept {
+    /// Nothing is kept before the first key is stored
+    fn from_secret(secret: Option<Vec<u8>>) -> Result<Self, ChatDelegateError> {
+        match secret {
+            Some(bytes) => from_reader(bytes.as_slice())
+                .map_err(|e| ChatDelegateError::Malformed(e.to_string())),
+            None => Ok(Kept::default()),
+        }
+    }
+
+    fn key(&self, self_vk: &VerifyingKey) -> Result<&SigningKey, ChatDelegateError> {
+        self.keys
+            .iter()
+            .find(|key| key.verifying_key() == *self_vk)
+            .ok_or(ChatDelegateError::UnknownKey {
+                member: self_vk.into(),
+            })
+    }
+
+    /// Our key in each room
+    fn keyring(&self) -> Keyring {
+        Keyring {
+            keys: self
+                .rooms
+                .iter()
+                .filter_map(|room| Some((room.owner_vk, self.key(&room.self_vk).ok()?.clone())))
+                .collect(),
+        }
+    }
+
+    /// Stores what's kept and replies with `response`
+    fn update(
+        &self,
+        app: ContractInstanceId,
+        response: ChatDelegateResponseMsg,
+    ) -> Vec<OutboundDelegateMsg> {
+        let mut bytes = Vec::new();
+        into_writer(self, &mut bytes).expect("Serialization should not fail");
+        vec![
+            set_secret(KEYS_SECRET.to_vec(), Some(bytes)),
+            reply(app, response),
+        ]
+    }
+}
@@ -0,0 +1,97 @@
+use crate::ChatDelegate;
+use ciborium::{de::from_reader, ser::into_writer};
+use common::chat_delegate::{ChatDelegateRequestMsg, ChatDelegateResponseMsg};
+use freenet_stdlib::prelude::*;
+use std::collections::{HashMap, VecDeque};
+
+/// The UI contract the delegate is registered for in tests
+static UI_CONTRACT: [u8; 32] = [1; 32];
+
+/// Stands in for the node's delegate runtime in tests: it keeps secrets in memory and answers
+/// the delegate's requests for them until the delegate replies to the app
+pub(crate) struct LocalRuntime {
+    app: ContractInstanceId,
+    /// The delegate's parameters, the contract it was registered for
+    parameters: Vec<u8>,
+    /// The contract the runtime attests the app to be
+    attested: Option<&'static [u8]>,
+    secrets: HashMap<SecretsId, Vec<u8>>,
+}
+
+impl LocalRuntime {
+    /// A runtime the UI talks to the delegate through
+    pub(crate) fn new() -> Self {
+        Self::attested(Some(UI_CONTRACT.as_slice()))
+    }
+
+    pub(crate) fn attested(attested: Option<&'static [u8]>) -> Self {
+        LocalRuntime {
+            app: ContractInstanceId::new(UI_CONTRACT),
+            parameters: UI_CONTRACT.to_vec(),
+            attested,
+            secrets: HashMap::new(),
+        }
+    }
+
+    /// A runtime where the delegate was registered for another contract than the UI it's used from
+    pub(crate) fn registered_for(parameters: &[u8]) -> Self {
+        LocalRuntime {
+            parameters: parameters.to_vec(),
+            ..Self::new()
+        }
+    }
+
+    pub(crate) fn request(&mut self, request: ChatDelegateRequestMsg) -> ChatDelegateResponseMsg {
+        let mut payload = Vec::new();
+        into_writer(&request, &mut payload).unwrap();
+        self.request_bytes(payload)
+    }
+
+    pub(crate) fn request_bytes(&mut self, payload: Vec<u8>) -> ChatDelegateResponseMsg {
+        self.run(payload)
+            .expect("the delegate should handle every message it's sent")
+    }
+
+    /// Sends `payload` to the delegate and runs it until it replies
+    pub(crate) fn run(
+        &mut self,
+        payload: Vec<u8>,
+    ) -> Result<ChatDelegateResponseMsg, DelegateError> {
+        let mut inbound = VecDeque::from([InboundDelegateMsg::ApplicationMessage(
+            ApplicationMessage::new(self.app, payload),
+        )]);
+        let mut replies = Vec::new();
+        while let Some(message) = inbound.pop_front() {
+            let parameters = Parameters::from(self.parameters.clone());
+            let outbound = ChatDelegate::process(parameters, self.attested, message)?;
+            for message in outbound {
+                match message {
+                    OutboundDelegateMsg::ApplicationMessage(reply) => {
+                        assert_eq!(reply.app, self.app);
+                        replies.push(reply.payload);
+                    }
+                    OutboundDelegateMsg::GetSecretRequest(get) => {
+                        inbound.push_back(InboundDelegateMsg::GetSecretResponse(
+                            GetSecretResponse {
+                                value: self.secrets.get(&get.key).cloned(),
+                                key: get.key,
+                                context: get.context,
+                            },
+                        ));
+                    }
+                    OutboundDelegateMsg::SetSecretRequest(set) => match set.value {
+                        Some(value) => {
+                            self.secrets.insert(set.key, value);
+                        }
+                        None => {
+                            self.secrets.remove(&set.key);
+                        }
+                    },
+                    other => panic!("unexpected message {:?}", other),
+                }
+            }
+        }
+        assert_eq!(replies.len(), 1, "the delegate should reply once");
+        Ok(from_reader(replies[0].as_slice()).unwrap())
+    }
+}
@@ -0,0 +1,306 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{DataEnum, DeriveInput, Fields, Ident, Type};
+
+/*
+ Enum states model versioned state, eg. `enum Room { V1(RoomV1), V2(RoomV2) }`. Variants are ordered
+ by declaration and a state only ever moves to a later variant, converting what it holds with the
+ `From` impl each variant's state must have from the state of the variant before it. No data is
+ lost on the way: when the summary is for an earlier variant, `delta` sends the whole state as
+ `{Name}Delta::Replace` and the other side converts its own state to that variant and merges the
+ sent one into it. When the summary is for a later variant, `delta` converts our state to that
+ variant and sends what the other side lacks of it. Both sides end up with the merge of both states
+ in the later variant, so `merge` stays commutative.
+
+ Unit variants hold nothing, a state moves from them by taking the later one. They must come before
+ the variants wrapping a state, which would lose what they hold moving to a unit variant.
+ */
+
+/// Name of the delta variant that carries a whole state of a different variant
+const REPLACE_VARIANT: &str = "Replace";
+
+struct Variant<'a> {
+    ident: &'a Ident,
+    /// None for unit variants
+    ty: Option<&'a Type>,
+}
+
+pub fn expand(input: &DeriveInput, data_enum: &DataEnum) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+
+    let variants = data_enum
+        .variants
+        .iter()
+        .map(|v| {
+            if v.ident == REPLACE_VARIANT {
+                return Err(syn::Error::new_spanned(
+                    &v.ident,
+                    "`Replace` is reserved for the generated delta of ComposableState enums",
+                ));
+            }
+            match &v.fields {
+                Fields::Unit => Ok(Variant { ident: &v.ident, ty: None }),
+                Fields::Unnamed(fields) if fields.unnamed.len() == 1 => Ok(Variant {
+                    ident: &v.ident,
+                    ty: Some(&fields.unnamed[0].ty),
+                }),
+                _ => Err(syn::Error::new_spanned(
+                    v,
+                    "ComposableState enum variants must be unit variants or wrap exactly one state",
+                )),
+            }
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    if let Some(unit) = variants
+        .iter()
+        .skip_while(|v| v.ty.is_none())
+        .find(|v| v.ty.is_none())
+    {
+        return Err(syn::Error::new_spanned(
+            unit.ident,
+            "Unit variants of ComposableState enums must come before those wrapping a state, which \
+             can't be converted to them",
+        ));
+    }
+
+    let field_types: Vec<&Type> = variants.iter().filter_map(|v| v.ty).collect();
+    let first_field_type = field_types.first().ok_or_else(|| {
+        syn::Error::new_spanned(
+            name,
+            "ComposableState enums need at least one variant wrapping a state",
+        )
+    })?;
+
+    let summary_name = format_ident!("{}Summary", name);
+    let delta_name = format_ident!("{}Delta", name);
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let summary_variants = variants.iter().map(|Variant { ident, ty }| match ty {
+        Some(ty) => quote! { #ident(<#ty as ComposableState>::Summary) },
+        None => quote! { #ident },
+    });
+
+    let delta_variants = variants.iter().filter_map(|Variant { ident, ty }| {
+        ty.map(|ty| quote! { #ident(<#ty as ComposableState>::Delta) })
+    });
+
+    let variant_index_arms = variants.iter().enumerate().map(|(index, Variant { ident, ty })| {
+        let pattern = ty.map_or_else(|| quote! { #ident }, |_| quote! { #ident(..) });
+        quote! {
+            #name::#pattern => #index
+        }
+    });
+
+    let summary_index_arms = variants.iter().enumerate().map(|(index, Variant { ident, ty })| {
+        let pattern = ty.map_or_else(|| quote! { #ident }, |_| quote! { #ident(..) });
+        quote! {
+            #summary_name::#pattern => #index
+        }
+    });
+
+    // Unit variants come first, so each state variant is followed by another or by none
+    let upgrade_arms = variants.windows(2).filter_map(|pair| {
+        let (ident, next_ident) = (pair[0].ident, pair[1].ident);
+        let (_, next_ty) = (pair[0].ty?, pair[1].ty?);
+        Some(quote! {
+            #name::#ident(state) => #name::#next_ident(<#next_ty as From<_>>::from(state))
+        })
+    });
+
+    let verify_arms = variants.iter().map(|Variant { ident, ty }| match ty {
+        Some(_) => quote! { #name::#ident(state) => state.verify(state, parameters) },
+        None => quote! { #name::#ident => Ok(()) },
+    });
+
+    let summarize_arms = variants.iter().map(|Variant { ident, ty }| match ty {
+        Some(_) => quote! {
+            #name::#ident(state) => #summary_name::#ident(state.summarize(state, parameters))
+        },
+        None => quote! { #name::#ident => #summary_name::#ident },
+    });
+
+    let delta_arms = variants.iter().filter_map(|Variant { ident, ty }| {
+        ty.map(|_| {
+            quote! {
+                (#name::#ident(state), #summary_name::#ident(summary)) => {
+                    state.delta(state, parameters, summary).map(#delta_name::#ident)
+                }
+            }
+        })
+    });
+
+    let apply_delta_arms = variants.iter().filter_map(|Variant { ident, ty }| {
+        ty.map(|_| {
+            quote! {
+                (#name::#ident(state), Some(#delta_name::#ident(delta))) => {
+                    let state_clone = state.clone();
+                    state.apply_delta(&state_clone, parameters, &Some(delta.clone()))
+                }
+                (#name::#ident(state), None) => {
+                    let state_clone = state.clone();
+                    state.apply_delta(&state_clone, parameters, &None)
+                }
+            }
+        })
+    });
+
+    let check_self_parented = field_types.iter().map(|ty| {
+        quote! {
+            const _: fn() = || {
+                fn check_parent_state<T: ComposableState<ParentState = T>>() {}
+                check_parent_state::<#ty>();
+            };
+        }
+    });
+
+    let check_matching_parameters = field_types.iter().map(|ty| {
+        quote! {
+            const _: fn() = || {
+                fn check_parameters<T: ComposableState<Parameters = <#first_field_type as ComposableState>::Parameters>>() {}
+                check_parameters::<#ty>();
+            };
+        }
+    });
+
+    let check_matching_error = field_types.iter().map(|ty| {
+        quote! {
+            const _: fn() = || {
+                fn check_error<T: ComposableState<Error = <#first_field_type as ComposableState>::Error>>() {}
+                check_error::<#ty>();
+            };
+        }
+    });
+
+    let replace = format_ident!("{}", REPLACE_VARIANT);
+
+    Ok(quote! {
+        use freenet_scaffold::ComposableState;
+
+        #input
+
+        #[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Debug)]
+        pub enum #summary_name #ty_generics #where_clause {
+            #(#summary_variants,)*
+        }
+
+        #[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Debug)]
+        #[allow(clippy::large_enum_variant)]
+        pub enum #delta_name #ty_generics #where_clause {
+            #(#delta_variants,)*
+            /// The whole state, sent when the other side holds an earlier variant
+            #replace(Box<#name #ty_generics>),
+        }
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            fn variant_index(&self) -> usize {
+                match self {
+                    #(#variant_index_arms,)*
+                }
+            }
+        }
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            /// This state moved to the later variant `index`, converted with `From` one variant at
+            /// a time. None if it's a unit variant, which has nothing to convert.
+            #[allow(unreachable_patterns)]
+            fn upgraded(self, index: usize) -> Option<Self> {
+                let mut state = self;
+                while state.variant_index() < index {
+                    state = match state {
+                        #(#upgrade_arms,)*
+                        _ => return None,
+                    };
+                }
+                Some(state)
+            }
+        }
+
+        impl #impl_generics #summary_name #ty_generics #where_clause {
+            fn variant_index(&self) -> usize {
+                match self {
+                    #(#summary_index_arms,)*
+                }
+            }
+        }
+
+        impl #impl_generics ComposableState for #name #ty_generics #where_clause {
+            type ParentState = #name #ty_generics;
+            type Summary = #summary_name #ty_generics;
+            type Delta = #delta_name #ty_generics;
+            type Parameters = <#first_field_type as ComposableState>::Parameters;
+            type Error = <#first_field_type as ComposableState>::Error;
+
+            fn verify(&self, _parent_state: &Self::ParentState, parameters: &Self::Parameters) -> Result<(), Self::Error> {
+                match self {
+                    #(#verify_arms,)*
+                }
+            }
+
+            fn summarize(&self, _parent_state: &Self::ParentState, parameters: &Self::Parameters) -> Self::Summary {
+                match self {
+                    #(#summarize_arms,)*
+                }
+            }
+
+            #[allow(unreachable_patterns)]
+            fn delta(&self, _parent_state: &Self::ParentState, parameters: &Self::Parameters, old_state_summary: &Self::Summary) -> Option<Self::Delta> {
+                let index = old_state_summary.variant_index();
+                if self.variant_index() > index {
+                    return Some(#delta_name::#replace(Box::new(self.clone())));
+                }
+                // The other side holds a later variant, ours is sent as it will merge it
+                let upgraded;
+                let state = if self.variant_index() < index {
+                    upgraded = self.clone().upgraded(index)?;
+                    &upgraded
+                } else {
+                    self
+                };
+                match (state, old_state_summary) {
+                    #(#delta_arms,)*
+                    // Unit variants
+                    _ => None,
+                }
+            }
+
+            #[allow(unreachable_patterns)]
+            fn apply_delta(&mut self, _parent_state: &Self::ParentState, parameters: &Self::Parameters, delta: &Option<Self::Delta>) -> Result<(), Self::Error> {
+                if let Some(#delta_name::#replace(new_state)) = delta {
+                    if new_state.variant_index() <= self.variant_index() {
+                        return Err(freenet_scaffold::ComposableError::StaleVariant {
+                            state: stringify!(#name).to_string(),
+                            current: self.variant_index(),
+                            replacement: new_state.variant_index(),
+                        }
+                        .into());
+                    }
+                    new_state.verify(new_state, parameters)?;
+                    // Our state moves to the later variant and takes in the new one
+                    let Some(mut upgraded) = self.clone().upgraded(new_state.variant_index()) else {
+                        *self = (**new_state).clone();
+                        return Ok(());
+                    };
+                    let current_state = upgraded.clone();
+                    upgraded.merge(&current_state, parameters, new_state)?;
+                    *self = upgraded;
+                    return Ok(());
+                }
+                match (self, delta) {
+                    #(#apply_delta_arms,)*
+                    (_, None) => Ok(()),
+                    (state, Some(_)) => Err(freenet_scaffold::ComposableError::VariantMismatch {
+                        state: stringify!(#name).to_string(),
+                        current: state.variant_index(),
+                    }
+                    .into()),
+                }
+            }
+        }
+
+        // Additional checks to provide better compile-time error messages
+        #(#check_self_parented)*
+        #(#check_matching_parameters)*
+        #(#check_matching_error)*
+    })
+}
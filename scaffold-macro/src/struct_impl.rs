@@ -0,0 +1,202 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{DataStruct, DeriveInput, Fields, Index, Member};
+
+pub fn expand(input: &DeriveInput, data_struct: &DataStruct) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+
+    if matches!(data_struct.fields, Fields::Unit) || data_struct.fields.is_empty() {
+        return Err(syn::Error::new_spanned(
+            name,
+            "ComposableState structs need at least one field",
+        ));
+    }
+
+    // syn::Member lets `self.#member` and `Name { #member: .. }` work for named and tuple fields
+    let members: Vec<Member> = data_struct
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| match &f.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(i)),
+        })
+        .collect();
+    let field_types: Vec<_> = data_struct.fields.iter().map(|f| &f.ty).collect();
+
+    // Take the type of the first field to derive ParentState and Parameters
+    let first_field_type = &field_types[0];
+
+    let summary_name = format_ident!("{}Summary", name);
+    let delta_name = format_ident!("{}Delta", name);
+
+    let summary_types = field_types
+        .iter()
+        .map(|ty| quote! { <#ty as ComposableState>::Summary })
+        .collect::<Vec<_>>();
+    let delta_types = field_types
+        .iter()
+        .map(|ty| quote! { Option<<#ty as ComposableState>::Delta> })
+        .collect::<Vec<_>>();
+
+    let where_clause = input.generics.where_clause.clone();
+    let (impl_generics, ty_generics, _) = input.generics.split_for_impl();
+
+    let (summary_struct, delta_struct) = match &data_struct.fields {
+        Fields::Named(fields_named) => {
+            let names: Vec<_> = fields_named.named.iter().map(|f| &f.ident).collect();
+            (
+                quote! { pub struct #summary_name #ty_generics #where_clause { #(pub #names: #summary_types,)* } },
+                quote! { pub struct #delta_name #ty_generics #where_clause { #(pub #names: #delta_types,)* } },
+            )
+        }
+        _ => (
+            quote! { pub struct #summary_name #ty_generics (#(pub #summary_types,)*) #where_clause; },
+            quote! { pub struct #delta_name #ty_generics (#(pub #delta_types,)*) #where_clause; },
+        ),
+    };
+
+    // Error messages for missing ComposableState implementation
+    let check_composable_impls = field_types.iter().map(|ty| {
+        quote! {
+            const _: fn() = || {
+                fn check_composable<T: ComposableState>() {}
+                check_composable::<#ty>();
+            };
+        }
+    });
+
+    // Ensure that all fields share the same ParentState, Parameters and Error
+    let check_matching_parent_state = field_types.iter().map(|ty| {
+        quote! {
+            const _: fn() = || {
+                fn check_parent_state<T: ComposableState<ParentState = <#first_field_type as ComposableState>::ParentState>>() {}
+                check_parent_state::<#ty>();
+            };
+        }
+    });
+
+    let check_matching_parameters = field_types.iter().map(|ty| {
+        quote! {
+            const _: fn() = || {
+                fn check_parameters<T: ComposableState<Parameters = <#first_field_type as ComposableState>::Parameters>>() {}
+                check_parameters::<#ty>();
+            };
+        }
+    });
+
+    let check_matching_error = field_types.iter().map(|ty| {
+        quote! {
+            const _: fn() = || {
+                fn check_error<T: ComposableState<Error = <#first_field_type as ComposableState>::Error>>() {}
+                check_error::<#ty>();
+            };
+        }
+    });
+
+    let verify_impl = members.iter().map(|member| {
+        quote! {
+            self.#member.verify(parent_state, parameters)?;
+        }
+    });
+
+    let summarize_impl = members.iter().map(|member| {
+        quote! {
+            #member: self.#member.summarize(parent_state, parameters)
+        }
+    });
+
+    let delta_impl = members.iter().map(|member| {
+        quote! {
+            #member: self.#member.delta(parent_state, parameters, &old_state_summary.#member)
+        }
+    });
+
+    let all_none_check = members
+        .iter()
+        .map(|member| {
+            quote! {
+                delta.#member.is_none()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Note: we're passing self_clone as the parent_state so that dependencies between fields work
+    let apply_delta_impl = members.iter().map(|member| {
+        quote! {
+            let self_clone = self.clone();
+            self.#member.apply_delta(&self_clone, parameters, &delta.#member)?;
+        }
+    });
+
+    let settle_impl = members.iter().map(|member| {
+        quote! {
+            let self_clone = self.clone();
+            self.#member.apply_delta(&self_clone, parameters, &None)?;
+        }
+    });
+
+    Ok(quote! {
+        use freenet_scaffold::ComposableState;
+
+        #input
+
+        // Automatically implement Serialize, Deserialize, Clone, PartialEq, and Debug for the generated Summary and Delta structs
+        #[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Debug)]
+        #summary_struct
+
+        #[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Debug, Default)]
+        #delta_struct
+
+        impl #impl_generics ComposableState for #name #ty_generics #where_clause
+        where
+            #(#field_types: ComposableState,)*
+        {
+            type ParentState = #name;
+            type Summary = #summary_name #ty_generics;
+            type Delta = #delta_name #ty_generics;
+            type Parameters = <#first_field_type as ComposableState>::Parameters;
+            type Error = <#first_field_type as ComposableState>::Error;
+
+            fn verify(&self, parent_state: &Self::ParentState, parameters: &Self::Parameters) -> Result<(), Self::Error> {
+                #(#verify_impl)*
+                Ok(())
+            }
+
+            fn summarize(&self, parent_state: &Self::ParentState, parameters: &Self::Parameters) -> Self::Summary {
+                #summary_name {
+                    #(#summarize_impl,)*
+                }
+            }
+
+            fn delta(&self, parent_state: &Self::ParentState, parameters: &Self::Parameters, old_state_summary: &Self::Summary) -> Option<Self::Delta> {
+                let delta = #delta_name {
+                    #(#delta_impl,)*
+                };
+
+                if #(#all_none_check)&&* {
+                    None
+                } else {
+                    Some(delta)
+                }
+            }
+
+            // parent_state disregarded because we need to use self so that dependencies between fields work, ugly
+            fn apply_delta(&mut self, _parent_state: &Self::ParentState, parameters: &Self::Parameters, delta: &Option<Self::Delta>) -> Result<(), Self::Error> {
+                if let Some(delta) = delta {
+                    #(#apply_delta_impl)*
+                    // A field can depend on fields declared after it, eg. a ban on a member added by
+                    // the same delta, so give every field a chance to catch up with the new state
+                    #(#settle_impl)*
+                }
+                Ok(())
+            }
+        }
+
+        // Additional checks to provide better compile-time error messages
+        #(#check_composable_impls)*
+        #(#check_matching_parent_state)*
+        #(#check_matching_parameters)*
+        #(#check_matching_error)*
+    })
+}
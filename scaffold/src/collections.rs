@@ -0,0 +1,57 @@
+//! Reusable `ComposableState` implementations for the collections that make up most contract
+//! states. Each one converges: `merge` is commutative, associative and idempotent as long as the
+//! elements' `verify` only depends on the element and the parent state.
+
+mod bounded_log;
+mod grow_only_set;
+mod lww_register;
+mod versioned_map;
+
+pub use bounded_log::{BoundedLog, LogEntry};
+pub use grow_only_set::GrowOnlySet;
+pub use lww_register::LwwRegister;
+pub use versioned_map::VersionedMap;
+
+use crate::ComposableError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+
+/// Something with a stable identity, typically a hash of its signature
+pub trait Identified {
+    type Id: Serialize + DeserializeOwned + Clone + Debug + Eq + Hash + Ord;
+
+    fn id(&self) -> Self::Id;
+}
+
+/// An element stored in one of the collections, `verify` is where it checks itself against the
+/// parent state, eg. a signature against the key of a member in another field.
+pub trait Element: Serialize + DeserializeOwned + Clone + Debug + PartialEq {
+    type ParentState: Serialize + DeserializeOwned + Clone + Debug;
+    type Parameters: Serialize + DeserializeOwned + Clone + Debug;
+    type Error: Debug + Display + From<ComposableError>;
+
+    fn verify(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), Self::Error>;
+}
+
+/// An element that supersedes earlier versions of itself
+pub trait Versioned {
+    type Version: Serialize + DeserializeOwned + Clone + Debug + Ord;
+
+    fn version(&self) -> Self::Version;
+}
+
+/// An element stored under a key, where newer versions replace older ones
+pub trait Keyed {
+    type Key: Serialize + DeserializeOwned + Clone + Debug + Ord;
+
+    fn key(&self) -> Self::Key;
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,139 @@
+use super::{Element, Identified};
+use crate::id_set::IdSet;
+use crate::{ComposableError, ComposableState};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// An entry of a [`BoundedLog`]
+pub trait LogEntry: Element + Identified {
+    type OrderKey: Ord;
+
+    /// Position of the entry in the log, typically a timestamp, ties are broken by id
+    fn order_key(&self) -> Self::OrderKey;
+
+    /// Maximum number of entries to retain, typically read from configuration in the parent
+    fn capacity(parent_state: &Self::ParentState, parameters: &Self::Parameters) -> usize;
+}
+
+/// An ordered log which keeps only the most recent entries, once the log is full the oldest
+/// entries are evicted. Entries which stop verifying, eg. because their author was removed from
+/// the parent state, are pruned whenever a delta is applied.
+///
+/// It's encoded as a plain list so that it can replace a `Vec` field without changing the state's
+/// encoding. States whose entries can only be checked together, eg. against each other, can add
+/// entries with [`BoundedLog::extend`], prune them themselves and then call
+/// [`BoundedLog::settle`].
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(transparent)]
+pub struct BoundedLog<T> {
+    pub entries: Vec<T>,
+}
+
+impl<T> Default for BoundedLog<T> {
+    fn default() -> Self {
+        BoundedLog {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<T> From<Vec<T>> for BoundedLog<T> {
+    /// Takes the entries as they are, they're put in order by the next `settle`
+    fn from(entries: Vec<T>) -> Self {
+        BoundedLog { entries }
+    }
+}
+
+impl<T: LogEntry> BoundedLog<T> {
+    /// Adds the entries that aren't in the log yet without verifying them
+    pub fn extend(&mut self, entries: impl IntoIterator<Item = T>) {
+        let mut known: HashSet<T::Id> = self.entries.iter().map(|entry| entry.id()).collect();
+        self.entries
+            .extend(entries.into_iter().filter(|entry| known.insert(entry.id())));
+    }
+
+    /// Puts the entries in order and evicts the oldest beyond `capacity`. The result only depends
+    /// on which entries the log holds, not on the order they came in.
+    pub fn settle(&mut self, capacity: usize) {
+        self.entries
+            .sort_by_key(|entry| (entry.order_key(), entry.id()));
+        self.entries.dedup_by_key(|entry| entry.id());
+        if self.entries.len() > capacity {
+            self.entries.drain(0..self.entries.len() - capacity);
+        }
+    }
+}
+
+impl<T: LogEntry> ComposableState for BoundedLog<T> {
+    type ParentState = T::ParentState;
+    type Summary = IdSet<T::Id>;
+    type Delta = Vec<T>;
+    type Parameters = T::Parameters;
+    type Error = T::Error;
+
+    fn verify(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), Self::Error> {
+        let capacity = T::capacity(parent_state, parameters);
+        if self.entries.len() > capacity {
+            return Err(ComposableError::TooManyEntries {
+                count: self.entries.len(),
+                max: capacity,
+            }
+            .into());
+        }
+        let in_order = self
+            .entries
+            .windows(2)
+            .all(|w| (w[0].order_key(), w[0].id()) < (w[1].order_key(), w[1].id()));
+        if !in_order {
+            return Err(ComposableError::Unordered.into());
+        }
+        self.entries
+            .iter()
+            .try_for_each(|entry| entry.verify(parent_state, parameters))
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        self.entries.iter().map(|entry| entry.id()).collect()
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let added: Vec<T> = self
+            .entries
+            .iter()
+            .filter(|entry| !old_state_summary.contains(&entry.id()))
+            .cloned()
+            .collect();
+        (!added.is_empty()).then_some(added)
+    }
+
+    fn apply_delta(
+        &mut self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), Self::Error> {
+        if let Some(added) = delta {
+            for entry in added {
+                entry.verify(parent_state, parameters)?;
+            }
+            self.extend(added.iter().cloned());
+        }
+        self.entries
+            .retain(|entry| entry.verify(parent_state, parameters).is_ok());
+        self.settle(T::capacity(parent_state, parameters));
+        Ok(())
+    }
+}
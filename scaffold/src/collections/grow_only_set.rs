@@ -0,0 +1,98 @@
+use super::{Element, Identified};
+use crate::id_set::IdSet;
+use crate::{ComposableError, ComposableState};
+use serde::{Deserialize, Serialize};
+
+/// A set that elements can be added to but never removed from, kept sorted by id so that
+/// states which have seen the same elements are equal. It's encoded as a plain list.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(transparent)]
+pub struct GrowOnlySet<T> {
+    pub items: Vec<T>,
+}
+
+impl<T> Default for GrowOnlySet<T> {
+    fn default() -> Self {
+        GrowOnlySet { items: Vec::new() }
+    }
+}
+
+impl<T: Element + Identified> GrowOnlySet<T> {
+    pub fn contains(&self, id: &T::Id) -> bool {
+        self.items
+            .binary_search_by(|item| item.id().cmp(id))
+            .is_ok()
+    }
+
+    /// Adds an element without verifying it, returns false if it was already present
+    pub fn insert(&mut self, item: T) -> bool {
+        match self.items.binary_search_by(|i| i.id().cmp(&item.id())) {
+            Ok(_) => false,
+            Err(position) => {
+                self.items.insert(position, item);
+                true
+            }
+        }
+    }
+}
+
+impl<T: Element + Identified> ComposableState for GrowOnlySet<T> {
+    type ParentState = T::ParentState;
+    type Summary = IdSet<T::Id>;
+    type Delta = Vec<T>;
+    type Parameters = T::Parameters;
+    type Error = T::Error;
+
+    fn verify(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), Self::Error> {
+        if !self.items.windows(2).all(|w| w[0].id() < w[1].id()) {
+            return Err(ComposableError::Unordered.into());
+        }
+        self.items
+            .iter()
+            .try_for_each(|item| item.verify(parent_state, parameters))
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        self.items.iter().map(|item| item.id()).collect()
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let added: Vec<T> = self
+            .items
+            .iter()
+            .filter(|item| !old_state_summary.contains(&item.id()))
+            .cloned()
+            .collect();
+        (!added.is_empty()).then_some(added)
+    }
+
+    fn apply_delta(
+        &mut self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), Self::Error> {
+        if let Some(added) = delta {
+            for item in added {
+                item.verify(parent_state, parameters)?;
+            }
+            for item in added {
+                self.insert(item.clone());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use super::{Element, Identified, Versioned};
+use crate::ComposableState;
+use serde::{Deserialize, Serialize};
+
+/// Holds at most one value, a value with a higher version replaces the current one. Equal
+/// versions are ordered by id so that every peer picks the same winner.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LwwRegister<T>(pub Option<T>);
+
+impl<T> Default for LwwRegister<T> {
+    fn default() -> Self {
+        LwwRegister(None)
+    }
+}
+
+impl<T: Element + Versioned + Identified> LwwRegister<T> {
+    fn supersedes(value: &T, summary: &Option<(T::Version, T::Id)>) -> bool {
+        summary
+            .as_ref()
+            .is_none_or(|current| (value.version(), value.id()) > *current)
+    }
+}
+
+impl<T: Element + Versioned + Identified> ComposableState for LwwRegister<T> {
+    type ParentState = T::ParentState;
+    type Summary = Option<(T::Version, T::Id)>;
+    type Delta = T;
+    type Parameters = T::Parameters;
+    type Error = T::Error;
+
+    fn verify(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), Self::Error> {
+        match &self.0 {
+            Some(value) => value.verify(parent_state, parameters),
+            None => Ok(()),
+        }
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        self.0.as_ref().map(|value| (value.version(), value.id()))
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        self.0
+            .as_ref()
+            .filter(|value| Self::supersedes(value, old_state_summary))
+            .cloned()
+    }
+
+    fn apply_delta(
+        &mut self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), Self::Error> {
+        if let Some(value) = delta {
+            value.verify(parent_state, parameters)?;
+            let current = self.summarize(parent_state, parameters);
+            if Self::supersedes(value, &current) {
+                self.0 = Some(value.clone());
+            }
+        }
+        Ok(())
+    }
+}
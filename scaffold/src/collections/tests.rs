@@ -0,0 +1,195 @@
+use super::*;
+use crate::ComposableState;
+use proptest::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const LOG_CAPACITY: usize = 4;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+struct TestItem {
+    id: u8,
+    key: u8,
+    version: u8,
+    valid: bool,
+}
+
+impl Identified for TestItem {
+    type Id = u8;
+
+    fn id(&self) -> u8 {
+        self.id
+    }
+}
+
+impl Element for TestItem {
+    type ParentState = ();
+    type Parameters = ();
+    type Error = String;
+
+    fn verify(&self, _parent_state: &(), _parameters: &()) -> Result<(), String> {
+        if self.valid {
+            Ok(())
+        } else {
+            Err(format!("Item {} is invalid", self.id))
+        }
+    }
+}
+
+impl Versioned for TestItem {
+    type Version = u8;
+
+    fn version(&self) -> u8 {
+        self.version
+    }
+}
+
+impl Keyed for TestItem {
+    type Key = u8;
+
+    fn key(&self) -> u8 {
+        self.key
+    }
+}
+
+impl LogEntry for TestItem {
+    type OrderKey = u8;
+
+    fn order_key(&self) -> u8 {
+        self.version
+    }
+
+    fn capacity(_parent_state: &(), _parameters: &()) -> usize {
+        LOG_CAPACITY
+    }
+}
+
+fn item(id: u8, key: u8, version: u8) -> TestItem {
+    TestItem {
+        id,
+        key,
+        version,
+        valid: true,
+    }
+}
+
+fn items() -> impl Strategy<Value = Vec<TestItem>> {
+    // Ids determine the content so that two items with the same id are always equal
+    prop::collection::vec(any::<u8>(), 0..8)
+        .prop_map(|ids| ids.into_iter().map(|id| item(id, id % 4, id / 4)).collect())
+}
+
+/// Builds a state by applying `items` to an empty one, the way a peer would receive them
+fn build<S: ComposableState<ParentState = (), Parameters = ()> + Default>(
+    items: Vec<TestItem>,
+    to_delta: impl Fn(Vec<TestItem>) -> S::Delta,
+) -> S {
+    let mut state = S::default();
+    state.apply_delta(&(), &(), &Some(to_delta(items))).unwrap();
+    state
+}
+
+fn merged<S: ComposableState<ParentState = (), Parameters = ()> + Clone>(a: &S, b: &S) -> S {
+    let mut merged = a.clone();
+    merged.merge(&(), &(), b).unwrap();
+    merged
+}
+
+/// Checks that merging is commutative, associative and idempotent and yields a valid state
+fn check_convergence<S>(a: S, b: S, c: S)
+where
+    S: ComposableState<ParentState = (), Parameters = (), Error = String>
+        + Clone
+        + PartialEq
+        + Debug,
+{
+    let ab = merged(&a, &b);
+    assert_eq!(ab, merged(&b, &a));
+    assert_eq!(merged(&ab, &c), merged(&a, &merged(&b, &c)));
+    assert_eq!(merged(&ab, &ab), ab);
+    assert_eq!(ab.verify(&(), &()), Ok(()));
+}
+
+proptest! {
+    #[test]
+    fn grow_only_set_converges(a in items(), b in items(), c in items()) {
+        let build = |items| build::<GrowOnlySet<TestItem>>(items, |d| d);
+        check_convergence(build(a), build(b), build(c));
+    }
+
+    #[test]
+    fn bounded_log_converges(a in items(), b in items(), c in items()) {
+        let build = |items| build::<BoundedLog<TestItem>>(items, |d| d);
+        check_convergence(build(a), build(b), build(c));
+    }
+
+    #[test]
+    fn lww_register_converges(a in items(), b in items(), c in items()) {
+        let build = |items: Vec<TestItem>| {
+            let mut register = LwwRegister::default();
+            for item in items {
+                register.apply_delta(&(), &(), &Some(item)).unwrap();
+            }
+            register
+        };
+        check_convergence(build(a), build(b), build(c));
+    }
+
+    #[test]
+    fn versioned_map_converges(a in items(), b in items(), c in items()) {
+        let build = |items| build::<VersionedMap<TestItem>>(items, |d| d);
+        check_convergence(build(a), build(b), build(c));
+    }
+}
+
+#[test]
+fn test_bounded_log_evicts_oldest() {
+    let items: Vec<TestItem> = (0..6).map(|i| item(i, 0, i)).collect();
+    let log: BoundedLog<TestItem> = build(items, |d| d);
+    let ids: Vec<u8> = log.entries.iter().map(|e| e.id).collect();
+    assert_eq!(ids, vec![2, 3, 4, 5]);
+}
+
+#[test]
+fn test_invalid_delta_is_rejected() {
+    let invalid = TestItem {
+        valid: false,
+        ..item(1, 1, 1)
+    };
+    let mut set = GrowOnlySet::default();
+    assert!(set
+        .apply_delta(&(), &(), &Some(vec![item(0, 0, 0), invalid]))
+        .is_err());
+    assert!(set.items.is_empty());
+}
+
+#[test]
+fn test_versioned_map_keeps_latest_version() {
+    let map: VersionedMap<TestItem> = build(vec![item(1, 7, 2), item(2, 7, 1)], |d| d);
+    assert_eq!(map.get(&7), Some(&item(1, 7, 2)));
+
+    let mut stale = map.clone();
+    stale
+        .apply_delta(&(), &(), &Some(vec![item(3, 7, 0)]))
+        .unwrap();
+    assert_eq!(stale, map);
+}
+
+fn encode(value: &impl Serialize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(value, &mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn test_collections_are_encoded_as_lists() {
+    let items = vec![item(1, 1, 0), item(2, 2, 0)];
+    let list = encode(&items);
+    assert_eq!(
+        encode(&GrowOnlySet {
+            items: items.clone()
+        }),
+        list
+    );
+    assert_eq!(encode(&BoundedLog::from(items.clone())), list);
+    assert_eq!(encode(&VersionedMap { entries: items }), list);
+}
@@ -0,0 +1,111 @@
+use super::{Element, Identified, Keyed, Versioned};
+use crate::{ComposableError, ComposableState};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// One value per key where each key behaves like an [`super::LwwRegister`], kept sorted by key.
+/// It's encoded as a plain list.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(transparent)]
+pub struct VersionedMap<T> {
+    pub entries: Vec<T>,
+}
+
+impl<T> Default for VersionedMap<T> {
+    fn default() -> Self {
+        VersionedMap {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<T: Element + Keyed + Versioned + Identified> VersionedMap<T> {
+    pub fn get(&self, key: &T::Key) -> Option<&T> {
+        self.position(key).ok().map(|i| &self.entries[i])
+    }
+
+    fn position(&self, key: &T::Key) -> Result<usize, usize> {
+        self.entries.binary_search_by(|entry| entry.key().cmp(key))
+    }
+
+    /// Stores the value unless the current value for its key has a higher version, doesn't verify
+    pub fn upsert(&mut self, value: T) {
+        match self.position(&value.key()) {
+            Ok(i) => {
+                let current = &self.entries[i];
+                if (value.version(), value.id()) > (current.version(), current.id()) {
+                    self.entries[i] = value;
+                }
+            }
+            Err(i) => self.entries.insert(i, value),
+        }
+    }
+}
+
+impl<T: Element + Keyed + Versioned + Identified> ComposableState for VersionedMap<T> {
+    type ParentState = T::ParentState;
+    type Summary = BTreeMap<T::Key, (T::Version, T::Id)>;
+    type Delta = Vec<T>;
+    type Parameters = T::Parameters;
+    type Error = T::Error;
+
+    fn verify(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), Self::Error> {
+        if !self.entries.windows(2).all(|w| w[0].key() < w[1].key()) {
+            return Err(ComposableError::Unordered.into());
+        }
+        self.entries
+            .iter()
+            .try_for_each(|entry| entry.verify(parent_state, parameters))
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        self.entries
+            .iter()
+            .map(|entry| (entry.key(), (entry.version(), entry.id())))
+            .collect()
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let changed: Vec<T> = self
+            .entries
+            .iter()
+            .filter(|entry| {
+                old_state_summary
+                    .get(&entry.key())
+                    .is_none_or(|old| (entry.version(), entry.id()) > *old)
+            })
+            .cloned()
+            .collect();
+        (!changed.is_empty()).then_some(changed)
+    }
+
+    fn apply_delta(
+        &mut self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), Self::Error> {
+        if let Some(changed) = delta {
+            for entry in changed {
+                entry.verify(parent_state, parameters)?;
+            }
+            for entry in changed {
+                self.upsert(entry.clone());
+            }
+        }
+        Ok(())
+    }
+}
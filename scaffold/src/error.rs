@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Errors detected by the scaffold itself rather than by the states it composes, ie. by the
+/// collections and by the code `#[composable]` generates. Every `ComposableState::Error` must be
+/// able to represent them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ComposableError {
+    /// A collection holds more entries than its capacity
+    TooManyEntries { count: usize, max: usize },
+    /// A collection's entries aren't in canonical order or contain duplicates
+    Unordered,
+    /// An enum state was sent a replacement that isn't a later variant
+    StaleVariant {
+        state: String,
+        current: usize,
+        replacement: usize,
+    },
+    /// An enum state was sent a delta for a different variant than the one it holds
+    VariantMismatch { state: String, current: usize },
+}
+
+impl fmt::Display for ComposableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ComposableError::TooManyEntries { count, max } => {
+                write!(f, "Too many entries: {} > {}", count, max)
+            }
+            ComposableError::Unordered => {
+                write!(f, "Entries must be ordered without duplicates")
+            }
+            ComposableError::StaleVariant {
+                state,
+                current,
+                replacement,
+            } => write!(
+                f,
+                "Can't replace {} state variant {} with earlier or equal variant {}",
+                state, current, replacement
+            ),
+            ComposableError::VariantMismatch { state, current } => {
+                write!(f, "{} delta doesn't match state variant {}", state, current)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ComposableError {}
+
+/// Lets states that don't need structured errors keep using `String`
+impl From<ComposableError> for String {
+    fn from(error: ComposableError) -> Self {
+        error.to_string()
+    }
+}
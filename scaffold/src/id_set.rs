@@ -0,0 +1,323 @@
+//! Sets of ids for summaries. A summary lists the ids of everything a peer has so that `delta`
+//! can leave those out; for large states the list can instead be compacted into a Bloom filter,
+//! which is a fraction of the size but answers `contains` with occasional false positives.
+
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+const BLOOM_SEED_CONTEXT: &str = "freenet-scaffold 2025-01 bloom filter seed";
+const BLOOM_HASH_CONTEXT: &str = "freenet-scaffold 2025-01 bloom filter hash";
+
+/// The ids a summary covers, either listed or compacted with [`IdSet::compact`].
+///
+/// A compacted set can claim to contain an id it doesn't, in which case `delta` leaves out
+/// something the peer is missing. It must only be used for summaries sent to other peers, never
+/// for the summary `merge` computes, or states would stop converging. The filter is seeded with
+/// the ids it covers, so a missed id is sent once the peer's set changes.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(
+    untagged,
+    bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de> + Ord")
+)]
+pub enum IdSet<T> {
+    /// Encoded as a plain list, as summaries were before they could be compacted
+    Listed(BTreeSet<T>),
+    Bloom(BloomFilter),
+}
+
+impl<T> Default for IdSet<T> {
+    fn default() -> Self {
+        IdSet::Listed(BTreeSet::new())
+    }
+}
+
+impl<T: Ord> FromIterator<T> for IdSet<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        IdSet::Listed(iter.into_iter().collect())
+    }
+}
+
+impl<T: Hash + Ord> IdSet<T> {
+    /// Whether `id` is in the set, possibly wrongly if the set was compacted
+    pub fn contains(&self, id: &T) -> bool {
+        match self {
+            IdSet::Listed(ids) => ids.contains(id),
+            IdSet::Bloom(filter) => filter.contains(id),
+        }
+    }
+
+    /// The listed ids, `None` once compacted
+    pub fn listed(&self) -> Option<&BTreeSet<T>> {
+        match self {
+            IdSet::Listed(ids) => Some(ids),
+            IdSet::Bloom(_) => None,
+        }
+    }
+
+    /// Replaces a list of at least `min_ids` ids with a Bloom filter that wrongly contains an id
+    /// with about `false_positive_rate` probability
+    pub fn compact(&mut self, min_ids: usize, false_positive_rate: f64) {
+        if let IdSet::Listed(ids) = self {
+            if !ids.is_empty() && ids.len() >= min_ids {
+                *self = IdSet::Bloom(BloomFilter::new(&*ids, false_positive_rate));
+            }
+        }
+    }
+}
+
+/// A Bloom filter over ids, hashed with blake3 so that peers on any platform agree on it
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct BloomFilter {
+    bits: Bits,
+    hashes: u32,
+    seed: u64,
+}
+
+impl BloomFilter {
+    pub fn new<T: Hash + Ord>(ids: &BTreeSet<T>, false_positive_rate: f64) -> Self {
+        let mut seed_hasher = IdHasher::new(BLOOM_SEED_CONTEXT);
+        ids.iter().for_each(|id| id.hash(&mut seed_hasher));
+        let seed = seed_hasher.finish();
+
+        // The optimal size for n ids is -n ln(p) / ln(2)^2 bits with (bits / n) ln(2) hashes
+        let rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let n = ids.len().max(1) as f64;
+        let len = ((-n * rate.ln() / (2f64.ln() * 2f64.ln())) / 8.0)
+            .ceil()
+            .max(1.0) as usize;
+        let hashes = ((len * 8) as f64 / n * 2f64.ln()).round().clamp(1.0, 32.0) as u32;
+
+        let mut filter = BloomFilter {
+            bits: Bits(vec![0; len]),
+            hashes,
+            seed,
+        };
+        for id in ids {
+            for bit in filter.bit_indices(id) {
+                filter.bits.0[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        filter
+    }
+
+    pub fn contains<T: Hash>(&self, id: &T) -> bool {
+        self.bit_indices(id).all(|bit| {
+            self.bits
+                .0
+                .get(bit / 8)
+                .is_some_and(|b| b & (1 << (bit % 8)) != 0)
+        })
+    }
+
+    /// Size of the filter in bytes
+    pub fn len(&self) -> usize {
+        self.bits.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.0.is_empty()
+    }
+
+    // Double hashing, the i-th bit is h1 + i * h2
+    fn bit_indices<T: Hash>(&self, id: &T) -> impl Iterator<Item = usize> {
+        let mut hasher = IdHasher::new(BLOOM_HASH_CONTEXT);
+        hasher.write_u64(self.seed);
+        id.hash(&mut hasher);
+        let hash = hasher.0.finalize();
+        let bytes = hash.as_bytes();
+        let h1 = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().unwrap()) | 1;
+        let bits = (self.bits.0.len() * 8).max(1) as u64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits) as usize)
+    }
+}
+
+/// Feeds `Hash` implementations to blake3 with fixed width little-endian integers, so that the
+/// result doesn't depend on the platform's pointer width or endianness
+struct IdHasher(blake3::Hasher);
+
+impl IdHasher {
+    fn new(context: &str) -> Self {
+        IdHasher(blake3::Hasher::new_derive_key(context))
+    }
+}
+
+impl Hasher for IdHasher {
+    fn finish(&self) -> u64 {
+        u64::from_le_bytes(self.0.finalize().as_bytes()[..8].try_into().unwrap())
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.write(&[i]);
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i8(&mut self, i: i8) {
+        self.write_u8(i as u8);
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as u64);
+    }
+}
+
+/// Filter bits, serialized as a byte string rather than a list of integers
+#[derive(Clone, PartialEq)]
+struct Bits(Vec<u8>);
+
+impl fmt::Debug for Bits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes", self.0.len())
+    }
+}
+
+impl Serialize for Bits {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bits {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_bytes(BitsVisitor)
+    }
+}
+
+struct BitsVisitor;
+
+impl<'de> Visitor<'de> for BitsVisitor {
+    type Value = Bits;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a byte string")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(Bits(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(Bits(v))
+    }
+
+    // Formats without a native byte string type, such as JSON, encode bytes as a sequence
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = Vec::new();
+        while let Some(byte) = seq.next_element::<u8>()? {
+            bytes.push(byte);
+        }
+        Ok(Bits(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{blake3_hash, VersionedHash};
+
+    fn ids(range: std::ops::Range<u32>) -> Vec<VersionedHash> {
+        range
+            .map(|i| blake3_hash("test", &i.to_le_bytes()))
+            .collect()
+    }
+
+    fn round_trip<T: Serialize + for<'de> Deserialize<'de>>(value: &T) -> T {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(value, &mut bytes).unwrap();
+        ciborium::de::from_reader(bytes.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_listed_reads_as_a_plain_list() {
+        let listed: IdSet<VersionedHash> = ids(0..3).into_iter().collect();
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&listed, &mut bytes).unwrap();
+        let list: Vec<VersionedHash> = ciborium::de::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(list.len(), 3);
+
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&ids(0..3), &mut bytes).unwrap();
+        let read: IdSet<VersionedHash> = ciborium::de::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(read, listed);
+    }
+
+    #[test]
+    fn test_compact() {
+        let present = ids(0..2000);
+        let mut set: IdSet<VersionedHash> = present.iter().cloned().collect();
+        let listed = set.clone();
+
+        // Small sets are left as they are
+        set.compact(5000, 0.01);
+        assert_eq!(set, listed);
+
+        set.compact(64, 0.01);
+        let IdSet::Bloom(filter) = &set else {
+            panic!("not compacted");
+        };
+        assert!(filter.len() < 2000 * 33 / 10);
+        assert_eq!(round_trip(&set), set);
+
+        // No false negatives, and false positives close to the rate asked for
+        assert!(present.iter().all(|id| set.contains(id)));
+        let false_positives = ids(2000..12000)
+            .iter()
+            .filter(|id| set.contains(id))
+            .count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_seed_follows_contents() {
+        let mut a: IdSet<VersionedHash> = ids(0..100).into_iter().collect();
+        let mut b: IdSet<VersionedHash> = ids(0..100).into_iter().rev().collect();
+        let mut c: IdSet<VersionedHash> = ids(0..101).into_iter().collect();
+        a.compact(1, 0.01);
+        b.compact(1, 0.01);
+        c.compact(1, 0.01);
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+}
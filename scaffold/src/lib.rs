@@ -1,21 +1,32 @@
+pub mod collections;
+mod error;
+pub mod id_set;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod util;
 
+// Lets code generated by `#[composable]` refer to `freenet_scaffold` from within this crate's tests
+extern crate self as freenet_scaffold;
+
+pub use error::ComposableError;
 pub use freenet_scaffold_macro::*;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
 
 pub trait ComposableState {
     type ParentState: Serialize + DeserializeOwned + Clone + Debug;
     type Summary: Serialize + DeserializeOwned + Clone + Debug;
     type Delta: Serialize + DeserializeOwned + Clone + Debug;
     type Parameters: Serialize + DeserializeOwned + Clone + Debug;
+    /// Why a state or delta was rejected, `String` will do for states that don't need more
+    type Error: Debug + Display + From<ComposableError>;
 
     fn verify(
         &self,
         parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
-    ) -> Result<(), String>;
+    ) -> Result<(), Self::Error>;
     fn summarize(
         &self,
         parent_state: &Self::ParentState,
@@ -37,7 +48,7 @@ pub trait ComposableState {
         parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
         delta: &Option<Self::Delta>,
-    ) -> Result<(), String>;
+    ) -> Result<(), Self::Error>;
 
     /// Merges the current state with another state.
     fn merge(
@@ -45,7 +56,7 @@ pub trait ComposableState {
         parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
         other_state: &Self,
-    ) -> Result<(), String> {
+    ) -> Result<(), Self::Error> {
         let my_summary = self.summarize(parent_state, parameters);
         let delta_in = other_state.delta(parent_state, parameters, &my_summary);
         self.apply_delta(parent_state, parameters, &delta_in)?;
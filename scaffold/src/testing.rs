@@ -0,0 +1,191 @@
+//! Property checks for top-level states, enabled by the `testing` feature.
+//!
+//! Peers start from the same state, make changes independently and synchronize with each other in
+//! arbitrary order. Whatever the order, every peer must end up with a state that verifies and that
+//! is identical to every other peer's once they have all exchanged their changes.
+
+use crate::ComposableState;
+use proptest::prelude::*;
+use std::fmt::Debug;
+
+/// A change a peer makes to its own copy of the state, eg. sending a message
+pub trait Mutation<S: ComposableState<ParentState = S>>: Clone + Debug {
+    /// The delta the peer holding `state` would produce, or None if the change can't be made from
+    /// that state, eg. because the author isn't a member yet
+    fn delta(&self, state: &S, parameters: &S::Parameters) -> Option<S::Delta>;
+}
+
+#[derive(Clone, Debug)]
+pub enum Step<M> {
+    Mutate { peer: usize, mutation: M },
+    Sync { from: usize, to: usize },
+}
+
+/// Generates up to `max_steps` steps for `peers` peers, roughly a third of them synchronizations
+pub fn steps<M: Clone + Debug>(
+    peers: usize,
+    mutation: impl Strategy<Value = M>,
+    max_steps: usize,
+) -> impl Strategy<Value = Vec<Step<M>>> {
+    assert!(peers > 0, "There must be at least one peer");
+    let step = prop_oneof![
+        2 => (0..peers, mutation).prop_map(|(peer, mutation)| Step::Mutate { peer, mutation }),
+        1 => (0..peers, 0..peers).prop_map(|(from, to)| Step::Sync { from, to }),
+    ];
+    prop::collection::vec(step, 0..=max_steps)
+}
+
+/// Runs `steps` starting with every peer holding `initial`, checking [`check_merge`] before each
+/// synchronization and [`check_convergence`] at the end. Returns the final state of every peer.
+pub fn run_steps<S, M>(
+    initial: &S,
+    parameters: &S::Parameters,
+    peers: usize,
+    steps: &[Step<M>],
+) -> Result<Vec<S>, String>
+where
+    S: ComposableState<ParentState = S> + Clone + PartialEq + Debug,
+    M: Mutation<S>,
+{
+    let mut states = vec![initial.clone(); peers];
+    for (index, step) in steps.iter().enumerate() {
+        match step {
+            Step::Mutate { peer, mutation } => {
+                let state = &mut states[*peer];
+                if let Some(delta) = mutation.delta(state, parameters) {
+                    let parent_state = state.clone();
+                    state
+                        .apply_delta(&parent_state, parameters, &Some(delta))
+                        .map_err(|e| {
+                            format!(
+                                "Step {}: peer {} rejected its own {:?}: {}",
+                                index, peer, mutation, e
+                            )
+                        })?;
+                    state.verify(state, parameters).map_err(|e| {
+                        format!(
+                            "Step {}: {:?} left peer {} invalid: {}",
+                            index, mutation, peer, e
+                        )
+                    })?;
+                }
+            }
+            Step::Sync { from, to } => {
+                check_merge(&states[*to], &states[*from], parameters).map_err(|e| {
+                    format!("Step {}: syncing peer {} into {}: {}", index, from, to, e)
+                })?;
+                states[*to] = merged(&states[*to], &states[*from], parameters)?;
+            }
+        }
+    }
+    check_convergence(&states, parameters)?;
+    Ok(states)
+}
+
+/// Checks that merging `a` and `b` succeeds in both directions with the same valid result, that
+/// merging again changes nothing and that the delta of the merged state against `a`'s summary
+/// takes `a` to the merged state.
+pub fn check_merge<S>(a: &S, b: &S, parameters: &S::Parameters) -> Result<(), String>
+where
+    S: ComposableState<ParentState = S> + Clone + PartialEq + Debug,
+{
+    let ab = merged(a, b, parameters)?;
+    let ba = merged(b, a, parameters)?;
+    ab.verify(&ab, parameters)
+        .map_err(|e| format!("Merged state doesn't verify: {}\n{:#?}", e, ab))?;
+    if ab != ba {
+        return Err(format!(
+            "Merge isn't commutative:\n{:#?}\n!=\n{:#?}",
+            ab, ba
+        ));
+    }
+    for other in [&ab, a, b] {
+        if merged(&ab, other, parameters)? != ab {
+            return Err(format!(
+                "Merge isn't idempotent, merging {:#?} changed the state",
+                other
+            ));
+        }
+    }
+
+    let summary = a.summarize(a, parameters);
+    let delta = ab.delta(&ab, parameters, &summary);
+    let mut applied = a.clone();
+    applied
+        .apply_delta(a, parameters, &delta)
+        .map_err(|e| format!("Applying the delta failed: {}", e))?;
+    if applied != ab {
+        return Err(format!(
+            "Applying the delta {:#?} doesn't match the merge:\n{:#?}\n!=\n{:#?}",
+            delta, applied, ab
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that a peer which keeps merging all `states`, until that changes nothing, ends up with
+/// the same valid state in forward and reverse order. Merging once may not be enough: an entry
+/// evicted under a limit that a later configuration raises again only comes back from a peer
+/// that still holds it, as it would on the next synchronization.
+pub fn check_convergence<S>(states: &[S], parameters: &S::Parameters) -> Result<(), String>
+where
+    S: ComposableState<ParentState = S> + Clone + PartialEq + Debug,
+{
+    let Some(first) = states.first() else {
+        return Ok(());
+    };
+    let forward = settled(first, states.iter(), parameters)?;
+    let backward = settled(
+        states.last().expect("states isn't empty"),
+        states.iter().rev(),
+        parameters,
+    )?;
+    forward
+        .verify(&forward, parameters)
+        .map_err(|e| format!("Converged state doesn't verify: {}", e))?;
+    if forward != backward {
+        return Err(format!(
+            "Peers didn't converge:\n{:#?}\n!=\n{:#?}",
+            forward, backward
+        ));
+    }
+    Ok(())
+}
+
+/// Merges `others` into `state` in turn, again and again until a round changes nothing. Merging a
+/// state into itself changes nothing, so `others` may include it.
+fn settled<'a, S>(
+    state: &S,
+    others: impl Iterator<Item = &'a S> + Clone,
+    parameters: &S::Parameters,
+) -> Result<S, String>
+where
+    S: ComposableState<ParentState = S> + Clone + PartialEq + Debug + 'a,
+{
+    let rounds = others.clone().count() + 2;
+    let mut state = state.clone();
+    for _ in 0..rounds {
+        let next = others.clone().try_fold(state.clone(), |state, other| {
+            merged(&state, other, parameters)
+        })?;
+        if next == state {
+            return Ok(state);
+        }
+        state = next;
+    }
+    Err(format!(
+        "Merging didn't settle after {} rounds:\n{:#?}",
+        rounds, state
+    ))
+}
+
+fn merged<S>(state: &S, other: &S, parameters: &S::Parameters) -> Result<S, String>
+where
+    S: ComposableState<ParentState = S> + Clone,
+{
+    let mut merged = state.clone();
+    merged
+        .merge(state, parameters, other)
+        .map_err(|e| format!("Merge failed: {}", e))?;
+    Ok(merged)
+}
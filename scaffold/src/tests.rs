@@ -1,6 +1,9 @@
 use super::*;
 use serde::Deserialize;
-use crate::ComposableState;
+use std::marker::PhantomData;
+
+mod composable_enum;
+mod composable_tuple;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct ContractualI32(pub i32);
@@ -16,6 +19,7 @@ impl ComposableState for ContractualI32 {
     type Summary = i32;
     type Delta = i32;
     type Parameters = TestStructParameters;
+    type Error = String;
 
     fn verify(
         &self,
@@ -63,6 +67,7 @@ impl ComposableState for ContractualString {
     type Summary = String;
     type Delta = String;
     type Parameters = TestStructParameters;
+    type Error = String;
 
     fn verify(
         &self,
@@ -146,3 +151,62 @@ fn test_contractual_macro() {
     assert!(test_struct.apply_delta(&test_struct.clone(), &parameters, &delta).is_ok());
     assert_eq!(test_struct, new_state);
 }
+
+/// Grows to the largest value seen, usable as a field of any parent state
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct MaxI32<P>(pub i32, #[serde(skip)] PhantomData<P>);
+
+impl<P> MaxI32<P> {
+    pub fn new(value: i32) -> Self {
+        MaxI32(value, PhantomData)
+    }
+}
+
+impl<P: Serialize + serde::de::DeserializeOwned + Clone + Debug> ComposableState for MaxI32<P> {
+    type ParentState = P;
+    type Summary = i32;
+    type Delta = i32;
+    type Parameters = TestStructParameters;
+    type Error = String;
+
+    fn verify(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Result<(), String> {
+        if self.0 < 0 {
+            Err("Negative value".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        self.0
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        (self.0 > *old_state_summary).then_some(self.0)
+    }
+
+    fn apply_delta(
+        &mut self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), String> {
+        if let Some(delta) = delta {
+            self.0 = self.0.max(*delta);
+        }
+        Ok(())
+    }
+}
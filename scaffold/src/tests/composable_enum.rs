@@ -0,0 +1,127 @@
+use super::{MaxI32, TestStructParameters};
+use crate::composable;
+use serde::{Deserialize, Serialize};
+
+mod version_one {
+    use super::*;
+
+    #[composable]
+    #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+    pub struct VersionOne {
+        pub count: MaxI32<VersionOne>,
+    }
+}
+
+mod version_two {
+    use super::*;
+
+    #[composable]
+    #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+    pub struct VersionTwo(pub MaxI32<VersionTwo>, pub MaxI32<VersionTwo>);
+
+    impl From<VersionOne> for VersionTwo {
+        fn from(v1: VersionOne) -> Self {
+            VersionTwo(MaxI32::new(v1.count.0), MaxI32::new(0))
+        }
+    }
+}
+
+use version_one::VersionOne;
+use version_two::VersionTwo;
+
+#[composable]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub enum Versioned {
+    Empty,
+    V1(VersionOne),
+    V2(VersionTwo),
+}
+
+fn v1(count: i32) -> Versioned {
+    Versioned::V1(VersionOne {
+        count: MaxI32::new(count),
+    })
+}
+
+fn v2(first: i32, second: i32) -> Versioned {
+    Versioned::V2(VersionTwo(MaxI32::new(first), MaxI32::new(second)))
+}
+
+#[test]
+fn test_same_variant_delta() {
+    let parameters = TestStructParameters;
+    let old_state = v1(1);
+    let new_state = v1(2);
+
+    let summary = old_state.summarize(&old_state, &parameters);
+    assert!(matches!(summary, VersionedSummary::V1(_)));
+
+    let delta = new_state.delta(&new_state, &parameters, &summary);
+    assert!(matches!(delta, Some(VersionedDelta::V1(_))));
+
+    let mut state = old_state.clone();
+    state.apply_delta(&old_state, &parameters, &delta).unwrap();
+    assert_eq!(state, new_state);
+}
+
+#[test]
+fn test_later_variant_merges_earlier() {
+    let parameters = TestStructParameters;
+    let old_state = v1(5);
+    let new_state = v2(1, 2);
+
+    let delta = new_state.delta(&new_state, &parameters, &old_state.summarize(&old_state, &parameters));
+    assert!(matches!(delta, Some(VersionedDelta::Replace(_))));
+
+    // The earlier state is upgraded and merged, keeping its count
+    let mut state = old_state.clone();
+    state.apply_delta(&old_state, &parameters, &delta).unwrap();
+    assert_eq!(state, v2(5, 2));
+
+    // The earlier variant sends what its upgrade adds to the later one
+    let delta = old_state.delta(&old_state, &parameters, &new_state.summarize(&new_state, &parameters));
+    assert!(matches!(delta, Some(VersionedDelta::V2(_))));
+    let mut state = new_state.clone();
+    state.apply_delta(&new_state, &parameters, &delta).unwrap();
+    assert_eq!(state, v2(5, 2));
+
+    // Nothing to send if the upgrade adds nothing
+    assert_eq!(v1(1).delta(&v1(1), &parameters, &new_state.summarize(&new_state, &parameters)), None);
+}
+
+#[test]
+fn test_merge_is_commutative_across_variants() {
+    let parameters = TestStructParameters;
+    let states = [Versioned::Empty, v1(3), v1(4), v2(1, 9), v2(7, 0)];
+
+    for a in &states {
+        for b in &states {
+            let mut ab = a.clone();
+            ab.merge(&a.clone(), &parameters, b).unwrap();
+            let mut ba = b.clone();
+            ba.merge(&b.clone(), &parameters, a).unwrap();
+            assert_eq!(ab, ba, "merge of {:?} and {:?}", a, b);
+        }
+    }
+}
+
+#[test]
+fn test_variant_mismatch_rejected() {
+    let parameters = TestStructParameters;
+
+    // A same-variant delta can't be applied to a different variant
+    let delta = v1(2).delta(&v1(2), &parameters, &v1(1).summarize(&v1(1), &parameters));
+    let mut state = v2(1, 1);
+    assert!(state.apply_delta(&v2(1, 1), &parameters, &delta).is_err());
+
+    // Replacing with an earlier variant is a downgrade
+    let downgrade = Some(VersionedDelta::Replace(Box::new(v1(9))));
+    assert!(state.apply_delta(&v2(1, 1), &parameters, &downgrade).is_err());
+    assert_eq!(state, v2(1, 1));
+
+    // Replacement states are verified before being accepted
+    let invalid = Some(VersionedDelta::Replace(Box::new(v2(-1, 0))));
+    let mut state = v1(1);
+    assert!(state.apply_delta(&v1(1), &parameters, &invalid).is_err());
+    assert_eq!(state, v1(1));
+}
@@ -0,0 +1,40 @@
+use super::{MaxI32, TestStructParameters};
+use crate::composable;
+use serde::{Deserialize, Serialize};
+
+#[composable]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct TestTuple(MaxI32<TestTuple>, MaxI32<TestTuple>);
+
+impl TestTuple {
+    fn new(first: i32, second: i32) -> Self {
+        TestTuple(MaxI32::new(first), MaxI32::new(second))
+    }
+}
+
+#[test]
+fn test_tuple_struct_summary_and_delta() {
+    let parameters = TestStructParameters;
+    let old_state = TestTuple::new(1, 5);
+    let new_state = TestTuple::new(3, 5);
+
+    let summary = old_state.summarize(&old_state, &parameters);
+    assert_eq!(summary, TestTupleSummary(1, 5));
+
+    let delta = new_state.delta(&new_state, &parameters, &summary);
+    assert_eq!(delta, Some(TestTupleDelta(Some(3), None)));
+
+    assert_eq!(new_state.delta(&new_state, &parameters, &new_state.summarize(&new_state, &parameters)), None);
+}
+
+#[test]
+fn test_tuple_struct_merge() {
+    let parameters = TestStructParameters;
+    let mut a = TestTuple::new(1, 7);
+    let b = TestTuple::new(4, 2);
+
+    a.merge(&a.clone(), &parameters, &b).unwrap();
+    assert_eq!(a, TestTuple::new(4, 7));
+    assert!(a.verify(&a, &parameters).is_ok());
+    assert!(TestTuple::new(-1, 0).verify(&a, &parameters).is_err());
+}
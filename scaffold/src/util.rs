@@ -1,5 +1,9 @@
-use serde::{Deserialize, Serialize};
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
 
+/// Legacy 31-multiplier polynomial hash, NOT collision resistant. Kept so that ids in states
+/// serialized before the switch to [`VersionedHash`] can still be recognised and migrated.
 pub fn fast_hash(bytes: &[u8]) -> FastHash {
     let mut hash: i64 = 0;
     for &byte in bytes {
@@ -10,3 +14,164 @@ pub fn fast_hash(bytes: &[u8]) -> FastHash {
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Hash, Clone, Debug, Ord, PartialOrd, Copy)]
 pub struct FastHash(pub i64);
+
+/// Version byte prepended to blake3 hashes when serialized
+const BLAKE3_V1: u8 = 1;
+
+/// Hashes `bytes` with blake3 in key derivation mode, `context` separates the domains of
+/// different id types so that eg. a message id can never equal a member id.
+pub fn blake3_hash(context: &str, bytes: &[u8]) -> VersionedHash {
+    let mut hasher = blake3::Hasher::new_derive_key(context);
+    hasher.update(bytes);
+    VersionedHash::Blake3V1(*hasher.finalize().as_bytes())
+}
+
+/// A collision-resistant identifier which records the scheme that produced it.
+///
+/// Serializes as a byte string of a version byte followed by the hash, except for
+/// [`VersionedHash::Legacy`] which keeps the plain integer encoding of [`FastHash`] so that
+/// old states still deserialize.
+#[derive(Eq, PartialEq, Hash, Clone, Ord, PartialOrd, Copy)]
+pub enum VersionedHash {
+    /// Only ever produced by deserializing old states, never equal to a freshly computed hash
+    Legacy(FastHash),
+    Blake3V1([u8; 32]),
+}
+
+impl VersionedHash {
+    pub fn is_legacy(&self) -> bool {
+        matches!(self, VersionedHash::Legacy(_))
+    }
+
+    /// Raw hash bytes without the version, legacy hashes are little-endian encoded
+    pub fn as_bytes(&self) -> Vec<u8> {
+        match self {
+            VersionedHash::Legacy(hash) => hash.0.to_le_bytes().to_vec(),
+            VersionedHash::Blake3V1(bytes) => bytes.to_vec(),
+        }
+    }
+}
+
+impl fmt::Debug for VersionedHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionedHash::Legacy(hash) => write!(f, "Legacy({})", hash.0),
+            VersionedHash::Blake3V1(bytes) => {
+                write!(f, "Blake3V1(")?;
+                for byte in &bytes[..8] {
+                    write!(f, "{:02x}", byte)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl Serialize for VersionedHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            VersionedHash::Legacy(hash) => serializer.serialize_i64(hash.0),
+            VersionedHash::Blake3V1(bytes) => {
+                let mut encoded = [0u8; 33];
+                encoded[0] = BLAKE3_V1;
+                encoded[1..].copy_from_slice(bytes);
+                serializer.serialize_bytes(&encoded)
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for VersionedHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(VersionedHashVisitor)
+    }
+}
+
+struct VersionedHashVisitor;
+
+impl<'de> Visitor<'de> for VersionedHashVisitor {
+    type Value = VersionedHash;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a legacy integer hash or a versioned hash byte string")
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(VersionedHash::Legacy(FastHash(v)))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        i64::try_from(v)
+            .map(|v| VersionedHash::Legacy(FastHash(v)))
+            .map_err(|_| E::custom("legacy hash out of range"))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        match v.split_first() {
+            Some((&BLAKE3_V1, hash)) => hash
+                .try_into()
+                .map(VersionedHash::Blake3V1)
+                .map_err(|_| E::invalid_length(v.len(), &"33 bytes")),
+            Some((version, _)) => Err(E::custom(format!("unknown hash version {}", version))),
+            None => Err(E::invalid_length(0, &self)),
+        }
+    }
+
+    // Formats without a native byte string type, such as JSON, encode bytes as a sequence
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = Vec::with_capacity(33);
+        while let Some(byte) = seq.next_element::<u8>()? {
+            bytes.push(byte);
+        }
+        self.visit_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(hash: &VersionedHash) -> VersionedHash {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(hash, &mut bytes).unwrap();
+        ciborium::de::from_reader(bytes.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_fast_hash_collisions_are_trivial() {
+        // Decreasing one byte by one and increasing the next by 31 cancels out
+        let a = [0u8, 40, 0, 0];
+        let b = [1u8, 9, 0, 0];
+        assert_eq!(fast_hash(&a), fast_hash(&b));
+        assert_ne!(blake3_hash("test", &a), blake3_hash("test", &b));
+    }
+
+    #[test]
+    fn test_context_separates_domains() {
+        assert_ne!(blake3_hash("one", b"data"), blake3_hash("two", b"data"));
+    }
+
+    #[test]
+    fn test_serialization_round_trip() {
+        let hash = blake3_hash("test", b"data");
+        assert_eq!(round_trip(&hash), hash);
+        let legacy = VersionedHash::Legacy(fast_hash(b"data"));
+        assert_eq!(round_trip(&legacy), legacy);
+    }
+
+    #[test]
+    fn test_deserialize_legacy_fast_hash() {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&fast_hash(b"data"), &mut bytes).unwrap();
+        let hash: VersionedHash = ciborium::de::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(hash, VersionedHash::Legacy(fast_hash(b"data")));
+        assert!(hash.is_legacy());
+    }
+
+    #[test]
+    fn test_reject_unknown_version() {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&ciborium::Value::Bytes(vec![2; 33]), &mut bytes).unwrap();
+        assert!(ciborium::de::from_reader::<VersionedHash, _>(bytes.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,10 @@
+use freenet_scaffold::composable;
+
+#[composable]
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Versioned {
+    V1(u32),
+    Retired,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use freenet_scaffold::composable;
+
+#[composable]
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Versioned {
+    Empty,
+}
+
+fn main() {}
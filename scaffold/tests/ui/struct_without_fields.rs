@@ -0,0 +1,7 @@
+use freenet_scaffold::composable;
+
+#[composable]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Empty;
+
+fn main() {}
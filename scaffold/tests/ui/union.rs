@@ -0,0 +1,9 @@
+use freenet_scaffold::composable;
+
+#[composable]
+pub union NotAState {
+    a: u32,
+    b: f32,
+}
+
+fn main() {}
@@ -1,26 +1,61 @@
+#[cfg(not(feature = "no-sync"))]
+mod chat_delegate;
+#[cfg(not(feature = "no-sync"))]
 mod freenet_api;
+mod keyring_modal;
+mod river;
+mod unlock_modal;
 
 use super::{conversation::Conversation, members::MemberList, room_list::RoomList};
 use crate::components::members::member_info_modal::MemberInfoModal;
 use crate::components::room_list::edit_room_modal::EditRoomModal;
+use crate::components::room_list::join_room_modal::JoinRoomModal;
 use crate::room_data::{CurrentRoom, Rooms};
-use common::room_state::member::MemberId;
+use crate::storage::{self, KeyringKey};
+use common::room_state::member::{InvitationToken, MemberId};
 use dioxus::prelude::*;
 use document::Stylesheet;
 use ed25519_dalek::VerifyingKey;
-use crate::components::app::freenet_api::FreenetApiSynchronizer;
+use futures::FutureExt;
+use keyring_modal::KeyringModal;
+use river_client::Keyring;
+use unlock_modal::UnlockModal;
+
+pub use river::River;
 
 pub fn App() -> Element {
-    use_context_provider(|| Signal::new(initial_rooms()));
-    use_context_provider(|| Signal::new(CurrentRoom { owner_key: None }));
+    let (initial_rooms, keys) = use_hook(initial_rooms);
+    let rooms = use_context_provider(|| Signal::new(initial_rooms));
+    let keyring = use_context_provider(|| {
+        Signal::new(KeyringSignal {
+            key: None,
+            // Example rooms are made up on every start, there's nothing to keep
+            show_unlock: cfg!(feature = "no-sync") && !cfg!(feature = "example-data"),
+        })
+    });
+    use_context_provider(|| Signal::new(KeyringModalSignal { show: false }));
+    let current_room = use_context_provider(|| Signal::new(CurrentRoom { owner_key: None }));
     use_context_provider(|| Signal::new(MemberInfoModalSignal { member: None }));
     use_context_provider(|| Signal::new(EditRoomModalSignal { room: None }));
     use_context_provider(|| Signal::new(CreateRoomModalSignal { show: false }));
+    use_context_provider(|| {
+        Signal::new(JoinRoomModalSignal {
+            invitation: invitation_from_location(),
+        })
+    });
 
-    #[cfg(not(feature = "no-sync"))]
-    {
-        FreenetApiSynchronizer::start();
-    }
+    let river = River::start(rooms, keys, current_room);
+    use_context_provider(|| river);
+
+    // Saves the rooms whenever they change
+    use_effect(move || save_rooms(&rooms.read(), &keyring.read()));
+    // Without a node our keys are kept in the page, and saved along with the rooms once unlocked
+    use_effect(move || {
+        let _ = rooms.read();
+        if let Some(key) = keyring.read().key.clone() {
+            spawn(save_keyring(river, key));
+        }
+    });
 
     rsx! {
         Stylesheet { href: asset!("./assets/bulma.min.css") }
@@ -33,22 +68,72 @@ pub fn App() -> Element {
         }
         EditRoomModal {}
         MemberInfoModal {}
-
+        JoinRoomModal {}
+        UnlockModal {}
+        KeyringModal {}
     }
 }
 
+/// The rooms we had, and keys for the key store. Our keys in stored rooms are already in the chat
+/// delegate. Without a node, the stored rooms are restored once the user unlocks our keys.
 #[cfg(not(feature = "example-data"))]
-fn initial_rooms() -> Rooms {
-    Rooms {
-        map: std::collections::HashMap::new(),
-    }
+fn initial_rooms() -> (Rooms, Keyring) {
+    let rooms = if cfg!(feature = "no-sync") {
+        Ok(Rooms::default())
+    } else {
+        storage::default_storage().and_then(|storage| storage::load_rooms(&*storage))
+    };
+    let rooms = rooms.unwrap_or_else(|e| {
+        log::error!("Failed to load rooms: {}", e);
+        Rooms::default()
+    });
+    (rooms, Keyring::default())
 }
 
 #[cfg(feature = "example-data")]
-fn initial_rooms() -> Rooms {
+fn initial_rooms() -> (Rooms, Keyring) {
     crate::example_data::create_example_rooms()
 }
 
+fn save_rooms(rooms: &Rooms, keyring: &KeyringSignal) {
+    // Example rooms are made up on every start, and without a node the stored rooms aren't back
+    // until our keys are unlocked
+    if cfg!(feature = "example-data") || (cfg!(feature = "no-sync") && keyring.key.is_none()) {
+        return;
+    }
+    if let Err(e) =
+        storage::default_storage().and_then(|storage| storage::save_rooms(&*storage, rooms))
+    {
+        log::error!("Failed to save rooms: {}", e);
+    }
+}
+
+async fn save_keyring(river: River, key: KeyringKey) {
+    let exported = river
+        .run(|client| async move { client.export_keyring(&key).await }.boxed_local())
+        .await;
+    let saved = match exported {
+        Ok(keyring) => storage::default_storage()
+            .and_then(|storage| storage::save_keyring(&*storage, &keyring))
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = saved {
+        log::error!("Failed to save keys: {}", e);
+    }
+}
+
+/// The key our keys are saved with without a node. Nothing is saved until the user unlocks the
+/// stored keyring or chooses a passphrase for a new one.
+pub struct KeyringSignal {
+    pub key: Option<KeyringKey>,
+    pub show_unlock: bool,
+}
+
+pub struct KeyringModalSignal {
+    pub show: bool,
+}
+
 pub struct EditRoomModalSignal {
     pub room: Option<VerifyingKey>,
 }
@@ -57,6 +142,17 @@ pub struct CreateRoomModalSignal {
     pub show: bool,
 }
 
+/// An invitation the user opened a link to and hasn't accepted or dismissed yet
+pub struct JoinRoomModalSignal {
+    pub invitation: Option<InvitationToken>,
+}
+
+/// Invite links carry the invitation in the fragment so it never reaches a server
+fn invitation_from_location() -> Option<InvitationToken> {
+    let hash = web_sys::window()?.location().hash().ok()?;
+    InvitationToken::decode(&hash)
+}
+
 pub struct MemberInfoModalSignal {
     pub member: Option<MemberId>,
 }
@@ -0,0 +1,217 @@
+//! The chat delegate as the `KeyStore` of the UI's `RiverClient`. Our signing keys are kept in the
+//! delegate's secret storage on the node and signed with there, the UI only ever holds our
+//! verifying keys. The delegate also keeps the rooms we're in, which the client fetches if they
+//! aren't stored in the page, and only signs what we do in them. The delegate only answers the
+//! UI's contract, which the node attests for us.
+
+use super::freenet_api::{self, Responses};
+use crate::constants::CHAT_DELEGATE_WASM;
+use crate::util::to_cbor_vec;
+use common::chat_delegate::{ChatDelegateRequestMsg, ChatDelegateResponseMsg, SignRequest};
+use common::keyring::{EncryptedKeyring, KeyringKey};
+use common::ChatRoomStateV1;
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use freenet_stdlib::client_api::{DelegateRequest, HostResponse, WebApi};
+use freenet_stdlib::prelude::{
+    ApplicationMessage, ContractInstanceId, Delegate, DelegateCode, DelegateContainer, DelegateKey,
+    DelegateWasmAPIVersion, InboundDelegateMsg, OutboundDelegateMsg, Parameters,
+};
+use futures::StreamExt;
+use river_client::{KeyStore, KeyStoreError};
+use std::collections::HashMap;
+
+pub struct DelegateKeyStore {
+    api: WebApi,
+    responses: Responses,
+    /// The UI's contract, which the delegate is registered for
+    app: ContractInstanceId,
+    key: DelegateKey,
+    params: Parameters<'static>,
+}
+
+impl DelegateKeyStore {
+    /// Connects to the node and registers the delegate for the UI's contract, which is kept if it
+    /// was registered before along with its secrets
+    pub async fn connect() -> Result<Self, KeyStoreError> {
+        let app = ui_contract()?;
+        let (mut api, mut responses) = freenet_api::open().await.map_err(unavailable)?;
+        let params = Parameters::from(app.as_bytes().to_vec());
+        let delegate = Delegate::from((&DelegateCode::from(CHAT_DELEGATE_WASM), &params));
+        let key = delegate.key().clone();
+        let register = DelegateRequest::RegisterDelegate {
+            delegate: DelegateContainer::Wasm(DelegateWasmAPIVersion::V1(delegate)),
+            cipher: DelegateRequest::DEFAULT_CIPHER,
+            nonce: DelegateRequest::DEFAULT_NONCE,
+        };
+        api.send(register.into()).await.map_err(unavailable)?;
+        next_response(&mut responses).await?;
+        Ok(DelegateKeyStore {
+            api,
+            responses,
+            app,
+            key,
+            params,
+        })
+    }
+
+    /// Sends `request` to the delegate and waits for its reply
+    async fn request(
+        &mut self,
+        request: ChatDelegateRequestMsg,
+    ) -> Result<ChatDelegateResponseMsg, KeyStoreError> {
+        let message = ApplicationMessage::new(self.app, to_cbor_vec(&request));
+        let request = DelegateRequest::ApplicationMessages {
+            key: self.key.clone(),
+            params: self.params.clone(),
+            inbound: vec![InboundDelegateMsg::ApplicationMessage(message)],
+        };
+        self.api.send(request.into()).await.map_err(unavailable)?;
+        loop {
+            let HostResponse::DelegateResponse { values, .. } =
+                next_response(&mut self.responses).await?
+            else {
+                continue;
+            };
+            let reply = values.into_iter().find_map(|value| match value {
+                OutboundDelegateMsg::ApplicationMessage(message) => Some(message.payload),
+                _ => None,
+            });
+            if let Some(payload) = reply {
+                return match ciborium::de::from_reader(payload.as_slice()) {
+                    Ok(ChatDelegateResponseMsg::Error(e)) => Err(e.into()),
+                    Ok(response) => Ok(response),
+                    Err(e) => Err(unavailable(e)),
+                };
+            }
+        }
+    }
+}
+
+impl KeyStore for DelegateKeyStore {
+    async fn store(&mut self, self_sk: SigningKey) -> Result<(), KeyStoreError> {
+        match self
+            .request(ChatDelegateRequestMsg::StoreKey { self_sk })
+            .await?
+        {
+            ChatDelegateResponseMsg::KeyStored { .. } => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn sign(
+        &mut self,
+        self_vk: &VerifyingKey,
+        request: SignRequest,
+    ) -> Result<Signature, KeyStoreError> {
+        let request = ChatDelegateRequestMsg::Sign {
+            self_vk: *self_vk,
+            request,
+        };
+        match self.request(request).await? {
+            ChatDelegateResponseMsg::Signed { signature, .. } => Ok(signature),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn sign_succession(
+        &mut self,
+        self_vk: &VerifyingKey,
+        owner_vk: &VerifyingKey,
+        room_state: &ChatRoomStateV1,
+    ) -> Result<Vec<Signature>, KeyStoreError> {
+        let request = ChatDelegateRequestMsg::SignSuccession {
+            self_vk: *self_vk,
+            owner_vk: *owner_vk,
+            room_state: Box::new(room_state.clone()),
+        };
+        match self.request(request).await? {
+            ChatDelegateResponseMsg::SignedSuccession { signatures, .. } => Ok(signatures),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn shared_secret(
+        &mut self,
+        self_vk: &VerifyingKey,
+        public_key: [u8; 32],
+    ) -> Result<[u8; 32], KeyStoreError> {
+        let request = ChatDelegateRequestMsg::SharedSecret {
+            self_vk: *self_vk,
+            public_key,
+        };
+        match self.request(request).await? {
+            ChatDelegateResponseMsg::SharedSecret { secret, .. } => Ok(secret),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn export(&mut self, key: &KeyringKey) -> Result<EncryptedKeyring, KeyStoreError> {
+        let request = ChatDelegateRequestMsg::ExportKeys { key: key.clone() };
+        match self.request(request).await? {
+            ChatDelegateResponseMsg::ExportedKeys(keyring) => Ok(keyring),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn store_room(
+        &mut self,
+        owner_vk: &VerifyingKey,
+        self_vk: &VerifyingKey,
+    ) -> Result<(), KeyStoreError> {
+        let request = ChatDelegateRequestMsg::StoreRoom {
+            owner_vk: *owner_vk,
+            self_vk: *self_vk,
+        };
+        match self.request(request).await? {
+            ChatDelegateResponseMsg::RoomStored { .. } => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn remove_room(&mut self, owner_vk: &VerifyingKey) -> Result<(), KeyStoreError> {
+        let request = ChatDelegateRequestMsg::RemoveRoom {
+            owner_vk: *owner_vk,
+        };
+        match self.request(request).await? {
+            ChatDelegateResponseMsg::RoomRemoved { .. } => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn rooms(&mut self) -> Result<HashMap<VerifyingKey, VerifyingKey>, KeyStoreError> {
+        match self.request(ChatDelegateRequestMsg::ListRooms).await? {
+            ChatDelegateResponseMsg::Rooms(rooms) => Ok(rooms
+                .into_iter()
+                .map(|room| (room.owner_vk, room.self_vk))
+                .collect()),
+            response => Err(unexpected(response)),
+        }
+    }
+}
+
+/// The UI's contract, whose web app the node serves at `/v1/contract/web/<id>/`
+fn ui_contract() -> Result<ContractInstanceId, KeyStoreError> {
+    let path = web_sys::window()
+        .and_then(|window| window.location().pathname().ok())
+        .unwrap_or_default();
+    path.split('/')
+        .skip_while(|segment| *segment != "web")
+        .nth(1)
+        .and_then(|id| ContractInstanceId::try_from(id.to_string()).ok())
+        .ok_or_else(|| KeyStoreError::Unavailable(format!("Not served by a node: {}", path)))
+}
+
+async fn next_response(responses: &mut Responses) -> Result<HostResponse, KeyStoreError> {
+    match responses.next().await {
+        Some(response) => response.map_err(unavailable),
+        None => Err(KeyStoreError::Unavailable("Disconnected".to_string())),
+    }
+}
+
+fn unavailable(error: impl ToString) -> KeyStoreError {
+    KeyStoreError::Unavailable(error.to_string())
+}
+
+fn unexpected(response: ChatDelegateResponseMsg) -> KeyStoreError {
+    KeyStoreError::Unavailable(format!("Unexpected response {:?}", response))
+}
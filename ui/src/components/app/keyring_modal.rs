@@ -0,0 +1,219 @@
+use crate::components::app::{KeyringModalSignal, River};
+use crate::storage::{IdentityBundle, KeyringKey};
+use dioxus::prelude::*;
+use futures::FutureExt;
+use wasm_bindgen::JsCast;
+
+/// Exports our keys in every room as an identity bundle, and imports a bundle exported on another
+/// device. Rooms of imported keys are fetched from the network.
+#[component]
+pub fn KeyringModal() -> Element {
+    let river = use_context::<River>();
+    let mut keyring_modal_signal = use_context::<Signal<KeyringModalSignal>>();
+
+    let mut export_passphrase = use_signal(String::new);
+    let mut exported = use_signal(|| None::<String>);
+    let mut import_text = use_signal(String::new);
+    let mut import_passphrase = use_signal(String::new);
+    let mut error_message = use_signal(String::new);
+    let mut info_message = use_signal(String::new);
+    let mut copy_button_text = use_signal(|| "Copy".to_string());
+
+    let show = keyring_modal_signal.read().show;
+
+    let mut close = move || {
+        keyring_modal_signal.write().show = false;
+        export_passphrase.set(String::new());
+        exported.set(None);
+        import_text.set(String::new());
+        import_passphrase.set(String::new());
+        error_message.set(String::new());
+        info_message.set(String::new());
+        copy_button_text.set("Copy".to_string());
+    };
+
+    let export = move |_| {
+        error_message.set(String::new());
+        info_message.set(String::new());
+        let passphrase = export_passphrase.read().clone();
+        if passphrase.is_empty() {
+            error_message.set("Please enter a passphrase to export with".to_string());
+            return;
+        }
+        spawn(async move {
+            // The key store encrypts our keys, the UI only derives the key from the passphrase
+            let bundle = match KeyringKey::new(&passphrase) {
+                Ok(key) => river
+                    .run(|client| async move { client.export_keyring(&key).await }.boxed_local())
+                    .await
+                    .map(IdentityBundle)
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            match bundle {
+                Ok(bundle) => {
+                    exported.set(Some(bundle.encode()));
+                    export_passphrase.set(String::new());
+                    copy_button_text.set("Copy".to_string());
+                }
+                Err(e) => error_message.set(e),
+            }
+        });
+    };
+
+    let copy_exported = move |_| {
+        if let (Some(window), Some(exported)) = (web_sys::window(), &*exported.read()) {
+            if let Ok(navigator) = window.navigator().dyn_into::<web_sys::Navigator>() {
+                let _ = navigator.clipboard().write_text(exported);
+                copy_button_text.set("Copied!".to_string());
+            }
+        }
+    };
+
+    let import = move |_| {
+        error_message.set(String::new());
+        info_message.set(String::new());
+        let imported = IdentityBundle::decode(&import_text.read())
+            .and_then(|bundle| bundle.import(&import_passphrase.read()));
+        let keyring = match imported {
+            Ok(keyring) => keyring,
+            Err(e) => {
+                error_message.set(e.to_string());
+                return;
+            }
+        };
+        spawn(async move {
+            let added = river
+                .run(move |client| async move { client.add_keys(keyring).await }.boxed_local())
+                .await;
+            match added {
+                Ok(added) => {
+                    info_message.set(format!("Added {} rooms", added));
+                    import_text.set(String::new());
+                    import_passphrase.set(String::new());
+                }
+                Err(e) => error_message.set(e.to_string()),
+            }
+        });
+    };
+
+    rsx! {
+        div {
+            class: format_args!("modal {}", if show { "is-active" } else { "" }),
+            div {
+                class: "modal-background",
+                onclick: move |_| close()
+            }
+            div {
+                class: "modal-content",
+                div {
+                    class: "box",
+                    h1 { class: "title is-4 mb-3", "Keys" }
+
+                    h2 { class: "subtitle is-5 mb-2", "Export" }
+                    match &*exported.read() {
+                        Some(exported) => rsx! {
+                            p { class: "mb-2",
+                                "Your keys in all rooms, protected by the passphrase you chose. Import them on another device to use your rooms there too."
+                            }
+                            div { class: "field",
+                                div { class: "control",
+                                    textarea {
+                                        class: "textarea is-family-monospace",
+                                        readonly: true,
+                                        value: "{exported}",
+                                    }
+                                }
+                            }
+                            div { class: "field",
+                                div { class: "control",
+                                    button {
+                                        class: "button",
+                                        onclick: copy_exported,
+                                        "{copy_button_text}"
+                                    }
+                                }
+                            }
+                        },
+                        None => rsx! {
+                            div { class: "field",
+                                label { class: "label", "Passphrase to Export With" }
+                                div { class: "control",
+                                    input {
+                                        class: "input",
+                                        r#type: "password",
+                                        value: "{export_passphrase}",
+                                        oninput: move |evt| export_passphrase.set(evt.value().to_string())
+                                    }
+                                }
+                            }
+                            div { class: "field",
+                                div { class: "control",
+                                    button {
+                                        class: "button",
+                                        onclick: export,
+                                        "Export"
+                                    }
+                                }
+                            }
+                        },
+                    }
+
+                    h2 { class: "subtitle is-5 mt-4 mb-2", "Import" }
+                    div { class: "field",
+                        label { class: "label", "Exported Keys" }
+                        div { class: "control",
+                            textarea {
+                                class: "textarea is-family-monospace",
+                                value: "{import_text}",
+                                oninput: move |evt| import_text.set(evt.value().to_string())
+                            }
+                        }
+                    }
+                    div { class: "field",
+                        label { class: "label", "Passphrase They Were Exported With" }
+                        div { class: "control",
+                            input {
+                                class: "input",
+                                r#type: "password",
+                                value: "{import_passphrase}",
+                                oninput: move |evt| import_passphrase.set(evt.value().to_string())
+                            }
+                        }
+                    }
+
+                    {
+                        (!error_message.read().is_empty()).then(|| rsx!(
+                            div {
+                                class: "notification is-danger",
+                                "{error_message}"
+                            }
+                        ))
+                    }
+                    {
+                        (!info_message.read().is_empty()).then(|| rsx!(
+                            div {
+                                class: "notification is-info",
+                                "{info_message}"
+                            }
+                        ))
+                    }
+
+                    div { class: "field",
+                        div { class: "control",
+                            button {
+                                class: "button is-primary",
+                                onclick: import,
+                                "Import"
+                            }
+                        }
+                    }
+                }
+            }
+            button {
+                class: "modal-close is-large",
+                onclick: move |_| close()
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! The UI's `RiverClient`, which owns our rooms and does all that's done in them. Components
+//! render `Signal<Rooms>`, a copy of the client's rooms kept up to date as they change, and act
+//! through `River`. Our keys are kept by the chat delegate, the UI never holds them.
+
+use crate::room_data::{CurrentRoom, Rooms};
+use dioxus::prelude::*;
+use futures::channel::oneshot;
+use futures::future::LocalBoxFuture;
+use futures::{FutureExt, StreamExt};
+use river_client::network::NetworkError;
+use river_client::{ClientError, Keyring, RiverClient, RoomEvent};
+
+#[cfg(not(feature = "no-sync"))]
+type UiNetwork = super::freenet_api::WebNetwork;
+/// Room contracts run in the page, nothing leaves it
+#[cfg(feature = "no-sync")]
+type UiNetwork = river_client::network::LocalConnection;
+
+#[cfg(not(feature = "no-sync"))]
+type UiKeyStore = super::chat_delegate::DelegateKeyStore;
+/// Keys are kept in the page, and stored in the keyring the user unlocks
+#[cfg(feature = "no-sync")]
+type UiKeyStore = river_client::LocalKeyStore;
+
+pub type Client = RiverClient<UiNetwork, UiKeyStore>;
+
+type Task = Box<dyn for<'a> FnOnce(&'a mut Client) -> LocalBoxFuture<'a, ()>>;
+
+/// A handle on the UI's client, provided by `App`
+#[derive(Clone, Copy)]
+pub struct River {
+    tasks: Coroutine<Task>,
+}
+
+impl River {
+    /// Starts the client with the rooms in `rooms`, which it keeps up to date from then on, and
+    /// gives it the keys in `keyring`. The current room follows rooms that move to a new contract.
+    pub fn start(
+        rooms: Signal<Rooms>,
+        keyring: Keyring,
+        current_room: Signal<CurrentRoom>,
+    ) -> Self {
+        let tasks = use_coroutine(move |tasks| run(tasks, rooms, keyring.clone(), current_room));
+        River { tasks }
+    }
+
+    /// Does `task` with the client once it's done with what it's doing, eg.
+    /// `river.run(move |client| async move { client.leave(&room).await }.boxed_local())`
+    pub async fn run<T: 'static>(
+        self,
+        task: impl for<'a> FnOnce(&'a mut Client) -> LocalBoxFuture<'a, Result<T, ClientError>>
+            + 'static,
+    ) -> Result<T, ClientError> {
+        let (result_sender, result) = oneshot::channel();
+        self.tasks.send(Box::new(move |client| {
+            async move {
+                let _ = result_sender.send(task(client).await);
+            }
+            .boxed_local()
+        }));
+        // The client is gone if it couldn't reach the node
+        result
+            .await
+            .unwrap_or(Err(ClientError::Network(NetworkError::Disconnected)))
+    }
+}
+
+enum Next {
+    Task(Option<Task>),
+    Event(Box<Result<RoomEvent, ClientError>>),
+}
+
+async fn run(
+    mut tasks: UnboundedReceiver<Task>,
+    mut rooms: Signal<Rooms>,
+    keyring: Keyring,
+    mut current_room: Signal<CurrentRoom>,
+) {
+    let (network, key_store) = match connect().await {
+        Ok(connected) => connected,
+        Err(e) => {
+            log::error!("Failed to connect to the node: {}", e);
+            return;
+        }
+    };
+    let mut client = RiverClient::new(network, key_store, rooms.peek().clone());
+    if let Err(e) = client.add_keys(keyring).await {
+        log::error!("Failed to store keys: {}", e);
+    }
+    if let Err(e) = client.resume().await {
+        log::error!("Failed to sync rooms: {}", e);
+    }
+    loop {
+        let next = {
+            let mut event = client.next_event().boxed_local().fuse();
+            futures::select! {
+                task = tasks.next() => Next::Task(task),
+                event = event => Next::Event(Box::new(event)),
+            }
+        };
+        match next {
+            Next::Task(Some(task)) => task(&mut client).await,
+            Next::Task(None) => return,
+            Next::Event(event) => match *event {
+                Ok(RoomEvent::Moved { room, new_room }) => {
+                    if current_room.peek().owner_key == Some(room) {
+                        current_room.set(CurrentRoom {
+                            owner_key: Some(new_room),
+                        });
+                    }
+                }
+                Ok(_) => {}
+                Err(ClientError::Network(NetworkError::Disconnected)) => {
+                    log::error!("Disconnected from the node");
+                    return;
+                }
+                Err(e) => log::error!("Failed to sync rooms: {}", e),
+            },
+        }
+        if *rooms.peek() != *client.rooms() {
+            rooms.set(client.rooms().clone());
+        }
+    }
+}
+
+#[cfg(not(feature = "no-sync"))]
+async fn connect() -> Result<(UiNetwork, UiKeyStore), ClientError> {
+    Ok((UiNetwork::connect().await?, UiKeyStore::connect().await?))
+}
+
+#[cfg(feature = "no-sync")]
+async fn connect() -> Result<(UiNetwork, UiKeyStore), ClientError> {
+    let network = river_client::network::LocalNode::default().connect();
+    Ok((network, UiKeyStore::default()))
+}
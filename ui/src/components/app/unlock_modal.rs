@@ -0,0 +1,160 @@
+use crate::components::app::{KeyringSignal, River};
+use crate::storage;
+use dioxus::prelude::*;
+use futures::FutureExt;
+
+/// Asks for the passphrase of the stored keyring and loads the stored rooms along with the keys it
+/// unlocks, or for a passphrase to protect a new keyring with if none was stored. Only without a
+/// node, which otherwise keeps our keys in the chat delegate.
+#[component]
+pub fn UnlockModal() -> Element {
+    let river = use_context::<River>();
+    let mut keyring_signal = use_context::<Signal<KeyringSignal>>();
+    let has_keyring = use_hook(|| {
+        storage::default_storage()
+            .and_then(|storage| storage::has_keyring(&*storage))
+            .map_err(|e| e.to_string())
+    });
+
+    let mut passphrase = use_signal(String::new);
+    let mut confirmation = use_signal(String::new);
+    let mut error_message = use_signal(String::new);
+
+    let mut close = move || {
+        keyring_signal.write().show_unlock = false;
+        passphrase.set(String::new());
+        confirmation.set(String::new());
+        error_message.set(String::new());
+    };
+
+    let is_new = has_keyring == Ok(false);
+    let unlock = move |_| {
+        let passphrase_value = passphrase.read().clone();
+        if passphrase_value.is_empty() {
+            error_message.set("Please enter a passphrase".to_string());
+            return;
+        }
+        if is_new && *confirmation.read() != passphrase_value {
+            error_message.set("The passphrases don't match".to_string());
+            return;
+        }
+
+        let loaded = storage::default_storage().and_then(|storage| {
+            let stored_rooms = storage::load_rooms(&*storage)?;
+            let (keyring, key) = storage::load_keyring(&*storage, &passphrase_value)?;
+            Ok((stored_rooms, keyring, key))
+        });
+        match loaded {
+            // Rooms created or joined before unlocking are kept along with the stored ones. Nothing
+            // is saved until the stored rooms are back, or they'd be saved over.
+            Ok((stored_rooms, keyring, key)) => {
+                spawn(async move {
+                    let restored = river
+                        .run(move |client| {
+                            async move { client.restore(stored_rooms, keyring).await }.boxed_local()
+                        })
+                        .await;
+                    match restored {
+                        Ok(()) => {
+                            keyring_signal.write().key = Some(key);
+                            close();
+                        }
+                        Err(e) => error_message.set(e.to_string()),
+                    }
+                });
+            }
+            Err(e) => error_message.set(e.to_string()),
+        }
+    };
+
+    rsx! {
+        div {
+            class: format_args!("modal {}", if keyring_signal.read().show_unlock { "is-active" } else { "" }),
+            div {
+                class: "modal-background",
+                onclick: move |_| close()
+            }
+            div {
+                class: "modal-content",
+                div {
+                    class: "box",
+                    match &has_keyring {
+                        Err(e) => rsx! {
+                            h1 { class: "title is-4 mb-3", "Rooms Won't Be Kept" }
+                            p { class: "mb-3", "Rooms can't be stored on this device: {e}" }
+                        },
+                        Ok(true) => rsx! {
+                            h1 { class: "title is-4 mb-3", "Unlock Your Rooms" }
+                            p { class: "mb-3", "Enter the passphrase your rooms and keys are stored with." }
+                        },
+                        Ok(false) => rsx! {
+                            h1 { class: "title is-4 mb-3", "Keep Your Rooms" }
+                            p { class: "mb-3",
+                                "Choose a passphrase to keep your rooms on this device. Your keys are stored encrypted with it, and there's no way to recover them if it's lost."
+                            }
+                        },
+                    }
+
+                    if has_keyring.is_ok() {
+                        div { class: "field",
+                            label { class: "label", "Passphrase" }
+                            div { class: "control",
+                                input {
+                                    class: "input",
+                                    r#type: "password",
+                                    value: "{passphrase}",
+                                    oninput: move |evt| passphrase.set(evt.value().to_string())
+                                }
+                            }
+                        }
+                    }
+                    if is_new {
+                        div { class: "field",
+                            label { class: "label", "Confirm Passphrase" }
+                            div { class: "control",
+                                input {
+                                    class: "input",
+                                    r#type: "password",
+                                    value: "{confirmation}",
+                                    oninput: move |evt| confirmation.set(evt.value().to_string())
+                                }
+                            }
+                        }
+                    }
+
+                    {
+                        (!error_message.read().is_empty()).then(|| rsx!(
+                            div {
+                                class: "notification is-danger",
+                                "{error_message}"
+                            }
+                        ))
+                    }
+
+                    div { class: "field is-grouped",
+                        if has_keyring.is_ok() {
+                            div { class: "control",
+                                button {
+                                    class: "button is-primary",
+                                    onclick: unlock,
+                                    if is_new { "Keep Rooms" } else { "Unlock" }
+                                }
+                            }
+                        }
+                        div { class: "control",
+                            button {
+                                class: "button is-light",
+                                onclick: move |_| close(),
+                                "Not Now"
+                            }
+                        }
+                    }
+                }
+            }
+            button {
+                class: "modal-close is-large",
+                onclick: move |_| close()
+            }
+        }
+    }
+}
@@ -1,6 +1,5 @@
-use crate::components::app::EditRoomModalSignal;
-use crate::room_data::{CurrentRoom, Rooms, SendMessageError};
-use crate::util::get_current_system_time;
+use crate::components::app::{EditRoomModalSignal, River};
+use crate::room_data::{CurrentRoom, OpenContentError, RoomData, Rooms, SendMessageError};
 mod message_input;
 mod not_member_notification;
 use self::not_member_notification::NotMemberNotification;
@@ -8,18 +7,21 @@ use crate::components::conversation::message_input::MessageInput;
 use chrono::{DateTime, Utc};
 use common::room_state::member::MemberId;
 use common::room_state::member_info::MemberInfoV1;
-use common::room_state::message::{AuthorizedMessageV1, MessageV1};
-use common::room_state::{ChatRoomParametersV1, ChatRoomStateV1Delta};
+use common::room_state::message::{
+    AuthorizedMessageV1, MessageAction, MessageContent, MessageId, MessagesV1, ReplyTarget,
+};
 use dioxus::logger::tracing::*;
 use dioxus::prelude::*;
-use dioxus_free_icons::icons::fa_solid_icons::FaPencil;
+use dioxus_free_icons::icons::fa_solid_icons::{FaPencil, FaReply, FaTrash, FaXmark};
 use dioxus_free_icons::Icon;
-use freenet_scaffold::ComposableState;
+use futures::FutureExt;
+use std::collections::HashMap;
 use std::rc::Rc;
 
 #[component]
 pub fn Conversation() -> Element {
-    let mut rooms_signal = use_context::<Signal<Rooms>>();
+    let rooms_signal = use_context::<Signal<Rooms>>();
+    let river = use_context::<River>();
     let current_room_signal = use_context::<Signal<CurrentRoom>>();
     let mut edit_room_modal_signal = use_context::<Signal<EditRoomModalSignal>>();
     let rooms = rooms_signal();
@@ -29,6 +31,8 @@ pub fn Conversation() -> Element {
         .and_then(|key| rooms.map.get(&key).cloned());
     let last_chat_element = use_signal(|| None as Option<Rc<MountedData>>);
     let mut new_message = use_signal(|| "".to_string());
+    // The message being replied to, if any
+    let mut replying_to = use_signal(|| None as Option<(MessageId, Quote)>);
 
     let current_room_label = use_memo({
         let rooms_signal = rooms_signal.clone();
@@ -39,12 +43,7 @@ pub fn Conversation() -> Element {
             current_room
                 .and_then(|key| rooms.map.get(&key))
                 .map(|room_data| {
-                    room_data
-                        .room_state
-                        .configuration
-                        .configuration
-                        .name
-                        .clone()
+                    room_data.room_state.configuration.name().to_string()
                 })
                 .unwrap_or_else(|| "No Room Selected".to_string())
         }
@@ -60,47 +59,68 @@ pub fn Conversation() -> Element {
         }
     });
 
-    let handle_send_message = {
-        let current_room_data = current_room_data.clone();
-        move || {
-            let message = new_message.peek().to_string();
-            if !message.is_empty() {
-                new_message.set(String::new());
-                if let (Some(current_room), Some(current_room_data)) =
-                    (current_room_signal.read().owner_key(), current_room_data)
-                {
-                    let message = MessageV1 {
-                        room_owner: MemberId::from(current_room),
-                        author: MemberId::from(&current_room_data.self_sk.verifying_key()),
-                        content: message,
-                        time: get_current_system_time(),
-                    };
-                    let auth_message =
-                        AuthorizedMessageV1::new(message, &current_room_data.self_sk);
-                    let delta = ChatRoomStateV1Delta {
-                        recent_messages: Some(vec![auth_message.clone()]),
-                        ..Default::default()
-                    };
-                    info!("Sending message: {:?}", auth_message);
-                    rooms_signal
-                        .write()
-                        .map
-                        .get_mut(&current_room)
-                        .unwrap()
-                        .room_state
-                        .apply_delta(
-                            &current_room_data.room_state,
-                            &ChatRoomParametersV1 {
-                                owner: *current_room,
-                            },
-                            &Some(delta),
-                        )
-                        .unwrap();
+    let handle_send_message = move || {
+        let message = new_message.peek().to_string();
+        if message.is_empty() {
+            warn!("Message is empty");
+            return;
+        }
+        let Some(current_room) = current_room_signal.read().owner_key else {
+            return;
+        };
+        let in_reply_to = replying_to.peek().as_ref().map(|(id, _)| id.clone());
+        spawn(async move {
+            let posted = river
+                .run(move |client| {
+                    async move { client.post(&current_room, message, in_reply_to).await }
+                        .boxed_local()
+                })
+                .await;
+            match posted {
+                Ok(message_id) => {
+                    info!("Sent message {:?}", message_id);
+                    new_message.set(String::new());
+                    replying_to.set(None);
                 }
-            } else {
-                warn!("Message is empty");
+                Err(e) => error!("Failed to send message: {}", e),
             }
-        }
+        });
+    };
+
+    // Edits or deletes `target` as the user
+    let handle_message_action = move |target: MessageId, action: MessageAction| {
+        let Some(current_room) = current_room_signal.read().owner_key else {
+            return;
+        };
+        spawn(async move {
+            let acted = river
+                .run(move |client| {
+                    async move { client.act_on_message(&current_room, target, action).await }
+                        .boxed_local()
+                })
+                .await;
+            if let Err(e) = acted {
+                error!("Failed to apply message action: {}", e);
+            }
+        });
+    };
+
+    // Reacts to `message_id` as the user
+    let handle_react = move |message_id: MessageId, reaction: String| {
+        let Some(current_room) = current_room_signal.read().owner_key else {
+            return;
+        };
+        spawn(async move {
+            let reacted = river
+                .run(move |client| {
+                    async move { client.react(&current_room, message_id, reaction).await }
+                        .boxed_local()
+                })
+                .await;
+            if let Err(e) = reacted {
+                error!("Failed to apply reaction: {}", e);
+            }
+        });
     };
 
     rsx! {
@@ -131,17 +151,50 @@ pub fn Conversation() -> Element {
                 {
                     current_room_data.as_ref().map(|room_data| {
                         let room_state = room_data.room_state.clone();
-                        if room_state.recent_messages.messages.is_empty() {
+                        if room_state.recent_messages.messages.entries.is_empty() {
                             rsx! { /* Empty state, can be left blank or add a placeholder here */ }
                         } else {
-                            let messages = &room_state.recent_messages.messages;
+                            let messages = &room_state.recent_messages.messages.entries;
+                            let self_id = MemberId::from(&room_data.self_vk);
+                            let is_owner = self_id == room_data.owner_id();
+                            let can_moderate = room_data.moderator_permissions().delete_messages;
+                            let secrets = &room_data.room_secrets;
                             rsx! {
                                 {messages.iter().enumerate().map(|(index, message)| {
                                     let is_last = index == messages.len() - 1;
+                                    let (content, edited) = match room_state.recent_messages.content(message) {
+                                        MessageContent::Original(content) => (Some(open_content(secrets, content)), false),
+                                        MessageContent::Edited(content) => (Some(open_content(secrets, content)), true),
+                                        MessageContent::Deleted => (None, false),
+                                    };
+                                    let is_author = message.message.author() == self_id;
+                                    let quote = room_state.recent_messages.reply_target(message).map(|target| match target {
+                                        ReplyTarget::Present(parent) => quote_of(&room_state.recent_messages, &room_state.member_info, secrets, parent),
+                                        ReplyTarget::Evicted(_) => Quote::Evicted,
+                                    });
+                                    let reply_target = (message.id(), quote_of(&room_state.recent_messages, &room_state.member_info, secrets, message));
+                                    let reactions: Vec<ReactionChip> = room_state.reactions.for_message(&message.id()).into_iter().map(|(reaction, members)| ReactionChip {
+                                        reaction: reaction.to_string(),
+                                        count: members.len(),
+                                        mine: members.contains(&self_id),
+                                    }).collect();
+                                    let edit_id = message.id();
+                                    let delete_id = message.id();
+                                    let react_id = message.id();
                                     rsx! {
                                         MessageItem {
                                             key: "{message.id().0:?}", // Ensure this is a unique key expression
                                             message: message.clone(),
+                                            content: content,
+                                            edited: edited,
+                                            quote: quote,
+                                            can_edit: is_author,
+                                            can_delete: is_author || is_owner || can_moderate,
+                                            on_edit: move |content: String| handle_message_action(edit_id.clone(), MessageAction::Edit { content }),
+                                            on_delete: move |_| handle_message_action(delete_id.clone(), MessageAction::Delete),
+                                            on_reply: move |_| replying_to.set(Some(reply_target.clone())),
+                                            reactions: reactions,
+                                            on_react: move |reaction: String| handle_react(react_id.clone(), reaction),
                                             member_info: room_state.member_info.clone(),
                                             last_chat_element: if is_last { Some(last_chat_element.clone()) } else { None },
                                         }
@@ -152,6 +205,22 @@ pub fn Conversation() -> Element {
                     })
                 }
             }
+            {
+                replying_to().map(|(_, quote)| rsx! {
+                    div { class: "notification is-light py-2 px-3 mb-2 is-flex is-align-items-center",
+                        div { class: "is-flex-grow-1",
+                            small { class: "has-text-grey", "Replying to" }
+                            QuoteView { quote: quote }
+                        }
+                        button {
+                            class: "button is-small is-white",
+                            title: "Cancel reply",
+                            onclick: move |_| replying_to.set(None),
+                            Icon { icon: FaXmark, width: 12, height: 12 }
+                        }
+                    }
+                })
+            }
             {
                 match current_room_data.as_ref() {
                     Some(room_data) => {
@@ -165,8 +234,13 @@ pub fn Conversation() -> Element {
                                     },
                                 }
                             },
+                            Err(SendMessageError::UserNotMember) if room_data.pending_join.is_some() => rsx! {
+                                div { class: "notification is-info",
+                                    "Joining the room, waiting for its state from the network..."
+                                }
+                            },
                             Err(SendMessageError::UserNotMember) => {
-                                let user_vk = room_data.self_sk.verifying_key();
+                                let user_vk = room_data.self_vk;
                                 let user_id = MemberId::from(&user_vk);
                                 if !room_data.room_state.members.members.iter().any(|m| MemberId::from(&m.member.member_vk) == user_id) {
                                     rsx! {
@@ -191,6 +265,11 @@ pub fn Conversation() -> Element {
                                     "You have been banned from sending messages in this room."
                                 }
                             },
+                            Err(SendMessageError::NoRoomSecret) => rsx! {
+                                div { class: "notification is-info",
+                                    "This room is private. You can send messages once the room owner has shared the room's encryption key with you."
+                                }
+                            },
                         }
                     },
                     None => rsx! {
@@ -212,25 +291,34 @@ pub fn Conversation() -> Element {
 #[component]
 fn MessageItem(
     message: AuthorizedMessageV1,
+    /// What the message shows after any edits, `None` once it's deleted
+    content: Option<String>,
+    edited: bool,
+    can_edit: bool,
+    can_delete: bool,
+    on_edit: EventHandler<String>,
+    on_delete: EventHandler<()>,
+    /// The message this one replies to
+    quote: Option<Quote>,
+    on_reply: EventHandler<()>,
+    reactions: Vec<ReactionChip>,
+    on_react: EventHandler<String>,
     member_info: MemberInfoV1,
     last_chat_element: Option<Signal<Option<Rc<MountedData>>>>,
 ) -> Element {
-    let author_id = message.message.author;
-    let member_name = member_info
-        .member_info
-        .iter()
-        .find(|ami| ami.member_info.member_id == author_id)
-        .map(|ami| ami.member_info.preferred_nickname.clone())
-        .unwrap_or_else(|| "Unknown".to_string());
+    let member_name = nickname(&member_info, message.message.author());
 
-    let time = DateTime::<Utc>::from(message.message.time)
+    let time = DateTime::<Utc>::from(message.message.time())
         .format("%H:%M")
         .to_string();
 
-    let content = markdown::to_html(message.message.content.as_str());
+    let is_deleted = content.is_none();
+    let html = content.as_deref().map(markdown::to_html).unwrap_or_default();
 
     let is_active_signal = use_signal(|| false);
     let mut is_active = is_active_signal.clone();
+    let mut editing = use_signal(|| None as Option<String>);
+    let mut picking_reaction = use_signal(|| false);
 
     rsx! {
         div { class: "box mb-3",
@@ -249,9 +337,105 @@ fn MessageItem(
                                 "{member_name}"
                             }
                             small { class: "has-text-grey", "{time}" }
+                            if edited {
+                                small { class: "has-text-grey ml-1", "(edited)" }
+                            }
                             br {},
-                            span {
-                                dangerous_inner_html : "{content}"
+                            {
+                                quote.map(|quote| rsx! { QuoteView { quote: quote } })
+                            }
+                            if is_deleted {
+                                em { class: "has-text-grey", "Message deleted" }
+                            } else if editing().is_some() {
+                                textarea {
+                                    class: "textarea",
+                                    value: "{editing().unwrap_or_default()}",
+                                    oninput: move |evt| editing.set(Some(evt.value())),
+                                }
+                                div { class: "buttons mt-2",
+                                    button {
+                                        class: "button is-small is-primary",
+                                        onclick: move |_| {
+                                            let draft = editing.write().take();
+                                            if let Some(draft) = draft {
+                                                if !draft.is_empty() {
+                                                    on_edit.call(draft);
+                                                }
+                                            }
+                                        },
+                                        "Save"
+                                    }
+                                    button {
+                                        class: "button is-small",
+                                        onclick: move |_| editing.set(None),
+                                        "Cancel"
+                                    }
+                                }
+                            } else {
+                                span {
+                                    dangerous_inner_html : "{html}"
+                                }
+                            }
+                        }
+                    }
+                    if !is_deleted {
+                        div { class: "tags mt-1",
+                            for chip in reactions {
+                                ReactionChipView {
+                                    key: "{chip.reaction}",
+                                    chip: chip.clone(),
+                                    // Reactions can't be taken back, so only ones the user hasn't made do anything
+                                    on_click: move |_| {
+                                        if !chip.mine {
+                                            on_react.call(chip.reaction.clone())
+                                        }
+                                    },
+                                }
+                            }
+                            if picking_reaction() {
+                                for reaction in QUICK_REACTIONS {
+                                    span {
+                                        class: "tag is-white is-clickable",
+                                        onclick: move |_| {
+                                            picking_reaction.set(false);
+                                            on_react.call(reaction.to_string());
+                                        },
+                                        "{reaction}"
+                                    }
+                                }
+                            } else {
+                                span {
+                                    class: "tag is-white is-clickable has-text-grey",
+                                    title: "Add reaction",
+                                    onclick: move |_| picking_reaction.set(true),
+                                    "+"
+                                }
+                            }
+                        }
+                    }
+                }
+                if !is_deleted && editing().is_none() {
+                    div { class: "media-right",
+                        button {
+                            class: "button is-small is-white",
+                            title: "Reply",
+                            onclick: move |_| on_reply.call(()),
+                            Icon { icon: FaReply, width: 12, height: 12 }
+                        }
+                        if can_edit {
+                            button {
+                                class: "button is-small is-white",
+                                title: "Edit message",
+                                onclick: move |_| editing.set(content.clone()),
+                                Icon { icon: FaPencil, width: 12, height: 12 }
+                            }
+                        }
+                        if can_delete {
+                            button {
+                                class: "button is-small is-white",
+                                title: "Delete message",
+                                onclick: move |_| on_delete.call(()),
+                                Icon { icon: FaTrash, width: 12, height: 12 }
                             }
                         }
                     }
@@ -260,3 +444,99 @@ fn MessageItem(
         }
     }
 }
+
+/// A message as shown above the replies to it
+#[derive(Clone, PartialEq)]
+enum Quote {
+    Message { author: String, content: String },
+    Deleted { author: String },
+    /// Replies can outlive their parent once it's evicted by `max_recent_messages`
+    Evicted,
+}
+
+fn quote_of(
+    messages: &MessagesV1,
+    member_info: &MemberInfoV1,
+    secrets: &HashMap<u32, [u8; 32]>,
+    message: &AuthorizedMessageV1,
+) -> Quote {
+    let author = nickname(member_info, message.message.author());
+    match messages.content(message) {
+        MessageContent::Original(content) | MessageContent::Edited(content) => Quote::Message {
+            author,
+            content: open_content(secrets, content),
+        },
+        MessageContent::Deleted => Quote::Deleted { author },
+    }
+}
+
+/// Decrypts the content of messages in private rooms
+fn open_content(secrets: &HashMap<u32, [u8; 32]>, content: &str) -> String {
+    match RoomData::open_content(secrets, content) {
+        Ok(content) => content,
+        Err(OpenContentError::MissingSecret { .. }) => {
+            "_Sent with a room key you haven't been given_".to_string()
+        }
+        Err(OpenContentError::Undecryptable) => "_This message couldn't be decrypted_".to_string(),
+    }
+}
+
+fn nickname(member_info: &MemberInfoV1, member_id: MemberId) -> String {
+    member_info
+        .member_info
+        .iter()
+        .find(|ami| ami.member_info.member_id == member_id)
+        .map(|ami| ami.member_info.preferred_nickname.clone())
+        .unwrap_or_else(|| "Unknown".to_string())
+}
+
+#[component]
+fn QuoteView(quote: Quote) -> Element {
+    rsx! {
+        blockquote { class: "my-1 py-1 px-2 has-text-grey",
+            {
+                match quote {
+                    Quote::Message { author, content } => rsx! {
+                        strong { class: "mr-2", "{author}" }
+                        "{content}"
+                    },
+                    Quote::Deleted { author } => rsx! {
+                        strong { class: "mr-2", "{author}" }
+                        em { "Message deleted" }
+                    },
+                    Quote::Evicted => rsx! {
+                        em { "An earlier message that is no longer available" }
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// Offered when adding a reaction
+const QUICK_REACTIONS: [&str; 6] = ["👍", "❤️", "😂", "🎉", "😮", "😢"];
+
+/// A reaction to a message with how many members made it
+#[derive(Clone, PartialEq)]
+struct ReactionChip {
+    reaction: String,
+    count: usize,
+    /// Whether the user is one of them
+    mine: bool,
+}
+
+#[component]
+fn ReactionChipView(chip: ReactionChip, on_click: EventHandler<()>) -> Element {
+    let class = if chip.mine {
+        "tag is-info is-light is-clickable"
+    } else {
+        "tag is-light is-clickable"
+    };
+    rsx! {
+        span {
+            class: "{class}",
+            onclick: move |_| on_click.call(()),
+            "{chip.reaction} {chip.count}"
+        }
+    }
+}
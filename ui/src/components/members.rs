@@ -17,6 +17,7 @@ struct MemberDisplay {
     nickname: String,
     member_id: MemberId,
     is_owner: bool,
+    is_moderator: bool,
     is_self: bool,
     invited_you: bool,     // Direct inviter
     sponsored_you: bool,   // Upstream in invite chain
@@ -62,7 +63,7 @@ fn is_in_your_network(member_id: MemberId, members: &MembersV1, self_id: MemberI
     members.members.iter().any(|m| {
         m.member.id() == member_id
             && members.members.iter().any(|inviter| {
-                inviter.member.id() == m.member.invited_by
+                m.inviter() == Some(inviter.member.id())
                     && did_you_invite_member(inviter.member.id(), members, self_id)
             })
     })
@@ -73,7 +74,7 @@ fn did_you_invite_member(member_id: MemberId, members: &MembersV1, self_id: Memb
         .members
         .iter()
         .find(|m| m.member.id() == member_id)
-        .map(|m| m.member.invited_by == self_id)
+        .map(|m| m.inviter() == Some(self_id))
         .unwrap_or(false)
 }
 
@@ -84,6 +85,9 @@ fn format_member_display(member: &MemberDisplay) -> String {
     if member.is_owner {
         tags.push("👑");
     }
+    if member.is_moderator {
+        tags.push("🛡️");
+    }
     if member.is_self {
         tags.push("⭐");
     }
@@ -122,7 +126,7 @@ pub fn MemberList() -> Element {
         let rooms = rooms.read();
         let room_data = rooms.map.get(&room_owner)?;
         let room_state = room_data.room_state.clone();
-        let self_member_id: MemberId = room_data.self_sk.verifying_key().into();
+        let self_member_id: MemberId = room_data.self_vk.into();
         let owner_id: MemberId = room_owner.clone().into();
 
         let member_info = &room_state.member_info;
@@ -142,6 +146,7 @@ pub fn MemberList() -> Element {
             nickname: owner_nickname,
             member_id: owner_id,
             is_owner: true,
+            is_moderator: false,
             is_self: owner_id == self_member_id,
             invited_you: did_member_invite_you(
                 owner_id,
@@ -176,6 +181,11 @@ pub fn MemberList() -> Element {
                 nickname,
                 member_id,
                 is_owner: false,
+                is_moderator: room_state
+                    .configuration
+                    .configuration
+                    .moderator_permissions(member_id)
+                    .is_moderator(),
                 is_self: member_id == self_member_id,
                 invited_you: did_member_invite_you(
                     member_id,
@@ -200,6 +210,18 @@ pub fn MemberList() -> Element {
             all_members.push((format_member_display(&member_display), member_id));
         }
 
+        // Banned members are only listed to whoever can unban them, their nickname is gone
+        let mut banned: Vec<MemberId> = room_data
+            .liftable_bans()
+            .map(|ban| ban.ban.banned_user)
+            .filter(|id| !members.members.iter().any(|m| m.member.id() == *id))
+            .collect();
+        banned.sort();
+        banned.dedup();
+        for member_id in banned {
+            all_members.push((format!("{} 🚫", member_id), member_id));
+        }
+
         Some(all_members)
     })()
     .unwrap_or_default();
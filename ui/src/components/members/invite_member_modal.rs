@@ -1,100 +1,105 @@
+use crate::components::app::River;
 use crate::constants::KEY_VERSION_PREFIX;
-use crate::room_data::{CurrentRoom, Rooms};
-use bs58;
-use common::room_state::member::{AuthorizedMember, Member, MembersDelta};
-use common::room_state::{ChatRoomParametersV1, ChatRoomStateV1Delta};
+use crate::room_data::CurrentRoom;
+use crate::util::get_current_system_time;
 use dioxus::prelude::*;
-use ed25519_dalek::VerifyingKey;
-use freenet_scaffold::ComposableState;
+use futures::FutureExt;
+use river_client::decode_member_key;
+use std::time::Duration;
+use wasm_bindgen::JsCast;
+
+/// How long new invite links can be used for
+const INVITE_LINK_EXPIRY_OPTIONS: [(u64, &str); 3] = [(1, "1 day"), (7, "1 week"), (30, "30 days")];
 
 #[component]
 pub fn InviteMemberModal(is_active: Signal<bool>) -> Element {
-    let mut rooms = use_context::<Signal<Rooms>>();
+    let river = use_context::<River>();
     let current_room = use_context::<Signal<CurrentRoom>>();
     let mut user_key = use_signal(String::new);
     let mut error_message = use_signal(String::new);
+    let mut expiry_days = use_signal(|| 7u64);
+    let mut max_uses = use_signal(|| "1".to_string());
+    let mut invite_link = use_signal(|| None as Option<String>);
+    let mut copy_button_text = use_signal(|| "Copy".to_string());
 
-    let invite_member = move |_| {
+    let create_invite_link = move |_| {
         error_message.set(String::new());
-
-        // Validate key format
-        let key = user_key.read().clone();
-        if !key.starts_with(KEY_VERSION_PREFIX) {
-            error_message.set(format!(
-                "Invalid key format - expected {}...",
-                KEY_VERSION_PREFIX
-            ));
+        let Some(owner_key) = current_room.read().owner_key else {
+            error_message.set("No room selected".to_string());
             return;
-        }
-
-        // Extract and decode the key
-        let encoded_key = key.trim_start_matches(KEY_VERSION_PREFIX);
-        let decoded_key = match bs58::decode(encoded_key).into_vec() {
-            Ok(bytes) => bytes,
-            Err(_) => {
-                error_message.set("Invalid key encoding".to_string());
-                return;
-            }
         };
-
-        // Convert to VerifyingKey
-        let member_vk =
-            match VerifyingKey::from_bytes(decoded_key.as_slice().try_into().unwrap_or(&[0; 32])) {
-                Ok(vk) => vk,
-                Err(_) => {
-                    error_message.set("Invalid verification key".to_string());
+        let max_uses = match max_uses.read().trim() {
+            "" => None,
+            uses => match uses.parse::<u32>() {
+                Ok(uses) if uses > 0 => Some(uses),
+                _ => {
+                    error_message.set("Max uses must be a positive number".to_string());
                     return;
                 }
-            };
-
-        // Get current room data
-        let current = current_room.read();
-        let owner_key = match &current.owner_key {
-            Some(key) => key,
-            None => {
-                error_message.set("No room selected".to_string());
-                return;
-            }
+            },
         };
-
-        let mut rooms_write = rooms.write();
-        let room_data = match rooms_write.map.get_mut(owner_key) {
-            Some(data) => data,
-            None => {
-                error_message.set("Room not found".to_string());
-                return;
+        let expires_at =
+            get_current_system_time() + Duration::from_secs(expiry_days() * 24 * 60 * 60);
+        spawn(async move {
+            let token = river
+                .run(move |client| {
+                    async move { client.invitation(&owner_key, expires_at, max_uses).await }
+                        .boxed_local()
+                })
+                .await;
+            match token {
+                Ok(token) => {
+                    invite_link.set(Some(format!("{}#{}", page_address(), token.encode())));
+                    copy_button_text.set("Copy".to_string());
+                }
+                Err(e) => error_message.set(e.to_string()),
             }
-        };
+        });
+    };
 
-        // Create new member
-        let member = Member {
-            owner_member_id: owner_key.into(),
-            invited_by: room_data.self_sk.verifying_key().into(),
-            member_vk,
-        };
+    let copy_invite_link = move |_| {
+        if let (Some(window), Some(link)) = (web_sys::window(), invite_link()) {
+            if let Ok(navigator) = window.navigator().dyn_into::<web_sys::Navigator>() {
+                let _ = navigator.clipboard().write_text(&link);
+                copy_button_text.set("Copied!".to_string());
+            }
+        }
+    };
 
-        // Create authorized member
-        let authorized_member = AuthorizedMember::new(member, &room_data.self_sk);
+    let invite_member = move |_| {
+        error_message.set(String::new());
 
-        let delta = ChatRoomStateV1Delta {
-            members: Some(MembersDelta::new(vec![authorized_member])),
-            ..Default::default()
+        let member_vk = match decode_member_key(&user_key.read()) {
+            Ok(member_vk) => member_vk,
+            Err(e) => {
+                error_message.set(e.to_string());
+                return;
+            }
         };
 
-        // Apply changes
-        if let Err(e) = room_data.room_state.apply_delta(
-            &room_data.room_state.clone(),
-            &ChatRoomParametersV1 {
-                owner: owner_key.clone(),
-            },
-            &Some(delta),
-        ) {
-            error_message.set(format!("Failed to apply delta: {:?}", e));
+        let Some(owner_key) = current_room.read().owner_key else {
+            error_message.set("No room selected".to_string());
             return;
-        }
+        };
+        spawn(async move {
+            let invited = river
+                .run(move |client| {
+                    async move { client.invite(&owner_key, member_vk).await }.boxed_local()
+                })
+                .await;
+            match invited {
+                // Reset and close modal
+                Ok(()) => {
+                    user_key.set(String::new());
+                    is_active.set(false);
+                }
+                Err(e) => error_message.set(format!("Failed to invite member: {}", e)),
+            }
+        });
+    };
 
-        // Reset and close modal
-        user_key.set(String::new());
+    let close = move |_| {
+        invite_link.set(None);
         is_active.set(false);
     };
 
@@ -103,9 +108,7 @@ pub fn InviteMemberModal(is_active: Signal<bool>) -> Element {
             class: if *is_active.read() { "modal is-active" } else { "modal" },
             div {
                 class: "modal-background",
-                onclick: move |_| {
-                    is_active.set(false);
-                }
+                onclick: close
             }
             div {
                 class: "modal-content",
@@ -148,14 +151,92 @@ pub fn InviteMemberModal(is_active: Signal<bool>) -> Element {
                             }
                         }
                     }
+
+                    hr {}
+
+                    h2 { class: "title is-5 mb-3", "Or Share an Invite Link" }
+                    p { class: "mb-3",
+                        "Anyone with the link can join the room, they don't need to send you their key first."
+                    }
+
+                    div { class: "field is-grouped",
+                        div { class: "control",
+                            label { class: "label", "Expires after" }
+                            div { class: "select",
+                                select {
+                                    onchange: move |evt| {
+                                        if let Ok(days) = evt.value().parse() {
+                                            expiry_days.set(days);
+                                        }
+                                    },
+                                    {INVITE_LINK_EXPIRY_OPTIONS.iter().map(|(days, label)| rsx! {
+                                        option {
+                                            value: "{days}",
+                                            selected: *days == expiry_days(),
+                                            "{label}"
+                                        }
+                                    })}
+                                }
+                            }
+                        }
+                        div { class: "control",
+                            label { class: "label", "Max uses" }
+                            input {
+                                class: "input",
+                                r#type: "number",
+                                min: "1",
+                                placeholder: "Unlimited",
+                                value: "{max_uses}",
+                                onchange: move |evt| max_uses.set(evt.value().to_string())
+                            }
+                        }
+                    }
+
+                    div { class: "field",
+                        div { class: "control",
+                            button {
+                                class: "button is-link",
+                                onclick: create_invite_link,
+                                "Create Invite Link"
+                            }
+                        }
+                    }
+
+                    {
+                        invite_link().map(|link| rsx! {
+                            div { class: "field has-addons",
+                                p { class: "control is-expanded",
+                                    input {
+                                        class: "input small-font-input",
+                                        r#type: "text",
+                                        value: "{link}",
+                                        readonly: "true"
+                                    }
+                                }
+                                p { class: "control",
+                                    button {
+                                        class: "button is-info copy-button",
+                                        onclick: copy_invite_link,
+                                        "{copy_button_text}"
+                                    }
+                                }
+                            }
+                        })
+                    }
                 }
             }
             button {
                 class: "modal-close is-large",
-                onclick: move |_| {
-                    is_active.set(false);
-                }
+                onclick: close
             }
         }
     }
 }
+
+/// The address of the page without any fragment, invite links open the app at the same address
+fn page_address() -> String {
+    web_sys::window()
+        .and_then(|window| window.location().href().ok())
+        .map(|href| href.split('#').next().unwrap_or_default().to_string())
+        .unwrap_or_default()
+}
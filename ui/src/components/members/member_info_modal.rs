@@ -1,11 +1,19 @@
 mod ban_button;
 mod invited_by_field;
+mod moderator_field;
 mod nickname_field;
+mod remove_button;
+mod successor_field;
+mod unban_button;
 
 use crate::components::app::MemberInfoModalSignal;
 use crate::components::members::member_info_modal::ban_button::BanButton;
 use crate::components::members::member_info_modal::invited_by_field::InvitedByField;
+use crate::components::members::member_info_modal::moderator_field::ModeratorField;
 use crate::components::members::member_info_modal::nickname_field::NicknameField;
+use crate::components::members::member_info_modal::remove_button::RemoveButton;
+use crate::components::members::member_info_modal::successor_field::SuccessorField;
+use crate::components::members::member_info_modal::unban_button::UnbanButton;
 pub use crate::room_data::{CurrentRoom, Rooms};
 use common::room_state::member::MemberId;
 use common::room_state::ChatRoomParametersV1;
@@ -33,7 +41,7 @@ pub fn MemberInfoModal() -> Element {
             .read()
             .map
             .get(&current_room_signal.read().owner_key?)
-            .map(|r| MemberId::from(&r.self_sk.verifying_key()))
+            .map(|r| MemberId::from(&r.self_vk))
     });
 
     // Memoized values
@@ -72,6 +80,50 @@ pub fn MemberInfoModal() -> Element {
             .find(|mi| mi.member_info.member_id == member_id)
         {
             Some(mi) => mi,
+            // A banned member's info is gone, all that can be done is to lift the ban
+            None if room_state
+                .room_state
+                .bans
+                .in_effect()
+                .any(|ban| ban.ban.banned_user == member_id) =>
+            {
+                return rsx! {
+                    div {
+                        class: "modal is-active",
+                        div {
+                            class: "modal-background",
+                            onclick: handle_close_modal.clone()
+                        }
+                        div {
+                            class: "modal-content",
+                            div {
+                                class: "box",
+                                h1 { class: "title is-4 mb-3", "Banned Member" }
+                                div {
+                                    class: "field",
+                                    label { class: "label is-medium", "Member ID" }
+                                    div {
+                                        class: "control",
+                                        input {
+                                            class: "input",
+                                            value: "{member_id}",
+                                            readonly: true
+                                        }
+                                    }
+                                }
+                                UnbanButton {
+                                    member_to_unban: member_id,
+                                    nickname: member_id.to_string()
+                                }
+                            }
+                        }
+                        button {
+                            class: "modal-close is-large",
+                            onclick: handle_close_modal
+                        }
+                    }
+                };
+            }
             None => {
                 error!("Member info not found for member {member_id}");
                 return rsx! {
@@ -125,6 +177,17 @@ pub fn MemberInfoModal() -> Element {
             })
             .unwrap_or(false);
 
+        // Moderators who may ban anyone can ban members outside their invite chain
+        let can_ban = is_downstream || room_state.moderator_permissions().ban_anyone;
+        let permissions = room_state
+            .room_state
+            .configuration
+            .configuration
+            .moderator_permissions(member_id);
+        let self_is_owner = self_member_id() == current_room_signal.read().owner_id();
+        let successor = room_state.room_state.configuration.configuration.successor;
+        let is_successor = member.is_some_and(|m| successor == Some(m.member.member_vk));
+
         info!(
             "Rendering MemberInfoModal for member_id: {:?} is_owner: {:?} is_downstream: {:?}",
             member_id, is_owner, is_downstream
@@ -133,6 +196,7 @@ pub fn MemberInfoModal() -> Element {
         // Get the inviter's nickname and ID
         let (invited_by, inviter_id) = match (member, is_owner) {
             (_, true) => ("N/A (Room Owner)".to_string(), None),
+            (Some(m), false) if m.inviter().is_none() => ("N/A (GhostKey)".to_string(), None),
             (Some(m), false) => {
                 let inviter_id = m.member.invited_by;
                 let nickname = member_info_list
@@ -168,13 +232,30 @@ pub fn MemberInfoModal() -> Element {
                                 span { class: "tag-emoji", "👑" } " " "Room Owner"
                             }
                         }
+                        if permissions.is_moderator() {
+                            div {
+                                class: "tag is-primary mb-3 mr-2",
+                                span { class: "tag-emoji", "🛡️" } " " "Moderator"
+                            }
+                        }
+                        if is_successor {
+                            div {
+                                class: "tag is-primary mb-3 mr-2",
+                                span { class: "tag-emoji", "🗝️" } " " "Successor"
+                            }
+                        }
                         if member_id == self_member_id.unwrap() {
                             div {
                                 class: "tag is-info mb-3 mr-2",
                                 span { class: "tag-emoji", "⭐" } " " "You"
                             }
                         }
-                        if is_downstream {
+                        if member.is_some_and(|m| m.ghost_key().is_some()) {
+                            div {
+                                class: "tag is-dark mb-3 mr-2",
+                                span { class: "tag-emoji", "👻" } " " "GhostKey"
+                            }
+                        } else if is_downstream {
                             div {
                                 class: "tag is-success mb-3 mr-2",
                                 span { class: "tag-emoji", "🔑" } " " "Invited by You"
@@ -182,7 +263,7 @@ pub fn MemberInfoModal() -> Element {
                         }
                         // Check if this member invited the current user
                         if let Some(self_member) = members_list.iter().find(|m| m.member.id() == self_member_id.unwrap()) {
-                            if self_member.member.invited_by == member_id {
+                            if self_member.inviter() == Some(member_id) {
                                 div {
                                     class: "tag is-warning mb-3",
                                     span { class: "tag-emoji", "🎪" } " " "Invited You"
@@ -213,20 +294,46 @@ pub fn MemberInfoModal() -> Element {
                                 inviter_id: inviter_id,
                             }
 
+                            if self_is_owner {
+                                if let Some(m) = member {
+                                    ModeratorField {
+                                        member_id: member_id,
+                                        permissions: permissions
+                                    }
+                                    SuccessorField {
+                                        member_vk: m.member.member_vk,
+                                        is_successor: is_successor
+                                    }
+                                }
+                            }
+
                             // Check if member is downstream of current user
                             {
                                 let _current_user_id = {
                                     current_room_data.as_ref()
-                                        .and_then(|r| Some(r.self_sk.verifying_key()))
+                                        .and_then(|r| Some(r.self_vk))
                                         .map(|k| MemberId::from(&k))
                                 };
 
                                 rsx! {
                                     BanButton {
                                         member_to_ban: member_id,
-                                        is_downstream: is_downstream,
+                                        can_ban: can_ban,
+                                        nickname: member_info.member_info.preferred_nickname.clone()
+                                    }
+                                    UnbanButton {
+                                        member_to_unban: member_id,
                                         nickname: member_info.member_info.preferred_nickname.clone()
                                     }
+                                    if let Some(m) = member {
+                                        RemoveButton {
+                                            member_to_remove: m.member.member_vk,
+                                            // Only whoever invited a member, or the owner, can remove them
+                                            can_remove: Some(m.member.invited_by) == self_member_id()
+                                                || self_member_id() == current_room_signal.read().owner_id(),
+                                            nickname: member_info.member_info.preferred_nickname.clone()
+                                        }
+                                    }
                                     ""
                                 }
                             }
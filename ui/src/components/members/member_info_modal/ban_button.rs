@@ -1,86 +1,67 @@
-use crate::components::app::MemberInfoModalSignal;
-use crate::room_data::{CurrentRoom, RoomData, Rooms};
+use crate::components::app::{MemberInfoModalSignal, River};
+use crate::room_data::{CurrentRoom, Rooms};
 use crate::util::get_current_system_time;
-use common::room_state::ban::{AuthorizedUserBan, UserBan};
 use common::room_state::member::MemberId;
-use common::room_state::{ChatRoomParametersV1, ChatRoomStateV1Delta};
+use dioxus::logger::tracing::*;
 use dioxus::prelude::*;
-use freenet_scaffold::ComposableState;
+use futures::FutureExt;
+use std::time::Duration;
+
+/// How long a ban lasts, in days, 0 for a ban that lasts until it's lifted
+const BAN_DURATION_OPTIONS: [(u64, &str); 4] = [
+    (0, "Until unbanned"),
+    (1, "1 day"),
+    (7, "1 week"),
+    (30, "30 days"),
+];
 
 #[component]
-pub fn BanButton(member_to_ban: MemberId, is_downstream: bool, nickname: String) -> Element {
+pub fn BanButton(member_to_ban: MemberId, can_ban: bool, nickname: String) -> Element {
     // Context signals
-    let mut rooms_signal = use_context::<Signal<Rooms>>();
+    let rooms_signal = use_context::<Signal<Rooms>>();
+    let river = use_context::<River>();
     let current_room_signal = use_context::<Signal<CurrentRoom>>();
     let mut modal_signal = use_context::<Signal<MemberInfoModalSignal>>();
 
     // Memos
-    let current_room_data_signal: Memo<Option<RoomData>> = use_memo(move || {
-        let rooms = rooms_signal.read();
-        let current_room = current_room_signal.read();
-        current_room
-            .owner_key
-            .as_ref()
-            .and_then(|key| rooms.map.get(key).cloned())
-    });
     let _self_member_id: Memo<Option<MemberId>> = use_memo(move || {
         rooms_signal
             .read()
             .map
             .get(&current_room_signal.read().owner_key?)
-            .map(|r| MemberId::from(&r.self_sk.verifying_key()))
+            .map(|r| MemberId::from(&r.self_vk))
     });
 
     // Memoized values
     let _owner_key_signal = use_memo(move || current_room_signal.read().owner_key);
 
     let mut show_confirmation = use_signal(|| false);
+    let mut ban_days = use_signal(|| 0u64);
 
     let execute_ban = move |_| {
-        if let (Some(current_room), Some(room_data)) = (
-            current_room_signal.read().owner_key,
-            current_room_data_signal.read().as_ref(),
-        ) {
-            let user_signing_key = &room_data.self_sk;
-            let ban = UserBan {
-                owner_member_id: MemberId::from(&current_room),
-                banned_at: get_current_system_time(),
-                banned_user: member_to_ban,
-            };
-
-            let authorized_ban = AuthorizedUserBan::new(
-                ban,
-                MemberId::from(&user_signing_key.verifying_key()),
-                user_signing_key,
-            );
-
-            let delta = ChatRoomStateV1Delta {
-                bans: Some(vec![authorized_ban]),
-                ..Default::default()
-            };
-
-            modal_signal.with_mut(|signal| {
-                signal.member = None;
-            });
-
-            rooms_signal
-                .write()
-                .map
-                .get_mut(&current_room)
-                .unwrap()
-                .room_state
-                .apply_delta(
-                    &room_data.room_state,
-                    &ChatRoomParametersV1 {
-                        owner: current_room,
-                    },
-                    &Some(delta),
-                )
-                .unwrap();
-        }
+        let Some(current_room) = current_room_signal.read().owner_key else {
+            return;
+        };
+        modal_signal.with_mut(|signal| {
+            signal.member = None;
+        });
+        let expires_at = (ban_days() > 0).then(|| {
+            get_current_system_time() + Duration::from_secs(ban_days() * 24 * 60 * 60)
+        });
+        spawn(async move {
+            let result = river
+                .run(move |client| {
+                    async move { client.ban(&current_room, member_to_ban, expires_at).await }
+                        .boxed_local()
+                })
+                .await;
+            if let Err(e) = result {
+                error!("Failed to ban member: {}", e);
+            }
+        });
     };
 
-    if is_downstream {
+    if can_ban {
         rsx! {
             div {
                 button {
@@ -111,7 +92,26 @@ pub fn BanButton(member_to_ban: MemberId, is_downstream: bool, nickname: String)
                                 strong { "{nickname}" }
                                 " (ID: "
                                 code { "{member_to_ban}" }
-                                ")? This action cannot be undone."
+                                ")? Members they invited will be removed too."
+                            }
+                            div { class: "field mt-3",
+                                label { class: "label", "Ban lasts" }
+                                div { class: "select",
+                                    select {
+                                        onchange: move |evt| {
+                                            if let Ok(days) = evt.value().parse() {
+                                                ban_days.set(days);
+                                            }
+                                        },
+                                        {BAN_DURATION_OPTIONS.iter().map(|(days, label)| rsx! {
+                                            option {
+                                                value: "{days}",
+                                                selected: *days == ban_days(),
+                                                "{label}"
+                                            }
+                                        })}
+                                    }
+                                }
                             }
                         }
 
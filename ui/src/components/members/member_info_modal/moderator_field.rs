@@ -0,0 +1,75 @@
+use crate::components::app::River;
+use crate::room_data::CurrentRoom;
+use common::room_state::configuration::ModeratorPermissions;
+use common::room_state::member::MemberId;
+use dioxus::prelude::*;
+use futures::FutureExt;
+
+/// Lets the owner choose what a member may do as a moderator
+#[component]
+pub fn ModeratorField(member_id: MemberId, permissions: ModeratorPermissions) -> Element {
+    let river = use_context::<River>();
+    let current_room_signal = use_context::<Signal<CurrentRoom>>();
+
+    let set_permissions = move |permissions: ModeratorPermissions| {
+        let Some(current_room) = current_room_signal.read().owner_key else {
+            return;
+        };
+        spawn(async move {
+            let result = river
+                .run(move |client| {
+                    async move {
+                        client
+                            .set_moderator(&current_room, member_id, permissions)
+                            .await
+                    }
+                    .boxed_local()
+                })
+                .await;
+            if let Err(e) = result {
+                log::error!("Failed to update moderator: {}", e);
+            }
+        });
+    };
+
+    rsx! {
+        div { class: "field",
+            label { class: "label is-medium", "Moderator" }
+            div { class: "control",
+                label { class: "checkbox mr-4",
+                    input {
+                        r#type: "checkbox",
+                        checked: permissions.ban_anyone,
+                        onchange: move |evt| set_permissions(ModeratorPermissions {
+                            ban_anyone: evt.checked(),
+                            ..permissions
+                        }),
+                    }
+                    " Ban anyone"
+                }
+                label { class: "checkbox mr-4",
+                    input {
+                        r#type: "checkbox",
+                        checked: permissions.delete_messages,
+                        onchange: move |evt| set_permissions(ModeratorPermissions {
+                            delete_messages: evt.checked(),
+                            ..permissions
+                        }),
+                    }
+                    " Delete messages"
+                }
+                label { class: "checkbox",
+                    input {
+                        r#type: "checkbox",
+                        checked: permissions.edit_room_name,
+                        onchange: move |evt| set_permissions(ModeratorPermissions {
+                            edit_room_name: evt.checked(),
+                            ..permissions
+                        }),
+                    }
+                    " Edit room name"
+                }
+            }
+        }
+    }
+}
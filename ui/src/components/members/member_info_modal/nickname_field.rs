@@ -1,11 +1,11 @@
+use crate::components::app::River;
 use crate::room_data::{CurrentRoom, Rooms};
 use common::room_state::member::MemberId;
-use common::room_state::member_info::{AuthorizedMemberInfo, MemberInfo};
-use common::room_state::{ChatRoomParametersV1, ChatRoomStateV1Delta};
+use common::room_state::member_info::AuthorizedMemberInfo;
 use dioxus::events::Key;
 use dioxus::logger::tracing::*;
 use dioxus::prelude::*;
-use freenet_scaffold::ComposableState;
+use futures::FutureExt;
 use std::rc::Rc;
 
 #[component]
@@ -13,22 +13,19 @@ pub fn NicknameField(member_info: AuthorizedMemberInfo) -> Element {
     // Retrieve contexts
     let rooms = use_context::<Signal<Rooms>>();
     let current_room = use_context::<Signal<CurrentRoom>>();
+    let river = use_context::<River>();
 
     // Compute values
-    let self_signing_key = {
+    let self_member_id = {
         let rooms = rooms.read();
         let current_room = current_room.read();
         current_room
             .owner_key
             .as_ref()
             .and_then(|key| rooms.map.get(key))
-            .map(|room_data| room_data.self_sk.clone())
+            .map(|room_data| MemberId::from(&room_data.self_vk))
     };
 
-    let self_member_id = self_signing_key
-        .as_ref()
-        .map(|sk| MemberId::from(&sk.verifying_key()));
-
     let member_id = member_info.member_info.member_id;
     let is_self = self_member_id
         .as_ref()
@@ -38,52 +35,24 @@ pub fn NicknameField(member_info: AuthorizedMemberInfo) -> Element {
     let mut temp_nickname = use_signal(|| member_info.member_info.preferred_nickname.clone());
     let mut input_element = use_signal(|| None as Option<Rc<MountedData>>);
 
-    let save_changes = {
-        let mut rooms = rooms.clone();
-        let current_room = current_room.clone();
-        let self_signing_key = self_signing_key.clone();
-        let member_info = member_info.clone();
-
-        move |new_value: String| {
-            if new_value.is_empty() {
-                warn!("Nickname cannot be empty");
-                return;
-            }
-
-            let delta = if let Some(signing_key) = self_signing_key.clone() {
-                let new_member_info = MemberInfo {
-                    member_id: member_info.member_info.member_id.clone(),
-                    version: member_info.member_info.version + 1,
-                    preferred_nickname: new_value,
-                };
-                let new_authorized_member_info =
-                    AuthorizedMemberInfo::new_with_member_key(new_member_info, &signing_key);
-                Some(ChatRoomStateV1Delta {
-                    member_info: Some(vec![new_authorized_member_info]),
-                    ..Default::default()
+    let save_changes = move |new_value: String| {
+        if new_value.is_empty() {
+            warn!("Nickname cannot be empty");
+            return;
+        }
+        let Some(owner_key) = current_room.read().owner_key else {
+            return;
+        };
+        spawn(async move {
+            let result = river
+                .run(move |client| {
+                    async move { client.set_nickname(&owner_key, new_value).await }.boxed_local()
                 })
-            } else {
-                warn!("No signing key available");
-                None
-            };
-
-            if let Some(delta) = delta {
-                let mut rooms = rooms.write();
-                if let Some(owner_key) = current_room.read().owner_key.clone() {
-                    if let Some(room_data) = rooms.map.get_mut(&owner_key) {
-                        if let Err(e) = room_data.room_state.apply_delta(
-                            &room_data.room_state.clone(),
-                            &ChatRoomParametersV1 { owner: owner_key },
-                            &Some(delta),
-                        ) {
-                            error!("Failed to apply delta: {:?}", e);
-                        }
-                    } else {
-                        warn!("Room state not found for current room");
-                    }
-                }
+                .await;
+            if let Err(e) = result {
+                error!("Failed to set nickname: {}", e);
             }
-        }
+        });
     };
 
     let on_input = move |evt: dioxus_core::Event<FormData>| {
@@ -91,7 +60,6 @@ pub fn NicknameField(member_info: AuthorizedMemberInfo) -> Element {
     };
 
     let on_blur = {
-        let mut save_changes = save_changes.clone();
         let temp_nickname = temp_nickname.clone();
         move |_| {
             let new_value = temp_nickname();
@@ -100,7 +68,6 @@ pub fn NicknameField(member_info: AuthorizedMemberInfo) -> Element {
     };
 
     let on_keydown = {
-        let mut save_changes = save_changes.clone();
         let temp_nickname = temp_nickname.clone();
         move |evt: dioxus_core::Event<KeyboardData>| {
             if evt.key() == Key::Enter {
@@ -0,0 +1,89 @@
+use crate::components::app::{MemberInfoModalSignal, River};
+use crate::room_data::CurrentRoom;
+use dioxus::prelude::*;
+use ed25519_dalek::VerifyingKey;
+use futures::FutureExt;
+
+/// Removes a member without banning them, they can be invited again later
+#[component]
+pub fn RemoveButton(member_to_remove: VerifyingKey, can_remove: bool, nickname: String) -> Element {
+    let river = use_context::<River>();
+    let current_room_signal = use_context::<Signal<CurrentRoom>>();
+    let mut modal_signal = use_context::<Signal<MemberInfoModalSignal>>();
+
+    let mut show_confirmation = use_signal(|| false);
+
+    let execute_remove = move |_| {
+        let Some(current_room) = current_room_signal.read().owner_key else {
+            return;
+        };
+        modal_signal.with_mut(|signal| {
+            signal.member = None;
+        });
+        spawn(async move {
+            let result = river
+                .run(move |client| {
+                    async move { client.remove_member(&current_room, member_to_remove).await }
+                        .boxed_local()
+                })
+                .await;
+            if let Err(e) = result {
+                log::error!("Failed to remove member: {}", e);
+            }
+        });
+    };
+
+    if can_remove {
+        rsx! {
+            div {
+                button {
+                    class: "button is-warning mt-3",
+                    onclick: move |_| show_confirmation.set(true),
+                    "Remove User"
+                }
+
+                div {
+                    class: "modal",
+                    class: if *show_confirmation.read() { "is-active" } else { "" },
+
+                    div { class: "modal-background" }
+
+                    div { class: "modal-card",
+                        header { class: "modal-card-head",
+                            p { class: "modal-card-title", "Confirm Removal" }
+                            button {
+                                class: "delete",
+                                onclick: move |_| show_confirmation.set(false),
+                                aria_label: "close"
+                            }
+                        }
+
+                        section { class: "modal-card-body",
+                            p {
+                                "Are you sure you want to remove "
+                                strong { "{nickname}" }
+                                " from the room? Members they invited will be removed too. "
+                                "Unlike a ban, they can be invited again."
+                            }
+                        }
+
+                        footer { class: "modal-card-foot",
+                            button {
+                                class: "button is-warning",
+                                onclick: execute_remove,
+                                "Yes, Remove User"
+                            }
+                            button {
+                                class: "button",
+                                onclick: move |_| show_confirmation.set(false),
+                                "Cancel"
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    } else {
+        rsx! { "" }
+    }
+}
@@ -0,0 +1,46 @@
+use crate::components::app::River;
+use crate::room_data::CurrentRoom;
+use dioxus::prelude::*;
+use ed25519_dalek::VerifyingKey;
+use futures::FutureExt;
+
+/// Lets the owner choose a member who may take the room over should the owner's key be lost
+#[component]
+pub fn SuccessorField(member_vk: VerifyingKey, is_successor: bool) -> Element {
+    let river = use_context::<River>();
+    let current_room_signal = use_context::<Signal<CurrentRoom>>();
+
+    let set_successor = move |evt: Event<FormData>| {
+        let Some(current_room) = current_room_signal.read().owner_key else {
+            return;
+        };
+        let successor = evt.checked().then_some(member_vk);
+        spawn(async move {
+            let result = river
+                .run(move |client| {
+                    async move { client.set_successor(&current_room, successor).await }
+                        .boxed_local()
+                })
+                .await;
+            if let Err(e) = result {
+                log::error!("Failed to update successor: {}", e);
+            }
+        });
+    };
+
+    rsx! {
+        div { class: "field",
+            label { class: "label is-medium", "Successor" }
+            div { class: "control",
+                label { class: "checkbox",
+                    input {
+                        r#type: "checkbox",
+                        checked: is_successor,
+                        onchange: set_successor,
+                    }
+                    " Can take the room over if your key is lost"
+                }
+            }
+        }
+    }
+}
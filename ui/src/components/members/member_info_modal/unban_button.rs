@@ -0,0 +1,101 @@
+use crate::components::app::{MemberInfoModalSignal, River};
+use crate::room_data::{CurrentRoom, Rooms};
+use common::room_state::member::MemberId;
+use dioxus::prelude::*;
+use futures::FutureExt;
+
+/// Lifts the bans on a member that we made, or any ban on them if we're the owner
+#[component]
+pub fn UnbanButton(member_to_unban: MemberId, nickname: String) -> Element {
+    let rooms_signal = use_context::<Signal<Rooms>>();
+    let river = use_context::<River>();
+    let current_room_signal = use_context::<Signal<CurrentRoom>>();
+    let mut modal_signal = use_context::<Signal<MemberInfoModalSignal>>();
+
+    let can_unban = use_memo(move || {
+        let rooms = rooms_signal.read();
+        current_room_signal
+            .read()
+            .owner_key
+            .and_then(|key| rooms.map.get(&key))
+            .is_some_and(|room| {
+                room.liftable_bans()
+                    .any(|ban| ban.ban.banned_user == member_to_unban)
+            })
+    });
+
+    let mut show_confirmation = use_signal(|| false);
+
+    let execute_unban = move |_| {
+        let Some(current_room) = current_room_signal.read().owner_key else {
+            return;
+        };
+        modal_signal.with_mut(|signal| {
+            signal.member = None;
+        });
+        spawn(async move {
+            let result = river
+                .run(move |client| {
+                    async move { client.unban(&current_room, member_to_unban).await }.boxed_local()
+                })
+                .await;
+            if let Err(e) = result {
+                log::error!("Failed to unban member: {}", e);
+            }
+        });
+    };
+
+    if can_unban() {
+        rsx! {
+            div {
+                button {
+                    class: "button is-success mt-3",
+                    onclick: move |_| show_confirmation.set(true),
+                    "Unban User"
+                }
+
+                div {
+                    class: "modal",
+                    class: if *show_confirmation.read() { "is-active" } else { "" },
+
+                    div { class: "modal-background" }
+
+                    div { class: "modal-card",
+                        header { class: "modal-card-head",
+                            p { class: "modal-card-title", "Confirm Unban" }
+                            button {
+                                class: "delete",
+                                onclick: move |_| show_confirmation.set(false),
+                                aria_label: "close"
+                            }
+                        }
+
+                        section { class: "modal-card-body",
+                            p {
+                                "Are you sure you want to unban "
+                                strong { "{nickname}" }
+                                "? They and the members they invited are back in the room unless "
+                                "someone else banned them too."
+                            }
+                        }
+
+                        footer { class: "modal-card-foot",
+                            button {
+                                class: "button is-success",
+                                onclick: execute_unban,
+                                "Yes, Unban User"
+                            }
+                            button {
+                                class: "button",
+                                onclick: move |_| show_confirmation.set(false),
+                                "Cancel"
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    } else {
+        rsx! { "" }
+    }
+}
@@ -1,13 +1,14 @@
 pub(crate) mod create_room_modal;
 pub(crate) mod edit_room_modal;
+pub(crate) mod join_room_modal;
 pub(crate) mod room_name_field;
 
-use crate::components::app::CreateRoomModalSignal;
+use crate::components::app::{CreateRoomModalSignal, KeyringModalSignal};
 use crate::room_data::{CurrentRoom, Rooms};
 use create_room_modal::CreateRoomModal;
 use dioxus::prelude::*;
 use dioxus_free_icons::{
-    icons::fa_solid_icons::{FaComments, FaLink, FaPlus},
+    icons::fa_solid_icons::{FaComments, FaKey, FaLink, FaPlus},
     Icon,
 };
 
@@ -41,7 +42,11 @@ pub fn RoomList() -> Element {
                 CreateRoomModal {}
                 {rooms.read().map.iter().map(|(room_key, room_data)| {
                     let room_key = *room_key;
-                    let room_name = room_data.room_state.configuration.configuration.name.clone();
+                    let room_name = if room_data.pending_join.is_some() {
+                        "Joining…".to_string()
+                    } else {
+                        room_data.room_state.configuration.name().to_string()
+                    };
                     let is_current = current_room.read().owner_key == Some(room_key);
                     let mut current_room_clone = current_room.clone(); // Clone the Signal
                     rsx! {
@@ -70,6 +75,7 @@ pub fn RoomList() -> Element {
             div { class: "room-actions",
                 {
                     let mut create_room_signal = use_context::<Signal<CreateRoomModalSignal>>();
+                    let mut keyring_modal_signal = use_context::<Signal<KeyringModalSignal>>();
                     rsx! {
                         button {
                             class: "create",
@@ -93,6 +99,18 @@ pub fn RoomList() -> Element {
                             }
                             span { "Add Room" }
                         }
+                        button {
+                            class: "add",
+                            onclick: move |_| {
+                                keyring_modal_signal.write().show = true;
+                            },
+                            Icon {
+                                width: 16,
+                                height: 16,
+                                icon: FaKey,
+                            }
+                            span { "Keys" }
+                        }
                     }
                 }
             }
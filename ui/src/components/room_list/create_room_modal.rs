@@ -1,16 +1,18 @@
-use crate::components::app::CreateRoomModalSignal;
-use crate::room_data::{CurrentRoom, Rooms};
+use crate::components::app::{CreateRoomModalSignal, River};
+use crate::room_data::CurrentRoom;
+use common::room_state::configuration::PrivacyMode;
 use dioxus::prelude::*;
-use ed25519_dalek::SigningKey;
+use futures::FutureExt;
 
 #[component]
 pub fn CreateRoomModal() -> Element {
-    let mut rooms = use_context::<Signal<Rooms>>();
+    let river = use_context::<River>();
     let mut current_room = use_context::<Signal<CurrentRoom>>();
     let mut create_room_signal = use_context::<Signal<CreateRoomModalSignal>>();
 
     let mut room_name = use_signal(String::new);
     let mut nickname = use_signal(String::new);
+    let mut is_private = use_signal(|| false);
 
     let create_room = move |_| {
         let name = room_name.read().clone();
@@ -18,18 +20,30 @@ pub fn CreateRoomModal() -> Element {
             return;
         }
 
-        let mut rooms_write = rooms.write();
-        let self_sk = SigningKey::generate(&mut rand::thread_rng());
         let nick = nickname.read().clone();
-        let new_room_key = rooms_write.create_new_room_with_name(self_sk, name, nick);
-
-        current_room.set(CurrentRoom {
-            owner_key: Some(new_room_key),
+        let privacy_mode = if is_private() {
+            PrivacyMode::Private
+        } else {
+            PrivacyMode::Public
+        };
+        spawn(async move {
+            let created = river
+                .run(move |client| {
+                    async move { client.create_room(name, nick, privacy_mode).await }.boxed_local()
+                })
+                .await;
+            match created {
+                Ok(new_room_key) => current_room.set(CurrentRoom {
+                    owner_key: Some(new_room_key),
+                }),
+                Err(e) => log::error!("Failed to create room: {}", e),
+            }
         });
 
         // Reset and close modal
         room_name.set(String::new());
         nickname.set(String::new());
+        is_private.set(false);
         create_room_signal.write().show = false;
     };
 
@@ -70,6 +84,19 @@ pub fn CreateRoomModal() -> Element {
                         }
                     }
 
+                    div { class: "field",
+                        div { class: "control",
+                            label { class: "checkbox",
+                                input {
+                                    r#type: "checkbox",
+                                    checked: is_private(),
+                                    onchange: move |evt| is_private.set(evt.checked())
+                                }
+                                " Private room (messages are end-to-end encrypted)"
+                            }
+                        }
+                    }
+
                     div { class: "field",
                         div { class: "control",
                             button {
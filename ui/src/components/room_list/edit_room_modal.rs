@@ -1,13 +1,16 @@
 use super::room_name_field::RoomNameField;
-use crate::components::app::EditRoomModalSignal;
-use crate::room_data::Rooms;
+use crate::components::app::{EditRoomModalSignal, River};
+use crate::room_data::{CurrentRoom, Rooms};
 use dioxus::prelude::*;
+use futures::FutureExt;
 use std::ops::Deref;
 
 #[component]
 pub fn EditRoomModal() -> Element {
     let rooms = use_context::<Signal<Rooms>>();
+    let river = use_context::<River>();
     let mut edit_room_signal = use_context::<Signal<EditRoomModalSignal>>();
+    let mut current_room = use_context::<Signal<CurrentRoom>>();
 
     // Memoize the room being edited
     let editing_room = use_memo(move || {
@@ -22,25 +25,91 @@ pub fn EditRoomModal() -> Element {
         })
     });
 
-    // Memoize the room configuration
-    let room_config = use_memo(move || {
+    // Memoize the room name, as last renamed
+    let room_name = use_memo(move || {
         editing_room
             .read()
             .as_ref()
-            .map(|room_data| room_data.room_state.configuration.configuration.clone())
+            .map(|room_data| room_data.room_state.configuration.name().to_string())
     });
 
     // Memoize if the current user is the owner of the room being edited
     let user_is_owner = use_memo(move || {
         editing_room.read().as_ref().map_or(false, |room_data| {
-            let user_vk = room_data.self_sk.verifying_key();
+            let user_vk = room_data.self_vk;
             let room_vk = edit_room_signal.read().room.unwrap();
             user_vk == room_vk
         })
     });
 
-    // Render the modal if room configuration is available
-    if let Some(config) = room_config.clone().read().deref() {
+    // Memoize if the current user is a moderator who can rename the room being edited
+    let user_can_rename = use_memo(move || {
+        editing_room
+            .read()
+            .as_ref()
+            .is_some_and(|room_data| room_data.moderator_permissions().edit_room_name)
+    });
+
+    // Memoize if the current user is a member who can leave the room being edited
+    let user_is_member = use_memo(move || {
+        editing_room.read().as_ref().map_or(false, |room_data| {
+            let user_vk = room_data.self_vk;
+            room_data
+                .room_state
+                .members
+                .members
+                .iter()
+                .any(|m| m.member.member_vk == user_vk)
+        })
+    });
+
+    // Memoize if the current user may take the room over, having been designated by the owner
+    let user_is_successor = use_memo(move || {
+        editing_room.read().as_ref().is_some_and(|room_data| {
+            room_data.room_state.configuration.configuration.successor == Some(room_data.self_vk)
+        })
+    });
+
+    let mut confirm_leave = use_signal(|| false);
+    let mut confirm_hand_over = use_signal(|| false);
+
+    let leave_room = move |_| {
+        let Some(room_vk) = edit_room_signal.read().room else {
+            return;
+        };
+        spawn(async move {
+            let left = river
+                .run(move |client| async move { client.leave(&room_vk).await }.boxed_local())
+                .await;
+            if let Err(e) = left {
+                log::error!("Failed to leave room: {}", e);
+            }
+        });
+        confirm_leave.set(false);
+        edit_room_signal.write().room = None;
+    };
+
+    let hand_over = move |_| {
+        let Some(room_vk) = edit_room_signal.read().room else {
+            return;
+        };
+        spawn(async move {
+            let handed_over = river
+                .run(move |client| async move { client.hand_over(&room_vk).await }.boxed_local())
+                .await;
+            match handed_over {
+                Ok(new_owner_vk) => current_room.set(CurrentRoom {
+                    owner_key: Some(new_owner_vk),
+                }),
+                Err(e) => log::error!("Failed to hand over room: {}", e),
+            }
+        });
+        confirm_hand_over.set(false);
+        edit_room_signal.write().room = None;
+    };
+
+    // Render the modal if the room is available
+    if let Some(name) = room_name.clone().read().deref() {
         rsx! {
             div {
                 class: "modal is-active",
@@ -57,8 +126,74 @@ pub fn EditRoomModal() -> Element {
                         h1 { class: "title is-4 mb-3", "Room Configuration" }
 
                         RoomNameField {
-                            config: config.clone(),
-                            is_owner: *user_is_owner.read()
+                            name: name.clone(),
+                            can_edit: *user_is_owner.read() || *user_can_rename.read()
+                        }
+
+                        if *user_is_owner.read() || *user_is_successor.read() {
+                            div {
+                                class: "field mt-5",
+                                if *confirm_hand_over.read() {
+                                    p {
+                                        class: "mb-3",
+                                        if *user_is_owner.read() {
+                                            "The room moves to a new owner key and members follow it there. Do this if your key may have been compromised."
+                                        } else {
+                                            "The room moves to you as its new owner and members follow it there. Do this if the owner's key was lost."
+                                        }
+                                    }
+                                    div {
+                                        class: "buttons",
+                                        button {
+                                            class: "button is-warning",
+                                            onclick: hand_over,
+                                            "Yes, Hand Over Room"
+                                        }
+                                        button {
+                                            class: "button",
+                                            onclick: move |_| confirm_hand_over.set(false),
+                                            "Cancel"
+                                        }
+                                    }
+                                } else {
+                                    button {
+                                        class: "button is-warning is-outlined",
+                                        onclick: move |_| confirm_hand_over.set(true),
+                                        if *user_is_owner.read() { "Rotate Owner Key" } else { "Take Over Room" }
+                                    }
+                                }
+                            }
+                        }
+
+                        if *user_is_member.read() {
+                            div {
+                                class: "field mt-5",
+                                if *confirm_leave.read() {
+                                    p {
+                                        class: "mb-3",
+                                        "Members you invited will leave with you. You can rejoin if someone invites you again."
+                                    }
+                                    div {
+                                        class: "buttons",
+                                        button {
+                                            class: "button is-danger",
+                                            onclick: leave_room,
+                                            "Yes, Leave Room"
+                                        }
+                                        button {
+                                            class: "button",
+                                            onclick: move |_| confirm_leave.set(false),
+                                            "Cancel"
+                                        }
+                                    }
+                                } else {
+                                    button {
+                                        class: "button is-danger is-outlined",
+                                        onclick: move |_| confirm_leave.set(true),
+                                        "Leave Room"
+                                    }
+                                }
+                            }
                         }
                     }
                 }
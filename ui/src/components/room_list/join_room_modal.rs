@@ -0,0 +1,110 @@
+use crate::components::app::{JoinRoomModalSignal, River};
+use crate::room_data::{CurrentRoom, Rooms};
+use dioxus::prelude::*;
+use futures::FutureExt;
+
+/// Asks for a nickname and joins the room of an invite link the app was opened with
+#[component]
+pub fn JoinRoomModal() -> Element {
+    let rooms = use_context::<Signal<Rooms>>();
+    let river = use_context::<River>();
+    let mut current_room = use_context::<Signal<CurrentRoom>>();
+    let mut join_room_signal = use_context::<Signal<JoinRoomModalSignal>>();
+
+    let mut nickname = use_signal(String::new);
+    let mut error_message = use_signal(String::new);
+
+    let mut close = move || {
+        join_room_signal.write().invitation = None;
+        nickname.set(String::new());
+        error_message.set(String::new());
+        // Don't offer the same invitation again if the page is reloaded
+        if let Some(window) = web_sys::window() {
+            let _ = window.location().set_hash("");
+        }
+    };
+
+    let join_room = move |_| {
+        let Some(invitation) = join_room_signal.read().invitation.clone() else {
+            return;
+        };
+        let nick = nickname.read().clone();
+        if nick.is_empty() {
+            error_message.set("Please choose a nickname".to_string());
+            return;
+        }
+        if rooms.read().map.contains_key(&invitation.room_owner) {
+            error_message.set("You're already in this room".to_string());
+            return;
+        }
+
+        spawn(async move {
+            let joined = river
+                .run(move |client| {
+                    async move { client.join(&invitation, nick).await }.boxed_local()
+                })
+                .await;
+            match joined {
+                Ok(owner_key) => {
+                    current_room.set(CurrentRoom {
+                        owner_key: Some(owner_key),
+                    });
+                    close();
+                }
+                Err(e) => error_message.set(e.to_string()),
+            }
+        });
+    };
+
+    rsx! {
+        div {
+            class: format_args!("modal {}", if join_room_signal.read().invitation.is_some() { "is-active" } else { "" }),
+            div {
+                class: "modal-background",
+                onclick: move |_| close()
+            }
+            div {
+                class: "modal-content",
+                div {
+                    class: "box",
+                    h1 { class: "title is-4 mb-3", "Join Room" }
+                    p { class: "mb-3", "You've been invited to a room on River." }
+
+                    div { class: "field",
+                        label { class: "label", "Your Nickname" }
+                        div { class: "control",
+                            input {
+                                class: "input",
+                                value: "{nickname}",
+                                onchange: move |evt| nickname.set(evt.value().to_string())
+                            }
+                        }
+                    }
+
+                    {
+                        (!error_message.read().is_empty()).then(|| rsx!(
+                            div {
+                                class: "notification is-danger",
+                                "{error_message}"
+                            }
+                        ))
+                    }
+
+                    div { class: "field",
+                        div { class: "control",
+                            button {
+                                class: "button is-primary",
+                                onclick: join_room,
+                                "Join Room"
+                            }
+                        }
+                    }
+                }
+            }
+            button {
+                class: "modal-close is-large",
+                onclick: move |_| close()
+            }
+        }
+    }
+}
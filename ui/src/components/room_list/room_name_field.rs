@@ -1,21 +1,21 @@
-use crate::room_data::{CurrentRoom, Rooms};
-use common::room_state::configuration::{AuthorizedConfigurationV1, Configuration};
-use common::room_state::{ChatRoomParametersV1, ChatRoomStateV1Delta};
+use crate::components::app::River;
+use crate::room_data::CurrentRoom;
 use dioxus::logger::tracing::*;
 use dioxus::prelude::*;
 use dioxus_core::Event;
-use freenet_scaffold::ComposableState;
+use futures::FutureExt;
 use log::info;
 
+/// The room's name, editable by the owner and by moderators allowed to rename the room
 #[component]
-pub fn RoomNameField(config: Configuration, is_owner: bool) -> Element {
-    let mut rooms = use_context::<Signal<Rooms>>();
+pub fn RoomNameField(name: String, can_edit: bool) -> Element {
+    let river = use_context::<River>();
     let current_room = use_context::<Signal<CurrentRoom>>();
 
-    let mut room_name = use_signal(|| config.name.clone());
+    let mut room_name = use_signal(|| name.clone());
 
     let update_room_name = move |evt: Event<FormData>| {
-        if !is_owner {
+        if !can_edit {
             return;
         }
 
@@ -23,36 +23,19 @@ pub fn RoomNameField(config: Configuration, is_owner: bool) -> Element {
         let new_name = evt.value().to_string();
         if !new_name.is_empty() {
             room_name.set(new_name.clone());
-            let mut new_config = config.clone();
-            new_config.name = new_name;
-            new_config.configuration_version += 1;
 
-            let mut rooms_write_guard = rooms.write();
             let owner_key = current_room.read().owner_key.expect("No owner key");
-
-            if let Some(room_data) = rooms_write_guard.map.get_mut(&owner_key) {
-                let signing_key = &room_data.self_sk;
-                let new_authorized_config = AuthorizedConfigurationV1::new(new_config, signing_key);
-
-                let delta = ChatRoomStateV1Delta {
-                    configuration: Some(new_authorized_config),
-                    ..Default::default()
-                };
-
-                info!("Applying delta to room state");
-                let parent_state = room_data.room_state.clone();
-                match ComposableState::apply_delta(
-                    &mut room_data.room_state,
-                    &parent_state,
-                    &ChatRoomParametersV1 { owner: owner_key },
-                    &Some(delta),
-                ) {
-                    Ok(_) => info!("Delta applied successfully"),
-                    Err(e) => error!("Failed to apply delta: {:?}", e),
+            spawn(async move {
+                let renamed = river
+                    .run(move |client| {
+                        async move { client.rename_room(&owner_key, new_name).await }.boxed_local()
+                    })
+                    .await;
+                match renamed {
+                    Ok(()) => info!("Room renamed"),
+                    Err(e) => error!("Failed to rename room: {}", e),
                 }
-            } else {
-                error!("Room state not found for current room");
-            }
+            });
         } else {
             error!("Room name is empty");
         }
@@ -65,7 +48,7 @@ pub fn RoomNameField(config: Configuration, is_owner: bool) -> Element {
                 input {
                     class: "input",
                     value: "{room_name}",
-                    readonly: !is_owner,
+                    readonly: !can_edit,
                     onchange: update_room_name,
                 }
             }
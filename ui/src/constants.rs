@@ -1,6 +1,10 @@
-pub const KEY_VERSION_PREFIX: &str = "river:v1:user:vk:";
+pub use river_client::constants::{KEY_VERSION_PREFIX, ROOM_CONTRACT_WASM};
 
-pub const ROOM_CONTRACT_WASM: &[u8] =
-    include_bytes!("../../target/wasm32-unknown-unknown/release/room_contract.wasm");
+pub const IDENTITY_VERSION_PREFIX: &str = "river:v1:user:id:";
+
+/// The chat delegate our keys are kept in, registered for the UI's contract
+#[cfg(not(feature = "no-sync"))]
+pub const CHAT_DELEGATE_WASM: &[u8] =
+    include_bytes!("../../target/wasm32-unknown-unknown/release/chat_delegate.wasm");
 
 // pub const ROOM_CONTRACT_CODE_HASH: CodeHash = CodeHash::from_code(ROOM_CONTRACT_WASM);
@@ -7,37 +7,44 @@ use freenet_stdlib::prelude::{ContractCode, ContractInstanceId, ContractKey, Par
 use crate::util::random_full_name;
 use common::room_state::ChatRoomParametersV1;
 use common::{
-    room_state::{configuration::*, member::*, member_info::*, message::*},
+    room_state::{configuration::*, member::*, member_info::*, message::*, pin::PinnedMessagesV1},
     ChatRoomStateV1,
 };
 use ed25519_dalek::{SigningKey, VerifyingKey};
 use freenet_scaffold::ComposableState;
 use lipsum::lipsum;
 use rand::rngs::OsRng;
+use river_client::Keyring;
 use std::collections::HashMap;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-pub fn create_example_rooms() -> Rooms {
+/// The example rooms, and our key in each for the client's key store
+pub fn create_example_rooms() -> (Rooms, Keyring) {
     let mut map = HashMap::new();
+    let mut keyring = Keyring::default();
 
     // Room where you're just an observer (not a member)
     let room1 = create_room(&"Public Discussion Room".to_string(), SelfIs::Observer);
     map.insert(room1.owner_vk, room1.room_data);
+    keyring.keys.insert(room1.owner_vk, room1.self_sk);
 
     // Room where you're a member
     let room2 = create_room(&"Team Chat Room".to_string(), SelfIs::Member);
     map.insert(room2.owner_vk, room2.room_data);
+    keyring.keys.insert(room2.owner_vk, room2.self_sk);
 
     // Room where you're the owner
     let room3 = create_room(&"Your Private Room".to_string(), SelfIs::Owner);
     map.insert(room3.owner_vk, room3.room_data);
+    keyring.keys.insert(room3.owner_vk, room3.self_sk);
 
-    Rooms { map }
+    (Rooms { map }, keyring)
 }
 
 struct CreatedRoom {
     owner_vk: VerifyingKey,
     room_data: RoomData,
+    self_sk: SigningKey,
 }
 
 #[derive(Debug, PartialEq)]
@@ -191,11 +198,16 @@ fn create_room(room_name: &String, self_is: SelfIs) -> CreatedRoom {
         owner_vk,
         room_data: RoomData {
             room_state,
-            self_sk: self_sk.clone(),
+            pinned_messages: PinnedMessagesV1::default(),
+            self_vk,
+            room_secrets: HashMap::new(),
             owner_vk: owner_vk.clone(),
             contract_key,
             sync_status: RoomSyncStatus::Unsubscribed,
+            pending_join: None,
+            pending_upgrade: None,
         },
+        self_sk,
     }
 }
 
@@ -270,7 +282,7 @@ fn add_example_messages(
 
         // Generate message with random length (15-35 words)
         let word_count = rand::random::<u8>() % 21 + 15;
-        messages.messages.push(AuthorizedMessageV1::new(
+        messages.messages.entries.push(AuthorizedMessageV1::new(
             MessageV1 {
                 room_owner: *owner_id,
                 author: author_id,
@@ -298,8 +310,9 @@ mod tests {
 
     #[test]
     fn test_create_example_rooms() {
-        let rooms = create_example_rooms();
+        let (rooms, keyring) = create_example_rooms();
         assert_eq!(rooms.map.len(), 3);
+        assert_eq!(keyring.keys.len(), 3);
 
         for (owner_vk, room_data) in rooms.map.iter() {
             // Verify the room state
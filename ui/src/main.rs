@@ -6,6 +6,7 @@ mod components;
 mod constants;
 mod example_data;
 mod room_data;
+mod storage;
 mod util;
 
 use components::app::App;
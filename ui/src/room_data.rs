@@ -1,76 +1,9 @@
-use common::room_state::configuration::{AuthorizedConfigurationV1, Configuration};
-use common::room_state::member::MemberId;
-use common::room_state::member_info::{AuthorizedMemberInfo, MemberInfo};
-use common::room_state::ChatRoomParametersV1;
-use common::ChatRoomStateV1;
-use ed25519_dalek::{SigningKey, VerifyingKey};
-use freenet_stdlib::prelude::{ContractCode, ContractInstanceId, ContractKey, Parameters};
-use std::collections::HashMap;
-use crate::{constants::ROOM_CONTRACT_WASM, util::to_cbor_vec};
-
-#[derive(Debug, PartialEq)]
-pub enum SendMessageError {
-    UserNotMember,
-    UserBanned,
-}
-
-#[derive(Clone, PartialEq, Debug)]
-pub enum RoomSyncStatus {
-    Unsubscribed,
-    Subscribing,
-    Subscribed,
-    Error(String),
-}
-
-#[derive(Clone, PartialEq)]
-pub struct RoomData {
-    pub owner_vk: VerifyingKey,
-    pub room_state: ChatRoomStateV1,
-    pub self_sk: SigningKey,
-    pub contract_key: ContractKey,
-    pub sync_status: RoomSyncStatus,
-}
+//! Rooms as the UI shows them, a copy of the rooms of the UI's `RiverClient` kept up to date by
+//! `River`, which does all that's done in them
 
-impl RoomData {
-    /// Check if the user can send a message in the room
-    pub fn can_send_message(&self) -> Result<(), SendMessageError> {
-        let verifying_key = self.self_sk.verifying_key();
-        // Must be owner or a member of the room to send a message
-        if verifying_key == self.owner_vk
-            || self
-                .room_state
-                .members
-                .members
-                .iter()
-                .any(|m| m.member.member_vk == verifying_key)
-        {
-            // Must not be banned from the room to send a message
-            if self
-                .room_state
-                .bans
-                .0
-                .iter()
-                .any(|b| b.ban.banned_user == verifying_key.into())
-            {
-                Err(SendMessageError::UserBanned)
-            } else {
-                Ok(())
-            }
-        } else {
-            Err(SendMessageError::UserNotMember)
-        }
-    }
-
-    pub fn owner_id(&self) -> MemberId {
-        self.owner_vk.into()
-    }
-
-    pub fn parameters(&self) -> ChatRoomParametersV1 {
-        ChatRoomParametersV1 {
-            owner: self.owner_vk,
-        }
-    }
-}
+use common::room_state::member::MemberId;
+use ed25519_dalek::VerifyingKey;
+pub use river_client::room_data::*;
 
 pub struct CurrentRoom {
     pub owner_key: Option<VerifyingKey>,
@@ -91,64 +24,3 @@ impl PartialEq for CurrentRoom {
         self.owner_key == other.owner_key
     }
 }
-
-#[derive(Clone)]
-pub struct Rooms {
-    pub map: HashMap<VerifyingKey, RoomData>,
-}
-
-impl PartialEq for Rooms {
-    fn eq(&self, other: &Self) -> bool {
-        self.map == other.map
-    }
-}
-
-impl Rooms {
-    pub fn create_new_room_with_name(
-        &mut self,
-        self_sk: SigningKey,
-        name: String,
-        nickname: String,
-    ) -> VerifyingKey {
-        let owner_vk = self_sk.verifying_key();
-        let mut room_state = ChatRoomStateV1::default();
-
-        // Set initial configuration
-        let mut config = Configuration::default();
-        config.name = name;
-        config.owner_member_id = owner_vk.into();
-        room_state.configuration = AuthorizedConfigurationV1::new(config, &self_sk);
-
-        // Add owner to member_info
-        let owner_info = MemberInfo {
-            member_id: owner_vk.into(),
-            version: 0,
-            preferred_nickname: nickname,
-        };
-        let authorized_owner_info = AuthorizedMemberInfo::new(owner_info, &self_sk);
-        room_state
-            .member_info
-            .member_info
-            .push(authorized_owner_info);
-
-        // Generate contract key for the room
-        let parameters = ChatRoomParametersV1 {
-            owner: owner_vk,
-        };
-        let params_bytes = to_cbor_vec(&parameters);
-        let contract_code = ContractCode::from(ROOM_CONTRACT_WASM);
-        let instance_id = ContractInstanceId::from_params_and_code(Parameters::from(params_bytes), contract_code);
-        let contract_key = ContractKey::from(instance_id);
-
-        let room_data = RoomData {
-            owner_vk,
-            room_state,
-            self_sk,
-            contract_key,
-            sync_status: RoomSyncStatus::Unsubscribed,
-        };
-
-        self.map.insert(owner_vk, room_data);
-        owner_vk
-    }
-}
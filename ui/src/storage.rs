@@ -0,0 +1,204 @@
+//! Keeps rooms across reloads. Room states are stored as they are with our verifying key in each.
+//! Our signing keys are kept by the chat delegate, and only without a node (`no-sync`) stored here
+//! in a keyring encrypted with the user's passphrase, so none are stored until the user chose one.
+
+#[cfg(not(target_arch = "wasm32"))]
+mod file_store;
+mod identity;
+#[cfg(target_arch = "wasm32")]
+mod local_storage;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use file_store::FileStore;
+pub use identity::IdentityBundle;
+#[cfg(target_arch = "wasm32")]
+pub use local_storage::LocalStorage;
+pub use common::keyring::{EncryptedKeyring, Keyring, KeyringError, KeyringKey};
+
+use crate::room_data::{PendingJoin, PendingUpgrade, RoomData, Rooms};
+use crate::util::to_cbor_vec;
+use common::ChatRoomState;
+use ed25519_dalek::VerifyingKey;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const ROOMS_KEY: &str = "rooms";
+const KEYRING_KEY: &str = "keyring";
+
+/// Where the app keeps what it stores, as bytes by key
+pub trait Storage {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError>;
+    fn set(&self, key: &str, value: &[u8]) -> Result<(), StorageError>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StorageError {
+    Unavailable(String),
+    Keyring(KeyringError),
+    Malformed(String),
+}
+
+/// A room as it's stored, our key in it is kept by the key store
+#[derive(Serialize, Deserialize)]
+struct StoredRoom {
+    owner_vk: VerifyingKey,
+    self_vk: VerifyingKey,
+    state: ChatRoomState,
+    pending_join: Option<PendingJoin>,
+    /// Whether `state` is a placeholder until the contract's state arrives, see
+    /// `PendingUpgrade::Fetch`. An upgrade yet to be announced isn't kept, it's sent right away.
+    fetch: bool,
+}
+
+/// The storage of the platform the app runs on
+pub fn default_storage() -> Result<Box<dyn Storage>, StorageError> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        Ok(Box::new(LocalStorage::new()?))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        Ok(Box::new(FileStore::in_data_dir()?))
+    }
+}
+
+/// Whether a keyring was stored, whose passphrase unlocks our keys
+pub fn has_keyring(storage: &dyn Storage) -> Result<bool, StorageError> {
+    Ok(storage.get(KEYRING_KEY)?.is_some())
+}
+
+/// Stores `rooms`, without our keys in them
+pub fn save_rooms(storage: &dyn Storage, rooms: &Rooms) -> Result<(), StorageError> {
+    let stored_rooms: Vec<StoredRoom> = rooms
+        .map
+        .values()
+        .map(|room_data| StoredRoom {
+            owner_vk: room_data.owner_vk,
+            self_vk: room_data.self_vk,
+            state: room_data.state(),
+            pending_join: room_data.pending_join.clone(),
+            fetch: matches!(room_data.pending_upgrade, Some(PendingUpgrade::Fetch)),
+        })
+        .collect();
+    storage.set(ROOMS_KEY, &to_cbor_vec(&stored_rooms))
+}
+
+/// The stored rooms, which can be used once the key store has our keys in them
+pub fn load_rooms(storage: &dyn Storage) -> Result<Rooms, StorageError> {
+    let stored_rooms: Vec<StoredRoom> = match storage.get(ROOMS_KEY)? {
+        Some(bytes) => from_cbor(&bytes)?,
+        None => Vec::new(),
+    };
+    let mut rooms = Rooms::default();
+    for stored_room in stored_rooms {
+        let mut room_data =
+            RoomData::restored(stored_room.owner_vk, stored_room.self_vk, stored_room.state);
+        room_data.pending_join = stored_room.pending_join;
+        if stored_room.fetch {
+            room_data.pending_upgrade = Some(PendingUpgrade::Fetch);
+        }
+        rooms.map.insert(stored_room.owner_vk, room_data);
+    }
+    Ok(rooms)
+}
+
+/// Stores our keys as the key store encrypted them, see `RiverClient::export_keyring`
+pub fn save_keyring(storage: &dyn Storage, keyring: &EncryptedKeyring) -> Result<(), StorageError> {
+    storage.set(KEYRING_KEY, &to_cbor_vec(keyring))
+}
+
+/// The stored keyring unlocked by `passphrase`, and the key to save it with from then on. Without
+/// a stored keyring it's empty and `passphrase` protects the keyring from now on.
+pub fn load_keyring(
+    storage: &dyn Storage,
+    passphrase: &str,
+) -> Result<(Keyring, KeyringKey), StorageError> {
+    let Some(encrypted) = storage.get(KEYRING_KEY)? else {
+        return Ok((Keyring::default(), KeyringKey::new(passphrase)?));
+    };
+    let (key, keyring) = KeyringKey::unlock(passphrase, &from_cbor(&encrypted)?)?;
+    Ok((keyring, key))
+}
+
+fn from_cbor<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, StorageError> {
+    ciborium::de::from_reader(bytes).map_err(|e| StorageError::Malformed(e.to_string()))
+}
+
+impl From<KeyringError> for StorageError {
+    fn from(error: KeyringError) -> Self {
+        StorageError::Keyring(error)
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Unavailable(error) => write!(f, "Storage unavailable: {}", error),
+            StorageError::Keyring(error) => write!(f, "{}", error),
+            StorageError::Malformed(error) => write!(f, "Invalid stored data: {}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::room_state::configuration::PrivacyMode;
+    use ed25519_dalek::SigningKey;
+    use futures::executor::block_on;
+    use rand::rngs::OsRng;
+    use river_client::{KeyStore, LocalKeyStore};
+
+    #[test]
+    fn test_save_load_rooms() {
+        let dir = std::env::temp_dir().join(format!("river-test-{}", rand::random::<u64>()));
+        let storage = FileStore::new(&dir);
+        assert!(!has_keyring(&storage).unwrap());
+        assert!(load_rooms(&storage).unwrap().map.is_empty());
+
+        let (mut keyring, key) = load_keyring(&storage, "passphrase").unwrap();
+        assert!(keyring.keys.is_empty());
+        let mut rooms = Rooms::default();
+        let mut key_store = LocalKeyStore::default();
+        let owner_sk = SigningKey::generate(&mut OsRng);
+        block_on(key_store.store(owner_sk.clone())).unwrap();
+        let owner_vk = block_on(rooms.create_new_room_with_name(
+            owner_sk.verifying_key(),
+            "Room".to_string(),
+            "Owner".to_string(),
+            PrivacyMode::Private,
+            &mut key_store,
+        ))
+        .unwrap();
+        keyring.keys.insert(owner_vk, owner_sk);
+        // A key we have no state for yet, as if imported
+        let imported_owner_vk = SigningKey::generate(&mut OsRng).verifying_key();
+        let mut imported = Keyring::default();
+        imported
+            .keys
+            .insert(imported_owner_vk, SigningKey::generate(&mut OsRng));
+        assert_eq!(rooms.add_keys(&imported), 1);
+        save_rooms(&storage, &rooms).unwrap();
+        save_keyring(&storage, &key.encrypt(&keyring)).unwrap();
+
+        assert!(has_keyring(&storage).unwrap());
+        assert!(matches!(
+            load_keyring(&storage, "wrong"),
+            Err(StorageError::Keyring(KeyringError::WrongPassphrase))
+        ));
+        let (loaded_keyring, _) = load_keyring(&storage, "passphrase").unwrap();
+        assert!(loaded_keyring.keys[&owner_vk] == keyring.keys[&owner_vk]);
+        let mut loaded = load_rooms(&storage).unwrap();
+        let room_data = loaded.map.get_mut(&owner_vk).unwrap();
+        assert_eq!(room_data.self_vk, owner_vk);
+        assert!(room_data.state() == rooms.map[&owner_vk].state());
+        // The room secret is recovered from the state with our key
+        block_on(room_data.decrypt_room_secrets(&mut key_store)).unwrap();
+        assert_eq!(room_data.room_secrets, rooms.map[&owner_vk].room_secrets);
+        assert!(loaded.map[&imported_owner_vk].pending_upgrade.is_some());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
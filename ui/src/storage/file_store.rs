@@ -0,0 +1,44 @@
+use super::{Storage, StorageError};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// A file per key in a directory, for running natively
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileStore { dir: dir.into() }
+    }
+
+    /// `river` in `$XDG_DATA_HOME`, or in `~/.local/share` if that isn't set
+    pub fn in_data_dir() -> Result<Self, StorageError> {
+        let data_dir = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+            .ok_or_else(|| StorageError::Unavailable("No data directory".to_string()))?;
+        Ok(FileStore::new(data_dir.join("river")))
+    }
+}
+
+impl Storage for FileStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        match fs::read(self.dir.join(key)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(StorageError::Unavailable(e.to_string())),
+        }
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<(), StorageError> {
+        // Written aside and renamed over the old file, so that a crash leaves one or the other
+        let path = self.dir.join(key);
+        let temp_path = self.dir.join(format!("{}.tmp", key));
+        fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&temp_path, value))
+            .and_then(|_| fs::rename(&temp_path, &path))
+            .map_err(|e| StorageError::Unavailable(e.to_string()))
+    }
+}
@@ -0,0 +1,85 @@
+use super::{EncryptedKeyring, Keyring, KeyringError, KeyringKey};
+use crate::constants::IDENTITY_VERSION_PREFIX;
+use crate::util::to_cbor_vec;
+use data_encoding::BASE32_NOPAD;
+
+/// Our keys in every room as text to use them from another device too, encrypted with a
+/// passphrase of its own. Rooms are fetched from the network on the device it's imported on.
+/// The key store encrypts it, see `RiverClient::export_keyring`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct IdentityBundle(pub EncryptedKeyring);
+
+impl IdentityBundle {
+
+    pub fn import(&self, passphrase: &str) -> Result<Keyring, KeyringError> {
+        KeyringKey::unlock(passphrase, &self.0).map(|(_, keyring)| keyring)
+    }
+
+    /// The bundle in base32 after the prefix. Uppercased as a whole it only has characters a QR
+    /// code's alphanumeric mode encodes, and it decodes the same.
+    pub fn encode(&self) -> String {
+        format!(
+            "{}{}",
+            IDENTITY_VERSION_PREFIX,
+            BASE32_NOPAD.encode(&to_cbor_vec(&self.0))
+        )
+    }
+
+    pub fn decode(text: &str) -> Result<Self, KeyringError> {
+        // Whitespace is ignored, the text may have been wrapped on its way
+        let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+        let prefix_len = IDENTITY_VERSION_PREFIX.len();
+        let encoded = text
+            .get(..prefix_len)
+            .filter(|prefix| prefix.eq_ignore_ascii_case(IDENTITY_VERSION_PREFIX))
+            .map(|_| &text[prefix_len..])
+            .ok_or_else(|| {
+                KeyringError::Malformed(format!("expected {}...", IDENTITY_VERSION_PREFIX))
+            })?;
+        let bytes = BASE32_NOPAD
+            .decode(encoded.to_ascii_uppercase().as_bytes())
+            .map_err(|e| KeyringError::Malformed(e.to_string()))?;
+        ciborium::de::from_reader(bytes.as_slice())
+            .map(IdentityBundle)
+            .map_err(|e| KeyringError::Malformed(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_export_import() {
+        let mut keyring = Keyring::default();
+        for _ in 0..3 {
+            keyring.keys.insert(
+                SigningKey::generate(&mut OsRng).verifying_key(),
+                SigningKey::generate(&mut OsRng),
+            );
+        }
+        let key = KeyringKey::new("correct horse").unwrap();
+        let encoded = IdentityBundle(key.encrypt(&keyring)).encode();
+
+        // As it would be read back from a QR code
+        let uppercased = encoded.to_uppercase();
+        assert!(uppercased
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_ascii_uppercase() || c == ':'));
+        for text in [encoded.clone(), uppercased, format!(" {}\n", encoded)] {
+            let bundle = IdentityBundle::decode(&text).unwrap();
+            assert!(bundle.import("correct horse").unwrap() == keyring);
+            assert_eq!(
+                bundle.import("battery staple").err(),
+                Some(KeyringError::WrongPassphrase)
+            );
+        }
+
+        assert!(matches!(
+            IdentityBundle::decode("river:v1:user:vk:abc"),
+            Err(KeyringError::Malformed(_))
+        ));
+    }
+}
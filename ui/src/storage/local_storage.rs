@@ -0,0 +1,45 @@
+use super::{Storage, StorageError};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+/// The browser's localStorage, which only holds strings so values are stored base64 encoded
+pub struct LocalStorage(web_sys::Storage);
+
+impl LocalStorage {
+    pub fn new() -> Result<Self, StorageError> {
+        let window =
+            web_sys::window().ok_or_else(|| StorageError::Unavailable("No window".to_string()))?;
+        let storage = window
+            .local_storage()
+            .map_err(|e| StorageError::Unavailable(format!("{:?}", e)))?
+            .ok_or_else(|| StorageError::Unavailable("localStorage is disabled".to_string()))?;
+        Ok(LocalStorage(storage))
+    }
+}
+
+impl Storage for LocalStorage {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        let value = self
+            .0
+            .get_item(&item(key))
+            .map_err(|e| StorageError::Unavailable(format!("{:?}", e)))?;
+        value
+            .map(|value| {
+                STANDARD
+                    .decode(value)
+                    .map_err(|e| StorageError::Malformed(e.to_string()))
+            })
+            .transpose()
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<(), StorageError> {
+        // Fails once the origin's quota is used up
+        self.0
+            .set_item(&item(key), &STANDARD.encode(value))
+            .map_err(|e| StorageError::Unavailable(format!("{:?}", e)))
+    }
+}
+
+/// Keys are prefixed, other apps may share the origin
+fn item(key: &str) -> String {
+    format!("river.{}", key)
+}